@@ -5,6 +5,7 @@ use std::{
     fmt::Debug,
     io,
     num::{NonZeroU32, TryFromIntError},
+    ops::Range,
 };
 
 use incrementalmerkletree::{frontier::Frontier, Retention};
@@ -26,13 +27,13 @@ use zcash_primitives::{
 use crate::{
     address::{AddressMetadata, UnifiedAddress},
     decrypt::DecryptedOutput,
-    keys::{UnifiedAddressRequest, UnifiedFullViewingKey, UnifiedSpendingKey},
+    keys::{SeedFingerprint, UnifiedAddressRequest, UnifiedFullViewingKey, UnifiedSpendingKey},
     proto::service::TreeState,
     wallet::{Note, NoteId, ReceivedNote, Recipient, WalletTransparentOutput, WalletTx},
-    ShieldedProtocol,
+    PoolType, ShieldedProtocol,
 };
 
-use self::chain::CommitmentTreeRoot;
+use self::chain::{error::RewindError, ChainView, CommitmentTreeRoot, RewindPlan};
 use self::scanning::ScanRange;
 
 #[cfg(feature = "transparent-inputs")]
@@ -40,6 +41,8 @@ use zcash_primitives::transaction::components::OutPoint;
 
 pub mod chain;
 pub mod error;
+#[cfg(feature = "unstable-memory-wallet")]
+pub mod mem;
 pub mod scanning;
 pub mod wallet;
 
@@ -49,6 +52,13 @@ pub mod wallet;
 /// `lightwalletd` when using the `GetSubtreeRoots` GRPC call.
 pub const SAPLING_SHARD_HEIGHT: u8 = sapling::NOTE_COMMITMENT_TREE_DEPTH / 2;
 
+/// The height of subtree roots in the Orchard note commitment tree.
+///
+/// This conforms to the structure of subtree data returned by
+/// `lightwalletd` when using the `GetSubtreeRoots` GRPC call.
+#[cfg(feature = "orchard")]
+pub const ORCHARD_SHARD_HEIGHT: u8 = { orchard::NOTE_COMMITMENT_TREE_DEPTH as u8 } / 2;
+
 /// An enumeration of constraints that can be applied when querying for nullifiers for notes
 /// belonging to the wallet.
 pub enum NullifierQuery {
@@ -62,6 +72,7 @@ pub struct Balance {
     spendable_value: NonNegativeAmount,
     change_pending_confirmation: NonNegativeAmount,
     value_pending_spendability: NonNegativeAmount,
+    incoming_pending: NonNegativeAmount,
 }
 
 impl Balance {
@@ -70,6 +81,7 @@ impl Balance {
         spendable_value: NonNegativeAmount::ZERO,
         change_pending_confirmation: NonNegativeAmount::ZERO,
         value_pending_spendability: NonNegativeAmount::ZERO,
+        incoming_pending: NonNegativeAmount::ZERO,
     };
 
     fn check_total_adding(
@@ -79,6 +91,7 @@ impl Balance {
         (self.spendable_value
             + self.change_pending_confirmation
             + self.value_pending_spendability
+            + self.incoming_pending
             + value)
             .ok_or(BalanceError::Overflow)
     }
@@ -113,9 +126,9 @@ impl Balance {
         Ok(())
     }
 
-    /// Returns the value in the account of all remaining received notes that either do not have
-    /// sufficient confirmations to be spendable, or for which witnesses cannot yet be constructed
-    /// without additional scanning.
+    /// Returns the value in the account of all remaining mined received notes that either do not
+    /// have sufficient confirmations to be spendable, or for which witnesses cannot yet be
+    /// constructed without additional scanning.
     pub fn value_pending_spendability(&self) -> NonNegativeAmount {
         self.value_pending_spendability
     }
@@ -130,7 +143,26 @@ impl Balance {
         Ok(())
     }
 
-    /// Returns the total value of funds represented by this [`Balance`].
+    /// Returns the value in the account of notes received in transactions that have been
+    /// observed in the mempool but have not yet been mined.
+    pub fn incoming_pending(&self) -> NonNegativeAmount {
+        self.incoming_pending
+    }
+
+    /// Adds the specified value to the incoming pending total, checking for overflow.
+    pub fn add_incoming_pending_value(
+        &mut self,
+        value: NonNegativeAmount,
+    ) -> Result<(), BalanceError> {
+        self.check_total_adding(value)?;
+        self.incoming_pending = (self.incoming_pending + value).unwrap();
+        Ok(())
+    }
+
+    /// Returns the total value of funds represented by this [`Balance`] that have been received
+    /// in mined transactions.
+    ///
+    /// This does not include [`Balance::incoming_pending`].
     pub fn total(&self) -> NonNegativeAmount {
         (self.spendable_value + self.change_pending_confirmation + self.value_pending_spendability)
             .expect("Balance cannot overflow MAX_MONEY")
@@ -166,7 +198,11 @@ impl AccountBalance {
     };
 
     fn check_total(&self) -> Result<NonNegativeAmount, BalanceError> {
-        (self.sapling_balance.total() + self.orchard_balance.total() + self.unshielded)
+        (self.sapling_balance.total()
+            + self.sapling_balance.incoming_pending
+            + self.orchard_balance.total()
+            + self.orchard_balance.incoming_pending
+            + self.unshielded)
             .ok_or(BalanceError::Overflow)
     }
 
@@ -217,7 +253,8 @@ impl AccountBalance {
         Ok(())
     }
 
-    /// Returns the total value of funds belonging to the account.
+    /// Returns the total value of funds belonging to the account, excluding the value of
+    /// notes received in unmined transactions (see [`AccountBalance::incoming_pending`]).
     pub fn total(&self) -> NonNegativeAmount {
         (self.sapling_balance.total() + self.orchard_balance.total() + self.unshielded)
             .expect("Account balance cannot overflow MAX_MONEY")
@@ -245,6 +282,13 @@ impl AccountBalance {
             + self.orchard_balance.value_pending_spendability)
             .expect("Account balance cannot overflow MAX_MONEY")
     }
+
+    /// Returns the value of shielded funds received in transactions that have been observed in
+    /// the mempool but have not yet been mined.
+    pub fn incoming_pending(&self) -> NonNegativeAmount {
+        (self.sapling_balance.incoming_pending + self.orchard_balance.incoming_pending)
+            .expect("Account balance cannot overflow MAX_MONEY")
+    }
 }
 
 /// A polymorphic ratio type, usually used for rational numbers.
@@ -347,6 +391,239 @@ impl WalletSummary {
     }
 }
 
+/// Criteria used to select the transactions returned by [`WalletRead::get_transactions`].
+///
+/// The default filter matches all of an account's transactions, including those that have not
+/// yet been mined.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransactionFilter {
+    mined_height_range: Option<Range<BlockHeight>>,
+    offset: u32,
+    limit: Option<u32>,
+}
+
+impl TransactionFilter {
+    /// Restricts the filter to transactions mined in the given range of block heights. Unmined
+    /// transactions are excluded by this restriction.
+    pub fn with_mined_height_range(mut self, range: Range<BlockHeight>) -> Self {
+        self.mined_height_range = Some(range);
+        self
+    }
+
+    /// Restricts the filter to at most `limit` transactions, after skipping the first `offset`
+    /// matching transactions.
+    pub fn with_page(mut self, offset: u32, limit: u32) -> Self {
+        self.offset = offset;
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Returns the range of mined heights to which the filter is restricted, if any.
+    pub fn mined_height_range(&self) -> Option<&Range<BlockHeight>> {
+        self.mined_height_range.as_ref()
+    }
+
+    /// Returns the number of matching transactions to skip.
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    /// Returns the maximum number of transactions to return, if any.
+    pub fn limit(&self) -> Option<u32> {
+        self.limit
+    }
+}
+
+/// A summary of the effects of a transaction on the balance of a single account, as returned
+/// by [`WalletRead::get_transactions`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionSummary {
+    account: AccountId,
+    txid: TxId,
+    mined_height: Option<BlockHeight>,
+    block_time: Option<u32>,
+    expiry_height: Option<BlockHeight>,
+    account_value_delta: Amount,
+    fee_paid: Option<NonNegativeAmount>,
+    expired_unmined: bool,
+    outputs: Vec<OutputSummary>,
+}
+
+impl TransactionSummary {
+    /// Constructs a new [`TransactionSummary`] from its constituent parts.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_parts(
+        account: AccountId,
+        txid: TxId,
+        mined_height: Option<BlockHeight>,
+        block_time: Option<u32>,
+        expiry_height: Option<BlockHeight>,
+        account_value_delta: Amount,
+        fee_paid: Option<NonNegativeAmount>,
+        expired_unmined: bool,
+        outputs: Vec<OutputSummary>,
+    ) -> Self {
+        Self {
+            account,
+            txid,
+            mined_height,
+            block_time,
+            expiry_height,
+            account_value_delta,
+            fee_paid,
+            expired_unmined,
+            outputs,
+        }
+    }
+
+    /// Returns the account whose balance is described by this summary.
+    pub fn account(&self) -> AccountId {
+        self.account
+    }
+
+    /// Returns the ID of the transaction.
+    pub fn txid(&self) -> TxId {
+        self.txid
+    }
+
+    /// Returns the height of the block in which the transaction was mined, or `None` if the
+    /// transaction has not been mined.
+    pub fn mined_height(&self) -> Option<BlockHeight> {
+        self.mined_height
+    }
+
+    /// Returns the time of the block in which the transaction was mined, in seconds since the
+    /// Unix epoch, if known.
+    pub fn block_time(&self) -> Option<u32> {
+        self.block_time
+    }
+
+    /// Returns the expiry height of the transaction, if known.
+    pub fn expiry_height(&self) -> Option<BlockHeight> {
+        self.expiry_height
+    }
+
+    /// Returns the net change in the account's balance as a consequence of the transaction.
+    /// This is negative for transactions that spend funds from the account.
+    pub fn account_value_delta(&self) -> Amount {
+        self.account_value_delta
+    }
+
+    /// Returns the fee paid by the transaction, if known. The fee is only known for
+    /// transactions created by the wallet.
+    pub fn fee_paid(&self) -> Option<NonNegativeAmount> {
+        self.fee_paid
+    }
+
+    /// Returns whether the transaction expired without having been mined.
+    pub fn expired_unmined(&self) -> bool {
+        self.expired_unmined
+    }
+
+    /// Returns the outputs of the transaction that were sent from or received by the account,
+    /// in order of pool and output index.
+    pub fn outputs(&self) -> &[OutputSummary] {
+        &self.outputs
+    }
+
+    /// Returns the non-empty memos attached to the transaction's outputs.
+    pub fn memos(&self) -> impl Iterator<Item = &MemoBytes> {
+        let empty = MemoBytes::empty();
+        self.outputs
+            .iter()
+            .filter_map(|output| output.memo.as_ref())
+            .filter(move |memo| memo != &&empty)
+    }
+
+    /// Returns the addresses of recipients outside of the wallet to which the transaction sent
+    /// funds.
+    pub fn counterparties(&self) -> impl Iterator<Item = &str> {
+        self.outputs
+            .iter()
+            .filter(|output| output.to_account.is_none())
+            .filter_map(|output| output.to_address.as_deref())
+    }
+}
+
+/// A summary of a single output of a transaction, as part of a [`TransactionSummary`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputSummary {
+    pool: PoolType,
+    output_index: u32,
+    from_account: Option<AccountId>,
+    to_account: Option<AccountId>,
+    to_address: Option<String>,
+    value: NonNegativeAmount,
+    is_change: bool,
+    memo: Option<MemoBytes>,
+}
+
+impl OutputSummary {
+    /// Constructs a new [`OutputSummary`] from its constituent parts.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_parts(
+        pool: PoolType,
+        output_index: u32,
+        from_account: Option<AccountId>,
+        to_account: Option<AccountId>,
+        to_address: Option<String>,
+        value: NonNegativeAmount,
+        is_change: bool,
+        memo: Option<MemoBytes>,
+    ) -> Self {
+        Self {
+            pool,
+            output_index,
+            from_account,
+            to_account,
+            to_address,
+            value,
+            is_change,
+            memo,
+        }
+    }
+
+    /// Returns the pool in which the output was created.
+    pub fn pool(&self) -> PoolType {
+        self.pool
+    }
+
+    /// Returns the index of the output within its pool's outputs in the transaction.
+    pub fn output_index(&self) -> u32 {
+        self.output_index
+    }
+
+    /// Returns the wallet account that sent this output, if it was sent by the wallet.
+    pub fn from_account(&self) -> Option<AccountId> {
+        self.from_account
+    }
+
+    /// Returns the wallet account that received this output, if it was received by the wallet.
+    pub fn to_account(&self) -> Option<AccountId> {
+        self.to_account
+    }
+
+    /// Returns the encoded address to which the output was sent, if known.
+    pub fn to_address(&self) -> Option<&str> {
+        self.to_address.as_deref()
+    }
+
+    /// Returns the value of the output.
+    pub fn value(&self) -> NonNegativeAmount {
+        self.value
+    }
+
+    /// Returns whether the output is change returned to the sending account.
+    pub fn is_change(&self) -> bool {
+        self.is_change
+    }
+
+    /// Returns the memo associated with the output, if known.
+    pub fn memo(&self) -> Option<&MemoBytes> {
+        self.memo.as_ref()
+    }
+}
+
 /// A trait representing the capability to query a data store for unspent transaction outputs
 /// belonging to a wallet.
 pub trait InputSource {
@@ -483,6 +760,25 @@ pub trait WalletRead {
     /// This will return `Ok(None)` if no blocks have been scanned.
     fn get_max_height_hash(&self) -> Result<Option<(BlockHeight, BlockHash)>, Self::Error>;
 
+    /// Determines how far the wallet must be rewound in order to resolve a chain continuity
+    /// error detected at `conflict_height`.
+    ///
+    /// This walks backwards from `conflict_height` comparing the block hashes stored by the
+    /// wallet with those provided by `chain_view`, and returns a [`RewindPlan`] describing the
+    /// greatest height at which the two agree along with the transactions and notes that would
+    /// be un-mined by truncating the wallet to that height. No wallet state is modified.
+    ///
+    /// Returns [`RewindError::RewindTooDeep`] if the fork point lies below the oldest checkpoint
+    /// retained by the wallet's note commitment trees, as truncating to such a height would
+    /// leave those trees in an inconsistent state.
+    ///
+    /// [`RewindError::RewindTooDeep`]: chain::error::RewindError::RewindTooDeep
+    fn plan_rewind<C: ChainView>(
+        &self,
+        conflict_height: BlockHeight,
+        chain_view: &C,
+    ) -> Result<RewindPlan, RewindError<Self::Error, C::Error>>;
+
     /// Returns the block height in which the specified transaction was mined, or `Ok(None)` if the
     /// transaction is not in the main chain.
     fn get_tx_height(&self, txid: TxId) -> Result<Option<BlockHeight>, Self::Error>;
@@ -518,6 +814,21 @@ pub trait WalletRead {
         ufvk: &UnifiedFullViewingKey,
     ) -> Result<Option<AccountId>, Self::Error>;
 
+    /// Returns the kind of the specified account, which determines whether the wallet is able
+    /// to spend funds from that account.
+    ///
+    /// This will return `Ok(None)` if the account identifier does not correspond to a known
+    /// account.
+    fn get_account_kind(&self, account: AccountId) -> Result<Option<AccountKind>, Self::Error>;
+
+    /// Returns the identifier of the account derived from the seed with the given fingerprint at
+    /// the given ZIP 32 account index, if that account is known to the wallet.
+    fn get_derived_account(
+        &self,
+        seed_fingerprint: &SeedFingerprint,
+        account_index: AccountId,
+    ) -> Result<Option<AccountId>, Self::Error>;
+
     /// Returns the wallet balances and sync status for an account given the specified minimum
     /// number of confirmations, or `Ok(None)` if the wallet has no balance data available.
     fn get_wallet_summary(
@@ -535,6 +846,25 @@ pub trait WalletRead {
     /// Returns a transaction.
     fn get_transaction(&self, txid: TxId) -> Result<Transaction, Self::Error>;
 
+    /// Returns the transactions that spend funds belonging to the wallet, but which have not yet
+    /// been mined and have not expired as of the current chain tip.
+    ///
+    /// Wallets should periodically rebroadcast these transactions until they are either mined
+    /// or expire. Transactions that have been abandoned via
+    /// [`WalletWrite::abandon_transaction`] are not returned.
+    fn get_unmined_sent_transactions(&self) -> Result<Vec<UnminedSentTransaction>, Self::Error>;
+
+    /// Returns the transaction history of the given account, restricted according to the
+    /// provided filter.
+    ///
+    /// Unmined transactions are returned first, followed by mined transactions in order of
+    /// decreasing mined height.
+    fn get_transactions(
+        &self,
+        account: AccountId,
+        filter: &TransactionFilter,
+    ) -> Result<Vec<TransactionSummary>, Self::Error>;
+
     /// Returns the nullifiers for Sapling notes that the wallet is tracking, along with their
     /// associated account IDs, that are either unspent or have not yet been confirmed as spent (in
     /// that a spending transaction known to the wallet has not yet been included in a block).
@@ -679,7 +1009,7 @@ pub struct ScannedBlockCommitments {
     /// The ordered vector of note commitments for Orchard outputs of the block.
     /// Present only when the `orchard` feature is enabled.
     #[cfg(feature = "orchard")]
-    pub orchard: Vec<(orchard::note::NoteCommitment, Retention<BlockHeight>)>,
+    pub orchard: Vec<(orchard::tree::MerkleHashOrchard, Retention<BlockHeight>)>,
 }
 
 /// The subset of information that is relevant to this wallet that has been
@@ -693,7 +1023,7 @@ pub struct ScannedBlock<Nf, S> {
     transactions: Vec<WalletTx<Nf, S>>,
     sapling: ScannedBundles<sapling::Node, sapling::Nullifier>,
     #[cfg(feature = "orchard")]
-    orchard: ScannedBundles<orchard::note::NoteCommitment, orchard::note::Nullifier>,
+    orchard: ScannedBundles<orchard::tree::MerkleHashOrchard, orchard::note::Nullifier>,
 }
 
 impl<Nf, S> ScannedBlock<Nf, S> {
@@ -705,7 +1035,7 @@ impl<Nf, S> ScannedBlock<Nf, S> {
         transactions: Vec<WalletTx<Nf, S>>,
         sapling: ScannedBundles<sapling::Node, sapling::Nullifier>,
         #[cfg(feature = "orchard")] orchard: ScannedBundles<
-            orchard::note::NoteCommitment,
+            orchard::tree::MerkleHashOrchard,
             orchard::note::Nullifier,
         >,
     ) -> Self {
@@ -749,7 +1079,7 @@ impl<Nf, S> ScannedBlock<Nf, S> {
     #[cfg(feature = "orchard")]
     pub fn orchard(
         &self,
-    ) -> &ScannedBundles<orchard::note::NoteCommitment, orchard::note::Nullifier> {
+    ) -> &ScannedBundles<orchard::tree::MerkleHashOrchard, orchard::note::Nullifier> {
         &self.orchard
     }
 
@@ -783,6 +1113,8 @@ impl<Nf, S> ScannedBlock<Nf, S> {
 pub struct DecryptedTransaction<'a> {
     pub tx: &'a Transaction,
     pub sapling_outputs: &'a Vec<DecryptedOutput<sapling::Note>>,
+    #[cfg(feature = "orchard")]
+    pub orchard_outputs: &'a Vec<DecryptedOutput<orchard::note::Note>>,
 }
 
 /// A transaction that was constructed and sent by the wallet.
@@ -800,13 +1132,64 @@ pub struct SentTransaction<'a> {
     pub utxos_spent: Vec<OutPoint>,
 }
 
-/// A type that represents an output (either Sapling or transparent) that was sent by the wallet.
+/// A transaction that spends funds belonging to the wallet, but that has not been mined and has
+/// not yet expired.
+///
+/// Values of this type are returned by [`WalletRead::get_unmined_sent_transactions`], and provide
+/// the information necessary to rebroadcast the transaction.
+#[derive(Debug, Clone)]
+pub struct UnminedSentTransaction {
+    txid: TxId,
+    raw: Vec<u8>,
+    expiry_height: BlockHeight,
+    created: Option<time::OffsetDateTime>,
+}
+
+impl UnminedSentTransaction {
+    /// Constructs an [`UnminedSentTransaction`] from its constituent parts.
+    pub fn from_parts(
+        txid: TxId,
+        raw: Vec<u8>,
+        expiry_height: BlockHeight,
+        created: Option<time::OffsetDateTime>,
+    ) -> Self {
+        Self {
+            txid,
+            raw,
+            expiry_height,
+            created,
+        }
+    }
+
+    /// Returns the ID of the transaction.
+    pub fn txid(&self) -> TxId {
+        self.txid
+    }
+
+    /// Returns the serialized transaction, suitable for rebroadcast.
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// Returns the expiry height of the transaction. A value of zero indicates that the
+    /// transaction does not expire.
+    pub fn expiry_height(&self) -> BlockHeight {
+        self.expiry_height
+    }
+
+    /// Returns the time at which the transaction was created by the wallet, if known.
+    pub fn created(&self) -> Option<time::OffsetDateTime> {
+        self.created
+    }
+}
+
+/// A type that represents an output (either shielded or transparent) that was sent by the wallet.
 pub struct SentTransactionOutput {
     output_index: usize,
     recipient: Recipient,
     value: NonNegativeAmount,
     memo: Option<MemoBytes>,
-    sapling_change_to: Option<(AccountId, sapling::Note)>,
+    change_to: Option<(AccountId, Note)>,
 }
 
 impl SentTransactionOutput {
@@ -815,14 +1198,14 @@ impl SentTransactionOutput {
         recipient: Recipient,
         value: NonNegativeAmount,
         memo: Option<MemoBytes>,
-        sapling_change_to: Option<(AccountId, sapling::Note)>,
+        change_to: Option<(AccountId, Note)>,
     ) -> Self {
         Self {
             output_index,
             recipient,
             value,
             memo,
-            sapling_change_to,
+            change_to,
         }
     }
 
@@ -830,6 +1213,8 @@ impl SentTransactionOutput {
     ///
     /// - If `recipient_address` is a Sapling address, this is an index into the Sapling
     ///   outputs of the transaction.
+    /// - If `recipient_address` is an Orchard address, this is an index into the Orchard
+    ///   actions of the transaction.
     /// - If `recipient_address` is a transparent address, this is an index into the
     ///   transparent outputs of the transaction.
     pub fn output_index(&self) -> usize {
@@ -852,8 +1237,50 @@ impl SentTransactionOutput {
 
     /// Returns the account to which change (or wallet-internal value in the case of a shielding
     /// transaction) was sent, along with the change note.
-    pub fn sapling_change_to(&self) -> Option<&(AccountId, sapling::Note)> {
-        self.sapling_change_to.as_ref()
+    pub fn change_to(&self) -> Option<&(AccountId, Note)> {
+        self.change_to.as_ref()
+    }
+
+    /// Returns the account to which Sapling change (or wallet-internal value in the case of a
+    /// shielding transaction) was sent, along with the change note.
+    pub fn sapling_change_to(&self) -> Option<(&AccountId, &sapling::Note)> {
+        match &self.change_to {
+            Some((account, Note::Sapling(note))) => Some((account, note)),
+            _ => None,
+        }
+    }
+
+    /// Returns the account to which Orchard change was sent, along with the change note.
+    #[cfg(feature = "orchard")]
+    pub fn orchard_change_to(&self) -> Option<(&AccountId, &orchard::Note)> {
+        match &self.change_to {
+            Some((account, Note::Orchard(note))) => Some((account, note)),
+            _ => None,
+        }
+    }
+}
+
+/// The source of the keys for an account known to the wallet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountKind {
+    /// An account derived from a seed via [`WalletWrite::create_account`]. The wallet's user may
+    /// spend funds from this account using the spending key derived from that seed.
+    Derived {
+        /// The fingerprint of the seed from which the account was derived.
+        seed_fingerprint: SeedFingerprint,
+        /// The ZIP 32 account index at which the account was derived from the seed.
+        account_index: AccountId,
+    },
+    /// An account imported from a [`UnifiedFullViewingKey`] via
+    /// [`WalletWrite::import_account_ufvk`]. No spending key is available for this account, so
+    /// the wallet may track its balance and transaction history but cannot spend its funds.
+    ViewOnly,
+}
+
+impl AccountKind {
+    /// Returns whether a spending key is available for accounts of this kind.
+    pub fn has_spending_key(&self) -> bool {
+        matches!(self, AccountKind::Derived { .. })
     }
 }
 
@@ -976,11 +1403,19 @@ pub trait WalletWrite: WalletRead {
     /// The type of identifiers used to look up transparent UTXOs.
     type UtxoRef;
 
-    /// Tells the wallet to track the next available account-level spend authority, given the
-    /// current set of [ZIP 316] account identifiers known to the wallet database.
+    /// Tells the wallet to track the next available account-level spend authority for the given
+    /// seed, given the current set of [ZIP 316] account identifiers derived from that seed that
+    /// are known to the wallet database.
+    ///
+    /// A wallet may hold accounts derived from several different seeds. Accounts derived from
+    /// each seed are numbered independently: the first account created for a seed is derived at
+    /// ZIP 32 account index 0, regardless of the accounts derived from other seeds. The seed and
+    /// account index from which an account was derived are available via
+    /// [`WalletRead::get_account_kind`].
     ///
     /// Returns the account identifier for the newly-created wallet database entry, along with the
-    /// associated [`UnifiedSpendingKey`].
+    /// associated [`UnifiedSpendingKey`]. The wallet-level account identifier is distinct from
+    /// the ZIP 32 account index used to derive the spending key.
     ///
     /// If `birthday.height()` is below the current chain tip, this operation will
     /// trigger a re-scan of the blocks at and above the provided height. The birthday height is
@@ -1008,6 +1443,21 @@ pub trait WalletWrite: WalletRead {
         birthday: AccountBirthday,
     ) -> Result<(AccountId, UnifiedSpendingKey), Self::Error>;
 
+    /// Tells the wallet to track an account using the given unified full viewing key, and
+    /// returns the identifier that the wallet assigned to the new account.
+    ///
+    /// The account is recorded as [`AccountKind::ViewOnly`]: the wallet will detect and track
+    /// funds received by and spent from the account, but will not be able to spend those funds
+    /// itself. As with [`WalletWrite::create_account`], the account's `birthday` determines the
+    /// height from which the chain must be scanned in order to discover the account's notes.
+    ///
+    /// Returns an error if the wallet already tracks an account with the same viewing key.
+    fn import_account_ufvk(
+        &mut self,
+        ufvk: &UnifiedFullViewingKey,
+        birthday: AccountBirthday,
+    ) -> Result<AccountId, Self::Error>;
+
     /// Generates and persists the next available diversified address, given the current
     /// addresses known to the wallet.
     ///
@@ -1045,6 +1495,16 @@ pub trait WalletWrite: WalletRead {
     /// persistent wallet store.
     fn store_sent_tx(&mut self, sent_tx: &SentTransaction) -> Result<(), Self::Error>;
 
+    /// Marks an unmined transaction as abandoned, immediately releasing the notes and UTXOs that
+    /// it spends so that they may be used in a new transaction. Notes received by the wallet in
+    /// the abandoned transaction are removed from the wallet's balance.
+    ///
+    /// This does not prevent the transaction from being mined if it has already been broadcast;
+    /// if it is subsequently mined, scanning the containing block will restore the effects of the
+    /// transaction on the wallet. Returns an error if the transaction is not known to the wallet
+    /// or has already been mined.
+    fn abandon_transaction(&mut self, txid: TxId) -> Result<(), Self::Error>;
+
     /// Truncates the wallet database to the specified height.
     ///
     /// This method assumes that the state of the underlying data store is
@@ -1068,9 +1528,6 @@ pub trait WalletWrite: WalletRead {
 }
 
 /// This trait describes a capability for manipulating wallet note commitment trees.
-///
-/// At present, this only serves the Sapling protocol, but it will be modified to
-/// also provide operations related to Orchard note commitment trees in the future.
 pub trait WalletCommitmentTrees {
     type Error;
     type SaplingShardStore<'a>: ShardStore<
@@ -1078,6 +1535,12 @@ pub trait WalletCommitmentTrees {
         CheckpointId = BlockHeight,
         Error = Self::Error,
     >;
+    #[cfg(feature = "orchard")]
+    type OrchardShardStore<'a>: ShardStore<
+        H = orchard::tree::MerkleHashOrchard,
+        CheckpointId = BlockHeight,
+        Error = Self::Error,
+    >;
 
     ///
     fn with_sapling_tree_mut<F, A, E>(&mut self, callback: F) -> Result<A, E>
@@ -1097,6 +1560,28 @@ pub trait WalletCommitmentTrees {
         start_index: u64,
         roots: &[CommitmentTreeRoot<sapling::Node>],
     ) -> Result<(), ShardTreeError<Self::Error>>;
+
+    /// Evaluates the given callback function with a reference to the Orchard
+    /// note commitment tree maintained by the wallet.
+    #[cfg(feature = "orchard")]
+    fn with_orchard_tree_mut<F, A, E>(&mut self, callback: F) -> Result<A, E>
+    where
+        for<'a> F: FnMut(
+            &'a mut ShardTree<
+                Self::OrchardShardStore<'a>,
+                { ORCHARD_SHARD_HEIGHT * 2 },
+                ORCHARD_SHARD_HEIGHT,
+            >,
+        ) -> Result<A, E>,
+        E: From<ShardTreeError<Self::Error>>;
+
+    /// Adds a sequence of Orchard note commitment tree subtree roots to the data store.
+    #[cfg(feature = "orchard")]
+    fn put_orchard_subtree_roots(
+        &mut self,
+        start_index: u64,
+        roots: &[CommitmentTreeRoot<orchard::tree::MerkleHashOrchard>],
+    ) -> Result<(), ShardTreeError<Self::Error>>;
 }
 
 #[cfg(feature = "test-dependencies")]
@@ -1117,17 +1602,25 @@ pub mod testing {
 
     use crate::{
         address::{AddressMetadata, UnifiedAddress},
-        keys::{UnifiedAddressRequest, UnifiedFullViewingKey, UnifiedSpendingKey},
+        keys::{SeedFingerprint, UnifiedAddressRequest, UnifiedFullViewingKey, UnifiedSpendingKey},
         wallet::{Note, NoteId, ReceivedNote, WalletTransparentOutput},
         ShieldedProtocol,
     };
 
     use super::{
-        chain::CommitmentTreeRoot, scanning::ScanRange, AccountBirthday, BlockMetadata,
-        DecryptedTransaction, InputSource, NullifierQuery, ScannedBlock, SentTransaction,
-        WalletCommitmentTrees, WalletRead, WalletSummary, WalletWrite, SAPLING_SHARD_HEIGHT,
+        chain::{error::RewindError, ChainView, CommitmentTreeRoot, RewindPlan},
+        scanning::ScanRange,
+        AccountBirthday, AccountKind, BlockMetadata, DecryptedTransaction, InputSource,
+        NullifierQuery, ScannedBlock, SentTransaction, TransactionFilter, TransactionSummary,
+        UnminedSentTransaction, WalletCommitmentTrees, WalletRead, WalletSummary, WalletWrite,
+        SAPLING_SHARD_HEIGHT,
     };
 
+    #[cfg(feature = "orchard")]
+    use super::ORCHARD_SHARD_HEIGHT;
+
+    pub mod conformance;
+
     pub struct MockWalletDb {
         pub network: Network,
         pub sapling_tree: ShardTree<
@@ -1135,6 +1628,12 @@ pub mod testing {
             { SAPLING_SHARD_HEIGHT * 2 },
             SAPLING_SHARD_HEIGHT,
         >,
+        #[cfg(feature = "orchard")]
+        pub orchard_tree: ShardTree<
+            MemoryShardStore<orchard::tree::MerkleHashOrchard, BlockHeight>,
+            { ORCHARD_SHARD_HEIGHT * 2 },
+            ORCHARD_SHARD_HEIGHT,
+        >,
     }
 
     impl MockWalletDb {
@@ -1142,6 +1641,8 @@ pub mod testing {
             Self {
                 network,
                 sapling_tree: ShardTree::new(MemoryShardStore::empty(), 100),
+                #[cfg(feature = "orchard")]
+                orchard_tree: ShardTree::new(MemoryShardStore::empty(), 100),
             }
         }
     }
@@ -1219,6 +1720,18 @@ pub mod testing {
             Ok(None)
         }
 
+        fn plan_rewind<C: ChainView>(
+            &self,
+            conflict_height: BlockHeight,
+            _chain_view: &C,
+        ) -> Result<RewindPlan, RewindError<Self::Error, C::Error>> {
+            Ok(RewindPlan::from_parts(
+                conflict_height.saturating_sub(1),
+                vec![],
+                vec![],
+            ))
+        }
+
         fn get_tx_height(&self, _txid: TxId) -> Result<Option<BlockHeight>, Self::Error> {
             Ok(None)
         }
@@ -1251,6 +1764,21 @@ pub mod testing {
             Ok(None)
         }
 
+        fn get_account_kind(
+            &self,
+            _account: AccountId,
+        ) -> Result<Option<AccountKind>, Self::Error> {
+            Ok(None)
+        }
+
+        fn get_derived_account(
+            &self,
+            _seed_fingerprint: &SeedFingerprint,
+            _account_index: AccountId,
+        ) -> Result<Option<AccountId>, Self::Error> {
+            Ok(None)
+        }
+
         fn get_wallet_summary(
             &self,
             _min_confirmations: u32,
@@ -1266,6 +1794,20 @@ pub mod testing {
             Err(())
         }
 
+        fn get_unmined_sent_transactions(
+            &self,
+        ) -> Result<Vec<UnminedSentTransaction>, Self::Error> {
+            Ok(Vec::new())
+        }
+
+        fn get_transactions(
+            &self,
+            _account: AccountId,
+            _filter: &TransactionFilter,
+        ) -> Result<Vec<TransactionSummary>, Self::Error> {
+            Ok(Vec::new())
+        }
+
         fn get_sapling_nullifiers(
             &self,
             _query: NullifierQuery,
@@ -1315,6 +1857,14 @@ pub mod testing {
                 .map_err(|_| ())
         }
 
+        fn import_account_ufvk(
+            &mut self,
+            _ufvk: &UnifiedFullViewingKey,
+            _birthday: AccountBirthday,
+        ) -> Result<AccountId, Self::Error> {
+            Ok(AccountId::ZERO)
+        }
+
         fn get_next_available_address(
             &mut self,
             _account: AccountId,
@@ -1346,6 +1896,10 @@ pub mod testing {
             Ok(())
         }
 
+        fn abandon_transaction(&mut self, _txid: TxId) -> Result<(), Self::Error> {
+            Err(())
+        }
+
         fn truncate_to_height(&mut self, _block_height: BlockHeight) -> Result<(), Self::Error> {
             Ok(())
         }
@@ -1362,6 +1916,9 @@ pub mod testing {
     impl WalletCommitmentTrees for MockWalletDb {
         type Error = Infallible;
         type SaplingShardStore<'a> = MemoryShardStore<sapling::Node, BlockHeight>;
+        #[cfg(feature = "orchard")]
+        type OrchardShardStore<'a> =
+            MemoryShardStore<orchard::tree::MerkleHashOrchard, BlockHeight>;
 
         fn with_sapling_tree_mut<F, A, E>(&mut self, mut callback: F) -> Result<A, E>
         where
@@ -1393,5 +1950,38 @@ pub mod testing {
 
             Ok(())
         }
+
+        #[cfg(feature = "orchard")]
+        fn with_orchard_tree_mut<F, A, E>(&mut self, mut callback: F) -> Result<A, E>
+        where
+            for<'a> F: FnMut(
+                &'a mut ShardTree<
+                    Self::OrchardShardStore<'a>,
+                    { ORCHARD_SHARD_HEIGHT * 2 },
+                    ORCHARD_SHARD_HEIGHT,
+                >,
+            ) -> Result<A, E>,
+            E: From<ShardTreeError<Self::Error>>,
+        {
+            callback(&mut self.orchard_tree)
+        }
+
+        #[cfg(feature = "orchard")]
+        fn put_orchard_subtree_roots(
+            &mut self,
+            start_index: u64,
+            roots: &[CommitmentTreeRoot<orchard::tree::MerkleHashOrchard>],
+        ) -> Result<(), ShardTreeError<Self::Error>> {
+            self.with_orchard_tree_mut(|t| {
+                for (root, i) in roots.iter().zip(0u64..) {
+                    let root_addr =
+                        Address::from_parts(ORCHARD_SHARD_HEIGHT.into(), start_index + i);
+                    t.insert(root_addr, *root.root_hash())?;
+                }
+                Ok::<_, ShardTreeError<Self::Error>>(())
+            })?;
+
+            Ok(())
+        }
     }
 }
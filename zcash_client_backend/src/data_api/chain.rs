@@ -1,6 +1,9 @@
 #![allow(clippy::needless_doctest_main)]
 //! Tools for blockchain validation & scanning
 //!
+//! The example below illustrates the steps required to synchronize a wallet with the chain.
+//! [`crate::sync::run`] provides a reusable implementation of this process.
+//!
 //! # Examples
 //!
 //! ```
@@ -86,6 +89,8 @@
 //!                     // the height at which the error occurred, but may be an earlier height
 //!                     // determined based on heuristics such as the platform, available bandwidth,
 //!                     // size of recent CompactBlocks, etc.
+//!                     // `WalletRead::plan_rewind` can be used to find the precise fork point
+//!                     // given a view of the chain's block hashes.
 //!                     let rewind_height = err.at_height().saturating_sub(10);
 //!
 //!                     // Rewind to the chosen height.
@@ -145,21 +150,21 @@
 
 use std::ops::Range;
 
-use sapling::note_encryption::PreparedIncomingViewingKey;
 use zcash_primitives::{
+    block::BlockHash,
     consensus::{self, BlockHeight},
-    zip32::Scope,
+    transaction::TxId,
 };
 
 use crate::{
-    data_api::{NullifierQuery, WalletWrite},
+    data_api::{NullifierQuery, WalletRead, WalletWrite},
     proto::compact_formats::CompactBlock,
-    scan::BatchRunner,
-    scanning::{add_block_to_runner, scan_block_with_runner, ScanningKey},
+    scanning::{scan_block_with_runner, BatchRunners, UnifiedScanningKeys},
+    wallet::NoteId,
 };
 
 pub mod error;
-use error::Error;
+use error::{Error, RewindError};
 
 /// A struct containing metadata about a subtree root of the note commitment tree.
 ///
@@ -213,6 +218,101 @@ pub trait BlockSource {
         F: FnMut(CompactBlock) -> Result<(), error::Error<WalletErrT, Self::Error>>;
 }
 
+/// This trait provides access to the block hashes of the chain that the wallet is being
+/// synchronized against, such as the chain exposed by a `lightwalletd` server.
+///
+/// It is used by [`WalletRead::plan_rewind`] to locate the point at which the wallet's view of
+/// the chain diverges from the chain provided by the implementation.
+pub trait ChainView {
+    type Error;
+
+    /// Returns the hash of the block at the given height in the main chain, or `Ok(None)` if
+    /// the chain does not contain a block at that height.
+    fn block_hash(&self, height: BlockHeight) -> Result<Option<BlockHash>, Self::Error>;
+}
+
+/// A description of the effects of rewinding the wallet in response to a chain reorg.
+///
+/// Values of this type are produced by [`WalletRead::plan_rewind`]; passing
+/// [`RewindPlan::fork_height`] to [`WalletWrite::truncate_to_height`] will bring the wallet
+/// back into agreement with the chain view that was used to construct the plan.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RewindPlan {
+    fork_height: BlockHeight,
+    unmined_transactions: Vec<TxId>,
+    unmined_notes: Vec<NoteId>,
+}
+
+impl RewindPlan {
+    /// Constructs a new [`RewindPlan`] from its constituent parts.
+    pub fn from_parts(
+        fork_height: BlockHeight,
+        unmined_transactions: Vec<TxId>,
+        unmined_notes: Vec<NoteId>,
+    ) -> Self {
+        Self {
+            fork_height,
+            unmined_transactions,
+            unmined_notes,
+        }
+    }
+
+    /// Returns the height of the last block that the wallet has in common with the chain view.
+    /// This is the height to which the wallet should be truncated.
+    pub fn fork_height(&self) -> BlockHeight {
+        self.fork_height
+    }
+
+    /// Returns the identifiers of transactions that are currently recorded as mined above the
+    /// fork height, and which will be marked as unmined by the rewind.
+    pub fn unmined_transactions(&self) -> &[TxId] {
+        &self.unmined_transactions
+    }
+
+    /// Returns the identifiers of notes received in blocks above the fork height, which will be
+    /// removed from the wallet by the rewind and must be rediscovered by scanning.
+    pub fn unmined_notes(&self) -> &[NoteId] {
+        &self.unmined_notes
+    }
+}
+
+/// Finds the greatest height below `conflict_height` at which the block hash stored by the
+/// wallet agrees with the block hash provided by `chain_view`.
+///
+/// Heights for which the wallet has no stored block are skipped. The search does not descend
+/// below `min_height`; if no agreeing block is found at or above that height, this returns
+/// [`RewindError::RewindTooDeep`]. This is intended for use by implementations of
+/// [`WalletRead::plan_rewind`], which are responsible for determining `min_height` from the
+/// checkpoints retained in their note commitment trees.
+pub fn find_fork_point<DbT, ChainT>(
+    wallet_db: &DbT,
+    chain_view: &ChainT,
+    conflict_height: BlockHeight,
+    min_height: BlockHeight,
+) -> Result<BlockHeight, RewindError<DbT::Error, ChainT::Error>>
+where
+    DbT: WalletRead + ?Sized,
+    ChainT: ChainView,
+{
+    let mut height = conflict_height;
+    while height > min_height {
+        height = height - 1;
+        if let Some(wallet_hash) = wallet_db
+            .get_block_hash(height)
+            .map_err(RewindError::Wallet)?
+        {
+            if chain_view.block_hash(height).map_err(RewindError::Chain)? == Some(wallet_hash) {
+                return Ok(height);
+            }
+        }
+    }
+
+    Err(RewindError::RewindTooDeep {
+        conflict_height,
+        min_rewind_height: min_height,
+    })
+}
+
 /// Metadata about modifications to the wallet state made in the course of scanning a set of
 /// blocks.
 #[derive(Clone, Debug)]
@@ -220,6 +320,10 @@ pub struct ScanSummary {
     scanned_range: Range<BlockHeight>,
     spent_sapling_note_count: usize,
     received_sapling_note_count: usize,
+    #[cfg(feature = "orchard")]
+    spent_orchard_note_count: usize,
+    #[cfg(feature = "orchard")]
+    received_orchard_note_count: usize,
 }
 
 impl ScanSummary {
@@ -228,11 +332,17 @@ impl ScanSummary {
         scanned_range: Range<BlockHeight>,
         spent_sapling_note_count: usize,
         received_sapling_note_count: usize,
+        #[cfg(feature = "orchard")] spent_orchard_note_count: usize,
+        #[cfg(feature = "orchard")] received_orchard_note_count: usize,
     ) -> Self {
         Self {
             scanned_range,
             spent_sapling_note_count,
             received_sapling_note_count,
+            #[cfg(feature = "orchard")]
+            spent_orchard_note_count,
+            #[cfg(feature = "orchard")]
+            received_orchard_note_count,
         }
     }
 
@@ -256,6 +366,24 @@ impl ScanSummary {
     pub fn received_sapling_note_count(&self) -> usize {
         self.received_sapling_note_count
     }
+
+    /// Returns the number of our previously-detected Orchard notes that were spent in transactions
+    /// in blocks in the scanned range. If we have not yet detected a particular note as ours, for
+    /// example because we are scanning the chain in reverse height order, we will not detect it
+    /// being spent at this time.
+    #[cfg(feature = "orchard")]
+    pub fn spent_orchard_note_count(&self) -> usize {
+        self.spent_orchard_note_count
+    }
+
+    /// Returns the number of Orchard notes belonging to the wallet that were received in blocks in
+    /// the scanned range. Note that depending upon the scanning order, it is possible that some of
+    /// the received notes counted here may already have been spent in later blocks closer to the
+    /// chain tip.
+    #[cfg(feature = "orchard")]
+    pub fn received_orchard_note_count(&self) -> usize {
+        self.received_orchard_note_count
+    }
 }
 
 /// Scans at most `limit` blocks from the provided block source for in order to find transactions
@@ -282,39 +410,22 @@ where
     let ufvks = data_db
         .get_unified_full_viewing_keys()
         .map_err(Error::Wallet)?;
-    // TODO: Change `scan_block` to also scan Orchard.
-    // https://github.com/zcash/librustzcash/issues/403
-    let dfvks: Vec<_> = ufvks
-        .iter()
-        .filter_map(|(account, ufvk)| ufvk.sapling().map(move |k| (account, k)))
-        .collect();
     // Precompute the IVKs instead of doing so per block.
-    let ivks = dfvks
+    let scanning_keys = ufvks
         .iter()
-        .flat_map(|(account, dfvk)| {
-            dfvk.to_sapling_keys()
-                .into_iter()
-                .map(|key| (*account, key))
-        })
+        .map(|(account, ufvk)| (account, UnifiedScanningKeys::from_ufvk(ufvk)))
         .collect::<Vec<_>>();
 
     // Get the nullifiers for the unspent notes we are tracking
     let mut sapling_nullifiers = data_db
         .get_sapling_nullifiers(NullifierQuery::Unspent)
         .map_err(Error::Wallet)?;
+    #[cfg(feature = "orchard")]
+    let mut orchard_nullifiers = data_db
+        .get_orchard_nullifiers(NullifierQuery::Unspent)
+        .map_err(Error::Wallet)?;
 
-    let mut batch_runner = BatchRunner::<_, _, _, _, ()>::new(
-        100,
-        dfvks
-            .iter()
-            .flat_map(|(account, dfvk)| {
-                [
-                    ((**account, Scope::External), dfvk.to_ivk(Scope::External)),
-                    ((**account, Scope::Internal), dfvk.to_ivk(Scope::Internal)),
-                ]
-            })
-            .map(|(tag, ivk)| (tag, PreparedIncomingViewingKey::new(&ivk))),
-    );
+    let mut batch_runners = BatchRunners::<_, ()>::for_keys(100, &scanning_keys);
 
     let mut prior_block_metadata = if from_height > BlockHeight::from(0) {
         data_db
@@ -328,18 +439,22 @@ where
         Some(from_height),
         Some(limit),
         |block: CompactBlock| {
-            add_block_to_runner(params, block, &mut batch_runner);
+            batch_runners.add_block(params, block);
 
             Ok(())
         },
     )?;
 
-    batch_runner.flush();
+    batch_runners.flush();
 
     let mut scanned_blocks = vec![];
     let mut scan_end_height = from_height;
-    let mut received_note_count = 0;
-    let mut spent_note_count = 0;
+    let mut sapling_received_note_count = 0;
+    let mut sapling_spent_note_count = 0;
+    #[cfg(feature = "orchard")]
+    let mut orchard_received_note_count = 0;
+    #[cfg(feature = "orchard")]
+    let mut orchard_spent_note_count = 0;
     block_source.with_blocks::<_, DbT::Error>(
         Some(from_height),
         Some(limit),
@@ -348,10 +463,12 @@ where
             let scanned_block = scan_block_with_runner(
                 params,
                 block,
-                &ivks,
+                &scanning_keys,
                 &sapling_nullifiers,
+                #[cfg(feature = "orchard")]
+                &orchard_nullifiers,
                 prior_block_metadata.as_ref(),
-                Some(&mut batch_runner),
+                Some(&mut batch_runners),
             )
             .map_err(Error::Scan)?;
 
@@ -361,8 +478,8 @@ where
                 .fold((0, 0), |(s, r), wtx| {
                     (s + wtx.sapling_spends.len(), r + wtx.sapling_outputs.len())
                 });
-            spent_note_count += s;
-            received_note_count += r;
+            sapling_spent_note_count += s;
+            sapling_received_note_count += r;
 
             let spent_nf: Vec<&sapling::Nullifier> = scanned_block
                 .transactions
@@ -377,6 +494,31 @@ where
                     .map(|out| (out.account(), *out.nf()))
             }));
 
+            #[cfg(feature = "orchard")]
+            {
+                let (s, r) = scanned_block
+                    .transactions
+                    .iter()
+                    .fold((0, 0), |(s, r), wtx| {
+                        (s + wtx.orchard_spends.len(), r + wtx.orchard_outputs.len())
+                    });
+                orchard_spent_note_count += s;
+                orchard_received_note_count += r;
+
+                let spent_nf: Vec<&orchard::note::Nullifier> = scanned_block
+                    .transactions
+                    .iter()
+                    .flat_map(|tx| tx.orchard_spends.iter().map(|spend| spend.nf()))
+                    .collect();
+
+                orchard_nullifiers.retain(|(_, nf)| !spent_nf.contains(&nf));
+                orchard_nullifiers.extend(scanned_block.transactions.iter().flat_map(|tx| {
+                    tx.orchard_outputs
+                        .iter()
+                        .map(|out| (out.account(), *out.nf()))
+                }));
+            }
+
             prior_block_metadata = Some(scanned_block.to_block_metadata());
             scanned_blocks.push(scanned_block);
 
@@ -387,8 +529,12 @@ where
     data_db.put_blocks(scanned_blocks).map_err(Error::Wallet)?;
     Ok(ScanSummary::from_parts(
         from_height..scan_end_height,
-        spent_note_count,
-        received_note_count,
+        sapling_spent_note_count,
+        sapling_received_note_count,
+        #[cfg(feature = "orchard")]
+        orchard_spent_note_count,
+        #[cfg(feature = "orchard")]
+        orchard_received_note_count,
     ))
 }
 
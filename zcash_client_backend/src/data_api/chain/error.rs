@@ -3,6 +3,8 @@
 use std::error;
 use std::fmt::{self, Debug, Display};
 
+use zcash_primitives::consensus::BlockHeight;
+
 use crate::scanning::ScanError;
 
 /// Errors related to chain validation and scanning.
@@ -64,3 +66,61 @@ impl<WE, BSE> From<ScanError> for Error<WE, BSE> {
         Error::Scan(e)
     }
 }
+
+/// Errors that can occur while planning a rewind of the wallet in response to a chain reorg.
+#[derive(Debug)]
+pub enum RewindError<WalletError, ChainError> {
+    /// An error that was produced by wallet operations while planning the rewind.
+    Wallet(WalletError),
+
+    /// An error that was produced by the chain view while retrieving block hashes.
+    Chain(ChainError),
+
+    /// No block at or above `min_rewind_height` agrees with the chain view. The wallet's note
+    /// commitment trees do not retain checkpoints below this height, so it cannot be truncated
+    /// far enough to reach the fork point without corrupting those trees; the wallet must
+    /// instead be recovered from its birthday.
+    RewindTooDeep {
+        conflict_height: BlockHeight,
+        min_rewind_height: BlockHeight,
+    },
+}
+
+impl<WE: fmt::Display, CE: fmt::Display> fmt::Display for RewindError<WE, CE> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            RewindError::Wallet(e) => {
+                write!(
+                    f,
+                    "The underlying datasource produced the following error: {}",
+                    e
+                )
+            }
+            RewindError::Chain(e) => {
+                write!(f, "The chain view produced the following error: {}", e)
+            }
+            RewindError::RewindTooDeep {
+                conflict_height,
+                min_rewind_height,
+            } => write!(
+                f,
+                "No common ancestor for the chain conflict at height {} was found at or above the minimum rewind height {}",
+                conflict_height, min_rewind_height
+            ),
+        }
+    }
+}
+
+impl<WE, CE> error::Error for RewindError<WE, CE>
+where
+    WE: Debug + Display + error::Error + 'static,
+    CE: Debug + Display + error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self {
+            RewindError::Wallet(e) => Some(e),
+            RewindError::Chain(e) => Some(e),
+            _ => None,
+        }
+    }
+}
@@ -13,7 +13,9 @@ use zcash_primitives::{
     zip32::AccountId,
 };
 
-use crate::data_api::wallet::input_selection::InputSelectorError;
+use crate::data_api::wallet::input_selection::{
+    InputSelectorError, PrivacyPolicy, PrivacyViolation,
+};
 use crate::PoolType;
 
 #[cfg(feature = "transparent-inputs")]
@@ -39,6 +41,10 @@ pub enum Error<DataSourceError, CommitmentTreeError, SelectionError, FeeError> {
     /// No account with the given identifier was found in the wallet.
     AccountNotFound(AccountId),
 
+    /// The wallet does not hold spending authority for the given account, because the account
+    /// was imported as a view-only account.
+    AccountCannotSpend(AccountId),
+
     /// Zcash amount computation encountered an overflow or underflow.
     BalanceError(BalanceError),
 
@@ -52,6 +58,13 @@ pub enum Error<DataSourceError, CommitmentTreeError, SelectionError, FeeError> {
     /// operations can be performed.
     ScanRequired,
 
+    /// The requested transaction cannot be created without revealing information that is
+    /// forbidden by the provided privacy policy.
+    PrivacyPolicyViolation {
+        policy: PrivacyPolicy,
+        violation: PrivacyViolation,
+    },
+
     /// An error occurred building a new transaction.
     Builder(builder::Error<FeeError>),
 
@@ -68,6 +81,22 @@ pub enum Error<DataSourceError, CommitmentTreeError, SelectionError, FeeError> {
     /// full viewing key for an account.
     NoteMismatch(NoteId),
 
+    /// A transaction could not be extracted from a partially created transaction, because
+    /// it has not yet been signed.
+    PcztNotSigned,
+
+    /// A recipient address recorded in a partially created transaction could not be decoded
+    /// for the network on which the wallet operates.
+    PcztInvalidRecipient(String),
+
+    /// The outputs of a signed partially created transaction do not correspond to the
+    /// outputs that it describes.
+    PcztOutputMismatch,
+
+    /// The proposal contains a step whose construction is not supported by the wallet, such
+    /// as one that spends a shielded output of a prior step of the proposal.
+    ProposalNotSupported,
+
     #[cfg(feature = "transparent-inputs")]
     AddressNotRecognized(TransparentAddress),
 
@@ -106,6 +135,11 @@ where
             Error::AccountNotFound(account) => {
                 write!(f, "Wallet does not contain account {}", u32::from(*account))
             }
+            Error::AccountCannotSpend(account) => write!(
+                f,
+                "Account {} is a view-only account; the wallet cannot spend its funds",
+                u32::from(*account)
+            ),
             Error::BalanceError(e) => write!(
                 f,
                 "The value lies outside the valid range of Zcash amounts: {:?}.",
@@ -118,11 +152,16 @@ where
                 u64::from(*required)
             ),
             Error::ScanRequired => write!(f, "Must scan blocks first"),
+            Error::PrivacyPolicyViolation { policy, violation } => write!(f, "The transaction would violate the {:?} privacy policy: {}", policy, violation),
             Error::Builder(e) => write!(f, "An error occurred building the transaction: {}", e),
             Error::MemoForbidden => write!(f, "It is not possible to send a memo to a transparent address."),
             Error::UnsupportedPoolType(t) => write!(f, "Attempted to send to an unsupported pool: {}", t),
             Error::NoSupportedReceivers(t) => write!(f, "Unified address contained only unsupported receiver types: {:?}", &t[..]),
             Error::NoteMismatch(n) => write!(f, "A note being spent ({:?}) does not correspond to either the internal or external full viewing key for the provided spending key.", n),
+            Error::PcztNotSigned => write!(f, "The partially created transaction has not been signed."),
+            Error::PcztInvalidRecipient(a) => write!(f, "The partially created transaction contains an invalid recipient address: {}", a),
+            Error::PcztOutputMismatch => write!(f, "The signed transaction does not contain the outputs described by the partially created transaction."),
+            Error::ProposalNotSupported => write!(f, "The proposal contains a step that the wallet is not able to construct."),
 
             #[cfg(feature = "transparent-inputs")]
             Error::AddressNotRecognized(_) => {
@@ -183,6 +222,9 @@ impl<DE, CE, SE, FE> From<InputSelectorError<DE, SE>> for Error<DE, CE, SE, FE>
                 required,
             },
             InputSelectorError::SyncRequired => Error::ScanRequired,
+            InputSelectorError::PrivacyPolicyViolation { policy, violation } => {
+                Error::PrivacyPolicyViolation { policy, violation }
+            }
         }
     }
 }
@@ -0,0 +1,2983 @@
+//! An in-memory implementation of the wallet data access traits.
+//!
+//! [`MemoryWalletDb`] holds all of its state in memory and implements [`InputSource`],
+//! [`WalletRead`], [`WalletWrite`] and [`WalletCommitmentTrees`], storing its note commitment
+//! trees in [`MemoryShardStore`]s. It follows the semantics of the `zcash_client_sqlite`
+//! backend, and is intended for testing backend-independent wallet logic and for short-lived
+//! wallets that do not need to persist their state; everything is lost when the wallet is
+//! dropped.
+
+use std::{
+    cmp::{max, min, Reverse},
+    collections::{btree_map::Entry, BTreeMap, BTreeSet, HashMap},
+    convert::Infallible,
+    error, fmt, io,
+    num::NonZeroU32,
+    ops::Range,
+};
+
+use incrementalmerkletree::{Address, Position, Retention};
+use secrecy::{ExposeSecret, SecretVec};
+use shardtree::{
+    error::ShardTreeError,
+    store::{memory::MemoryShardStore, ShardStore},
+    ShardTree,
+};
+use zcash_primitives::{
+    block::BlockHash,
+    consensus::{self, BlockHeight, BranchId, NetworkUpgrade},
+    legacy::TransparentAddress,
+    memo::{self, Memo, MemoBytes},
+    transaction::{
+        components::{
+            amount::{Amount, BalanceError, NonNegativeAmount},
+            OutPoint,
+        },
+        Transaction, TxId,
+    },
+    zip32::{AccountId, DiversifierIndex, Scope},
+};
+
+use crate::{
+    address::{AddressMetadata, UnifiedAddress},
+    encoding::AddressCodec,
+    keys::{SeedFingerprint, UnifiedAddressRequest, UnifiedFullViewingKey, UnifiedSpendingKey},
+    wallet::{Note, NoteId, ReceivedNote, Recipient, WalletTransparentOutput},
+    DecryptedOutput, PoolType, ShieldedProtocol, TransferType,
+};
+
+use super::{
+    chain::{error::RewindError, find_fork_point, ChainView, CommitmentTreeRoot, RewindPlan},
+    scanning::{spanning_tree::SpanningTree, ScanPriority, ScanRange},
+    AccountBalance, AccountBirthday, AccountKind, Balance, BlockMetadata, DecryptedTransaction,
+    InputSource, NullifierQuery, OutputSummary, Ratio, ScannedBlock, SentTransaction,
+    TransactionFilter, TransactionSummary, UnminedSentTransaction, WalletCommitmentTrees,
+    WalletRead, WalletSummary, WalletWrite, SAPLING_SHARD_HEIGHT,
+};
+
+#[cfg(feature = "orchard")]
+use super::ORCHARD_SHARD_HEIGHT;
+
+/// The number of blocks below the chain tip for which note commitment tree checkpoints and
+/// nullifier map entries are retained, and within which the chain is considered subject to
+/// reorgs.
+const PRUNING_DEPTH: u32 = 100;
+
+/// The number of blocks above the maximum scanned height that are prioritized for
+/// verification when the chain tip is updated after a long period offline.
+const VERIFY_LOOKAHEAD: u32 = 10;
+
+#[cfg(not(feature = "transparent-inputs"))]
+const UA_TRANSPARENT: bool = false;
+#[cfg(feature = "transparent-inputs")]
+const UA_TRANSPARENT: bool = true;
+
+const DEFAULT_UA_REQUEST: UnifiedAddressRequest =
+    UnifiedAddressRequest::unsafe_new(false, true, UA_TRANSPARENT);
+
+type SaplingTree = ShardTree<
+    MemoryShardStore<sapling::Node, BlockHeight>,
+    { sapling::NOTE_COMMITMENT_TREE_DEPTH },
+    SAPLING_SHARD_HEIGHT,
+>;
+
+#[cfg(feature = "orchard")]
+type OrchardTree = ShardTree<
+    MemoryShardStore<orchard::tree::MerkleHashOrchard, BlockHeight>,
+    { ORCHARD_SHARD_HEIGHT * 2 },
+    ORCHARD_SHARD_HEIGHT,
+>;
+
+/// The errors that may be produced by operations on a [`MemoryWalletDb`].
+#[derive(Debug)]
+pub enum Error {
+    /// The wallet already tracks the viewing key being added, as the given account.
+    AccountCollision(AccountId),
+
+    /// The account being referenced is not known to the wallet.
+    AccountUnknown(AccountId),
+
+    /// The wallet has run out of account identifiers.
+    AccountIdOutOfRange,
+
+    /// A spending key could not be derived from the provided seed at the given ZIP 32 account
+    /// index.
+    KeyDerivation(AccountId),
+
+    /// The space of available diversifier indices for an account is exhausted.
+    DiversifierIndexOutOfRange,
+
+    /// The blocks provided to [`WalletWrite::put_blocks`] were not sequential.
+    NonSequentialBlocks,
+
+    /// A rewind was requested to a height that lies between the wallet's minimum unspent note
+    /// height (the first value) and the pruning depth; the second value is the requested height.
+    RequestedRewindInvalid(BlockHeight, BlockHeight),
+
+    /// The transaction being referenced is not known to the wallet, or its raw data is not
+    /// available.
+    TransactionNotFound(TxId),
+
+    /// The wallet does not contain an unmined transaction with the given ID.
+    UnminedTransactionNotFound(TxId),
+
+    /// A stored memo could not be decoded.
+    InvalidMemo(memo::Error),
+
+    /// A transaction could not be serialized or parsed.
+    Io(io::Error),
+
+    /// A balance computation overflowed or underflowed.
+    Balance(BalanceError),
+
+    /// An error occurred in updating a note commitment tree.
+    CommitmentTree(ShardTreeError<Infallible>),
+
+    /// A transparent key could not be derived for an account.
+    #[cfg(feature = "transparent-inputs")]
+    HdwalletError(hdwallet::error::Error),
+
+    /// A transparent output was received at an address that is not known to the wallet.
+    #[cfg(feature = "transparent-inputs")]
+    AddressNotRecognized(TransparentAddress),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::AccountCollision(account) => write!(
+                f,
+                "The wallet already tracks this viewing key as account {}.",
+                u32::from(*account)
+            ),
+            Error::AccountUnknown(account) => write!(
+                f,
+                "Account {} does not belong to this wallet.",
+                u32::from(*account)
+            ),
+            Error::AccountIdOutOfRange => write!(
+                f,
+                "Wallet account identifiers must be less than 0x7FFFFFFF."
+            ),
+            Error::KeyDerivation(account) => write!(
+                f,
+                "Key derivation failed for account index {}",
+                u32::from(*account)
+            ),
+            Error::DiversifierIndexOutOfRange => write!(
+                f,
+                "The space of available diversifier indices is exhausted"
+            ),
+            Error::NonSequentialBlocks => write!(
+                f,
+                "`put_blocks` requires that the provided block range be sequential"
+            ),
+            Error::RequestedRewindInvalid(h, r) => write!(
+                f,
+                "A rewind must be either of less than {} blocks, or at least back to block {} for your wallet; the requested height was {}.",
+                PRUNING_DEPTH, h, r
+            ),
+            Error::TransactionNotFound(txid) => {
+                write!(f, "The wallet does not contain transaction {}", txid)
+            }
+            Error::UnminedTransactionNotFound(txid) => write!(
+                f,
+                "The wallet does not contain an unmined transaction with ID {}",
+                txid
+            ),
+            Error::InvalidMemo(e) => write!(f, "{}", e),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Balance(e) => write!(f, "Balance error: {}", e),
+            Error::CommitmentTree(e) => write!(
+                f,
+                "An error occurred updating note commitment tree data: {}.",
+                e
+            ),
+            #[cfg(feature = "transparent-inputs")]
+            Error::HdwalletError(e) => write!(f, "{:?}", e),
+            #[cfg(feature = "transparent-inputs")]
+            Error::AddressNotRecognized(_) => write!(
+                f,
+                "The address associated with a received txo is not identifiable as belonging to the wallet."
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::InvalidMemo(e) => Some(e),
+            Error::Io(e) => Some(e),
+            Error::Balance(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<memo::Error> for Error {
+    fn from(e: memo::Error) -> Self {
+        Error::InvalidMemo(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<BalanceError> for Error {
+    fn from(e: BalanceError) -> Self {
+        Error::Balance(e)
+    }
+}
+
+impl From<ShardTreeError<Infallible>> for Error {
+    fn from(e: ShardTreeError<Infallible>) -> Self {
+        Error::CommitmentTree(e)
+    }
+}
+
+struct Account {
+    ufvk: UnifiedFullViewingKey,
+    kind: AccountKind,
+    birthday: BlockHeight,
+    /// The addresses that have been generated for the account, keyed by the big-endian
+    /// encoding of their diversifier indices so that the most recent address sorts last.
+    addresses: BTreeMap<[u8; 11], UnifiedAddress>,
+}
+
+struct Block {
+    hash: BlockHash,
+    time: u32,
+    sapling_tree_size: u32,
+    sapling_output_count: u32,
+    #[cfg(feature = "orchard")]
+    orchard_tree_size: u32,
+}
+
+struct TransactionRecord {
+    /// The order in which the wallet first learned of the transaction.
+    seq: usize,
+    mined_height: Option<BlockHeight>,
+    tx_index: Option<usize>,
+    expiry_height: Option<BlockHeight>,
+    fee: Option<NonNegativeAmount>,
+    created: Option<time::OffsetDateTime>,
+    raw: Option<(Vec<u8>, BranchId)>,
+}
+
+struct ReceivedNoteRecord {
+    /// The order in which the wallet first learned of the note.
+    seq: u64,
+    account: AccountId,
+    note: Note,
+    nf: Option<[u8; 32]>,
+    is_change: bool,
+    memo: Option<MemoBytes>,
+    position: Option<Position>,
+    scope: Scope,
+    spent: Option<TxId>,
+}
+
+struct SentOutputRecord {
+    from_account: AccountId,
+    to_address: Option<String>,
+    to_account: Option<AccountId>,
+    value: NonNegativeAmount,
+    memo: Option<MemoBytes>,
+}
+
+#[derive(Clone, Copy)]
+struct TxLocator {
+    block_height: BlockHeight,
+    tx_index: u16,
+    txid: TxId,
+}
+
+#[cfg(feature = "transparent-inputs")]
+struct ReceivedUtxo {
+    account: AccountId,
+    output: WalletTransparentOutput,
+    spent: Option<TxId>,
+}
+
+/// The block height extent of a note commitment tree shard, along with the scan state of the
+/// blocks that it spans.
+struct ShardScanState {
+    positions: Range<u64>,
+    /// The maximum priority of the scan ranges overlapping the shard, if any.
+    max_priority: Option<ScanPriority>,
+    /// The unscanned ranges overlapping the shard that end above the wallet birthday.
+    unscanned: Vec<Range<BlockHeight>>,
+    start_height: BlockHeight,
+    end_height: Option<BlockHeight>,
+}
+
+impl ShardScanState {
+    fn overlaps(&self, range: &Range<BlockHeight>) -> bool {
+        self.start_height < range.end && self.end_height.map_or(true, |end| range.start <= end)
+    }
+
+    fn contains_height(&self, height: BlockHeight) -> bool {
+        self.start_height <= height && self.end_height.map_or(true, |end| height <= end)
+    }
+}
+
+/// A wallet backend that holds all of its data in memory.
+///
+/// See the [module documentation](self) for details.
+pub struct MemoryWalletDb<P> {
+    params: P,
+    accounts: BTreeMap<AccountId, Account>,
+    blocks: BTreeMap<BlockHeight, Block>,
+    transactions: HashMap<TxId, TransactionRecord>,
+    received_notes: BTreeMap<NoteId, ReceivedNoteRecord>,
+    next_note_seq: u64,
+    sent_outputs: BTreeMap<(TxId, PoolType, u32), SentOutputRecord>,
+    nullifier_map: BTreeMap<(ShieldedProtocol, [u8; 32]), TxLocator>,
+    #[cfg(feature = "transparent-inputs")]
+    utxos: BTreeMap<OutPoint, ReceivedUtxo>,
+    /// The scan queue, as a list of non-overlapping ranges ordered by start height.
+    scan_queue: Vec<ScanRange>,
+    sapling_tree: SaplingTree,
+    sapling_subtree_end_heights: BTreeMap<u64, BlockHeight>,
+    #[cfg(feature = "orchard")]
+    orchard_tree: OrchardTree,
+    #[cfg(feature = "orchard")]
+    orchard_subtree_end_heights: BTreeMap<u64, BlockHeight>,
+}
+
+impl<P: consensus::Parameters> MemoryWalletDb<P> {
+    /// Constructs a new, empty wallet for the given network.
+    ///
+    /// At most `max_checkpoints` checkpoints will be retained in each of the wallet's note
+    /// commitment trees; this bounds the depth of the reorgs that the wallet can recover from.
+    pub fn new(params: P, max_checkpoints: usize) -> Self {
+        Self {
+            params,
+            accounts: BTreeMap::new(),
+            blocks: BTreeMap::new(),
+            transactions: HashMap::new(),
+            received_notes: BTreeMap::new(),
+            next_note_seq: 0,
+            sent_outputs: BTreeMap::new(),
+            nullifier_map: BTreeMap::new(),
+            #[cfg(feature = "transparent-inputs")]
+            utxos: BTreeMap::new(),
+            scan_queue: vec![],
+            sapling_tree: ShardTree::new(MemoryShardStore::empty(), max_checkpoints),
+            sapling_subtree_end_heights: BTreeMap::new(),
+            #[cfg(feature = "orchard")]
+            orchard_tree: ShardTree::new(MemoryShardStore::empty(), max_checkpoints),
+            #[cfg(feature = "orchard")]
+            orchard_subtree_end_heights: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the network parameters that the wallet was constructed with.
+    pub fn params(&self) -> &P {
+        &self.params
+    }
+
+    fn sapling_activation_height(&self) -> BlockHeight {
+        self.params
+            .activation_height(NetworkUpgrade::Sapling)
+            .expect("Sapling activation height must be available.")
+    }
+
+    fn wallet_birthday(&self) -> Option<BlockHeight> {
+        self.accounts.values().map(|a| a.birthday).min()
+    }
+
+    fn chain_tip(&self) -> Option<BlockHeight> {
+        self.scan_queue
+            .iter()
+            .map(|r| r.block_range().end)
+            .max()
+            .map(|end| end - 1)
+    }
+
+    fn max_scanned_height(&self) -> Option<BlockHeight> {
+        self.blocks.keys().next_back().copied()
+    }
+
+    fn block_metadata_at(&self, height: BlockHeight) -> Option<BlockMetadata> {
+        self.blocks.get(&height).map(|block| {
+            BlockMetadata::from_parts(
+                height,
+                block.hash,
+                Some(block.sapling_tree_size),
+                #[cfg(feature = "orchard")]
+                Some(block.orchard_tree_size),
+            )
+        })
+    }
+
+    fn mined_height(&self, txid: &TxId) -> Option<BlockHeight> {
+        self.transactions.get(txid).and_then(|tx| tx.mined_height)
+    }
+
+    /// Returns the record for the given transaction, creating an empty record if the
+    /// transaction is not yet known to the wallet.
+    fn tx_record_mut(&mut self, txid: TxId) -> &mut TransactionRecord {
+        let seq = self.transactions.len();
+        self.transactions
+            .entry(txid)
+            .or_insert_with(|| TransactionRecord {
+                seq,
+                mined_height: None,
+                tx_index: None,
+                expiry_height: None,
+                fee: None,
+                created: None,
+                raw: None,
+            })
+    }
+
+    fn put_tx_meta(&mut self, txid: TxId, height: BlockHeight, tx_index: usize) {
+        let tx = self.tx_record_mut(txid);
+        tx.mined_height = Some(height);
+        tx.tx_index = Some(tx_index);
+    }
+
+    fn put_tx_data(
+        &mut self,
+        tx: &Transaction,
+        fee: Option<NonNegativeAmount>,
+        created: Option<time::OffsetDateTime>,
+    ) -> Result<TxId, Error> {
+        let txid = tx.txid();
+        let mut raw = vec![];
+        tx.write(&mut raw)?;
+
+        let is_new = !self.transactions.contains_key(&txid);
+        let record = self.tx_record_mut(txid);
+        record.expiry_height = Some(tx.expiry_height());
+        record.raw = Some((raw, tx.consensus_branch_id()));
+        if fee.is_some() {
+            record.fee = fee;
+        }
+        if is_new {
+            record.created = created;
+        }
+
+        Ok(txid)
+    }
+
+    /// Inserts or updates a received note. When updating, values that are not known to the
+    /// caller (such as the nullifier of a note detected by decrypting a mempool transaction)
+    /// do not overwrite previously stored values.
+    #[allow(clippy::too_many_arguments)]
+    fn put_received_note(
+        &mut self,
+        note_id: NoteId,
+        account: AccountId,
+        note: Note,
+        nf: Option<[u8; 32]>,
+        is_change: bool,
+        memo: Option<MemoBytes>,
+        position: Option<Position>,
+        scope: Scope,
+        spent: Option<TxId>,
+    ) {
+        match self.received_notes.entry(note_id) {
+            Entry::Occupied(mut e) => {
+                let existing = e.get_mut();
+                existing.account = account;
+                existing.note = note;
+                existing.nf = nf.or(existing.nf);
+                existing.is_change = is_change;
+                existing.memo = memo.or_else(|| existing.memo.take());
+                existing.position = position.or(existing.position);
+                existing.scope = scope;
+                existing.spent = spent.or(existing.spent);
+            }
+            Entry::Vacant(e) => {
+                e.insert(ReceivedNoteRecord {
+                    seq: self.next_note_seq,
+                    account,
+                    note,
+                    nf,
+                    is_change,
+                    memo,
+                    position,
+                    scope,
+                    spent,
+                });
+                self.next_note_seq += 1;
+            }
+        }
+    }
+
+    /// Records a note received in a mempool or sent transaction, given its decrypted output.
+    fn put_decrypted_note<N>(
+        &mut self,
+        txid: TxId,
+        protocol: ShieldedProtocol,
+        output: &DecryptedOutput<N>,
+        note: Note,
+    ) {
+        let is_internal = output.transfer_type == TransferType::WalletInternal;
+        self.put_received_note(
+            NoteId::new(
+                txid,
+                protocol,
+                u16::try_from(output.index).expect("output indices are representable as u16"),
+            ),
+            output.account,
+            note,
+            None,
+            is_internal,
+            Some(output.memo.clone()),
+            None,
+            if is_internal {
+                Scope::Internal
+            } else {
+                Scope::External
+            },
+            None,
+        );
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn put_sent_output(
+        &mut self,
+        txid: TxId,
+        from_account: AccountId,
+        output_index: usize,
+        recipient: &Recipient,
+        value: NonNegativeAmount,
+        memo: Option<&MemoBytes>,
+    ) {
+        let (to_address, to_account, pool) = match recipient {
+            Recipient::Transparent(addr) => {
+                (Some(addr.encode(&self.params)), None, PoolType::Transparent)
+            }
+            Recipient::Sapling(addr) => (
+                Some(addr.encode(&self.params)),
+                None,
+                PoolType::Shielded(ShieldedProtocol::Sapling),
+            ),
+            Recipient::Unified(addr, pool) => (Some(addr.encode(&self.params)), None, *pool),
+            Recipient::InternalAccount(id, pool) => (None, Some(*id), *pool),
+        };
+        let output_index =
+            u32::try_from(output_index).expect("output indices are representable as u32");
+
+        match self.sent_outputs.entry((txid, pool, output_index)) {
+            Entry::Occupied(mut e) => {
+                let existing = e.get_mut();
+                existing.from_account = from_account;
+                existing.to_address = to_address;
+                existing.to_account = to_account;
+                existing.value = value;
+                if let Some(memo) = memo {
+                    existing.memo = Some(memo.clone());
+                }
+            }
+            Entry::Vacant(e) => {
+                e.insert(SentOutputRecord {
+                    from_account,
+                    to_address,
+                    to_account,
+                    value,
+                    memo: memo.cloned(),
+                });
+            }
+        }
+    }
+
+    /// Marks the note with the given nullifier (if it belongs to the wallet) as spent in the
+    /// given transaction.
+    fn mark_note_spent(&mut self, protocol: ShieldedProtocol, nf: &[u8; 32], txid: TxId) {
+        if let Some(note) = self
+            .received_notes
+            .iter_mut()
+            .find(|(id, note)| id.protocol() == protocol && note.nf.as_ref() == Some(nf))
+            .map(|(_, note)| note)
+        {
+            note.spent = Some(txid);
+        }
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn mark_utxo_spent(&mut self, outpoint: &OutPoint, txid: TxId) {
+        if let Some(utxo) = self.utxos.get_mut(outpoint) {
+            utxo.spent = Some(txid);
+        }
+    }
+
+    /// Looks up the transaction that spent the given nullifier in a previously scanned block,
+    /// creating a record for that transaction if necessary.
+    fn query_nullifier_map(&mut self, protocol: ShieldedProtocol, nf: &[u8; 32]) -> Option<TxId> {
+        let locator = *self.nullifier_map.get(&(protocol, *nf))?;
+        self.put_tx_meta(locator.txid, locator.block_height, locator.tx_index.into());
+        Some(locator.txid)
+    }
+
+    fn insert_nullifier_map(
+        &mut self,
+        block_height: BlockHeight,
+        protocol: ShieldedProtocol,
+        entries: impl Iterator<Item = (TxId, u16, [u8; 32])>,
+    ) {
+        for (txid, tx_index, nf) in entries {
+            self.nullifier_map.insert(
+                (protocol, nf),
+                TxLocator {
+                    block_height,
+                    tx_index,
+                    txid,
+                },
+            );
+        }
+    }
+
+    /// Releases notes that were marked as spent by transactions that expired without being
+    /// mined.
+    fn update_expired_notes(&mut self, expiry_height: BlockHeight) {
+        let transactions = &self.transactions;
+        for note in self.received_notes.values_mut() {
+            let expired = note
+                .spent
+                .and_then(|txid| transactions.get(&txid))
+                .map_or(false, |tx| {
+                    tx.mined_height.is_none()
+                        && tx.expiry_height.map_or(false, |h| h < expiry_height)
+                });
+            if expired {
+                note.spent = None;
+            }
+        }
+    }
+
+    /// Replaces the scan queue entries overlapping or adjacent to `query_range` with the result
+    /// of merging them with `entries`.
+    fn replace_queue_entries(
+        &mut self,
+        query_range: &Range<BlockHeight>,
+        entries: impl Iterator<Item = ScanRange>,
+        force_rescans: bool,
+    ) {
+        let (mut existing, rest): (Vec<_>, Vec<_>) = self.scan_queue.drain(..).partition(|range| {
+            !(range.block_range().start > query_range.end
+                || query_range.start > range.block_range().end)
+        });
+        existing.sort_by_key(|range| range.block_range().end);
+
+        let mut to_create: Option<SpanningTree> = None;
+        for entry in existing.into_iter().chain(entries) {
+            to_create = Some(match to_create {
+                Some(cur) => cur.insert(entry, force_rescans),
+                None => SpanningTree::Leaf(entry),
+            });
+        }
+
+        self.scan_queue = rest;
+        if let Some(tree) = to_create {
+            self.scan_queue.extend(
+                tree.into_vec()
+                    .into_iter()
+                    .filter(|range| !range.is_empty()),
+            );
+        }
+        self.scan_queue
+            .sort_by_key(|range| range.block_range().start);
+    }
+
+    fn add_account(
+        &mut self,
+        account: AccountId,
+        ufvk: UnifiedFullViewingKey,
+        kind: AccountKind,
+        birthday: AccountBirthday,
+    ) -> Result<(), Error> {
+        if let Some(existing) = self.get_account_for_ufvk(&ufvk)? {
+            return Err(Error::AccountCollision(existing));
+        }
+
+        // If a birthday frontier is available, insert it into the note commitment tree. If the
+        // birthday frontier is the empty frontier, we don't need to do anything.
+        if let Some(frontier) = birthday.sapling_frontier().value() {
+            self.sapling_tree.insert_frontier_nodes(
+                frontier.clone(),
+                Retention::Checkpoint {
+                    id: birthday.height() - 1,
+                    is_marked: false,
+                },
+            )?;
+        }
+
+        let (addr, diversifier_index) = ufvk.default_address(DEFAULT_UA_REQUEST);
+        let mut addresses = BTreeMap::new();
+        addresses.insert(diversifier_index_be(&diversifier_index), addr);
+
+        self.accounts.insert(
+            account,
+            Account {
+                ufvk,
+                kind,
+                birthday: birthday.height(),
+                addresses,
+            },
+        );
+
+        // Add the ignored range up to the birthday height.
+        let sapling_activation_height = self.sapling_activation_height();
+        if sapling_activation_height < birthday.height() {
+            let ignored_range = sapling_activation_height..birthday.height();
+            self.replace_queue_entries(
+                &ignored_range,
+                Some(ScanRange::from_parts(
+                    ignored_range.clone(),
+                    ScanPriority::Ignored,
+                ))
+                .into_iter(),
+                false,
+            );
+        }
+
+        // Rewrite the scan ranges from the birthday height up to the chain tip so that we'll
+        // re-scan to find any notes that might belong to the newly added account.
+        if let Some(t) = self.chain_tip() {
+            let rescan_range = birthday.height()..(t + 1);
+            self.replace_queue_entries(
+                &rescan_range,
+                Some(ScanRange::from_parts(
+                    rescan_range.clone(),
+                    ScanPriority::Historic,
+                ))
+                .into_iter(),
+                true,
+            );
+        }
+
+        Ok(())
+    }
+
+    fn next_account_id(&self) -> Result<AccountId, Error> {
+        self.accounts
+            .keys()
+            .next_back()
+            .map(|a| a.next().ok_or(Error::AccountIdOutOfRange))
+            .transpose()
+            .map(|a| a.unwrap_or(AccountId::ZERO))
+    }
+
+    fn scan_complete(&mut self, range: Range<BlockHeight>, wallet_note_positions: &[Position]) {
+        let wallet_birthday = self.wallet_birthday();
+
+        // If notes have been detected in the scan, we need to extend any adjacent un-scanned
+        // ranges starting from the wallet birthday to include the blocks needed to complete
+        // the note commitment tree subtrees containing the positions of the discovered notes.
+        let required_subtrees = wallet_note_positions
+            .iter()
+            .map(|p| Address::above_position(SAPLING_SHARD_HEIGHT.into(), *p).index())
+            .collect::<BTreeSet<_>>();
+
+        let extended_range = required_subtrees
+            .iter()
+            .next()
+            .zip(required_subtrees.iter().next_back())
+            .map(|(min_idx, max_idx)| {
+                let range_min = if *min_idx > 0 {
+                    // get the block height of the end of the previous shard
+                    self.sapling_subtree_end_heights
+                        .get(&(*min_idx - 1))
+                        .copied()
+                } else {
+                    // our lower bound is going to be the Sapling activation height
+                    self.params.activation_height(NetworkUpgrade::Sapling)
+                };
+
+                // bound the minimum to the wallet birthday
+                let range_min = range_min.map(|h| wallet_birthday.map_or(h, |b| max(b, h)));
+
+                // Get the block height for the end of the current shard, and make it an
+                // exclusive end bound.
+                let range_max = self
+                    .sapling_subtree_end_heights
+                    .get(max_idx)
+                    .map(|end| *end + 1);
+
+                Range {
+                    start: range.start.min(range_min.unwrap_or(range.start)),
+                    end: range.end.max(range_max.unwrap_or(range.end)),
+                }
+            });
+
+        let query_range = extended_range.clone().unwrap_or_else(|| range.clone());
+
+        let scanned = ScanRange::from_parts(range.clone(), ScanPriority::Scanned);
+
+        // If any of the extended range actually extends beyond the scanned range, we need to
+        // scan that extension in order to make the found note(s) spendable. Empty ranges are
+        // not created, as they would prevent the merging of scanned ranges on either side.
+        let extended_before = extended_range
+            .as_ref()
+            .map(|extended| {
+                ScanRange::from_parts(extended.start..range.start, ScanPriority::FoundNote)
+            })
+            .filter(|range| !range.is_empty());
+        let extended_after = extended_range
+            .map(|extended| ScanRange::from_parts(range.end..extended.end, ScanPriority::FoundNote))
+            .filter(|range| !range.is_empty());
+
+        self.replace_queue_entries(
+            &query_range,
+            Some(scanned)
+                .into_iter()
+                .chain(extended_before)
+                .chain(extended_after),
+            false,
+        );
+    }
+
+    /// Returns the scan state of each note commitment tree shard known to the wallet for the
+    /// given protocol.
+    fn shard_scan_states(&self, protocol: ShieldedProtocol) -> Vec<ShardScanState> {
+        let (shard_height, mut shard_indices, end_heights): (u8, BTreeSet<u64>, _) = match protocol
+        {
+            ShieldedProtocol::Sapling => (
+                SAPLING_SHARD_HEIGHT,
+                shard_root_indices(self.sapling_tree.store()),
+                &self.sapling_subtree_end_heights,
+            ),
+            #[cfg(all(zcash_unstable = "orchard", feature = "orchard"))]
+            ShieldedProtocol::Orchard => (
+                ORCHARD_SHARD_HEIGHT,
+                shard_root_indices(self.orchard_tree.store()),
+                &self.orchard_subtree_end_heights,
+            ),
+            #[cfg(all(zcash_unstable = "orchard", not(feature = "orchard")))]
+            ShieldedProtocol::Orchard => return vec![],
+        };
+        shard_indices.extend(end_heights.keys().copied());
+
+        let wallet_birthday = self.wallet_birthday();
+        let sapling_activation_height = self.sapling_activation_height();
+
+        shard_indices
+            .into_iter()
+            .map(|index| {
+                let start_height = index
+                    .checked_sub(1)
+                    .and_then(|prev| end_heights.get(&prev).copied())
+                    .unwrap_or(sapling_activation_height);
+                let mut state = ShardScanState {
+                    positions: (index << shard_height)..((index + 1) << shard_height),
+                    max_priority: None,
+                    unscanned: vec![],
+                    start_height,
+                    end_height: end_heights.get(&index).copied(),
+                };
+
+                for range in self
+                    .scan_queue
+                    .iter()
+                    .filter(|r| state.overlaps(r.block_range()))
+                {
+                    state.max_priority = max(state.max_priority, Some(range.priority()));
+                    if range.priority() > ScanPriority::Scanned
+                        && wallet_birthday.map_or(false, |b| range.block_range().end > b)
+                    {
+                        state.unscanned.push(range.block_range().clone());
+                    }
+                }
+
+                state
+            })
+            .collect()
+    }
+
+    /// Returns whether the shard containing `height` has unscanned ranges that start at or
+    /// below that height, in which case no notes are spendable with an anchor at `height`.
+    fn unscanned_tip_exists(shards: &[ShardScanState], height: BlockHeight) -> bool {
+        shards.iter().any(|shard| {
+            shard.contains_height(height) && shard.unscanned.iter().any(|r| r.start <= height)
+        })
+    }
+
+    fn count_notes(
+        &self,
+        protocol: ShieldedProtocol,
+        chain_tip_height: BlockHeight,
+        summary_height: BlockHeight,
+        account_balances: &mut BTreeMap<AccountId, AccountBalance>,
+    ) -> Result<(), Error> {
+        let shards = self.shard_scan_states(protocol);
+        let any_spendable = !Self::unscanned_tip_exists(&shards, summary_height);
+
+        for (note_id, note) in self
+            .received_notes
+            .iter()
+            .filter(|(id, note)| id.protocol() == protocol && note.spent.is_none())
+        {
+            let tx = self.transactions.get(note_id.txid());
+            let received_height = tx.and_then(|tx| tx.mined_height);
+            let expiry_height = tx.and_then(|tx| tx.expiry_height);
+
+            // Skip notes received in transactions that expired without being mined.
+            if received_height.is_none() && expiry_height.map_or(false, |h| h < summary_height) {
+                continue;
+            }
+
+            // A note that is not positioned is not spendable, so we assign it the `ChainTip`
+            // scan priority, which is greater than `Scanned`.
+            let max_priority = note
+                .position
+                .and_then(|pos| {
+                    shards
+                        .iter()
+                        .find(|shard| shard.positions.contains(&u64::from(pos)))
+                        .and_then(|shard| shard.max_priority)
+                })
+                .unwrap_or(ScanPriority::ChainTip);
+
+            // Notes received in transactions that have been observed in the mempool but not
+            // mined are reported as incoming pending value, unless the transaction has expired.
+            let is_incoming_pending = !note.is_change && received_height.is_none();
+            if is_incoming_pending
+                && expiry_height
+                    .iter()
+                    .any(|h| u32::from(*h) != 0 && h <= &chain_tip_height)
+            {
+                continue;
+            }
+
+            let is_spendable = any_spendable
+                && received_height.iter().any(|h| h <= &summary_height)
+                && max_priority <= ScanPriority::Scanned;
+
+            let is_pending_change =
+                note.is_change && received_height.iter().all(|h| h > &summary_height);
+
+            let value = note.note.value();
+            let zero = NonNegativeAmount::ZERO;
+            let (spendable, pending_change, pending_spendable, incoming_pending) = if is_spendable {
+                (value, zero, zero, zero)
+            } else if is_pending_change {
+                (zero, value, zero, zero)
+            } else if is_incoming_pending {
+                (zero, zero, zero, value)
+            } else {
+                (zero, zero, value, zero)
+            };
+
+            if let Some(balances) = account_balances.get_mut(&note.account) {
+                let update = |bal: &mut Balance| {
+                    bal.add_spendable_value(spendable)?;
+                    bal.add_pending_change_value(pending_change)?;
+                    bal.add_pending_spendable_value(pending_spendable)?;
+                    bal.add_incoming_pending_value(incoming_pending)?;
+                    Ok::<_, Error>(())
+                };
+                match protocol {
+                    ShieldedProtocol::Sapling => balances.with_sapling_balance_mut(update)?,
+                    #[cfg(zcash_unstable = "orchard")]
+                    ShieldedProtocol::Orchard => balances.with_orchard_balance_mut(update)?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn sapling_scan_progress(
+        &self,
+        birthday_height: BlockHeight,
+        fully_scanned_height: BlockHeight,
+        chain_tip_height: BlockHeight,
+    ) -> Option<Ratio<u64>> {
+        if fully_scanned_height == chain_tip_height {
+            // Compute the total number of outputs scanned since the wallet birthday.
+            let mut blocks = self.blocks.range(birthday_height..).peekable();
+            blocks.peek()?;
+            let scanned = blocks
+                .map(|(_, block)| u64::from(block.sapling_output_count))
+                .sum();
+            Some(Ratio::new(scanned, scanned))
+        } else {
+            let start_size = self
+                .blocks
+                .range(..=birthday_height)
+                .map(|(_, block)| u64::from(block.sapling_tree_size))
+                .max();
+            let scanned_count = self
+                .blocks
+                .range((birthday_height + 1)..)
+                .map(|(_, block)| u64::from(block.sapling_output_count))
+                .sum::<u64>();
+
+            // We don't know how many outputs will exist in the shard at the chain tip without
+            // having scanned the chain tip block, so we overestimate by computing the maximum
+            // possible number of notes directly from the shard indices.
+            let shard_indices = self
+                .shard_scan_states(ShieldedProtocol::Sapling)
+                .into_iter()
+                .filter(|shard| shard.end_height.map_or(true, |h| h > birthday_height))
+                .map(|shard| shard.positions.start >> SAPLING_SHARD_HEIGHT)
+                .collect::<Vec<_>>();
+            let min_tree_size = shard_indices
+                .iter()
+                .min()
+                .map(|min| min << SAPLING_SHARD_HEIGHT);
+
+            start_size
+                .or(min_tree_size)
+                .zip(shard_indices.iter().max())
+                .map(|(min_tree_size, max)| {
+                    let max_tree_size = (max + 1) << SAPLING_SHARD_HEIGHT;
+                    Ratio::new(scanned_count, max_tree_size - min_tree_size)
+                })
+        }
+    }
+
+    /// Returns the IDs of transactions mined above the given height, ordered by mined height
+    /// and index within the block, along with the notes received in those transactions.
+    fn get_mined_above(&self, block_height: BlockHeight) -> (Vec<TxId>, Vec<NoteId>) {
+        let mut mined = self
+            .transactions
+            .iter()
+            .filter_map(|(txid, tx)| {
+                tx.mined_height
+                    .filter(|h| *h > block_height)
+                    .map(|h| (h, tx.tx_index, *txid))
+            })
+            .collect::<Vec<_>>();
+        mined.sort();
+
+        let mut note_ids = self
+            .received_notes
+            .keys()
+            .filter_map(|id| {
+                let tx = self.transactions.get(id.txid())?;
+                tx.mined_height
+                    .filter(|h| *h > block_height)
+                    .map(|h| ((id.protocol(), h, tx.tx_index, id.output_index()), *id))
+            })
+            .collect::<Vec<_>>();
+        note_ids.sort_by_key(|(key, _)| *key);
+
+        (
+            mined.into_iter().map(|(_, _, txid)| txid).collect(),
+            note_ids.into_iter().map(|(_, id)| id).collect(),
+        )
+    }
+
+    /// Returns the outputs of the given transaction that were sent from or received by the
+    /// given account, ordered by pool and output index.
+    fn tx_outputs(&self, txid: TxId, account: AccountId) -> Vec<OutputSummary> {
+        let mut outputs = vec![];
+
+        for (note_id, note) in self
+            .received_notes
+            .iter()
+            .filter(|(id, _)| id.txid() == &txid)
+        {
+            let pool = PoolType::Shielded(note_id.protocol());
+            let sent = self
+                .sent_outputs
+                .get(&(txid, pool, u32::from(note_id.output_index())));
+            // Non-change notes that the wallet also sent are reported with the sent output.
+            if sent.is_some() && !note.is_change {
+                continue;
+            }
+            let from_account = sent.map(|s| s.from_account);
+            if note.account == account || from_account == Some(account) {
+                outputs.push(OutputSummary::from_parts(
+                    pool,
+                    u32::from(note_id.output_index()),
+                    from_account,
+                    Some(note.account),
+                    None,
+                    note.note.value(),
+                    note.is_change,
+                    note.memo.clone(),
+                ));
+            }
+        }
+
+        for ((_, pool, output_index), sent) in self
+            .sent_outputs
+            .iter()
+            .filter(|((id, _, _), _)| id == &txid)
+        {
+            let received = match pool {
+                PoolType::Shielded(protocol) => u16::try_from(*output_index)
+                    .ok()
+                    .and_then(|idx| self.received_notes.get(&NoteId::new(txid, *protocol, idx))),
+                PoolType::Transparent => None,
+            };
+            if received.map_or(false, |note| note.is_change) {
+                continue;
+            }
+            let to_account = received.map(|note| note.account).or(sent.to_account);
+            if sent.from_account == account || to_account == Some(account) {
+                outputs.push(OutputSummary::from_parts(
+                    *pool,
+                    *output_index,
+                    Some(sent.from_account),
+                    to_account,
+                    sent.to_address.clone(),
+                    sent.value,
+                    false,
+                    sent.memo
+                        .clone()
+                        .or_else(|| received.and_then(|note| note.memo.clone())),
+                ));
+            }
+        }
+
+        #[cfg(feature = "transparent-inputs")]
+        for (outpoint, utxo) in self
+            .utxos
+            .iter()
+            .filter(|(outpoint, utxo)| outpoint.hash() == txid.as_ref() && utxo.account == account)
+        {
+            outputs.push(OutputSummary::from_parts(
+                PoolType::Transparent,
+                outpoint.n(),
+                None,
+                Some(utxo.account),
+                Some(utxo.output.recipient_address().encode(&self.params)),
+                utxo.output.value(),
+                false,
+                None,
+            ));
+        }
+
+        outputs.sort_by_key(|output| (output.pool(), output.output_index()));
+        outputs
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn is_utxo_spendable(&self, utxo: &ReceivedUtxo, stable_height: BlockHeight) -> bool {
+        utxo.spent.map_or(true, |txid| {
+            self.transactions.get(&txid).map_or(true, |tx| {
+                tx.mined_height.is_none() && tx.expiry_height.map_or(false, |h| h <= stable_height)
+            })
+        })
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn legacy_transparent_address(
+        ufvk: &UnifiedFullViewingKey,
+    ) -> Result<Option<(TransparentAddress, DiversifierIndex)>, Error> {
+        ufvk.transparent()
+            .map(|tfvk| {
+                tfvk.derive_external_ivk()
+                    .map(|tivk| {
+                        let (taddr, child_index) = tivk.default_address();
+                        (taddr, DiversifierIndex::from(child_index))
+                    })
+                    .map_err(Error::HdwalletError)
+            })
+            .transpose()
+    }
+}
+
+/// Returns the big-endian encoding of a diversifier index, which sorts in index order.
+fn diversifier_index_be(diversifier_index: &DiversifierIndex) -> [u8; 11] {
+    let mut di_be = *diversifier_index.as_bytes();
+    di_be.reverse();
+    di_be
+}
+
+/// Returns the memo to record for a change note, which is empty if the sender did not attach
+/// one.
+fn change_memo(memo: Option<&MemoBytes>) -> MemoBytes {
+    memo.map_or_else(MemoBytes::empty, |memo| memo.clone())
+}
+
+fn shard_root_indices<S: ShardStore<Error = Infallible>>(store: &S) -> BTreeSet<u64> {
+    store
+        .get_shard_roots()
+        .unwrap_or_else(|e| match e {})
+        .into_iter()
+        .map(|addr| addr.index())
+        .collect()
+}
+
+fn max_checkpoint_at_or_below<S: ShardStore<CheckpointId = BlockHeight, Error = Infallible>>(
+    store: &S,
+    height: BlockHeight,
+) -> Option<BlockHeight> {
+    let count = store.checkpoint_count().unwrap_or_else(|e| match e {});
+    (0..=count)
+        .filter_map(|depth| {
+            store
+                .get_checkpoint_at_depth(depth)
+                .unwrap_or_else(|e| match e {})
+        })
+        .map(|(id, _)| id)
+        .find(|id| *id <= height)
+}
+
+impl<P: consensus::Parameters> InputSource for MemoryWalletDb<P> {
+    type Error = Error;
+    type NoteRef = NoteId;
+
+    fn get_spendable_note(
+        &self,
+        txid: &TxId,
+        protocol: ShieldedProtocol,
+        index: u32,
+    ) -> Result<Option<ReceivedNote<Self::NoteRef, Note>>, Self::Error> {
+        let note_id = match u16::try_from(index) {
+            Ok(index) => NoteId::new(*txid, protocol, index),
+            Err(_) => return Ok(None),
+        };
+
+        Ok(self.received_notes.get(&note_id).and_then(|note| {
+            note.position
+                .filter(|_| note.spent.is_none())
+                .map(|position| {
+                    ReceivedNote::from_parts(
+                        note_id,
+                        *txid,
+                        note_id.output_index(),
+                        note.note.clone(),
+                        note.scope,
+                        position,
+                    )
+                })
+        }))
+    }
+
+    fn select_spendable_notes(
+        &self,
+        account: AccountId,
+        target_value: Amount,
+        sources: &[ShieldedProtocol],
+        anchor_height: BlockHeight,
+        exclude: &[Self::NoteRef],
+    ) -> Result<Vec<ReceivedNote<Self::NoteRef, Note>>, Self::Error> {
+        // The wallet birthday can only be unknown if there are no accounts in the wallet; in
+        // such a case, the wallet has no notes to spend.
+        if self.wallet_birthday().is_none() {
+            return Ok(vec![]);
+        }
+
+        let mut selected = vec![];
+        let mut remaining = target_value;
+        for source in sources {
+            if remaining <= Amount::zero() {
+                break;
+            }
+
+            let shards = self.shard_scan_states(*source);
+            if Self::unscanned_tip_exists(&shards, anchor_height) {
+                continue;
+            }
+
+            let mut eligible = self
+                .received_notes
+                .iter()
+                .filter(|(id, note)| {
+                    id.protocol() == *source
+                        && note.account == account
+                        && note.spent.is_none()
+                        && !exclude.contains(id)
+                        && self
+                            .mined_height(id.txid())
+                            .map_or(false, |h| h <= anchor_height)
+                })
+                .filter_map(|(id, note)| note.position.map(|pos| (id, note, pos)))
+                .filter(|(_, _, pos)| {
+                    // Exclude notes in shards containing unscanned ranges that start at or
+                    // below the anchor height.
+                    !shards.iter().any(|shard| {
+                        shard.positions.contains(&u64::from(*pos))
+                            && shard.unscanned.iter().any(|r| r.start <= anchor_height)
+                    })
+                })
+                .collect::<Vec<_>>();
+            eligible.sort_by_key(|(_, note, _)| note.seq);
+
+            // Select the oldest notes until the remaining value has been reached.
+            let mut so_far = Amount::zero();
+            for (note_id, note, position) in eligible {
+                if so_far >= remaining {
+                    break;
+                }
+                so_far = (so_far + Amount::from(note.note.value()))
+                    .ok_or(Error::Balance(BalanceError::Overflow))?;
+                selected.push(ReceivedNote::from_parts(
+                    *note_id,
+                    *note_id.txid(),
+                    note_id.output_index(),
+                    note.note.clone(),
+                    note.scope,
+                    position,
+                ));
+            }
+
+            remaining = (remaining - so_far).ok_or(Error::Balance(BalanceError::Underflow))?;
+        }
+
+        Ok(selected)
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn get_unspent_transparent_output(
+        &self,
+        outpoint: &OutPoint,
+    ) -> Result<Option<WalletTransparentOutput>, Self::Error> {
+        Ok(self
+            .utxos
+            .get(outpoint)
+            .filter(|utxo| {
+                utxo.spent
+                    .map_or(true, |txid| self.mined_height(&txid).is_none())
+            })
+            .map(|utxo| utxo.output.clone()))
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn get_unspent_transparent_outputs(
+        &self,
+        address: &TransparentAddress,
+        max_height: BlockHeight,
+        exclude: &[OutPoint],
+    ) -> Result<Vec<WalletTransparentOutput>, Self::Error> {
+        let stable_height = self
+            .chain_tip()
+            .unwrap_or(max_height)
+            .saturating_sub(PRUNING_DEPTH);
+
+        Ok(self
+            .utxos
+            .iter()
+            .filter(|(outpoint, utxo)| {
+                utxo.output.recipient_address() == address
+                    && utxo.output.height() <= max_height
+                    && !exclude.contains(outpoint)
+                    && self.is_utxo_spendable(utxo, stable_height)
+            })
+            .map(|(_, utxo)| utxo.output.clone())
+            .collect())
+    }
+}
+
+impl<P: consensus::Parameters> WalletRead for MemoryWalletDb<P> {
+    type Error = Error;
+
+    fn chain_height(&self) -> Result<Option<BlockHeight>, Self::Error> {
+        Ok(self.chain_tip())
+    }
+
+    fn block_metadata(&self, height: BlockHeight) -> Result<Option<BlockMetadata>, Self::Error> {
+        Ok(self.block_metadata_at(height))
+    }
+
+    fn block_fully_scanned(&self) -> Result<Option<BlockMetadata>, Self::Error> {
+        let birthday_height = match self.wallet_birthday() {
+            Some(h) => h,
+            None => return Ok(None),
+        };
+
+        // The fully-scanned height is the last height that falls within the first range in
+        // the scan queue with priority "Scanned", provided that range includes the birthday.
+        Ok(self
+            .scan_queue
+            .iter()
+            .filter(|range| range.priority() == ScanPriority::Scanned)
+            .min_by_key(|range| range.block_range().start)
+            .filter(|range| range.block_range().start <= birthday_height)
+            .and_then(|range| self.block_metadata_at(range.block_range().end - 1)))
+    }
+
+    fn block_max_scanned(&self) -> Result<Option<BlockMetadata>, Self::Error> {
+        Ok(self
+            .max_scanned_height()
+            .and_then(|h| self.block_metadata_at(h)))
+    }
+
+    fn suggest_scan_ranges(&self) -> Result<Vec<ScanRange>, Self::Error> {
+        let mut ranges = self
+            .scan_queue
+            .iter()
+            .filter(|range| range.priority() >= ScanPriority::Historic)
+            .cloned()
+            .collect::<Vec<_>>();
+        ranges.sort_by_key(|range| (Reverse(range.priority()), Reverse(range.block_range().end)));
+        Ok(ranges)
+    }
+
+    fn get_target_and_anchor_heights(
+        &self,
+        min_confirmations: NonZeroU32,
+    ) -> Result<Option<(BlockHeight, BlockHeight)>, Self::Error> {
+        Ok(self.chain_tip().and_then(|chain_tip_height| {
+            let max_checkpoint_height = BlockHeight::from(
+                u32::from(chain_tip_height).saturating_sub(u32::from(min_confirmations) - 1),
+            );
+            max_checkpoint_at_or_below(self.sapling_tree.store(), max_checkpoint_height)
+                .map(|anchor_height| (chain_tip_height + 1, anchor_height))
+        }))
+    }
+
+    fn get_min_unspent_height(&self) -> Result<Option<BlockHeight>, Self::Error> {
+        Ok(self
+            .received_notes
+            .iter()
+            .filter(|(_, note)| note.spent.is_none())
+            .filter_map(|(id, _)| self.mined_height(id.txid()))
+            .min())
+    }
+
+    fn get_block_hash(&self, block_height: BlockHeight) -> Result<Option<BlockHash>, Self::Error> {
+        Ok(self.blocks.get(&block_height).map(|block| block.hash))
+    }
+
+    fn get_max_height_hash(&self) -> Result<Option<(BlockHeight, BlockHash)>, Self::Error> {
+        Ok(self
+            .blocks
+            .iter()
+            .next_back()
+            .map(|(height, block)| (*height, block.hash)))
+    }
+
+    fn plan_rewind<C: ChainView>(
+        &self,
+        conflict_height: BlockHeight,
+        chain_view: &C,
+    ) -> Result<RewindPlan, RewindError<Self::Error, C::Error>> {
+        let sapling_min = self
+            .sapling_tree
+            .store()
+            .min_checkpoint_id()
+            .unwrap_or_else(|e| match e {});
+        #[cfg(feature = "orchard")]
+        let orchard_min = self
+            .orchard_tree
+            .store()
+            .min_checkpoint_id()
+            .unwrap_or_else(|e| match e {});
+        #[cfg(not(feature = "orchard"))]
+        let orchard_min = None;
+
+        let fork_height = match max(sapling_min, orchard_min) {
+            Some(min_rewind_height) => {
+                find_fork_point(self, chain_view, conflict_height, min_rewind_height)?
+            }
+            // If the note commitment trees contain no checkpoints then no blocks have been
+            // scanned, so there are no stored block hashes to compare against.
+            None => conflict_height.saturating_sub(1),
+        };
+
+        let (unmined_txids, unmined_notes) = self.get_mined_above(fork_height);
+
+        Ok(RewindPlan::from_parts(
+            fork_height,
+            unmined_txids,
+            unmined_notes,
+        ))
+    }
+
+    fn get_tx_height(&self, txid: TxId) -> Result<Option<BlockHeight>, Self::Error> {
+        Ok(self.mined_height(&txid))
+    }
+
+    fn get_wallet_birthday(&self) -> Result<Option<BlockHeight>, Self::Error> {
+        Ok(self.wallet_birthday())
+    }
+
+    fn get_account_birthday(&self, account: AccountId) -> Result<BlockHeight, Self::Error> {
+        self.accounts
+            .get(&account)
+            .map(|a| a.birthday)
+            .ok_or(Error::AccountUnknown(account))
+    }
+
+    fn get_current_address(
+        &self,
+        account: AccountId,
+    ) -> Result<Option<UnifiedAddress>, Self::Error> {
+        Ok(self
+            .accounts
+            .get(&account)
+            .and_then(|a| a.addresses.values().next_back().cloned()))
+    }
+
+    fn get_unified_full_viewing_keys(
+        &self,
+    ) -> Result<HashMap<AccountId, UnifiedFullViewingKey>, Self::Error> {
+        Ok(self
+            .accounts
+            .iter()
+            .map(|(id, account)| (*id, account.ufvk.clone()))
+            .collect())
+    }
+
+    fn get_account_for_ufvk(
+        &self,
+        ufvk: &UnifiedFullViewingKey,
+    ) -> Result<Option<AccountId>, Self::Error> {
+        let encoded = ufvk.encode(&self.params);
+        Ok(self
+            .accounts
+            .iter()
+            .find(|(_, account)| account.ufvk.encode(&self.params) == encoded)
+            .map(|(id, _)| *id))
+    }
+
+    fn get_account_kind(&self, account: AccountId) -> Result<Option<AccountKind>, Self::Error> {
+        Ok(self.accounts.get(&account).map(|a| a.kind))
+    }
+
+    fn get_derived_account(
+        &self,
+        seed_fingerprint: &SeedFingerprint,
+        account_index: AccountId,
+    ) -> Result<Option<AccountId>, Self::Error> {
+        let kind = AccountKind::Derived {
+            seed_fingerprint: *seed_fingerprint,
+            account_index,
+        };
+        Ok(self
+            .accounts
+            .iter()
+            .find(|(_, account)| account.kind == kind)
+            .map(|(id, _)| *id))
+    }
+
+    fn get_wallet_summary(
+        &self,
+        min_confirmations: u32,
+    ) -> Result<Option<WalletSummary>, Self::Error> {
+        let chain_tip_height = match self.chain_tip() {
+            Some(h) => h,
+            None => return Ok(None),
+        };
+        let birthday_height = self
+            .wallet_birthday()
+            .expect("If a scan range exists, we know the wallet birthday.");
+
+        let fully_scanned_height = self
+            .block_fully_scanned()?
+            .map_or(birthday_height - 1, |m| m.block_height());
+        let summary_height = (chain_tip_height + 1).saturating_sub(max(min_confirmations, 1));
+
+        let scan_progress =
+            self.sapling_scan_progress(birthday_height, fully_scanned_height, chain_tip_height);
+
+        let mut account_balances = self
+            .accounts
+            .keys()
+            .map(|id| (*id, AccountBalance::ZERO))
+            .collect::<BTreeMap<_, _>>();
+
+        self.count_notes(
+            ShieldedProtocol::Sapling,
+            chain_tip_height,
+            summary_height,
+            &mut account_balances,
+        )?;
+        #[cfg(feature = "orchard")]
+        self.count_notes(
+            ShieldedProtocol::Orchard,
+            chain_tip_height,
+            summary_height,
+            &mut account_balances,
+        )?;
+
+        #[cfg(feature = "transparent-inputs")]
+        {
+            let zero_conf_height = (chain_tip_height + 1).saturating_sub(min_confirmations);
+            let stable_height = chain_tip_height.saturating_sub(PRUNING_DEPTH);
+
+            for utxo in self.utxos.values().filter(|utxo| {
+                utxo.output.height() <= zero_conf_height
+                    && self.is_utxo_spendable(utxo, stable_height)
+            }) {
+                if let Some(balances) = account_balances.get_mut(&utxo.account) {
+                    balances.add_unshielded_value(utxo.output.value())?;
+                }
+            }
+        }
+
+        // The last shard will be incomplete, and we want the next range to overlap with the
+        // last complete shard, so return the index of the second-to-last shard root.
+        let next_sapling_subtree_index = shard_root_indices(self.sapling_tree.store())
+            .into_iter()
+            .rev()
+            .nth(1)
+            .unwrap_or(0);
+
+        Ok(Some(WalletSummary::new(
+            account_balances,
+            chain_tip_height,
+            fully_scanned_height,
+            scan_progress,
+            next_sapling_subtree_index,
+        )))
+    }
+
+    fn get_memo(&self, note_id: NoteId) -> Result<Option<Memo>, Self::Error> {
+        let sent_memo = self
+            .sent_outputs
+            .get(&(
+                *note_id.txid(),
+                PoolType::Shielded(note_id.protocol()),
+                u32::from(note_id.output_index()),
+            ))
+            .and_then(|output| output.memo.clone());
+        let received_memo = || {
+            self.received_notes
+                .get(&note_id)
+                .and_then(|note| note.memo.clone())
+        };
+
+        sent_memo
+            .or_else(received_memo)
+            .map(Memo::try_from)
+            .transpose()
+            .map_err(Error::from)
+    }
+
+    fn get_transaction(&self, txid: TxId) -> Result<Transaction, Self::Error> {
+        let (raw, branch_id) = self
+            .transactions
+            .get(&txid)
+            .and_then(|tx| tx.raw.as_ref())
+            .ok_or(Error::TransactionNotFound(txid))?;
+
+        Transaction::read(&raw[..], *branch_id).map_err(Error::from)
+    }
+
+    fn get_unmined_sent_transactions(&self) -> Result<Vec<UnminedSentTransaction>, Self::Error> {
+        let chain_tip_height = self.chain_tip();
+
+        let spends_wallet_funds = |txid: &TxId| {
+            #[cfg(feature = "transparent-inputs")]
+            if self.utxos.values().any(|utxo| utxo.spent == Some(*txid)) {
+                return true;
+            }
+            self.received_notes
+                .values()
+                .any(|note| note.spent == Some(*txid))
+        };
+
+        let mut unmined = self
+            .transactions
+            .iter()
+            .filter_map(|(txid, tx)| {
+                let (raw, _) = tx.raw.as_ref()?;
+                let expiry_height = tx.expiry_height?;
+                (tx.mined_height.is_none()
+                    && (chain_tip_height.is_none()
+                        || u32::from(expiry_height) == 0
+                        || Some(expiry_height) > chain_tip_height)
+                    && spends_wallet_funds(txid))
+                .then(|| {
+                    (
+                        tx.seq,
+                        UnminedSentTransaction::from_parts(
+                            *txid,
+                            raw.clone(),
+                            expiry_height,
+                            tx.created,
+                        ),
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        unmined.sort_by_key(|(seq, _)| *seq);
+
+        Ok(unmined.into_iter().map(|(_, tx)| tx).collect())
+    }
+
+    fn get_transactions(
+        &self,
+        account: AccountId,
+        filter: &TransactionFilter,
+    ) -> Result<Vec<TransactionSummary>, Self::Error> {
+        // Accumulate the change in the account's balance due to each transaction that sends
+        // funds to or from the account.
+        let mut deltas: BTreeMap<TxId, (Option<BlockHeight>, i64)> = BTreeMap::new();
+        let mut add_delta = |txid: TxId, mined_height: Option<BlockHeight>, value: i64| {
+            let entry = deltas.entry(txid).or_insert((mined_height, 0));
+            entry.1 += value;
+        };
+
+        for (note_id, note) in self
+            .received_notes
+            .iter()
+            .filter(|(_, note)| note.account == account)
+        {
+            let value = i64::from(Amount::from(note.note.value()));
+            add_delta(*note_id.txid(), self.mined_height(note_id.txid()), value);
+            if let Some(spent) = note.spent {
+                add_delta(spent, self.mined_height(&spent), -value);
+            }
+        }
+
+        #[cfg(feature = "transparent-inputs")]
+        for (outpoint, utxo) in self
+            .utxos
+            .iter()
+            .filter(|(_, utxo)| utxo.account == account)
+        {
+            let value = i64::from(Amount::from(utxo.output.value()));
+            let txid = TxId::from_bytes(*outpoint.hash());
+            add_delta(
+                txid,
+                self.mined_height(&txid).or(Some(utxo.output.height())),
+                value,
+            );
+            if let Some(spent) = utxo.spent {
+                add_delta(spent, self.mined_height(&spent), -value);
+            }
+        }
+
+        let max_block_height = self.max_scanned_height();
+        let mut summaries = deltas
+            .into_iter()
+            .filter(|(_, (mined_height, _))| {
+                filter.mined_height_range().map_or(true, |range| {
+                    mined_height.map_or(false, |h| range.contains(&h))
+                })
+            })
+            .map(|(txid, (mined_height, delta))| {
+                let tx = self.transactions.get(&txid);
+                let expiry_height = tx.and_then(|tx| tx.expiry_height);
+                let tx_index = tx.and_then(|tx| tx.tx_index);
+                let expired_unmined = mined_height.is_none()
+                    && expiry_height
+                        .zip(max_block_height)
+                        .map_or(false, |(expiry, max_height)| {
+                            u32::from(expiry) >= 1 && expiry <= max_height
+                        });
+
+                let summary = TransactionSummary::from_parts(
+                    account,
+                    txid,
+                    mined_height,
+                    mined_height.and_then(|h| self.blocks.get(&h).map(|block| block.time)),
+                    expiry_height,
+                    Amount::from_i64(delta).map_err(|_| Error::Balance(BalanceError::Overflow))?,
+                    tx.and_then(|tx| tx.fee),
+                    expired_unmined,
+                    self.tx_outputs(txid, account),
+                );
+
+                Ok((
+                    (
+                        mined_height.is_some(),
+                        Reverse(mined_height),
+                        Reverse(tx_index),
+                        txid,
+                    ),
+                    summary,
+                ))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        // Unmined transactions are returned first, followed by mined transactions in order of
+        // decreasing mined height.
+        summaries.sort_by_key(|(key, _)| *key);
+
+        let offset = usize::try_from(filter.offset()).expect("u32 fits in usize");
+        let limit = filter.limit().map_or(usize::MAX, |l| {
+            usize::try_from(l).expect("u32 fits in usize")
+        });
+        Ok(summaries
+            .into_iter()
+            .map(|(_, summary)| summary)
+            .skip(offset)
+            .take(limit)
+            .collect())
+    }
+
+    fn get_sapling_nullifiers(
+        &self,
+        query: NullifierQuery,
+    ) -> Result<Vec<(AccountId, sapling::Nullifier)>, Self::Error> {
+        Ok(self
+            .received_notes
+            .iter()
+            .filter(|(id, note)| {
+                id.protocol() == ShieldedProtocol::Sapling
+                    && match query {
+                        NullifierQuery::Unspent => note
+                            .spent
+                            .map_or(true, |txid| self.mined_height(&txid).is_none()),
+                        NullifierQuery::All => true,
+                    }
+            })
+            .filter_map(|(_, note)| note.nf.map(|nf| (note.account, sapling::Nullifier(nf))))
+            .collect())
+    }
+
+    #[cfg(feature = "orchard")]
+    fn get_orchard_nullifiers(
+        &self,
+        query: NullifierQuery,
+    ) -> Result<Vec<(AccountId, orchard::note::Nullifier)>, Self::Error> {
+        Ok(self
+            .received_notes
+            .iter()
+            .filter(|(id, note)| {
+                id.protocol() == ShieldedProtocol::Orchard
+                    && match query {
+                        NullifierQuery::Unspent => note
+                            .spent
+                            .map_or(true, |txid| self.mined_height(&txid).is_none()),
+                        NullifierQuery::All => true,
+                    }
+            })
+            .filter_map(|(_, note)| {
+                note.nf.and_then(|nf| {
+                    Option::from(orchard::note::Nullifier::from_bytes(&nf))
+                        .map(|nf| (note.account, nf))
+                })
+            })
+            .collect())
+    }
+
+    fn get_transparent_receivers(
+        &self,
+        _account: AccountId,
+    ) -> Result<HashMap<TransparentAddress, AddressMetadata>, Self::Error> {
+        #[cfg(feature = "transparent-inputs")]
+        {
+            let mut ret = HashMap::new();
+            if let Some(account) = self.accounts.get(&_account) {
+                for (di_be, ua) in &account.addresses {
+                    if let Some(taddr) = ua.transparent() {
+                        let mut di_le = *di_be;
+                        di_le.reverse();
+                        ret.insert(
+                            *taddr,
+                            AddressMetadata::new(_account, DiversifierIndex::from(di_le)),
+                        );
+                    }
+                }
+
+                if let Some((taddr, diversifier_index)) =
+                    Self::legacy_transparent_address(&account.ufvk)?
+                {
+                    ret.insert(taddr, AddressMetadata::new(_account, diversifier_index));
+                }
+            }
+            Ok(ret)
+        }
+
+        #[cfg(not(feature = "transparent-inputs"))]
+        panic!(
+            "The wallet must be compiled with the transparent-inputs feature to use this method."
+        );
+    }
+
+    fn get_transparent_balances(
+        &self,
+        _account: AccountId,
+        _max_height: BlockHeight,
+    ) -> Result<HashMap<TransparentAddress, Amount>, Self::Error> {
+        #[cfg(feature = "transparent-inputs")]
+        {
+            let stable_height = self
+                .chain_tip()
+                .unwrap_or(_max_height)
+                .saturating_sub(PRUNING_DEPTH);
+
+            let mut res: HashMap<TransparentAddress, Amount> = HashMap::new();
+            for utxo in self.utxos.values().filter(|utxo| {
+                utxo.account == _account
+                    && utxo.output.height() <= _max_height
+                    && self.is_utxo_spendable(utxo, stable_height)
+            }) {
+                let balance = res
+                    .entry(*utxo.output.recipient_address())
+                    .or_insert_with(Amount::zero);
+                *balance = (*balance + Amount::from(utxo.output.value()))
+                    .ok_or(Error::Balance(BalanceError::Overflow))?;
+            }
+            Ok(res)
+        }
+
+        #[cfg(not(feature = "transparent-inputs"))]
+        panic!(
+            "The wallet must be compiled with the transparent-inputs feature to use this method."
+        );
+    }
+
+    fn get_account_ids(&self) -> Result<Vec<AccountId>, Self::Error> {
+        Ok(self.accounts.keys().copied().collect())
+    }
+}
+
+impl<P: consensus::Parameters> WalletWrite for MemoryWalletDb<P> {
+    type UtxoRef = OutPoint;
+
+    fn create_account(
+        &mut self,
+        seed: &SecretVec<u8>,
+        birthday: AccountBirthday,
+    ) -> Result<(AccountId, UnifiedSpendingKey), Self::Error> {
+        // A seed that cannot be fingerprinted is too short or too long for key derivation to
+        // succeed at any account index.
+        let seed_fingerprint = SeedFingerprint::from_seed(seed.expose_secret())
+            .ok_or(Error::KeyDerivation(AccountId::ZERO))?;
+
+        // ZIP 32 account indices are allocated independently for each seed.
+        let account_index = self
+            .accounts
+            .values()
+            .filter_map(|account| match &account.kind {
+                AccountKind::Derived {
+                    seed_fingerprint: fp,
+                    account_index,
+                } if fp == &seed_fingerprint => Some(*account_index),
+                _ => None,
+            })
+            .max()
+            .map(|a| a.next().ok_or(Error::AccountIdOutOfRange))
+            .transpose()?
+            .unwrap_or(AccountId::ZERO);
+
+        let account = self.next_account_id()?;
+
+        let usk = UnifiedSpendingKey::from_seed(&self.params, seed.expose_secret(), account_index)
+            .map_err(|_| Error::KeyDerivation(account_index))?;
+        let ufvk = usk.to_unified_full_viewing_key();
+
+        self.add_account(
+            account,
+            ufvk,
+            AccountKind::Derived {
+                seed_fingerprint,
+                account_index,
+            },
+            birthday,
+        )?;
+
+        Ok((account, usk))
+    }
+
+    fn import_account_ufvk(
+        &mut self,
+        ufvk: &UnifiedFullViewingKey,
+        birthday: AccountBirthday,
+    ) -> Result<AccountId, Self::Error> {
+        let account = self.next_account_id()?;
+        self.add_account(account, ufvk.clone(), AccountKind::ViewOnly, birthday)?;
+        Ok(account)
+    }
+
+    fn get_next_available_address(
+        &mut self,
+        account: AccountId,
+        request: UnifiedAddressRequest,
+    ) -> Result<Option<UnifiedAddress>, Self::Error> {
+        let account = match self.accounts.get_mut(&account) {
+            Some(account) => account,
+            None => return Ok(None),
+        };
+
+        let search_from = match account.addresses.keys().next_back() {
+            Some(di_be) => {
+                let mut di_le = *di_be;
+                di_le.reverse();
+                let mut last_diversifier_index = DiversifierIndex::from(di_le);
+                last_diversifier_index
+                    .increment()
+                    .map_err(|_| Error::DiversifierIndexOutOfRange)?;
+                last_diversifier_index
+            }
+            None => DiversifierIndex::default(),
+        };
+
+        let (addr, diversifier_index) = account
+            .ufvk
+            .find_address(search_from, request)
+            .ok_or(Error::DiversifierIndexOutOfRange)?;
+
+        account
+            .addresses
+            .insert(diversifier_index_be(&diversifier_index), addr.clone());
+
+        Ok(Some(addr))
+    }
+
+    fn put_blocks(
+        &mut self,
+        blocks: Vec<ScannedBlock<sapling::Nullifier, Scope>>,
+    ) -> Result<(), Self::Error> {
+        if blocks
+            .windows(2)
+            .any(|pair| pair[1].height() != pair[0].height() + 1)
+        {
+            return Err(Error::NonSequentialBlocks);
+        }
+
+        let (start_height, last_scanned_height) = match (blocks.first(), blocks.last()) {
+            (Some(first), Some(last)) => (first.height(), last.height()),
+            _ => return Ok(()),
+        };
+        let sapling_start_position = Position::from(
+            u64::from(blocks[0].sapling().final_tree_size())
+                - u64::try_from(blocks[0].sapling().commitments().len()).unwrap(),
+        );
+        #[cfg(feature = "orchard")]
+        let orchard_start_position = Position::from(
+            u64::from(blocks[0].orchard().final_tree_size())
+                - u64::try_from(blocks[0].orchard().commitments().len()).unwrap(),
+        );
+
+        let mut sapling_commitments = vec![];
+        #[cfg(feature = "orchard")]
+        let mut orchard_commitments = vec![];
+        let mut note_positions = vec![];
+        for block in blocks.into_iter() {
+            self.blocks.insert(
+                block.height(),
+                Block {
+                    hash: block.block_hash(),
+                    time: block.block_time(),
+                    sapling_tree_size: block.sapling().final_tree_size(),
+                    sapling_output_count: block.sapling().commitments().len().try_into().unwrap(),
+                    #[cfg(feature = "orchard")]
+                    orchard_tree_size: block.orchard().final_tree_size(),
+                },
+            );
+
+            for tx in block.transactions() {
+                self.put_tx_meta(tx.txid, block.height(), tx.index);
+
+                // Mark notes as spent.
+                for spend in &tx.sapling_spends {
+                    self.mark_note_spent(ShieldedProtocol::Sapling, &spend.nf().0, tx.txid);
+                }
+
+                for output in &tx.sapling_outputs {
+                    // Check whether this note was spent in a later block range that we
+                    // previously scanned.
+                    let spent_in =
+                        self.query_nullifier_map(ShieldedProtocol::Sapling, &output.nf().0);
+
+                    self.put_received_note(
+                        NoteId::new(
+                            tx.txid,
+                            ShieldedProtocol::Sapling,
+                            u16::try_from(output.index())
+                                .expect("output indices are representable as u16"),
+                        ),
+                        output.account(),
+                        Note::Sapling(output.note().clone()),
+                        Some(output.nf().0),
+                        output.is_change(),
+                        None,
+                        Some(output.note_commitment_tree_position()),
+                        *output.recipient_key_scope(),
+                        spent_in,
+                    );
+                }
+
+                #[cfg(feature = "orchard")]
+                for spend in &tx.orchard_spends {
+                    self.mark_note_spent(
+                        ShieldedProtocol::Orchard,
+                        &spend.nf().to_bytes(),
+                        tx.txid,
+                    );
+                }
+
+                #[cfg(feature = "orchard")]
+                for output in &tx.orchard_outputs {
+                    let nf = output.nf().to_bytes();
+                    let spent_in = self.query_nullifier_map(ShieldedProtocol::Orchard, &nf);
+
+                    self.put_received_note(
+                        NoteId::new(
+                            tx.txid,
+                            ShieldedProtocol::Orchard,
+                            u16::try_from(output.index())
+                                .expect("action indices are representable as u16"),
+                        ),
+                        output.account(),
+                        Note::Orchard(*output.note()),
+                        Some(nf),
+                        output.is_change(),
+                        None,
+                        Some(output.note_commitment_tree_position()),
+                        *output.recipient_key_scope(),
+                        spent_in,
+                    );
+                }
+            }
+
+            // Insert the new nullifiers from this block into the nullifier map.
+            self.insert_nullifier_map(
+                block.height(),
+                ShieldedProtocol::Sapling,
+                block
+                    .sapling()
+                    .nullifier_map()
+                    .iter()
+                    .flat_map(|(txid, tx_index, nfs)| {
+                        nfs.iter().map(move |nf| (*txid, *tx_index, nf.0))
+                    }),
+            );
+            #[cfg(feature = "orchard")]
+            self.insert_nullifier_map(
+                block.height(),
+                ShieldedProtocol::Orchard,
+                block
+                    .orchard()
+                    .nullifier_map()
+                    .iter()
+                    .flat_map(|(txid, tx_index, nfs)| {
+                        nfs.iter().map(move |nf| (*txid, *tx_index, nf.to_bytes()))
+                    }),
+            );
+
+            note_positions.extend(block.transactions().iter().flat_map(|wtx| {
+                wtx.sapling_outputs
+                    .iter()
+                    .map(|out| out.note_commitment_tree_position())
+            }));
+
+            let block_commitments = block.into_commitments();
+            sapling_commitments.extend(block_commitments.sapling);
+            #[cfg(feature = "orchard")]
+            orchard_commitments.extend(block_commitments.orchard);
+        }
+
+        // Prune the nullifier map of entries we no longer need.
+        if let Some(meta) = self.block_fully_scanned()? {
+            let prune_below = meta.block_height().saturating_sub(PRUNING_DEPTH);
+            self.nullifier_map
+                .retain(|_, locator| locator.block_height >= prune_below);
+        }
+
+        // Update the note commitment trees with all newly read note commitments.
+        self.sapling_tree
+            .batch_insert(sapling_start_position, sapling_commitments.into_iter())?;
+        #[cfg(feature = "orchard")]
+        self.orchard_tree
+            .batch_insert(orchard_start_position, orchard_commitments.into_iter())?;
+
+        // Update now-expired transactions that didn't get mined.
+        self.update_expired_notes(last_scanned_height);
+
+        self.scan_complete(start_height..(last_scanned_height + 1), &note_positions);
+
+        Ok(())
+    }
+
+    fn update_chain_tip(&mut self, tip_height: BlockHeight) -> Result<(), Self::Error> {
+        // If the caller provided a chain tip that is before Sapling activation, do nothing.
+        let sapling_activation = match self.params.activation_height(NetworkUpgrade::Sapling) {
+            Some(h) if h <= tip_height => h,
+            _ => return Ok(()),
+        };
+
+        let max_scanned = self.max_scanned_height();
+        let wallet_birthday = self.wallet_birthday();
+
+        // If the chain tip is below the prior max scanned height, then the caller has caught
+        // the chain in the middle of a reorg. Do nothing; the caller will detect the reorg when
+        // scanning and rewind the wallet via `truncate_to_height`.
+        if max_scanned.map_or(false, |h| tip_height < h) {
+            return Ok(());
+        }
+
+        // `ScanRange` uses an exclusive upper bound.
+        let chain_end = tip_height + 1;
+
+        // Create a scanning range for the fragment of the last shard leading up to new tip.
+        // We set a lower bound at the wallet birthday (if known), because account creation
+        // requires specifying a tree frontier that ensures we don't need tree information
+        // prior to the birthday.
+        let shard_start_height = self.sapling_subtree_end_heights.values().max().copied();
+        let tip_shard_entry = shard_start_height.filter(|h| h < &chain_end).map(|h| {
+            let min_to_scan = wallet_birthday.filter(|b| b > &h).unwrap_or(h);
+            ScanRange::from_parts(min_to_scan..chain_end, ScanPriority::ChainTip)
+        });
+
+        // Create scan ranges to either validate potentially invalid blocks at the wallet's
+        // view of the chain tip, or connect the prior tip to the new tip.
+        let tip_entry = match max_scanned {
+            // No blocks have been scanned, so the new scan range is anchored at the wallet
+            // birthday if there is one, or else all blocks up to the chain tip are ignored.
+            None => wallet_birthday.map_or_else(
+                || ScanRange::from_parts(sapling_activation..chain_end, ScanPriority::Ignored),
+                |wallet_birthday| {
+                    ScanRange::from_parts(wallet_birthday..chain_end, ScanPriority::Historic)
+                },
+            ),
+            Some(max_scanned) => {
+                let min_unscanned = max_scanned + 1;
+
+                if tip_shard_entry.is_none() {
+                    // Without shard metadata, we are scanning linearly.
+                    ScanRange::from_parts(min_unscanned..chain_end, ScanPriority::Historic)
+                } else {
+                    let stable_height = tip_height.saturating_sub(PRUNING_DEPTH);
+                    if max_scanned > stable_height {
+                        ScanRange::from_parts(min_unscanned..chain_end, ScanPriority::ChainTip)
+                    } else {
+                        // Verify the `VERIFY_LOOKAHEAD` blocks above the max scanned height
+                        // before any `ChainTip` ranges are scanned, limited to the stable
+                        // region of the chain.
+                        ScanRange::from_parts(
+                            min_unscanned..min(stable_height + 1, min_unscanned + VERIFY_LOOKAHEAD),
+                            ScanPriority::Verify,
+                        )
+                    }
+                }
+            }
+        };
+
+        let query_range = match tip_shard_entry.as_ref() {
+            Some(se) => Range {
+                start: min(se.block_range().start, tip_entry.block_range().start),
+                end: max(se.block_range().end, tip_entry.block_range().end),
+            },
+            None => tip_entry.block_range().clone(),
+        };
+
+        self.replace_queue_entries(
+            &query_range,
+            tip_shard_entry.into_iter().chain(Some(tip_entry)),
+            false,
+        );
+
+        Ok(())
+    }
+
+    fn store_decrypted_tx(&mut self, d_tx: DecryptedTransaction) -> Result<(), Self::Error> {
+        let txid = self.put_tx_data(d_tx.tx, None, None)?;
+
+        for output in d_tx.sapling_outputs {
+            let value = NonNegativeAmount::try_from(output.note.value())
+                .map_err(|_| Error::Balance(BalanceError::Overflow))?;
+            match output.transfer_type {
+                TransferType::Outgoing => {
+                    self.put_sent_output(
+                        txid,
+                        output.account,
+                        output.index,
+                        &Recipient::Sapling(output.note.recipient()),
+                        value,
+                        Some(&output.memo),
+                    );
+                }
+                TransferType::WalletInternal => {
+                    self.put_sent_output(
+                        txid,
+                        output.account,
+                        output.index,
+                        &Recipient::InternalAccount(
+                            output.account,
+                            PoolType::Shielded(ShieldedProtocol::Sapling),
+                        ),
+                        value,
+                        Some(&output.memo),
+                    );
+                    self.put_decrypted_note(
+                        txid,
+                        ShieldedProtocol::Sapling,
+                        output,
+                        Note::Sapling(output.note.clone()),
+                    );
+                }
+                TransferType::Incoming => {
+                    self.put_decrypted_note(
+                        txid,
+                        ShieldedProtocol::Sapling,
+                        output,
+                        Note::Sapling(output.note.clone()),
+                    );
+                }
+            }
+        }
+
+        #[cfg(feature = "orchard")]
+        for output in d_tx.orchard_outputs {
+            let value = NonNegativeAmount::from_u64(output.note.value().inner())
+                .map_err(|_| Error::Balance(BalanceError::Overflow))?;
+            match output.transfer_type {
+                TransferType::Outgoing => {
+                    let recipient =
+                        UnifiedAddress::from_receivers(Some(output.note.recipient()), None, None)
+                            .expect(
+                                "An Orchard receiver is sufficient to construct a unified address",
+                            );
+                    self.put_sent_output(
+                        txid,
+                        output.account,
+                        output.index,
+                        &Recipient::Unified(
+                            recipient,
+                            PoolType::Shielded(ShieldedProtocol::Orchard),
+                        ),
+                        value,
+                        Some(&output.memo),
+                    );
+                }
+                TransferType::WalletInternal => {
+                    self.put_sent_output(
+                        txid,
+                        output.account,
+                        output.index,
+                        &Recipient::InternalAccount(
+                            output.account,
+                            PoolType::Shielded(ShieldedProtocol::Orchard),
+                        ),
+                        value,
+                        Some(&output.memo),
+                    );
+                    self.put_decrypted_note(
+                        txid,
+                        ShieldedProtocol::Orchard,
+                        output,
+                        Note::Orchard(output.note),
+                    );
+                }
+                TransferType::Incoming => {
+                    self.put_decrypted_note(
+                        txid,
+                        ShieldedProtocol::Orchard,
+                        output,
+                        Note::Orchard(output.note),
+                    );
+                }
+            }
+        }
+
+        // If any of the shielded notes spent in the transaction are ours, mark them as spent.
+        // The transaction may not yet have been mined; if it expires without being mined, the
+        // notes will be released when a block above its expiry height is scanned.
+        if let Some(bundle) = d_tx.tx.sapling_bundle() {
+            for spend in bundle.shielded_spends() {
+                self.mark_note_spent(ShieldedProtocol::Sapling, &spend.nullifier().0, txid);
+            }
+        }
+
+        #[cfg(feature = "orchard")]
+        if let Some(bundle) = d_tx.tx.orchard_bundle() {
+            for action in bundle.actions() {
+                self.mark_note_spent(
+                    ShieldedProtocol::Orchard,
+                    &action.nullifier().to_bytes(),
+                    txid,
+                );
+            }
+        }
+
+        // If any of the utxos spent in the transaction are ours, mark them as spent.
+        #[cfg(feature = "transparent-inputs")]
+        for txin in d_tx
+            .tx
+            .transparent_bundle()
+            .iter()
+            .flat_map(|b| b.vin.iter())
+        {
+            self.mark_utxo_spent(&txin.prevout, txid);
+        }
+
+        // If the transaction has transparent outputs and spends shielded notes belonging to
+        // the wallet, record the transparent outputs as sent by the spending account.
+        if d_tx
+            .tx
+            .transparent_bundle()
+            .iter()
+            .any(|b| !b.vout.is_empty())
+        {
+            let spending_account = d_tx
+                .tx
+                .sapling_bundle()
+                .iter()
+                .flat_map(|b| b.shielded_spends().iter())
+                .find_map(|spend| {
+                    self.received_notes.iter().find_map(|(id, note)| {
+                        (id.protocol() == ShieldedProtocol::Sapling
+                            && note.nf == Some(spend.nullifier().0))
+                        .then_some(note.account)
+                    })
+                });
+
+            if let Some(account) = spending_account {
+                for (output_index, txout) in d_tx
+                    .tx
+                    .transparent_bundle()
+                    .iter()
+                    .flat_map(|b| b.vout.iter())
+                    .enumerate()
+                {
+                    if let Some(address) = txout.recipient_address() {
+                        self.put_sent_output(
+                            txid,
+                            account,
+                            output_index,
+                            &Recipient::Transparent(address),
+                            txout.value,
+                            None,
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn store_sent_tx(&mut self, sent_tx: &SentTransaction) -> Result<(), Self::Error> {
+        let fee = NonNegativeAmount::try_from(sent_tx.fee_amount)
+            .map_err(|_| Error::Balance(BalanceError::Underflow))?;
+        let txid = self.put_tx_data(sent_tx.tx, Some(fee), Some(sent_tx.created))?;
+
+        // Mark notes as spent, so that they aren't selected again by a subsequent transaction
+        // before this one has been mined.
+        if let Some(bundle) = sent_tx.tx.sapling_bundle() {
+            for spend in bundle.shielded_spends() {
+                self.mark_note_spent(ShieldedProtocol::Sapling, &spend.nullifier().0, txid);
+            }
+        }
+
+        #[cfg(feature = "orchard")]
+        if let Some(bundle) = sent_tx.tx.orchard_bundle() {
+            for action in bundle.actions() {
+                self.mark_note_spent(
+                    ShieldedProtocol::Orchard,
+                    &action.nullifier().to_bytes(),
+                    txid,
+                );
+            }
+        }
+
+        #[cfg(feature = "transparent-inputs")]
+        for utxo_outpoint in &sent_tx.utxos_spent {
+            self.mark_utxo_spent(utxo_outpoint, txid);
+        }
+
+        for output in &sent_tx.outputs {
+            self.put_sent_output(
+                txid,
+                sent_tx.account,
+                output.output_index(),
+                output.recipient(),
+                output.value(),
+                output.memo(),
+            );
+
+            if let Some((account, note)) = output.sapling_change_to() {
+                self.put_decrypted_note(
+                    txid,
+                    ShieldedProtocol::Sapling,
+                    &DecryptedOutput {
+                        index: output.output_index(),
+                        note: note.clone(),
+                        account: *account,
+                        memo: change_memo(output.memo()),
+                        transfer_type: TransferType::WalletInternal,
+                    },
+                    Note::Sapling(note.clone()),
+                );
+            }
+
+            #[cfg(feature = "orchard")]
+            if let Some((account, note)) = output.orchard_change_to() {
+                self.put_decrypted_note(
+                    txid,
+                    ShieldedProtocol::Orchard,
+                    &DecryptedOutput {
+                        index: output.output_index(),
+                        note: *note,
+                        account: *account,
+                        memo: change_memo(output.memo()),
+                        transfer_type: TransferType::WalletInternal,
+                    },
+                    Note::Orchard(*note),
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    fn abandon_transaction(&mut self, txid: TxId) -> Result<(), Self::Error> {
+        match self.transactions.get(&txid) {
+            Some(tx) if tx.mined_height.is_none() => (),
+            _ => return Err(Error::UnminedTransactionNotFound(txid)),
+        }
+
+        for note in self.received_notes.values_mut() {
+            if note.spent == Some(txid) {
+                note.spent = None;
+            }
+        }
+        #[cfg(feature = "transparent-inputs")]
+        for utxo in self.utxos.values_mut() {
+            if utxo.spent == Some(txid) {
+                utxo.spent = None;
+            }
+        }
+
+        // Notes received in the transaction will be rediscovered by scanning if the
+        // transaction is subsequently mined.
+        self.received_notes.retain(|id, _| id.txid() != &txid);
+
+        Ok(())
+    }
+
+    fn truncate_to_height(&mut self, block_height: BlockHeight) -> Result<(), Self::Error> {
+        // Recall where we synced up to previously.
+        let last_scanned_height = self
+            .max_scanned_height()
+            .unwrap_or_else(|| self.sapling_activation_height() - 1);
+
+        if block_height < last_scanned_height - PRUNING_DEPTH {
+            if let Some(h) = self.get_min_unspent_height()? {
+                if block_height > h {
+                    return Err(Error::RequestedRewindInvalid(h, block_height));
+                }
+            }
+        }
+
+        // nothing to do if we're deleting back down to the max height
+        if block_height < last_scanned_height {
+            // Truncate the note commitment trees
+            self.sapling_tree
+                .truncate_removing_checkpoint(&block_height)?;
+            #[cfg(feature = "orchard")]
+            self.orchard_tree
+                .truncate_removing_checkpoint(&block_height)?;
+
+            // Rewind received notes. Sent outputs are retained, as they may contain data that
+            // is not recoverable from the chain.
+            let transactions = &self.transactions;
+            self.received_notes.retain(|id, _| {
+                transactions
+                    .get(id.txid())
+                    .and_then(|tx| tx.mined_height)
+                    .map_or(true, |h| h <= block_height)
+            });
+
+            // Rewind utxos
+            #[cfg(feature = "transparent-inputs")]
+            self.utxos
+                .retain(|_, utxo| utxo.output.height() <= block_height);
+
+            // Un-mine transactions.
+            for tx in self.transactions.values_mut() {
+                if tx.mined_height.map_or(false, |h| h > block_height) {
+                    tx.mined_height = None;
+                    tx.tx_index = None;
+                }
+            }
+
+            // Now that they aren't depended on, delete scanned blocks.
+            self.blocks.retain(|h, _| *h <= block_height);
+
+            // Delete from the nullifier map any entries with a locator referencing a block
+            // height greater than the truncation height.
+            self.nullifier_map
+                .retain(|_, locator| locator.block_height <= block_height);
+
+            // Delete from the scanning queue any range with a start height greater than the
+            // truncation height, and then truncate any remaining range so that it ends at the
+            // truncation height + 1.
+            let end_height = block_height + 1;
+            self.scan_queue = self
+                .scan_queue
+                .drain(..)
+                .filter(|range| range.block_range().start <= block_height)
+                .map(|range| {
+                    if range.block_range().end > end_height {
+                        ScanRange::from_parts(
+                            range.block_range().start..end_height,
+                            range.priority(),
+                        )
+                    } else {
+                        range
+                    }
+                })
+                .collect();
+
+            // Prioritize the height we just rewound to for verification.
+            let query_range = block_height..end_height;
+            let scan_range = ScanRange::from_parts(query_range.clone(), ScanPriority::Verify);
+            self.replace_queue_entries(&query_range, Some(scan_range).into_iter(), false);
+        }
+
+        Ok(())
+    }
+
+    fn put_received_transparent_utxo(
+        &mut self,
+        _output: &WalletTransparentOutput,
+    ) -> Result<Self::UtxoRef, Self::Error> {
+        #[cfg(feature = "transparent-inputs")]
+        {
+            let mut receiving_account = None;
+            for id in self.accounts.keys() {
+                if self
+                    .get_transparent_receivers(*id)?
+                    .contains_key(_output.recipient_address())
+                {
+                    receiving_account = Some(*id);
+                    break;
+                }
+            }
+            let account = receiving_account
+                .ok_or(Error::AddressNotRecognized(*_output.recipient_address()))?;
+
+            let outpoint = _output.outpoint().clone();
+            match self.utxos.entry(outpoint.clone()) {
+                Entry::Occupied(mut e) => {
+                    let existing = e.get_mut();
+                    existing.account = account;
+                    existing.output = _output.clone();
+                }
+                Entry::Vacant(e) => {
+                    e.insert(ReceivedUtxo {
+                        account,
+                        output: _output.clone(),
+                        spent: None,
+                    });
+                }
+            }
+            Ok(outpoint)
+        }
+
+        #[cfg(not(feature = "transparent-inputs"))]
+        panic!(
+            "The wallet must be compiled with the transparent-inputs feature to use this method."
+        );
+    }
+}
+
+impl<P: consensus::Parameters> WalletCommitmentTrees for MemoryWalletDb<P> {
+    type Error = Infallible;
+    type SaplingShardStore<'a> = MemoryShardStore<sapling::Node, BlockHeight>;
+    #[cfg(feature = "orchard")]
+    type OrchardShardStore<'a> = MemoryShardStore<orchard::tree::MerkleHashOrchard, BlockHeight>;
+
+    fn with_sapling_tree_mut<F, A, E>(&mut self, mut callback: F) -> Result<A, E>
+    where
+        for<'a> F: FnMut(
+            &'a mut ShardTree<
+                Self::SaplingShardStore<'a>,
+                { sapling::NOTE_COMMITMENT_TREE_DEPTH },
+                SAPLING_SHARD_HEIGHT,
+            >,
+        ) -> Result<A, E>,
+        E: From<ShardTreeError<Infallible>>,
+    {
+        callback(&mut self.sapling_tree)
+    }
+
+    fn put_sapling_subtree_roots(
+        &mut self,
+        start_index: u64,
+        roots: &[CommitmentTreeRoot<sapling::Node>],
+    ) -> Result<(), ShardTreeError<Self::Error>> {
+        for (root, i) in roots.iter().zip(0u64..) {
+            let root_addr = Address::from_parts(SAPLING_SHARD_HEIGHT.into(), start_index + i);
+            self.sapling_tree.insert(root_addr, *root.root_hash())?;
+            self.sapling_subtree_end_heights
+                .insert(start_index + i, root.subtree_end_height());
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "orchard")]
+    fn with_orchard_tree_mut<F, A, E>(&mut self, mut callback: F) -> Result<A, E>
+    where
+        for<'a> F: FnMut(
+            &'a mut ShardTree<
+                Self::OrchardShardStore<'a>,
+                { ORCHARD_SHARD_HEIGHT * 2 },
+                ORCHARD_SHARD_HEIGHT,
+            >,
+        ) -> Result<A, E>,
+        E: From<ShardTreeError<Self::Error>>,
+    {
+        callback(&mut self.orchard_tree)
+    }
+
+    #[cfg(feature = "orchard")]
+    fn put_orchard_subtree_roots(
+        &mut self,
+        start_index: u64,
+        roots: &[CommitmentTreeRoot<orchard::tree::MerkleHashOrchard>],
+    ) -> Result<(), ShardTreeError<Self::Error>> {
+        for (root, i) in roots.iter().zip(0u64..) {
+            let root_addr = Address::from_parts(ORCHARD_SHARD_HEIGHT.into(), start_index + i);
+            self.orchard_tree.insert(root_addr, *root.root_hash())?;
+            self.orchard_subtree_end_heights
+                .insert(start_index + i, root.subtree_end_height());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secrecy::SecretVec;
+    use zcash_primitives::{
+        consensus::{BlockHeight, Network, NetworkUpgrade, Parameters},
+        transaction::components::amount::NonNegativeAmount,
+        zip32::AccountId,
+    };
+
+    use crate::{
+        data_api::{
+            scanning::{ScanPriority, ScanRange},
+            AccountBirthday, AccountKind, WalletRead, WalletWrite,
+        },
+        keys::UnifiedSpendingKey,
+        proto::service::TreeState,
+    };
+
+    use super::{Error, MemoryWalletDb, DEFAULT_UA_REQUEST};
+
+    /// Returns a birthday at the given height, with an empty Sapling note commitment tree.
+    fn birthday_at(height: BlockHeight) -> AccountBirthday {
+        AccountBirthday::from_treestate(
+            TreeState {
+                height: u64::from(u32::from(height) - 1),
+                // The serialization of an empty `CommitmentTree`.
+                sapling_tree: "000000".to_string(),
+                ..Default::default()
+            },
+            None,
+        )
+        .ok()
+        .unwrap()
+    }
+
+    fn sapling_activation() -> BlockHeight {
+        Network::TestNetwork
+            .activation_height(NetworkUpgrade::Sapling)
+            .unwrap()
+    }
+
+    #[test]
+    fn create_account_and_generate_addresses() {
+        let mut db = MemoryWalletDb::new(Network::TestNetwork, 100);
+        let seed = SecretVec::new(vec![0u8; 32]);
+        let birthday = birthday_at(sapling_activation() + 1000);
+
+        let (account, usk) = db.create_account(&seed, birthday.clone()).unwrap();
+        assert_eq!(account, AccountId::ZERO);
+        let ufvk = usk.to_unified_full_viewing_key();
+        assert_eq!(db.get_account_for_ufvk(&ufvk).unwrap(), Some(account));
+
+        // The second account derived from the same seed uses the next ZIP 32 account index.
+        let (account1, _) = db.create_account(&seed, birthday.clone()).unwrap();
+        assert_eq!(account1, AccountId::try_from(1).unwrap());
+        assert_matches!(
+            db.get_account_kind(account1).unwrap(),
+            Some(AccountKind::Derived { account_index, .. })
+                if account_index == AccountId::try_from(1).unwrap()
+        );
+        assert_eq!(db.get_account_ids().unwrap(), vec![account, account1]);
+
+        // The default address is available as soon as the account is created.
+        let (default_addr, _) = ufvk.default_address(DEFAULT_UA_REQUEST);
+        assert_eq!(
+            db.get_current_address(account).unwrap(),
+            Some(default_addr.clone())
+        );
+
+        let next_addr = db
+            .get_next_available_address(account, DEFAULT_UA_REQUEST)
+            .unwrap()
+            .unwrap();
+        assert_ne!(next_addr, default_addr);
+        assert_eq!(db.get_current_address(account).unwrap(), Some(next_addr));
+
+        // Unknown accounts have no addresses.
+        let unknown = AccountId::try_from(5).unwrap();
+        assert_eq!(db.get_current_address(unknown).unwrap(), None);
+        assert_eq!(
+            db.get_next_available_address(unknown, DEFAULT_UA_REQUEST)
+                .unwrap(),
+            None
+        );
+
+        // A viewing key that is already tracked cannot be imported again.
+        assert_matches!(
+            db.import_account_ufvk(&ufvk, birthday),
+            Err(Error::AccountCollision(a)) if a == account
+        );
+    }
+
+    #[test]
+    fn update_chain_tip_schedules_scanning_from_birthday() {
+        let mut db = MemoryWalletDb::new(Network::TestNetwork, 100);
+        let birthday_height = sapling_activation() + 1000;
+        db.create_account(&SecretVec::new(vec![0u8; 32]), birthday_at(birthday_height))
+            .unwrap();
+
+        assert_eq!(db.chain_height().unwrap(), None);
+        assert_eq!(db.get_wallet_birthday().unwrap(), Some(birthday_height));
+
+        let tip = birthday_height + 500;
+        db.update_chain_tip(tip).unwrap();
+        assert_eq!(db.chain_height().unwrap(), Some(tip));
+
+        // Blocks below the birthday are ignored, and are not suggested for scanning.
+        assert_eq!(
+            db.suggest_scan_ranges().unwrap(),
+            vec![ScanRange::from_parts(
+                birthday_height..(tip + 1),
+                ScanPriority::Historic
+            )]
+        );
+
+        // Nothing has been scanned, so the wallet has no balance.
+        let summary = db.get_wallet_summary(1).unwrap().unwrap();
+        assert_eq!(summary.chain_tip_height(), tip);
+        assert_eq!(summary.fully_scanned_height(), birthday_height - 1);
+        assert_eq!(
+            summary.account_balances()[&AccountId::ZERO].total(),
+            NonNegativeAmount::ZERO
+        );
+        assert_eq!(
+            db.block_fully_scanned().unwrap().map(|m| m.block_height()),
+            None
+        );
+    }
+
+    #[test]
+    fn import_ufvk_and_truncate_unscanned_wallet() {
+        let mut db = MemoryWalletDb::new(Network::TestNetwork, 100);
+        let birthday_height = sapling_activation() + 1000;
+        db.import_account_ufvk(
+            &UnifiedSpendingKey::from_seed(&Network::TestNetwork, &[1u8; 32], AccountId::ZERO)
+                .unwrap()
+                .to_unified_full_viewing_key(),
+            birthday_at(birthday_height),
+        )
+        .unwrap();
+        assert_eq!(
+            db.get_account_kind(AccountId::ZERO).unwrap(),
+            Some(AccountKind::ViewOnly)
+        );
+
+        // With no scanned blocks, truncation leaves the wallet unchanged.
+        db.update_chain_tip(birthday_height + 10).unwrap();
+        db.truncate_to_height(birthday_height + 5).unwrap();
+        assert_eq!(db.chain_height().unwrap(), Some(birthday_height + 10));
+        assert_eq!(db.get_max_height_hash().unwrap(), None);
+    }
+
+    /// Runs the backend-agnostic Sapling conformance scenarios against the in-memory wallet.
+    #[cfg(feature = "test-dependencies")]
+    mod conformance {
+        use std::convert::Infallible;
+
+        use zcash_primitives::consensus::Network;
+
+        use crate::data_api::{
+            mem::{Error, MemoryWalletDb},
+            testing::conformance::{sapling as scenarios, DataStoreFactory},
+        };
+
+        struct MemoryWalletDbFactory;
+
+        impl DataStoreFactory for MemoryWalletDbFactory {
+            type Error = Error;
+            type CommitmentTreeError = Infallible;
+            type DataStore = MemoryWalletDb<Network>;
+
+            fn new_data_store(&self, network: Network) -> Result<Self::DataStore, Self::Error> {
+                Ok(MemoryWalletDb::new(network, 100))
+            }
+        }
+
+        #[test]
+        fn send_proposed_transfer() {
+            scenarios::send_proposed_transfer(MemoryWalletDbFactory)
+        }
+
+        #[test]
+        fn create_to_address_fails_on_incorrect_usk() {
+            scenarios::create_to_address_fails_on_incorrect_usk(MemoryWalletDbFactory)
+        }
+
+        #[test]
+        fn create_to_address_fails_for_view_only_account() {
+            scenarios::create_to_address_fails_for_view_only_account(MemoryWalletDbFactory)
+        }
+
+        #[test]
+        fn proposal_fails_with_no_blocks() {
+            scenarios::proposal_fails_with_no_blocks(MemoryWalletDbFactory)
+        }
+
+        #[test]
+        fn spend_fails_on_unverified_notes() {
+            scenarios::spend_fails_on_unverified_notes(MemoryWalletDbFactory)
+        }
+
+        #[test]
+        fn spend_fails_on_locked_notes() {
+            scenarios::spend_fails_on_locked_notes(MemoryWalletDbFactory)
+        }
+
+        #[test]
+        fn ovk_policy_prevents_recovery_from_chain() {
+            scenarios::ovk_policy_prevents_recovery_from_chain(MemoryWalletDbFactory)
+        }
+
+        #[test]
+        fn spend_succeeds_to_t_addr_zero_change() {
+            scenarios::spend_succeeds_to_t_addr_zero_change(MemoryWalletDbFactory)
+        }
+
+        #[test]
+        fn change_note_spends_succeed() {
+            scenarios::change_note_spends_succeed(MemoryWalletDbFactory)
+        }
+
+        #[test]
+        fn external_address_change_spends_detected_in_restore_from_seed() {
+            scenarios::external_address_change_spends_detected_in_restore_from_seed(
+                MemoryWalletDbFactory,
+            )
+        }
+
+        #[test]
+        fn mempool_transaction_is_pending_until_mined() {
+            scenarios::mempool_transaction_is_pending_until_mined(MemoryWalletDbFactory)
+        }
+
+        #[test]
+        fn mempool_transaction_expires_unmined() {
+            scenarios::mempool_transaction_expires_unmined(MemoryWalletDbFactory)
+        }
+
+        #[test]
+        fn abandoned_transaction_releases_spent_notes() {
+            scenarios::abandoned_transaction_releases_spent_notes(MemoryWalletDbFactory)
+        }
+
+        #[test]
+        fn zip317_spend() {
+            scenarios::zip317_spend(MemoryWalletDbFactory)
+        }
+
+        #[test]
+        #[cfg(feature = "transparent-inputs")]
+        fn shield_transparent() {
+            scenarios::shield_transparent(MemoryWalletDbFactory)
+        }
+
+        #[test]
+        fn birthday_in_anchor_shard() {
+            scenarios::birthday_in_anchor_shard(MemoryWalletDbFactory)
+        }
+    }
+}
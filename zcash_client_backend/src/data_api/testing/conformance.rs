@@ -0,0 +1,1336 @@
+//! A backend-agnostic test harness for implementations of the wallet data access traits.
+//!
+//! The harness in this module drives a wallet data store through the [`chain`] and [`wallet`]
+//! APIs, generating fake compact blocks and feeding them to the store via
+//! [`scan_cached_blocks`]. It is parameterized over a [`DataStoreFactory`], so that every
+//! storage backend can be checked against the same scenarios; the scenarios themselves are
+//! provided by the [`sapling`](self::sapling) and `orchard` modules.
+//!
+//! A backend runs the suite by implementing [`DataStoreFactory`] and invoking each scenario
+//! from its own tests:
+//!
+//! ```ignore
+//! #[test]
+//! fn send_proposed_transfer() {
+//!     conformance::sapling::send_proposed_transfer(MyDbFactory)
+//! }
+//! ```
+//!
+//! [`chain`]: crate::data_api::chain
+//! [`wallet`]: crate::data_api::wallet
+
+use std::{cell::RefCell, collections::BTreeMap, convert::Infallible, fmt, num::NonZeroU32};
+
+use ::sapling::{
+    note_encryption::{sapling_note_encryption, SaplingDomain},
+    prover::{OutputProver, SpendProver},
+    util::generate_random_rseed,
+    value::NoteValue,
+    zip32::DiversifiableFullViewingKey,
+    Note, Nullifier, PaymentAddress,
+};
+use nonempty::NonEmpty;
+use rand_core::{OsRng, RngCore};
+use secrecy::Secret;
+use zcash_note_encryption::Domain;
+use zcash_primitives::{
+    block::BlockHash,
+    consensus::{self, BlockHeight, Network, NetworkUpgrade, Parameters},
+    memo::{Memo, MemoBytes},
+    transaction::{
+        components::amount::NonNegativeAmount,
+        fees::{zip317::FeeError as Zip317FeeError, FeeRule, StandardFeeRule},
+        Transaction, TxId,
+    },
+    zip32::{AccountId, DiversifierIndex},
+};
+use zcash_proofs::prover::LocalTxProver;
+
+use crate::{
+    address::Address,
+    data_api::{
+        self,
+        chain::{self, scan_cached_blocks, BlockSource, ScanSummary},
+        wallet::{
+            create_proposed_transactions, estimate_fee,
+            input_selection::{
+                GreedyInputSelector, GreedyInputSelectorError, InputSelector, PrivacyPolicy,
+                Proposal,
+            },
+            propose_consolidation, propose_send_max_transfer, propose_standard_transfer_to_address,
+            propose_transfer, spend,
+        },
+        AccountBalance, AccountBirthday, InputSource, WalletCommitmentTrees, WalletRead,
+        WalletSummary, WalletWrite,
+    },
+    fees::{standard, ChangePoolPolicy, ChangeStrategy, DustOutputPolicy},
+    keys::UnifiedSpendingKey,
+    proto::compact_formats::{
+        self as compact, CompactBlock, CompactSaplingOutput, CompactSaplingSpend, CompactTx,
+    },
+    proto::proposal,
+    wallet::OvkPolicy,
+    zip321, ShieldedProtocol,
+};
+
+#[cfg(feature = "transparent-inputs")]
+use {
+    crate::data_api::wallet::{
+        input_selection::ShieldingSelector, propose_shielding, shield_transparent_funds,
+    },
+    zcash_primitives::legacy::TransparentAddress,
+};
+
+#[cfg(feature = "orchard")]
+pub mod orchard;
+pub mod sapling;
+
+/// A factory for the wallet data stores under test.
+///
+/// Each call to [`DataStoreFactory::new_data_store`] must return a new, empty data store that
+/// shares no state with any previously created store.
+pub trait DataStoreFactory {
+    /// The type of errors produced by the data store.
+    type Error: fmt::Debug;
+
+    /// The type of errors produced by the data store's note commitment trees.
+    type CommitmentTreeError: fmt::Debug;
+
+    /// The type of the data store under test.
+    type DataStore: InputSource<Error = Self::Error>
+        + WalletRead<Error = Self::Error>
+        + WalletWrite
+        + WalletCommitmentTrees<Error = Self::CommitmentTreeError>;
+
+    /// Constructs a new, empty data store for the given network.
+    fn new_data_store(&self, network: Network) -> Result<Self::DataStore, Self::Error>;
+}
+
+/// Trait used by tests that require a block cache.
+pub trait TestCache {
+    type BlockSource: BlockSource;
+    type InsertResult;
+
+    /// Exposes the block cache as a [`BlockSource`].
+    fn block_source(&self) -> &Self::BlockSource;
+
+    /// Inserts a CompactBlock into the cache DB.
+    fn insert(&self, cb: &CompactBlock) -> Self::InsertResult;
+}
+
+/// A block cache that holds compact blocks in memory.
+#[derive(Default)]
+pub struct BlockCache {
+    blocks: RefCell<BTreeMap<BlockHeight, CompactBlock>>,
+}
+
+impl BlockCache {
+    /// Constructs a new, empty block cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl BlockSource for BlockCache {
+    type Error = Infallible;
+
+    fn with_blocks<F, WalletErrT>(
+        &self,
+        from_height: Option<BlockHeight>,
+        limit: Option<usize>,
+        mut with_block: F,
+    ) -> Result<(), chain::error::Error<WalletErrT, Self::Error>>
+    where
+        F: FnMut(CompactBlock) -> Result<(), chain::error::Error<WalletErrT, Self::Error>>,
+    {
+        let blocks = self
+            .blocks
+            .borrow()
+            .range(from_height.unwrap_or_else(|| BlockHeight::from_u32(0))..)
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|(_, cb)| cb.clone())
+            .collect::<Vec<_>>();
+
+        for cb in blocks {
+            with_block(cb)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl TestCache for BlockCache {
+    type BlockSource = BlockCache;
+    type InsertResult = ();
+
+    fn block_source(&self) -> &Self::BlockSource {
+        self
+    }
+
+    fn insert(&self, cb: &CompactBlock) {
+        self.blocks.borrow_mut().insert(cb.height(), cb.clone());
+    }
+}
+
+/// A builder for a conformance test.
+pub struct TestBuilder<Cache, DsFactory> {
+    network: Network,
+    cache: Cache,
+    ds_factory: DsFactory,
+    test_account_birthday: Option<AccountBirthday>,
+}
+
+impl TestBuilder<(), ()> {
+    /// Constructs a new test.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        TestBuilder {
+            network: Network::TestNetwork,
+            cache: (),
+            ds_factory: (),
+            test_account_birthday: None,
+        }
+    }
+}
+
+impl<DsFactory> TestBuilder<(), DsFactory> {
+    /// Adds a block cache to the test.
+    pub fn with_block_cache<Cache: TestCache>(self, cache: Cache) -> TestBuilder<Cache, DsFactory> {
+        TestBuilder {
+            network: self.network,
+            cache,
+            ds_factory: self.ds_factory,
+            test_account_birthday: self.test_account_birthday,
+        }
+    }
+}
+
+impl<Cache> TestBuilder<Cache, ()> {
+    /// Sets the factory used to construct the wallet data store under test.
+    pub fn with_data_store_factory<DsFactory: DataStoreFactory>(
+        self,
+        ds_factory: DsFactory,
+    ) -> TestBuilder<Cache, DsFactory> {
+        TestBuilder {
+            network: self.network,
+            cache: self.cache,
+            ds_factory,
+            test_account_birthday: self.test_account_birthday,
+        }
+    }
+}
+
+impl<Cache, DsFactory> TestBuilder<Cache, DsFactory> {
+    /// Adds an account to the wallet, derived from the all-zeroes seed, having the birthday
+    /// returned by the provided function.
+    pub fn with_test_account<F: FnOnce(&Network) -> AccountBirthday>(
+        mut self,
+        birthday: F,
+    ) -> Self {
+        self.test_account_birthday = Some(birthday(&self.network));
+        self
+    }
+}
+
+impl<Cache, DsFactory: DataStoreFactory> TestBuilder<Cache, DsFactory> {
+    /// Builds the state for this test.
+    pub fn build(self) -> TestState<Cache, DsFactory> {
+        let mut wallet_data = self.ds_factory.new_data_store(self.network).unwrap();
+
+        let test_account = self.test_account_birthday.map(|birthday| {
+            let seed = Secret::new(vec![0u8; 32]);
+            let (account, usk) = wallet_data.create_account(&seed, birthday.clone()).unwrap();
+            (account, usk, birthday)
+        });
+
+        TestState {
+            network: self.network,
+            cache: self.cache,
+            latest_cached_block: None,
+            orchard_tree_size: 0,
+            ds_factory: self.ds_factory,
+            wallet_data,
+            test_account,
+        }
+    }
+}
+
+/// The state for a conformance test.
+pub struct TestState<Cache, DsFactory: DataStoreFactory> {
+    network: Network,
+    cache: Cache,
+    latest_cached_block: Option<(BlockHeight, BlockHash, u32)>,
+    orchard_tree_size: u32,
+    ds_factory: DsFactory,
+    wallet_data: DsFactory::DataStore,
+    test_account: Option<(AccountId, UnifiedSpendingKey, AccountBirthday)>,
+}
+
+impl<Cache: TestCache, DsFactory: DataStoreFactory> TestState<Cache, DsFactory>
+where
+    <Cache::BlockSource as BlockSource>::Error: fmt::Debug,
+{
+    /// Exposes an immutable reference to the test's [`BlockSource`].
+    pub fn cache(&self) -> &Cache::BlockSource {
+        self.cache.block_source()
+    }
+
+    /// Returns the height, hash and Sapling note commitment tree size of the most recently
+    /// generated block.
+    pub fn latest_cached_block(&self) -> &Option<(BlockHeight, BlockHash, u32)> {
+        &self.latest_cached_block
+    }
+
+    fn next_block_position(&self) -> (BlockHeight, BlockHash, u32) {
+        self.latest_cached_block
+            .map(|(prev_height, prev_hash, end_size)| (prev_height + 1, prev_hash, end_size))
+            .unwrap_or_else(|| (self.sapling_activation_height(), BlockHash([0; 32]), 0))
+    }
+
+    fn cache_block(
+        &mut self,
+        cb: &CompactBlock,
+        initial_sapling_tree_size: u32,
+    ) -> Cache::InsertResult {
+        let res = self.cache.insert(cb);
+
+        self.latest_cached_block = Some((
+            cb.height(),
+            cb.hash(),
+            initial_sapling_tree_size
+                + cb.vtx.iter().map(|tx| tx.outputs.len() as u32).sum::<u32>(),
+        ));
+        self.orchard_tree_size = cb
+            .chain_metadata
+            .as_ref()
+            .map_or(0, |m| m.orchard_commitment_tree_size);
+
+        res
+    }
+
+    /// Creates a fake block at the expected next height containing a single output of the
+    /// given value, and inserts it into the cache.
+    pub fn generate_next_block(
+        &mut self,
+        dfvk: &DiversifiableFullViewingKey,
+        req: AddressType,
+        value: NonNegativeAmount,
+    ) -> (BlockHeight, Cache::InsertResult, Nullifier) {
+        let (height, prev_hash, initial_sapling_tree_size) = self.next_block_position();
+
+        let (res, nf) = self.generate_block_at(
+            height,
+            prev_hash,
+            dfvk,
+            req,
+            value,
+            initial_sapling_tree_size,
+        );
+
+        (height, res, nf)
+    }
+
+    /// Creates a fake block with the given height and hash containing a single output of
+    /// the given value, and inserts it into the cache.
+    ///
+    /// This generated block will be treated as the latest block, and subsequent calls to
+    /// [`Self::generate_next_block`] will build on it. The Orchard note commitment tree size
+    /// is carried over from the most recently generated block.
+    pub fn generate_block_at(
+        &mut self,
+        height: BlockHeight,
+        prev_hash: BlockHash,
+        dfvk: &DiversifiableFullViewingKey,
+        req: AddressType,
+        value: NonNegativeAmount,
+        initial_sapling_tree_size: u32,
+    ) -> (Cache::InsertResult, Nullifier) {
+        let (cb, nf) = fake_compact_block(
+            &self.network,
+            height,
+            prev_hash,
+            dfvk,
+            req,
+            value,
+            initial_sapling_tree_size,
+            self.orchard_tree_size,
+        );
+        let res = self.cache_block(&cb, initial_sapling_tree_size);
+
+        (res, nf)
+    }
+
+    /// Creates a fake block at the expected next height containing a single Orchard note of
+    /// the given value, sent to the default external address of the given full viewing key,
+    /// and inserts it into the cache.
+    #[cfg(feature = "orchard")]
+    pub fn generate_next_orchard_block(
+        &mut self,
+        fvk: &::orchard::keys::FullViewingKey,
+        value: NonNegativeAmount,
+    ) -> (BlockHeight, Cache::InsertResult, ::orchard::note::Nullifier) {
+        let (height, prev_hash, initial_sapling_tree_size) = self.next_block_position();
+
+        let (res, nf) = self.generate_orchard_block_at(
+            height,
+            prev_hash,
+            fvk,
+            value,
+            initial_sapling_tree_size,
+            self.orchard_tree_size,
+        );
+
+        (height, res, nf)
+    }
+
+    /// Creates a fake block with the given height and hash containing a single Orchard note
+    /// of the given value, and inserts it into the cache.
+    ///
+    /// This generated block will be treated as the latest block, and subsequent calls to
+    /// [`Self::generate_next_block`] (or similar) will build on it.
+    #[cfg(feature = "orchard")]
+    pub fn generate_orchard_block_at(
+        &mut self,
+        height: BlockHeight,
+        prev_hash: BlockHash,
+        fvk: &::orchard::keys::FullViewingKey,
+        value: NonNegativeAmount,
+        initial_sapling_tree_size: u32,
+        initial_orchard_tree_size: u32,
+    ) -> (Cache::InsertResult, ::orchard::note::Nullifier) {
+        let (cb, nf) = fake_compact_block_orchard(
+            &self.network,
+            height,
+            prev_hash,
+            fvk,
+            value,
+            initial_sapling_tree_size,
+            initial_orchard_tree_size,
+        );
+        let res = self.cache_block(&cb, initial_sapling_tree_size);
+
+        (res, nf)
+    }
+
+    /// Creates a fake block at the expected next height spending the given note, and
+    /// inserts it into the cache.
+    pub fn generate_next_block_spending(
+        &mut self,
+        dfvk: &DiversifiableFullViewingKey,
+        note: (Nullifier, NonNegativeAmount),
+        to: PaymentAddress,
+        value: NonNegativeAmount,
+    ) -> (BlockHeight, Cache::InsertResult) {
+        let (height, prev_hash, initial_sapling_tree_size) = self.next_block_position();
+
+        let cb = fake_compact_block_spending(
+            &self.network,
+            height,
+            prev_hash,
+            note,
+            dfvk,
+            to,
+            value,
+            initial_sapling_tree_size,
+            self.orchard_tree_size,
+        );
+        let res = self.cache_block(&cb, initial_sapling_tree_size);
+
+        (height, res)
+    }
+
+    /// Creates a fake block at the expected next height containing only the wallet
+    /// transaction with the given txid, and inserts it into the cache.
+    ///
+    /// This generated block will be treated as the latest block, and subsequent calls to
+    /// [`Self::generate_next_block`] (or similar) will build on it.
+    pub fn generate_next_block_including(
+        &mut self,
+        txid: TxId,
+    ) -> (BlockHeight, Cache::InsertResult) {
+        let tx = self
+            .wallet()
+            .get_transaction(txid)
+            .expect("TxId should exist in the wallet");
+
+        // Index 0 is by definition a coinbase transaction, and the wallet doesn't
+        // construct coinbase transactions. So we pretend here that the block has a
+        // coinbase transaction that does not have shielded coinbase outputs.
+        self.generate_next_block_from_tx(1, &tx)
+    }
+
+    /// Creates a fake block at the expected next height containing only the given
+    /// transaction, and inserts it into the cache.
+    ///
+    /// This generated block will be treated as the latest block, and subsequent calls to
+    /// [`Self::generate_next_block`] will build on it.
+    pub fn generate_next_block_from_tx(
+        &mut self,
+        tx_index: usize,
+        tx: &Transaction,
+    ) -> (BlockHeight, Cache::InsertResult) {
+        let (height, prev_hash, initial_sapling_tree_size) = self.next_block_position();
+
+        let cb = fake_compact_block_from_tx(
+            height,
+            prev_hash,
+            tx_index,
+            tx,
+            initial_sapling_tree_size,
+            self.orchard_tree_size,
+        );
+        let res = self.cache_block(&cb, initial_sapling_tree_size);
+
+        (height, res)
+    }
+
+    /// Invokes [`scan_cached_blocks`] with the given arguments, expecting success.
+    pub fn scan_cached_blocks(&mut self, from_height: BlockHeight, limit: usize) -> ScanSummary {
+        let result = self.try_scan_cached_blocks(from_height, limit);
+        assert!(result.is_ok(), "Scanning failed: {:?}", result.err());
+        result.unwrap()
+    }
+
+    /// Invokes [`scan_cached_blocks`] with the given arguments.
+    pub fn try_scan_cached_blocks(
+        &mut self,
+        from_height: BlockHeight,
+        limit: usize,
+    ) -> Result<
+        ScanSummary,
+        chain::error::Error<DsFactory::Error, <Cache::BlockSource as BlockSource>::Error>,
+    > {
+        scan_cached_blocks(
+            &self.network,
+            self.cache.block_source(),
+            &mut self.wallet_data,
+            from_height,
+            limit,
+        )
+    }
+
+    /// Resets the latest cached block to the most recent one in the block cache.
+    pub fn reset_latest_cached_block(&mut self) {
+        let mut latest = None;
+        let mut orchard_tree_size = 0;
+        self.cache
+            .block_source()
+            .with_blocks::<_, Infallible>(None, None, |block: CompactBlock| {
+                let chain_metadata = block.chain_metadata.unwrap();
+                latest = Some((
+                    block.height(),
+                    block.hash(),
+                    chain_metadata.sapling_commitment_tree_size,
+                ));
+                orchard_tree_size = chain_metadata.orchard_commitment_tree_size;
+                Ok(())
+            })
+            .unwrap();
+        self.latest_cached_block = latest;
+        self.orchard_tree_size = orchard_tree_size;
+    }
+}
+
+impl<Cache, DsFactory: DataStoreFactory> TestState<Cache, DsFactory> {
+    /// Exposes an immutable reference to the test's data store.
+    pub fn wallet(&self) -> &DsFactory::DataStore {
+        &self.wallet_data
+    }
+
+    /// Exposes a mutable reference to the test's data store.
+    pub fn wallet_mut(&mut self) -> &mut DsFactory::DataStore {
+        &mut self.wallet_data
+    }
+
+    /// Exposes the network in use.
+    pub fn network(&self) -> Network {
+        self.network
+    }
+
+    /// Convenience method for obtaining the Sapling activation height for the network under test.
+    pub fn sapling_activation_height(&self) -> BlockHeight {
+        self.network
+            .activation_height(NetworkUpgrade::Sapling)
+            .expect("Sapling activation height must be known.")
+    }
+
+    /// Exposes the test account, if enabled via [`TestBuilder::with_test_account`].
+    pub fn test_account(&self) -> Option<(AccountId, UnifiedSpendingKey, AccountBirthday)> {
+        self.test_account.as_ref().cloned()
+    }
+
+    /// Exposes the test account's Sapling DFVK, if enabled via [`TestBuilder::with_test_account`].
+    pub fn test_account_sapling(&self) -> Option<DiversifiableFullViewingKey> {
+        self.test_account
+            .as_ref()
+            .and_then(|(_, usk, _)| usk.to_unified_full_viewing_key().sapling().cloned())
+    }
+
+    /// Exposes the test account's Orchard FVK, if enabled via [`TestBuilder::with_test_account`].
+    #[cfg(feature = "orchard")]
+    pub fn test_account_orchard(&self) -> Option<::orchard::keys::FullViewingKey> {
+        self.test_account
+            .as_ref()
+            .and_then(|(_, usk, _)| usk.to_unified_full_viewing_key().orchard().cloned())
+    }
+
+    /// Replaces the wallet with a new, empty data store, and returns the old data store.
+    ///
+    /// The block cache is retained, but the resulting wallet has no accounts and has not
+    /// scanned any blocks; in particular, it has no test account.
+    pub fn reset(&mut self) -> DsFactory::DataStore {
+        self.test_account = None;
+        let wallet_data = self.ds_factory.new_data_store(self.network).unwrap();
+        std::mem::replace(&mut self.wallet_data, wallet_data)
+    }
+
+    /// Invokes [`create_spend_to_address`] with the given arguments.
+    ///
+    /// [`create_spend_to_address`]: crate::data_api::wallet::create_spend_to_address
+    #[allow(deprecated)]
+    #[allow(clippy::type_complexity)]
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_spend_to_address(
+        &mut self,
+        usk: &UnifiedSpendingKey,
+        to: &Address,
+        amount: NonNegativeAmount,
+        memo: Option<MemoBytes>,
+        ovk_policy: OvkPolicy,
+        min_confirmations: NonZeroU32,
+        change_memo: Option<MemoBytes>,
+    ) -> Result<
+        NonEmpty<TxId>,
+        data_api::error::Error<
+            DsFactory::Error,
+            DsFactory::CommitmentTreeError,
+            GreedyInputSelectorError<
+                Zip317FeeError,
+                <DsFactory::DataStore as InputSource>::NoteRef,
+            >,
+            Zip317FeeError,
+        >,
+    > {
+        let prover = test_prover();
+        data_api::wallet::create_spend_to_address(
+            &mut self.wallet_data,
+            &self.network,
+            &prover,
+            &prover,
+            usk,
+            to,
+            amount,
+            memo,
+            ovk_policy,
+            min_confirmations,
+            change_memo,
+        )
+    }
+
+    /// Invokes [`spend`] with the given arguments.
+    #[allow(clippy::type_complexity)]
+    pub fn spend<InputsT>(
+        &mut self,
+        input_selector: &InputsT,
+        usk: &UnifiedSpendingKey,
+        request: zip321::TransactionRequest,
+        privacy_policy: PrivacyPolicy,
+        ovk_policy: OvkPolicy,
+        min_confirmations: NonZeroU32,
+    ) -> Result<
+        NonEmpty<TxId>,
+        data_api::error::Error<
+            DsFactory::Error,
+            DsFactory::CommitmentTreeError,
+            InputsT::Error,
+            <InputsT::FeeRule as FeeRule>::Error,
+        >,
+    >
+    where
+        InputsT: InputSelector<InputSource = DsFactory::DataStore>,
+    {
+        let prover = test_prover();
+        spend(
+            &mut self.wallet_data,
+            &self.network,
+            &prover,
+            &prover,
+            input_selector,
+            usk,
+            request,
+            privacy_policy,
+            ovk_policy,
+            min_confirmations,
+        )
+    }
+
+    /// Invokes [`propose_transfer`] with the given arguments.
+    #[allow(clippy::type_complexity)]
+    pub fn propose_transfer<InputsT>(
+        &mut self,
+        spend_from_account: AccountId,
+        input_selector: &InputsT,
+        request: zip321::TransactionRequest,
+        privacy_policy: PrivacyPolicy,
+        min_confirmations: NonZeroU32,
+    ) -> Result<
+        Proposal<InputsT::FeeRule, <DsFactory::DataStore as InputSource>::NoteRef>,
+        data_api::error::Error<
+            DsFactory::Error,
+            Infallible,
+            InputsT::Error,
+            <InputsT::FeeRule as FeeRule>::Error,
+        >,
+    >
+    where
+        InputsT: InputSelector<InputSource = DsFactory::DataStore>,
+    {
+        propose_transfer::<_, _, _, Infallible>(
+            &mut self.wallet_data,
+            &self.network,
+            spend_from_account,
+            input_selector,
+            request,
+            privacy_policy,
+            min_confirmations,
+        )
+    }
+
+    /// Invokes [`estimate_fee`] with the given arguments.
+    #[allow(clippy::type_complexity)]
+    pub fn estimate_fee<InputsT>(
+        &self,
+        spend_from_account: AccountId,
+        input_selector: &InputsT,
+        request: zip321::TransactionRequest,
+        privacy_policy: PrivacyPolicy,
+        min_confirmations: NonZeroU32,
+    ) -> Result<
+        NonNegativeAmount,
+        data_api::error::Error<
+            DsFactory::Error,
+            Infallible,
+            InputsT::Error,
+            <InputsT::FeeRule as FeeRule>::Error,
+        >,
+    >
+    where
+        InputsT: InputSelector<InputSource = DsFactory::DataStore>,
+    {
+        estimate_fee::<_, _, _, Infallible>(
+            &self.wallet_data,
+            &self.network,
+            spend_from_account,
+            input_selector,
+            request,
+            privacy_policy,
+            min_confirmations,
+        )
+    }
+
+    /// Invokes [`propose_standard_transfer_to_address`] with the given arguments, and checks
+    /// that the resulting proposal survives a round trip through its protobuf serialization.
+    #[allow(clippy::type_complexity)]
+    #[allow(clippy::too_many_arguments)]
+    pub fn propose_standard_transfer<CommitmentTreeErrT>(
+        &mut self,
+        spend_from_account: AccountId,
+        fee_rule: StandardFeeRule,
+        privacy_policy: PrivacyPolicy,
+        min_confirmations: NonZeroU32,
+        to: &Address,
+        amount: NonNegativeAmount,
+        memo: Option<MemoBytes>,
+        change_memo: Option<MemoBytes>,
+    ) -> Result<
+        Proposal<StandardFeeRule, <DsFactory::DataStore as InputSource>::NoteRef>,
+        data_api::error::Error<
+            DsFactory::Error,
+            CommitmentTreeErrT,
+            GreedyInputSelectorError<
+                Zip317FeeError,
+                <DsFactory::DataStore as InputSource>::NoteRef,
+            >,
+            Zip317FeeError,
+        >,
+    > {
+        let result = propose_standard_transfer_to_address::<_, _, CommitmentTreeErrT>(
+            &mut self.wallet_data,
+            &self.network,
+            fee_rule,
+            spend_from_account,
+            privacy_policy,
+            min_confirmations,
+            to,
+            amount,
+            memo,
+            change_memo,
+        );
+
+        if let Ok(proposal) = &result {
+            check_proposal_serialization_roundtrip(&self.network, self.wallet(), proposal);
+        }
+
+        result
+    }
+
+    /// Invokes [`propose_send_max_transfer`] with the given arguments.
+    #[allow(clippy::type_complexity)]
+    pub fn propose_send_max_transfer<ChangeT>(
+        &mut self,
+        spend_from_account: AccountId,
+        input_selector: &GreedyInputSelector<DsFactory::DataStore, ChangeT>,
+        recipient: &Address,
+        memo: Option<MemoBytes>,
+        privacy_policy: PrivacyPolicy,
+        min_confirmations: NonZeroU32,
+    ) -> Result<
+        Proposal<ChangeT::FeeRule, <DsFactory::DataStore as InputSource>::NoteRef>,
+        data_api::error::Error<
+            DsFactory::Error,
+            Infallible,
+            GreedyInputSelectorError<
+                ChangeT::Error,
+                <DsFactory::DataStore as InputSource>::NoteRef,
+            >,
+            <ChangeT::FeeRule as FeeRule>::Error,
+        >,
+    >
+    where
+        ChangeT: ChangeStrategy,
+        ChangeT::FeeRule: Clone,
+    {
+        propose_send_max_transfer::<_, _, _, Infallible>(
+            &mut self.wallet_data,
+            &self.network,
+            spend_from_account,
+            input_selector,
+            recipient,
+            memo,
+            privacy_policy,
+            min_confirmations,
+        )
+    }
+
+    /// Invokes [`propose_consolidation`] with the given arguments.
+    #[allow(clippy::type_complexity)]
+    pub fn propose_consolidation<ChangeT>(
+        &mut self,
+        spend_from_account: AccountId,
+        input_selector: &GreedyInputSelector<DsFactory::DataStore, ChangeT>,
+        max_inputs: usize,
+        target_pool: ShieldedProtocol,
+        privacy_policy: PrivacyPolicy,
+        min_confirmations: NonZeroU32,
+    ) -> Result<
+        Proposal<ChangeT::FeeRule, <DsFactory::DataStore as InputSource>::NoteRef>,
+        data_api::error::Error<
+            DsFactory::Error,
+            Infallible,
+            GreedyInputSelectorError<
+                ChangeT::Error,
+                <DsFactory::DataStore as InputSource>::NoteRef,
+            >,
+            <ChangeT::FeeRule as FeeRule>::Error,
+        >,
+    >
+    where
+        ChangeT: ChangeStrategy,
+        ChangeT::FeeRule: Clone,
+    {
+        propose_consolidation::<_, _, _, Infallible>(
+            &mut self.wallet_data,
+            &self.network,
+            spend_from_account,
+            input_selector,
+            max_inputs,
+            target_pool,
+            privacy_policy,
+            min_confirmations,
+        )
+    }
+
+    /// Invokes [`propose_shielding`] with the given arguments.
+    #[cfg(feature = "transparent-inputs")]
+    #[allow(clippy::type_complexity)]
+    pub fn propose_shielding<InputsT>(
+        &mut self,
+        input_selector: &InputsT,
+        shielding_threshold: NonNegativeAmount,
+        from_addrs: &[TransparentAddress],
+        min_confirmations: u32,
+    ) -> Result<
+        Proposal<InputsT::FeeRule, Infallible>,
+        data_api::error::Error<
+            DsFactory::Error,
+            Infallible,
+            InputsT::Error,
+            <InputsT::FeeRule as FeeRule>::Error,
+        >,
+    >
+    where
+        InputsT: ShieldingSelector<InputSource = DsFactory::DataStore>,
+    {
+        propose_shielding::<_, _, _, Infallible>(
+            &mut self.wallet_data,
+            &self.network,
+            input_selector,
+            shielding_threshold,
+            from_addrs,
+            min_confirmations,
+        )
+    }
+
+    /// Invokes [`create_proposed_transactions`] with the given arguments.
+    #[allow(clippy::type_complexity)]
+    pub fn create_proposed_transactions<InputsErrT, FeeRuleT>(
+        &mut self,
+        usk: &UnifiedSpendingKey,
+        ovk_policy: OvkPolicy,
+        proposal: &Proposal<FeeRuleT, <DsFactory::DataStore as InputSource>::NoteRef>,
+    ) -> Result<
+        NonEmpty<TxId>,
+        data_api::error::Error<
+            DsFactory::Error,
+            DsFactory::CommitmentTreeError,
+            InputsErrT,
+            FeeRuleT::Error,
+        >,
+    >
+    where
+        FeeRuleT: FeeRule,
+    {
+        let prover = test_prover();
+        create_proposed_transactions(
+            &mut self.wallet_data,
+            &self.network,
+            &prover,
+            &prover,
+            usk,
+            ovk_policy,
+            proposal,
+        )
+    }
+
+    /// Invokes [`shield_transparent_funds`] with the given arguments.
+    #[cfg(feature = "transparent-inputs")]
+    #[allow(clippy::type_complexity)]
+    pub fn shield_transparent_funds<InputsT>(
+        &mut self,
+        input_selector: &InputsT,
+        shielding_threshold: NonNegativeAmount,
+        usk: &UnifiedSpendingKey,
+        from_addrs: &[TransparentAddress],
+        min_confirmations: u32,
+    ) -> Result<
+        NonEmpty<TxId>,
+        data_api::error::Error<
+            DsFactory::Error,
+            DsFactory::CommitmentTreeError,
+            InputsT::Error,
+            <InputsT::FeeRule as FeeRule>::Error,
+        >,
+    >
+    where
+        InputsT: ShieldingSelector<InputSource = DsFactory::DataStore>,
+    {
+        let prover = test_prover();
+        shield_transparent_funds(
+            &mut self.wallet_data,
+            &self.network,
+            &prover,
+            &prover,
+            input_selector,
+            shielding_threshold,
+            usk,
+            from_addrs,
+            min_confirmations,
+        )
+    }
+
+    fn with_account_balance<T, F: FnOnce(&AccountBalance) -> T>(
+        &self,
+        account: AccountId,
+        min_confirmations: u32,
+        f: F,
+    ) -> T {
+        let binding = self.get_wallet_summary(min_confirmations).unwrap();
+        f(binding.account_balances().get(&account).unwrap())
+    }
+
+    /// Returns the total balance of the given account, including unconfirmed funds.
+    pub fn get_total_balance(&self, account: AccountId) -> NonNegativeAmount {
+        self.with_account_balance(account, 0, |balance| balance.total())
+    }
+
+    /// Returns the spendable Sapling balance of the given account.
+    pub fn get_spendable_balance(
+        &self,
+        account: AccountId,
+        min_confirmations: u32,
+    ) -> NonNegativeAmount {
+        self.with_account_balance(account, min_confirmations, |balance| {
+            balance.sapling_balance().spendable_value()
+        })
+    }
+
+    /// Returns the Sapling balance of the given account that is not yet spendable, including
+    /// pending change.
+    pub fn get_pending_shielded_balance(
+        &self,
+        account: AccountId,
+        min_confirmations: u32,
+    ) -> NonNegativeAmount {
+        self.with_account_balance(account, min_confirmations, |balance| {
+            balance.sapling_balance().value_pending_spendability()
+                + balance.sapling_balance().change_pending_confirmation()
+        })
+        .unwrap()
+    }
+
+    /// Returns the Sapling change of the given account that is pending confirmation.
+    pub fn get_pending_change(
+        &self,
+        account: AccountId,
+        min_confirmations: u32,
+    ) -> NonNegativeAmount {
+        self.with_account_balance(account, min_confirmations, |balance| {
+            balance.sapling_balance().change_pending_confirmation()
+        })
+    }
+
+    /// Returns the wallet summary as of the given number of confirmations, if the wallet
+    /// has been synchronized.
+    pub fn get_wallet_summary(&self, min_confirmations: u32) -> Option<WalletSummary> {
+        self.wallet_data
+            .get_wallet_summary(min_confirmations)
+            .unwrap()
+    }
+}
+
+/// Returns the prover used to create transactions in conformance tests.
+pub fn test_prover() -> impl SpendProver + OutputProver {
+    LocalTxProver::bundled()
+}
+
+/// Returns a [`GreedyInputSelector`] that uses the given fee rule, and attaches the given
+/// memo (if any) to change outputs.
+pub fn input_selector<DbT: InputSource>(
+    fee_rule: StandardFeeRule,
+    change_memo: Option<&str>,
+) -> GreedyInputSelector<DbT, standard::SingleOutputChangeStrategy> {
+    let change_memo = change_memo.map(|m| MemoBytes::from(m.parse::<Memo>().unwrap()));
+    let change_strategy = standard::SingleOutputChangeStrategy::new(
+        fee_rule,
+        change_memo,
+        ChangePoolPolicy::default(),
+    );
+    GreedyInputSelector::new(change_strategy, DustOutputPolicy::default())
+}
+
+/// Checks that a protobuf proposal serialized from the provided proposal value correctly
+/// parses to the same proposal value.
+pub fn check_proposal_serialization_roundtrip<DbT: InputSource>(
+    params: &Network,
+    wallet_data: &DbT,
+    proposal: &Proposal<StandardFeeRule, DbT::NoteRef>,
+) where
+    DbT::Error: fmt::Debug,
+{
+    let proposal_proto = proposal::Proposal::from_standard_proposal(params, proposal);
+    let deserialized_proposal = proposal_proto.try_into_standard_proposal(params, wallet_data);
+    assert_matches!(deserialized_proposal, Ok(r) if &r == proposal);
+}
+
+/// The kind of address to which a fake output is sent.
+pub enum AddressType {
+    DefaultExternal,
+    DiversifiedExternal(DiversifierIndex),
+    Internal,
+}
+
+/// Create a fake CompactBlock at the given height, containing a single output paying
+/// an address. Returns the CompactBlock and the nullifier for the new note.
+pub fn fake_compact_block<P: consensus::Parameters>(
+    params: &P,
+    height: BlockHeight,
+    prev_hash: BlockHash,
+    dfvk: &DiversifiableFullViewingKey,
+    req: AddressType,
+    value: NonNegativeAmount,
+    initial_sapling_tree_size: u32,
+    initial_orchard_tree_size: u32,
+) -> (CompactBlock, Nullifier) {
+    let to = match req {
+        AddressType::DefaultExternal => dfvk.default_address().1,
+        AddressType::DiversifiedExternal(idx) => dfvk.find_address(idx).unwrap().1,
+        AddressType::Internal => dfvk.change_address().1,
+    };
+
+    // Create a fake Note for the account
+    let mut rng = OsRng;
+    let rseed = generate_random_rseed(
+        consensus::sapling_zip212_enforcement(params, height),
+        &mut rng,
+    );
+    let note = Note::from_parts(to, NoteValue::from(value), rseed);
+    let cout = compact_sapling_output(dfvk, note.clone(), &mut rng);
+
+    // Create a fake CompactBlock containing the note
+    let mut ctx = CompactTx::default();
+    let mut txid = vec![0; 32];
+    rng.fill_bytes(&mut txid);
+    ctx.hash = txid;
+    ctx.outputs.push(cout);
+
+    let cb = fake_compact_block_from_compact_tx(
+        ctx,
+        height,
+        prev_hash,
+        initial_sapling_tree_size,
+        initial_orchard_tree_size,
+    );
+    (cb, note.nf(&dfvk.fvk().vk.nk, 0))
+}
+
+/// Create a fake CompactBlock at the given height, containing a single Orchard action paying
+/// the default external address of the given full viewing key. Returns the CompactBlock and
+/// the nullifier for the new note.
+///
+/// The wallet selects anchors from the heights at which both note commitment trees are
+/// checkpointed, so the block also contains a Sapling output to an address that does not
+/// belong to the wallet.
+#[cfg(feature = "orchard")]
+pub fn fake_compact_block_orchard<P: consensus::Parameters>(
+    params: &P,
+    height: BlockHeight,
+    prev_hash: BlockHash,
+    fvk: &::orchard::keys::FullViewingKey,
+    value: NonNegativeAmount,
+    initial_sapling_tree_size: u32,
+    initial_orchard_tree_size: u32,
+) -> (CompactBlock, ::orchard::note::Nullifier) {
+    use ::orchard::{
+        keys::Scope,
+        note::{ExtractedNoteCommitment, RandomSeed},
+        note_encryption::{OrchardDomain, OrchardNoteEncryption},
+    };
+
+    let mut rng = OsRng;
+
+    // The nullifier revealed by the action is also used as `rho` for the new note.
+    let spent_nf = loop {
+        let mut bytes = [0; 32];
+        rng.fill_bytes(&mut bytes);
+        let nf = ::orchard::note::Nullifier::from_bytes(&bytes);
+        if nf.is_some().into() {
+            break nf.unwrap();
+        }
+    };
+    let rseed = loop {
+        let mut bytes = [0; 32];
+        rng.fill_bytes(&mut bytes);
+        let rseed = RandomSeed::from_bytes(bytes, &spent_nf);
+        if rseed.is_some().into() {
+            break rseed.unwrap();
+        }
+    };
+
+    // Create a fake Note for the account
+    let note = ::orchard::Note::from_parts(
+        fvk.address_at(0u32, Scope::External),
+        ::orchard::value::NoteValue::from(value),
+        spent_nf,
+        rseed,
+    )
+    .unwrap();
+    let encryptor = OrchardNoteEncryption::new(
+        Some(fvk.to_ovk(Scope::External)),
+        note,
+        *MemoBytes::empty().as_array(),
+    );
+
+    // Create a fake Note for an address that does not belong to the wallet
+    let other_dfvk =
+        ::sapling::zip32::ExtendedSpendingKey::master(&[]).to_diversifiable_full_viewing_key();
+    let rseed = generate_random_rseed(
+        consensus::sapling_zip212_enforcement(params, height),
+        &mut rng,
+    );
+    let other_note = Note::from_parts(
+        other_dfvk.default_address().1,
+        NoteValue::from(value),
+        rseed,
+    );
+
+    // Create a fake CompactBlock containing both notes
+    let mut ctx = CompactTx::default();
+    let mut txid = vec![0; 32];
+    rng.fill_bytes(&mut txid);
+    ctx.hash = txid;
+    ctx.outputs
+        .push(compact_sapling_output(&other_dfvk, other_note, &mut rng));
+    ctx.actions.push(compact::CompactOrchardAction {
+        nullifier: spent_nf.to_bytes().to_vec(),
+        cmx: ExtractedNoteCommitment::from(note.commitment())
+            .to_bytes()
+            .to_vec(),
+        ephemeral_key: OrchardDomain::epk_bytes(encryptor.epk()).0.to_vec(),
+        ciphertext: encryptor.encrypt_note_plaintext().as_ref()[..52].to_vec(),
+    });
+
+    let cb = fake_compact_block_from_compact_tx(
+        ctx,
+        height,
+        prev_hash,
+        initial_sapling_tree_size,
+        initial_orchard_tree_size,
+    );
+    (cb, note.nullifier(fvk))
+}
+
+/// Create a fake CompactBlock at the given height containing only the given transaction.
+pub fn fake_compact_block_from_tx(
+    height: BlockHeight,
+    prev_hash: BlockHash,
+    tx_index: usize,
+    tx: &Transaction,
+    initial_sapling_tree_size: u32,
+    initial_orchard_tree_size: u32,
+) -> CompactBlock {
+    // Create a fake CompactTx containing the transaction.
+    let mut ctx = CompactTx {
+        index: tx_index as u64,
+        hash: tx.txid().as_ref().to_vec(),
+        ..Default::default()
+    };
+
+    if let Some(bundle) = tx.sapling_bundle() {
+        for spend in bundle.shielded_spends() {
+            ctx.spends.push(spend.into());
+        }
+        for output in bundle.shielded_outputs() {
+            ctx.outputs.push(output.into());
+        }
+    }
+
+    #[cfg(feature = "orchard")]
+    if let Some(bundle) = tx.orchard_bundle() {
+        for action in bundle.actions() {
+            ctx.actions.push(action.into());
+        }
+    }
+
+    fake_compact_block_from_compact_tx(
+        ctx,
+        height,
+        prev_hash,
+        initial_sapling_tree_size,
+        initial_orchard_tree_size,
+    )
+}
+
+/// Create a fake CompactBlock at the given height, spending a single note from the
+/// given address.
+#[allow(clippy::too_many_arguments)]
+pub fn fake_compact_block_spending<P: consensus::Parameters>(
+    params: &P,
+    height: BlockHeight,
+    prev_hash: BlockHash,
+    (nf, in_value): (Nullifier, NonNegativeAmount),
+    dfvk: &DiversifiableFullViewingKey,
+    to: PaymentAddress,
+    value: NonNegativeAmount,
+    initial_sapling_tree_size: u32,
+    initial_orchard_tree_size: u32,
+) -> CompactBlock {
+    let zip212_enforcement = consensus::sapling_zip212_enforcement(params, height);
+    let mut rng = OsRng;
+    let rseed = generate_random_rseed(zip212_enforcement, &mut rng);
+
+    // Create a fake CompactBlock containing the note
+    let cspend = CompactSaplingSpend { nf: nf.to_vec() };
+    let mut ctx = CompactTx::default();
+    let mut txid = vec![0; 32];
+    rng.fill_bytes(&mut txid);
+    ctx.hash = txid;
+    ctx.spends.push(cspend);
+
+    // Create a fake Note for the payment
+    let note = Note::from_parts(to, NoteValue::from(value), rseed);
+    ctx.outputs
+        .push(compact_sapling_output(dfvk, note, &mut rng));
+
+    // Create a fake Note for the change
+    let change_addr = dfvk.default_address().1;
+    let rseed = generate_random_rseed(zip212_enforcement, &mut rng);
+    let note = Note::from_parts(
+        change_addr,
+        NoteValue::from((in_value - value).unwrap()),
+        rseed,
+    );
+    ctx.outputs
+        .push(compact_sapling_output(dfvk, note, &mut rng));
+
+    fake_compact_block_from_compact_tx(
+        ctx,
+        height,
+        prev_hash,
+        initial_sapling_tree_size,
+        initial_orchard_tree_size,
+    )
+}
+
+/// Create a fake CompactBlock at the given height containing only the given compact
+/// transaction.
+pub fn fake_compact_block_from_compact_tx(
+    ctx: CompactTx,
+    height: BlockHeight,
+    prev_hash: BlockHash,
+    initial_sapling_tree_size: u32,
+    initial_orchard_tree_size: u32,
+) -> CompactBlock {
+    let mut rng = OsRng;
+    let mut cb = CompactBlock {
+        hash: {
+            let mut hash = vec![0; 32];
+            rng.fill_bytes(&mut hash);
+            hash
+        },
+        height: height.into(),
+        ..Default::default()
+    };
+    cb.prev_hash.extend_from_slice(&prev_hash.0);
+    cb.vtx.push(ctx);
+    cb.chain_metadata = Some(compact::ChainMetadata {
+        sapling_commitment_tree_size: initial_sapling_tree_size
+            + cb.vtx.iter().map(|tx| tx.outputs.len() as u32).sum::<u32>(),
+        orchard_commitment_tree_size: initial_orchard_tree_size
+            + cb.vtx.iter().map(|tx| tx.actions.len() as u32).sum::<u32>(),
+    });
+    cb
+}
+
+/// Encrypts the given note to its recipient with an empty memo, using the given key's
+/// external outgoing viewing key, and returns the compact form of the resulting output.
+fn compact_sapling_output<R: RngCore>(
+    dfvk: &DiversifiableFullViewingKey,
+    note: Note,
+    rng: &mut R,
+) -> CompactSaplingOutput {
+    let encryptor = sapling_note_encryption(
+        Some(dfvk.fvk().ovk),
+        note.clone(),
+        *MemoBytes::empty().as_array(),
+        rng,
+    );
+    let cmu = note.cmu().to_bytes().to_vec();
+    let ephemeral_key = SaplingDomain::epk_bytes(encryptor.epk()).0.to_vec();
+    let enc_ciphertext = encryptor.encrypt_note_plaintext();
+
+    CompactSaplingOutput {
+        cmu,
+        ephemeral_key,
+        ciphertext: enc_ciphertext.as_ref()[..52].to_vec(),
+    }
+}
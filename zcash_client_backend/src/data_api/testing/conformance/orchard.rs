@@ -0,0 +1,503 @@
+//! Conformance scenarios for sending and receiving Orchard funds.
+//!
+//! Each function in this module runs a single scenario against a fresh data store obtained
+//! from the provided [`DataStoreFactory`], panicking if the data store does not behave as
+//! expected.
+//!
+//! Orchard notes may only be spent once NU5 has activated, so these scenarios create the
+//! test account with a birthday at NU5 activation, and begin the fake chain at that height.
+
+use std::{convert::Infallible, num::NonZeroU32};
+
+use ::orchard::keys::{FullViewingKey, Scope, SpendingKey};
+use incrementalmerkletree::frontier::Frontier;
+use sapling::zip32::ExtendedSpendingKey;
+use zcash_primitives::{
+    block::BlockHash,
+    consensus::{NetworkUpgrade, Parameters},
+    legacy::TransparentAddress,
+    transaction::{components::amount::NonNegativeAmount, fees::StandardFeeRule},
+};
+
+use crate::{
+    address::{Address, UnifiedAddress},
+    data_api::{
+        error::Error,
+        wallet::input_selection::{
+            GreedyInputSelectorError, PrivacyPolicy, PrivacyViolation, Proposal,
+        },
+        AccountBirthday, NullifierQuery, WalletRead,
+    },
+    wallet::OvkPolicy,
+    zip321::{Payment, TransactionRequest},
+    ShieldedProtocol,
+};
+
+use super::{input_selector, AddressType, BlockCache, DataStoreFactory, TestBuilder, TestState};
+
+/// Receives an Orchard note, then spends it to an external Sapling recipient, checking that
+/// the note is tracked as spent and that the Orchard change is detected once mined.
+pub fn receive_then_spend<DSF: DataStoreFactory>(dsf: DSF) {
+    let mut st = TestBuilder::new()
+        .with_block_cache(BlockCache::new())
+        .with_data_store_factory(dsf)
+        .with_test_account(|network| {
+            AccountBirthday::from_parts(
+                network.activation_height(NetworkUpgrade::Nu5).unwrap(),
+                Frontier::empty(),
+                None,
+            )
+        })
+        .build();
+
+    let (account, usk, birthday) = st.test_account().unwrap();
+    let fvk = st.test_account_orchard().unwrap();
+
+    // Add funds to the wallet in a single Orchard note
+    let value = NonNegativeAmount::const_from_u64(60000);
+    let (_, nf) =
+        st.generate_orchard_block_at(birthday.height(), BlockHash([0; 32]), &fvk, value, 0, 0);
+    st.scan_cached_blocks(birthday.height(), 1);
+
+    let orchard_spendable = |st: &TestState<BlockCache, DSF>| {
+        st.get_wallet_summary(1)
+            .and_then(|s| {
+                s.account_balances()
+                    .get(&account)
+                    .map(|b| b.orchard_balance().spendable_value())
+            })
+            .unwrap()
+    };
+
+    // The note is spendable, and its nullifier is tracked so that its spend can be detected.
+    assert_eq!(st.get_total_balance(account), value);
+    assert_eq!(orchard_spendable(&st), value);
+    assert_eq!(
+        st.get_spendable_balance(account, 1),
+        NonNegativeAmount::ZERO
+    );
+    assert!(st
+        .wallet()
+        .get_orchard_nullifiers(NullifierQuery::Unspent)
+        .unwrap()
+        .contains(&(account, nf)));
+
+    let amount_sent = NonNegativeAmount::const_from_u64(10000);
+    let to: Address = ExtendedSpendingKey::master(&[]).default_address().1.into();
+    let request = TransactionRequest::new(vec![Payment {
+        recipient_address: to,
+        amount: amount_sent,
+        memo: None,
+        label: None,
+        message: None,
+        other_params: vec![],
+    }])
+    .unwrap();
+
+    // Paying a Sapling recipient from Orchard funds reveals the amount crossing between pools.
+    let input_selector = input_selector(StandardFeeRule::Zip317, None);
+    let proposal = st
+        .propose_transfer(
+            account,
+            &input_selector,
+            request,
+            PrivacyPolicy::AllowRevealedAmounts,
+            NonZeroU32::new(1).unwrap(),
+        )
+        .unwrap();
+
+    // The Orchard note is spent, and change is returned to the Orchard pool.
+    let step = proposal.steps().first();
+    assert_eq!(
+        step.shielded_inputs().map(|inputs| inputs
+            .notes()
+            .iter()
+            .map(|n| n.note().protocol())
+            .collect::<Vec<_>>()),
+        Some(vec![ShieldedProtocol::Orchard])
+    );
+    assert_matches!(
+        &step.balance().proposed_change()[..],
+        [change] if change.output_pool() == ShieldedProtocol::Orchard
+    );
+    let fee = step.balance().fee_required();
+
+    let txid = st
+        .create_proposed_transactions::<Infallible, _>(&usk, OvkPolicy::Sender, &proposal)
+        .unwrap()[0];
+
+    // Once the transaction is mined, the spent note's nullifier is no longer reported as
+    // unspent, and only the Orchard change remains in the wallet.
+    let (h, _) = st.generate_next_block_including(txid);
+    st.scan_cached_blocks(h, 1);
+
+    assert!(!st
+        .wallet()
+        .get_orchard_nullifiers(NullifierQuery::Unspent)
+        .unwrap()
+        .contains(&(account, nf)));
+
+    let expected = (value - (amount_sent + fee).unwrap()).unwrap();
+    assert_eq!(st.get_total_balance(account), expected);
+    assert_eq!(orchard_spendable(&st), expected);
+}
+
+/// Pays an Orchard recipient from Orchard funds, checking that change is returned to the
+/// Orchard pool and that the change note is stored by the wallet as soon as the transaction
+/// has been created.
+pub fn send_with_orchard_change<DSF: DataStoreFactory>(dsf: DSF) {
+    let mut st = TestBuilder::new()
+        .with_block_cache(BlockCache::new())
+        .with_data_store_factory(dsf)
+        .with_test_account(|network| {
+            AccountBirthday::from_parts(
+                network.activation_height(NetworkUpgrade::Nu5).unwrap(),
+                Frontier::empty(),
+                None,
+            )
+        })
+        .build();
+
+    let (account, usk, birthday) = st.test_account().unwrap();
+    let fvk = st.test_account_orchard().unwrap();
+
+    // Add funds to the wallet in a single Orchard note
+    let value = NonNegativeAmount::const_from_u64(60000);
+    st.generate_orchard_block_at(birthday.height(), BlockHash([0; 32]), &fvk, value, 0, 0);
+    st.scan_cached_blocks(birthday.height(), 1);
+
+    let to_fvk = FullViewingKey::from(&SpendingKey::from_bytes([0xf5; 32]).unwrap());
+    let to: Address =
+        UnifiedAddress::from_receivers(Some(to_fvk.address_at(0u32, Scope::External)), None, None)
+            .unwrap()
+            .into();
+    let amount_sent = NonNegativeAmount::const_from_u64(10000);
+    let request = TransactionRequest::new(vec![Payment {
+        recipient_address: to,
+        amount: amount_sent,
+        memo: None,
+        label: None,
+        message: None,
+        other_params: vec![],
+    }])
+    .unwrap();
+
+    let input_selector = input_selector(StandardFeeRule::Zip317, Some("Test change memo"));
+    let proposal = st
+        .propose_transfer(
+            account,
+            &input_selector,
+            request,
+            PrivacyPolicy::FullPrivacy,
+            NonZeroU32::new(1).unwrap(),
+        )
+        .unwrap();
+
+    let step = proposal.steps().first();
+    assert_matches!(
+        &step.balance().proposed_change()[..],
+        [change] if change.output_pool() == ShieldedProtocol::Orchard
+    );
+    let expected_change = (value - (amount_sent + step.balance().fee_required()).unwrap()).unwrap();
+
+    let txid = st
+        .create_proposed_transactions::<Infallible, _>(&usk, OvkPolicy::Sender, &proposal)
+        .unwrap()[0];
+
+    // The spent note has been replaced by the change note, which is pending confirmation until
+    // the transaction is mined.
+    let orchard_pending_change = |st: &TestState<BlockCache, DSF>| {
+        st.get_wallet_summary(1)
+            .and_then(|s| {
+                s.account_balances()
+                    .get(&account)
+                    .map(|b| b.orchard_balance().change_pending_confirmation())
+            })
+            .unwrap()
+    };
+    assert_eq!(st.get_total_balance(account), expected_change);
+    assert_eq!(orchard_pending_change(&st), expected_change);
+
+    // Once the transaction is mined, the change note becomes spendable.
+    let (h, _) = st.generate_next_block_including(txid);
+    st.scan_cached_blocks(h, 1);
+
+    assert_eq!(st.get_total_balance(account), expected_change);
+    assert_eq!(orchard_pending_change(&st), NonNegativeAmount::ZERO);
+    assert_eq!(
+        st.get_wallet_summary(1)
+            .and_then(|s| {
+                s.account_balances()
+                    .get(&account)
+                    .map(|b| b.orchard_balance().spendable_value())
+            })
+            .unwrap(),
+        expected_change
+    );
+}
+
+/// Pays a Sapling recipient from Orchard funds, checking that the payment is only proposed
+/// when the privacy policy permits the amount crossing between pools to be revealed.
+pub fn cross_pool_payment_requires_revealed_amounts<DSF: DataStoreFactory>(dsf: DSF) {
+    let mut st = TestBuilder::new()
+        .with_block_cache(BlockCache::new())
+        .with_data_store_factory(dsf)
+        .with_test_account(|network| {
+            AccountBirthday::from_parts(
+                network.activation_height(NetworkUpgrade::Nu5).unwrap(),
+                Frontier::empty(),
+                None,
+            )
+        })
+        .build();
+
+    let (account, _, birthday) = st.test_account().unwrap();
+    let fvk = st.test_account_orchard().unwrap();
+
+    // Add funds to the wallet in a single Orchard note
+    let value = NonNegativeAmount::const_from_u64(60000);
+    st.generate_orchard_block_at(birthday.height(), BlockHash([0; 32]), &fvk, value, 0, 0);
+    st.scan_cached_blocks(birthday.height(), 1);
+
+    let to: Address = ExtendedSpendingKey::master(&[]).default_address().1.into();
+    let amount = NonNegativeAmount::const_from_u64(10000);
+    let min_confirmations = NonZeroU32::new(1).unwrap();
+
+    // Under full privacy, only Sapling notes may fund a Sapling payment, and the wallet has
+    // none.
+    assert_matches!(
+        st.propose_standard_transfer::<Infallible>(
+            account,
+            StandardFeeRule::Zip317,
+            PrivacyPolicy::FullPrivacy,
+            min_confirmations,
+            &to,
+            amount,
+            None,
+            None,
+        ),
+        Err(Error::InsufficientFunds { available, .. }) if available == NonNegativeAmount::ZERO
+    );
+
+    // Once amounts may be revealed, the Orchard note funds the payment.
+    let proposal = st
+        .propose_standard_transfer::<Infallible>(
+            account,
+            StandardFeeRule::Zip317,
+            PrivacyPolicy::AllowRevealedAmounts,
+            min_confirmations,
+            &to,
+            amount,
+            None,
+            None,
+        )
+        .unwrap();
+    assert_eq!(
+        proposal
+            .steps()
+            .first()
+            .shielded_inputs()
+            .map(|inputs| inputs
+                .notes()
+                .iter()
+                .map(|n| n.note().protocol())
+                .collect::<Vec<_>>()),
+        Some(vec![ShieldedProtocol::Orchard])
+    );
+}
+
+/// Sends the entire balance of an account holding both Orchard and Sapling notes to a Sapling
+/// recipient, checking that the Orchard notes are only spent when the privacy policy permits
+/// value to cross between pools.
+pub fn send_max_respects_privacy_policy<DSF: DataStoreFactory>(dsf: DSF) {
+    let mut st = TestBuilder::new()
+        .with_block_cache(BlockCache::new())
+        .with_data_store_factory(dsf)
+        .with_test_account(|network| {
+            AccountBirthday::from_parts(
+                network.activation_height(NetworkUpgrade::Nu5).unwrap(),
+                Frontier::empty(),
+                None,
+            )
+        })
+        .build();
+
+    let (account, _, birthday) = st.test_account().unwrap();
+    let fvk = st.test_account_orchard().unwrap();
+    let dfvk = st.test_account_sapling().unwrap();
+
+    // Add funds to the wallet in an Orchard note and a Sapling note
+    st.generate_orchard_block_at(
+        birthday.height(),
+        BlockHash([0; 32]),
+        &fvk,
+        NonNegativeAmount::const_from_u64(60000),
+        0,
+        0,
+    );
+    st.generate_next_block(
+        &dfvk,
+        AddressType::DefaultExternal,
+        NonNegativeAmount::const_from_u64(40000),
+    );
+    st.scan_cached_blocks(birthday.height(), 2);
+
+    let input_selector = input_selector(StandardFeeRule::Zip317, None);
+    let min_confirmations = NonZeroU32::new(1).unwrap();
+    let spent_pools = |proposal: &Proposal<StandardFeeRule, _>| {
+        proposal.steps().first().shielded_inputs().map(|inputs| {
+            inputs
+                .notes()
+                .iter()
+                .map(|n| n.note().protocol())
+                .collect::<Vec<_>>()
+        })
+    };
+
+    // A transparent recipient may not be paid unless recipients may be revealed.
+    assert_matches!(
+        st.propose_send_max_transfer(
+            account,
+            &input_selector,
+            &TransparentAddress::PublicKeyHash([7; 20]).into(),
+            None,
+            PrivacyPolicy::AllowRevealedAmounts,
+            min_confirmations,
+        ),
+        Err(Error::PrivacyPolicyViolation {
+            policy: PrivacyPolicy::AllowRevealedAmounts,
+            violation: PrivacyViolation::RevealedRecipients,
+        })
+    );
+
+    // Under full privacy, only the Sapling note may fund a payment to a Sapling recipient.
+    let to: Address = ExtendedSpendingKey::master(&[]).default_address().1.into();
+    let proposal = st
+        .propose_send_max_transfer(
+            account,
+            &input_selector,
+            &to,
+            None,
+            PrivacyPolicy::FullPrivacy,
+            min_confirmations,
+        )
+        .unwrap();
+    assert_eq!(
+        spent_pools(&proposal),
+        Some(vec![ShieldedProtocol::Sapling])
+    );
+    assert_eq!(
+        proposal.steps().first().transaction_request().total(),
+        Ok(NonNegativeAmount::const_from_u64(30000))
+    );
+
+    // Once amounts may be revealed, both notes are spent.
+    let proposal = st
+        .propose_send_max_transfer(
+            account,
+            &input_selector,
+            &to,
+            None,
+            PrivacyPolicy::AllowRevealedAmounts,
+            min_confirmations,
+        )
+        .unwrap();
+    let mut pools = spent_pools(&proposal).unwrap();
+    pools.sort();
+    assert_eq!(
+        pools,
+        vec![ShieldedProtocol::Sapling, ShieldedProtocol::Orchard]
+    );
+}
+
+/// Consolidates Orchard notes, checking that Orchard dust notes are skipped and that no
+/// transaction is proposed when the fee would outweigh the value of the consolidated note.
+pub fn consolidation_skips_dust<DSF: DataStoreFactory>(dsf: DSF) {
+    let mut st = TestBuilder::new()
+        .with_block_cache(BlockCache::new())
+        .with_data_store_factory(dsf)
+        .with_test_account(|network| {
+            AccountBirthday::from_parts(
+                network.activation_height(NetworkUpgrade::Nu5).unwrap(),
+                Frontier::empty(),
+                None,
+            )
+        })
+        .build();
+
+    let (account, _, birthday) = st.test_account().unwrap();
+    let fvk = st.test_account_orchard().unwrap();
+
+    // Add two dust notes and three small notes to the wallet
+    st.generate_orchard_block_at(
+        birthday.height(),
+        BlockHash([0; 32]),
+        &fvk,
+        NonNegativeAmount::const_from_u64(1000),
+        0,
+        0,
+    );
+    for value in [1000, 6000, 7000, 8000] {
+        st.generate_next_orchard_block(&fvk, NonNegativeAmount::const_from_u64(value));
+    }
+    st.scan_cached_blocks(birthday.height(), 5);
+
+    let input_selector = input_selector(StandardFeeRule::Zip317, None);
+    let min_confirmations = NonZeroU32::new(1).unwrap();
+
+    // Once the dust notes are skipped, merging the three small notes would pay a fee of
+    // 15000 zatoshis to create a note worth only 6000 zatoshis.
+    assert_matches!(
+        st.propose_consolidation(
+            account,
+            &input_selector,
+            3,
+            ShieldedProtocol::Orchard,
+            PrivacyPolicy::FullPrivacy,
+            min_confirmations,
+        ),
+        Err(Error::NoteSelection(
+            GreedyInputSelectorError::NothingToConsolidate
+        ))
+    );
+
+    // With larger notes available, consolidation is worthwhile.
+    let (h, _, _) = st.generate_next_orchard_block(&fvk, NonNegativeAmount::const_from_u64(20000));
+    st.generate_next_orchard_block(&fvk, NonNegativeAmount::const_from_u64(30000));
+    st.scan_cached_blocks(h, 2);
+
+    let proposal = st
+        .propose_consolidation(
+            account,
+            &input_selector,
+            5,
+            ShieldedProtocol::Orchard,
+            PrivacyPolicy::FullPrivacy,
+            min_confirmations,
+        )
+        .unwrap();
+
+    let step = proposal.steps().first();
+    assert_eq!(
+        step.shielded_inputs().map(|i| i
+            .notes()
+            .iter()
+            .map(|n| n.note().value())
+            .collect::<Vec<_>>()),
+        Some(
+            [6000, 7000, 8000, 20000, 30000]
+                .into_iter()
+                .map(NonNegativeAmount::const_from_u64)
+                .collect()
+        )
+    );
+    assert_eq!(
+        step.balance().fee_required(),
+        NonNegativeAmount::const_from_u64(25000)
+    );
+    assert_matches!(
+        &step.balance().proposed_change()[..],
+        [change] if change.value() == NonNegativeAmount::const_from_u64(46000)
+            && change.output_pool() == ShieldedProtocol::Orchard
+    );
+}
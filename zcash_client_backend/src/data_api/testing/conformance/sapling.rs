@@ -0,0 +1,1428 @@
+//! Conformance scenarios for sending and receiving Sapling funds.
+//!
+//! Each function in this module runs a single scenario against a fresh data store obtained
+//! from the provided [`DataStoreFactory`], panicking if the data store does not behave as
+//! expected.
+
+use std::{convert::Infallible, io, num::NonZeroU32};
+
+use incrementalmerkletree::{frontier::Frontier, Hashable, Position};
+use sapling::{
+    note_encryption::try_sapling_output_recovery, zip32::ExtendedSpendingKey, Node, PaymentAddress,
+};
+use secrecy::Secret;
+use zcash_primitives::{
+    block::BlockHash,
+    consensus::{sapling_zip212_enforcement, NetworkUpgrade, Parameters},
+    legacy::TransparentAddress,
+    memo::{Memo, MemoBytes},
+    transaction::{
+        components::{amount::NonNegativeAmount, Amount},
+        fees::{
+            fixed::FeeRule as FixedFeeRule, zip317::FeeError as Zip317FeeError, StandardFeeRule,
+        },
+    },
+    zip32::{AccountId, Scope},
+};
+
+use crate::{
+    address::Address,
+    data_api::{
+        self,
+        chain::CommitmentTreeRoot,
+        error::Error,
+        wallet::{
+            decrypt_and_store_mempool_transaction,
+            input_selection::{GreedyInputSelector, GreedyInputSelectorError, PrivacyPolicy},
+        },
+        AccountBirthday, AccountKind, InputSource, Ratio, WalletCommitmentTrees, WalletRead,
+        WalletWrite,
+    },
+    decrypt_transaction,
+    fees::{fixed, standard, ChangePoolPolicy, DustOutputPolicy},
+    keys::UnifiedSpendingKey,
+    proto::service::RawTransaction,
+    wallet::{NoteId, OvkPolicy},
+    zip321::{self, Payment, TransactionRequest},
+    ShieldedProtocol,
+};
+
+use super::{input_selector, AddressType, BlockCache, DataStoreFactory, TestBuilder, TestState};
+
+#[cfg(feature = "transparent-inputs")]
+use {
+    crate::wallet::WalletTransparentOutput,
+    zcash_primitives::transaction::components::{OutPoint, TxOut},
+};
+
+pub fn send_proposed_transfer<DSF: DataStoreFactory>(dsf: DSF) {
+    let mut st = TestBuilder::new()
+        .with_block_cache(BlockCache::new())
+        .with_data_store_factory(dsf)
+        .with_test_account(AccountBirthday::from_sapling_activation)
+        .build();
+
+    let (account, usk, _) = st.test_account().unwrap();
+    let dfvk = st.test_account_sapling().unwrap();
+
+    // Add funds to the wallet in a single note
+    let value = NonNegativeAmount::const_from_u64(60000);
+    let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+    st.scan_cached_blocks(h, 1);
+
+    // Spendable balance matches total balance
+    assert_eq!(st.get_total_balance(account), value);
+    assert_eq!(st.get_spendable_balance(account, 1), value);
+
+    assert_eq!(
+        st.wallet()
+            .block_max_scanned()
+            .unwrap()
+            .unwrap()
+            .block_height(),
+        h
+    );
+
+    let to_extsk = ExtendedSpendingKey::master(&[]);
+    let to: Address = to_extsk.default_address().1.into();
+    let request = zip321::TransactionRequest::new(vec![Payment {
+        recipient_address: to,
+        amount: NonNegativeAmount::const_from_u64(10000),
+        memo: None, // this should result in the creation of an empty memo
+        label: None,
+        message: None,
+        other_params: vec![],
+    }])
+    .unwrap();
+
+    let fee_rule = StandardFeeRule::Zip317;
+
+    let change_memo = "Test change memo".parse::<Memo>().unwrap();
+    let change_strategy = standard::SingleOutputChangeStrategy::new(
+        fee_rule,
+        Some(change_memo.clone().into()),
+        ChangePoolPolicy::default(),
+    );
+    let input_selector = &GreedyInputSelector::new(change_strategy, DustOutputPolicy::default());
+
+    let proposal = st
+        .propose_transfer(
+            account,
+            input_selector,
+            request,
+            PrivacyPolicy::FullPrivacy,
+            NonZeroU32::new(1).unwrap(),
+        )
+        .unwrap();
+
+    let create_proposed_result =
+        st.create_proposed_transactions::<Infallible, _>(&usk, OvkPolicy::Sender, &proposal);
+    assert_matches!(create_proposed_result, Ok(_));
+
+    let sent_tx_id = create_proposed_result.unwrap()[0];
+
+    // Verify that the sent transaction was stored and that we can decrypt the memos
+    let tx = st
+        .wallet()
+        .get_transaction(sent_tx_id)
+        .expect("Created transaction was stored.");
+    let ufvks = [(account, usk.to_unified_full_viewing_key())]
+        .into_iter()
+        .collect();
+    let decrypted_outputs = decrypt_transaction(&st.network(), h + 1, &tx, &ufvks);
+    assert_eq!(decrypted_outputs.len(), 2);
+
+    let mut found_tx_change_memo = false;
+    let mut found_tx_empty_memo = false;
+    for output in decrypted_outputs {
+        if output.memo == change_memo.clone().into() {
+            found_tx_change_memo = true
+        }
+        if output.memo == Memo::Empty.into() {
+            found_tx_empty_memo = true
+        }
+    }
+    assert!(found_tx_change_memo);
+    assert!(found_tx_empty_memo);
+
+    // Verify that the stored sent notes match what we're expecting
+    let sent_note_ids = (0..tx.sapling_bundle().unwrap().shielded_outputs().len())
+        .map(|i| NoteId::new(sent_tx_id, ShieldedProtocol::Sapling, i as u16))
+        .collect::<Vec<_>>();
+
+    assert_eq!(sent_note_ids.len(), 2);
+
+    // The sent memo should be the empty memo for the sent output, and the
+    // change output's memo should be as specified.
+    let mut found_sent_change_memo = false;
+    let mut found_sent_empty_memo = false;
+    for sent_note_id in sent_note_ids {
+        match st
+            .wallet()
+            .get_memo(sent_note_id)
+            .expect("Note id is valid")
+            .as_ref()
+        {
+            Some(m) if m == &change_memo => {
+                found_sent_change_memo = true;
+            }
+            Some(m) if m == &Memo::Empty => {
+                found_sent_empty_memo = true;
+            }
+            Some(other) => panic!("Unexpected memo value: {:?}", other),
+            None => panic!("Memo should not be stored as NULL"),
+        }
+    }
+    assert!(found_sent_change_memo);
+    assert!(found_sent_empty_memo);
+
+    // Check that querying for a nonexistent sent note returns None
+    assert_matches!(
+        st.wallet()
+            .get_memo(NoteId::new(sent_tx_id, ShieldedProtocol::Sapling, 12345)),
+        Ok(None)
+    );
+}
+
+#[allow(deprecated)]
+pub fn create_to_address_fails_on_incorrect_usk<DSF: DataStoreFactory>(dsf: DSF) {
+    let mut st = TestBuilder::new()
+        .with_data_store_factory(dsf)
+        .with_test_account(AccountBirthday::from_sapling_activation)
+        .build();
+    let dfvk = st.test_account_sapling().unwrap();
+    let to = dfvk.default_address().1.into();
+
+    // Create a USK that doesn't exist in the wallet
+    let acct1 = AccountId::try_from(1).unwrap();
+    let usk1 = UnifiedSpendingKey::from_seed(&st.network(), &[1u8; 32], acct1).unwrap();
+
+    // Attempting to spend with a USK that is not in the wallet results in an error
+    assert_matches!(
+        st.create_spend_to_address(
+            &usk1,
+            &to,
+            NonNegativeAmount::const_from_u64(1),
+            None,
+            OvkPolicy::Sender,
+            NonZeroU32::new(1).unwrap(),
+            None
+        ),
+        Err(data_api::error::Error::KeyNotRecognized)
+    );
+}
+
+#[allow(deprecated)]
+pub fn create_to_address_fails_for_view_only_account<DSF: DataStoreFactory>(dsf: DSF) {
+    let mut st = TestBuilder::new()
+        .with_block_cache(BlockCache::new())
+        .with_data_store_factory(dsf)
+        .build();
+
+    // Import only the viewing key for an account
+    let usk = UnifiedSpendingKey::from_seed(&st.network(), &[0u8; 32], AccountId::ZERO).unwrap();
+    let ufvk = usk.to_unified_full_viewing_key();
+    let birthday = AccountBirthday::from_sapling_activation(&st.network());
+    let account = st
+        .wallet_mut()
+        .import_account_ufvk(&ufvk, birthday.clone())
+        .unwrap();
+    assert_eq!(
+        st.wallet().get_account_kind(account).unwrap(),
+        Some(AccountKind::ViewOnly)
+    );
+
+    // Importing the same viewing key a second time is rejected
+    assert_matches!(st.wallet_mut().import_account_ufvk(&ufvk, birthday), Err(_));
+
+    // Funds received by the view-only account are tracked by the wallet
+    let dfvk = usk.sapling().to_diversifiable_full_viewing_key();
+    let value = NonNegativeAmount::const_from_u64(50000);
+    let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+    st.scan_cached_blocks(h, 1);
+    assert_eq!(st.get_total_balance(account), value);
+
+    // The wallet refuses to spend those funds, even given the matching spending key
+    let to = ExtendedSpendingKey::master(&[]).default_address().1.into();
+    assert_matches!(
+        st.create_spend_to_address(
+            &usk,
+            &to,
+            NonNegativeAmount::const_from_u64(10000),
+            None,
+            OvkPolicy::Sender,
+            NonZeroU32::new(1).unwrap(),
+            None
+        ),
+        Err(data_api::error::Error::AccountCannotSpend(a)) if a == account
+    );
+}
+
+#[allow(deprecated)]
+pub fn proposal_fails_with_no_blocks<DSF: DataStoreFactory>(dsf: DSF) {
+    let mut st = TestBuilder::new()
+        .with_data_store_factory(dsf)
+        .with_test_account(AccountBirthday::from_sapling_activation)
+        .build();
+
+    let (account, _, _) = st.test_account().unwrap();
+    let dfvk = st.test_account_sapling().unwrap();
+    let to = dfvk.default_address().1.into();
+
+    // Wallet summary is not yet available
+    assert_eq!(st.get_wallet_summary(0), None);
+
+    // We cannot do anything if we aren't synchronised
+    assert_matches!(
+        st.propose_standard_transfer::<Infallible>(
+            account,
+            StandardFeeRule::PreZip313,
+            PrivacyPolicy::FullPrivacy,
+            NonZeroU32::new(1).unwrap(),
+            &to,
+            NonNegativeAmount::const_from_u64(1),
+            None,
+            None
+        ),
+        Err(data_api::error::Error::ScanRequired)
+    );
+}
+
+pub fn spend_fails_on_unverified_notes<DSF: DataStoreFactory>(dsf: DSF) {
+    let mut st = TestBuilder::new()
+        .with_block_cache(BlockCache::new())
+        .with_data_store_factory(dsf)
+        .with_test_account(AccountBirthday::from_sapling_activation)
+        .build();
+
+    let (account, usk, _) = st.test_account().unwrap();
+    let dfvk = st.test_account_sapling().unwrap();
+
+    // Add funds to the wallet in a single note
+    let value = NonNegativeAmount::const_from_u64(50000);
+    let (h1, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+    st.scan_cached_blocks(h1, 1);
+
+    // Spendable balance matches total balance at 1 confirmation.
+    assert_eq!(st.get_total_balance(account), value);
+    assert_eq!(st.get_spendable_balance(account, 1), value);
+
+    // Value is considered pending at 10 confirmations.
+    assert_eq!(st.get_pending_shielded_balance(account, 10), value);
+    assert_eq!(
+        st.get_spendable_balance(account, 10),
+        NonNegativeAmount::ZERO
+    );
+
+    // Wallet is fully scanned
+    let summary = st.get_wallet_summary(1);
+    assert_eq!(
+        summary.and_then(|s| s.scan_progress()),
+        Some(Ratio::new(1, 1))
+    );
+
+    // Add more funds to the wallet in a second note
+    let (h2, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+    st.scan_cached_blocks(h2, 1);
+
+    // Verified balance does not include the second note
+    let total = (value + value).unwrap();
+    assert_eq!(st.get_spendable_balance(account, 2), value);
+    assert_eq!(st.get_pending_shielded_balance(account, 2), value);
+    assert_eq!(st.get_total_balance(account), total);
+
+    // Wallet is still fully scanned
+    let summary = st.get_wallet_summary(1);
+    assert_eq!(
+        summary.and_then(|s| s.scan_progress()),
+        Some(Ratio::new(2, 2))
+    );
+
+    // Spend fails because there are insufficient verified notes
+    let extsk2 = ExtendedSpendingKey::master(&[]);
+    let to = extsk2.default_address().1.into();
+    assert_matches!(
+        st.propose_standard_transfer::<Infallible>(
+            account,
+            StandardFeeRule::Zip317,
+            PrivacyPolicy::FullPrivacy,
+            NonZeroU32::new(2).unwrap(),
+            &to,
+            NonNegativeAmount::const_from_u64(70000),
+            None,
+            None
+        ),
+        Err(data_api::error::Error::InsufficientFunds {
+            available,
+            required
+        })
+        if available == NonNegativeAmount::const_from_u64(50000)
+            && required == NonNegativeAmount::const_from_u64(80000)
+    );
+
+    // Mine blocks SAPLING_ACTIVATION_HEIGHT + 2 to 9 until just before the second
+    // note is verified
+    for _ in 2..10 {
+        st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+    }
+    st.scan_cached_blocks(h2 + 1, 8);
+
+    // Total balance is value * number of blocks scanned (10).
+    assert_eq!(st.get_total_balance(account), (value * 10).unwrap());
+
+    // Spend still fails
+    assert_matches!(
+        st.propose_standard_transfer::<Infallible>(
+            account,
+            StandardFeeRule::Zip317,
+            PrivacyPolicy::FullPrivacy,
+            NonZeroU32::new(10).unwrap(),
+            &to,
+            NonNegativeAmount::const_from_u64(70000),
+            None,
+            None
+        ),
+        Err(data_api::error::Error::InsufficientFunds {
+            available,
+            required
+        })
+        if available == NonNegativeAmount::const_from_u64(50000)
+            && required == NonNegativeAmount::const_from_u64(80000)
+    );
+
+    // Mine block 11 so that the second note becomes verified
+    let (h11, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+    st.scan_cached_blocks(h11, 1);
+
+    // Total balance is value * number of blocks scanned (11).
+    assert_eq!(st.get_total_balance(account), (value * 11).unwrap());
+    // Spendable balance at 10 confirmations is value * 2.
+    assert_eq!(st.get_spendable_balance(account, 10), (value * 2).unwrap());
+    assert_eq!(
+        st.get_pending_shielded_balance(account, 10),
+        (value * 9).unwrap()
+    );
+
+    // Should now be able to generate a proposal
+    let amount_sent = NonNegativeAmount::from_u64(70000).unwrap();
+    let min_confirmations = NonZeroU32::new(10).unwrap();
+    let proposal = st
+        .propose_standard_transfer::<Infallible>(
+            account,
+            StandardFeeRule::Zip317,
+            PrivacyPolicy::FullPrivacy,
+            min_confirmations,
+            &to,
+            amount_sent,
+            None,
+            None,
+        )
+        .unwrap();
+
+    // Executing the proposal should succeed
+    let txid = st
+        .create_proposed_transactions::<Infallible, _>(&usk, OvkPolicy::Sender, &proposal)
+        .unwrap()[0];
+
+    let (h, _) = st.generate_next_block_including(txid);
+    st.scan_cached_blocks(h, 1);
+
+    // The recipient is external to the wallet, so only the sender's balance can be checked.
+    assert_eq!(
+        st.get_total_balance(account),
+        ((value * 11).unwrap()
+            - (amount_sent + NonNegativeAmount::from_u64(10000).unwrap()).unwrap())
+        .unwrap()
+    );
+}
+
+pub fn spend_fails_on_locked_notes<DSF: DataStoreFactory>(dsf: DSF) {
+    let mut st = TestBuilder::new()
+        .with_block_cache(BlockCache::new())
+        .with_data_store_factory(dsf)
+        .with_test_account(AccountBirthday::from_sapling_activation)
+        .build();
+
+    let (account, usk, _) = st.test_account().unwrap();
+    let dfvk = st.test_account_sapling().unwrap();
+
+    let fee_rule = StandardFeeRule::Zip317;
+
+    // Add funds to the wallet in a single note
+    let value = NonNegativeAmount::const_from_u64(50000);
+    let (h1, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+    st.scan_cached_blocks(h1, 1);
+
+    // Spendable balance matches total balance at 1 confirmation.
+    assert_eq!(st.get_total_balance(account), value);
+    assert_eq!(st.get_spendable_balance(account, 1), value);
+
+    // Send some of the funds to another address, but don't mine the tx.
+    let extsk2 = ExtendedSpendingKey::master(&[]);
+    let to = extsk2.default_address().1.into();
+    let min_confirmations = NonZeroU32::new(1).unwrap();
+    let proposal = st
+        .propose_standard_transfer::<Infallible>(
+            account,
+            fee_rule,
+            PrivacyPolicy::FullPrivacy,
+            min_confirmations,
+            &to,
+            NonNegativeAmount::const_from_u64(15000),
+            None,
+            None,
+        )
+        .unwrap();
+
+    // Executing the proposal should succeed
+    assert_matches!(
+        st.create_proposed_transactions::<Infallible, _>(&usk, OvkPolicy::Sender, &proposal,),
+        Ok(_)
+    );
+
+    // A second proposal fails because there are no usable notes
+    assert_matches!(
+        st.propose_standard_transfer::<Infallible>(
+            account,
+            fee_rule,
+            PrivacyPolicy::FullPrivacy,
+            NonZeroU32::new(1).unwrap(),
+            &to,
+            NonNegativeAmount::const_from_u64(2000),
+            None,
+            None
+        ),
+        Err(data_api::error::Error::InsufficientFunds {
+            available,
+            required
+        })
+        if available == NonNegativeAmount::ZERO && required == NonNegativeAmount::const_from_u64(12000)
+    );
+
+    // Mine blocks SAPLING_ACTIVATION_HEIGHT + 1 to 41 (that don't send us funds)
+    // until just before the first transaction expires
+    for i in 1..42 {
+        st.generate_next_block(
+            &ExtendedSpendingKey::master(&[i as u8]).to_diversifiable_full_viewing_key(),
+            AddressType::DefaultExternal,
+            value,
+        );
+    }
+    st.scan_cached_blocks(h1 + 1, 41);
+
+    // Second proposal still fails
+    assert_matches!(
+        st.propose_standard_transfer::<Infallible>(
+            account,
+            fee_rule,
+            PrivacyPolicy::FullPrivacy,
+            NonZeroU32::new(1).unwrap(),
+            &to,
+            NonNegativeAmount::const_from_u64(2000),
+            None,
+            None
+        ),
+        Err(data_api::error::Error::InsufficientFunds {
+            available,
+            required
+        })
+        if available == NonNegativeAmount::ZERO && required == NonNegativeAmount::const_from_u64(12000)
+    );
+
+    // Mine block SAPLING_ACTIVATION_HEIGHT + 42 so that the first transaction expires
+    let (h43, _, _) = st.generate_next_block(
+        &ExtendedSpendingKey::master(&[42]).to_diversifiable_full_viewing_key(),
+        AddressType::DefaultExternal,
+        value,
+    );
+    st.scan_cached_blocks(h43, 1);
+
+    // Spendable balance matches total balance at 1 confirmation.
+    assert_eq!(st.get_total_balance(account), value);
+    assert_eq!(st.get_spendable_balance(account, 1), value);
+
+    // Second spend should now succeed
+    let amount_sent2 = NonNegativeAmount::const_from_u64(2000);
+    let min_confirmations = NonZeroU32::new(1).unwrap();
+    let proposal = st
+        .propose_standard_transfer::<Infallible>(
+            account,
+            fee_rule,
+            PrivacyPolicy::FullPrivacy,
+            min_confirmations,
+            &to,
+            amount_sent2,
+            None,
+            None,
+        )
+        .unwrap();
+
+    let txid2 = st
+        .create_proposed_transactions::<Infallible, _>(&usk, OvkPolicy::Sender, &proposal)
+        .unwrap()[0];
+
+    let (h, _) = st.generate_next_block_including(txid2);
+    st.scan_cached_blocks(h, 1);
+
+    // The recipient is external to the wallet, so only the sender's balance can be checked.
+    assert_eq!(
+        st.get_total_balance(account),
+        (value - (amount_sent2 + NonNegativeAmount::from_u64(10000).unwrap()).unwrap()).unwrap()
+    );
+}
+
+pub fn ovk_policy_prevents_recovery_from_chain<DSF: DataStoreFactory>(dsf: DSF) {
+    let mut st = TestBuilder::new()
+        .with_block_cache(BlockCache::new())
+        .with_data_store_factory(dsf)
+        .with_test_account(AccountBirthday::from_sapling_activation)
+        .build();
+
+    let (account, usk, _) = st.test_account().unwrap();
+    let dfvk = st.test_account_sapling().unwrap();
+
+    // Add funds to the wallet in a single note
+    let value = NonNegativeAmount::const_from_u64(50000);
+    let (h1, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+    st.scan_cached_blocks(h1, 1);
+
+    // Spendable balance matches total balance at 1 confirmation.
+    assert_eq!(st.get_total_balance(account), value);
+    assert_eq!(st.get_spendable_balance(account, 1), value);
+
+    let extsk2 = ExtendedSpendingKey::master(&[]);
+    let addr2 = extsk2.default_address().1;
+    let to = addr2.into();
+
+    let fee_rule = StandardFeeRule::Zip317;
+
+    #[allow(clippy::type_complexity)]
+    let send_and_recover_with_policy = |st: &mut TestState<BlockCache, DSF>,
+                                        ovk_policy|
+     -> Result<
+        Option<(sapling::Note, PaymentAddress, MemoBytes)>,
+        Error<
+            DSF::Error,
+            DSF::CommitmentTreeError,
+            GreedyInputSelectorError<Zip317FeeError, <DSF::DataStore as InputSource>::NoteRef>,
+            Zip317FeeError,
+        >,
+    > {
+        let min_confirmations = NonZeroU32::new(1).unwrap();
+        let proposal = st.propose_standard_transfer(
+            account,
+            fee_rule,
+            PrivacyPolicy::FullPrivacy,
+            min_confirmations,
+            &to,
+            NonNegativeAmount::const_from_u64(15000),
+            None,
+            None,
+        )?;
+
+        // Executing the proposal should succeed
+        let txid = st.create_proposed_transactions(&usk, ovk_policy, &proposal)?[0];
+
+        // Fetch the transaction from the wallet
+        let tx = st
+            .wallet()
+            .get_transaction(txid)
+            .map_err(Error::DataSource)?;
+
+        for output in tx.sapling_bundle().unwrap().shielded_outputs() {
+            // Find the output that decrypts with the external OVK
+            let result = try_sapling_output_recovery(
+                &dfvk.to_ovk(Scope::External),
+                output,
+                sapling_zip212_enforcement(&st.network(), h1),
+            );
+
+            if result.is_some() {
+                return Ok(result.map(|(note, addr, memo)| {
+                    (
+                        note,
+                        addr,
+                        MemoBytes::from_bytes(&memo).expect("correct length"),
+                    )
+                }));
+            }
+        }
+
+        Ok(None)
+    };
+
+    // Send some of the funds to another address, keeping history.
+    // The recipient output is decryptable by the sender.
+    assert_matches!(
+        send_and_recover_with_policy(&mut st, OvkPolicy::Sender),
+        Ok(Some((_, recovered_to, _))) if recovered_to == addr2
+    );
+
+    // Mine blocks SAPLING_ACTIVATION_HEIGHT + 1 to 42 (that don't send us funds)
+    // so that the first transaction expires
+    for i in 1..=42 {
+        st.generate_next_block(
+            &ExtendedSpendingKey::master(&[i as u8]).to_diversifiable_full_viewing_key(),
+            AddressType::DefaultExternal,
+            value,
+        );
+    }
+    st.scan_cached_blocks(h1 + 1, 42);
+
+    // Send the funds again, discarding history.
+    // Neither transaction output is decryptable by the sender.
+    assert_matches!(
+        send_and_recover_with_policy(&mut st, OvkPolicy::Discard),
+        Ok(None)
+    );
+}
+
+pub fn spend_succeeds_to_t_addr_zero_change<DSF: DataStoreFactory>(dsf: DSF) {
+    let mut st = TestBuilder::new()
+        .with_block_cache(BlockCache::new())
+        .with_data_store_factory(dsf)
+        .with_test_account(AccountBirthday::from_sapling_activation)
+        .build();
+
+    let (account, usk, _) = st.test_account().unwrap();
+    let dfvk = st.test_account_sapling().unwrap();
+
+    // Add funds to the wallet in a single note
+    let value = NonNegativeAmount::const_from_u64(65000);
+    let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+    st.scan_cached_blocks(h, 1);
+
+    // Spendable balance matches total balance at 1 confirmation.
+    assert_eq!(st.get_total_balance(account), value);
+    assert_eq!(st.get_spendable_balance(account, 1), value);
+
+    // The ZIP 317 fee for a single Sapling spend (with padding to two Sapling outputs) and a
+    // single transparent output is 15000 zatoshis, so the payment below leaves no change.
+    let fee_rule = StandardFeeRule::Zip317;
+
+    // The transaction is not mined, because fake compact blocks do not carry transparent outputs.
+    let to = TransparentAddress::PublicKeyHash([7; 20]).into();
+    let min_confirmations = NonZeroU32::new(1).unwrap();
+    let proposal = st
+        .propose_standard_transfer::<Infallible>(
+            account,
+            fee_rule,
+            PrivacyPolicy::AllowRevealedRecipients,
+            min_confirmations,
+            &to,
+            NonNegativeAmount::const_from_u64(50000),
+            None,
+            None,
+        )
+        .unwrap();
+
+    // Executing the proposal should succeed
+    assert_matches!(
+        st.create_proposed_transactions::<Infallible, _>(&usk, OvkPolicy::Sender, &proposal),
+        Ok(_)
+    );
+}
+
+pub fn change_note_spends_succeed<DSF: DataStoreFactory>(dsf: DSF) {
+    let mut st = TestBuilder::new()
+        .with_block_cache(BlockCache::new())
+        .with_data_store_factory(dsf)
+        .with_test_account(AccountBirthday::from_sapling_activation)
+        .build();
+
+    let (account, usk, _) = st.test_account().unwrap();
+    let dfvk = st.test_account_sapling().unwrap();
+
+    // Add funds to the wallet in a single note owned by the internal spending key
+    let value = NonNegativeAmount::const_from_u64(65000);
+    let (h, _, _) = st.generate_next_block(&dfvk, AddressType::Internal, value);
+    st.scan_cached_blocks(h, 1);
+
+    // Spendable balance matches total balance at 1 confirmation.
+    assert_eq!(st.get_total_balance(account), value);
+    assert_eq!(st.get_spendable_balance(account, 1), value);
+
+    // Value is considered pending at 10 confirmations.
+    assert_eq!(st.get_pending_shielded_balance(account, 10), value);
+    assert_eq!(
+        st.get_spendable_balance(account, 10),
+        NonNegativeAmount::ZERO
+    );
+
+    let spendable = st
+        .wallet()
+        .select_spendable_notes(account, value.into(), &[ShieldedProtocol::Sapling], h, &[])
+        .unwrap();
+    assert_eq!(spendable.len(), 1);
+    assert_eq!(spendable[0].spending_key_scope(), Scope::Internal);
+
+    let fee_rule = StandardFeeRule::Zip317;
+
+    // The transaction is not mined, because fake compact blocks do not carry transparent outputs.
+    let to = TransparentAddress::PublicKeyHash([7; 20]).into();
+    let min_confirmations = NonZeroU32::new(1).unwrap();
+    let proposal = st
+        .propose_standard_transfer::<Infallible>(
+            account,
+            fee_rule,
+            PrivacyPolicy::AllowRevealedRecipients,
+            min_confirmations,
+            &to,
+            NonNegativeAmount::const_from_u64(50000),
+            None,
+            None,
+        )
+        .unwrap();
+
+    // Executing the proposal should succeed
+    assert_matches!(
+        st.create_proposed_transactions::<Infallible, _>(&usk, OvkPolicy::Sender, &proposal),
+        Ok(_)
+    );
+}
+
+pub fn external_address_change_spends_detected_in_restore_from_seed<DSF: DataStoreFactory>(
+    dsf: DSF,
+) {
+    let mut st = TestBuilder::new()
+        .with_block_cache(BlockCache::new())
+        .with_data_store_factory(dsf)
+        .build();
+
+    // Add two accounts to the wallet.
+    let seed = Secret::new([0u8; 32].to_vec());
+    let birthday = AccountBirthday::from_sapling_activation(&st.network());
+    let (_, usk) = st
+        .wallet_mut()
+        .create_account(&seed, birthday.clone())
+        .unwrap();
+    let dfvk = usk.sapling().to_diversifiable_full_viewing_key();
+
+    let (_, usk2) = st
+        .wallet_mut()
+        .create_account(&seed, birthday.clone())
+        .unwrap();
+    let dfvk2 = usk2.sapling().to_diversifiable_full_viewing_key();
+
+    // Add funds to the wallet in a single note
+    let value = NonNegativeAmount::from_u64(100000).unwrap();
+    let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+    st.scan_cached_blocks(h, 1);
+
+    // Spendable balance matches total balance
+    assert_eq!(st.get_total_balance(AccountId::ZERO), value);
+    assert_eq!(st.get_spendable_balance(AccountId::ZERO, 1), value);
+    assert_eq!(
+        st.get_total_balance(AccountId::try_from(1).unwrap()),
+        NonNegativeAmount::ZERO,
+    );
+
+    let amount_sent = NonNegativeAmount::from_u64(20000).unwrap();
+    let amount_legacy_change = NonNegativeAmount::from_u64(30000).unwrap();
+    let addr = dfvk.default_address().1;
+    let addr2 = dfvk2.default_address().1;
+    let req = TransactionRequest::new(vec![
+        // payment to an external recipient
+        Payment {
+            recipient_address: Address::Sapling(addr2),
+            amount: amount_sent,
+            memo: None,
+            label: None,
+            message: None,
+            other_params: vec![],
+        },
+        // payment back to the originating wallet, simulating legacy change
+        Payment {
+            recipient_address: Address::Sapling(addr),
+            amount: amount_legacy_change,
+            memo: None,
+            label: None,
+            message: None,
+            other_params: vec![],
+        },
+    ])
+    .unwrap();
+
+    #[allow(deprecated)]
+    let fee_rule = FixedFeeRule::standard();
+    let input_selector = GreedyInputSelector::new(
+        fixed::SingleOutputChangeStrategy::new(fee_rule, None, ChangePoolPolicy::default()),
+        DustOutputPolicy::default(),
+    );
+
+    let txid = st
+        .spend(
+            &input_selector,
+            &usk,
+            req,
+            PrivacyPolicy::FullPrivacy,
+            OvkPolicy::Sender,
+            NonZeroU32::new(1).unwrap(),
+        )
+        .unwrap()[0];
+
+    let amount_left = (value - (amount_sent + fee_rule.fixed_fee()).unwrap()).unwrap();
+    let pending_change = (amount_left - amount_legacy_change).unwrap();
+
+    // The "legacy change" is not counted by get_pending_change().
+    assert_eq!(st.get_pending_change(AccountId::ZERO, 1), pending_change);
+    // We spent the only note so we only have pending change.
+    assert_eq!(st.get_total_balance(AccountId::ZERO), pending_change);
+
+    let (h, _) = st.generate_next_block_including(txid);
+    st.scan_cached_blocks(h, 1);
+
+    assert_eq!(
+        st.get_total_balance(AccountId::try_from(1).unwrap()),
+        amount_sent,
+    );
+    assert_eq!(st.get_total_balance(AccountId::ZERO), amount_left);
+
+    st.reset();
+
+    // Account creation and DFVK derivation should be deterministic.
+    let (_, restored_usk) = st
+        .wallet_mut()
+        .create_account(&seed, birthday.clone())
+        .unwrap();
+    assert_eq!(
+        restored_usk
+            .sapling()
+            .to_diversifiable_full_viewing_key()
+            .to_bytes(),
+        dfvk.to_bytes()
+    );
+
+    let (_, restored_usk2) = st.wallet_mut().create_account(&seed, birthday).unwrap();
+    assert_eq!(
+        restored_usk2
+            .sapling()
+            .to_diversifiable_full_viewing_key()
+            .to_bytes(),
+        dfvk2.to_bytes()
+    );
+
+    st.scan_cached_blocks(st.sapling_activation_height(), 2);
+
+    assert_eq!(
+        st.get_total_balance(AccountId::try_from(1).unwrap()),
+        amount_sent,
+    );
+    assert_eq!(st.get_total_balance(AccountId::ZERO), amount_left);
+}
+
+pub fn mempool_transaction_is_pending_until_mined<DSF: DataStoreFactory>(dsf: DSF)
+where
+    DSF::Error: From<io::Error>,
+{
+    let mut st = TestBuilder::new()
+        .with_block_cache(BlockCache::new())
+        .with_data_store_factory(dsf)
+        .build();
+
+    // Add two accounts to the wallet.
+    let seed = Secret::new([0u8; 32].to_vec());
+    let birthday = AccountBirthday::from_sapling_activation(&st.network());
+    let (account, usk) = st
+        .wallet_mut()
+        .create_account(&seed, birthday.clone())
+        .unwrap();
+    let dfvk = usk.sapling().to_diversifiable_full_viewing_key();
+
+    let (account2, usk2) = st.wallet_mut().create_account(&seed, birthday).unwrap();
+    let dfvk2 = usk2.sapling().to_diversifiable_full_viewing_key();
+
+    // Add funds to the first account in a single note
+    let value = NonNegativeAmount::const_from_u64(100000);
+    let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+    st.scan_cached_blocks(h, 1);
+    assert_eq!(st.get_spendable_balance(account, 1), value);
+
+    // Send funds from the first account to the second
+    let amount_sent = NonNegativeAmount::const_from_u64(20000);
+    let req = TransactionRequest::new(vec![Payment {
+        recipient_address: Address::Sapling(dfvk2.default_address().1),
+        amount: amount_sent,
+        memo: None,
+        label: None,
+        message: None,
+        other_params: vec![],
+    }])
+    .unwrap();
+
+    let txid = st
+        .spend(
+            &input_selector(StandardFeeRule::Zip317, None),
+            &usk,
+            req,
+            PrivacyPolicy::FullPrivacy,
+            OvkPolicy::Sender,
+            NonZeroU32::new(1).unwrap(),
+        )
+        .unwrap()[0];
+
+    // The second account has no knowledge of the transaction until it is seen in the mempool
+    assert_eq!(st.get_total_balance(account2), NonNegativeAmount::ZERO);
+
+    // Feed the transaction to the wallet as though it had been received from the mempool
+    let mut data = vec![];
+    st.wallet()
+        .get_transaction(txid)
+        .unwrap()
+        .write(&mut data)
+        .unwrap();
+    let raw_tx = RawTransaction {
+        data,
+        height: u32::from(h).into(),
+    };
+    let network = st.network();
+    assert_eq!(
+        decrypt_and_store_mempool_transaction(&network, st.wallet_mut(), &raw_tx).unwrap(),
+        txid
+    );
+
+    // The received value is reported as incoming pending, and is not yet spendable
+    let summary = st.get_wallet_summary(1).unwrap();
+    let balance = summary.account_balances().get(&account2).unwrap();
+    assert_eq!(balance.incoming_pending(), amount_sent);
+    assert_eq!(
+        balance.value_pending_spendability(),
+        NonNegativeAmount::ZERO
+    );
+    assert_eq!(balance.total(), NonNegativeAmount::ZERO);
+    assert_eq!(
+        st.get_spendable_balance(account2, 1),
+        NonNegativeAmount::ZERO
+    );
+
+    // Once the transaction is mined, the value is no longer pending
+    let (h2, _) = st.generate_next_block_including(txid);
+    st.scan_cached_blocks(h2, 1);
+
+    let summary = st.get_wallet_summary(1).unwrap();
+    let balance = summary.account_balances().get(&account2).unwrap();
+    assert_eq!(balance.incoming_pending(), NonNegativeAmount::ZERO);
+    assert_eq!(balance.total(), amount_sent);
+    assert_eq!(st.get_spendable_balance(account2, 1), amount_sent);
+}
+
+pub fn mempool_transaction_expires_unmined<DSF: DataStoreFactory>(dsf: DSF)
+where
+    DSF::Error: From<io::Error>,
+{
+    let mut st = TestBuilder::new()
+        .with_block_cache(BlockCache::new())
+        .with_data_store_factory(dsf)
+        .build();
+
+    // Add two accounts to the wallet.
+    let seed = Secret::new([0u8; 32].to_vec());
+    let birthday = AccountBirthday::from_sapling_activation(&st.network());
+    let (account, usk) = st
+        .wallet_mut()
+        .create_account(&seed, birthday.clone())
+        .unwrap();
+    let dfvk = usk.sapling().to_diversifiable_full_viewing_key();
+
+    let (account2, usk2) = st.wallet_mut().create_account(&seed, birthday).unwrap();
+    let dfvk2 = usk2.sapling().to_diversifiable_full_viewing_key();
+
+    // Add funds to the first account in a single note
+    let value = NonNegativeAmount::const_from_u64(100000);
+    let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+    st.scan_cached_blocks(h, 1);
+    assert_eq!(st.get_spendable_balance(account, 1), value);
+
+    // Send funds from the first account to the second
+    let amount_sent = NonNegativeAmount::const_from_u64(20000);
+    let req = TransactionRequest::new(vec![Payment {
+        recipient_address: Address::Sapling(dfvk2.default_address().1),
+        amount: amount_sent,
+        memo: None,
+        label: None,
+        message: None,
+        other_params: vec![],
+    }])
+    .unwrap();
+
+    let txid = st
+        .spend(
+            &input_selector(StandardFeeRule::Zip317, None),
+            &usk,
+            req,
+            PrivacyPolicy::FullPrivacy,
+            OvkPolicy::Sender,
+            NonZeroU32::new(1).unwrap(),
+        )
+        .unwrap()[0];
+
+    // Feed the transaction to the wallet as though it had been received from the mempool
+    let tx = st.wallet().get_transaction(txid).unwrap();
+    let expiry_height = tx.expiry_height();
+    let mut data = vec![];
+    tx.write(&mut data).unwrap();
+    let raw_tx = RawTransaction {
+        data,
+        height: u32::from(h).into(),
+    };
+    let network = st.network();
+    decrypt_and_store_mempool_transaction(&network, st.wallet_mut(), &raw_tx).unwrap();
+
+    let incoming_pending = |st: &TestState<BlockCache, DSF>| {
+        st.get_wallet_summary(1)
+            .unwrap()
+            .account_balances()
+            .get(&account2)
+            .unwrap()
+            .incoming_pending()
+    };
+    assert_eq!(incoming_pending(&st), amount_sent);
+
+    // Advance the chain to the transaction's expiry height without mining it.
+    let not_our_key = ExtendedSpendingKey::master(&[]).to_diversifiable_full_viewing_key();
+    let (start, _, _) = st.generate_next_block(&not_our_key, AddressType::DefaultExternal, value);
+    let mut tip = start;
+    while tip < expiry_height {
+        tip = st
+            .generate_next_block(&not_our_key, AddressType::DefaultExternal, value)
+            .0;
+    }
+    st.scan_cached_blocks(start, (u32::from(tip) - u32::from(start) + 1) as usize);
+
+    // The expired transaction's value is no longer reported as pending.
+    assert_eq!(incoming_pending(&st), NonNegativeAmount::ZERO);
+    assert_eq!(st.get_total_balance(account2), NonNegativeAmount::ZERO);
+}
+
+#[allow(deprecated)]
+pub fn abandoned_transaction_releases_spent_notes<DSF: DataStoreFactory>(dsf: DSF) {
+    let mut st = TestBuilder::new()
+        .with_block_cache(BlockCache::new())
+        .with_data_store_factory(dsf)
+        .with_test_account(AccountBirthday::from_sapling_activation)
+        .build();
+
+    let (account, usk, _) = st.test_account().unwrap();
+    let dfvk = st.test_account_sapling().unwrap();
+
+    // Add funds to the wallet in a single note
+    let value = NonNegativeAmount::const_from_u64(60000);
+    let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+    st.scan_cached_blocks(h, 1);
+    assert_eq!(st.get_spendable_balance(account, 1), value);
+
+    // Nothing has been sent yet
+    assert!(st
+        .wallet()
+        .get_unmined_sent_transactions()
+        .unwrap()
+        .is_empty());
+
+    let extsk2 = ExtendedSpendingKey::master(&[]);
+    let to = extsk2.default_address().1.into();
+    let txid = st
+        .create_spend_to_address(
+            &usk,
+            &to,
+            NonNegativeAmount::const_from_u64(15000),
+            None,
+            OvkPolicy::Sender,
+            NonZeroU32::new(1).unwrap(),
+            None,
+        )
+        .unwrap()[0];
+
+    // The sent transaction is available for rebroadcast, and the note it spends is locked
+    let unmined = st.wallet().get_unmined_sent_transactions().unwrap();
+    assert_eq!(unmined.len(), 1);
+    assert_eq!(unmined[0].txid(), txid);
+    let mut raw = vec![];
+    st.wallet()
+        .get_transaction(txid)
+        .unwrap()
+        .write(&mut raw)
+        .unwrap();
+    assert_eq!(unmined[0].raw(), &raw[..]);
+    assert!(unmined[0].expiry_height() > h);
+    assert!(unmined[0].created().is_some());
+    assert_eq!(
+        st.get_spendable_balance(account, 1),
+        NonNegativeAmount::ZERO
+    );
+
+    // Abandoning the transaction releases the spent note immediately
+    st.wallet_mut().abandon_transaction(txid).unwrap();
+    assert!(st
+        .wallet()
+        .get_unmined_sent_transactions()
+        .unwrap()
+        .is_empty());
+    assert_eq!(st.get_spendable_balance(account, 1), value);
+    assert_eq!(st.get_total_balance(account), value);
+
+    // The transaction can no longer be abandoned once mined
+    let (h2, _) = st.generate_next_block_including(txid);
+    st.scan_cached_blocks(h2, 1);
+    assert_matches!(st.wallet_mut().abandon_transaction(txid), Err(_));
+}
+
+pub fn zip317_spend<DSF: DataStoreFactory>(dsf: DSF) {
+    let mut st = TestBuilder::new()
+        .with_block_cache(BlockCache::new())
+        .with_data_store_factory(dsf)
+        .with_test_account(AccountBirthday::from_sapling_activation)
+        .build();
+
+    let (account, usk, _) = st.test_account().unwrap();
+    let dfvk = st.test_account_sapling().unwrap();
+
+    // Add funds to the wallet
+    let (h1, _, _) = st.generate_next_block(
+        &dfvk,
+        AddressType::Internal,
+        NonNegativeAmount::const_from_u64(50000),
+    );
+
+    // Add 10 dust notes to the wallet
+    for _ in 1..=10 {
+        st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(1000),
+        );
+    }
+
+    st.scan_cached_blocks(h1, 11);
+
+    // Spendable balance matches total balance
+    let total = NonNegativeAmount::const_from_u64(60000);
+    assert_eq!(st.get_total_balance(account), total);
+    assert_eq!(st.get_spendable_balance(account, 1), total);
+
+    let input_selector = input_selector(StandardFeeRule::Zip317, None);
+
+    // This first request will fail due to insufficient non-dust funds
+    let req = TransactionRequest::new(vec![Payment {
+        recipient_address: Address::Sapling(dfvk.default_address().1),
+        amount: NonNegativeAmount::const_from_u64(50000),
+        memo: None,
+        label: None,
+        message: None,
+        other_params: vec![],
+    }])
+    .unwrap();
+
+    assert_matches!(
+        st.spend(
+            &input_selector,
+            &usk,
+            req,
+            PrivacyPolicy::FullPrivacy,
+            OvkPolicy::Sender,
+            NonZeroU32::new(1).unwrap(),
+        ),
+        Err(Error::InsufficientFunds { available, required })
+            if available == NonNegativeAmount::const_from_u64(51000)
+            && required == NonNegativeAmount::const_from_u64(60000)
+    );
+
+    // This request will succeed, spending a single dust input to pay the 10000
+    // ZAT fee in addition to the 41000 ZAT output to the recipient
+    let req = TransactionRequest::new(vec![Payment {
+        recipient_address: Address::Sapling(dfvk.default_address().1),
+        amount: NonNegativeAmount::const_from_u64(41000),
+        memo: None,
+        label: None,
+        message: None,
+        other_params: vec![],
+    }])
+    .unwrap();
+
+    let txid = st
+        .spend(
+            &input_selector,
+            &usk,
+            req,
+            PrivacyPolicy::FullPrivacy,
+            OvkPolicy::Sender,
+            NonZeroU32::new(1).unwrap(),
+        )
+        .unwrap()[0];
+
+    let (h, _) = st.generate_next_block_including(txid);
+    st.scan_cached_blocks(h, 1);
+
+    // We sent back to the same account, so only the fee is deducted from the total balance.
+    assert_eq!(
+        st.get_total_balance(account),
+        (total - NonNegativeAmount::const_from_u64(10000)).unwrap()
+    );
+}
+
+#[cfg(feature = "transparent-inputs")]
+pub fn shield_transparent<DSF: DataStoreFactory>(dsf: DSF) {
+    let mut st = TestBuilder::new()
+        .with_block_cache(BlockCache::new())
+        .with_data_store_factory(dsf)
+        .with_test_account(AccountBirthday::from_sapling_activation)
+        .build();
+
+    let (account_id, usk, _) = st.test_account().unwrap();
+    let dfvk = st.test_account_sapling().unwrap();
+
+    let uaddr = st
+        .wallet()
+        .get_current_address(account_id)
+        .unwrap()
+        .unwrap();
+    let taddr = uaddr.transparent().unwrap();
+
+    // Ensure that the wallet has at least one block
+    let (h, _, _) = st.generate_next_block(
+        &dfvk,
+        AddressType::Internal,
+        NonNegativeAmount::const_from_u64(50000),
+    );
+    st.scan_cached_blocks(h, 1);
+
+    let utxo = WalletTransparentOutput::from_parts(
+        OutPoint::new([1u8; 32], 1),
+        TxOut {
+            value: NonNegativeAmount::const_from_u64(20000),
+            script_pubkey: taddr.script(),
+        },
+        h,
+    )
+    .unwrap();
+
+    let res0 = st.wallet_mut().put_received_transparent_utxo(&utxo);
+    assert!(matches!(res0, Ok(_)));
+
+    let fee_rule = StandardFeeRule::Zip317;
+
+    let input_selector = GreedyInputSelector::new(
+        standard::SingleOutputChangeStrategy::new(fee_rule, None, ChangePoolPolicy::default()),
+        DustOutputPolicy::default(),
+    );
+
+    assert_matches!(
+        st.shield_transparent_funds(
+            &input_selector,
+            NonNegativeAmount::from_u64(10000).unwrap(),
+            &usk,
+            &[*taddr],
+            1
+        ),
+        Ok(_)
+    );
+}
+
+pub fn birthday_in_anchor_shard<DSF: DataStoreFactory>(dsf: DSF) {
+    let mut st = TestBuilder::new()
+        .with_block_cache(BlockCache::new())
+        .with_data_store_factory(dsf)
+        .with_test_account(|network| {
+            // We use Canopy activation as an arbitrary birthday height that's greater than Sapling
+            // activation. We set the Canopy frontier to be 1234 notes into the second shard.
+            let birthday_height = network.activation_height(NetworkUpgrade::Canopy).unwrap();
+            let frontier_position = Position::from((0x1 << 16) + 1234);
+            let frontier = Frontier::from_parts(
+                frontier_position,
+                Node::empty_leaf(),
+                vec![Node::empty_leaf(); frontier_position.past_ommer_count().into()],
+            )
+            .unwrap();
+            AccountBirthday::from_parts(birthday_height, frontier, None)
+        })
+        .build();
+
+    let (account, _, birthday) = st.test_account().unwrap();
+    let dfvk = st.test_account_sapling().unwrap();
+
+    // Set up the following situation:
+    //
+    //        |<------ 500 ------->|<--- 10 --->|<--- 10 --->|
+    // last_shard_start   wallet_birthday  received_tx  anchor_height
+    //
+    // Set up some shard root history before the wallet birthday.
+    let prev_shard_start = birthday.height() - 500;
+    st.wallet_mut()
+        .put_sapling_subtree_roots(
+            0,
+            &[CommitmentTreeRoot::from_parts(
+                prev_shard_start,
+                // fake a hash, the value doesn't matter
+                Node::empty_leaf(),
+            )],
+        )
+        .unwrap();
+
+    let received_tx_height = birthday.height() + 10;
+
+    let initial_sapling_tree_size =
+        u64::from(birthday.sapling_frontier().value().unwrap().position() + 1)
+            .try_into()
+            .unwrap();
+
+    // Generate 9 blocks that have no value for us, starting at the birthday height.
+    let not_our_key = ExtendedSpendingKey::master(&[]).to_diversifiable_full_viewing_key();
+    let not_our_value = NonNegativeAmount::const_from_u64(10000);
+    st.generate_block_at(
+        birthday.height(),
+        BlockHash([0; 32]),
+        &not_our_key,
+        AddressType::DefaultExternal,
+        not_our_value,
+        initial_sapling_tree_size,
+    );
+    for _ in 1..9 {
+        st.generate_next_block(&not_our_key, AddressType::DefaultExternal, not_our_value);
+    }
+
+    // Now, generate a block that belongs to our wallet
+    st.generate_next_block(
+        &dfvk,
+        AddressType::DefaultExternal,
+        NonNegativeAmount::const_from_u64(500000),
+    );
+
+    // Generate some more blocks to get above our anchor height
+    for _ in 0..15 {
+        st.generate_next_block(&not_our_key, AddressType::DefaultExternal, not_our_value);
+    }
+
+    // Scan a block range that includes our received note, but skips some blocks we need to
+    // make it spendable.
+    st.scan_cached_blocks(birthday.height() + 5, 20);
+
+    // Verify that the received note is not considered spendable
+    let spendable = st
+        .wallet()
+        .select_spendable_notes(
+            account,
+            Amount::const_from_i64(300000),
+            &[ShieldedProtocol::Sapling],
+            received_tx_height + 10,
+            &[],
+        )
+        .unwrap();
+
+    assert_eq!(spendable.len(), 0);
+
+    // Scan the blocks we skipped
+    st.scan_cached_blocks(birthday.height(), 5);
+
+    // Verify that the received note is now considered spendable
+    let spendable = st
+        .wallet()
+        .select_spendable_notes(
+            account,
+            Amount::const_from_i64(300000),
+            &[ShieldedProtocol::Sapling],
+            received_tx_height + 10,
+            &[],
+        )
+        .unwrap();
+
+    assert_eq!(spendable.len(), 1);
+}
@@ -1,16 +1,17 @@
-use std::num::NonZeroU32;
+use std::{io, num::NonZeroU32};
 
+use nonempty::NonEmpty;
 use rand_core::OsRng;
 use sapling::{
     note_encryption::{try_sapling_note_decryption, PreparedIncomingViewingKey},
     prover::{OutputProver, SpendProver},
 };
 use zcash_primitives::{
-    consensus::{self, NetworkUpgrade},
+    consensus::{self, BlockHeight, BranchId, NetworkUpgrade},
     memo::MemoBytes,
     transaction::{
-        builder::{BuildConfig, Builder},
-        components::amount::{Amount, NonNegativeAmount},
+        builder::{BuildConfig, BuildResult, Builder},
+        components::amount::{Amount, BalanceError, NonNegativeAmount},
         fees::{zip317::FeeError as Zip317FeeError, FeeRule, StandardFeeRule},
         Transaction, TxId,
     },
@@ -20,29 +21,40 @@ use zcash_primitives::{
 use crate::{
     address::Address,
     data_api::{
-        error::Error, wallet::input_selection::Proposal, DecryptedTransaction, SentTransaction,
-        SentTransactionOutput, WalletCommitmentTrees, WalletRead, WalletWrite,
+        error::Error,
+        wallet::input_selection::{Proposal, Step},
+        DecryptedTransaction, SentTransaction, SentTransactionOutput, WalletCommitmentTrees,
+        WalletRead, WalletWrite,
     },
     decrypt_transaction,
-    fees::{self, DustOutputPolicy},
+    fees::{self, ChangeStrategy, DustOutputPolicy},
     keys::UnifiedSpendingKey,
+    proto::service::RawTransaction,
     wallet::{Note, OvkPolicy, Recipient},
     zip321::{self, Payment},
     PoolType, ShieldedProtocol,
 };
 
 pub mod input_selection;
+pub mod pczt;
 use input_selection::{
-    GreedyInputSelector, GreedyInputSelectorError, InputSelector, InputSelectorError,
+    GreedyInputSelector, GreedyInputSelectorError, InputSelector, InputSelectorError, PrivacyPolicy,
 };
 
 use super::InputSource;
 
+#[cfg(feature = "orchard")]
+use crate::decrypt_transaction_orchard;
+
 #[cfg(feature = "transparent-inputs")]
 use {
-    crate::wallet::WalletTransparentOutput, input_selection::ShieldingSelector,
-    sapling::keys::OutgoingViewingKey, std::convert::Infallible,
-    zcash_primitives::legacy::TransparentAddress,
+    input_selection::{ShieldingSelector, StepOutputIndex},
+    sapling::keys::OutgoingViewingKey,
+    std::convert::Infallible,
+    zcash_primitives::{
+        legacy::TransparentAddress,
+        transaction::components::{OutPoint, TxOut},
+    },
 };
 
 /// Scans a [`Transaction`] for any information that can be decrypted by the accounts in
@@ -70,11 +82,63 @@ where
     data.store_decrypted_tx(DecryptedTransaction {
         tx,
         sapling_outputs: &decrypt_transaction(params, height, tx, &ufvks),
+        #[cfg(feature = "orchard")]
+        orchard_outputs: &decrypt_transaction_orchard(tx, &ufvks),
     })?;
 
     Ok(())
 }
 
+/// Parses a transaction that was observed in the mempool, decrypts any outputs that are
+/// visible to the accounts in the wallet, and stores it as an unmined transaction.
+///
+/// `raw_tx` is expected to have been obtained from the `GetMempoolStream` RPC, such that its
+/// `height` field contains the height of the chain tip at the time the transaction was observed;
+/// if this height is not available, the wallet's view of the chain tip is used instead. Value
+/// received by the wallet in the transaction is reported as
+/// [`Balance::incoming_pending`] until the transaction is mined, at which point scanning the
+/// containing block will record its mined height. If the transaction expires without being
+/// mined, its value is no longer included in the wallet's balance.
+///
+/// Returns the ID of the stored transaction.
+///
+/// [`Balance::incoming_pending`]: crate::data_api::Balance::incoming_pending
+pub fn decrypt_and_store_mempool_transaction<ParamsT, DbT>(
+    params: &ParamsT,
+    data: &mut DbT,
+    raw_tx: &RawTransaction,
+) -> Result<TxId, DbT::Error>
+where
+    ParamsT: consensus::Parameters,
+    DbT: WalletWrite,
+    DbT::Error: From<io::Error>,
+{
+    // Fetch the UnifiedFullViewingKeys we are tracking
+    let ufvks = data.get_unified_full_viewing_keys()?;
+
+    // The transaction can be mined no earlier than the block following the chain tip.
+    let height = match u32::try_from(raw_tx.height) {
+        Ok(tip_height) if tip_height > 0 && tip_height < u32::MAX => {
+            BlockHeight::from(tip_height + 1)
+        }
+        _ => data
+            .chain_height()?
+            .map(|max_height| max_height + 1)
+            .or_else(|| params.activation_height(NetworkUpgrade::Sapling))
+            .expect("Sapling activation height must be known."),
+    };
+
+    let tx = Transaction::read(&raw_tx.data[..], BranchId::for_height(params, height))?;
+    data.store_decrypted_tx(DecryptedTransaction {
+        tx: &tx,
+        sapling_outputs: &decrypt_transaction(params, height, &tx, &ufvks),
+        #[cfg(feature = "orchard")]
+        orchard_outputs: &decrypt_transaction_orchard(&tx, &ufvks),
+    })?;
+
+    Ok(tx.txid())
+}
+
 #[allow(clippy::needless_doctest_main)]
 /// Creates a transaction paying the specified address from the given account.
 ///
@@ -206,7 +270,7 @@ pub fn create_spend_to_address<DbT, ParamsT>(
     min_confirmations: NonZeroU32,
     change_memo: Option<MemoBytes>,
 ) -> Result<
-    TxId,
+    NonEmpty<TxId>,
     Error<
         <DbT as WalletRead>::Error,
         <DbT as WalletCommitmentTrees>::Error,
@@ -230,6 +294,9 @@ where
         params,
         StandardFeeRule::PreZip313,
         account,
+        // The greedy input selector never spends transparent funds, so this policy permits
+        // everything that the selector is able to propose.
+        PrivacyPolicy::AllowRevealedRecipients,
         min_confirmations,
         to,
         amount,
@@ -237,7 +304,7 @@ where
         change_memo,
     )?;
 
-    create_proposed_transaction(
+    create_proposed_transactions(
         wallet_db,
         params,
         spend_prover,
@@ -293,6 +360,8 @@ where
 ///   USK does not correspond to an account known to the wallet.
 /// * `request`: The ZIP-321 payment request specifying the recipients and amounts
 ///   for the transaction.
+/// * `privacy_policy`: The policy that constrains the information that the resulting
+///   transaction may reveal on chain.
 /// * `ovk_policy`: The policy to use for constructing outgoing viewing keys that
 ///   can allow the sender to view the resulting notes on the blockchain.
 /// * `min_confirmations`: The minimum number of confirmations that a previously
@@ -312,10 +381,11 @@ pub fn spend<DbT, ParamsT, InputsT>(
     input_selector: &InputsT,
     usk: &UnifiedSpendingKey,
     request: zip321::TransactionRequest,
+    privacy_policy: PrivacyPolicy,
     ovk_policy: OvkPolicy,
     min_confirmations: NonZeroU32,
 ) -> Result<
-    TxId,
+    NonEmpty<TxId>,
     Error<
         <DbT as WalletRead>::Error,
         <DbT as WalletCommitmentTrees>::Error,
@@ -340,10 +410,11 @@ where
         account,
         input_selector,
         request,
+        privacy_policy,
         min_confirmations,
     )?;
 
-    create_proposed_transaction(
+    create_proposed_transactions(
         wallet_db,
         params,
         spend_prover,
@@ -356,7 +427,11 @@ where
 
 /// Select transaction inputs, compute fees, and construct a proposal for a transaction
 /// that can then be authorized and made ready for submission to the network with
-/// [`create_proposed_transaction`].
+/// [`create_proposed_transactions`].
+///
+/// The proposed transaction will not reveal any information that is forbidden by
+/// `privacy_policy`; if the request cannot be satisfied under that policy,
+/// [`Error::PrivacyPolicyViolation`] is returned.
 #[allow(clippy::too_many_arguments)]
 #[allow(clippy::type_complexity)]
 pub fn propose_transfer<DbT, ParamsT, InputsT, CommitmentTreeErrT>(
@@ -365,6 +440,7 @@ pub fn propose_transfer<DbT, ParamsT, InputsT, CommitmentTreeErrT>(
     spend_from_account: AccountId,
     input_selector: &InputsT,
     request: zip321::TransactionRequest,
+    privacy_policy: PrivacyPolicy,
     min_confirmations: NonZeroU32,
 ) -> Result<
     Proposal<InputsT::FeeRule, <DbT as InputSource>::NoteRef>,
@@ -394,13 +470,71 @@ where
             anchor_height,
             spend_from_account,
             request,
+            privacy_policy,
         )
         .map_err(Error::from)
 }
 
+/// Estimates the total fee that would be paid to satisfy the given transaction request,
+/// including the fee for any change outputs that would be required.
+///
+/// This performs the same input selection as [`propose_transfer`], but requires only read
+/// access to the wallet. No notes are reserved by this operation, so the fee that is
+/// ultimately paid may differ if the wallet's spendable notes change before a transaction is
+/// proposed. If the request would be satisfied by a multi-step proposal, the fees for all
+/// steps are included.
+#[allow(clippy::type_complexity)]
+pub fn estimate_fee<DbT, ParamsT, InputsT, CommitmentTreeErrT>(
+    wallet_db: &DbT,
+    params: &ParamsT,
+    spend_from_account: AccountId,
+    input_selector: &InputsT,
+    request: zip321::TransactionRequest,
+    privacy_policy: PrivacyPolicy,
+    min_confirmations: NonZeroU32,
+) -> Result<
+    NonNegativeAmount,
+    Error<
+        <DbT as WalletRead>::Error,
+        CommitmentTreeErrT,
+        InputsT::Error,
+        <InputsT::FeeRule as FeeRule>::Error,
+    >,
+>
+where
+    DbT: WalletRead + InputSource<Error = <DbT as WalletRead>::Error>,
+    <DbT as InputSource>::NoteRef: Copy + Eq + Ord,
+    ParamsT: consensus::Parameters + Clone,
+    InputsT: InputSelector<InputSource = DbT>,
+{
+    let (target_height, anchor_height) = wallet_db
+        .get_target_and_anchor_heights(min_confirmations)
+        .map_err(|e| Error::from(InputSelectorError::DataSource(e)))?
+        .ok_or_else(|| Error::from(InputSelectorError::SyncRequired))?;
+
+    let proposal = input_selector
+        .propose_transaction(
+            params,
+            wallet_db,
+            target_height,
+            anchor_height,
+            spend_from_account,
+            request,
+            privacy_policy,
+        )
+        .map_err(Error::from)?;
+
+    proposal
+        .steps()
+        .iter()
+        .map(|step| step.balance().fee_required())
+        .sum::<Option<NonNegativeAmount>>()
+        .ok_or_else(|| BalanceError::Overflow.into())
+}
+
 /// Proposes a transaction paying the specified address from the given account.
 ///
-/// Returns the proposal, which may then be executed using [`create_proposed_transaction`]
+/// Returns the proposal, which may then be executed using [`create_proposed_transactions`]
 ///
 /// Parameters:
 /// * `wallet_db`: A read/write reference to the wallet database.
@@ -409,6 +543,8 @@ where
 /// * `spend_from_account`: The unified account that controls the funds that will be spent
 ///   in the resulting transaction. This procedure will return an error if the
 ///   account ID does not correspond to an account known to the wallet.
+/// * `privacy_policy`: The policy constraining the information that the proposed
+///   transaction may reveal.
 /// * `min_confirmations`: The minimum number of confirmations that a previously
 ///   received note must have in the blockchain in order to be considered for being
 ///   spent. A value of 10 confirmations is recommended and 0-conf transactions are
@@ -424,6 +560,7 @@ pub fn propose_standard_transfer_to_address<DbT, ParamsT, CommitmentTreeErrT>(
     params: &ParamsT,
     fee_rule: StandardFeeRule,
     spend_from_account: AccountId,
+    privacy_policy: PrivacyPolicy,
     min_confirmations: NonZeroU32,
     to: &Address,
     amount: NonNegativeAmount,
@@ -455,7 +592,11 @@ where
         "It should not be possible for this to violate ZIP 321 request construction invariants.",
     );
 
-    let change_strategy = fees::standard::SingleOutputChangeStrategy::new(fee_rule, change_memo);
+    let change_strategy = fees::standard::SingleOutputChangeStrategy::new(
+        fee_rule,
+        change_memo,
+        fees::ChangePoolPolicy::default(),
+    );
     let input_selector =
         GreedyInputSelector::<DbT, _>::new(change_strategy, DustOutputPolicy::default());
 
@@ -465,10 +606,166 @@ where
         spend_from_account,
         &input_selector,
         request,
+        privacy_policy,
         min_confirmations,
     )
 }
 
+/// Proposes a transaction that pays the entire spendable balance of the given account,
+/// less fees, to the specified address.
+///
+/// All of the account's spendable notes are selected, and the fee is computed by the change
+/// strategy of the provided input selector. The resulting transaction produces no change
+/// output. Returns the proposal, which may then be executed using
+/// [`create_proposed_transactions`].
+///
+/// Parameters:
+/// * `wallet_db`: A read/write reference to the wallet database.
+/// * `params`: Consensus parameters.
+/// * `spend_from_account`: The unified account that controls the funds that will be spent
+///   in the resulting transaction. This procedure will return an error if the
+///   account ID does not correspond to an account known to the wallet.
+/// * `input_selector`: The input selector whose change strategy will be used to compute
+///   the fee for the transaction.
+/// * `recipient`: The address to which the account's balance will be paid.
+/// * `memo`: A memo to be included in the output to the recipient. This must be `None`
+///   if `recipient` is a transparent address.
+/// * `privacy_policy`: The policy constraining the information that the proposed
+///   transaction may reveal. Paying a transparent recipient requires a policy that permits
+///   revealed recipients, and if the policy does not permit revealed amounts, only notes
+///   from the recipient's pool are spent.
+/// * `min_confirmations`: The minimum number of confirmations that a previously
+///   received note must have in the blockchain in order to be considered for being
+///   spent. A value of 10 confirmations is recommended and 0-conf transactions are
+///   not supported.
+#[allow(clippy::too_many_arguments)]
+#[allow(clippy::type_complexity)]
+pub fn propose_send_max_transfer<DbT, ParamsT, ChangeT, CommitmentTreeErrT>(
+    wallet_db: &mut DbT,
+    params: &ParamsT,
+    spend_from_account: AccountId,
+    input_selector: &GreedyInputSelector<DbT, ChangeT>,
+    recipient: &Address,
+    memo: Option<MemoBytes>,
+    privacy_policy: PrivacyPolicy,
+    min_confirmations: NonZeroU32,
+) -> Result<
+    Proposal<ChangeT::FeeRule, DbT::NoteRef>,
+    Error<
+        <DbT as WalletRead>::Error,
+        CommitmentTreeErrT,
+        GreedyInputSelectorError<ChangeT::Error, DbT::NoteRef>,
+        <ChangeT::FeeRule as FeeRule>::Error,
+    >,
+>
+where
+    ParamsT: consensus::Parameters,
+    DbT: WalletRead + InputSource<Error = <DbT as WalletRead>::Error>,
+    ChangeT: ChangeStrategy,
+    ChangeT::FeeRule: Clone,
+{
+    if memo.is_some() && matches!(recipient, Address::Transparent(_)) {
+        return Err(Error::MemoForbidden);
+    }
+
+    let (target_height, anchor_height) = wallet_db
+        .get_target_and_anchor_heights(min_confirmations)
+        .map_err(|e| Error::from(InputSelectorError::DataSource(e)))?
+        .ok_or_else(|| Error::from(InputSelectorError::SyncRequired))?;
+
+    input_selector
+        .propose_send_max(
+            params,
+            wallet_db,
+            target_height,
+            anchor_height,
+            spend_from_account,
+            recipient,
+            memo,
+            privacy_policy,
+        )
+        .map_err(Error::from)
+}
+
+/// Proposes a transaction that merges a number of the given account's smallest spendable
+/// notes in the specified shielded pool into a single note sent back to the account.
+///
+/// Spending each note is a separate logical action under [ZIP 317], so wallets that have
+/// received many small notes will pay increasingly large fees to spend them. Consolidating
+/// such notes ahead of time reduces the fees of subsequent transactions. Notes that are
+/// worth less than the marginal fee of spending them are not selected, and no transaction is
+/// proposed unless the consolidated note would be worth more than the fee paid to create it.
+/// Returns the proposal, which may then be executed using [`create_proposed_transactions`].
+///
+/// Parameters:
+/// * `wallet_db`: A read/write reference to the wallet database.
+/// * `params`: Consensus parameters.
+/// * `spend_from_account`: The unified account that controls the notes to be consolidated.
+///   This procedure will return an error if the account ID does not correspond to an
+///   account known to the wallet.
+/// * `input_selector`: The input selector whose change strategy will be used to compute
+///   the fee for the transaction.
+/// * `max_inputs`: The maximum number of notes to be spent in the resulting transaction.
+/// * `target_pool`: The shielded pool from which notes will be selected, and to which the
+///   consolidated note will be sent.
+/// * `privacy_policy`: The policy constraining the information that the proposed
+///   transaction may reveal.
+/// * `min_confirmations`: The minimum number of confirmations that a previously
+///   received note must have in the blockchain in order to be considered for being
+///   spent. A value of 10 confirmations is recommended and 0-conf transactions are
+///   not supported.
+///
+/// [ZIP 317]: https://zips.z.cash/zip-0317
+#[allow(clippy::too_many_arguments)]
+#[allow(clippy::type_complexity)]
+pub fn propose_consolidation<DbT, ParamsT, ChangeT, CommitmentTreeErrT>(
+    wallet_db: &mut DbT,
+    params: &ParamsT,
+    spend_from_account: AccountId,
+    input_selector: &GreedyInputSelector<DbT, ChangeT>,
+    max_inputs: usize,
+    target_pool: ShieldedProtocol,
+    privacy_policy: PrivacyPolicy,
+    min_confirmations: NonZeroU32,
+) -> Result<
+    Proposal<ChangeT::FeeRule, DbT::NoteRef>,
+    Error<
+        <DbT as WalletRead>::Error,
+        CommitmentTreeErrT,
+        GreedyInputSelectorError<ChangeT::Error, DbT::NoteRef>,
+        <ChangeT::FeeRule as FeeRule>::Error,
+    >,
+>
+where
+    ParamsT: consensus::Parameters,
+    DbT: WalletRead + InputSource<Error = <DbT as WalletRead>::Error>,
+    ChangeT: ChangeStrategy,
+    ChangeT::FeeRule: Clone,
+{
+    #[cfg(not(feature = "orchard"))]
+    if target_pool != ShieldedProtocol::Sapling {
+        return Err(Error::UnsupportedPoolType(PoolType::Shielded(target_pool)));
+    }
+
+    let (target_height, anchor_height) = wallet_db
+        .get_target_and_anchor_heights(min_confirmations)
+        .map_err(|e| Error::from(InputSelectorError::DataSource(e)))?
+        .ok_or_else(|| Error::from(InputSelectorError::SyncRequired))?;
+
+    input_selector
+        .propose_consolidation(
+            params,
+            wallet_db,
+            target_height,
+            anchor_height,
+            spend_from_account,
+            max_inputs,
+            target_pool,
+            privacy_policy,
+        )
+        .map_err(Error::from)
+}
+
 #[cfg(feature = "transparent-inputs")]
 #[allow(clippy::too_many_arguments)]
 #[allow(clippy::type_complexity)]
@@ -510,17 +807,33 @@ where
         .map_err(Error::from)
 }
 
-/// Construct, prove, and sign a transaction using the inputs supplied by the given proposal,
-/// and persist it to the wallet database.
+/// Construct, prove, and sign a transaction or series of transactions using the inputs supplied
+/// by the given proposal, and persist it to the wallet database.
+///
+/// The steps of the proposal are executed in order; a step may spend the outputs of the
+/// transactions created for the steps that precede it. Returns the database identifiers for
+/// the newly constructed transactions, in the order of the steps of the proposal, or an error
+/// if an error occurs in transaction construction, proving, or signing. If an error occurs
+/// part way through the proposal, the transactions created for the preceding steps will
+/// already have been stored to the wallet database.
+///
+/// At present, a step may only spend an output of a prior step if that output is a payment
+/// to a transparent address belonging to the account that owns `usk`; the change outputs of
+/// prior steps cannot be spent until they have been mined. Any other reference to a prior
+/// step output will result in [`Error::ProposalNotSupported`].
 ///
-/// Returns the database identifier for the newly constructed transaction, or an error if
-/// an error occurs in transaction construction, proving, or signing.
+/// If the proposal directs change to the Orchard pool, the change output will be sent to
+/// the internal Orchard address of the account that owns `usk`.
 ///
-/// Note: If the payment includes a recipient with an Orchard-only UA, this will attempt
-/// to fall back to the transparent receiver until full Orchard support is implemented.
+/// Returns [`Error::AccountCannotSpend`] if the account corresponding to `usk` was imported
+/// into the wallet as a view-only account; the wallet does not spend funds from such accounts.
+///
+/// A payment to a unified address is made to its Orchard receiver if it has one and the
+/// `orchard` feature is enabled, and otherwise to its Sapling or, failing that, its
+/// transparent receiver.
 #[allow(clippy::too_many_arguments)]
 #[allow(clippy::type_complexity)]
-pub fn create_proposed_transaction<DbT, ParamsT, InputsErrT, FeeRuleT, N>(
+pub fn create_proposed_transactions<DbT, ParamsT, InputsErrT, FeeRuleT, N>(
     wallet_db: &mut DbT,
     params: &ParamsT,
     spend_prover: &impl SpendProver,
@@ -529,7 +842,7 @@ pub fn create_proposed_transaction<DbT, ParamsT, InputsErrT, FeeRuleT, N>(
     ovk_policy: OvkPolicy,
     proposal: &Proposal<FeeRuleT, N>,
 ) -> Result<
-    TxId,
+    NonEmpty<TxId>,
     Error<
         <DbT as WalletRead>::Error,
         <DbT as WalletCommitmentTrees>::Error,
@@ -547,18 +860,81 @@ where
         .map_err(Error::DataSource)?
         .ok_or(Error::KeyNotRecognized)?;
 
+    let account_kind = wallet_db
+        .get_account_kind(account)
+        .map_err(Error::DataSource)?
+        .ok_or(Error::AccountNotFound(account))?;
+    if !account_kind.has_spending_key() {
+        return Err(Error::AccountCannotSpend(account));
+    }
+
+    let mut step_results = Vec::with_capacity(proposal.steps().len());
+    for step in proposal.steps().iter() {
+        let step_result = create_proposed_transaction(
+            wallet_db,
+            params,
+            spend_prover,
+            output_prover,
+            usk,
+            account,
+            ovk_policy.clone(),
+            proposal.fee_rule(),
+            proposal.min_target_height(),
+            &step_results,
+            step,
+        )?;
+        step_results.push((step, step_result));
+    }
+
+    Ok(NonEmpty::from_vec(
+        step_results
+            .iter()
+            .map(|(_, r)| r.transaction().txid())
+            .collect(),
+    )
+    .expect("proposal.steps is NonEmpty"))
+}
+
+#[allow(clippy::too_many_arguments)]
+#[allow(clippy::type_complexity)]
+fn create_proposed_transaction<DbT, ParamsT, InputsErrT, FeeRuleT, N>(
+    wallet_db: &mut DbT,
+    params: &ParamsT,
+    spend_prover: &impl SpendProver,
+    output_prover: &impl OutputProver,
+    usk: &UnifiedSpendingKey,
+    account: AccountId,
+    ovk_policy: OvkPolicy,
+    fee_rule: &FeeRuleT,
+    min_target_height: BlockHeight,
+    prior_step_results: &[(&Step<N>, BuildResult)],
+    proposal_step: &Step<N>,
+) -> Result<
+    BuildResult,
+    Error<
+        <DbT as WalletRead>::Error,
+        <DbT as WalletCommitmentTrees>::Error,
+        InputsErrT,
+        FeeRuleT::Error,
+    >,
+>
+where
+    DbT: WalletWrite + WalletCommitmentTrees,
+    ParamsT: consensus::Parameters + Clone,
+    FeeRuleT: FeeRule,
+{
     let dfvk = usk.sapling().to_diversifiable_full_viewing_key();
 
     // Apply the outgoing viewing key policy.
-    let external_ovk = match ovk_policy {
+    let external_ovk = match &ovk_policy {
         OvkPolicy::Sender => Some(dfvk.to_ovk(Scope::External)),
-        OvkPolicy::Custom(ovk) => Some(ovk),
+        OvkPolicy::Custom(ovk) => Some(sapling::keys::OutgoingViewingKey(ovk.0)),
         OvkPolicy::Discard => None,
     };
 
     let internal_ovk = || {
         #[cfg(feature = "transparent-inputs")]
-        return if proposal.is_shielding() {
+        return if proposal_step.is_shielding() {
             Some(OutgoingViewingKey(
                 usk.transparent()
                     .to_account_pubkey()
@@ -573,7 +949,32 @@ where
         Some(dfvk.to_ovk(Scope::Internal))
     };
 
-    let (sapling_anchor, sapling_inputs) = proposal.shielded_inputs().map_or_else(
+    #[cfg(feature = "orchard")]
+    let orchard_fvk = orchard::keys::FullViewingKey::from(usk.orchard());
+
+    #[cfg(feature = "orchard")]
+    let orchard_external_ovk = match &ovk_policy {
+        OvkPolicy::Sender => Some(orchard_fvk.to_ovk(orchard::keys::Scope::External)),
+        OvkPolicy::Custom(ovk) => Some(orchard::keys::OutgoingViewingKey::from(ovk.0)),
+        OvkPolicy::Discard => None,
+    };
+
+    #[cfg(feature = "orchard")]
+    let orchard_internal_ovk = || {
+        #[cfg(feature = "transparent-inputs")]
+        if proposal_step.is_shielding() {
+            return Some(orchard::keys::OutgoingViewingKey::from(
+                usk.transparent()
+                    .to_account_pubkey()
+                    .internal_ovk()
+                    .as_bytes(),
+            ));
+        }
+
+        Some(orchard_fvk.to_ovk(orchard::keys::Scope::Internal))
+    };
+
+    let (sapling_anchor, sapling_inputs) = proposal_step.shielded_inputs().map_or_else(
         || Ok((sapling::Anchor::empty_tree(), vec![])),
         |inputs| {
             wallet_db.with_sapling_tree_mut::<_, _, Error<_, _, _, _>>(|sapling_tree| {
@@ -584,28 +985,25 @@ where
                 let sapling_inputs = inputs
                     .notes()
                     .iter()
-                    .map(|selected| {
-                        match selected.note() {
-                            Note::Sapling(note) => {
-                                let key = match selected.spending_key_scope() {
-                                    Scope::External => usk.sapling().clone(),
-                                    Scope::Internal => usk.sapling().derive_internal(),
-                                };
-
-                                let merkle_path = sapling_tree.witness_at_checkpoint_id_caching(
-                                    selected.note_commitment_tree_position(),
-                                    &inputs.anchor_height(),
-                                )?;
-
-                                Ok((key, note, merkle_path))
-                            }
-                            #[cfg(feature = "orchard")]
-                            Note::Orchard(_) => {
-                                // FIXME: Implement this once `Proposal` has been refactored to
-                                // include Orchard notes.
-                                panic!("Orchard spends are not yet supported");
-                            }
+                    .filter_map(|selected| match selected.note() {
+                        Note::Sapling(note) => {
+                            let key = match selected.spending_key_scope() {
+                                Scope::External => usk.sapling().clone(),
+                                Scope::Internal => usk.sapling().derive_internal(),
+                            };
+
+                            Some(
+                                sapling_tree
+                                    .witness_at_checkpoint_id_caching(
+                                        selected.note_commitment_tree_position(),
+                                        &inputs.anchor_height(),
+                                    )
+                                    .map(|merkle_path| (key, note, merkle_path))
+                                    .map_err(Error::from),
+                            )
                         }
+                        #[cfg(feature = "orchard")]
+                        Note::Orchard(_) => None,
                     })
                     .collect::<Result<Vec<_>, Error<_, _, _, _>>>()?;
 
@@ -614,14 +1012,77 @@ where
         },
     )?;
 
+    #[cfg(feature = "orchard")]
+    let (orchard_anchor, orchard_inputs) = proposal_step.shielded_inputs().map_or_else(
+        || Ok((None, vec![])),
+        |inputs| {
+            let orchard_notes = inputs
+                .notes()
+                .iter()
+                .filter_map(|selected| match selected.note() {
+                    Note::Orchard(note) => Some((selected, *note)),
+                    Note::Sapling(_) => None,
+                })
+                .collect::<Vec<_>>();
+
+            if orchard_notes.is_empty() {
+                return Ok((None, vec![]));
+            }
+
+            wallet_db.with_orchard_tree_mut::<_, _, Error<_, _, _, _>>(|orchard_tree| {
+                let anchor = orchard_tree
+                    .root_at_checkpoint_id(&inputs.anchor_height())?
+                    .into();
+
+                let orchard_inputs = orchard_notes
+                    .iter()
+                    .map(|(selected, note)| {
+                        let merkle_path = orchard_tree.witness_at_checkpoint_id_caching(
+                            selected.note_commitment_tree_position(),
+                            &inputs.anchor_height(),
+                        )?;
+
+                        Ok((*note, orchard::tree::MerklePath::from(merkle_path)))
+                    })
+                    .collect::<Result<Vec<_>, Error<_, _, _, _>>>()?;
+
+                Ok((Some(anchor), orchard_inputs))
+            })
+        },
+    )?;
+
+    // The Orchard builder is also needed if the proposal creates Orchard outputs (change, or
+    // payments to the Orchard receiver of a unified address) without spending any Orchard
+    // notes. Such a bundle contains only outputs (and dummy spends), for which the empty tree
+    // is a valid anchor.
+    #[cfg(feature = "orchard")]
+    let orchard_anchor = orchard_anchor.or_else(|| {
+        let has_orchard_change = proposal_step
+            .balance()
+            .proposed_change()
+            .iter()
+            .any(|change_value| change_value.output_pool() == ShieldedProtocol::Orchard);
+        let has_orchard_payment = proposal_step
+            .transaction_request()
+            .payments()
+            .iter()
+            .any(|payment| {
+                matches!(&payment.recipient_address, Address::Unified(ua) if ua.orchard().is_some())
+            });
+
+        (has_orchard_change || has_orchard_payment).then(orchard::Anchor::empty_tree)
+    });
+    #[cfg(not(feature = "orchard"))]
+    let orchard_anchor = None;
+
     // Create the transaction. The type of the proposal ensures that there
     // are no possible transparent inputs, so we ignore those
     let mut builder = Builder::new(
         params.clone(),
-        proposal.min_target_height(),
+        min_target_height,
         BuildConfig::Standard {
             sapling_anchor: Some(sapling_anchor),
-            orchard_anchor: None,
+            orchard_anchor,
         },
     );
 
@@ -629,19 +1090,79 @@ where
         builder.add_sapling_spend(&key, note.clone(), merkle_path)?;
     }
 
+    #[cfg(feature = "orchard")]
+    for (note, merkle_path) in orchard_inputs.into_iter() {
+        builder.add_orchard_spend(usk.orchard(), note, merkle_path)?;
+    }
+
     #[cfg(feature = "transparent-inputs")]
-    let utxos = {
+    let utxos_spent = {
         let known_addrs = wallet_db
             .get_transparent_receivers(account)
             .map_err(Error::DataSource)?;
 
-        let mut utxos: Vec<WalletTransparentOutput> = vec![];
-        for utxo in proposal.transparent_inputs() {
-            utxos.push(utxo.clone());
+        let mut transparent_inputs: Vec<(TransparentAddress, OutPoint, TxOut)> = proposal_step
+            .transparent_inputs()
+            .iter()
+            .map(|utxo| {
+                (
+                    *utxo.recipient_address(),
+                    utxo.outpoint().clone(),
+                    utxo.txout().clone(),
+                )
+            })
+            .collect();
+
+        for input_ref in proposal_step.prior_step_inputs() {
+            let (prior_step, prior_result) = prior_step_results
+                .get(input_ref.step_index())
+                .ok_or(Error::ProposalNotSupported)?;
+
+            let payment_index = match input_ref.output_index() {
+                StepOutputIndex::Payment(i) => i,
+                // Change outputs are always shielded, and cannot be spent until the
+                // transaction that created them has been mined.
+                StepOutputIndex::Change(_) => return Err(Error::ProposalNotSupported),
+            };
+
+            let payment = prior_step
+                .transaction_request()
+                .payments()
+                .get(payment_index)
+                .ok_or(Error::ProposalNotSupported)?;
+            let addr = match &payment.recipient_address {
+                Address::Transparent(addr) => *addr,
+                _ => return Err(Error::ProposalNotSupported),
+            };
+
+            let script = addr.script();
+            let (output_index, txout) = prior_result
+                .transaction()
+                .transparent_bundle()
+                .and_then(|b| {
+                    b.vout.iter().enumerate().find(|(_, tx_out)| {
+                        tx_out.script_pubkey == script && tx_out.value == payment.amount
+                    })
+                })
+                .expect("An output should exist in the transaction for each transparent payment.");
+
+            transparent_inputs.push((
+                addr,
+                OutPoint::new(
+                    prior_result.transaction().txid().into(),
+                    u32::try_from(output_index).expect("output indices are representable as u32"),
+                ),
+                txout.clone(),
+            ));
+        }
+
+        let mut utxos_spent: Vec<OutPoint> = vec![];
+        for (addr, outpoint, txout) in transparent_inputs {
+            utxos_spent.push(outpoint.clone());
 
             let diversifier_index = known_addrs
-                .get(utxo.recipient_address())
-                .ok_or_else(|| Error::AddressNotRecognized(*utxo.recipient_address()))?
+                .get(&addr)
+                .ok_or(Error::AddressNotRecognized(addr))?
                 .diversifier_index();
 
             let child_index = u32::try_from(*diversifier_index)
@@ -652,18 +1173,23 @@ where
                 .derive_external_secret_key(child_index)
                 .unwrap();
 
-            builder.add_transparent_input(
-                secret_key,
-                utxo.outpoint().clone(),
-                utxo.txout().clone(),
-            )?;
+            builder.add_transparent_input(secret_key, outpoint, txout)?;
         }
-        utxos
+        utxos_spent
     };
 
+    #[cfg(not(feature = "transparent-inputs"))]
+    if !proposal_step.prior_step_inputs().is_empty() {
+        // Outputs of prior steps can only be spent as transparent inputs.
+        let _ = prior_step_results;
+        return Err(Error::ProposalNotSupported);
+    }
+
     let mut sapling_output_meta = vec![];
+    #[cfg(feature = "orchard")]
+    let mut orchard_output_meta = vec![];
     let mut transparent_output_meta = vec![];
-    for payment in proposal.transaction_request().payments() {
+    for payment in proposal_step.transaction_request().payments() {
         match &payment.recipient_address {
             Address::Unified(ua) => {
                 let memo = payment
@@ -671,6 +1197,27 @@ where
                     .as_ref()
                     .map_or_else(MemoBytes::empty, |m| m.clone());
 
+                // Payments are made to the most preferred receiver of the unified address that
+                // the wallet supports, as the input selector assumes when computing the fee.
+                #[cfg(feature = "orchard")]
+                if let Some(orchard_receiver) = ua.orchard() {
+                    builder.add_orchard_output(
+                        orchard_external_ovk.clone(),
+                        *orchard_receiver,
+                        payment.amount.into(),
+                        memo.clone(),
+                    )?;
+                    orchard_output_meta.push((
+                        Recipient::Unified(
+                            ua.clone(),
+                            PoolType::Shielded(ShieldedProtocol::Orchard),
+                        ),
+                        payment.amount,
+                        Some(memo),
+                    ));
+                    continue;
+                }
+
                 if let Some(sapling_receiver) = ua.sapling() {
                     builder.add_sapling_output(
                         external_ovk,
@@ -692,6 +1239,7 @@ where
                     } else {
                         builder.add_transparent_output(taddr, payment.amount)?;
                     }
+                    transparent_output_meta.push((*taddr, payment.amount));
                 } else {
                     return Err(Error::NoSupportedReceivers(
                         ua.unknown().iter().map(|(tc, _)| *tc).collect(),
@@ -717,7 +1265,7 @@ where
         }
     }
 
-    for change_value in proposal.balance().proposed_change() {
+    for change_value in proposal_step.balance().proposed_change() {
         let memo = change_value
             .memo()
             .map_or_else(MemoBytes::empty, |m| m.clone());
@@ -746,13 +1294,28 @@ where
                 )));
 
                 #[cfg(feature = "orchard")]
-                unimplemented!("FIXME: implement Orchard change output creation.")
+                {
+                    builder.add_orchard_output(
+                        orchard_internal_ovk(),
+                        orchard_fvk.address_at(0u32, orchard::keys::Scope::Internal),
+                        change_value.value().into(),
+                        memo.clone(),
+                    )?;
+                    orchard_output_meta.push((
+                        Recipient::InternalAccount(
+                            account,
+                            PoolType::Shielded(ShieldedProtocol::Orchard),
+                        ),
+                        change_value.value(),
+                        Some(memo),
+                    ))
+                }
             }
         }
     }
 
     // Build the transaction with the specified fee rule
-    let build_result = builder.build(OsRng, spend_prover, output_prover, proposal.fee_rule())?;
+    let build_result = builder.build(OsRng, spend_prover, output_prover, fee_rule)?;
 
     let internal_ivk = PreparedIncomingViewingKey::new(&dfvk.to_ivk(Scope::Internal));
     let sapling_outputs =
@@ -777,12 +1340,42 @@ where
                             try_sapling_note_decryption(
                                 &internal_ivk,
                                 &bundle.shielded_outputs()[output_index],
-                                consensus::sapling_zip212_enforcement(
-                                    params,
-                                    proposal.min_target_height(),
-                                ),
+                                consensus::sapling_zip212_enforcement(params, min_target_height),
                             )
-                            .map(|(note, _, _)| (account, note))
+                            .map(|(note, _, _)| (account, Note::Sapling(note)))
+                        })
+                } else {
+                    None
+                };
+
+                SentTransactionOutput::from_parts(output_index, recipient, value, memo, received_as)
+            });
+
+    #[cfg(feature = "orchard")]
+    let orchard_internal_ivk = orchard_fvk.to_ivk(orchard::keys::Scope::Internal);
+    #[cfg(feature = "orchard")]
+    let orchard_outputs =
+        orchard_output_meta
+            .into_iter()
+            .enumerate()
+            .map(|(i, (recipient, value, memo))| {
+                let output_index = build_result
+                    .orchard_meta()
+                    .output_action_index(i)
+                    .expect("An action should exist in the transaction for each Orchard output.");
+
+                let received_as = if let Recipient::InternalAccount(
+                    account,
+                    PoolType::Shielded(ShieldedProtocol::Orchard),
+                ) = recipient
+                {
+                    build_result
+                        .transaction()
+                        .orchard_bundle()
+                        .and_then(|bundle| {
+                            bundle
+                                .decrypt_output_with_key(output_index, &orchard_internal_ivk)
+                                .map(|(note, _, _)| (account, Note::Orchard(note)))
                         })
                 } else {
                     None
@@ -790,6 +1383,8 @@ where
 
                 SentTransactionOutput::from_parts(output_index, recipient, value, memo, received_as)
             });
+    #[cfg(not(feature = "orchard"))]
+    let orchard_outputs = std::iter::empty();
 
     let transparent_outputs = transparent_output_meta.into_iter().map(|(addr, value)| {
         let script = addr.script();
@@ -819,14 +1414,17 @@ where
             tx: build_result.transaction(),
             created: time::OffsetDateTime::now_utc(),
             account,
-            outputs: sapling_outputs.chain(transparent_outputs).collect(),
-            fee_amount: Amount::from(proposal.balance().fee_required()),
+            outputs: sapling_outputs
+                .chain(orchard_outputs)
+                .chain(transparent_outputs)
+                .collect(),
+            fee_amount: Amount::from(proposal_step.balance().fee_required()),
             #[cfg(feature = "transparent-inputs")]
-            utxos_spent: utxos.iter().map(|utxo| utxo.outpoint().clone()).collect(),
+            utxos_spent,
         })
         .map_err(Error::DataSource)?;
 
-    Ok(build_result.transaction().txid())
+    Ok(build_result)
 }
 
 /// Constructs a transaction that consumes available transparent UTXOs belonging to
@@ -876,7 +1474,7 @@ pub fn shield_transparent_funds<DbT, ParamsT, InputsT>(
     from_addrs: &[TransparentAddress],
     min_confirmations: u32,
 ) -> Result<
-    TxId,
+    NonEmpty<TxId>,
     Error<
         <DbT as WalletRead>::Error,
         <DbT as WalletCommitmentTrees>::Error,
@@ -898,7 +1496,7 @@ where
         min_confirmations,
     )?;
 
-    create_proposed_transaction(
+    create_proposed_transactions(
         wallet_db,
         params,
         spend_prover,
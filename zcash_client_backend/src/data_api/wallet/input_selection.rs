@@ -1,18 +1,21 @@
 //! Types related to the process of selecting inputs to be spent given a transaction request.
 
 use core::marker::PhantomData;
+use std::collections::BTreeSet;
 use std::fmt::{self, Debug, Display};
 
 use nonempty::NonEmpty;
 use zcash_primitives::{
     consensus::{self, BlockHeight},
     legacy::TransparentAddress,
+    memo::MemoBytes,
     transaction::{
         components::{
-            amount::{BalanceError, NonNegativeAmount},
+            amount::{Amount, BalanceError, NonNegativeAmount, MAX_MONEY},
             TxOut,
         },
         fees::FeeRule,
+        TxId,
     },
     zip32::AccountId,
 };
@@ -22,15 +25,15 @@ use crate::{
     data_api::InputSource,
     fees::{sapling, ChangeError, ChangeStrategy, DustOutputPolicy, TransactionBalance},
     wallet::{Note, ReceivedNote, WalletTransparentOutput},
-    zip321::TransactionRequest,
-    ShieldedProtocol,
+    zip321::{Payment, TransactionRequest},
+    PoolType, ShieldedProtocol,
 };
 
 #[cfg(any(feature = "transparent-inputs"))]
 use std::convert::Infallible;
 
 #[cfg(feature = "transparent-inputs")]
-use {std::collections::BTreeSet, zcash_primitives::transaction::components::OutPoint};
+use zcash_primitives::transaction::components::OutPoint;
 
 #[cfg(feature = "orchard")]
 use crate::fees::orchard as orchard_fees;
@@ -50,6 +53,12 @@ pub enum InputSelectorError<DbErrT, SelectorErrT> {
     /// The data source does not have enough information to choose an expiry height
     /// for the transaction.
     SyncRequired,
+    /// The transaction request could only be satisfied by a transaction that reveals
+    /// information not permitted by the caller's privacy policy.
+    PrivacyPolicyViolation {
+        policy: PrivacyPolicy,
+        violation: PrivacyViolation,
+    },
 }
 
 impl<DE: fmt::Display, SE: fmt::Display> fmt::Display for InputSelectorError<DE, SE> {
@@ -77,23 +86,88 @@ impl<DE: fmt::Display, SE: fmt::Display> fmt::Display for InputSelectorError<DE,
             InputSelectorError::SyncRequired => {
                 write!(f, "Insufficient chain data is available, sync required.")
             }
+            InputSelectorError::PrivacyPolicyViolation { policy, violation } => write!(
+                f,
+                "The transaction would violate the {:?} privacy policy: {}",
+                policy, violation
+            ),
+        }
+    }
+}
+
+/// A policy that constrains the information that a transaction proposed by an
+/// [`InputSelector`] may reveal on chain.
+///
+/// These policies correspond to those accepted by the `z_sendmany` RPC method of `zcashd`.
+/// Each policy permits everything that is permitted by the policies it is documented to imply.
+/// [`InputSelector::propose_transaction`] never spends transparent funds, so the `zcashd`
+/// policies that permit doing so are not supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrivacyPolicy {
+    /// Only fully-shielded transactions in which no value crosses between shielded pools
+    /// are permitted.
+    FullPrivacy,
+    /// Value may cross between shielded pools, revealing the amount that crosses the pool
+    /// boundary.
+    AllowRevealedAmounts,
+    /// Transparent recipients are permitted. This implies [`PrivacyPolicy::AllowRevealedAmounts`].
+    AllowRevealedRecipients,
+}
+
+impl PrivacyPolicy {
+    /// Returns whether this policy permits value to cross between pools.
+    pub fn allows_revealed_amounts(&self) -> bool {
+        !matches!(self, PrivacyPolicy::FullPrivacy)
+    }
+
+    /// Returns whether this policy permits transparent recipients.
+    pub fn allows_revealed_recipients(&self) -> bool {
+        matches!(self, PrivacyPolicy::AllowRevealedRecipients)
+    }
+
+    /// Returns whether this policy permits the given disclosure.
+    pub fn permits(&self, violation: PrivacyViolation) -> bool {
+        match violation {
+            PrivacyViolation::RevealedAmounts => self.allows_revealed_amounts(),
+            PrivacyViolation::RevealedRecipients => self.allows_revealed_recipients(),
         }
     }
 }
 
-/// The inputs to be consumed and outputs to be produced in a proposed transaction.
+/// A disclosure of transaction information that a [`PrivacyPolicy`] may forbid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrivacyViolation {
+    /// Value would cross between pools, revealing the amount that crosses the pool boundary.
+    RevealedAmounts,
+    /// The transaction would have one or more transparent recipients.
+    RevealedRecipients,
+}
+
+impl fmt::Display for PrivacyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PrivacyViolation::RevealedAmounts => {
+                write!(f, "value would cross between pools, revealing amounts")
+            }
+            PrivacyViolation::RevealedRecipients => {
+                write!(f, "payments to transparent recipients would be revealed")
+            }
+        }
+    }
+}
+/// A proposal for a series of transactions to be created.
+///
+/// Each step of the proposal represents a separate transaction to be created. At present, only
+/// transparent outputs of earlier steps may be spent in later steps; the ability to chain shielded
+/// transaction steps may be added in a future update.
 #[derive(Clone, PartialEq, Eq)]
 pub struct Proposal<FeeRuleT, NoteRef> {
-    transaction_request: TransactionRequest,
-    transparent_inputs: Vec<WalletTransparentOutput>,
-    shielded_inputs: Option<ShieldedInputs<NoteRef>>,
-    balance: TransactionBalance,
     fee_rule: FeeRuleT,
     min_target_height: BlockHeight,
-    is_shielding: bool,
+    steps: NonEmpty<Step<NoteRef>>,
 }
 
-/// Errors that can occur in construction of a [`Proposal`].
+/// Errors that can occur in construction of a [`Proposal`] or a [`Step`].
 #[derive(Debug, Clone)]
 pub enum ProposalError {
     /// The total output value of the transaction request is not a valid Zcash amount.
@@ -113,6 +187,12 @@ pub enum ProposalError {
     /// * There provided transaction request is empty; i.e. the only output values specified
     ///   are change and fee amounts.
     ShieldingInvalid,
+    /// A reference to the output of a prior step is invalid.
+    ReferenceError(StepOutput),
+    /// An attempted double-spend of a prior step output was detected.
+    StepDoubleSpend(StepOutput),
+    /// An attempted double-spend of an output belonging to the wallet was detected.
+    ChainDoubleSpend(PoolType, TxId, u32),
 }
 
 impl Display for ProposalError {
@@ -139,6 +219,19 @@ impl Display for ProposalError {
                 f,
                 "The proposal violates the rules for a shielding transaction."
             ),
+            ProposalError::ReferenceError(r) => {
+                write!(f, "No prior step output found for reference {:?}", r)
+            }
+            ProposalError::StepDoubleSpend(r) => write!(
+                f,
+                "The proposal uses the output of step {:?} in more than one place.",
+                r
+            ),
+            ProposalError::ChainDoubleSpend(pool, txid, index) => write!(
+                f,
+                "The proposal attempts to spend the same output twice: {}, {}, {}",
+                pool, txid, index
+            ),
         }
     }
 }
@@ -146,12 +239,81 @@ impl Display for ProposalError {
 impl std::error::Error for ProposalError {}
 
 impl<FeeRuleT, NoteRef> Proposal<FeeRuleT, NoteRef> {
-    /// Constructs a validated [`Proposal`] from its constituent parts.
+    /// Constructs a validated multi-step [`Proposal`].
+    ///
+    /// This operation validates that each step only spends outputs of steps that precede
+    /// it, and that no output (whether of a prior step or one already belonging to the
+    /// wallet) is spent more than once across all of the steps of the proposal.
+    pub fn multi_step(
+        fee_rule: FeeRuleT,
+        min_target_height: BlockHeight,
+        steps: NonEmpty<Step<NoteRef>>,
+    ) -> Result<Self, ProposalError> {
+        let mut consumed_chain_inputs: BTreeSet<(PoolType, TxId, u32)> = BTreeSet::new();
+        let mut consumed_prior_inputs: BTreeSet<StepOutput> = BTreeSet::new();
+
+        for (i, step) in steps.iter().enumerate() {
+            for prior_ref in step.prior_step_inputs() {
+                // check that there are no forward references
+                if prior_ref.step_index() >= i {
+                    return Err(ProposalError::ReferenceError(*prior_ref));
+                }
+                // check that the reference is valid
+                let prior_step = &steps[prior_ref.step_index()];
+                match prior_ref.output_index() {
+                    StepOutputIndex::Payment(idx) => {
+                        if prior_step.transaction_request().payments().len() <= idx {
+                            return Err(ProposalError::ReferenceError(*prior_ref));
+                        }
+                    }
+                    StepOutputIndex::Change(idx) => {
+                        if prior_step.balance().proposed_change().len() <= idx {
+                            return Err(ProposalError::ReferenceError(*prior_ref));
+                        }
+                    }
+                }
+                // check that there are no double-spends
+                if !consumed_prior_inputs.insert(*prior_ref) {
+                    return Err(ProposalError::StepDoubleSpend(*prior_ref));
+                }
+            }
+
+            for t_out in step.transparent_inputs() {
+                let key = (
+                    PoolType::Transparent,
+                    TxId::from_bytes(*t_out.outpoint().hash()),
+                    t_out.outpoint().n(),
+                );
+                if !consumed_chain_inputs.insert(key) {
+                    return Err(ProposalError::ChainDoubleSpend(key.0, key.1, key.2));
+                }
+            }
+
+            for s_out in step.shielded_inputs().iter().flat_map(|i| i.notes().iter()) {
+                let key = (
+                    PoolType::Shielded(s_out.note().protocol()),
+                    *s_out.txid(),
+                    s_out.output_index().into(),
+                );
+                if !consumed_chain_inputs.insert(key) {
+                    return Err(ProposalError::ChainDoubleSpend(key.0, key.1, key.2));
+                }
+            }
+        }
+
+        Ok(Self {
+            fee_rule,
+            min_target_height,
+            steps,
+        })
+    }
+
+    /// Constructs a validated [`Proposal`] having only a single step from its constituent parts.
     ///
     /// This operation validates the proposal for balance consistency and agreement between
     /// the `is_shielding` flag and the structure of the proposal.
     #[allow(clippy::too_many_arguments)]
-    pub fn from_parts(
+    pub fn single_step(
         transaction_request: TransactionRequest,
         transparent_inputs: Vec<WalletTransparentOutput>,
         shielded_inputs: Option<ShieldedInputs<NoteRef>>,
@@ -159,6 +321,115 @@ impl<FeeRuleT, NoteRef> Proposal<FeeRuleT, NoteRef> {
         fee_rule: FeeRuleT,
         min_target_height: BlockHeight,
         is_shielding: bool,
+    ) -> Result<Self, ProposalError> {
+        Ok(Self {
+            fee_rule,
+            min_target_height,
+            steps: NonEmpty::singleton(Step::from_parts(
+                &[],
+                transaction_request,
+                transparent_inputs,
+                shielded_inputs,
+                vec![],
+                balance,
+                is_shielding,
+            )?),
+        })
+    }
+
+    /// Returns the fee rule to be used by the transaction builder.
+    pub fn fee_rule(&self) -> &FeeRuleT {
+        &self.fee_rule
+    }
+
+    /// Returns the target height for which the proposal was prepared.
+    ///
+    /// The chain must contain at least this many blocks in order for the proposal to
+    /// be executed.
+    pub fn min_target_height(&self) -> BlockHeight {
+        self.min_target_height
+    }
+
+    /// Returns the steps of the proposal. Each step corresponds to an independent
+    /// transaction to be generated as a result of this proposal.
+    pub fn steps(&self) -> &NonEmpty<Step<NoteRef>> {
+        &self.steps
+    }
+}
+
+impl<FeeRuleT, NoteRef> Debug for Proposal<FeeRuleT, NoteRef> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Proposal")
+            .field("steps", &self.steps)
+            //.field("fee_rule", &self.fee_rule)
+            .field("min_target_height", &self.min_target_height)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A reference to either a payment or change output within a step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StepOutputIndex {
+    Payment(usize),
+    Change(usize),
+}
+
+/// A reference to the output of a step in a proposal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StepOutput {
+    step_index: usize,
+    output_index: StepOutputIndex,
+}
+
+impl StepOutput {
+    /// Constructs a new [`StepOutput`] from its constituent parts.
+    pub fn new(step_index: usize, output_index: StepOutputIndex) -> Self {
+        Self {
+            step_index,
+            output_index,
+        }
+    }
+
+    /// Returns the step index to which this reference refers.
+    pub fn step_index(&self) -> usize {
+        self.step_index
+    }
+
+    /// Returns the identifier for the payment or change output within
+    /// the referenced step.
+    pub fn output_index(&self) -> StepOutputIndex {
+        self.output_index
+    }
+}
+
+/// The inputs to be consumed and outputs to be produced in a single transaction within a
+/// [`Proposal`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct Step<NoteRef> {
+    transaction_request: TransactionRequest,
+    transparent_inputs: Vec<WalletTransparentOutput>,
+    shielded_inputs: Option<ShieldedInputs<NoteRef>>,
+    prior_step_inputs: Vec<StepOutput>,
+    balance: TransactionBalance,
+    is_shielding: bool,
+}
+
+impl<NoteRef> Step<NoteRef> {
+    /// Constructs a validated [`Step`] from its constituent parts.
+    ///
+    /// This operation validates the step for balance consistency and agreement between
+    /// the `is_shielding` flag and the structure of the step. The value of each output of
+    /// a prior step that is referenced by `prior_step_inputs` is included in the input
+    /// total of this step.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_parts(
+        prior_steps: &[Step<NoteRef>],
+        transaction_request: TransactionRequest,
+        transparent_inputs: Vec<WalletTransparentOutput>,
+        shielded_inputs: Option<ShieldedInputs<NoteRef>>,
+        prior_step_inputs: Vec<StepOutput>,
+        balance: TransactionBalance,
+        is_shielding: bool,
     ) -> Result<Self, ProposalError> {
         let transparent_input_total = transparent_inputs
             .iter()
@@ -172,8 +443,29 @@ impl<FeeRuleT, NoteRef> Proposal<FeeRuleT, NoteRef> {
             .map(|out| out.note().value())
             .fold(Some(NonNegativeAmount::ZERO), |acc, a| (acc? + a))
             .ok_or(ProposalError::Overflow)?;
-        let input_total =
-            (transparent_input_total + shielded_input_total).ok_or(ProposalError::Overflow)?;
+        let prior_step_input_total = prior_step_inputs
+            .iter()
+            .map(|s_ref| {
+                let step = prior_steps
+                    .get(s_ref.step_index())
+                    .ok_or(ProposalError::ReferenceError(*s_ref))?;
+                match s_ref.output_index() {
+                    StepOutputIndex::Payment(i) => step
+                        .transaction_request()
+                        .payments()
+                        .get(i)
+                        .map(|p| p.amount),
+                    StepOutputIndex::Change(i) => {
+                        step.balance().proposed_change().get(i).map(|c| c.value())
+                    }
+                }
+                .ok_or(ProposalError::ReferenceError(*s_ref))
+            })
+            .fold(Ok(NonNegativeAmount::ZERO), |acc, a| {
+                (acc? + a?).ok_or(ProposalError::Overflow)
+            })?;
+        let input_total = (transparent_input_total + shielded_input_total + prior_step_input_total)
+            .ok_or(ProposalError::Overflow)?;
 
         let request_total = transaction_request
             .total()
@@ -193,9 +485,8 @@ impl<FeeRuleT, NoteRef> Proposal<FeeRuleT, NoteRef> {
                 transaction_request,
                 transparent_inputs,
                 shielded_inputs,
+                prior_step_inputs,
                 balance,
-                fee_rule,
-                min_target_height,
                 is_shielding,
             })
         } else {
@@ -218,21 +509,15 @@ impl<FeeRuleT, NoteRef> Proposal<FeeRuleT, NoteRef> {
     pub fn shielded_inputs(&self) -> Option<&ShieldedInputs<NoteRef>> {
         self.shielded_inputs.as_ref()
     }
+    /// Returns the inputs that should be obtained from the outputs of the transaction
+    /// created to satisfy a previous step of the proposal.
+    pub fn prior_step_inputs(&self) -> &[StepOutput] {
+        self.prior_step_inputs.as_ref()
+    }
     /// Returns the change outputs to be added to the transaction and the fee to be paid.
     pub fn balance(&self) -> &TransactionBalance {
         &self.balance
     }
-    /// Returns the fee rule to be used by the transaction builder.
-    pub fn fee_rule(&self) -> &FeeRuleT {
-        &self.fee_rule
-    }
-    /// Returns the target height for which the proposal was prepared.
-    ///
-    /// The chain must contain at least this many blocks in order for the proposal to
-    /// be executed.
-    pub fn min_target_height(&self) -> BlockHeight {
-        self.min_target_height
-    }
     /// Returns a flag indicating whether or not the proposed transaction
     /// is exclusively wallet-internal (if it does not involve any external
     /// recipients).
@@ -241,22 +526,21 @@ impl<FeeRuleT, NoteRef> Proposal<FeeRuleT, NoteRef> {
     }
 }
 
-impl<FeeRuleT, NoteRef> Debug for Proposal<FeeRuleT, NoteRef> {
+impl<NoteRef> Debug for Step<NoteRef> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("Proposal")
+        f.debug_struct("Step")
             .field("transaction_request", &self.transaction_request)
             .field("transparent_inputs", &self.transparent_inputs)
             .field(
                 "shielded_inputs",
                 &self.shielded_inputs().map(|i| i.notes.len()),
             )
+            .field("prior_step_inputs", &self.prior_step_inputs)
             .field(
                 "anchor_height",
                 &self.shielded_inputs().map(|i| i.anchor_height),
             )
             .field("balance", &self.balance)
-            //.field("fee_rule", &self.fee_rule)
-            .field("min_target_height", &self.min_target_height)
             .field("is_shielding", &self.is_shielding)
             .finish_non_exhaustive()
     }
@@ -324,7 +608,12 @@ pub trait InputSelector {
     ///
     /// If insufficient funds are available to satisfy the required outputs for the shielding
     /// request, this operation must fail and return [`InputSelectorError::InsufficientFunds`].
+    ///
+    /// Implementations must not propose a transaction that reveals information forbidden by
+    /// `privacy_policy`. If the request cannot be satisfied without doing so, this operation
+    /// must fail and return [`InputSelectorError::PrivacyPolicyViolation`].
     #[allow(clippy::type_complexity)]
+    #[allow(clippy::too_many_arguments)]
     fn propose_transaction<ParamsT>(
         &self,
         params: &ParamsT,
@@ -333,6 +622,7 @@ pub trait InputSelector {
         anchor_height: BlockHeight,
         account: AccountId,
         transaction_request: TransactionRequest,
+        privacy_policy: PrivacyPolicy,
     ) -> Result<
         Proposal<Self::FeeRule, <Self::InputSource as InputSource>::NoteRef>,
         InputSelectorError<<Self::InputSource as InputSource>::Error, Self::Error>,
@@ -391,6 +681,10 @@ pub enum GreedyInputSelectorError<ChangeStrategyErrT, NoteRefT> {
     UnsupportedAddress(Box<UnifiedAddress>),
     /// An error was encountered in change selection.
     Change(ChangeError<ChangeStrategyErrT, NoteRefT>),
+    /// Consolidating notes would not be worthwhile: either fewer than two spendable notes
+    /// are worth more than the marginal fee of spending them, or the fee for consolidating
+    /// the selected notes is at least as large as the value of the consolidated note.
+    NothingToConsolidate,
 }
 
 impl<CE: fmt::Display, N: fmt::Display> fmt::Display for GreedyInputSelectorError<CE, N> {
@@ -409,6 +703,10 @@ impl<CE: fmt::Display, N: fmt::Display> fmt::Display for GreedyInputSelectorErro
             GreedyInputSelectorError::Change(err) => {
                 write!(f, "An error occurred computing change and fees: {}", err)
             }
+            GreedyInputSelectorError::NothingToConsolidate => write!(
+                f,
+                "There are not enough notes worth more than the fee to spend them to justify consolidating them."
+            ),
         }
     }
 }
@@ -438,6 +736,38 @@ impl<DbErrT, ChangeStrategyErrT, NoteRefT> From<BalanceError>
     }
 }
 
+/// Adds the shielded notes identified by a [`ChangeError::DustInputs`] error to `exclude`,
+/// so that they are not selected again.
+///
+/// Returns `false` if no note was newly excluded, in which case selecting notes again would
+/// produce the same result, and the error must instead be returned to the caller.
+fn exclude_dust_notes<E, NoteRef: Clone + PartialEq>(
+    exclude: &mut Vec<NoteRef>,
+    err: &ChangeError<E, NoteRef>,
+) -> bool {
+    let mut excluded = false;
+    if let ChangeError::DustInputs {
+        sapling,
+        #[cfg(feature = "orchard")]
+        orchard,
+        ..
+    } = err
+    {
+        #[cfg(feature = "orchard")]
+        let dust_notes = sapling.iter().chain(orchard.iter());
+        #[cfg(not(feature = "orchard"))]
+        let dust_notes = sapling.iter();
+
+        for note_ref in dust_notes {
+            if !exclude.contains(note_ref) {
+                exclude.push(note_ref.clone());
+                excluded = true;
+            }
+        }
+    }
+    excluded
+}
+
 pub(crate) struct SaplingPayment(NonNegativeAmount);
 
 #[cfg(test)]
@@ -456,13 +786,12 @@ impl sapling::OutputView for SaplingPayment {
 #[cfg(feature = "orchard")]
 pub(crate) struct OrchardPayment(NonNegativeAmount);
 
-// TODO: introduce this method when it is needed for testing.
-// #[cfg(test)]
-// impl OrchardPayment {
-//     pub(crate) fn new(amount: NonNegativeAmount) -> Self {
-//         OrchardPayment(amount)
-//     }
-// }
+#[cfg(all(test, feature = "orchard"))]
+impl OrchardPayment {
+    pub(crate) fn new(amount: NonNegativeAmount) -> Self {
+        OrchardPayment(amount)
+    }
+}
 
 #[cfg(feature = "orchard")]
 impl orchard_fees::OutputView for OrchardPayment {
@@ -471,6 +800,16 @@ impl orchard_fees::OutputView for OrchardPayment {
     }
 }
 
+/// The outputs of a transaction request, grouped by the pool to which they will be sent.
+struct PaymentOutputs {
+    transparent: Vec<TxOut>,
+    sapling: Vec<SaplingPayment>,
+    #[cfg(feature = "orchard")]
+    orchard: Vec<OrchardPayment>,
+    output_pools: BTreeSet<ShieldedProtocol>,
+    selectable_pools: Vec<ShieldedProtocol>,
+}
+
 /// An [`InputSelector`] implementation that uses a greedy strategy to select between available
 /// notes.
 ///
@@ -513,6 +852,7 @@ where
         anchor_height: BlockHeight,
         account: AccountId,
         transaction_request: TransactionRequest,
+        privacy_policy: PrivacyPolicy,
     ) -> Result<
         Proposal<Self::FeeRule, DbT::NoteRef>,
         InputSelectorError<<DbT as InputSource>::Error, Self::Error>,
@@ -521,53 +861,7 @@ where
         ParamsT: consensus::Parameters,
         Self::InputSource: InputSource,
     {
-        let mut transparent_outputs = vec![];
-        let mut sapling_outputs = vec![];
-        #[cfg(feature = "orchard")]
-        let mut orchard_outputs = vec![];
-        for payment in transaction_request.payments() {
-            let mut push_transparent = |taddr: TransparentAddress| {
-                transparent_outputs.push(TxOut {
-                    value: payment.amount,
-                    script_pubkey: taddr.script(),
-                });
-            };
-            let mut push_sapling = || {
-                sapling_outputs.push(SaplingPayment(payment.amount));
-            };
-            #[cfg(feature = "orchard")]
-            let mut push_orchard = || {
-                orchard_outputs.push(OrchardPayment(payment.amount));
-            };
-
-            match &payment.recipient_address {
-                Address::Transparent(addr) => {
-                    push_transparent(*addr);
-                }
-                Address::Sapling(_) => {
-                    push_sapling();
-                }
-                Address::Unified(addr) => {
-                    #[cfg(feature = "orchard")]
-                    let has_orchard = addr.orchard().is_some();
-                    #[cfg(not(feature = "orchard"))]
-                    let has_orchard = false;
-
-                    if has_orchard {
-                        #[cfg(feature = "orchard")]
-                        push_orchard();
-                    } else if addr.sapling().is_some() {
-                        push_sapling();
-                    } else if let Some(addr) = addr.transparent() {
-                        push_transparent(*addr);
-                    } else {
-                        return Err(InputSelectorError::Selection(
-                            GreedyInputSelectorError::UnsupportedAddress(Box::new(addr.clone())),
-                        ));
-                    }
-                }
-            }
-        }
+        let outputs = Self::payment_outputs(&transaction_request, privacy_policy)?;
 
         let mut shielded_inputs: Vec<ReceivedNote<DbT::NoteRef, Note>> = vec![];
         let mut prior_available = NonNegativeAmount::ZERO;
@@ -577,61 +871,30 @@ where
         // of funds selected is strictly increasing. The loop will either return a successful
         // result or the wallet will eventually run out of funds to select.
         loop {
-            let balance = self.change_strategy.compute_balance(
-                params,
-                target_height,
-                &Vec::<WalletTransparentOutput>::new(),
-                &transparent_outputs,
-                &(
-                    ::sapling::builder::BundleType::DEFAULT,
-                    &shielded_inputs
-                        .iter()
-                        .filter_map(|i| {
-                            i.clone().traverse_opt(|wn| match wn {
-                                Note::Sapling(n) => Some(n),
-                                #[cfg(feature = "orchard")]
-                                _ => None,
-                            })
-                        })
-                        .collect::<Vec<_>>()[..],
-                    &sapling_outputs[..],
-                ),
-                #[cfg(feature = "orchard")]
-                &(
-                    ::orchard::builder::BundleType::DEFAULT,
-                    &shielded_inputs
-                        .iter()
-                        .filter_map(|i| {
-                            i.clone().traverse_opt(|wn| match wn {
-                                Note::Orchard(n) => Some(n),
-                                _ => None,
-                            })
-                        })
-                        .collect::<Vec<_>>()[..],
-                    &orchard_outputs[..],
-                ),
-                &self.dust_output_policy,
-            );
+            let balance =
+                self.compute_shielded_balance(params, target_height, &outputs, &shielded_inputs);
 
             match balance {
                 Ok(balance) => {
-                    return Ok(Proposal {
+                    Self::check_pool_crossing(
+                        &outputs.output_pools,
+                        &shielded_inputs,
+                        &balance,
+                        privacy_policy,
+                    )?;
+
+                    return Ok(self.single_step_proposal(
+                        target_height,
+                        anchor_height,
                         transaction_request,
-                        transparent_inputs: vec![],
-                        shielded_inputs: NonEmpty::from_vec(shielded_inputs).map(|notes| {
-                            ShieldedInputs {
-                                anchor_height,
-                                notes,
-                            }
-                        }),
+                        shielded_inputs,
                         balance,
-                        fee_rule: (*self.change_strategy.fee_rule()).clone(),
-                        min_target_height: target_height,
-                        is_shielding: false,
-                    });
+                    ));
                 }
-                Err(ChangeError::DustInputs { mut sapling, .. }) => {
-                    exclude.append(&mut sapling);
+                Err(err @ ChangeError::DustInputs { .. }) => {
+                    if !exclude_dust_notes(&mut exclude, &err) {
+                        return Err(err.into());
+                    }
                 }
                 Err(ChangeError::InsufficientFunds { required, .. }) => {
                     amount_required = required;
@@ -639,16 +902,11 @@ where
                 Err(other) => return Err(other.into()),
             }
 
-            #[cfg(not(zcash_unstable = "orchard"))]
-            let selectable_pools = &[ShieldedProtocol::Sapling];
-            #[cfg(zcash_unstable = "orchard")]
-            let selectable_pools = &[ShieldedProtocol::Sapling, ShieldedProtocol::Orchard];
-
             shielded_inputs = wallet_db
                 .select_spendable_notes(
                     account,
                     amount_required.into(),
-                    selectable_pools,
+                    &outputs.selectable_pools,
                     anchor_height,
                     &exclude,
                 )
@@ -674,16 +932,639 @@ where
     }
 }
 
-#[cfg(feature = "transparent-inputs")]
-impl<DbT, ChangeT> ShieldingSelector for GreedyInputSelector<DbT, ChangeT>
+impl<DbT, ChangeT> GreedyInputSelector<DbT, ChangeT>
 where
     DbT: InputSource,
     ChangeT: ChangeStrategy,
     ChangeT::FeeRule: Clone,
 {
-    type Error = GreedyInputSelectorError<ChangeT::Error, Infallible>;
-    type InputSource = DbT;
-    type FeeRule = ChangeT::FeeRule;
+    /// Groups the payments of the given transaction request by the pool to which each will be
+    /// sent, and determines the pools from which notes may be selected to fund them under the
+    /// given privacy policy.
+    #[allow(clippy::type_complexity)]
+    fn payment_outputs(
+        transaction_request: &TransactionRequest,
+        privacy_policy: PrivacyPolicy,
+    ) -> Result<
+        PaymentOutputs,
+        InputSelectorError<
+            <DbT as InputSource>::Error,
+            GreedyInputSelectorError<ChangeT::Error, DbT::NoteRef>,
+        >,
+    > {
+        let mut transparent_outputs = vec![];
+        let mut sapling_outputs = vec![];
+        #[cfg(feature = "orchard")]
+        let mut orchard_outputs = vec![];
+        for payment in transaction_request.payments() {
+            let mut push_transparent = |taddr: TransparentAddress| {
+                transparent_outputs.push(TxOut {
+                    value: payment.amount,
+                    script_pubkey: taddr.script(),
+                });
+            };
+            let mut push_sapling = || {
+                sapling_outputs.push(SaplingPayment(payment.amount));
+            };
+            #[cfg(feature = "orchard")]
+            let mut push_orchard = || {
+                orchard_outputs.push(OrchardPayment(payment.amount));
+            };
+
+            match &payment.recipient_address {
+                Address::Transparent(addr) => {
+                    push_transparent(*addr);
+                }
+                Address::Sapling(_) => {
+                    push_sapling();
+                }
+                Address::Unified(addr) => {
+                    #[cfg(feature = "orchard")]
+                    let has_orchard = addr.orchard().is_some();
+                    #[cfg(not(feature = "orchard"))]
+                    let has_orchard = false;
+
+                    if has_orchard {
+                        #[cfg(feature = "orchard")]
+                        push_orchard();
+                    } else if addr.sapling().is_some() {
+                        push_sapling();
+                    } else if let Some(addr) = addr.transparent() {
+                        push_transparent(*addr);
+                    } else {
+                        return Err(InputSelectorError::Selection(
+                            GreedyInputSelectorError::UnsupportedAddress(Box::new(addr.clone())),
+                        ));
+                    }
+                }
+            }
+        }
+
+        if !(transparent_outputs.is_empty()
+            || privacy_policy.permits(PrivacyViolation::RevealedRecipients))
+        {
+            return Err(InputSelectorError::PrivacyPolicyViolation {
+                policy: privacy_policy,
+                violation: PrivacyViolation::RevealedRecipients,
+            });
+        }
+
+        let mut output_pools = BTreeSet::new();
+        if !sapling_outputs.is_empty() {
+            output_pools.insert(ShieldedProtocol::Sapling);
+        }
+        #[cfg(feature = "orchard")]
+        if !orchard_outputs.is_empty() {
+            output_pools.insert(ShieldedProtocol::Orchard);
+        }
+
+        // If value may not cross between pools, only notes from the pool to which the payments
+        // are being made may be selected.
+        let selectable_pools =
+            if privacy_policy.allows_revealed_amounts() || output_pools.is_empty() {
+                vec![
+                    ShieldedProtocol::Sapling,
+                    #[cfg(zcash_unstable = "orchard")]
+                    ShieldedProtocol::Orchard,
+                ]
+            } else {
+                if output_pools.len() > 1 {
+                    return Err(InputSelectorError::PrivacyPolicyViolation {
+                        policy: privacy_policy,
+                        violation: PrivacyViolation::RevealedAmounts,
+                    });
+                }
+                output_pools.iter().copied().collect()
+            };
+
+        Ok(PaymentOutputs {
+            transparent: transparent_outputs,
+            sapling: sapling_outputs,
+            #[cfg(feature = "orchard")]
+            orchard: orchard_outputs,
+            output_pools,
+            selectable_pools,
+        })
+    }
+
+    /// Computes the balance of a transaction that spends the given shielded inputs to the given
+    /// outputs, using this selector's change strategy.
+    fn compute_shielded_balance<ParamsT>(
+        &self,
+        params: &ParamsT,
+        target_height: BlockHeight,
+        outputs: &PaymentOutputs,
+        shielded_inputs: &[ReceivedNote<DbT::NoteRef, Note>],
+    ) -> Result<TransactionBalance, ChangeError<ChangeT::Error, DbT::NoteRef>>
+    where
+        ParamsT: consensus::Parameters,
+    {
+        self.change_strategy.compute_balance(
+            params,
+            target_height,
+            &Vec::<WalletTransparentOutput>::new(),
+            &outputs.transparent,
+            &(
+                ::sapling::builder::BundleType::DEFAULT,
+                &shielded_inputs
+                    .iter()
+                    .filter_map(|i| {
+                        i.clone().traverse_opt(|wn| match wn {
+                            Note::Sapling(n) => Some(n),
+                            #[cfg(feature = "orchard")]
+                            _ => None,
+                        })
+                    })
+                    .collect::<Vec<_>>()[..],
+                &outputs.sapling[..],
+            ),
+            #[cfg(feature = "orchard")]
+            &(
+                ::orchard::builder::BundleType::DEFAULT,
+                &shielded_inputs
+                    .iter()
+                    .filter_map(|i| {
+                        i.clone().traverse_opt(|wn| match wn {
+                            Note::Orchard(n) => Some(n),
+                            _ => None,
+                        })
+                    })
+                    .collect::<Vec<_>>()[..],
+                &outputs.orchard[..],
+            ),
+            &self.dust_output_policy,
+        )
+    }
+
+    /// Checks that the change strategy has not moved value across pools, if the privacy policy
+    /// does not permit doing so.
+    #[allow(clippy::type_complexity)]
+    fn check_pool_crossing(
+        output_pools: &BTreeSet<ShieldedProtocol>,
+        shielded_inputs: &[ReceivedNote<DbT::NoteRef, Note>],
+        balance: &TransactionBalance,
+        privacy_policy: PrivacyPolicy,
+    ) -> Result<
+        (),
+        InputSelectorError<
+            <DbT as InputSource>::Error,
+            GreedyInputSelectorError<ChangeT::Error, DbT::NoteRef>,
+        >,
+    > {
+        let pools = shielded_inputs
+            .iter()
+            .map(|n| n.note().protocol())
+            .chain(balance.proposed_change().iter().map(|c| c.output_pool()))
+            .chain(output_pools.iter().copied())
+            .collect::<BTreeSet<_>>();
+        if pools.len() > 1 && !privacy_policy.allows_revealed_amounts() {
+            return Err(InputSelectorError::PrivacyPolicyViolation {
+                policy: privacy_policy,
+                violation: PrivacyViolation::RevealedAmounts,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Constructs a single-step proposal that spends the given shielded inputs.
+    fn single_step_proposal(
+        &self,
+        target_height: BlockHeight,
+        anchor_height: BlockHeight,
+        transaction_request: TransactionRequest,
+        shielded_inputs: Vec<ReceivedNote<DbT::NoteRef, Note>>,
+        balance: TransactionBalance,
+    ) -> Proposal<ChangeT::FeeRule, DbT::NoteRef> {
+        Proposal {
+            fee_rule: (*self.change_strategy.fee_rule()).clone(),
+            min_target_height: target_height,
+            steps: NonEmpty::singleton(Step {
+                transaction_request,
+                transparent_inputs: vec![],
+                shielded_inputs: NonEmpty::from_vec(shielded_inputs).map(|notes| ShieldedInputs {
+                    anchor_height,
+                    notes,
+                }),
+                prior_step_inputs: vec![],
+                balance,
+                is_shielding: false,
+            }),
+        }
+    }
+
+    /// Selects all of the account's spendable notes, and returns a proposal for a transaction
+    /// that pays their total value, less the fee computed by this selector's change strategy,
+    /// to `recipient`. The proposed transaction produces no change.
+    ///
+    /// Notes that would cost more in fees to spend than they are worth, as determined by the
+    /// change strategy, are not spent.
+    ///
+    /// If `privacy_policy` does not permit revealed amounts, only notes from the pool of the
+    /// recipient are spent. A transparent recipient may only be paid if `privacy_policy`
+    /// permits revealed recipients; otherwise, [`InputSelectorError::PrivacyPolicyViolation`]
+    /// is returned.
+    ///
+    /// The caller is responsible for ensuring that `memo` is `None` if `recipient` is a
+    /// transparent address.
+    #[allow(clippy::type_complexity)]
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn propose_send_max<ParamsT>(
+        &self,
+        params: &ParamsT,
+        wallet_db: &DbT,
+        target_height: BlockHeight,
+        anchor_height: BlockHeight,
+        account: AccountId,
+        recipient: &Address,
+        memo: Option<MemoBytes>,
+        privacy_policy: PrivacyPolicy,
+    ) -> Result<
+        Proposal<ChangeT::FeeRule, DbT::NoteRef>,
+        InputSelectorError<
+            <DbT as InputSource>::Error,
+            GreedyInputSelectorError<ChangeT::Error, DbT::NoteRef>,
+        >,
+    >
+    where
+        ParamsT: consensus::Parameters,
+    {
+        enum RecipientPool {
+            Transparent(TransparentAddress),
+            Sapling,
+            #[cfg(feature = "orchard")]
+            Orchard,
+        }
+
+        let recipient_pool = match recipient {
+            Address::Transparent(addr) => RecipientPool::Transparent(*addr),
+            Address::Sapling(_) => RecipientPool::Sapling,
+            Address::Unified(addr) => {
+                #[cfg(feature = "orchard")]
+                let orchard_pool = addr.orchard().map(|_| RecipientPool::Orchard);
+                #[cfg(not(feature = "orchard"))]
+                let orchard_pool = None;
+
+                if let Some(pool) = orchard_pool {
+                    pool
+                } else if addr.sapling().is_some() {
+                    RecipientPool::Sapling
+                } else if let Some(addr) = addr.transparent() {
+                    RecipientPool::Transparent(*addr)
+                } else {
+                    return Err(InputSelectorError::Selection(
+                        GreedyInputSelectorError::UnsupportedAddress(Box::new(addr.clone())),
+                    ));
+                }
+            }
+        };
+
+        let mut output_pools = BTreeSet::new();
+        match recipient_pool {
+            RecipientPool::Transparent(_) => {
+                if !privacy_policy.permits(PrivacyViolation::RevealedRecipients) {
+                    return Err(InputSelectorError::PrivacyPolicyViolation {
+                        policy: privacy_policy,
+                        violation: PrivacyViolation::RevealedRecipients,
+                    });
+                }
+            }
+            RecipientPool::Sapling => {
+                output_pools.insert(ShieldedProtocol::Sapling);
+            }
+            #[cfg(feature = "orchard")]
+            RecipientPool::Orchard => {
+                output_pools.insert(ShieldedProtocol::Orchard);
+            }
+        }
+
+        // If value may not cross between pools, only notes from the recipient's pool may be
+        // selected.
+        let selectable_pools: Vec<ShieldedProtocol> =
+            if privacy_policy.allows_revealed_amounts() || output_pools.is_empty() {
+                vec![
+                    ShieldedProtocol::Sapling,
+                    #[cfg(zcash_unstable = "orchard")]
+                    ShieldedProtocol::Orchard,
+                ]
+            } else {
+                output_pools.iter().copied().collect()
+            };
+
+        let mut exclude: Vec<DbT::NoteRef> = vec![];
+        let mut shielded_inputs = wallet_db
+            .select_spendable_notes(
+                account,
+                Amount::const_from_i64(MAX_MONEY),
+                &selectable_pools,
+                anchor_height,
+                &exclude,
+            )
+            .map_err(InputSelectorError::DataSource)?;
+
+        // We begin by computing the balance of a transaction that pays nothing to the
+        // recipient; all of the available value is then proposed as change. Because the fee
+        // computed by the change strategy already accounts for the change output, moving the
+        // proposed change into the payment to the recipient yields a transaction that has no
+        // change and pays the same fee. The payment amount strictly increases with each
+        // iteration, so this loop terminates.
+        let mut amount = NonNegativeAmount::ZERO;
+        loop {
+            let mut transparent_outputs = vec![];
+            let mut sapling_outputs = vec![];
+            #[cfg(feature = "orchard")]
+            let mut orchard_outputs = vec![];
+            match recipient_pool {
+                RecipientPool::Transparent(addr) => transparent_outputs.push(TxOut {
+                    value: amount,
+                    script_pubkey: addr.script(),
+                }),
+                RecipientPool::Sapling => sapling_outputs.push(SaplingPayment(amount)),
+                #[cfg(feature = "orchard")]
+                RecipientPool::Orchard => orchard_outputs.push(OrchardPayment(amount)),
+            }
+
+            let balance = self.change_strategy.compute_balance(
+                params,
+                target_height,
+                &Vec::<WalletTransparentOutput>::new(),
+                &transparent_outputs,
+                &(
+                    ::sapling::builder::BundleType::DEFAULT,
+                    &shielded_inputs
+                        .iter()
+                        .filter_map(|i| {
+                            i.clone().traverse_opt(|wn| match wn {
+                                Note::Sapling(n) => Some(n),
+                                #[cfg(feature = "orchard")]
+                                _ => None,
+                            })
+                        })
+                        .collect::<Vec<_>>()[..],
+                    &sapling_outputs[..],
+                ),
+                #[cfg(feature = "orchard")]
+                &(
+                    ::orchard::builder::BundleType::DEFAULT,
+                    &shielded_inputs
+                        .iter()
+                        .filter_map(|i| {
+                            i.clone().traverse_opt(|wn| match wn {
+                                Note::Orchard(n) => Some(n),
+                                _ => None,
+                            })
+                        })
+                        .collect::<Vec<_>>()[..],
+                    &orchard_outputs[..],
+                ),
+                &self.dust_output_policy,
+            );
+
+            match balance {
+                Ok(balance) => {
+                    let change_total = balance
+                        .proposed_change()
+                        .iter()
+                        .map(|c| c.value())
+                        .sum::<Option<NonNegativeAmount>>()
+                        .ok_or(BalanceError::Overflow)?;
+
+                    if change_total.is_positive() {
+                        amount = (amount + change_total).ok_or(BalanceError::Overflow)?;
+                        continue;
+                    }
+
+                    Self::check_pool_crossing(
+                        &output_pools,
+                        &shielded_inputs,
+                        &balance,
+                        privacy_policy,
+                    )?;
+
+                    let request = TransactionRequest::new(vec![Payment {
+                        recipient_address: recipient.clone(),
+                        amount,
+                        memo,
+                        label: None,
+                        message: None,
+                        other_params: vec![],
+                    }])
+                    .expect(
+                        "It should not be possible for this to violate ZIP 321 request construction invariants.",
+                    );
+
+                    return Ok(Proposal {
+                        fee_rule: (*self.change_strategy.fee_rule()).clone(),
+                        min_target_height: target_height,
+                        steps: NonEmpty::singleton(Step {
+                            transaction_request: request,
+                            transparent_inputs: vec![],
+                            shielded_inputs: NonEmpty::from_vec(shielded_inputs).map(|notes| {
+                                ShieldedInputs {
+                                    anchor_height,
+                                    notes,
+                                }
+                            }),
+                            prior_step_inputs: vec![],
+                            balance,
+                            is_shielding: false,
+                        }),
+                    });
+                }
+                Err(err @ ChangeError::DustInputs { .. }) => {
+                    if !exclude_dust_notes(&mut exclude, &err) {
+                        return Err(err.into());
+                    }
+                    shielded_inputs = wallet_db
+                        .select_spendable_notes(
+                            account,
+                            Amount::const_from_i64(MAX_MONEY),
+                            &selectable_pools,
+                            anchor_height,
+                            &exclude,
+                        )
+                        .map_err(InputSelectorError::DataSource)?;
+                    amount = NonNegativeAmount::ZERO;
+                }
+                Err(ChangeError::InsufficientFunds {
+                    available,
+                    required,
+                }) => {
+                    return Err(InputSelectorError::InsufficientFunds {
+                        available,
+                        required,
+                    });
+                }
+                Err(other) => return Err(other.into()),
+            }
+        }
+    }
+
+    /// Returns a proposal for a transaction that merges up to `max_inputs` of the account's
+    /// smallest spendable notes in `target_pool` into a single note, which is sent to the
+    /// account's internal address in the same pool.
+    ///
+    /// Notes that would cost more in fees to spend than they are worth, as determined by the
+    /// change strategy, are not consolidated; in their place, the next-smallest notes are
+    /// selected. Consolidation is only considered worthwhile if the value of the consolidated
+    /// note exceeds the fee paid to create it. If fewer than two notes remain, or if the fee
+    /// would consume half or more of their total value,
+    /// [`GreedyInputSelectorError::NothingToConsolidate`] is returned. If the change strategy
+    /// would send the consolidated value to a pool other than `target_pool` and
+    /// `privacy_policy` does not permit revealed amounts,
+    /// [`InputSelectorError::PrivacyPolicyViolation`] is returned.
+    #[allow(clippy::type_complexity)]
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn propose_consolidation<ParamsT>(
+        &self,
+        params: &ParamsT,
+        wallet_db: &DbT,
+        target_height: BlockHeight,
+        anchor_height: BlockHeight,
+        account: AccountId,
+        max_inputs: usize,
+        target_pool: ShieldedProtocol,
+        privacy_policy: PrivacyPolicy,
+    ) -> Result<
+        Proposal<ChangeT::FeeRule, DbT::NoteRef>,
+        InputSelectorError<
+            <DbT as InputSource>::Error,
+            GreedyInputSelectorError<ChangeT::Error, DbT::NoteRef>,
+        >,
+    >
+    where
+        ParamsT: consensus::Parameters,
+    {
+        let mut exclude: Vec<DbT::NoteRef> = vec![];
+        loop {
+            // Only notes from the target pool are selected, so that the change strategy directs
+            // the consolidated value back to that pool without crossing pool boundaries.
+            let mut shielded_inputs = wallet_db
+                .select_spendable_notes(
+                    account,
+                    Amount::const_from_i64(MAX_MONEY),
+                    &[target_pool],
+                    anchor_height,
+                    &exclude,
+                )
+                .map_err(InputSelectorError::DataSource)?;
+            shielded_inputs.sort_by_key(|n| n.note().value());
+            shielded_inputs.truncate(max_inputs);
+
+            if shielded_inputs.len() < 2 {
+                return Err(InputSelectorError::Selection(
+                    GreedyInputSelectorError::NothingToConsolidate,
+                ));
+            }
+
+            let balance = self.change_strategy.compute_balance(
+                params,
+                target_height,
+                &Vec::<WalletTransparentOutput>::new(),
+                &Vec::<TxOut>::new(),
+                &(
+                    ::sapling::builder::BundleType::DEFAULT,
+                    &shielded_inputs
+                        .iter()
+                        .filter_map(|i| {
+                            i.clone().traverse_opt(|wn| match wn {
+                                Note::Sapling(n) => Some(n),
+                                #[cfg(feature = "orchard")]
+                                _ => None,
+                            })
+                        })
+                        .collect::<Vec<_>>()[..],
+                    &Vec::<SaplingPayment>::new()[..],
+                ),
+                #[cfg(feature = "orchard")]
+                &(
+                    ::orchard::builder::BundleType::DEFAULT,
+                    &shielded_inputs
+                        .iter()
+                        .filter_map(|i| {
+                            i.clone().traverse_opt(|wn| match wn {
+                                Note::Orchard(n) => Some(n),
+                                _ => None,
+                            })
+                        })
+                        .collect::<Vec<_>>()[..],
+                    &Vec::<OrchardPayment>::new()[..],
+                ),
+                &self.dust_output_policy,
+            );
+
+            match balance {
+                Ok(balance) => {
+                    // The consolidated value is proposed as change. If the dust output policy
+                    // added it to the fee, or if the fee is at least as large as the value
+                    // that would be consolidated, the transaction is not worth making.
+                    let consolidated_value = balance
+                        .proposed_change()
+                        .iter()
+                        .map(|c| c.value())
+                        .sum::<Option<NonNegativeAmount>>()
+                        .ok_or(BalanceError::Overflow)?;
+                    if consolidated_value <= balance.fee_required() {
+                        return Err(InputSelectorError::Selection(
+                            GreedyInputSelectorError::NothingToConsolidate,
+                        ));
+                    }
+
+                    Self::check_pool_crossing(
+                        &BTreeSet::new(),
+                        &shielded_inputs,
+                        &balance,
+                        privacy_policy,
+                    )?;
+
+                    return Ok(Proposal {
+                        fee_rule: (*self.change_strategy.fee_rule()).clone(),
+                        min_target_height: target_height,
+                        steps: NonEmpty::singleton(Step {
+                            transaction_request: TransactionRequest::empty(),
+                            transparent_inputs: vec![],
+                            shielded_inputs: NonEmpty::from_vec(shielded_inputs).map(|notes| {
+                                ShieldedInputs {
+                                    anchor_height,
+                                    notes,
+                                }
+                            }),
+                            prior_step_inputs: vec![],
+                            balance,
+                            is_shielding: false,
+                        }),
+                    });
+                }
+                Err(err @ ChangeError::DustInputs { .. }) => {
+                    if !exclude_dust_notes(&mut exclude, &err) {
+                        return Err(err.into());
+                    }
+                }
+                Err(ChangeError::InsufficientFunds {
+                    available,
+                    required,
+                }) => {
+                    return Err(InputSelectorError::InsufficientFunds {
+                        available,
+                        required,
+                    });
+                }
+                Err(other) => return Err(other.into()),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "transparent-inputs")]
+impl<DbT, ChangeT> ShieldingSelector for GreedyInputSelector<DbT, ChangeT>
+where
+    DbT: InputSource,
+    ChangeT: ChangeStrategy,
+    ChangeT::FeeRule: Clone,
+{
+    type Error = GreedyInputSelectorError<ChangeT::Error, Infallible>;
+    type InputSource = DbT;
+    type FeeRule = ChangeT::FeeRule;
 
     #[allow(clippy::type_complexity)]
     fn propose_shielding<ParamsT>(
@@ -767,13 +1648,16 @@ where
 
         if balance.total() >= shielding_threshold {
             Ok(Proposal {
-                transaction_request: TransactionRequest::empty(),
-                transparent_inputs,
-                shielded_inputs: None,
-                balance,
                 fee_rule: (*self.change_strategy.fee_rule()).clone(),
                 min_target_height: target_height,
-                is_shielding: true,
+                steps: NonEmpty::singleton(Step {
+                    transaction_request: TransactionRequest::empty(),
+                    transparent_inputs,
+                    shielded_inputs: None,
+                    prior_step_inputs: vec![],
+                    balance,
+                    is_shielding: true,
+                }),
             })
         } else {
             Err(InputSelectorError::InsufficientFunds {
@@ -783,3 +1667,623 @@ where
         }
     }
 }
+
+/// The cost of a candidate set of inputs, as evaluated by [`BranchAndBoundInputSelector`].
+///
+/// Costs are compared lexicographically: first by the sum of the fee and any dust change, then
+/// by the total value of change produced, and finally by the number of inputs spent.
+type SelectionCost = (NonNegativeAmount, NonNegativeAmount, usize);
+
+/// An [`InputSelector`] implementation that searches for the set of notes that minimizes the
+/// cost of a transaction.
+///
+/// The cost of spending a set of notes is the fee required, plus the total value of any change
+/// outputs below the dust threshold of the selector's [`DustOutputPolicy`]. Among sets of notes
+/// having equal cost, the set that produces the least change is preferred, and then the set
+/// that spends the fewest notes.
+///
+/// Selection is performed using a depth-first branch-and-bound search over the account's
+/// spendable notes, ordered by descending value. The search is seeded with the proposal
+/// produced by [`GreedyInputSelector`] for the same request; if the search does not find a
+/// cheaper set of notes within the configured number of iterations, the greedy proposal is
+/// returned. If the greedy selector fails for lack of funds or because its selection would
+/// violate the privacy policy, the search is still performed, and the greedy error is
+/// returned only if the search finds no acceptable set of notes. For a given set of spendable
+/// notes, the result of selection is deterministic.
+pub struct BranchAndBoundInputSelector<DbT, ChangeT> {
+    greedy: GreedyInputSelector<DbT, ChangeT>,
+    max_iterations: usize,
+}
+
+impl<DbT, ChangeT: ChangeStrategy> BranchAndBoundInputSelector<DbT, ChangeT> {
+    /// Constructs a new branch-and-bound input selector that uses the provided change strategy
+    /// to determine change values and fee amounts, and that evaluates at most `max_iterations`
+    /// candidate sets of notes.
+    pub fn new(
+        change_strategy: ChangeT,
+        dust_output_policy: DustOutputPolicy,
+        max_iterations: usize,
+    ) -> Self {
+        BranchAndBoundInputSelector {
+            greedy: GreedyInputSelector::new(change_strategy, dust_output_policy),
+            max_iterations,
+        }
+    }
+
+    /// Returns the cost of a transaction having the given balance and number of inputs, or
+    /// `None` if the cost cannot be represented.
+    fn cost(&self, balance: &TransactionBalance, input_count: usize) -> Option<SelectionCost> {
+        let dust_threshold = self.greedy.dust_output_policy.dust_threshold();
+        let dust_change = balance
+            .proposed_change()
+            .iter()
+            .map(|c| c.value())
+            .filter(|v| dust_threshold.map_or(false, |t| *v < t))
+            .sum::<Option<NonNegativeAmount>>()?;
+        let total_change = balance
+            .proposed_change()
+            .iter()
+            .map(|c| c.value())
+            .sum::<Option<NonNegativeAmount>>()?;
+
+        Some((
+            (balance.fee_required() + dust_change)?,
+            total_change,
+            input_count,
+        ))
+    }
+}
+
+impl<DbT, ChangeT> InputSelector for BranchAndBoundInputSelector<DbT, ChangeT>
+where
+    DbT: InputSource,
+    ChangeT: ChangeStrategy,
+    ChangeT::FeeRule: Clone,
+{
+    type Error = GreedyInputSelectorError<ChangeT::Error, DbT::NoteRef>;
+    type InputSource = DbT;
+    type FeeRule = ChangeT::FeeRule;
+
+    #[allow(clippy::type_complexity)]
+    fn propose_transaction<ParamsT>(
+        &self,
+        params: &ParamsT,
+        wallet_db: &Self::InputSource,
+        target_height: BlockHeight,
+        anchor_height: BlockHeight,
+        account: AccountId,
+        transaction_request: TransactionRequest,
+        privacy_policy: PrivacyPolicy,
+    ) -> Result<
+        Proposal<Self::FeeRule, DbT::NoteRef>,
+        InputSelectorError<<DbT as InputSource>::Error, Self::Error>,
+    >
+    where
+        ParamsT: consensus::Parameters,
+        Self::InputSource: InputSource,
+    {
+        let greedy_result = self.greedy.propose_transaction(
+            params,
+            wallet_db,
+            target_height,
+            anchor_height,
+            account,
+            transaction_request.clone(),
+            privacy_policy,
+        );
+
+        let mut best_cost = match &greedy_result {
+            Ok(greedy_proposal) => {
+                let greedy_step = greedy_proposal.steps().first();
+                match self.cost(
+                    greedy_step.balance(),
+                    greedy_step
+                        .shielded_inputs()
+                        .map_or(0, |inputs| inputs.notes().len()),
+                ) {
+                    Some(cost) => Some(cost),
+                    None => return greedy_result,
+                }
+            }
+            // The greedy selector only considers the notes that `select_spendable_notes`
+            // returns for each target value, and `InputSource` does not require those to be
+            // the notes that best satisfy the request; in particular, they may span pools that
+            // the privacy policy does not permit value to cross between. Another selection may
+            // therefore succeed where the greedy selection did not, so the search is performed
+            // without an initial bound.
+            Err(InputSelectorError::InsufficientFunds { .. })
+            | Err(InputSelectorError::PrivacyPolicyViolation { .. })
+            | Err(InputSelectorError::Selection(GreedyInputSelectorError::Change(
+                ChangeError::DustInputs { .. },
+            ))) => None,
+            Err(_) => return greedy_result,
+        };
+
+        let outputs = GreedyInputSelector::<DbT, ChangeT>::payment_outputs(
+            &transaction_request,
+            privacy_policy,
+        )?;
+        let payment_total = transaction_request
+            .total()
+            .map_err(|_| BalanceError::Overflow)?;
+
+        let mut candidates = wallet_db
+            .select_spendable_notes(
+                account,
+                Amount::const_from_i64(MAX_MONEY),
+                &outputs.selectable_pools,
+                anchor_height,
+                &[],
+            )
+            .map_err(InputSelectorError::DataSource)?;
+
+        // Notes that are not worth the fee required to spend them are never selected.
+        if let Err(err @ ChangeError::DustInputs { .. }) =
+            self.greedy
+                .compute_shielded_balance(params, target_height, &outputs, &candidates)
+        {
+            let mut dust = vec![];
+            exclude_dust_notes(&mut dust, &err);
+            candidates.retain(|n| !dust.contains(n.internal_note_id()));
+        }
+
+        candidates.sort_by(|a, b| {
+            b.note()
+                .value()
+                .cmp(&a.note().value())
+                .then_with(|| a.internal_note_id().cmp(b.internal_note_id()))
+        });
+
+        // `remaining[i]` is the total value of the candidates at indices `i..`.
+        let mut remaining = vec![0u64; candidates.len() + 1];
+        for (i, note) in candidates.iter().enumerate().rev() {
+            remaining[i] = remaining[i + 1] + u64::from(note.note().value());
+        }
+
+        let mut best: Option<(Vec<ReceivedNote<DbT::NoteRef, Note>>, TransactionBalance)> = None;
+        let mut selection: Vec<usize> = vec![];
+        let mut depth = 0;
+        for _ in 0..self.max_iterations {
+            let mut backtrack = depth == candidates.len();
+            if !selection.is_empty() {
+                let inputs = selection
+                    .iter()
+                    .map(|i| candidates[*i].clone())
+                    .collect::<Vec<_>>();
+                match self
+                    .greedy
+                    .compute_shielded_balance(params, target_height, &outputs, &inputs)
+                {
+                    Ok(balance) => {
+                        // Supersets of a sufficient set of notes are not explored, as spending
+                        // additional notes cannot reduce the fee.
+                        backtrack = true;
+                        if let Some(cost) = self.cost(&balance, inputs.len()) {
+                            if best_cost.map_or(true, |best| cost < best)
+                                && GreedyInputSelector::<DbT, ChangeT>::check_pool_crossing(
+                                    &outputs.output_pools,
+                                    &inputs,
+                                    &balance,
+                                    privacy_policy,
+                                )
+                                .is_ok()
+                            {
+                                best_cost = Some(cost);
+                                best = Some((inputs, balance));
+                            }
+                        }
+                    }
+                    Err(ChangeError::InsufficientFunds {
+                        available,
+                        required,
+                    }) => {
+                        // Prune this branch if the remaining candidates cannot make up the
+                        // shortfall, or if the fee alone already exceeds the best cost found.
+                        let reachable = u64::from(available) + remaining[depth];
+                        let fee_bound =
+                            (required - payment_total).unwrap_or(NonNegativeAmount::ZERO);
+                        backtrack = reachable < u64::from(required)
+                            || best_cost.map_or(false, |best| fee_bound > best.0);
+                    }
+                    Err(ChangeError::DustInputs { .. }) => {
+                        backtrack = true;
+                    }
+                    Err(other) => return Err(other.into()),
+                }
+            }
+
+            if backtrack {
+                // Remove the most recently included candidate, and explore the branch in which
+                // it is excluded.
+                match selection.pop() {
+                    Some(i) => depth = i + 1,
+                    None => break,
+                }
+            } else {
+                selection.push(depth);
+                depth += 1;
+            }
+        }
+
+        Ok(match best {
+            Some((inputs, balance)) => self.greedy.single_step_proposal(
+                target_height,
+                anchor_height,
+                transaction_request,
+                inputs,
+                balance,
+            ),
+            None => greedy_result?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use incrementalmerkletree::Position;
+    use proptest::prelude::*;
+    use sapling::{value::NoteValue, zip32::ExtendedSpendingKey, Rseed};
+    use zcash_primitives::{
+        consensus::{BlockHeight, Network, NetworkUpgrade, Parameters},
+        transaction::{
+            components::amount::{Amount, NonNegativeAmount},
+            fees::zip317::FeeRule as Zip317FeeRule,
+            TxId,
+        },
+        zip32::{AccountId, Scope},
+    };
+
+    use crate::{
+        address::Address,
+        data_api::InputSource,
+        fees::{
+            zip317::SingleOutputChangeStrategy, ChangePoolPolicy, DustAction, DustOutputPolicy,
+        },
+        wallet::{Note, ReceivedNote},
+        zip321::{Payment, TransactionRequest},
+        ShieldedProtocol,
+    };
+
+    use super::{
+        BranchAndBoundInputSelector, GreedyInputSelector, InputSelector, InputSelectorError,
+        PrivacyPolicy, Proposal,
+    };
+
+    /// An [`InputSource`] holding a fixed set of notes, which selects notes from the requested
+    /// pools in the order in which they were provided until the target value is reached.
+    struct MockInputSource {
+        notes: Vec<ReceivedNote<u32, Note>>,
+    }
+
+    impl MockInputSource {
+        /// Constructs a source holding a Sapling note for each of the given values.
+        fn new(values: &[u64]) -> Self {
+            Self::from_notes(values.iter().map(|value| sapling_note(*value)))
+        }
+
+        fn from_notes(notes: impl Iterator<Item = Note>) -> Self {
+            MockInputSource {
+                notes: notes
+                    .enumerate()
+                    .map(|(i, note)| {
+                        ReceivedNote::from_parts(
+                            i as u32,
+                            TxId::from_bytes([0; 32]),
+                            i as u16,
+                            note,
+                            Scope::External,
+                            Position::from(i as u64),
+                        )
+                    })
+                    .collect(),
+            }
+        }
+    }
+
+    fn sapling_note(value: u64) -> Note {
+        let to = ExtendedSpendingKey::master(&[])
+            .to_diversifiable_full_viewing_key()
+            .default_address()
+            .1;
+        Note::Sapling(sapling::Note::from_parts(
+            to,
+            NoteValue::from_raw(value),
+            Rseed::AfterZip212([0; 32]),
+        ))
+    }
+
+    #[cfg(feature = "orchard")]
+    fn orchard_note(value: u64) -> Note {
+        use ::orchard::{
+            keys::{FullViewingKey, SpendingKey},
+            note::{Nullifier, RandomSeed},
+        };
+
+        let sk = SpendingKey::from_bytes([0; 32]).unwrap();
+        let to = FullViewingKey::from(&sk).address_at(0u32, ::orchard::keys::Scope::External);
+        let rho = Nullifier::from_bytes(&[0; 32]).unwrap();
+        let rseed = RandomSeed::from_bytes([0; 32], &rho).unwrap();
+        Note::Orchard(
+            ::orchard::Note::from_parts(
+                to,
+                ::orchard::value::NoteValue::from_raw(value),
+                rho,
+                rseed,
+            )
+            .unwrap(),
+        )
+    }
+
+    impl InputSource for MockInputSource {
+        type Error = Infallible;
+        type NoteRef = u32;
+
+        fn get_spendable_note(
+            &self,
+            _txid: &TxId,
+            _protocol: ShieldedProtocol,
+            _index: u32,
+        ) -> Result<Option<ReceivedNote<Self::NoteRef, Note>>, Self::Error> {
+            Ok(None)
+        }
+
+        fn select_spendable_notes(
+            &self,
+            _account: AccountId,
+            target_value: Amount,
+            sources: &[ShieldedProtocol],
+            _anchor_height: BlockHeight,
+            exclude: &[Self::NoteRef],
+        ) -> Result<Vec<ReceivedNote<Self::NoteRef, Note>>, Self::Error> {
+            let mut selected = vec![];
+            let mut total = Amount::zero();
+            for note in self.notes.iter().filter(|n| {
+                sources.contains(&n.note().protocol()) && !exclude.contains(n.internal_note_id())
+            }) {
+                if total >= target_value {
+                    break;
+                }
+                total = (total + Amount::from(note.note().value())).unwrap();
+                selected.push(note.clone());
+            }
+            Ok(selected)
+        }
+    }
+
+    fn change_strategy() -> SingleOutputChangeStrategy {
+        SingleOutputChangeStrategy::new(
+            Zip317FeeRule::standard(),
+            None,
+            ChangePoolPolicy::default(),
+        )
+    }
+
+    fn dust_output_policy() -> DustOutputPolicy {
+        DustOutputPolicy::new(
+            DustAction::AllowDustChange,
+            Some(NonNegativeAmount::const_from_u64(5000)),
+        )
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn propose<S: InputSelector<InputSource = MockInputSource>>(
+        selector: &S,
+        wallet_db: &MockInputSource,
+        amount: u64,
+        privacy_policy: PrivacyPolicy,
+    ) -> Result<Proposal<S::FeeRule, u32>, InputSelectorError<Infallible, S::Error>> {
+        let params = Network::TestNetwork;
+        let target_height = params.activation_height(NetworkUpgrade::Nu5).unwrap();
+        let to = ExtendedSpendingKey::master(&[1])
+            .to_diversifiable_full_viewing_key()
+            .default_address()
+            .1;
+        let request = TransactionRequest::new(vec![Payment {
+            recipient_address: Address::Sapling(to),
+            amount: NonNegativeAmount::from_u64(amount).unwrap(),
+            memo: None,
+            label: None,
+            message: None,
+            other_params: vec![],
+        }])
+        .unwrap();
+
+        selector.propose_transaction(
+            &params,
+            wallet_db,
+            target_height,
+            target_height,
+            AccountId::ZERO,
+            request,
+            privacy_policy,
+        )
+    }
+
+    fn input_ids<FeeRuleT>(proposal: &Proposal<FeeRuleT, u32>) -> Vec<u32> {
+        proposal
+            .steps()
+            .first()
+            .shielded_inputs()
+            .map_or(vec![], |inputs| {
+                inputs
+                    .notes()
+                    .iter()
+                    .map(|n| *n.internal_note_id())
+                    .collect()
+            })
+    }
+
+    #[test]
+    fn branch_and_bound_improves_on_greedy() {
+        let wallet_db = MockInputSource::new(&[10000, 20000, 30000, 55000]);
+
+        // The greedy selector spends all four notes, paying a fee for four logical actions.
+        let greedy = GreedyInputSelector::new(change_strategy(), dust_output_policy());
+        let proposal = propose(&greedy, &wallet_db, 50000, PrivacyPolicy::FullPrivacy).unwrap();
+        let step = proposal.steps().first();
+        assert_eq!(input_ids(&proposal), vec![0, 1, 2, 3]);
+        assert_eq!(
+            step.balance().fee_required(),
+            NonNegativeAmount::const_from_u64(20000)
+        );
+
+        // The branch-and-bound selector finds the pair of notes that minimizes the fee, and
+        // among those, the pair that produces the least change.
+        let bnb = BranchAndBoundInputSelector::new(change_strategy(), dust_output_policy(), 1000);
+        let proposal = propose(&bnb, &wallet_db, 50000, PrivacyPolicy::FullPrivacy).unwrap();
+        let step = proposal.steps().first();
+        assert_eq!(input_ids(&proposal), vec![3, 0]);
+        assert_eq!(
+            step.balance().fee_required(),
+            NonNegativeAmount::const_from_u64(10000)
+        );
+        assert_eq!(
+            step.balance().total(),
+            NonNegativeAmount::const_from_u64(15000)
+        );
+    }
+
+    /// Returns whether notes having the given values are certain to be sufficient to pay
+    /// `amount`, whichever of them are selected.
+    ///
+    /// Under ZIP 317, each note spent and each of the payment and change outputs adds at most one
+    /// logical action, and notes worth no more than the marginal fee are never spent.
+    fn is_sufficient(values: &[u64], amount: u64) -> bool {
+        let spendable = values.iter().filter(|v| **v > 5000).sum::<u64>();
+        spendable >= amount + 5000 * (values.len() as u64 + 2)
+    }
+
+    /// Checks that the branch-and-bound selector succeeds whenever the greedy selector does, and
+    /// that its selection costs no more than the greedy selection. Returns the proposal made by
+    /// the branch-and-bound selector, if any.
+    fn check_no_worse_than_greedy(
+        wallet_db: &MockInputSource,
+        amount: u64,
+        privacy_policy: PrivacyPolicy,
+    ) -> Result<Option<Proposal<Zip317FeeRule, u32>>, TestCaseError> {
+        let greedy = GreedyInputSelector::new(change_strategy(), dust_output_policy());
+        let bnb = BranchAndBoundInputSelector::new(change_strategy(), dust_output_policy(), 10000);
+
+        let greedy_result = propose(&greedy, wallet_db, amount, privacy_policy);
+        let bnb_result = propose(&bnb, wallet_db, amount, privacy_policy);
+        prop_assert!(greedy_result.is_err() || bnb_result.is_ok());
+
+        if let Ok(bnb_proposal) = &bnb_result {
+            let cost = |proposal: &Proposal<Zip317FeeRule, u32>| {
+                let step = proposal.steps().first();
+                bnb.cost(step.balance(), input_ids(proposal).len()).unwrap()
+            };
+            if let Ok(greedy_proposal) = &greedy_result {
+                prop_assert!(cost(bnb_proposal) <= cost(greedy_proposal));
+            }
+
+            // The selected notes must pay for the payment, the fee, and any change.
+            let step = bnb_proposal.steps().first();
+            let input_total = step
+                .shielded_inputs()
+                .map(|inputs| {
+                    inputs
+                        .notes()
+                        .iter()
+                        .map(|n| u64::from(n.note().value()))
+                        .sum()
+                })
+                .unwrap_or(0u64);
+            prop_assert_eq!(input_total, amount + u64::from(step.balance().total()));
+
+            // Selection is deterministic.
+            let repeated = propose(&bnb, wallet_db, amount, privacy_policy).unwrap();
+            prop_assert_eq!(input_ids(bnb_proposal), input_ids(&repeated));
+        }
+
+        Ok(bnb_result.ok())
+    }
+
+    /// Returns the pools of the notes spent by the given proposal.
+    #[cfg(all(feature = "orchard", zcash_unstable = "orchard"))]
+    fn input_pools<FeeRuleT>(proposal: &Proposal<FeeRuleT, u32>) -> Vec<ShieldedProtocol> {
+        proposal
+            .steps()
+            .first()
+            .shielded_inputs()
+            .map_or(vec![], |inputs| {
+                inputs.notes().iter().map(|n| n.note().protocol()).collect()
+            })
+    }
+
+    proptest! {
+        #[test]
+        fn branch_and_bound_is_no_worse_than_greedy(
+            values in prop::collection::vec(1000u64..100000, 1..10),
+            amount in 1000u64..200000,
+        ) {
+            let wallet_db = MockInputSource::new(&values);
+            let proposal =
+                check_no_worse_than_greedy(&wallet_db, amount, PrivacyPolicy::FullPrivacy)?;
+            prop_assert!(proposal.is_some() || !is_sufficient(&values, amount));
+        }
+
+        #[test]
+        #[cfg(all(feature = "orchard", zcash_unstable = "orchard"))]
+        fn branch_and_bound_is_no_worse_than_greedy_for_orchard_notes(
+            values in prop::collection::vec(1000u64..100000, 1..10),
+            amount in 1000u64..200000,
+        ) {
+            let wallet_db = MockInputSource::from_notes(values.iter().map(|v| orchard_note(*v)));
+            let proposal = check_no_worse_than_greedy(
+                &wallet_db,
+                amount,
+                PrivacyPolicy::AllowRevealedAmounts,
+            )?;
+            match proposal {
+                Some(proposal) => {
+                    let pools = input_pools(&proposal);
+                    prop_assert!(!pools.is_empty());
+                    prop_assert!(pools.iter().all(|p| *p == ShieldedProtocol::Orchard));
+                }
+                None => prop_assert!(!is_sufficient(&values, amount)),
+            }
+        }
+
+        #[test]
+        #[cfg(all(feature = "orchard", zcash_unstable = "orchard"))]
+        fn branch_and_bound_is_no_worse_than_greedy_for_mixed_notes(
+            notes in prop::collection::vec((any::<bool>(), 1000u64..100000), 1..10),
+            amount in 1000u64..200000,
+            allow_revealed_amounts in any::<bool>(),
+        ) {
+            let wallet_db = MockInputSource::from_notes(notes.iter().map(|(is_orchard, v)| {
+                if *is_orchard {
+                    orchard_note(*v)
+                } else {
+                    sapling_note(*v)
+                }
+            }));
+            let privacy_policy = if allow_revealed_amounts {
+                PrivacyPolicy::AllowRevealedAmounts
+            } else {
+                PrivacyPolicy::FullPrivacy
+            };
+            let proposal = check_no_worse_than_greedy(&wallet_db, amount, privacy_policy)?;
+
+            // Without revealed amounts, only notes from the recipient's Sapling pool may be spent.
+            let selectable = notes
+                .iter()
+                .filter(|(is_orchard, _)| allow_revealed_amounts || !*is_orchard)
+                .map(|(_, v)| *v)
+                .collect::<Vec<_>>();
+            match proposal {
+                Some(proposal) => {
+                    if !allow_revealed_amounts {
+                        prop_assert!(input_pools(&proposal)
+                            .iter()
+                            .all(|p| *p == ShieldedProtocol::Sapling));
+                    }
+                }
+                None => prop_assert!(!is_sufficient(&selectable, amount)),
+            }
+        }
+    }
+}
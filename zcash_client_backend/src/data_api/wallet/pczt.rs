@@ -0,0 +1,1225 @@
+//! Support for constructing transactions across multiple devices.
+//!
+//! A [`Pczt`] (partially created Zcash transaction) contains everything that is needed to
+//! build a transaction from a [`Proposal`]: the notes and UTXOs being spent along with their
+//! witnesses, the anchors against which the spends are made, and the outputs of the
+//! transaction. It can be serialized with [`Pczt::write`] and moved between devices, each
+//! of which performs one step of transaction creation:
+//!
+//! - [`create_pczt_from_proposal`] runs on an online device with access to the wallet
+//!   database, and does not require any spending keys. This means that it can be used with
+//!   accounts that were imported into the wallet from a viewing key.
+//! - [`sign_pczt`] runs on a (possibly air-gapped) device holding the
+//!   [`UnifiedSpendingKey`] for the account, and produces the proven and signed
+//!   transaction.
+//! - [`extract_and_store_pczt`] runs on the online device, and stores the signed
+//!   transaction in the wallet database so that it can be broadcast.
+//!
+//! Proving and signing are not separate roles, and there is no step that combines the
+//! results of several signers. The Sapling transaction builder requires the spending key in
+//! order to construct the proof for a Sapling spend, so proving and signing are both
+//! performed by [`sign_pczt`]; the signing device must therefore have access to the Sapling
+//! proving parameters. Likewise, all of the spends in a PCZT belong to a single account, and
+//! so are signed together by the holder of that account's spending key.
+
+use std::{
+    convert::Infallible,
+    io::{self, Read, Write},
+};
+
+use group::ff::PrimeField;
+use incrementalmerkletree::MerklePath;
+use rand_core::OsRng;
+use sapling::{
+    note_encryption::{try_sapling_note_decryption, PreparedIncomingViewingKey},
+    prover::{OutputProver, SpendProver},
+    Rseed,
+};
+use zcash_encoding::{Optional, Vector};
+use zcash_primitives::{
+    consensus::{self, BlockHeight, BranchId},
+    legacy::TransparentAddress,
+    memo::MemoBytes,
+    merkle_tree::{read_position, write_position, HashSer},
+    transaction::{
+        builder::{BuildConfig, Builder},
+        components::{
+            amount::{Amount, NonNegativeAmount},
+            transparent::{OutPoint, TxOut},
+        },
+        fees::{fixed, FeeRule},
+        Transaction, TxId,
+    },
+    zip32::{AccountId, Scope},
+};
+
+use crate::{
+    address::Address,
+    data_api::{
+        error::Error, wallet::input_selection::Proposal, SentTransaction, SentTransactionOutput,
+        WalletCommitmentTrees, WalletRead, WalletWrite,
+    },
+    keys::UnifiedSpendingKey,
+    wallet::{Note, OvkPolicy, Recipient},
+    PoolType, ShieldedProtocol,
+};
+
+#[cfg(feature = "transparent-inputs")]
+use {sapling::keys::OutgoingViewingKey, zcash_primitives::zip32::DiversifierIndex};
+
+#[cfg(feature = "orchard")]
+use zcash_primitives::transaction::components::orchard::read_anchor;
+
+/// The version of the [`Pczt`] serialization format written by this library.
+const PCZT_VERSION: u32 = 1;
+
+const RSEED_BEFORE_ZIP_212: u8 = 0;
+const RSEED_AFTER_ZIP_212: u8 = 1;
+
+/// The recipient of a transaction output, as it will be recorded in the wallet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum OutputRecipient {
+    /// An output to an external recipient, identified by its encoded address.
+    External(String),
+    /// A change output to an internal address of the account creating the transaction.
+    Change,
+}
+
+#[derive(Clone, Debug)]
+struct SaplingSpend {
+    scope: Scope,
+    note: sapling::Note,
+    merkle_path: MerklePath<sapling::Node, { sapling::NOTE_COMMITMENT_TREE_DEPTH }>,
+}
+
+#[derive(Clone, Debug)]
+struct SaplingOutput {
+    scope: Scope,
+    to: sapling::PaymentAddress,
+    value: NonNegativeAmount,
+    memo: MemoBytes,
+    recipient: OutputRecipient,
+}
+
+#[cfg(feature = "orchard")]
+#[derive(Clone, Debug)]
+struct OrchardSpend {
+    note: orchard::note::Note,
+    merkle_path:
+        MerklePath<orchard::tree::MerkleHashOrchard, { orchard::NOTE_COMMITMENT_TREE_DEPTH as u8 }>,
+}
+
+#[cfg(feature = "orchard")]
+#[derive(Clone, Debug)]
+struct OrchardOutput {
+    scope: Scope,
+    to: orchard::Address,
+    value: NonNegativeAmount,
+    memo: MemoBytes,
+    recipient: OutputRecipient,
+}
+
+#[derive(Clone, Debug)]
+struct TransparentInput {
+    child_index: u32,
+    outpoint: OutPoint,
+    coin: TxOut,
+}
+
+#[derive(Clone, Debug)]
+struct TransparentOutput {
+    to: TransparentAddress,
+    value: NonNegativeAmount,
+    recipient: OutputRecipient,
+}
+
+/// A transaction that has been proven and signed, along with the positions of the shielded
+/// outputs of the [`Pczt`] within its bundles.
+#[derive(Debug)]
+struct SignedTransaction {
+    transaction: Transaction,
+    sapling_output_indices: Vec<usize>,
+    orchard_output_indices: Vec<usize>,
+}
+
+/// A partially created Zcash transaction.
+///
+/// See the [module documentation](self) for details.
+#[derive(Debug)]
+pub struct Pczt {
+    consensus_branch_id: BranchId,
+    target_height: BlockHeight,
+    account: AccountId,
+    fee: NonNegativeAmount,
+    ovk_policy: OvkPolicy,
+    is_shielding: bool,
+    sapling_anchor: sapling::Anchor,
+    sapling_spends: Vec<SaplingSpend>,
+    sapling_outputs: Vec<SaplingOutput>,
+    #[cfg(feature = "orchard")]
+    orchard_anchor: Option<orchard::Anchor>,
+    #[cfg(feature = "orchard")]
+    orchard_spends: Vec<OrchardSpend>,
+    #[cfg(feature = "orchard")]
+    orchard_outputs: Vec<OrchardOutput>,
+    transparent_inputs: Vec<TransparentInput>,
+    transparent_outputs: Vec<TransparentOutput>,
+    signed: Option<SignedTransaction>,
+}
+
+impl Pczt {
+    /// Returns the account from which the transaction spends funds.
+    pub fn account(&self) -> AccountId {
+        self.account
+    }
+
+    /// Returns the height of the block in which the transaction is expected to be mined.
+    pub fn target_height(&self) -> BlockHeight {
+        self.target_height
+    }
+
+    /// Returns the fee that the transaction pays.
+    pub fn fee(&self) -> NonNegativeAmount {
+        self.fee
+    }
+
+    /// Returns the signed transaction, if [`sign_pczt`] has been applied to this PCZT.
+    pub fn transaction(&self) -> Option<&Transaction> {
+        self.signed.as_ref().map(|signed| &signed.transaction)
+    }
+
+    /// Reads a PCZT in the format written by [`Pczt::write`].
+    pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let version = read_u32(&mut reader)?;
+        if version != PCZT_VERSION {
+            return Err(invalid_data(format!(
+                "Unsupported PCZT version {}",
+                version
+            )));
+        }
+
+        let consensus_branch_id =
+            BranchId::try_from(read_u32(&mut reader)?).map_err(|e| invalid_data(e.to_string()))?;
+        let target_height = BlockHeight::from(read_u32(&mut reader)?);
+        let account = AccountId::try_from(read_u32(&mut reader)?)
+            .map_err(|_| invalid_data("Invalid account identifier"))?;
+        let fee = read_amount(&mut reader)?;
+        let ovk_policy = match read_u8(&mut reader)? {
+            0 => OvkPolicy::Sender,
+            1 => OvkPolicy::Custom(sapling::keys::OutgoingViewingKey(read_bytes(&mut reader)?)),
+            2 => OvkPolicy::Discard,
+            t => return Err(invalid_data(format!("Invalid OVK policy {}", t))),
+        };
+        let is_shielding = read_bool(&mut reader)?;
+
+        let sapling_anchor = Option::from(sapling::Anchor::from_bytes(read_bytes(&mut reader)?))
+            .ok_or_else(|| invalid_data("Invalid Sapling anchor"))?;
+        let sapling_spends = Vector::read(&mut reader, |r| {
+            Ok(SaplingSpend {
+                scope: read_scope(&mut *r)?,
+                note: read_sapling_note(&mut *r)?,
+                merkle_path: read_merkle_path(r)?,
+            })
+        })?;
+        let sapling_outputs = Vector::read(&mut reader, |r| {
+            Ok(SaplingOutput {
+                scope: read_scope(&mut *r)?,
+                to: sapling::PaymentAddress::from_bytes(&read_bytes(&mut *r)?)
+                    .ok_or_else(|| invalid_data("Invalid Sapling recipient address"))?,
+                value: read_amount(&mut *r)?,
+                memo: read_memo(&mut *r)?,
+                recipient: read_recipient(r)?,
+            })
+        })?;
+
+        #[cfg(feature = "orchard")]
+        let orchard_anchor = Optional::read(&mut reader, read_anchor)?;
+        #[cfg(feature = "orchard")]
+        let orchard_spends = Vector::read(&mut reader, |r| {
+            Ok(OrchardSpend {
+                note: read_orchard_note(&mut *r)?,
+                merkle_path: read_merkle_path(r)?,
+            })
+        })?;
+        #[cfg(feature = "orchard")]
+        let orchard_outputs = Vector::read(&mut reader, |r| {
+            Ok(OrchardOutput {
+                scope: read_scope(&mut *r)?,
+                to: Option::from(orchard::Address::from_raw_address_bytes(&read_bytes(
+                    &mut *r,
+                )?))
+                .ok_or_else(|| invalid_data("Invalid Orchard recipient address"))?,
+                value: read_amount(&mut *r)?,
+                memo: read_memo(&mut *r)?,
+                recipient: read_recipient(r)?,
+            })
+        })?;
+
+        #[cfg(not(feature = "orchard"))]
+        {
+            let orchard_unsupported = || {
+                Err::<(), _>(invalid_data(
+                    "PCZT contains Orchard data, but Orchard support is not enabled",
+                ))
+            };
+            Optional::read(&mut reader, |_| orchard_unsupported())?;
+            Vector::read(&mut reader, |_| orchard_unsupported())?;
+            Vector::read(&mut reader, |_| orchard_unsupported())?;
+        }
+
+        let transparent_inputs = Vector::read(&mut reader, |r| {
+            if cfg!(not(feature = "transparent-inputs")) {
+                return Err(invalid_data(
+                    "PCZT contains transparent inputs, but transparent input support is not enabled",
+                ));
+            }
+
+            Ok(TransparentInput {
+                child_index: read_u32(&mut *r)?,
+                outpoint: OutPoint::read(&mut *r)?,
+                coin: TxOut::read(r)?,
+            })
+        })?;
+        let transparent_outputs = Vector::read(&mut reader, |r| {
+            let coin = TxOut::read(&mut *r)?;
+            Ok(TransparentOutput {
+                to: coin
+                    .recipient_address()
+                    .ok_or_else(|| invalid_data("Invalid transparent recipient address"))?,
+                value: coin.value,
+                recipient: read_recipient(r)?,
+            })
+        })?;
+
+        let signed = Optional::read(&mut reader, |mut r| {
+            Ok(SignedTransaction {
+                transaction: Transaction::read(&mut r, consensus_branch_id)?,
+                sapling_output_indices: Vector::read(&mut r, |r| read_index(r))?,
+                orchard_output_indices: Vector::read(&mut r, |r| read_index(r))?,
+            })
+        })?;
+
+        Ok(Pczt {
+            consensus_branch_id,
+            target_height,
+            account,
+            fee,
+            ovk_policy,
+            is_shielding,
+            sapling_anchor,
+            sapling_spends,
+            sapling_outputs,
+            #[cfg(feature = "orchard")]
+            orchard_anchor,
+            #[cfg(feature = "orchard")]
+            orchard_spends,
+            #[cfg(feature = "orchard")]
+            orchard_outputs,
+            transparent_inputs,
+            transparent_outputs,
+            signed,
+        })
+    }
+
+    /// Writes this PCZT in a form that can be read by [`Pczt::read`].
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&PCZT_VERSION.to_le_bytes())?;
+        writer.write_all(&u32::from(self.consensus_branch_id).to_le_bytes())?;
+        writer.write_all(&u32::from(self.target_height).to_le_bytes())?;
+        writer.write_all(&u32::from(self.account).to_le_bytes())?;
+        write_amount(&mut writer, self.fee)?;
+        match &self.ovk_policy {
+            OvkPolicy::Sender => writer.write_all(&[0])?,
+            OvkPolicy::Custom(ovk) => {
+                writer.write_all(&[1])?;
+                writer.write_all(&ovk.0)?;
+            }
+            OvkPolicy::Discard => writer.write_all(&[2])?,
+        }
+        writer.write_all(&[u8::from(self.is_shielding)])?;
+
+        writer.write_all(&self.sapling_anchor.to_bytes())?;
+        Vector::write(&mut writer, &self.sapling_spends, |w, spend| {
+            write_scope(&mut *w, spend.scope)?;
+            write_sapling_note(&mut *w, &spend.note)?;
+            write_merkle_path(w, &spend.merkle_path)
+        })?;
+        Vector::write(&mut writer, &self.sapling_outputs, |w, output| {
+            write_scope(&mut *w, output.scope)?;
+            w.write_all(&output.to.to_bytes())?;
+            write_amount(&mut *w, output.value)?;
+            w.write_all(output.memo.as_array())?;
+            write_recipient(w, &output.recipient)
+        })?;
+
+        #[cfg(feature = "orchard")]
+        {
+            Optional::write(&mut writer, self.orchard_anchor, |mut w, anchor| {
+                w.write_all(&anchor.to_bytes())
+            })?;
+            Vector::write(&mut writer, &self.orchard_spends, |w, spend| {
+                write_orchard_note(&mut *w, &spend.note)?;
+                write_merkle_path(w, &spend.merkle_path)
+            })?;
+            Vector::write(&mut writer, &self.orchard_outputs, |w, output| {
+                write_scope(&mut *w, output.scope)?;
+                w.write_all(&output.to.to_raw_address_bytes())?;
+                write_amount(&mut *w, output.value)?;
+                w.write_all(output.memo.as_array())?;
+                write_recipient(w, &output.recipient)
+            })?;
+        }
+        #[cfg(not(feature = "orchard"))]
+        {
+            Optional::write(&mut writer, None::<()>, |_, _| Ok(()))?;
+            Vector::write(&mut writer, &[] as &[()], |_, _| Ok(()))?;
+            Vector::write(&mut writer, &[] as &[()], |_, _| Ok(()))?;
+        }
+
+        Vector::write(&mut writer, &self.transparent_inputs, |w, input| {
+            w.write_all(&input.child_index.to_le_bytes())?;
+            input.outpoint.write(&mut *w)?;
+            input.coin.write(w)
+        })?;
+        Vector::write(&mut writer, &self.transparent_outputs, |w, output| {
+            TxOut {
+                value: output.value,
+                script_pubkey: output.to.script(),
+            }
+            .write(&mut *w)?;
+            write_recipient(w, &output.recipient)
+        })?;
+
+        Optional::write(&mut writer, self.signed.as_ref(), |mut w, signed| {
+            signed.transaction.write(&mut w)?;
+            Vector::write(&mut w, &signed.sapling_output_indices, |w, i| {
+                write_index(w, *i)
+            })?;
+            Vector::write(&mut w, &signed.orchard_output_indices, |w, i| {
+                write_index(w, *i)
+            })
+        })
+    }
+}
+
+/// Constructs a [`Pczt`] that spends the inputs and creates the outputs described by the
+/// given proposal, on behalf of the specified account.
+///
+/// This retrieves the witnesses for the notes being spent from the wallet's note commitment
+/// trees, and determines the change addresses for the transaction from the account's
+/// unified full viewing key. No spending key is required; the resulting PCZT must be passed
+/// to [`sign_pczt`] by a holder of the account's spending key.
+///
+/// Transaction outputs will be encrypted to the outgoing viewing key selected by the
+/// `ovk_policy`; this policy is applied by [`sign_pczt`] using the account's spending key.
+///
+/// A PCZT describes a single transaction, so only single-step proposals are supported;
+/// [`Error::ProposalNotSupported`] is returned for a proposal having more than one step.
+#[allow(clippy::type_complexity)]
+pub fn create_pczt_from_proposal<DbT, ParamsT, InputsErrT, FeeRuleT, N>(
+    wallet_db: &mut DbT,
+    params: &ParamsT,
+    account: AccountId,
+    ovk_policy: OvkPolicy,
+    proposal: &Proposal<FeeRuleT, N>,
+) -> Result<
+    Pczt,
+    Error<
+        <DbT as WalletRead>::Error,
+        <DbT as WalletCommitmentTrees>::Error,
+        InputsErrT,
+        FeeRuleT::Error,
+    >,
+>
+where
+    DbT: WalletRead + WalletCommitmentTrees,
+    ParamsT: consensus::Parameters,
+    FeeRuleT: FeeRule,
+{
+    let ufvk = wallet_db
+        .get_unified_full_viewing_keys()
+        .map_err(Error::DataSource)?
+        .remove(&account)
+        .ok_or(Error::AccountNotFound(account))?;
+
+    if proposal.steps().len() > 1 {
+        return Err(Error::ProposalNotSupported);
+    }
+    let step = proposal.steps().first();
+
+    let (sapling_anchor, sapling_spends) = step.shielded_inputs().map_or_else(
+        || Ok((sapling::Anchor::empty_tree(), vec![])),
+        |inputs| {
+            wallet_db.with_sapling_tree_mut::<_, _, Error<_, _, _, _>>(|sapling_tree| {
+                let anchor = sapling_tree
+                    .root_at_checkpoint_id(&inputs.anchor_height())?
+                    .into();
+
+                let sapling_spends = inputs
+                    .notes()
+                    .iter()
+                    .filter_map(|selected| match selected.note() {
+                        Note::Sapling(note) => Some(
+                            sapling_tree
+                                .witness_at_checkpoint_id_caching(
+                                    selected.note_commitment_tree_position(),
+                                    &inputs.anchor_height(),
+                                )
+                                .map(|merkle_path| SaplingSpend {
+                                    scope: selected.spending_key_scope(),
+                                    note: note.clone(),
+                                    merkle_path,
+                                })
+                                .map_err(Error::from),
+                        ),
+                        #[cfg(feature = "orchard")]
+                        Note::Orchard(_) => None,
+                    })
+                    .collect::<Result<Vec<_>, Error<_, _, _, _>>>()?;
+
+                Ok((anchor, sapling_spends))
+            })
+        },
+    )?;
+
+    #[cfg(feature = "orchard")]
+    let (orchard_anchor, orchard_spends) = step.shielded_inputs().map_or_else(
+        || Ok((None, vec![])),
+        |inputs| {
+            let orchard_notes = inputs
+                .notes()
+                .iter()
+                .filter_map(|selected| match selected.note() {
+                    Note::Orchard(note) => Some((selected, *note)),
+                    Note::Sapling(_) => None,
+                })
+                .collect::<Vec<_>>();
+
+            if orchard_notes.is_empty() {
+                return Ok((None, vec![]));
+            }
+
+            wallet_db.with_orchard_tree_mut::<_, _, Error<_, _, _, _>>(|orchard_tree| {
+                let anchor = orchard_tree
+                    .root_at_checkpoint_id(&inputs.anchor_height())?
+                    .into();
+
+                let orchard_spends = orchard_notes
+                    .iter()
+                    .map(|(selected, note)| {
+                        let merkle_path = orchard_tree.witness_at_checkpoint_id_caching(
+                            selected.note_commitment_tree_position(),
+                            &inputs.anchor_height(),
+                        )?;
+
+                        Ok(OrchardSpend {
+                            note: *note,
+                            merkle_path,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, Error<_, _, _, _>>>()?;
+
+                Ok((Some(anchor), orchard_spends))
+            })
+        },
+    )?;
+
+    // As in `create_proposed_transaction`, Orchard outputs may be created without spending
+    // any Orchard notes, in which case the empty tree is a valid anchor.
+    #[cfg(feature = "orchard")]
+    let orchard_anchor = orchard_anchor.or_else(|| {
+        let has_orchard_payment = step
+            .transaction_request()
+            .payments()
+            .iter()
+            .any(|payment| {
+                matches!(&payment.recipient_address, Address::Unified(ua) if ua.orchard().is_some())
+            });
+        let has_orchard_change = step
+            .balance()
+            .proposed_change()
+            .iter()
+            .any(|change_value| change_value.output_pool() == ShieldedProtocol::Orchard);
+
+        (has_orchard_payment || has_orchard_change).then(orchard::Anchor::empty_tree)
+    });
+
+    #[cfg(feature = "transparent-inputs")]
+    let transparent_inputs = {
+        let known_addrs = wallet_db
+            .get_transparent_receivers(account)
+            .map_err(Error::DataSource)?;
+
+        step.transparent_inputs()
+            .iter()
+            .map(|utxo| {
+                let diversifier_index = known_addrs
+                    .get(utxo.recipient_address())
+                    .ok_or_else(|| Error::AddressNotRecognized(*utxo.recipient_address()))?
+                    .diversifier_index();
+
+                let child_index = u32::try_from(*diversifier_index)
+                    .map_err(|_| Error::ChildIndexOutOfRange(*diversifier_index))?;
+
+                Ok(TransparentInput {
+                    child_index,
+                    outpoint: utxo.outpoint().clone(),
+                    coin: utxo.txout().clone(),
+                })
+            })
+            .collect::<Result<Vec<_>, Error<_, _, _, _>>>()?
+    };
+    #[cfg(not(feature = "transparent-inputs"))]
+    let transparent_inputs = vec![];
+
+    let mut sapling_outputs = vec![];
+    #[cfg(feature = "orchard")]
+    let mut orchard_outputs = vec![];
+    let mut transparent_outputs = vec![];
+    for payment in step.transaction_request().payments() {
+        let memo = payment
+            .memo
+            .as_ref()
+            .map_or_else(MemoBytes::empty, |m| m.clone());
+        let recipient = OutputRecipient::External(payment.recipient_address.encode(params));
+
+        let transparent_to = match &payment.recipient_address {
+            Address::Unified(ua) => {
+                #[cfg(feature = "orchard")]
+                if let Some(orchard_receiver) = ua.orchard() {
+                    orchard_outputs.push(OrchardOutput {
+                        scope: Scope::External,
+                        to: *orchard_receiver,
+                        value: payment.amount,
+                        memo,
+                        recipient,
+                    });
+                    continue;
+                }
+
+                if let Some(sapling_receiver) = ua.sapling() {
+                    sapling_outputs.push(SaplingOutput {
+                        scope: Scope::External,
+                        to: *sapling_receiver,
+                        value: payment.amount,
+                        memo,
+                        recipient,
+                    });
+                    continue;
+                } else if let Some(taddr) = ua.transparent() {
+                    taddr
+                } else {
+                    return Err(Error::NoSupportedReceivers(
+                        ua.unknown().iter().map(|(tc, _)| *tc).collect(),
+                    ));
+                }
+            }
+            Address::Sapling(addr) => {
+                sapling_outputs.push(SaplingOutput {
+                    scope: Scope::External,
+                    to: *addr,
+                    value: payment.amount,
+                    memo,
+                    recipient,
+                });
+                continue;
+            }
+            Address::Transparent(to) => to,
+        };
+
+        if payment.memo.is_some() {
+            return Err(Error::MemoForbidden);
+        }
+        transparent_outputs.push(TransparentOutput {
+            to: *transparent_to,
+            value: payment.amount,
+            recipient,
+        });
+    }
+
+    for change_value in step.balance().proposed_change() {
+        let memo = change_value
+            .memo()
+            .map_or_else(MemoBytes::empty, |m| m.clone());
+        match change_value.output_pool() {
+            ShieldedProtocol::Sapling => {
+                let dfvk = ufvk
+                    .sapling()
+                    .ok_or(Error::UnsupportedPoolType(PoolType::Shielded(
+                        ShieldedProtocol::Sapling,
+                    )))?;
+                sapling_outputs.push(SaplingOutput {
+                    scope: Scope::Internal,
+                    to: dfvk.change_address().1,
+                    value: change_value.value(),
+                    memo,
+                    recipient: OutputRecipient::Change,
+                });
+            }
+            #[cfg(zcash_unstable = "orchard")]
+            ShieldedProtocol::Orchard => {
+                #[cfg(not(feature = "orchard"))]
+                return Err(Error::UnsupportedPoolType(PoolType::Shielded(
+                    ShieldedProtocol::Orchard,
+                )));
+
+                #[cfg(feature = "orchard")]
+                {
+                    let fvk =
+                        ufvk.orchard()
+                            .ok_or(Error::UnsupportedPoolType(PoolType::Shielded(
+                                ShieldedProtocol::Orchard,
+                            )))?;
+                    orchard_outputs.push(OrchardOutput {
+                        scope: Scope::Internal,
+                        to: fvk.address_at(0u32, orchard::keys::Scope::Internal),
+                        value: change_value.value(),
+                        memo,
+                        recipient: OutputRecipient::Change,
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(Pczt {
+        consensus_branch_id: BranchId::for_height(params, proposal.min_target_height()),
+        target_height: proposal.min_target_height(),
+        account,
+        fee: step.balance().fee_required(),
+        ovk_policy,
+        is_shielding: step.is_shielding(),
+        sapling_anchor,
+        sapling_spends,
+        sapling_outputs,
+        #[cfg(feature = "orchard")]
+        orchard_anchor,
+        #[cfg(feature = "orchard")]
+        orchard_spends,
+        #[cfg(feature = "orchard")]
+        orchard_outputs,
+        transparent_inputs,
+        transparent_outputs,
+        signed: None,
+    })
+}
+
+/// Proves and signs the transaction described by the given [`Pczt`].
+///
+/// `usk` must be the spending key for the account that the PCZT was created for. This
+/// function does not access the wallet database, and so may be run on an offline device.
+/// The returned PCZT contains the signed transaction, and should be passed to
+/// [`extract_and_store_pczt`].
+#[allow(clippy::type_complexity)]
+pub fn sign_pczt<ParamsT>(
+    params: &ParamsT,
+    spend_prover: &impl SpendProver,
+    output_prover: &impl OutputProver,
+    usk: &UnifiedSpendingKey,
+    mut pczt: Pczt,
+) -> Result<Pczt, Error<Infallible, Infallible, Infallible, Infallible>>
+where
+    ParamsT: consensus::Parameters + Clone,
+{
+    let dfvk = usk.sapling().to_diversifiable_full_viewing_key();
+
+    // Apply the outgoing viewing key policy.
+    let external_ovk = match &pczt.ovk_policy {
+        OvkPolicy::Sender => Some(dfvk.to_ovk(Scope::External)),
+        OvkPolicy::Custom(ovk) => Some(sapling::keys::OutgoingViewingKey(ovk.0)),
+        OvkPolicy::Discard => None,
+    };
+
+    let is_shielding = pczt.is_shielding;
+    let internal_ovk = || {
+        #[cfg(feature = "transparent-inputs")]
+        return if is_shielding {
+            Some(OutgoingViewingKey(
+                usk.transparent()
+                    .to_account_pubkey()
+                    .internal_ovk()
+                    .as_bytes(),
+            ))
+        } else {
+            Some(dfvk.to_ovk(Scope::Internal))
+        };
+
+        #[cfg(not(feature = "transparent-inputs"))]
+        {
+            let _ = is_shielding;
+            Some(dfvk.to_ovk(Scope::Internal))
+        }
+    };
+
+    #[cfg(feature = "orchard")]
+    let orchard_fvk = orchard::keys::FullViewingKey::from(usk.orchard());
+
+    #[cfg(feature = "orchard")]
+    let orchard_ovk = |scope: Scope| match scope {
+        Scope::External => match &pczt.ovk_policy {
+            OvkPolicy::Sender => Some(orchard_fvk.to_ovk(orchard::keys::Scope::External)),
+            OvkPolicy::Custom(ovk) => Some(orchard::keys::OutgoingViewingKey::from(ovk.0)),
+            OvkPolicy::Discard => None,
+        },
+        Scope::Internal => {
+            #[cfg(feature = "transparent-inputs")]
+            if is_shielding {
+                return Some(orchard::keys::OutgoingViewingKey::from(
+                    usk.transparent()
+                        .to_account_pubkey()
+                        .internal_ovk()
+                        .as_bytes(),
+                ));
+            }
+
+            Some(orchard_fvk.to_ovk(orchard::keys::Scope::Internal))
+        }
+    };
+
+    #[cfg(feature = "orchard")]
+    let orchard_anchor = pczt.orchard_anchor;
+    #[cfg(not(feature = "orchard"))]
+    let orchard_anchor = None;
+
+    let mut builder = Builder::new(
+        params.clone(),
+        pczt.target_height,
+        BuildConfig::Standard {
+            sapling_anchor: Some(pczt.sapling_anchor),
+            orchard_anchor,
+        },
+    );
+
+    for spend in &pczt.sapling_spends {
+        let key = match spend.scope {
+            Scope::External => usk.sapling().clone(),
+            Scope::Internal => usk.sapling().derive_internal(),
+        };
+        builder.add_sapling_spend(&key, spend.note.clone(), spend.merkle_path.clone())?;
+    }
+
+    #[cfg(feature = "orchard")]
+    for spend in &pczt.orchard_spends {
+        builder.add_orchard_spend(
+            usk.orchard(),
+            spend.note,
+            orchard::tree::MerklePath::from(spend.merkle_path.clone()),
+        )?;
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    for input in &pczt.transparent_inputs {
+        let secret_key = usk
+            .transparent()
+            .derive_external_secret_key(input.child_index)
+            .map_err(|_| Error::ChildIndexOutOfRange(DiversifierIndex::from(input.child_index)))?;
+
+        builder.add_transparent_input(secret_key, input.outpoint.clone(), input.coin.clone())?;
+    }
+
+    for output in &pczt.sapling_outputs {
+        let ovk = match output.scope {
+            Scope::External => external_ovk,
+            Scope::Internal => internal_ovk(),
+        };
+        builder.add_sapling_output(ovk, output.to, output.value, output.memo.clone())?;
+    }
+
+    #[cfg(feature = "orchard")]
+    for output in &pczt.orchard_outputs {
+        builder.add_orchard_output(
+            orchard_ovk(output.scope),
+            output.to,
+            output.value.into(),
+            output.memo.clone(),
+        )?;
+    }
+
+    for output in &pczt.transparent_outputs {
+        builder.add_transparent_output(&output.to, output.value)?;
+    }
+
+    // The fee was determined when the proposal was constructed; the signer uses exactly
+    // that fee, rather than recomputing it.
+    let build_result = builder.build(
+        OsRng,
+        spend_prover,
+        output_prover,
+        &fixed::FeeRule::non_standard(pczt.fee),
+    )?;
+
+    let sapling_output_indices = (0..pczt.sapling_outputs.len())
+        .map(|i| {
+            build_result
+                .sapling_meta()
+                .output_index(i)
+                .ok_or(Error::PcztOutputMismatch)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    #[cfg(feature = "orchard")]
+    let orchard_output_indices = (0..pczt.orchard_outputs.len())
+        .map(|i| {
+            build_result
+                .orchard_meta()
+                .output_action_index(i)
+                .ok_or(Error::PcztOutputMismatch)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    #[cfg(not(feature = "orchard"))]
+    let orchard_output_indices = vec![];
+
+    pczt.signed = Some(SignedTransaction {
+        transaction: build_result.into_transaction(),
+        sapling_output_indices,
+        orchard_output_indices,
+    });
+
+    Ok(pczt)
+}
+
+/// Extracts the signed transaction from the given [`Pczt`] and persists it to the wallet
+/// database.
+///
+/// Returns the transaction ID of the extracted transaction, or [`Error::PcztNotSigned`] if
+/// [`sign_pczt`] has not been applied to the PCZT. [`Error::PcztOutputMismatch`] is returned
+/// if the signed transaction does not contain each of the outputs that the PCZT describes.
+/// The transaction can subsequently be broadcast in the same way as one created by
+/// [`create_proposed_transactions`].
+///
+/// [`create_proposed_transactions`]: super::create_proposed_transactions
+#[allow(clippy::type_complexity)]
+pub fn extract_and_store_pczt<DbT, ParamsT>(
+    wallet_db: &mut DbT,
+    params: &ParamsT,
+    pczt: &Pczt,
+) -> Result<TxId, Error<<DbT as WalletRead>::Error, Infallible, Infallible, Infallible>>
+where
+    DbT: WalletWrite,
+    ParamsT: consensus::Parameters,
+{
+    let signed = pczt.signed.as_ref().ok_or(Error::PcztNotSigned)?;
+    let tx = &signed.transaction;
+
+    #[cfg(feature = "orchard")]
+    let orchard_outputs_len = pczt.orchard_outputs.len();
+    #[cfg(not(feature = "orchard"))]
+    let orchard_outputs_len = 0;
+
+    if signed.sapling_output_indices.len() != pczt.sapling_outputs.len()
+        || signed.orchard_output_indices.len() != orchard_outputs_len
+    {
+        return Err(Error::PcztOutputMismatch);
+    }
+
+    let ufvk = wallet_db
+        .get_unified_full_viewing_keys()
+        .map_err(Error::DataSource)?
+        .remove(&pczt.account)
+        .ok_or(Error::AccountNotFound(pczt.account))?;
+
+    let mut outputs = vec![];
+
+    let sapling_internal_ivk = ufvk
+        .sapling()
+        .map(|dfvk| PreparedIncomingViewingKey::new(&dfvk.to_ivk(Scope::Internal)));
+    let zip212_enforcement = consensus::sapling_zip212_enforcement(params, pczt.target_height);
+    for (output, output_index) in pczt
+        .sapling_outputs
+        .iter()
+        .zip(signed.sapling_output_indices.iter().copied())
+    {
+        let pool = PoolType::Shielded(ShieldedProtocol::Sapling);
+        let received_as = match (&output.recipient, &sapling_internal_ivk) {
+            (OutputRecipient::Change, Some(ivk)) => tx
+                .sapling_bundle()
+                .and_then(|bundle| bundle.shielded_outputs().get(output_index))
+                .and_then(|tx_output| {
+                    try_sapling_note_decryption(ivk, tx_output, zip212_enforcement)
+                })
+                .map(|(note, _, _)| (pczt.account, Note::Sapling(note))),
+            _ => None,
+        };
+
+        outputs.push(SentTransactionOutput::from_parts(
+            output_index,
+            to_recipient(params, pczt.account, &output.recipient, pool)
+                .map_err(Error::PcztInvalidRecipient)?,
+            output.value,
+            Some(output.memo.clone()),
+            received_as,
+        ));
+    }
+
+    #[cfg(feature = "orchard")]
+    {
+        let orchard_internal_ivk = ufvk
+            .orchard()
+            .map(|fvk| fvk.to_ivk(orchard::keys::Scope::Internal));
+        for (output, output_index) in pczt
+            .orchard_outputs
+            .iter()
+            .zip(signed.orchard_output_indices.iter().copied())
+        {
+            let pool = PoolType::Shielded(ShieldedProtocol::Orchard);
+            let received_as = match (&output.recipient, &orchard_internal_ivk) {
+                (OutputRecipient::Change, Some(ivk)) => tx
+                    .orchard_bundle()
+                    .and_then(|bundle| bundle.decrypt_output_with_key(output_index, ivk))
+                    .map(|(note, _, _)| (pczt.account, Note::Orchard(note))),
+                _ => None,
+            };
+
+            outputs.push(SentTransactionOutput::from_parts(
+                output_index,
+                to_recipient(params, pczt.account, &output.recipient, pool)
+                    .map_err(Error::PcztInvalidRecipient)?,
+                output.value,
+                Some(output.memo.clone()),
+                received_as,
+            ));
+        }
+    }
+
+    // Several outputs may pay the same address, so each transaction output is matched to at
+    // most one output of the PCZT.
+    let mut transparent_output_used = tx
+        .transparent_bundle()
+        .map_or_else(Vec::new, |b| vec![false; b.vout.len()]);
+    for output in &pczt.transparent_outputs {
+        let script = output.to.script();
+        let output_index = tx
+            .transparent_bundle()
+            .and_then(|b| {
+                b.vout.iter().enumerate().position(|(i, tx_out)| {
+                    !transparent_output_used[i]
+                        && tx_out.script_pubkey == script
+                        && tx_out.value == output.value
+                })
+            })
+            .ok_or(Error::PcztOutputMismatch)?;
+        transparent_output_used[output_index] = true;
+
+        outputs.push(SentTransactionOutput::from_parts(
+            output_index,
+            to_recipient(
+                params,
+                pczt.account,
+                &output.recipient,
+                PoolType::Transparent,
+            )
+            .map_err(Error::PcztInvalidRecipient)?,
+            output.value,
+            None,
+            None,
+        ));
+    }
+
+    wallet_db
+        .store_sent_tx(&SentTransaction {
+            tx,
+            created: time::OffsetDateTime::now_utc(),
+            account: pczt.account,
+            outputs,
+            fee_amount: Amount::from(pczt.fee),
+            #[cfg(feature = "transparent-inputs")]
+            utxos_spent: pczt
+                .transparent_inputs
+                .iter()
+                .map(|input| input.outpoint.clone())
+                .collect(),
+        })
+        .map_err(Error::DataSource)?;
+
+    Ok(tx.txid())
+}
+
+/// Returns the wallet recipient for an output of a PCZT, or the encoded address of the
+/// recipient if it cannot be decoded.
+fn to_recipient<P: consensus::Parameters>(
+    params: &P,
+    account: AccountId,
+    recipient: &OutputRecipient,
+    pool: PoolType,
+) -> Result<Recipient, String> {
+    match recipient {
+        OutputRecipient::External(addr) => match Address::decode(params, addr) {
+            Some(Address::Sapling(addr)) => Ok(Recipient::Sapling(addr)),
+            Some(Address::Transparent(addr)) => Ok(Recipient::Transparent(addr)),
+            Some(Address::Unified(ua)) => Ok(Recipient::Unified(ua, pool)),
+            None => Err(addr.clone()),
+        },
+        OutputRecipient::Change => Ok(Recipient::InternalAccount(account, pool)),
+    }
+}
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+fn read_bytes<R: Read, const N: usize>(mut reader: R) -> io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u8<R: Read>(reader: R) -> io::Result<u8> {
+    read_bytes::<_, 1>(reader).map(|b| b[0])
+}
+
+fn read_bool<R: Read>(reader: R) -> io::Result<bool> {
+    match read_u8(reader)? {
+        0 => Ok(false),
+        1 => Ok(true),
+        b => Err(invalid_data(format!("Invalid boolean value {}", b))),
+    }
+}
+
+fn read_u32<R: Read>(reader: R) -> io::Result<u32> {
+    read_bytes(reader).map(u32::from_le_bytes)
+}
+
+fn read_index<R: Read>(reader: R) -> io::Result<usize> {
+    read_u32(reader).map(|i| i as usize)
+}
+
+fn write_index<W: Write>(mut writer: W, index: usize) -> io::Result<()> {
+    let index = u32::try_from(index).map_err(|_| invalid_data("Output index out of range"))?;
+    writer.write_all(&index.to_le_bytes())
+}
+
+fn read_amount<R: Read>(reader: R) -> io::Result<NonNegativeAmount> {
+    NonNegativeAmount::from_u64_le_bytes(read_bytes(reader)?)
+        .map_err(|_| invalid_data("Invalid amount"))
+}
+
+fn write_amount<W: Write>(mut writer: W, value: NonNegativeAmount) -> io::Result<()> {
+    writer.write_all(&u64::from(value).to_le_bytes())
+}
+
+fn read_scope<R: Read>(reader: R) -> io::Result<Scope> {
+    match read_u8(reader)? {
+        0 => Ok(Scope::External),
+        1 => Ok(Scope::Internal),
+        s => Err(invalid_data(format!("Invalid key scope {}", s))),
+    }
+}
+
+fn write_scope<W: Write>(mut writer: W, scope: Scope) -> io::Result<()> {
+    writer.write_all(&[match scope {
+        Scope::External => 0,
+        Scope::Internal => 1,
+    }])
+}
+
+fn read_memo<R: Read>(reader: R) -> io::Result<MemoBytes> {
+    let bytes: [u8; 512] = read_bytes(reader)?;
+    MemoBytes::from_bytes(&bytes).map_err(|_| invalid_data("Invalid memo"))
+}
+
+fn read_recipient<R: Read>(mut reader: R) -> io::Result<OutputRecipient> {
+    match read_u8(&mut reader)? {
+        0 => {
+            let addr = Vector::read(&mut reader, |r| read_u8(r))?;
+            String::from_utf8(addr)
+                .map(OutputRecipient::External)
+                .map_err(|_| invalid_data("Invalid recipient address encoding"))
+        }
+        1 => Ok(OutputRecipient::Change),
+        t => Err(invalid_data(format!("Invalid recipient type {}", t))),
+    }
+}
+
+fn write_recipient<W: Write>(mut writer: W, recipient: &OutputRecipient) -> io::Result<()> {
+    match recipient {
+        OutputRecipient::External(addr) => {
+            writer.write_all(&[0])?;
+            Vector::write(&mut writer, addr.as_bytes(), |w, b| w.write_all(&[*b]))
+        }
+        OutputRecipient::Change => writer.write_all(&[1]),
+    }
+}
+
+fn read_merkle_path<H: HashSer, R: Read, const DEPTH: u8>(
+    mut reader: R,
+) -> io::Result<MerklePath<H, DEPTH>> {
+    let position = read_position(&mut reader)?;
+    let path_elems = (0..DEPTH)
+        .map(|_| H::read(&mut reader))
+        .collect::<io::Result<Vec<_>>>()?;
+    MerklePath::from_parts(path_elems, position).map_err(|_| invalid_data("Invalid Merkle path"))
+}
+
+fn write_merkle_path<H: HashSer, W: Write, const DEPTH: u8>(
+    mut writer: W,
+    merkle_path: &MerklePath<H, DEPTH>,
+) -> io::Result<()> {
+    write_position(&mut writer, merkle_path.position())?;
+    for elem in merkle_path.path_elems() {
+        elem.write(&mut writer)?;
+    }
+    Ok(())
+}
+
+fn read_sapling_note<R: Read>(mut reader: R) -> io::Result<sapling::Note> {
+    let recipient = sapling::PaymentAddress::from_bytes(&read_bytes(&mut reader)?)
+        .ok_or_else(|| invalid_data("Invalid Sapling note recipient"))?;
+    let value =
+        sapling::value::NoteValue::from_raw(read_bytes(&mut reader).map(u64::from_le_bytes)?);
+    let rseed = match read_u8(&mut reader)? {
+        RSEED_BEFORE_ZIP_212 => Rseed::BeforeZip212(read_scalar(&mut reader)?),
+        RSEED_AFTER_ZIP_212 => Rseed::AfterZip212(read_bytes(&mut reader)?),
+        t => return Err(invalid_data(format!("Invalid rseed type {}", t))),
+    };
+
+    Ok(sapling::Note::from_parts(recipient, value, rseed))
+}
+
+fn read_scalar<F: PrimeField<Repr = [u8; 32]>, R: Read>(reader: R) -> io::Result<F> {
+    Option::from(F::from_repr(read_bytes(reader)?))
+        .ok_or_else(|| invalid_data("Invalid note commitment randomness"))
+}
+
+fn write_sapling_note<W: Write>(mut writer: W, note: &sapling::Note) -> io::Result<()> {
+    writer.write_all(&note.recipient().to_bytes())?;
+    writer.write_all(&note.value().inner().to_le_bytes())?;
+    match note.rseed() {
+        Rseed::BeforeZip212(rcm) => {
+            writer.write_all(&[RSEED_BEFORE_ZIP_212])?;
+            writer.write_all(&rcm.to_repr())
+        }
+        Rseed::AfterZip212(rseed) => {
+            writer.write_all(&[RSEED_AFTER_ZIP_212])?;
+            writer.write_all(rseed)
+        }
+    }
+}
+
+#[cfg(feature = "orchard")]
+fn read_orchard_note<R: Read>(mut reader: R) -> io::Result<orchard::note::Note> {
+    use orchard::note::{Nullifier, RandomSeed};
+
+    let recipient = Option::from(orchard::Address::from_raw_address_bytes(&read_bytes(
+        &mut reader,
+    )?))
+    .ok_or_else(|| invalid_data("Invalid Orchard note recipient"))?;
+    let value =
+        orchard::value::NoteValue::from_raw(read_bytes(&mut reader).map(u64::from_le_bytes)?);
+    let rho = Option::from(Nullifier::from_bytes(&read_bytes(&mut reader)?))
+        .ok_or_else(|| invalid_data("Invalid Orchard note rho"))?;
+    let rseed = Option::from(RandomSeed::from_bytes(read_bytes(&mut reader)?, &rho))
+        .ok_or_else(|| invalid_data("Invalid Orchard note rseed"))?;
+
+    Option::from(orchard::note::Note::from_parts(
+        recipient, value, rho, rseed,
+    ))
+    .ok_or_else(|| invalid_data("Invalid Orchard note"))
+}
+
+#[cfg(feature = "orchard")]
+fn write_orchard_note<W: Write>(mut writer: W, note: &orchard::note::Note) -> io::Result<()> {
+    writer.write_all(&note.recipient().to_raw_address_bytes())?;
+    writer.write_all(&note.value().inner().to_le_bytes())?;
+    writer.write_all(&note.rho().to_bytes())?;
+    writer.write_all(note.rseed().as_bytes())
+}
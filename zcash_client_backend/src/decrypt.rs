@@ -28,7 +28,8 @@ pub enum TransferType {
 
 /// A decrypted shielded output.
 pub struct DecryptedOutput<Note> {
-    /// The index of the output within [`shielded_outputs`].
+    /// The index of the output within [`shielded_outputs`] for a Sapling output, or the index
+    /// of the action within the Orchard bundle for an Orchard output.
     ///
     /// [`shielded_outputs`]: zcash_primitives::transaction::TransactionData
     pub index: usize,
@@ -101,3 +102,67 @@ pub fn decrypt_transaction<P: consensus::Parameters>(
         })
         .collect()
 }
+
+/// Scans the Orchard bundle of a [`Transaction`] for any actions that can be decrypted by the
+/// set of [`UnifiedFullViewingKey`]s.
+#[cfg(feature = "orchard")]
+pub fn decrypt_transaction_orchard(
+    tx: &Transaction,
+    ufvks: &HashMap<AccountId, UnifiedFullViewingKey>,
+) -> Vec<DecryptedOutput<orchard::note::Note>> {
+    use orchard::{
+        keys::{PreparedIncomingViewingKey, Scope},
+        note_encryption::OrchardDomain,
+    };
+    use zcash_note_encryption::{try_note_decryption, try_output_recovery_with_ovk};
+
+    tx.orchard_bundle()
+        .iter()
+        .flat_map(|bundle| {
+            ufvks
+                .iter()
+                .flat_map(move |(account, ufvk)| {
+                    ufvk.orchard().into_iter().map(|fvk| (*account, fvk))
+                })
+                .flat_map(move |(account, fvk)| {
+                    let ivk_external =
+                        PreparedIncomingViewingKey::new(&fvk.to_ivk(Scope::External));
+                    let ivk_internal =
+                        PreparedIncomingViewingKey::new(&fvk.to_ivk(Scope::Internal));
+                    let ovk = fvk.to_ovk(Scope::External);
+
+                    bundle
+                        .actions()
+                        .iter()
+                        .enumerate()
+                        .flat_map(move |(index, action)| {
+                            let domain = OrchardDomain::for_action(action);
+                            try_note_decryption(&domain, &ivk_external, action)
+                                .map(|ret| (ret, TransferType::Incoming))
+                                .or_else(|| {
+                                    try_note_decryption(&domain, &ivk_internal, action)
+                                        .map(|ret| (ret, TransferType::WalletInternal))
+                                })
+                                .or_else(|| {
+                                    try_output_recovery_with_ovk(
+                                        &domain,
+                                        &ovk,
+                                        action,
+                                        action.cv_net(),
+                                        &action.encrypted_note().out_ciphertext,
+                                    )
+                                    .map(|ret| (ret, TransferType::Outgoing))
+                                })
+                                .into_iter()
+                                .map(move |((note, _, memo), transfer_type)| DecryptedOutput {
+                                    index,
+                                    note,
+                                    account,
+                                    memo: MemoBytes::from_bytes(&memo).expect("correct length"),
+                                    transfer_type,
+                                })
+                        })
+                })
+        })
+        .collect()
+}
@@ -1,12 +1,14 @@
 use std::fmt;
+use std::num::NonZeroUsize;
 
 use zcash_primitives::{
     consensus::{self, BlockHeight},
+    legacy::TransparentAddress,
     memo::MemoBytes,
     transaction::{
         components::{
             amount::{BalanceError, NonNegativeAmount},
-            OutPoint,
+            OutPoint, TxOut,
         },
         fees::{transparent, FeeRule},
     },
@@ -149,6 +151,9 @@ pub enum ChangeError<E, NoteRefT> {
         transparent: Vec<OutPoint>,
         /// The identifiers for Sapling inputs having no current economic value
         sapling: Vec<NoteRefT>,
+        /// The identifiers for Orchard inputs having no current economic value
+        #[cfg(feature = "orchard")]
+        orchard: Vec<NoteRefT>,
     },
     /// An error occurred that was specific to the change selection strategy in use.
     StrategyError(E),
@@ -169,9 +174,13 @@ impl<E, NoteRefT> ChangeError<E, NoteRefT> {
             ChangeError::DustInputs {
                 transparent,
                 sapling,
+                #[cfg(feature = "orchard")]
+                orchard,
             } => ChangeError::DustInputs {
                 transparent,
                 sapling,
+                #[cfg(feature = "orchard")]
+                orchard,
             },
             ChangeError::StrategyError(e) => ChangeError::StrategyError(f(e)),
             ChangeError::BundleError(e) => ChangeError::BundleError(e),
@@ -194,10 +203,17 @@ impl<CE: fmt::Display, N: fmt::Display> fmt::Display for ChangeError<CE, N> {
             ChangeError::DustInputs {
                 transparent,
                 sapling,
+                #[cfg(feature = "orchard")]
+                orchard,
             } => {
+                #[cfg(feature = "orchard")]
+                let orchard_len = orchard.len();
+                #[cfg(not(feature = "orchard"))]
+                let orchard_len = 0;
+
                 // we can't encode the UA to its string representation because we
                 // don't have network parameters here
-                write!(f, "Insufficient funds: {} dust inputs were present, but would cost more to spend than they are worth.", transparent.len() + sapling.len())
+                write!(f, "Insufficient funds: {} dust inputs were present, but would cost more to spend than they are worth.", transparent.len() + sapling.len() + orchard_len)
             }
             ChangeError::StrategyError(err) => {
                 write!(f, "{}", err)
@@ -270,6 +286,221 @@ impl Default for DustOutputPolicy {
     }
 }
 
+/// A policy describing how a [`ChangeStrategy`] should choose the shielded pool(s) to which
+/// change is sent.
+///
+/// Change is sent to the pool for which the value of the transaction's inputs most exceeds the
+/// value of its outputs, so that as little value as possible crosses between pools. When both
+/// pools are equally suitable, change is kept in the pool that receives the larger value of
+/// payments.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChangePoolPolicy {
+    fallback_pool: ShieldedProtocol,
+    allow_split: bool,
+}
+
+impl ChangePoolPolicy {
+    /// Constructs a new change pool policy.
+    ///
+    /// `fallback_pool` is the pool to which change is sent when the transaction neither spends
+    /// nor creates any shielded outputs, such as when shielding transparent funds. If
+    /// `allow_split` is `true`, then when the transaction spends more than it pays out in each
+    /// of several shielded pools, change may be split across those pools so that no value
+    /// crosses between them, at the cost of an additional change output.
+    pub fn new(fallback_pool: ShieldedProtocol, allow_split: bool) -> Self {
+        Self {
+            fallback_pool,
+            allow_split,
+        }
+    }
+
+    /// Returns the pool to which change is sent for transactions that do not involve any
+    /// shielded pool.
+    pub fn fallback_pool(&self) -> ShieldedProtocol {
+        self.fallback_pool
+    }
+
+    /// Returns whether change may be split across multiple shielded pools.
+    pub fn allow_split(&self) -> bool {
+        self.allow_split
+    }
+}
+
+impl Default for ChangePoolPolicy {
+    fn default() -> Self {
+        ChangePoolPolicy::new(ShieldedProtocol::Sapling, false)
+    }
+}
+
+/// A policy describing how a [`ChangeStrategy`] should split change into multiple outputs.
+///
+/// Splitting change into several notes allows a wallet to make several payments concurrently,
+/// rather than waiting for a single change note to be confirmed before it can be spent again.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SplitPolicy {
+    target_output_count: NonZeroUsize,
+    target_output_value: NonNegativeAmount,
+}
+
+impl SplitPolicy {
+    /// Constructs a new split policy.
+    ///
+    /// Change will be split into at most `target_output_count` outputs. Each output but the
+    /// last has a value of exactly `target_output_value`, and the last output receives the
+    /// remainder of the change, which is never less than `target_output_value`. As many
+    /// outputs are produced as the change can fund once the fee for the additional outputs has
+    /// been paid; if the change does not suffice for two outputs, a single change output is
+    /// produced.
+    ///
+    /// Under ZIP 317, an additional change output only increases the fee if it increases the
+    /// number of logical actions of the transaction beyond the grace actions and any padding
+    /// of the shielded bundle to which it is added.
+    pub fn new(target_output_count: NonZeroUsize, target_output_value: NonNegativeAmount) -> Self {
+        Self {
+            target_output_count,
+            target_output_value,
+        }
+    }
+
+    /// Constructs a split policy that never splits change.
+    pub fn single_output() -> Self {
+        Self::new(
+            NonZeroUsize::new(1).expect("1 is nonzero"),
+            NonNegativeAmount::ZERO,
+        )
+    }
+
+    /// Returns the maximum number of change outputs to produce.
+    pub fn target_output_count(&self) -> NonZeroUsize {
+        self.target_output_count
+    }
+
+    /// Returns the value of each change output other than the last, when change is split.
+    pub fn target_output_value(&self) -> NonNegativeAmount {
+        self.target_output_value
+    }
+}
+
+impl Default for SplitPolicy {
+    fn default() -> Self {
+        SplitPolicy::single_output()
+    }
+}
+
+/// The numbers of inputs and outputs of each kind in a transaction, for use in estimating the
+/// fee for the transaction without constructing it.
+///
+/// The numbers of Sapling spends and outputs and Orchard actions should be those that will
+/// appear in the transaction, including any padding added by the transaction builder.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TransactionShape {
+    transparent_inputs: usize,
+    transparent_outputs: usize,
+    sapling_spends: usize,
+    sapling_outputs: usize,
+    orchard_actions: usize,
+}
+
+impl TransactionShape {
+    /// Constructs a new transaction shape.
+    ///
+    /// Transparent inputs are assumed to spend P2PKH outputs, as required by ZIP 317.
+    pub fn new(
+        transparent_inputs: usize,
+        transparent_outputs: usize,
+        sapling_spends: usize,
+        sapling_outputs: usize,
+        orchard_actions: usize,
+    ) -> Self {
+        Self {
+            transparent_inputs,
+            transparent_outputs,
+            sapling_spends,
+            sapling_outputs,
+            orchard_actions,
+        }
+    }
+
+    /// Returns the number of P2PKH inputs to the transaction.
+    pub fn transparent_inputs(&self) -> usize {
+        self.transparent_inputs
+    }
+
+    /// Returns the number of transparent outputs of the transaction.
+    pub fn transparent_outputs(&self) -> usize {
+        self.transparent_outputs
+    }
+
+    /// Returns the number of Sapling spends in the transaction.
+    pub fn sapling_spends(&self) -> usize {
+        self.sapling_spends
+    }
+
+    /// Returns the number of Sapling outputs of the transaction.
+    pub fn sapling_outputs(&self) -> usize {
+        self.sapling_outputs
+    }
+
+    /// Returns the number of Orchard actions in the transaction.
+    pub fn orchard_actions(&self) -> usize {
+        self.orchard_actions
+    }
+}
+
+/// A placeholder P2PKH input, used in fee estimation.
+#[derive(Debug)]
+struct EstimatedInput {
+    outpoint: OutPoint,
+    coin: TxOut,
+}
+
+impl transparent::InputView for EstimatedInput {
+    fn outpoint(&self) -> &OutPoint {
+        &self.outpoint
+    }
+
+    fn coin(&self) -> &TxOut {
+        &self.coin
+    }
+}
+
+/// Computes the fee that the given fee rule requires for a transaction of the given shape.
+///
+/// This does not require access to a wallet, and so may be used to display fees before any
+/// notes have been selected. The values of inputs and outputs are not taken into account; to
+/// estimate the fee for a particular transaction request, including any change output that
+/// the request would require, use [`crate::data_api::wallet::estimate_fee`].
+pub fn estimate_fee<P: consensus::Parameters, F: FeeRule>(
+    params: &P,
+    target_height: BlockHeight,
+    fee_rule: &F,
+    shape: &TransactionShape,
+) -> Result<NonNegativeAmount, F::Error> {
+    let script_pubkey = TransparentAddress::PublicKeyHash([0; 20]).script();
+    let coin = TxOut {
+        value: NonNegativeAmount::ZERO,
+        script_pubkey,
+    };
+
+    let transparent_inputs = (0..shape.transparent_inputs)
+        .map(|n| EstimatedInput {
+            outpoint: OutPoint::new([0; 32], n as u32),
+            coin: coin.clone(),
+        })
+        .collect::<Vec<_>>();
+    let transparent_outputs = vec![coin; shape.transparent_outputs];
+
+    fee_rule.fee_required(
+        params,
+        target_height,
+        &transparent_inputs,
+        &transparent_outputs,
+        shape.sapling_spends,
+        shape.sapling_outputs,
+        shape.orchard_actions,
+    )
+}
+
 /// A trait that represents the ability to compute the suggested change and fees that must be paid
 /// by a transaction having a specified set of inputs and outputs.
 pub trait ChangeStrategy {
@@ -340,4 +571,20 @@ pub(crate) mod tests {
             self.value
         }
     }
+
+    #[cfg(feature = "orchard")]
+    pub(crate) struct TestOrchardInput {
+        pub note_id: u32,
+        pub value: NonNegativeAmount,
+    }
+
+    #[cfg(feature = "orchard")]
+    impl super::orchard::InputView<u32> for TestOrchardInput {
+        fn note_id(&self) -> &u32 {
+            &self.note_id
+        }
+        fn value(&self) -> NonNegativeAmount {
+            self.value
+        }
+    }
 }
@@ -2,7 +2,7 @@ use zcash_primitives::{
     consensus::{self, BlockHeight},
     memo::MemoBytes,
     transaction::{
-        components::amount::{BalanceError, NonNegativeAmount},
+        components::amount::{Amount, BalanceError, NonNegativeAmount},
         fees::{transparent, FeeRule},
     },
 };
@@ -10,20 +10,15 @@ use zcash_primitives::{
 use crate::ShieldedProtocol;
 
 use super::{
-    sapling as sapling_fees, ChangeError, ChangeValue, DustAction, DustOutputPolicy,
-    TransactionBalance,
+    sapling as sapling_fees, ChangeError, ChangePoolPolicy, ChangeValue, DustAction,
+    DustOutputPolicy, SplitPolicy, TransactionBalance,
 };
 
 #[cfg(feature = "orchard")]
 use super::orchard as orchard_fees;
 
 #[allow(clippy::too_many_arguments)]
-pub(crate) fn single_change_output_balance<
-    P: consensus::Parameters,
-    NoteRefT: Clone,
-    F: FeeRule,
-    E,
->(
+pub(crate) fn change_output_balance<P: consensus::Parameters, NoteRefT: Clone, F: FeeRule, E>(
     params: &P,
     fee_rule: &F,
     target_height: BlockHeight,
@@ -34,6 +29,8 @@ pub(crate) fn single_change_output_balance<
     dust_output_policy: &DustOutputPolicy,
     default_dust_threshold: NonNegativeAmount,
     change_memo: Option<MemoBytes>,
+    change_pool_policy: &ChangePoolPolicy,
+    split_policy: &SplitPolicy,
 ) -> Result<TransactionBalance, ChangeError<E, NoteRefT>>
 where
     E: From<F::Error> + From<BalanceError>,
@@ -84,58 +81,171 @@ where
     #[cfg(not(feature = "orchard"))]
     let orchard_out = NonNegativeAmount::ZERO;
 
-    // TODO: implement a less naive strategy for selecting the pool to which change will be sent.
-    #[cfg(feature = "orchard")]
-    #[allow(clippy::if_same_then_else)]
-    let (change_pool, sapling_change, orchard_change) =
-        if orchard_in.is_positive() || orchard_out.is_positive() {
-            // Send change to Orchard if we're spending any Orchard inputs or creating any Orchard outputs
-            (ShieldedProtocol::Orchard, 0, 1)
-        } else if sapling_in.is_positive() || sapling_out.is_positive() {
-            // Otherwise, send change to Sapling if we're spending any Sapling inputs or creating any
-            // Sapling outputs, so that we avoid pool-crossing.
-            (ShieldedProtocol::Sapling, 1, 0)
-        } else {
-            // For all other transactions, send change to Sapling.
-            // FIXME: Change this to Orchard once Orchard outputs are enabled.
-            (ShieldedProtocol::Sapling, 1, 0)
+    let fee_required = |sapling_change: usize,
+                        orchard_change: usize|
+     -> Result<NonNegativeAmount, ChangeError<E, NoteRefT>> {
+        #[cfg(feature = "orchard")]
+        let orchard_num_actions = orchard
+            .bundle_type()
+            .num_actions(
+                orchard.inputs().len(),
+                orchard.outputs().len() + orchard_change,
+            )
+            .map_err(ChangeError::BundleError)?;
+        #[cfg(not(feature = "orchard"))]
+        let orchard_num_actions = {
+            // Orchard change is never proposed without Orchard support.
+            debug_assert_eq!(orchard_change, 0);
+            0
         };
-    #[cfg(not(feature = "orchard"))]
-    let (change_pool, sapling_change) = (ShieldedProtocol::Sapling, 1);
 
+        fee_rule
+            .fee_required(
+                params,
+                target_height,
+                transparent_inputs,
+                transparent_outputs,
+                sapling
+                    .bundle_type()
+                    .num_spends(sapling.inputs().len())
+                    .map_err(ChangeError::BundleError)?,
+                sapling
+                    .bundle_type()
+                    .num_outputs(
+                        sapling.inputs().len(),
+                        sapling.outputs().len() + sapling_change,
+                    )
+                    .map_err(ChangeError::BundleError)?,
+                orchard_num_actions,
+            )
+            .map_err(|fee_error| ChangeError::StrategyError(E::from(fee_error)))
+    };
+
+    let dust_threshold = dust_output_policy
+        .dust_threshold()
+        .unwrap_or(default_dust_threshold);
+
+    let total_in = (t_in + sapling_in + orchard_in).ok_or_else(overflow)?;
+
+    // The net value that each shielded pool contributes to the transaction. Change that is
+    // sent to a pool having a positive surplus does not cross the pool boundary.
+    let sapling_surplus =
+        (Amount::from(sapling_in) - Amount::from(sapling_out)).ok_or_else(overflow)?;
     #[cfg(feature = "orchard")]
-    let orchard_num_actions = orchard
-        .bundle_type()
-        .num_actions(
-            orchard.inputs().len(),
-            orchard.outputs().len() + orchard_change,
-        )
-        .map_err(ChangeError::BundleError)?;
+    let orchard_surplus =
+        (Amount::from(orchard_in) - Amount::from(orchard_out)).ok_or_else(overflow)?;
+
+    // If both pools have a surplus and the policy permits it, return each pool's surplus to that
+    // pool as change, so that only the fee and any transparent value cross pool boundaries.
+    #[cfg(feature = "orchard")]
+    if change_pool_policy.allow_split()
+        && sapling_surplus.is_positive()
+        && orchard_surplus.is_positive()
+    {
+        let fee_amount = fee_required(1, 1)?;
+        let total_out = (t_out + sapling_out + orchard_out + fee_amount).ok_or_else(overflow)?;
+
+        // Any transparent value and the fee are attributed to the pool having the larger
+        // surplus, which receives the remainder of the change.
+        let (primary_pool, secondary_pool, secondary_surplus) =
+            if orchard_surplus >= sapling_surplus {
+                (
+                    ShieldedProtocol::Orchard,
+                    ShieldedProtocol::Sapling,
+                    sapling_surplus,
+                )
+            } else {
+                (
+                    ShieldedProtocol::Sapling,
+                    ShieldedProtocol::Orchard,
+                    orchard_surplus,
+                )
+            };
+        let secondary_change =
+            NonNegativeAmount::try_from(secondary_surplus).map_err(|_| underflow())?;
+        let primary_change = (total_in - total_out).and_then(|change| change - secondary_change);
+
+        if let Some(primary_change) = primary_change {
+            if primary_change >= dust_threshold && secondary_change >= dust_threshold {
+                return TransactionBalance::new(
+                    vec![
+                        ChangeValue::new(primary_pool, primary_change, change_memo.clone()),
+                        ChangeValue::new(secondary_pool, secondary_change, change_memo),
+                    ],
+                    fee_amount,
+                )
+                .map_err(|_| overflow());
+            }
+        }
+    }
+
+    #[cfg(feature = "orchard")]
+    let change_pool = {
+        let sapling_involved = sapling_in.is_positive() || sapling_out.is_positive();
+        let orchard_involved = orchard_in.is_positive() || orchard_out.is_positive();
+        match (sapling_involved, orchard_involved) {
+            // For a transaction that does not involve any shielded pool, the policy decides.
+            (false, false) => change_pool_policy.fallback_pool(),
+            // If only a single pool is involved, keep change in that pool to avoid crossing.
+            (true, false) => ShieldedProtocol::Sapling,
+            (false, true) => ShieldedProtocol::Orchard,
+            // Otherwise, send change to the pool having the greatest surplus, so that the least
+            // value crosses between pools. In the case of a tie, keep change in the pool that
+            // receives the greater value of payments.
+            (true, true) => {
+                if orchard_surplus > sapling_surplus
+                    || (orchard_surplus == sapling_surplus && orchard_out >= sapling_out)
+                {
+                    ShieldedProtocol::Orchard
+                } else {
+                    ShieldedProtocol::Sapling
+                }
+            }
+        }
+    };
+    // Without Orchard support, Sapling is the only pool to which change may be sent.
     #[cfg(not(feature = "orchard"))]
-    let orchard_num_actions = 0;
-
-    let fee_amount = fee_rule
-        .fee_required(
-            params,
-            target_height,
-            transparent_inputs,
-            transparent_outputs,
-            sapling
-                .bundle_type()
-                .num_spends(sapling.inputs().len())
-                .map_err(ChangeError::BundleError)?,
-            sapling
-                .bundle_type()
-                .num_outputs(
-                    sapling.inputs().len(),
-                    sapling.outputs().len() + sapling_change,
+    let change_pool = {
+        let _ = (change_pool_policy, sapling_surplus);
+        ShieldedProtocol::Sapling
+    };
+
+    let change_output_counts = |count: usize| match change_pool {
+        ShieldedProtocol::Sapling => (count, 0),
+        #[cfg(zcash_unstable = "orchard")]
+        ShieldedProtocol::Orchard => (0, count),
+    };
+
+    // If the split policy calls for multiple change outputs, create as many as possible (up to
+    // the policy's target count) such that each has at least the policy's target value. Each
+    // additional output may increase the fee, so the fee is recomputed for each candidate count.
+    let target_value = split_policy.target_output_value();
+    if target_value.is_positive() && target_value >= dust_threshold {
+        for count in (2..=split_policy.target_output_count().get()).rev() {
+            let (sapling_change, orchard_change) = change_output_counts(count);
+            let fee_amount = fee_required(sapling_change, orchard_change)?;
+            let total_out =
+                (t_out + sapling_out + orchard_out + fee_amount).ok_or_else(overflow)?;
+
+            let split_total = (target_value * (count - 1)).ok_or_else(overflow)?;
+            let last_value = (total_in - total_out).and_then(|change| change - split_total);
+            if let Some(last_value) = last_value.filter(|v| *v >= target_value) {
+                return TransactionBalance::new(
+                    std::iter::repeat(target_value)
+                        .take(count - 1)
+                        .chain(std::iter::once(last_value))
+                        .map(|value| ChangeValue::new(change_pool, value, change_memo.clone()))
+                        .collect(),
+                    fee_amount,
                 )
-                .map_err(ChangeError::BundleError)?,
-            orchard_num_actions,
-        )
-        .map_err(|fee_error| ChangeError::StrategyError(E::from(fee_error)))?;
+                .map_err(|_| overflow());
+            }
+        }
+    }
+
+    let (sapling_change, orchard_change) = change_output_counts(1);
+    let fee_amount = fee_required(sapling_change, orchard_change)?;
 
-    let total_in = (t_in + sapling_in + orchard_in).ok_or_else(overflow)?;
     let total_out = (t_out + sapling_out + orchard_out + fee_amount).ok_or_else(overflow)?;
 
     let proposed_change = (total_in - total_out).ok_or(ChangeError::InsufficientFunds {
@@ -145,38 +255,32 @@ where
 
     if proposed_change.is_zero() {
         TransactionBalance::new(vec![], fee_amount).map_err(|_| overflow())
-    } else {
-        let dust_threshold = dust_output_policy
-            .dust_threshold()
-            .unwrap_or(default_dust_threshold);
-
-        if proposed_change < dust_threshold {
-            match dust_output_policy.action() {
-                DustAction::Reject => {
-                    let shortfall = (dust_threshold - proposed_change).ok_or_else(underflow)?;
-
-                    Err(ChangeError::InsufficientFunds {
-                        available: total_in,
-                        required: (total_in + shortfall).ok_or_else(overflow)?,
-                    })
-                }
-                DustAction::AllowDustChange => TransactionBalance::new(
-                    vec![ChangeValue::new(change_pool, proposed_change, change_memo)],
-                    fee_amount,
-                )
-                .map_err(|_| overflow()),
-                DustAction::AddDustToFee => TransactionBalance::new(
-                    vec![],
-                    (fee_amount + proposed_change).ok_or_else(overflow)?,
-                )
-                .map_err(|_| overflow()),
+    } else if proposed_change < dust_threshold {
+        match dust_output_policy.action() {
+            DustAction::Reject => {
+                let shortfall = (dust_threshold - proposed_change).ok_or_else(underflow)?;
+
+                Err(ChangeError::InsufficientFunds {
+                    available: total_in,
+                    required: (total_in + shortfall).ok_or_else(overflow)?,
+                })
             }
-        } else {
-            TransactionBalance::new(
+            DustAction::AllowDustChange => TransactionBalance::new(
                 vec![ChangeValue::new(change_pool, proposed_change, change_memo)],
                 fee_amount,
             )
-            .map_err(|_| overflow())
+            .map_err(|_| overflow()),
+            DustAction::AddDustToFee => TransactionBalance::new(
+                vec![],
+                (fee_amount + proposed_change).ok_or_else(overflow)?,
+            )
+            .map_err(|_| overflow()),
         }
+    } else {
+        TransactionBalance::new(
+            vec![ChangeValue::new(change_pool, proposed_change, change_memo)],
+            fee_amount,
+        )
+        .map_err(|_| overflow())
     }
 }
@@ -10,27 +10,37 @@ use zcash_primitives::{
 };
 
 use super::{
-    common::single_change_output_balance, sapling as sapling_fees, ChangeError, ChangeStrategy,
-    DustOutputPolicy, TransactionBalance,
+    common::change_output_balance, sapling as sapling_fees, ChangeError, ChangePoolPolicy,
+    ChangeStrategy, DustOutputPolicy, SplitPolicy, TransactionBalance,
 };
 
 #[cfg(feature = "orchard")]
 use super::orchard as orchard_fees;
 
-/// A change strategy that proposes change as a single output to the most current supported
-/// shielded pool and delegates fee calculation to the provided fee rule.
+/// A change strategy that proposes change as a single output and delegates fee calculation to
+/// the provided fee rule.
+///
+/// The pool to which change is sent is chosen according to the strategy's [`ChangePoolPolicy`].
+/// If that policy permits change to be split across pools, a change output may instead be
+/// proposed for each shielded pool from which the transaction draws a surplus.
 pub struct SingleOutputChangeStrategy {
     fee_rule: FixedFeeRule,
     change_memo: Option<MemoBytes>,
+    change_pool_policy: ChangePoolPolicy,
 }
 
 impl SingleOutputChangeStrategy {
     /// Constructs a new [`SingleOutputChangeStrategy`] with the specified fee rule
-    /// and change memo.
-    pub fn new(fee_rule: FixedFeeRule, change_memo: Option<MemoBytes>) -> Self {
+    /// change memo, and change pool policy.
+    pub fn new(
+        fee_rule: FixedFeeRule,
+        change_memo: Option<MemoBytes>,
+        change_pool_policy: ChangePoolPolicy,
+    ) -> Self {
         Self {
             fee_rule,
             change_memo,
+            change_pool_policy,
         }
     }
 }
@@ -53,7 +63,7 @@ impl ChangeStrategy for SingleOutputChangeStrategy {
         #[cfg(feature = "orchard")] orchard: &impl orchard_fees::BundleView<NoteRefT>,
         dust_output_policy: &DustOutputPolicy,
     ) -> Result<TransactionBalance, ChangeError<Self::Error, NoteRefT>> {
-        single_change_output_balance(
+        change_output_balance(
             params,
             &self.fee_rule,
             target_height,
@@ -65,6 +75,8 @@ impl ChangeStrategy for SingleOutputChangeStrategy {
             dust_output_policy,
             self.fee_rule().fixed_fee(),
             self.change_memo.clone(),
+            &self.change_pool_policy,
+            &SplitPolicy::single_output(),
         )
     }
 }
@@ -87,7 +99,7 @@ mod tests {
         data_api::wallet::input_selection::SaplingPayment,
         fees::{
             tests::{TestSaplingInput, TestTransparentInput},
-            ChangeError, ChangeStrategy, ChangeValue, DustOutputPolicy,
+            ChangeError, ChangePoolPolicy, ChangeStrategy, ChangeValue, DustOutputPolicy,
         },
     };
 
@@ -95,7 +107,8 @@ mod tests {
     fn change_without_dust() {
         #[allow(deprecated)]
         let fee_rule = FixedFeeRule::standard();
-        let change_strategy = SingleOutputChangeStrategy::new(fee_rule, None);
+        let change_strategy =
+            SingleOutputChangeStrategy::new(fee_rule, None, ChangePoolPolicy::default());
 
         // spend a single Sapling note that is sufficient to pay the fee
         let result = change_strategy.compute_balance(
@@ -136,7 +149,8 @@ mod tests {
     fn dust_change() {
         #[allow(deprecated)]
         let fee_rule = FixedFeeRule::standard();
-        let change_strategy = SingleOutputChangeStrategy::new(fee_rule, None);
+        let change_strategy =
+            SingleOutputChangeStrategy::new(fee_rule, None, ChangePoolPolicy::default());
 
         // spend a single Sapling note that is sufficient to pay the fee
         let result = change_strategy.compute_balance(
@@ -15,27 +15,37 @@ use zcash_primitives::{
 };
 
 use super::{
-    fixed, sapling as sapling_fees, zip317, ChangeError, ChangeStrategy, DustOutputPolicy,
-    TransactionBalance,
+    fixed, sapling as sapling_fees, zip317, ChangeError, ChangePoolPolicy, ChangeStrategy,
+    DustOutputPolicy, TransactionBalance,
 };
 
 #[cfg(feature = "orchard")]
 use super::orchard as orchard_fees;
 
-/// A change strategy that proposes change as a single output to the most current supported
-/// shielded pool and delegates fee calculation to the provided fee rule.
+/// A change strategy that proposes change as a single output and delegates fee calculation to
+/// the provided fee rule.
+///
+/// The pool to which change is sent is chosen according to the strategy's [`ChangePoolPolicy`].
+/// If that policy permits change to be split across pools, a change output may instead be
+/// proposed for each shielded pool from which the transaction draws a surplus.
 pub struct SingleOutputChangeStrategy {
     fee_rule: StandardFeeRule,
     change_memo: Option<MemoBytes>,
+    change_pool_policy: ChangePoolPolicy,
 }
 
 impl SingleOutputChangeStrategy {
     /// Constructs a new [`SingleOutputChangeStrategy`] with the specified ZIP 317
-    /// fee parameters.
-    pub fn new(fee_rule: StandardFeeRule, change_memo: Option<MemoBytes>) -> Self {
+    /// fee parameters, change memo, and change pool policy.
+    pub fn new(
+        fee_rule: StandardFeeRule,
+        change_memo: Option<MemoBytes>,
+        change_pool_policy: ChangePoolPolicy,
+    ) -> Self {
         Self {
             fee_rule,
             change_memo,
+            change_pool_policy,
         }
     }
 }
@@ -63,6 +73,7 @@ impl ChangeStrategy for SingleOutputChangeStrategy {
             StandardFeeRule::PreZip313 => fixed::SingleOutputChangeStrategy::new(
                 FixedFeeRule::non_standard(NonNegativeAmount::const_from_u64(10000)),
                 self.change_memo.clone(),
+                self.change_pool_policy,
             )
             .compute_balance(
                 params,
@@ -78,6 +89,7 @@ impl ChangeStrategy for SingleOutputChangeStrategy {
             StandardFeeRule::Zip313 => fixed::SingleOutputChangeStrategy::new(
                 FixedFeeRule::non_standard(NonNegativeAmount::const_from_u64(1000)),
                 self.change_memo.clone(),
+                self.change_pool_policy,
             )
             .compute_balance(
                 params,
@@ -93,6 +105,7 @@ impl ChangeStrategy for SingleOutputChangeStrategy {
             StandardFeeRule::Zip317 => zip317::SingleOutputChangeStrategy::new(
                 Zip317FeeRule::standard(),
                 self.change_memo.clone(),
+                self.change_pool_policy,
             )
             .compute_balance(
                 params,
@@ -14,27 +14,37 @@ use zcash_primitives::{
 };
 
 use super::{
-    common::single_change_output_balance, sapling as sapling_fees, ChangeError, ChangeStrategy,
-    DustOutputPolicy, TransactionBalance,
+    common::change_output_balance, sapling as sapling_fees, ChangeError, ChangePoolPolicy,
+    ChangeStrategy, DustOutputPolicy, SplitPolicy, TransactionBalance,
 };
 
 #[cfg(feature = "orchard")]
 use super::orchard as orchard_fees;
 
-/// A change strategy that proposes change as a single output to the most current supported
-/// shielded pool and delegates fee calculation to the provided fee rule.
+/// A change strategy that proposes change as a single output and delegates fee calculation to
+/// the provided fee rule.
+///
+/// The pool to which change is sent is chosen according to the strategy's [`ChangePoolPolicy`].
+/// If that policy permits change to be split across pools, a change output may instead be
+/// proposed for each shielded pool from which the transaction draws a surplus.
 pub struct SingleOutputChangeStrategy {
     fee_rule: Zip317FeeRule,
     change_memo: Option<MemoBytes>,
+    change_pool_policy: ChangePoolPolicy,
 }
 
 impl SingleOutputChangeStrategy {
     /// Constructs a new [`SingleOutputChangeStrategy`] with the specified ZIP 317
-    /// fee parameters and change memo.
-    pub fn new(fee_rule: Zip317FeeRule, change_memo: Option<MemoBytes>) -> Self {
+    /// fee parameters, change memo, and change pool policy.
+    pub fn new(
+        fee_rule: Zip317FeeRule,
+        change_memo: Option<MemoBytes>,
+        change_pool_policy: ChangePoolPolicy,
+    ) -> Self {
         Self {
             fee_rule,
             change_memo,
+            change_pool_policy,
         }
     }
 }
@@ -57,83 +67,97 @@ impl ChangeStrategy for SingleOutputChangeStrategy {
         #[cfg(feature = "orchard")] orchard: &impl orchard_fees::BundleView<NoteRefT>,
         dust_output_policy: &DustOutputPolicy,
     ) -> Result<TransactionBalance, ChangeError<Self::Error, NoteRefT>> {
-        let mut transparent_dust: Vec<_> = transparent_inputs
-            .iter()
-            .filter_map(|i| {
-                // for now, we're just assuming p2pkh inputs, so we don't check the size of the input
-                // script
-                if i.coin().value < self.fee_rule.marginal_fee() {
-                    Some(i.outpoint().clone())
-                } else {
-                    None
-                }
-            })
-            .collect();
-
-        let mut sapling_dust: Vec<_> = sapling
-            .inputs()
-            .iter()
-            .filter_map(|i| {
-                if sapling_fees::InputView::<NoteRefT>::value(i) < self.fee_rule.marginal_fee() {
-                    Some(sapling_fees::InputView::<NoteRefT>::note_id(i).clone())
-                } else {
-                    None
-                }
-            })
-            .collect();
-
-        // Depending on the shape of the transaction, we may be able to spend up to
-        // `grace_actions - 1` dust inputs. If we don't have any dust inputs though,
-        // we don't need to worry about any of that.
-        if !(transparent_dust.is_empty() && sapling_dust.is_empty()) {
-            let t_non_dust = transparent_inputs.len() - transparent_dust.len();
-            let t_allowed_dust = transparent_outputs.len().saturating_sub(t_non_dust);
-
-            // We add one to the sapling outputs for the (single) change output Note that this
-            // means that wallet-internal shielding transactions are an opportunity to spend a dust
-            // note.
-            let s_non_dust = sapling.inputs().len() - sapling_dust.len();
-            let s_allowed_dust = (sapling.outputs().len() + 1).saturating_sub(s_non_dust);
-
-            let available_grace_inputs = self
-                .fee_rule
-                .grace_actions()
-                .saturating_sub(t_non_dust)
-                .saturating_sub(s_non_dust);
-
-            let mut t_disallowed_dust = transparent_dust.len().saturating_sub(t_allowed_dust);
-            let mut s_disallowed_dust = sapling_dust.len().saturating_sub(s_allowed_dust);
-
-            if available_grace_inputs > 0 {
-                // If we have available grace inputs, allocate them first to transparent dust
-                // and then to Sapling dust. The caller has provided inputs that it is willing
-                // to spend, so we don't need to consider privacy effects at this layer.
-                let t_grace_dust = available_grace_inputs.saturating_sub(t_disallowed_dust);
-                t_disallowed_dust = t_disallowed_dust.saturating_sub(t_grace_dust);
-
-                let s_grace_dust = available_grace_inputs
-                    .saturating_sub(t_grace_dust)
-                    .saturating_sub(s_disallowed_dust);
-                s_disallowed_dust = s_disallowed_dust.saturating_sub(s_grace_dust);
-            }
+        check_dust_inputs(
+            &self.fee_rule,
+            transparent_inputs,
+            transparent_outputs,
+            sapling,
+            #[cfg(feature = "orchard")]
+            orchard,
+            1,
+        )?;
 
-            // Truncate the lists of inputs to be disregarded in input selection to just the
-            // disallowed lengths. This has the effect of prioritizing inputs for inclusion by the
-            // order of the original input slices, with the most preferred inputs first.
-            transparent_dust.reverse();
-            transparent_dust.truncate(t_disallowed_dust);
-            sapling_dust.reverse();
-            sapling_dust.truncate(s_disallowed_dust);
-
-            if !(transparent_dust.is_empty() && sapling_dust.is_empty()) {
-                return Err(ChangeError::DustInputs {
-                    transparent: transparent_dust,
-                    sapling: sapling_dust,
-                });
-            }
+        change_output_balance(
+            params,
+            &self.fee_rule,
+            target_height,
+            transparent_inputs,
+            transparent_outputs,
+            sapling,
+            #[cfg(feature = "orchard")]
+            orchard,
+            dust_output_policy,
+            self.fee_rule.marginal_fee(),
+            self.change_memo.clone(),
+            &self.change_pool_policy,
+            &SplitPolicy::single_output(),
+        )
+    }
+}
+
+/// A change strategy that may split change into multiple outputs, as determined by a
+/// [`SplitPolicy`], and delegates fee calculation to the provided fee rule.
+///
+/// Each additional change output is a logical action under ZIP 317 once the grace actions have
+/// been exhausted, so the fee for the transaction accounts for the number of change outputs
+/// produced. The pool to which change is sent is chosen according to the strategy's
+/// [`ChangePoolPolicy`]; change is only split within a single pool.
+pub struct MultiOutputChangeStrategy {
+    fee_rule: Zip317FeeRule,
+    change_memo: Option<MemoBytes>,
+    change_pool_policy: ChangePoolPolicy,
+    split_policy: SplitPolicy,
+}
+
+impl MultiOutputChangeStrategy {
+    /// Constructs a new [`MultiOutputChangeStrategy`] with the specified ZIP 317
+    /// fee parameters, change memo, change pool policy, and split policy.
+    ///
+    /// The change memo, if any, is included in every change output.
+    pub fn new(
+        fee_rule: Zip317FeeRule,
+        change_memo: Option<MemoBytes>,
+        change_pool_policy: ChangePoolPolicy,
+        split_policy: SplitPolicy,
+    ) -> Self {
+        Self {
+            fee_rule,
+            change_memo,
+            change_pool_policy,
+            split_policy,
         }
+    }
+}
 
-        single_change_output_balance(
+impl ChangeStrategy for MultiOutputChangeStrategy {
+    type FeeRule = Zip317FeeRule;
+    type Error = Zip317FeeError;
+
+    fn fee_rule(&self) -> &Self::FeeRule {
+        &self.fee_rule
+    }
+
+    fn compute_balance<P: consensus::Parameters, NoteRefT: Clone>(
+        &self,
+        params: &P,
+        target_height: BlockHeight,
+        transparent_inputs: &[impl transparent::InputView],
+        transparent_outputs: &[impl transparent::OutputView],
+        sapling: &impl sapling_fees::BundleView<NoteRefT>,
+        #[cfg(feature = "orchard")] orchard: &impl orchard_fees::BundleView<NoteRefT>,
+        dust_output_policy: &DustOutputPolicy,
+    ) -> Result<TransactionBalance, ChangeError<Self::Error, NoteRefT>> {
+        check_dust_inputs(
+            &self.fee_rule,
+            transparent_inputs,
+            transparent_outputs,
+            sapling,
+            #[cfg(feature = "orchard")]
+            orchard,
+            self.split_policy.target_output_count().get(),
+        )?;
+
+        change_output_balance(
             params,
             &self.fee_rule,
             target_height,
@@ -145,10 +169,129 @@ impl ChangeStrategy for SingleOutputChangeStrategy {
             dust_output_policy,
             self.fee_rule.marginal_fee(),
             self.change_memo.clone(),
+            &self.change_pool_policy,
+            &self.split_policy,
         )
     }
 }
 
+/// Returns [`ChangeError::DustInputs`] if the provided inputs include dust inputs (inputs whose
+/// value is less than the marginal fee of spending them) beyond those that can be spent for
+/// free in the grace actions of the transaction.
+///
+/// `change_output_count` is the maximum number of change outputs that the change strategy may
+/// add to the transaction.
+fn check_dust_inputs<NoteRefT: Clone>(
+    fee_rule: &Zip317FeeRule,
+    transparent_inputs: &[impl transparent::InputView],
+    transparent_outputs: &[impl transparent::OutputView],
+    sapling: &impl sapling_fees::BundleView<NoteRefT>,
+    #[cfg(feature = "orchard")] orchard: &impl orchard_fees::BundleView<NoteRefT>,
+    change_output_count: usize,
+) -> Result<(), ChangeError<Zip317FeeError, NoteRefT>> {
+    let mut transparent_dust: Vec<_> = transparent_inputs
+        .iter()
+        .filter_map(|i| {
+            // for now, we're just assuming p2pkh inputs, so we don't check the size of the input
+            // script
+            if i.coin().value < fee_rule.marginal_fee() {
+                Some(i.outpoint().clone())
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    let mut sapling_dust: Vec<_> = sapling
+        .inputs()
+        .iter()
+        .filter_map(|i| {
+            if sapling_fees::InputView::<NoteRefT>::value(i) < fee_rule.marginal_fee() {
+                Some(sapling_fees::InputView::<NoteRefT>::note_id(i).clone())
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    #[cfg(feature = "orchard")]
+    let mut orchard_dust: Vec<_> = orchard
+        .inputs()
+        .iter()
+        .filter_map(|i| {
+            if orchard_fees::InputView::<NoteRefT>::value(i) < fee_rule.marginal_fee() {
+                Some(orchard_fees::InputView::<NoteRefT>::note_id(i).clone())
+            } else {
+                None
+            }
+        })
+        .collect();
+    #[cfg(not(feature = "orchard"))]
+    let mut orchard_dust: Vec<NoteRefT> = vec![];
+
+    // Depending on the shape of the transaction, we may be able to spend up to
+    // `grace_actions - 1` dust inputs. If we don't have any dust inputs though,
+    // we don't need to worry about any of that.
+    if !(transparent_dust.is_empty() && sapling_dust.is_empty() && orchard_dust.is_empty()) {
+        let t_non_dust = transparent_inputs.len() - transparent_dust.len();
+        let t_allowed_dust = transparent_outputs.len().saturating_sub(t_non_dust);
+
+        // We add the change outputs to the outputs of each shielded pool, as change may be
+        // sent to either pool. Note that this means that wallet-internal shielding
+        // transactions are an opportunity to spend a dust note.
+        let s_non_dust = sapling.inputs().len() - sapling_dust.len();
+        let s_allowed_dust =
+            (sapling.outputs().len() + change_output_count).saturating_sub(s_non_dust);
+
+        #[cfg(feature = "orchard")]
+        let (o_inputs, o_outputs) = (orchard.inputs().len(), orchard.outputs().len());
+        #[cfg(not(feature = "orchard"))]
+        let (o_inputs, o_outputs) = (0, 0);
+        let o_non_dust = o_inputs - orchard_dust.len();
+        let o_allowed_dust = (o_outputs + change_output_count).saturating_sub(o_non_dust);
+
+        let mut available_grace_inputs = fee_rule
+            .grace_actions()
+            .saturating_sub(t_non_dust)
+            .saturating_sub(s_non_dust)
+            .saturating_sub(o_non_dust);
+
+        // If we have available grace inputs, allocate them first to transparent dust, then
+        // to Sapling dust, and then to Orchard dust. The caller has provided inputs that it
+        // is willing to spend, so we don't need to consider privacy effects at this layer.
+        let mut disallowed_dust = |dust_len: usize, allowed_dust: usize| {
+            let disallowed = dust_len.saturating_sub(allowed_dust);
+            let grace_dust = disallowed.min(available_grace_inputs);
+            available_grace_inputs -= grace_dust;
+            disallowed - grace_dust
+        };
+        let t_disallowed_dust = disallowed_dust(transparent_dust.len(), t_allowed_dust);
+        let s_disallowed_dust = disallowed_dust(sapling_dust.len(), s_allowed_dust);
+        let o_disallowed_dust = disallowed_dust(orchard_dust.len(), o_allowed_dust);
+
+        // Truncate the lists of inputs to be disregarded in input selection to just the
+        // disallowed lengths. This has the effect of prioritizing inputs for inclusion by the
+        // order of the original input slices, with the most preferred inputs first.
+        transparent_dust.reverse();
+        transparent_dust.truncate(t_disallowed_dust);
+        sapling_dust.reverse();
+        sapling_dust.truncate(s_disallowed_dust);
+        orchard_dust.reverse();
+        orchard_dust.truncate(o_disallowed_dust);
+
+        if !(transparent_dust.is_empty() && sapling_dust.is_empty() && orchard_dust.is_empty()) {
+            return Err(ChangeError::DustInputs {
+                transparent: transparent_dust,
+                sapling: sapling_dust,
+                #[cfg(feature = "orchard")]
+                orchard: orchard_dust,
+            });
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -159,22 +302,36 @@ mod tests {
         legacy::Script,
         transaction::{
             components::{amount::NonNegativeAmount, transparent::TxOut},
-            fees::zip317::FeeRule as Zip317FeeRule,
+            fees::{zip317::FeeRule as Zip317FeeRule, StandardFeeRule},
         },
     };
 
-    use super::SingleOutputChangeStrategy;
+    use std::num::NonZeroUsize;
+
+    use super::{MultiOutputChangeStrategy, SingleOutputChangeStrategy};
     use crate::{
         data_api::wallet::input_selection::SaplingPayment,
         fees::{
+            estimate_fee,
             tests::{TestSaplingInput, TestTransparentInput},
-            ChangeError, ChangeStrategy, ChangeValue, DustOutputPolicy,
+            ChangeError, ChangePoolPolicy, ChangeStrategy, ChangeValue, DustOutputPolicy,
+            SplitPolicy, TransactionShape,
         },
     };
 
+    #[cfg(feature = "orchard")]
+    use crate::{
+        data_api::wallet::input_selection::OrchardPayment, fees::tests::TestOrchardInput,
+        ShieldedProtocol,
+    };
+
     #[test]
     fn change_without_dust() {
-        let change_strategy = SingleOutputChangeStrategy::new(Zip317FeeRule::standard(), None);
+        let change_strategy = SingleOutputChangeStrategy::new(
+            Zip317FeeRule::standard(),
+            None,
+            ChangePoolPolicy::default(),
+        );
 
         // spend a single Sapling note that is sufficient to pay the fee
         let result = change_strategy.compute_balance(
@@ -213,7 +370,11 @@ mod tests {
 
     #[test]
     fn change_with_transparent_payments() {
-        let change_strategy = SingleOutputChangeStrategy::new(Zip317FeeRule::standard(), None);
+        let change_strategy = SingleOutputChangeStrategy::new(
+            Zip317FeeRule::standard(),
+            None,
+            ChangePoolPolicy::default(),
+        );
 
         // spend a single Sapling note that is sufficient to pay the fee
         let result = change_strategy.compute_balance(
@@ -252,7 +413,11 @@ mod tests {
 
     #[test]
     fn change_with_allowable_dust() {
-        let change_strategy = SingleOutputChangeStrategy::new(Zip317FeeRule::standard(), None);
+        let change_strategy = SingleOutputChangeStrategy::new(
+            Zip317FeeRule::standard(),
+            None,
+            ChangePoolPolicy::default(),
+        );
 
         // spend a single Sapling note that is sufficient to pay the fee
         let result = change_strategy.compute_balance(
@@ -296,7 +461,11 @@ mod tests {
 
     #[test]
     fn change_with_disallowed_dust() {
-        let change_strategy = SingleOutputChangeStrategy::new(Zip317FeeRule::standard(), None);
+        let change_strategy = SingleOutputChangeStrategy::new(
+            Zip317FeeRule::standard(),
+            None,
+            ChangePoolPolicy::default(),
+        );
 
         // spend a single Sapling note that is sufficient to pay the fee
         let result = change_strategy.compute_balance(
@@ -342,4 +511,366 @@ mod tests {
             Err(ChangeError::DustInputs { sapling, .. }) if sapling == vec![2]
         );
     }
+
+    #[test]
+    fn change_split_into_multiple_outputs() {
+        let change_strategy = MultiOutputChangeStrategy::new(
+            Zip317FeeRule::standard(),
+            None,
+            ChangePoolPolicy::default(),
+            SplitPolicy::new(
+                NonZeroUsize::new(3).unwrap(),
+                NonNegativeAmount::const_from_u64(10000),
+            ),
+        );
+
+        let compute_balance = |change_strategy: &MultiOutputChangeStrategy| {
+            change_strategy.compute_balance(
+                &Network::TestNetwork,
+                Network::TestNetwork
+                    .activation_height(NetworkUpgrade::Nu5)
+                    .unwrap(),
+                &Vec::<TestTransparentInput>::new(),
+                &Vec::<TxOut>::new(),
+                &(
+                    sapling::builder::BundleType::DEFAULT,
+                    &[TestSaplingInput {
+                        note_id: 0,
+                        value: NonNegativeAmount::const_from_u64(100000),
+                    }][..],
+                    &[SaplingPayment::new(NonNegativeAmount::const_from_u64(
+                        10000,
+                    ))][..],
+                ),
+                #[cfg(feature = "orchard")]
+                &(
+                    orchard::builder::BundleType::DEFAULT,
+                    &Vec::<Infallible>::new()[..],
+                    &Vec::<Infallible>::new()[..],
+                ),
+                &DustOutputPolicy::default(),
+            )
+        };
+
+        // The change is split into three outputs, paying for four logical actions; the
+        // remainder of the change is added to the last output.
+        assert_matches!(
+            compute_balance(&change_strategy),
+            Ok(balance) if
+                balance.proposed_change() == [
+                    ChangeValue::sapling(NonNegativeAmount::const_from_u64(10000), None),
+                    ChangeValue::sapling(NonNegativeAmount::const_from_u64(10000), None),
+                    ChangeValue::sapling(NonNegativeAmount::const_from_u64(50000), None),
+                ] &&
+                balance.fee_required() == NonNegativeAmount::const_from_u64(20000)
+        );
+
+        // If the change cannot fund three outputs of the target value, fewer outputs are
+        // produced.
+        let change_strategy = MultiOutputChangeStrategy::new(
+            Zip317FeeRule::standard(),
+            None,
+            ChangePoolPolicy::default(),
+            SplitPolicy::new(
+                NonZeroUsize::new(3).unwrap(),
+                NonNegativeAmount::const_from_u64(30000),
+            ),
+        );
+        assert_matches!(
+            compute_balance(&change_strategy),
+            Ok(balance) if
+                balance.proposed_change() == [
+                    ChangeValue::sapling(NonNegativeAmount::const_from_u64(30000), None),
+                    ChangeValue::sapling(NonNegativeAmount::const_from_u64(45000), None),
+                ] &&
+                balance.fee_required() == NonNegativeAmount::const_from_u64(15000)
+        );
+    }
+
+    #[test]
+    fn change_split_within_sapling_padding() {
+        let change_strategy = MultiOutputChangeStrategy::new(
+            Zip317FeeRule::standard(),
+            None,
+            ChangePoolPolicy::default(),
+            SplitPolicy::new(
+                NonZeroUsize::new(2).unwrap(),
+                NonNegativeAmount::const_from_u64(10000),
+            ),
+        );
+
+        // spend a single Sapling note to make a transparent payment
+        let result = change_strategy.compute_balance(
+            &Network::TestNetwork,
+            Network::TestNetwork
+                .activation_height(NetworkUpgrade::Nu5)
+                .unwrap(),
+            &Vec::<TestTransparentInput>::new(),
+            &[TxOut {
+                value: NonNegativeAmount::const_from_u64(10000),
+                script_pubkey: Script(vec![]),
+            }],
+            &(
+                sapling::builder::BundleType::DEFAULT,
+                &[TestSaplingInput {
+                    note_id: 0,
+                    value: NonNegativeAmount::const_from_u64(100000),
+                }][..],
+                &Vec::<Infallible>::new()[..],
+            ),
+            #[cfg(feature = "orchard")]
+            &(
+                orchard::builder::BundleType::DEFAULT,
+                &Vec::<Infallible>::new()[..],
+                &Vec::<Infallible>::new()[..],
+            ),
+            &DustOutputPolicy::default(),
+        );
+
+        // The Sapling bundle is padded to two outputs, so the second change output occupies
+        // the padding and the fee is the same as for a single change output.
+        assert_matches!(
+            result,
+            Ok(balance) if
+                balance.proposed_change() == [
+                    ChangeValue::sapling(NonNegativeAmount::const_from_u64(10000), None),
+                    ChangeValue::sapling(NonNegativeAmount::const_from_u64(65000), None),
+                ] &&
+                balance.fee_required() == NonNegativeAmount::const_from_u64(15000)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "orchard")]
+    fn change_to_orchard_pool() {
+        use zcash_primitives::memo::{Memo, MemoBytes};
+
+        let change_memo = MemoBytes::from("Test change memo".parse::<Memo>().unwrap());
+        let change_strategy = SingleOutputChangeStrategy::new(
+            Zip317FeeRule::standard(),
+            Some(change_memo.clone()),
+            ChangePoolPolicy::default(),
+        );
+
+        // spend a single Orchard note to make an Orchard payment
+        let result = change_strategy.compute_balance(
+            &Network::TestNetwork,
+            Network::TestNetwork
+                .activation_height(NetworkUpgrade::Nu5)
+                .unwrap(),
+            &Vec::<TestTransparentInput>::new(),
+            &Vec::<TxOut>::new(),
+            &(
+                sapling::builder::BundleType::DEFAULT,
+                &Vec::<Infallible>::new()[..],
+                &Vec::<Infallible>::new()[..],
+            ),
+            &(
+                orchard::builder::BundleType::DEFAULT,
+                &[TestOrchardInput {
+                    note_id: 0,
+                    value: NonNegativeAmount::const_from_u64(55000),
+                }][..],
+                &[OrchardPayment::new(NonNegativeAmount::const_from_u64(
+                    40000,
+                ))][..],
+            ),
+            &DustOutputPolicy::default(),
+        );
+
+        // Change, including its memo, is proposed in the Orchard pool.
+        assert_matches!(
+            result,
+            Ok(balance) if
+                balance.proposed_change() == [ChangeValue::orchard(NonNegativeAmount::const_from_u64(5000), Some(change_memo.clone()))] &&
+                balance.fee_required() == NonNegativeAmount::const_from_u64(10000)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "orchard")]
+    fn change_with_disallowed_orchard_dust() {
+        let change_strategy = SingleOutputChangeStrategy::new(
+            Zip317FeeRule::standard(),
+            None,
+            ChangePoolPolicy::default(),
+        );
+
+        // spend two Orchard notes that are sufficient to pay the fee, along with a dust note
+        let result = change_strategy.compute_balance(
+            &Network::TestNetwork,
+            Network::TestNetwork
+                .activation_height(NetworkUpgrade::Nu5)
+                .unwrap(),
+            &Vec::<TestTransparentInput>::new(),
+            &Vec::<TxOut>::new(),
+            &(
+                sapling::builder::BundleType::DEFAULT,
+                &Vec::<Infallible>::new()[..],
+                &Vec::<Infallible>::new()[..],
+            ),
+            &(
+                orchard::builder::BundleType::DEFAULT,
+                &[
+                    TestOrchardInput {
+                        note_id: 0,
+                        value: NonNegativeAmount::const_from_u64(29000),
+                    },
+                    TestOrchardInput {
+                        note_id: 1,
+                        value: NonNegativeAmount::const_from_u64(20000),
+                    },
+                    TestOrchardInput {
+                        note_id: 2,
+                        value: NonNegativeAmount::const_from_u64(1000),
+                    },
+                ][..],
+                &[OrchardPayment::new(NonNegativeAmount::const_from_u64(
+                    40000,
+                ))][..],
+            ),
+            &DustOutputPolicy::default(),
+        );
+
+        // Orchard dust is reported in the same way as Sapling dust.
+        assert_matches!(
+            result,
+            Err(ChangeError::DustInputs { sapling, orchard, .. })
+                if sapling.is_empty() && orchard == vec![2]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "orchard")]
+    fn change_to_pool_with_surplus() {
+        let change_strategy = SingleOutputChangeStrategy::new(
+            Zip317FeeRule::standard(),
+            None,
+            ChangePoolPolicy::default(),
+        );
+
+        // spend a Sapling note and an Orchard note to make an Orchard payment
+        let result = change_strategy.compute_balance(
+            &Network::TestNetwork,
+            Network::TestNetwork
+                .activation_height(NetworkUpgrade::Nu5)
+                .unwrap(),
+            &Vec::<TestTransparentInput>::new(),
+            &Vec::<TxOut>::new(),
+            &(
+                sapling::builder::BundleType::DEFAULT,
+                &[TestSaplingInput {
+                    note_id: 0,
+                    value: NonNegativeAmount::const_from_u64(60000),
+                }][..],
+                &Vec::<Infallible>::new()[..],
+            ),
+            &(
+                orchard::builder::BundleType::DEFAULT,
+                &[TestOrchardInput {
+                    note_id: 1,
+                    value: NonNegativeAmount::const_from_u64(10000),
+                }][..],
+                &[OrchardPayment::new(NonNegativeAmount::const_from_u64(
+                    30000,
+                ))][..],
+            ),
+            &DustOutputPolicy::default(),
+        );
+
+        // Change is kept in the Sapling pool, so that only 20000 zatoshis cross into the
+        // Orchard pool.
+        assert_matches!(
+            result,
+            Ok(balance) if
+                balance.proposed_change() == [ChangeValue::sapling(NonNegativeAmount::const_from_u64(20000), None)] &&
+                balance.fee_required() == NonNegativeAmount::const_from_u64(20000)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "orchard")]
+    fn change_split_across_pools() {
+        let change_strategy = SingleOutputChangeStrategy::new(
+            Zip317FeeRule::standard(),
+            None,
+            ChangePoolPolicy::new(ShieldedProtocol::Sapling, true),
+        );
+
+        // spend a Sapling note and an Orchard note to make an Orchard payment
+        let result = change_strategy.compute_balance(
+            &Network::TestNetwork,
+            Network::TestNetwork
+                .activation_height(NetworkUpgrade::Nu5)
+                .unwrap(),
+            &Vec::<TestTransparentInput>::new(),
+            &Vec::<TxOut>::new(),
+            &(
+                sapling::builder::BundleType::DEFAULT,
+                &[TestSaplingInput {
+                    note_id: 0,
+                    value: NonNegativeAmount::const_from_u64(50000),
+                }][..],
+                &Vec::<Infallible>::new()[..],
+            ),
+            &(
+                orchard::builder::BundleType::DEFAULT,
+                &[TestOrchardInput {
+                    note_id: 1,
+                    value: NonNegativeAmount::const_from_u64(40000),
+                }][..],
+                &[OrchardPayment::new(NonNegativeAmount::const_from_u64(
+                    10000,
+                ))][..],
+            ),
+            &DustOutputPolicy::default(),
+        );
+
+        // The Orchard surplus is returned to the Orchard pool, and the remainder of the change
+        // (after the fee for the additional change output) to the Sapling pool.
+        assert_matches!(
+            result,
+            Ok(balance) if
+                balance.proposed_change() == [
+                    ChangeValue::sapling(NonNegativeAmount::const_from_u64(30000), None),
+                    ChangeValue::orchard(NonNegativeAmount::const_from_u64(30000), None),
+                ] &&
+                balance.fee_required() == NonNegativeAmount::const_from_u64(20000)
+        );
+    }
+
+    #[test]
+    fn estimate_fee_for_shape() {
+        let params = Network::TestNetwork;
+        let target_height = params.activation_height(NetworkUpgrade::Nu5).unwrap();
+        let estimate = |shape: TransactionShape| {
+            let fee = estimate_fee(&params, target_height, &Zip317FeeRule::standard(), &shape);
+            // The standard fee rule computes the same fee as the ZIP 317 fee rule.
+            assert_eq!(
+                estimate_fee(&params, target_height, &StandardFeeRule::Zip317, &shape),
+                fee
+            );
+            fee.unwrap()
+        };
+
+        // Transactions having no more than two logical actions pay the minimum fee.
+        assert_eq!(
+            estimate(TransactionShape::new(0, 0, 1, 2, 0)),
+            NonNegativeAmount::const_from_u64(10000)
+        );
+        assert_eq!(
+            estimate(TransactionShape::new(2, 1, 0, 0, 0)),
+            NonNegativeAmount::const_from_u64(10000)
+        );
+
+        // Each additional logical action adds the marginal fee.
+        assert_eq!(
+            estimate(TransactionShape::new(0, 0, 3, 2, 0)),
+            NonNegativeAmount::const_from_u64(15000)
+        );
+        assert_eq!(
+            estimate(TransactionShape::new(1, 0, 1, 2, 2)),
+            NonNegativeAmount::const_from_u64(25000)
+        );
+    }
 }
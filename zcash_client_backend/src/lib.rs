@@ -70,6 +70,7 @@ pub use zcash_keys::keys;
 pub mod proto;
 pub mod scan;
 pub mod scanning;
+pub mod sync;
 pub mod wallet;
 pub mod zip321;
 
@@ -80,7 +81,10 @@ use std::fmt;
 
 pub use decrypt::{decrypt_transaction, DecryptedOutput, TransferType};
 
-#[cfg(test)]
+#[cfg(feature = "orchard")]
+pub use decrypt::decrypt_transaction_orchard;
+
+#[cfg(any(test, feature = "test-dependencies"))]
 #[macro_use]
 extern crate assert_matches;
 
@@ -100,7 +104,7 @@ pub enum ShieldedProtocol {
 }
 
 /// A value pool to which the wallet supports sending transaction outputs.
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum PoolType {
     /// The transparent value pool
     Transparent,
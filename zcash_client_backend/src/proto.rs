@@ -22,7 +22,9 @@ use zcash_note_encryption::{EphemeralKeyBytes, COMPACT_NOTE_SIZE};
 
 use crate::{
     data_api::{
-        wallet::input_selection::{Proposal, ProposalError, ShieldedInputs},
+        wallet::input_selection::{
+            Proposal, ProposalError, ShieldedInputs, Step, StepOutput, StepOutputIndex,
+        },
         InputSource,
     },
     fees::{ChangeValue, TransactionBalance},
@@ -185,6 +187,55 @@ impl<A: sapling::bundle::Authorization> From<&sapling::bundle::SpendDescription<
     }
 }
 
+#[cfg(feature = "orchard")]
+impl compact_formats::CompactOrchardAction {
+    /// Returns the nullifier for the note spent by this action.
+    ///
+    /// A convenience method that parses [`CompactOrchardAction.nullifier`].
+    ///
+    /// [`CompactOrchardAction.nullifier`]: #structfield.nullifier
+    pub fn nf(&self) -> Result<orchard::note::Nullifier, ()> {
+        let nf_bytes: [u8; 32] = self.nullifier[..].try_into().map_err(|_| ())?;
+        Option::from(orchard::note::Nullifier::from_bytes(&nf_bytes)).ok_or(())
+    }
+
+    /// Returns the note commitment for the output of this action.
+    ///
+    /// A convenience method that parses [`CompactOrchardAction.cmx`].
+    ///
+    /// [`CompactOrchardAction.cmx`]: #structfield.cmx
+    pub fn cmx(&self) -> Result<orchard::note::ExtractedNoteCommitment, ()> {
+        let cmx_bytes: [u8; 32] = self.cmx[..].try_into().map_err(|_| ())?;
+        Option::from(orchard::note::ExtractedNoteCommitment::from_bytes(&cmx_bytes)).ok_or(())
+    }
+
+    /// Returns the ephemeral public key for the output of this action.
+    ///
+    /// A convenience method that parses [`CompactOrchardAction.ephemeral_key`].
+    ///
+    /// [`CompactOrchardAction.ephemeral_key`]: #structfield.ephemeral_key
+    pub fn ephemeral_key(&self) -> Result<EphemeralKeyBytes, ()> {
+        self.ephemeral_key[..]
+            .try_into()
+            .map(EphemeralKeyBytes)
+            .map_err(|_| ())
+    }
+}
+
+#[cfg(feature = "orchard")]
+impl TryFrom<&compact_formats::CompactOrchardAction> for orchard::note_encryption::CompactAction {
+    type Error = ();
+
+    fn try_from(value: &compact_formats::CompactOrchardAction) -> Result<Self, Self::Error> {
+        Ok(orchard::note_encryption::CompactAction::from_parts(
+            value.nf()?,
+            value.cmx()?,
+            value.ephemeral_key()?,
+            value.ciphertext[..].try_into().map_err(|_| ())?,
+        ))
+    }
+}
+
 #[cfg(feature = "orchard")]
 impl<SpendAuth> From<&orchard::Action<SpendAuth>> for compact_formats::CompactOrchardAction {
     fn from(action: &orchard::Action<SpendAuth>) -> compact_formats::CompactOrchardAction {
@@ -234,6 +285,8 @@ pub enum ProposalDecodingError<DbError> {
     MemoInvalid(memo::Error),
     /// The serialization version returned by the protobuf was not recognized.
     VersionInvalid(u32),
+    /// The proposal did not contain any steps.
+    NoSteps,
     /// The proposal did not correctly specify a standard fee rule.
     FeeRuleNotSpecified,
     /// The proposal violated balance or structural constraints.
@@ -242,6 +295,8 @@ pub enum ProposalDecodingError<DbError> {
     EmptyShieldedInputs(ShieldedProtocol),
     /// Change outputs to the specified pool are not supported.
     InvalidChangeRecipient(PoolType),
+    /// The input at the given index of a proposal step did not specify any value.
+    NullInput(usize),
 }
 
 impl<E> From<Zip321Error> for ProposalDecodingError<E> {
@@ -279,6 +334,9 @@ impl<E: Display> Display for ProposalDecodingError<E> {
             ProposalDecodingError::VersionInvalid(v) => {
                 write!(f, "Unrecognized proposal version {}", v)
             }
+            ProposalDecodingError::NoSteps => {
+                write!(f, "The proposal did not contain any steps.")
+            }
             ProposalDecodingError::FeeRuleNotSpecified => {
                 write!(f, "Proposal did not specify a known fee rule.")
             }
@@ -293,6 +351,9 @@ impl<E: Display> Display for ProposalDecodingError<E> {
                 "Change outputs to the {} pool are not supported.",
                 pool_type
             ),
+            ProposalDecodingError::NullInput(i) => {
+                write!(f, "Proposed input was null at index {}", i)
+            }
         }
     }
 }
@@ -318,7 +379,7 @@ fn pool_type<T>(pool_id: i32) -> Result<PoolType, ProposalDecodingError<T>> {
     }
 }
 
-impl proposal::ProposedInput {
+impl proposal::ReceivedOutput {
     pub fn parse_txid(&self) -> Result<TxId, TryFromSliceError> {
         Ok(TxId::from_bytes(self.txid[..].try_into()?))
     }
@@ -351,54 +412,97 @@ impl proposal::Proposal {
         params: &P,
         value: &Proposal<StandardFeeRule, NoteRef>,
     ) -> Self {
-        let transaction_request = value.transaction_request().to_uri(params);
+        use proposal::proposed_input;
+        use proposal::{PriorStepChange, PriorStepOutput, ReceivedOutput};
+        let steps = value
+            .steps()
+            .iter()
+            .map(|step| {
+                let transaction_request = step.transaction_request().to_uri(params);
+
+                let anchor_height = step
+                    .shielded_inputs()
+                    .map_or_else(|| 0, |i| u32::from(i.anchor_height()));
+
+                let inputs = step
+                    .transparent_inputs()
+                    .iter()
+                    .map(|utxo| proposal::ProposedInput {
+                        value: Some(proposed_input::Value::ReceivedOutput(ReceivedOutput {
+                            txid: utxo.outpoint().hash().to_vec(),
+                            value_pool: proposal::ValuePool::Transparent.into(),
+                            index: utxo.outpoint().n(),
+                            value: utxo.txout().value.into(),
+                        })),
+                    })
+                    .chain(step.shielded_inputs().iter().flat_map(|s_in| {
+                        s_in.notes().iter().map(|rec_note| proposal::ProposedInput {
+                            value: Some(proposed_input::Value::ReceivedOutput(ReceivedOutput {
+                                txid: rec_note.txid().as_ref().to_vec(),
+                                value_pool: proposal::ValuePool::from(rec_note.note().protocol())
+                                    .into(),
+                                index: rec_note.output_index().into(),
+                                value: rec_note.note().value().into(),
+                            })),
+                        })
+                    }))
+                    .chain(step.prior_step_inputs().iter().map(|p_in| {
+                        let step_index = p_in
+                            .step_index()
+                            .try_into()
+                            .expect("Step index fits into a u32");
+                        proposal::ProposedInput {
+                            value: Some(match p_in.output_index() {
+                                StepOutputIndex::Payment(i) => {
+                                    proposed_input::Value::PriorStepOutput(PriorStepOutput {
+                                        step_index,
+                                        payment_index: i
+                                            .try_into()
+                                            .expect("Payment index fits into a u32"),
+                                    })
+                                }
+                                StepOutputIndex::Change(i) => {
+                                    proposed_input::Value::PriorStepChange(PriorStepChange {
+                                        step_index,
+                                        change_index: i
+                                            .try_into()
+                                            .expect("Change index fits into a u32"),
+                                    })
+                                }
+                            }),
+                        }
+                    }))
+                    .collect();
 
-        let anchor_height = value
-            .shielded_inputs()
-            .map_or_else(|| 0, |i| u32::from(i.anchor_height()));
+                let balance = Some(proposal::TransactionBalance {
+                    proposed_change: step
+                        .balance()
+                        .proposed_change()
+                        .iter()
+                        .map(|change| proposal::ChangeValue {
+                            value: change.value().into(),
+                            value_pool: proposal::ValuePool::from(change.output_pool()).into(),
+                            memo: change.memo().map(|memo_bytes| proposal::MemoBytes {
+                                value: memo_bytes.as_slice().to_vec(),
+                            }),
+                        })
+                        .collect(),
+                    fee_required: step.balance().fee_required().into(),
+                });
 
-        let inputs = value
-            .transparent_inputs()
-            .iter()
-            .map(|utxo| proposal::ProposedInput {
-                txid: utxo.outpoint().hash().to_vec(),
-                value_pool: proposal::ValuePool::Transparent.into(),
-                index: utxo.outpoint().n(),
-                value: utxo.txout().value.into(),
+                proposal::ProposalStep {
+                    transaction_request,
+                    anchor_height,
+                    inputs,
+                    balance,
+                    is_shielding: step.is_shielding(),
+                }
             })
-            .chain(value.shielded_inputs().iter().flat_map(|s_in| {
-                s_in.notes().iter().map(|rec_note| proposal::ProposedInput {
-                    txid: rec_note.txid().as_ref().to_vec(),
-                    value_pool: proposal::ValuePool::from(rec_note.note().protocol()).into(),
-                    index: rec_note.output_index().into(),
-                    value: rec_note.note().value().into(),
-                })
-            }))
             .collect();
 
-        let balance = Some(proposal::TransactionBalance {
-            proposed_change: value
-                .balance()
-                .proposed_change()
-                .iter()
-                .map(|change| proposal::ChangeValue {
-                    value: change.value().into(),
-                    value_pool: proposal::ValuePool::from(change.output_pool()).into(),
-                    memo: change.memo().map(|memo_bytes| proposal::MemoBytes {
-                        value: memo_bytes.as_slice().to_vec(),
-                    }),
-                })
-                .collect(),
-            fee_required: value.balance().fee_required().into(),
-        });
-
         #[allow(deprecated)]
         proposal::Proposal {
             proto_version: PROPOSAL_SER_V1,
-            transaction_request,
-            anchor_height,
-            inputs,
-            balance,
             fee_rule: match value.fee_rule() {
                 StandardFeeRule::PreZip313 => proposal::FeeRule::PreZip313,
                 StandardFeeRule::Zip313 => proposal::FeeRule::Zip313,
@@ -406,7 +510,7 @@ impl proposal::Proposal {
             }
             .into(),
             min_target_height: value.min_target_height().into(),
-            is_shielding: value.is_shielding(),
+            steps,
         }
     }
 
@@ -420,6 +524,7 @@ impl proposal::Proposal {
     where
         DbT: InputSource<Error = DbError>,
     {
+        use self::proposal::proposed_input::Value::*;
         match self.proto_version {
             PROPOSAL_SER_V1 => {
                 #[allow(deprecated)]
@@ -432,102 +537,138 @@ impl proposal::Proposal {
                     }
                 };
 
-                let transaction_request =
-                    TransactionRequest::from_uri(params, &self.transaction_request)?;
-
-                #[cfg(not(feature = "transparent-inputs"))]
-                let transparent_inputs = vec![];
-                #[cfg(feature = "transparent-inputs")]
-                let mut transparent_inputs = vec![];
-
-                let mut received_notes = vec![];
-                for input in self.inputs.iter() {
-                    let txid = input
-                        .parse_txid()
-                        .map_err(ProposalDecodingError::TxIdInvalid)?;
-
-                    match input.pool_type()? {
-                        PoolType::Transparent => {
-                            #[cfg(not(feature = "transparent-inputs"))]
-                            return Err(ProposalDecodingError::ValuePoolNotSupported(1));
-
-                            #[cfg(feature = "transparent-inputs")]
-                            {
-                                let outpoint = OutPoint::new(txid.into(), input.index);
-                                transparent_inputs.push(
-                                    wallet_db
-                                        .get_unspent_transparent_output(&outpoint)
-                                        .map_err(ProposalDecodingError::InputRetrieval)?
-                                        .ok_or({
-                                            ProposalDecodingError::InputNotFound(
-                                                txid,
-                                                PoolType::Transparent,
-                                                input.index,
-                                            )
-                                        })?,
-                                );
+                let mut steps = Vec::with_capacity(self.steps.len());
+                for proto_step in self.steps.iter() {
+                    let transaction_request =
+                        TransactionRequest::from_uri(params, &proto_step.transaction_request)?;
+
+                    #[cfg(not(feature = "transparent-inputs"))]
+                    let transparent_inputs = vec![];
+                    #[cfg(feature = "transparent-inputs")]
+                    let mut transparent_inputs = vec![];
+                    let mut received_notes = vec![];
+                    let mut prior_step_inputs = vec![];
+                    for (i, input) in proto_step.inputs.iter().enumerate() {
+                        match input
+                            .value
+                            .as_ref()
+                            .ok_or(ProposalDecodingError::NullInput(i))?
+                        {
+                            ReceivedOutput(out) => {
+                                let txid = out
+                                    .parse_txid()
+                                    .map_err(ProposalDecodingError::TxIdInvalid)?;
+
+                                match out.pool_type()? {
+                                    PoolType::Transparent => {
+                                        #[cfg(not(feature = "transparent-inputs"))]
+                                        return Err(ProposalDecodingError::ValuePoolNotSupported(
+                                            1,
+                                        ));
+
+                                        #[cfg(feature = "transparent-inputs")]
+                                        {
+                                            let outpoint = OutPoint::new(txid.into(), out.index);
+                                            transparent_inputs.push(
+                                                wallet_db
+                                                    .get_unspent_transparent_output(&outpoint)
+                                                    .map_err(ProposalDecodingError::InputRetrieval)?
+                                                    .ok_or({
+                                                        ProposalDecodingError::InputNotFound(
+                                                            txid,
+                                                            PoolType::Transparent,
+                                                            out.index,
+                                                        )
+                                                    })?,
+                                            );
+                                        }
+                                    }
+                                    PoolType::Shielded(protocol) => received_notes.push(
+                                        wallet_db
+                                            .get_spendable_note(&txid, protocol, out.index)
+                                            .map_err(ProposalDecodingError::InputRetrieval)
+                                            .and_then(|opt| {
+                                                opt.ok_or({
+                                                    ProposalDecodingError::InputNotFound(
+                                                        txid,
+                                                        PoolType::Shielded(protocol),
+                                                        out.index,
+                                                    )
+                                                })
+                                            })?,
+                                    ),
+                                }
+                            }
+                            PriorStepOutput(s_ref) => {
+                                prior_step_inputs.push(StepOutput::new(
+                                    s_ref.step_index as usize,
+                                    StepOutputIndex::Payment(s_ref.payment_index as usize),
+                                ));
+                            }
+                            PriorStepChange(s_ref) => {
+                                prior_step_inputs.push(StepOutput::new(
+                                    s_ref.step_index as usize,
+                                    StepOutputIndex::Change(s_ref.change_index as usize),
+                                ));
                             }
                         }
-                        PoolType::Shielded(protocol) => received_notes.push(
-                            wallet_db
-                                .get_spendable_note(&txid, protocol, input.index)
-                                .map_err(ProposalDecodingError::InputRetrieval)
-                                .and_then(|opt| {
-                                    opt.ok_or({
-                                        ProposalDecodingError::InputNotFound(
-                                            txid,
-                                            PoolType::Shielded(protocol),
-                                            input.index,
-                                        )
-                                    })
-                                })?,
-                        ),
                     }
-                }
-
-                let shielded_inputs = NonEmpty::from_vec(received_notes)
-                    .map(|notes| ShieldedInputs::from_parts(self.anchor_height.into(), notes));
 
-                let proto_balance = self
-                    .balance
-                    .as_ref()
-                    .ok_or(ProposalDecodingError::BalanceInvalid)?;
-                let balance = TransactionBalance::new(
-                    proto_balance
-                        .proposed_change
-                        .iter()
-                        .map(|cv| -> Result<ChangeValue, ProposalDecodingError<_>> {
-                            match cv.pool_type()? {
-                                PoolType::Shielded(ShieldedProtocol::Sapling) => {
-                                    Ok(ChangeValue::sapling(
-                                        NonNegativeAmount::from_u64(cv.value)
-                                            .map_err(|_| ProposalDecodingError::BalanceInvalid)?,
-                                        cv.memo
-                                            .as_ref()
-                                            .map(|bytes| {
-                                                MemoBytes::from_bytes(&bytes.value)
-                                                    .map_err(ProposalDecodingError::MemoInvalid)
-                                            })
-                                            .transpose()?,
-                                    ))
+                    let shielded_inputs = NonEmpty::from_vec(received_notes).map(|notes| {
+                        ShieldedInputs::from_parts(proto_step.anchor_height.into(), notes)
+                    });
+
+                    let proto_balance = proto_step
+                        .balance
+                        .as_ref()
+                        .ok_or(ProposalDecodingError::BalanceInvalid)?;
+                    let balance = TransactionBalance::new(
+                        proto_balance
+                            .proposed_change
+                            .iter()
+                            .map(|cv| -> Result<ChangeValue, ProposalDecodingError<_>> {
+                                match cv.pool_type()? {
+                                    PoolType::Shielded(ShieldedProtocol::Sapling) => {
+                                        Ok(ChangeValue::sapling(
+                                            NonNegativeAmount::from_u64(cv.value).map_err(
+                                                |_| ProposalDecodingError::BalanceInvalid,
+                                            )?,
+                                            cv.memo
+                                                .as_ref()
+                                                .map(|bytes| {
+                                                    MemoBytes::from_bytes(&bytes.value)
+                                                        .map_err(ProposalDecodingError::MemoInvalid)
+                                                })
+                                                .transpose()?,
+                                        ))
+                                    }
+                                    t => Err(ProposalDecodingError::InvalidChangeRecipient(t)),
                                 }
-                                t => Err(ProposalDecodingError::InvalidChangeRecipient(t)),
-                            }
-                        })
-                        .collect::<Result<Vec<_>, _>>()?,
-                    NonNegativeAmount::from_u64(proto_balance.fee_required)
-                        .map_err(|_| ProposalDecodingError::BalanceInvalid)?,
-                )
-                .map_err(|_| ProposalDecodingError::BalanceInvalid)?;
+                            })
+                            .collect::<Result<Vec<_>, _>>()?,
+                        NonNegativeAmount::from_u64(proto_balance.fee_required)
+                            .map_err(|_| ProposalDecodingError::BalanceInvalid)?,
+                    )
+                    .map_err(|_| ProposalDecodingError::BalanceInvalid)?;
+
+                    let step = Step::from_parts(
+                        &steps,
+                        transaction_request,
+                        transparent_inputs,
+                        shielded_inputs,
+                        prior_step_inputs,
+                        balance,
+                        proto_step.is_shielding,
+                    )
+                    .map_err(ProposalDecodingError::ProposalInvalid)?;
+
+                    steps.push(step);
+                }
 
-                Proposal::from_parts(
-                    transaction_request,
-                    transparent_inputs,
-                    shielded_inputs,
-                    balance,
+                Proposal::multi_step(
                     fee_rule,
                     self.min_target_height.into(),
-                    self.is_shielding,
+                    NonEmpty::from_vec(steps).ok_or(ProposalDecodingError::NoSteps)?,
                 )
                 .map_err(ProposalDecodingError::ProposalInvalid)
             }
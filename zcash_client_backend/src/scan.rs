@@ -401,15 +401,16 @@ where
 {
     /// Adds the given outputs to this batch.
     ///
+    /// `domain` will be called to obtain the decryption domain for each output, and
     /// `replier` will be called with the result of every output.
     fn add_outputs(
         &mut self,
-        domain: impl Fn() -> D,
+        domain: impl Fn(&Output) -> D,
         outputs: &[Output],
         replier: channel::Sender<OutputItem<A, D, Dec::Memo>>,
     ) {
         self.outputs
-            .extend(outputs.iter().cloned().map(|output| (domain(), output)));
+            .extend(outputs.iter().cloned().map(|output| (domain(&output), output)));
         self.repliers.extend((0..outputs.len()).map(|output_index| {
             OutputReplier(OutputIndex {
                 output_index,
@@ -531,7 +532,7 @@ where
         &mut self,
         block_tag: BlockHash,
         txid: TxId,
-        domain: impl Fn() -> D,
+        domain: impl Fn(&Output) -> D,
         outputs: &[Output],
     ) {
         let (tx, rx) = channel::unbounded();
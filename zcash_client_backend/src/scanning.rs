@@ -20,14 +20,30 @@ use zcash_primitives::{
 
 use crate::data_api::{BlockMetadata, ScannedBlock, ScannedBundles};
 use crate::{
+    keys::UnifiedFullViewingKey,
     proto::compact_formats::CompactBlock,
     scan::{Batch, BatchRunner, CompactDecryptor, Tasks},
     wallet::{WalletSaplingOutput, WalletSaplingSpend, WalletTx},
     ShieldedProtocol,
 };
 
+#[cfg(feature = "orchard")]
+use {
+    crate::wallet::{WalletOrchardOutput, WalletOrchardSpend},
+    orchard::{
+        keys::{
+            FullViewingKey as OrchardFvk, IncomingViewingKey as OrchardIvk,
+            PreparedIncomingViewingKey as OrchardPreparedIvk,
+        },
+        note_encryption::{CompactAction, OrchardDomain},
+        tree::MerkleHashOrchard,
+    },
+    std::convert::Infallible,
+};
+
 /// A key that can be used to perform trial decryption and nullifier
-/// computation for a Sapling [`CompactSaplingOutput`]
+/// computation for a Sapling [`CompactSaplingOutput`] or an Orchard
+/// [`CompactOrchardAction`].
 ///
 /// The purpose of this trait is to enable [`scan_block`]
 /// and related methods to be used with either incoming viewing keys
@@ -37,7 +53,12 @@ use crate::{
 /// will be returned; in the case of a full viewing key, the
 /// nullifier for the note can also be obtained.
 ///
+/// Orchard nullifiers do not depend upon the position of the note in the note commitment
+/// tree, and so Orchard decryption is only supported for keys that are able to provide the
+/// Orchard full viewing key from which each incoming viewing key was derived.
+///
 /// [`CompactSaplingOutput`]: crate::proto::compact_formats::CompactSaplingOutput
+/// [`CompactOrchardAction`]: crate::proto::compact_formats::CompactOrchardAction
 /// [`scan_block`]: crate::scanning::scan_block
 pub trait ScanningKey {
     /// The type representing the scope of the scanning key.
@@ -48,6 +69,11 @@ pub trait ScanningKey {
 
     type SaplingKeys: IntoIterator<Item = (Self::Scope, SaplingIvk, Self::SaplingNk)>;
 
+    /// The type of the collection of keys that are used to decrypt Orchard actions, each
+    /// paired with the full viewing key from which it was derived.
+    #[cfg(feature = "orchard")]
+    type OrchardKeys: IntoIterator<Item = (Self::Scope, OrchardIvk, OrchardFvk)>;
+
     /// The type of nullifier extracted when a note is successfully
     /// obtained by trial decryption.
     type Nf;
@@ -55,6 +81,11 @@ pub trait ScanningKey {
     /// Obtain the underlying Sapling incoming viewing key(s) for this scanning key.
     fn to_sapling_keys(&self) -> Self::SaplingKeys;
 
+    /// Obtain the underlying Orchard incoming viewing key(s) for this scanning key, along with
+    /// the full viewing key(s) from which they were derived.
+    #[cfg(feature = "orchard")]
+    fn to_orchard_keys(&self) -> Self::OrchardKeys;
+
     /// Produces the nullifier for the specified note and witness, if possible.
     ///
     /// IVK-based implementations of this trait cannot successfully derive
@@ -68,12 +99,19 @@ impl<K: ScanningKey> ScanningKey for &K {
     type Scope = K::Scope;
     type SaplingNk = K::SaplingNk;
     type SaplingKeys = K::SaplingKeys;
+    #[cfg(feature = "orchard")]
+    type OrchardKeys = K::OrchardKeys;
     type Nf = K::Nf;
 
     fn to_sapling_keys(&self) -> Self::SaplingKeys {
         (*self).to_sapling_keys()
     }
 
+    #[cfg(feature = "orchard")]
+    fn to_orchard_keys(&self) -> Self::OrchardKeys {
+        (*self).to_orchard_keys()
+    }
+
     fn sapling_nf(key: &Self::SaplingNk, note: &sapling::Note, position: Position) -> Self::Nf {
         K::sapling_nf(key, note, position)
     }
@@ -83,6 +121,8 @@ impl ScanningKey for DiversifiableFullViewingKey {
     type Scope = Scope;
     type SaplingNk = sapling::NullifierDerivingKey;
     type SaplingKeys = [(Self::Scope, SaplingIvk, Self::SaplingNk); 2];
+    #[cfg(feature = "orchard")]
+    type OrchardKeys = [(Self::Scope, OrchardIvk, OrchardFvk); 0];
     type Nf = sapling::Nullifier;
 
     fn to_sapling_keys(&self) -> Self::SaplingKeys {
@@ -100,6 +140,11 @@ impl ScanningKey for DiversifiableFullViewingKey {
         ]
     }
 
+    #[cfg(feature = "orchard")]
+    fn to_orchard_keys(&self) -> Self::OrchardKeys {
+        []
+    }
+
     fn sapling_nf(key: &Self::SaplingNk, note: &sapling::Note, position: Position) -> Self::Nf {
         note.nf(key, position.into())
     }
@@ -109,12 +154,19 @@ impl ScanningKey for (Scope, SaplingIvk, sapling::NullifierDerivingKey) {
     type Scope = Scope;
     type SaplingNk = sapling::NullifierDerivingKey;
     type SaplingKeys = [(Self::Scope, SaplingIvk, Self::SaplingNk); 1];
+    #[cfg(feature = "orchard")]
+    type OrchardKeys = [(Self::Scope, OrchardIvk, OrchardFvk); 0];
     type Nf = sapling::Nullifier;
 
     fn to_sapling_keys(&self) -> Self::SaplingKeys {
         [self.clone()]
     }
 
+    #[cfg(feature = "orchard")]
+    fn to_orchard_keys(&self) -> Self::OrchardKeys {
+        []
+    }
+
     fn sapling_nf(key: &Self::SaplingNk, note: &sapling::Note, position: Position) -> Self::Nf {
         note.nf(key, position.into())
     }
@@ -128,15 +180,122 @@ impl ScanningKey for SaplingIvk {
     type Scope = ();
     type SaplingNk = ();
     type SaplingKeys = [(Self::Scope, SaplingIvk, Self::SaplingNk); 1];
+    #[cfg(feature = "orchard")]
+    type OrchardKeys = [(Self::Scope, OrchardIvk, OrchardFvk); 0];
     type Nf = ();
 
     fn to_sapling_keys(&self) -> Self::SaplingKeys {
         [((), self.clone(), ())]
     }
 
+    #[cfg(feature = "orchard")]
+    fn to_orchard_keys(&self) -> Self::OrchardKeys {
+        []
+    }
+
     fn sapling_nf(_key: &Self::SaplingNk, _note: &sapling::Note, _position: Position) {}
 }
 
+/// The [`ScanningKey`] implementation for Orchard [`FullViewingKey`]s.
+///
+/// A key of this type does not provide any Sapling keys, and so its `SaplingNk` type is
+/// uninhabited. The `Nf` type only describes the (always empty) Sapling outputs of a block
+/// scanned with this key; it is the Sapling nullifier type, rather than `()`, because
+/// [`WalletWrite::put_blocks`] only accepts blocks having that nullifier type.
+///
+/// [`FullViewingKey`]: orchard::keys::FullViewingKey
+/// [`WalletWrite::put_blocks`]: crate::data_api::WalletWrite::put_blocks
+#[cfg(feature = "orchard")]
+impl ScanningKey for OrchardFvk {
+    type Scope = Scope;
+    type SaplingNk = Infallible;
+    type SaplingKeys = [(Self::Scope, SaplingIvk, Self::SaplingNk); 0];
+    type OrchardKeys = [(Self::Scope, OrchardIvk, OrchardFvk); 2];
+    type Nf = sapling::Nullifier;
+
+    fn to_sapling_keys(&self) -> Self::SaplingKeys {
+        []
+    }
+
+    fn to_orchard_keys(&self) -> Self::OrchardKeys {
+        [
+            (
+                Scope::External,
+                self.to_ivk(orchard_scope(Scope::External)),
+                self.clone(),
+            ),
+            (
+                Scope::Internal,
+                self.to_ivk(orchard_scope(Scope::Internal)),
+                self.clone(),
+            ),
+        ]
+    }
+
+    fn sapling_nf(key: &Self::SaplingNk, _note: &sapling::Note, _position: Position) -> Self::Nf {
+        match *key {}
+    }
+}
+
+/// Converts a ZIP 32 key scope to the corresponding Orchard key scope.
+#[cfg(feature = "orchard")]
+fn orchard_scope(scope: Scope) -> orchard::keys::Scope {
+    match scope {
+        Scope::External => orchard::keys::Scope::External,
+        Scope::Internal => orchard::keys::Scope::Internal,
+    }
+}
+
+/// The scanning keys for each of the shielded protocols supported by a
+/// [`UnifiedFullViewingKey`].
+///
+/// Deriving incoming viewing keys is relatively expensive, so this type allows the keys for
+/// an account to be derived once and then reused when scanning a range of blocks.
+#[derive(Clone)]
+pub struct UnifiedScanningKeys {
+    sapling: Vec<(Scope, SaplingIvk, sapling::NullifierDerivingKey)>,
+    #[cfg(feature = "orchard")]
+    orchard: Vec<(Scope, OrchardIvk, OrchardFvk)>,
+}
+
+impl UnifiedScanningKeys {
+    /// Derives the scanning keys for each of the shielded components of the given
+    /// [`UnifiedFullViewingKey`].
+    pub fn from_ufvk(ufvk: &UnifiedFullViewingKey) -> Self {
+        Self {
+            sapling: ufvk.sapling().map_or_else(Vec::new, |dfvk| {
+                dfvk.to_sapling_keys().into_iter().collect()
+            }),
+            #[cfg(feature = "orchard")]
+            orchard: ufvk
+                .orchard()
+                .map_or_else(Vec::new, |fvk| fvk.to_orchard_keys().into_iter().collect()),
+        }
+    }
+}
+
+impl ScanningKey for UnifiedScanningKeys {
+    type Scope = Scope;
+    type SaplingNk = sapling::NullifierDerivingKey;
+    type SaplingKeys = Vec<(Self::Scope, SaplingIvk, Self::SaplingNk)>;
+    #[cfg(feature = "orchard")]
+    type OrchardKeys = Vec<(Self::Scope, OrchardIvk, OrchardFvk)>;
+    type Nf = sapling::Nullifier;
+
+    fn to_sapling_keys(&self) -> Self::SaplingKeys {
+        self.sapling.clone()
+    }
+
+    #[cfg(feature = "orchard")]
+    fn to_orchard_keys(&self) -> Self::OrchardKeys {
+        self.orchard.clone()
+    }
+
+    fn sapling_nf(key: &Self::SaplingNk, note: &sapling::Note, position: Position) -> Self::Nf {
+        note.nf(key, position.into())
+    }
+}
+
 /// Errors that may occur in chain scanning
 #[derive(Clone, Debug)]
 pub enum ScanError {
@@ -241,7 +400,8 @@ impl fmt::Display for ScanError {
 /// the nullifiers for received notes; the implementation for [`ExtendedFullViewingKey`]
 /// will derive the nullifiers for received notes and return them as part of the resulting
 /// [`WalletSaplingOutput`]s, whereas the implementation for [`SaplingIvk`] cannot
-/// do so and will return the unit value in those outputs instead.
+/// do so and will return the unit value in those outputs instead. Nullifiers for received
+/// Orchard notes are always computed.
 ///
 /// [`ExtendedFullViewingKey`]: sapling::zip32::ExtendedFullViewingKey
 /// [`SaplingIvk`]: sapling::SaplingIvk
@@ -256,6 +416,7 @@ pub fn scan_block<P: consensus::Parameters + Send + 'static, K: ScanningKey>(
     block: CompactBlock,
     vks: &[(&AccountId, &K)],
     sapling_nullifiers: &[(AccountId, sapling::Nullifier)],
+    #[cfg(feature = "orchard")] orchard_nullifiers: &[(AccountId, orchard::note::Nullifier)],
     prior_block_metadata: Option<&BlockMetadata>,
 ) -> Result<ScannedBlock<K::Nf, K::Scope>, ScanError> {
     scan_block_with_runner::<_, _, ()>(
@@ -263,47 +424,139 @@ pub fn scan_block<P: consensus::Parameters + Send + 'static, K: ScanningKey>(
         block,
         vks,
         sapling_nullifiers,
+        #[cfg(feature = "orchard")]
+        orchard_nullifiers,
         prior_block_metadata,
         None,
     )
 }
 
-type TaggedBatch<S> =
+type TaggedSaplingBatch<S> =
     Batch<(AccountId, S), SaplingDomain, CompactOutputDescription, CompactDecryptor>;
-type TaggedBatchRunner<S, T> =
+type TaggedSaplingBatchRunner<S, T> =
     BatchRunner<(AccountId, S), SaplingDomain, CompactOutputDescription, CompactDecryptor, T>;
 
-#[tracing::instrument(skip_all, fields(height = block.height))]
-pub(crate) fn add_block_to_runner<P, S, T>(
-    params: &P,
-    block: CompactBlock,
-    batch_runner: &mut TaggedBatchRunner<S, T>,
-) where
-    P: consensus::Parameters + Send + 'static,
+#[cfg(feature = "orchard")]
+type TaggedOrchardBatch<S> = Batch<(AccountId, S), OrchardDomain, CompactAction, CompactDecryptor>;
+#[cfg(feature = "orchard")]
+type TaggedOrchardBatchRunner<S, T> =
+    BatchRunner<(AccountId, S), OrchardDomain, CompactAction, CompactDecryptor, T>;
+
+/// The set of batch decryption tasks required to scan the outputs of every supported
+/// shielded protocol.
+#[cfg(not(feature = "orchard"))]
+pub(crate) trait ScanTasks<S>: Tasks<TaggedSaplingBatch<S>> {}
+#[cfg(not(feature = "orchard"))]
+impl<S, T: Tasks<TaggedSaplingBatch<S>>> ScanTasks<S> for T {}
+
+/// The set of batch decryption tasks required to scan the outputs of every supported
+/// shielded protocol.
+#[cfg(feature = "orchard")]
+pub(crate) trait ScanTasks<S>:
+    Tasks<TaggedSaplingBatch<S>> + Tasks<TaggedOrchardBatch<S>>
+{
+}
+#[cfg(feature = "orchard")]
+impl<S, T: Tasks<TaggedSaplingBatch<S>> + Tasks<TaggedOrchardBatch<S>>> ScanTasks<S> for T {}
+
+/// A batch runner for each of the supported shielded protocols.
+pub(crate) struct BatchRunners<S, T: ScanTasks<S>> {
+    sapling: TaggedSaplingBatchRunner<S, T>,
+    #[cfg(feature = "orchard")]
+    orchard: TaggedOrchardBatchRunner<S, T>,
+}
+
+impl<S, T> BatchRunners<S, T>
+where
     S: Clone + Send + 'static,
-    T: Tasks<TaggedBatch<S>>,
+    T: ScanTasks<S>,
 {
-    let block_hash = block.hash();
-    let block_height = block.height();
-    let zip212_enforcement = consensus::sapling_zip212_enforcement(params, block_height);
+    /// Constructs a set of batch runners that will trial-decrypt with the incoming viewing
+    /// keys of each of the given scanning keys.
+    pub(crate) fn for_keys<K: ScanningKey<Scope = S>>(
+        batch_size_threshold: usize,
+        vks: &[(&AccountId, K)],
+    ) -> Self {
+        BatchRunners {
+            sapling: BatchRunner::new(
+                batch_size_threshold,
+                vks.iter().flat_map(|(account, key)| {
+                    key.to_sapling_keys()
+                        .into_iter()
+                        .map(move |(scope, ivk, _)| {
+                            ((**account, scope), PreparedIncomingViewingKey::new(&ivk))
+                        })
+                }),
+            ),
+            #[cfg(feature = "orchard")]
+            orchard: BatchRunner::new(
+                batch_size_threshold,
+                vks.iter().flat_map(|(account, key)| {
+                    key.to_orchard_keys()
+                        .into_iter()
+                        .map(move |(scope, ivk, _)| {
+                            ((**account, scope), OrchardPreparedIvk::new(&ivk))
+                        })
+                }),
+            ),
+        }
+    }
 
-    for tx in block.vtx.into_iter() {
-        let txid = tx.txid();
-        let outputs = tx
-            .outputs
-            .into_iter()
-            .map(|output| {
-                CompactOutputDescription::try_from(output)
-                    .expect("Invalid output found in compact block decoding.")
-            })
-            .collect::<Vec<_>>();
-
-        batch_runner.add_outputs(
-            block_hash,
-            txid,
-            || SaplingDomain::new(zip212_enforcement),
-            &outputs,
-        )
+    /// Dispatches any pending outputs to the batch decryption tasks.
+    pub(crate) fn flush(&mut self) {
+        self.sapling.flush();
+        #[cfg(feature = "orchard")]
+        self.orchard.flush();
+    }
+
+    /// Adds the outputs of each transaction in the given block to the batch runners.
+    #[tracing::instrument(skip_all, fields(height = block.height))]
+    pub(crate) fn add_block<P>(&mut self, params: &P, block: CompactBlock)
+    where
+        P: consensus::Parameters + Send + 'static,
+    {
+        let block_hash = block.hash();
+        let block_height = block.height();
+        let zip212_enforcement = consensus::sapling_zip212_enforcement(params, block_height);
+
+        for tx in block.vtx.into_iter() {
+            let txid = tx.txid();
+
+            #[cfg(feature = "orchard")]
+            {
+                let actions = tx
+                    .actions
+                    .iter()
+                    .map(|action| {
+                        CompactAction::try_from(action)
+                            .expect("Invalid Orchard action found in compact block decoding.")
+                    })
+                    .collect::<Vec<_>>();
+
+                self.orchard.add_outputs(
+                    block_hash,
+                    txid,
+                    OrchardDomain::for_compact_action,
+                    &actions,
+                );
+            }
+
+            let outputs = tx
+                .outputs
+                .into_iter()
+                .map(|output| {
+                    CompactOutputDescription::try_from(output)
+                        .expect("Invalid output found in compact block decoding.")
+                })
+                .collect::<Vec<_>>();
+
+            self.sapling.add_outputs(
+                block_hash,
+                txid,
+                |_| SaplingDomain::new(zip212_enforcement),
+                &outputs,
+            );
+        }
     }
 }
 
@@ -329,18 +582,53 @@ fn check_hash_continuity(
     None
 }
 
+/// Finds the first tracked nullifier that matches the given spend nullifier, and returns the
+/// account to which it belongs if there is a match, in constant time.
+// TODO: However, this is O(|nullifiers| * |notes|); does using
+// constant-time operations here really make sense?
+fn find_spent<Nf: ConstantTimeEq>(
+    nullifiers: &[(AccountId, Nf)],
+    spend_nf: &Nf,
+) -> Option<AccountId> {
+    nullifiers
+        .iter()
+        .map(|(account, nf)| CtOption::new(*account, nf.ct_eq(spend_nf)))
+        .fold(CtOption::new(AccountId::ZERO, 0.into()), |first, next| {
+            CtOption::conditional_select(&next, &first, first.is_some())
+        })
+        .into()
+}
+
+/// Returns the retention that should be applied to a note commitment when adding it to the
+/// note commitment tree.
+fn commitment_retention(
+    height: BlockHeight,
+    is_marked: bool,
+    is_checkpoint: bool,
+) -> Retention<BlockHeight> {
+    match (is_marked, is_checkpoint) {
+        (is_marked, true) => Retention::Checkpoint {
+            id: height,
+            is_marked,
+        },
+        (true, false) => Retention::Marked,
+        (false, false) => Retention::Ephemeral,
+    }
+}
+
 #[tracing::instrument(skip_all, fields(height = block.height))]
 pub(crate) fn scan_block_with_runner<
     P: consensus::Parameters + Send + 'static,
     K: ScanningKey,
-    T: Tasks<TaggedBatch<K::Scope>> + Sync,
+    T: ScanTasks<K::Scope> + Sync,
 >(
     params: &P,
     block: CompactBlock,
     vks: &[(&AccountId, K)],
-    nullifiers: &[(AccountId, sapling::Nullifier)],
+    sapling_nullifiers: &[(AccountId, sapling::Nullifier)],
+    #[cfg(feature = "orchard")] orchard_nullifiers: &[(AccountId, orchard::note::Nullifier)],
     prior_block_metadata: Option<&BlockMetadata>,
-    mut batch_runner: Option<&mut TaggedBatchRunner<K::Scope, T>>,
+    mut batch_runners: Option<&mut BatchRunners<K::Scope, T>>,
 ) -> Result<ScannedBlock<K::Nf, K::Scope>, ScanError> {
     if let Some(scan_error) = check_hash_continuity(&block, prior_block_metadata) {
         return Err(scan_error);
@@ -443,10 +731,60 @@ pub(crate) fn scan_block_with_runner<
             Ok,
         )?;
 
+    // Derive the keys for each protocol once per block, rather than once per transaction.
+    let sapling_keys = vks
+        .iter()
+        .flat_map(|(a, k)| {
+            k.to_sapling_keys()
+                .into_iter()
+                .map(move |(scope, ivk, nk)| (**a, scope, ivk, nk))
+        })
+        .collect::<Vec<_>>();
+    let sapling_nks = sapling_keys
+        .iter()
+        .map(|(a, scope, _, nk)| ((*a, scope.clone()), nk))
+        .collect::<HashMap<_, _>>();
+    let sapling_ivks = if batch_runners.is_none() {
+        sapling_keys
+            .iter()
+            .map(|(_, _, ivk, _)| PreparedIncomingViewingKey::new(ivk))
+            .collect::<Vec<_>>()
+    } else {
+        vec![]
+    };
+
+    #[cfg(feature = "orchard")]
+    let orchard_keys = vks
+        .iter()
+        .flat_map(|(a, k)| {
+            k.to_orchard_keys()
+                .into_iter()
+                .map(move |(scope, ivk, fvk)| (**a, scope, ivk, fvk))
+        })
+        .collect::<Vec<_>>();
+    #[cfg(feature = "orchard")]
+    let orchard_fvks = orchard_keys
+        .iter()
+        .map(|(a, scope, _, fvk)| ((*a, scope.clone()), fvk))
+        .collect::<HashMap<_, _>>();
+    #[cfg(feature = "orchard")]
+    let orchard_ivks = if batch_runners.is_none() {
+        orchard_keys
+            .iter()
+            .map(|(_, _, ivk, _)| OrchardPreparedIvk::new(ivk))
+            .collect::<Vec<_>>()
+    } else {
+        vec![]
+    };
+
     let compact_block_tx_count = block.vtx.len();
     let mut wtxs: Vec<WalletTx<K::Nf, K::Scope>> = vec![];
     let mut sapling_nullifier_map = Vec::with_capacity(block.vtx.len());
     let mut sapling_note_commitments: Vec<(sapling::Node, Retention<BlockHeight>)> = vec![];
+    #[cfg(feature = "orchard")]
+    let mut orchard_nullifier_map = Vec::with_capacity(block.vtx.len());
+    #[cfg(feature = "orchard")]
+    let mut orchard_note_commitments: Vec<(MerkleHashOrchard, Retention<BlockHeight>)> = vec![];
     for (tx_idx, tx) in block.vtx.into_iter().enumerate() {
         let txid = tx.txid();
         let tx_index =
@@ -454,40 +792,53 @@ pub(crate) fn scan_block_with_runner<
 
         // Check for spent notes. The comparison against known-unspent nullifiers is done
         // in constant time.
-        // TODO: However, this is O(|nullifiers| * |notes|); does using
-        // constant-time operations here really make sense?
-        let mut shielded_spends = vec![];
+        let mut sapling_spends = vec![];
         let mut sapling_unlinked_nullifiers = Vec::with_capacity(tx.spends.len());
         for (index, spend) in tx.spends.into_iter().enumerate() {
             let spend_nf = spend
                 .nf()
                 .expect("Could not deserialize nullifier for spend from protobuf representation.");
 
-            // Find the first tracked nullifier that matches this spend, and produce
-            // a WalletShieldedSpend if there is a match, in constant time.
-            let spend = nullifiers
-                .iter()
-                .map(|&(account, nf)| CtOption::new(account, nf.ct_eq(&spend_nf)))
-                .fold(CtOption::new(AccountId::ZERO, 0.into()), |first, next| {
-                    CtOption::conditional_select(&next, &first, first.is_some())
-                })
-                .map(|account| WalletSaplingSpend::from_parts(index, spend_nf, account));
-
-            if spend.is_some().into() {
-                shielded_spends.push(spend.unwrap());
-            } else {
-                // This nullifier didn't match any we are currently tracking; save it in
-                // case it matches an earlier block range we haven't scanned yet.
-                sapling_unlinked_nullifiers.push(spend_nf);
+            match find_spent(sapling_nullifiers, &spend_nf) {
+                Some(account) => {
+                    sapling_spends.push(WalletSaplingSpend::from_parts(index, spend_nf, account))
+                }
+                None => {
+                    // This nullifier didn't match any we are currently tracking; save it in
+                    // case it matches an earlier block range we haven't scanned yet.
+                    sapling_unlinked_nullifiers.push(spend_nf);
+                }
             }
         }
         sapling_nullifier_map.push((txid, tx_index, sapling_unlinked_nullifiers));
 
+        // Each Orchard action both spends and creates a note; check the spent side of each
+        // action in the same fashion as for Sapling spends.
+        #[cfg(feature = "orchard")]
+        let mut orchard_spends = vec![];
+        #[cfg(feature = "orchard")]
+        {
+            let mut orchard_unlinked_nullifiers = Vec::with_capacity(tx.actions.len());
+            for (index, action) in tx.actions.iter().enumerate() {
+                let spend_nf = action.nf().expect(
+                    "Could not deserialize nullifier for Orchard action from protobuf representation.",
+                );
+
+                match find_spent(orchard_nullifiers, &spend_nf) {
+                    Some(account) => orchard_spends
+                        .push(WalletOrchardSpend::from_parts(index, spend_nf, account)),
+                    None => orchard_unlinked_nullifiers.push(spend_nf),
+                }
+            }
+            orchard_nullifier_map.push((txid, tx_index, orchard_unlinked_nullifiers));
+        }
+
         // Collect the set of accounts that were spent from in this transaction
-        let spent_from_accounts: HashSet<_> = shielded_spends
-            .iter()
-            .map(|spend| spend.account())
-            .collect();
+        let spent_from_accounts = sapling_spends.iter().map(|spend| spend.account());
+        #[cfg(feature = "orchard")]
+        let spent_from_accounts =
+            spent_from_accounts.chain(orchard_spends.iter().map(|spend| spend.account()));
+        let spent_from_accounts: HashSet<_> = spent_from_accounts.collect();
 
         // We keep track of the number of outputs and actions here because tx.outputs
         // and tx.actions end up being moved.
@@ -498,7 +849,7 @@ pub(crate) fn scan_block_with_runner<
             u32::try_from(tx.actions.len()).expect("Orchard action count cannot exceed a u32");
 
         // Check for incoming notes while incrementing tree and witnesses
-        let mut shielded_outputs: Vec<WalletSaplingOutput<K::Nf, K::Scope>> = vec![];
+        let mut sapling_outputs: Vec<WalletSaplingOutput<K::Nf, K::Scope>> = vec![];
         {
             let decoded = &tx
                 .outputs
@@ -512,51 +863,27 @@ pub(crate) fn scan_block_with_runner<
                 })
                 .collect::<Vec<_>>();
 
-            let decrypted: Vec<_> = if let Some(runner) = batch_runner.as_mut() {
-                let vks = vks
-                    .iter()
-                    .flat_map(|(a, k)| {
-                        k.to_sapling_keys()
-                            .into_iter()
-                            .map(move |(scope, _, nk)| ((**a, scope), nk))
-                    })
-                    .collect::<HashMap<_, _>>();
-
-                let mut decrypted = runner.collect_results(cur_hash, txid);
+            let decrypted: Vec<_> = if let Some(runners) = batch_runners.as_mut() {
+                let mut decrypted = runners.sapling.collect_results(cur_hash, txid);
                 (0..decoded.len())
                     .map(|i| {
                         decrypted.remove(&(txid, i)).map(|d_out| {
                             let a = d_out.ivk_tag.0;
-                            let nk = vks.get(&d_out.ivk_tag).expect(
+                            let nk = sapling_nks.get(&d_out.ivk_tag).expect(
                                 "The batch runner and scan_block must use the same set of IVKs.",
                             );
 
-                            (d_out.note, a, d_out.ivk_tag.1, (*nk).clone())
+                            (d_out.note, a, d_out.ivk_tag.1, *nk)
                         })
                     })
                     .collect()
             } else {
-                let vks = vks
-                    .iter()
-                    .flat_map(|(a, k)| {
-                        k.to_sapling_keys()
-                            .into_iter()
-                            .map(move |(scope, ivk, nk)| (**a, scope, ivk, nk))
-                    })
-                    .collect::<Vec<_>>();
-
-                let ivks = vks
-                    .iter()
-                    .map(|(_, _, ivk, _)| ivk)
-                    .map(PreparedIncomingViewingKey::new)
-                    .collect::<Vec<_>>();
-
-                batch::try_compact_note_decryption(&ivks, &decoded[..])
+                batch::try_compact_note_decryption(&sapling_ivks, &decoded[..])
                     .into_iter()
                     .map(|v| {
                         v.map(|((note, _), ivk_idx)| {
-                            let (account, scope, _, nk) = &vks[ivk_idx];
-                            (note, *account, scope.clone(), (*nk).clone())
+                            let (account, scope, _, nk) = &sapling_keys[ivk_idx];
+                            (note, *account, scope.clone(), nk)
                         })
                     })
                     .collect()
@@ -568,14 +895,8 @@ pub(crate) fn scan_block_with_runner<
                 let node = sapling::Node::from_cmu(&output.cmu);
                 let is_checkpoint =
                     output_idx + 1 == decoded.len() && tx_idx + 1 == compact_block_tx_count;
-                let retention = match (dec_output.is_some(), is_checkpoint) {
-                    (is_marked, true) => Retention::Checkpoint {
-                        id: cur_height,
-                        is_marked,
-                    },
-                    (true, false) => Retention::Marked,
-                    (false, false) => Retention::Ephemeral,
-                };
+                let retention =
+                    commitment_retention(cur_height, dec_output.is_some(), is_checkpoint);
 
                 if let Some((note, account, scope, nk)) = dec_output {
                     // A note is marked as "change" if the account that received it
@@ -588,9 +909,9 @@ pub(crate) fn scan_block_with_runner<
                     let note_commitment_tree_position = Position::from(u64::from(
                         sapling_commitment_tree_size + u32::try_from(output_idx).unwrap(),
                     ));
-                    let nf = K::sapling_nf(&nk, &note, note_commitment_tree_position);
+                    let nf = K::sapling_nf(nk, &note, note_commitment_tree_position);
 
-                    shielded_outputs.push(WalletSaplingOutput::from_parts(
+                    sapling_outputs.push(WalletSaplingOutput::from_parts(
                         output_idx,
                         output.cmu,
                         output.ephemeral_key.clone(),
@@ -607,12 +928,110 @@ pub(crate) fn scan_block_with_runner<
             }
         }
 
-        if !(shielded_spends.is_empty() && shielded_outputs.is_empty()) {
+        #[cfg(feature = "orchard")]
+        let mut orchard_outputs: Vec<WalletOrchardOutput<K::Scope>> = vec![];
+        #[cfg(feature = "orchard")]
+        {
+            let (decoded, parts): (Vec<_>, Vec<_>) = tx
+                .actions
+                .iter()
+                .map(|action| {
+                    let cmx = action.cmx().expect(
+                        "Could not deserialize note commitment for Orchard action from protobuf representation.",
+                    );
+                    let ephemeral_key = action.ephemeral_key().expect(
+                        "Could not deserialize ephemeral key for Orchard action from protobuf representation.",
+                    );
+                    let compact_action = CompactAction::try_from(action)
+                        .expect("Invalid Orchard action found in compact block decoding.");
+
+                    (
+                        (
+                            OrchardDomain::for_compact_action(&compact_action),
+                            compact_action,
+                        ),
+                        (cmx, ephemeral_key),
+                    )
+                })
+                .unzip();
+
+            let decrypted: Vec<_> = if let Some(runners) = batch_runners.as_mut() {
+                let mut decrypted = runners.orchard.collect_results(cur_hash, txid);
+                (0..decoded.len())
+                    .map(|i| {
+                        decrypted.remove(&(txid, i)).map(|d_out| {
+                            let a = d_out.ivk_tag.0;
+                            let fvk = orchard_fvks.get(&d_out.ivk_tag).expect(
+                                "The batch runner and scan_block must use the same set of IVKs.",
+                            );
+
+                            (d_out.note, a, d_out.ivk_tag.1, *fvk)
+                        })
+                    })
+                    .collect()
+            } else {
+                batch::try_compact_note_decryption(&orchard_ivks, &decoded[..])
+                    .into_iter()
+                    .map(|v| {
+                        v.map(|((note, _), ivk_idx)| {
+                            let (account, scope, _, fvk) = &orchard_keys[ivk_idx];
+                            (note, *account, scope.clone(), fvk)
+                        })
+                    })
+                    .collect()
+            };
+
+            for (action_idx, ((cmx, ephemeral_key), dec_output)) in
+                parts.into_iter().zip(decrypted).enumerate()
+            {
+                // Collect block note commitments
+                let node = MerkleHashOrchard::from_cmx(&cmx);
+                let is_checkpoint =
+                    action_idx + 1 == decoded.len() && tx_idx + 1 == compact_block_tx_count;
+                let retention =
+                    commitment_retention(cur_height, dec_output.is_some(), is_checkpoint);
+
+                if let Some((note, account, scope, fvk)) = dec_output {
+                    // See the Sapling case above for the definition of "change".
+                    let is_change = spent_from_accounts.contains(&account);
+                    let note_commitment_tree_position = Position::from(u64::from(
+                        orchard_commitment_tree_size + u32::try_from(action_idx).unwrap(),
+                    ));
+                    let nf = note.nullifier(fvk);
+
+                    orchard_outputs.push(WalletOrchardOutput::from_parts(
+                        action_idx,
+                        cmx,
+                        ephemeral_key,
+                        account,
+                        note,
+                        is_change,
+                        note_commitment_tree_position,
+                        nf,
+                        scope,
+                    ));
+                }
+
+                orchard_note_commitments.push((node, retention));
+            }
+        }
+
+        let has_sapling = !(sapling_spends.is_empty() && sapling_outputs.is_empty());
+        #[cfg(feature = "orchard")]
+        let has_orchard = !(orchard_spends.is_empty() && orchard_outputs.is_empty());
+        #[cfg(not(feature = "orchard"))]
+        let has_orchard = false;
+
+        if has_sapling || has_orchard {
             wtxs.push(WalletTx {
                 txid,
                 index: tx_index as usize,
-                sapling_spends: shielded_spends,
-                sapling_outputs: shielded_outputs,
+                sapling_spends,
+                sapling_outputs,
+                #[cfg(feature = "orchard")]
+                orchard_spends,
+                #[cfg(feature = "orchard")]
+                orchard_outputs,
             });
         }
 
@@ -657,8 +1076,8 @@ pub(crate) fn scan_block_with_runner<
         #[cfg(feature = "orchard")]
         ScannedBundles::new(
             orchard_commitment_tree_size,
-            vec![], // FIXME: collect the Orchard nullifiers
-            vec![], // FIXME: collect the Orchard note commitments
+            orchard_note_commitments,
+            orchard_nullifier_map,
         ),
     ))
 }
@@ -673,7 +1092,7 @@ mod tests {
     use rand_core::{OsRng, RngCore};
     use sapling::{
         constants::SPENDING_KEY_GENERATOR,
-        note_encryption::{sapling_note_encryption, PreparedIncomingViewingKey, SaplingDomain},
+        note_encryption::{sapling_note_encryption, SaplingDomain},
         util::generate_random_rseed,
         value::NoteValue,
         zip32::{DiversifiableFullViewingKey, ExtendedSpendingKey},
@@ -693,10 +1112,9 @@ mod tests {
         proto::compact_formats::{
             self as compact, CompactBlock, CompactSaplingOutput, CompactSaplingSpend, CompactTx,
         },
-        scan::BatchRunner,
     };
 
-    use super::{add_block_to_runner, scan_block, scan_block_with_runner, ScanningKey};
+    use super::{scan_block, scan_block_with_runner, BatchRunners};
 
     fn random_compact_tx(mut rng: impl RngCore) -> CompactTx {
         let fake_nf = {
@@ -835,18 +1253,12 @@ mod tests {
             assert_eq!(cb.vtx.len(), 2);
 
             let mut batch_runner = if scan_multithreaded {
-                let mut runner = BatchRunner::<_, _, _, _, ()>::new(
-                    10,
-                    dfvk.to_sapling_keys()
-                        .iter()
-                        .map(|(scope, ivk, _)| ((account, *scope), ivk))
-                        .map(|(tag, ivk)| (tag, PreparedIncomingViewingKey::new(ivk))),
-                );
+                let mut runners = BatchRunners::<_, ()>::for_keys(10, &[(&account, &dfvk)]);
 
-                add_block_to_runner(&Network::TestNetwork, cb.clone(), &mut runner);
-                runner.flush();
+                runners.add_block(&Network::TestNetwork, cb.clone());
+                runners.flush();
 
-                Some(runner)
+                Some(runners)
             } else {
                 None
             };
@@ -856,6 +1268,8 @@ mod tests {
                 cb,
                 &[(&account, &dfvk)],
                 &[],
+                #[cfg(feature = "orchard")]
+                &[],
                 Some(&BlockMetadata::from_parts(
                     BlockHeight::from(0),
                     BlockHash([0u8; 32]),
@@ -922,18 +1336,12 @@ mod tests {
             assert_eq!(cb.vtx.len(), 3);
 
             let mut batch_runner = if scan_multithreaded {
-                let mut runner = BatchRunner::<_, _, _, _, ()>::new(
-                    10,
-                    dfvk.to_sapling_keys()
-                        .iter()
-                        .map(|(scope, ivk, _)| ((account, *scope), ivk))
-                        .map(|(tag, ivk)| (tag, PreparedIncomingViewingKey::new(ivk))),
-                );
+                let mut runners = BatchRunners::<_, ()>::for_keys(10, &[(&account, &dfvk)]);
 
-                add_block_to_runner(&Network::TestNetwork, cb.clone(), &mut runner);
-                runner.flush();
+                runners.add_block(&Network::TestNetwork, cb.clone());
+                runners.flush();
 
-                Some(runner)
+                Some(runners)
             } else {
                 None
             };
@@ -943,6 +1351,8 @@ mod tests {
                 cb,
                 &[(&AccountId::ZERO, &dfvk)],
                 &[],
+                #[cfg(feature = "orchard")]
+                &[],
                 None,
                 batch_runner.as_mut(),
             )
@@ -999,8 +1409,16 @@ mod tests {
         assert_eq!(cb.vtx.len(), 2);
         let vks: Vec<(&AccountId, &SaplingIvk)> = vec![];
 
-        let scanned_block =
-            scan_block(&Network::TestNetwork, cb, &vks[..], &[(account, nf)], None).unwrap();
+        let scanned_block = scan_block(
+            &Network::TestNetwork,
+            cb,
+            &vks[..],
+            &[(account, nf)],
+            #[cfg(feature = "orchard")]
+            &[],
+            None,
+        )
+        .unwrap();
         let txs = scanned_block.transactions();
         assert_eq!(txs.len(), 1);
 
@@ -1028,4 +1446,156 @@ mod tests {
             ]
         );
     }
+
+    #[cfg(feature = "orchard")]
+    #[test]
+    fn scan_block_with_my_orchard_action() {
+        use orchard::{
+            keys::{FullViewingKey, Scope as OrchardScope, SpendingKey},
+            note::{ExtractedNoteCommitment, RandomSeed},
+            note_encryption::{OrchardDomain, OrchardNoteEncryption},
+            value::NoteValue as OrchardNoteValue,
+        };
+        use zcash_primitives::transaction::TxId;
+
+        use crate::proto::compact_formats::CompactOrchardAction;
+
+        fn go(scan_multithreaded: bool) {
+            let mut rng = OsRng;
+            let account = AccountId::ZERO;
+            let sk = SpendingKey::from_bytes([7; 32]).unwrap();
+            let fvk = FullViewingKey::from(&sk);
+
+            // The nullifier revealed by the action is also used as `rho` for the new note.
+            let spent_nf = orchard::note::Nullifier::from_bytes(&[0; 32]).unwrap();
+            let rseed = loop {
+                let mut bytes = [0; 32];
+                rng.fill_bytes(&mut bytes);
+                let rseed = RandomSeed::from_bytes(bytes, &spent_nf);
+                if rseed.is_some().into() {
+                    break rseed.unwrap();
+                }
+            };
+            let note = orchard::Note::from_parts(
+                fvk.address_at(0u32, OrchardScope::External),
+                OrchardNoteValue::from_raw(5),
+                spent_nf,
+                rseed,
+            )
+            .unwrap();
+            let encryptor = OrchardNoteEncryption::new(
+                Some(fvk.to_ovk(OrchardScope::External)),
+                note,
+                *MemoBytes::empty().as_array(),
+            );
+            let enc_ciphertext = encryptor.encrypt_note_plaintext();
+
+            let mut cb = CompactBlock {
+                hash: vec![1; 32],
+                prev_hash: vec![0; 32],
+                height: 1,
+                ..Default::default()
+            };
+            cb.vtx.push(random_compact_tx(&mut rng));
+
+            let mut ctx = CompactTx {
+                hash: vec![2; 32],
+                index: 1,
+                ..Default::default()
+            };
+            ctx.actions.push(CompactOrchardAction {
+                nullifier: spent_nf.to_bytes().to_vec(),
+                cmx: ExtractedNoteCommitment::from(note.commitment())
+                    .to_bytes()
+                    .to_vec(),
+                ephemeral_key: OrchardDomain::epk_bytes(encryptor.epk()).0.to_vec(),
+                ciphertext: enc_ciphertext.as_ref()[..52].to_vec(),
+            });
+            cb.vtx.push(ctx);
+            cb.chain_metadata = Some(compact::ChainMetadata {
+                sapling_commitment_tree_size: 1,
+                orchard_commitment_tree_size: 3,
+            });
+
+            let mut batch_runners = if scan_multithreaded {
+                let mut runners = BatchRunners::<_, ()>::for_keys(10, &[(&account, &fvk)]);
+
+                runners.add_block(&Network::TestNetwork, cb.clone());
+                runners.flush();
+
+                Some(runners)
+            } else {
+                None
+            };
+
+            let unlinked_cb = cb.clone();
+            let scanned_block = scan_block_with_runner(
+                &Network::TestNetwork,
+                cb,
+                &[(&account, &fvk)],
+                &[],
+                &[(account, spent_nf)],
+                None,
+                batch_runners.as_mut(),
+            )
+            .unwrap();
+            let txs = scanned_block.transactions();
+            assert_eq!(txs.len(), 1);
+
+            let tx = &txs[0];
+            assert_eq!(tx.index, 1);
+            assert_eq!(tx.sapling_spends.len(), 0);
+            assert_eq!(tx.sapling_outputs.len(), 0);
+            assert_eq!(tx.orchard_spends.len(), 1);
+            assert_eq!(tx.orchard_spends[0].nf(), &spent_nf);
+            assert_eq!(tx.orchard_spends[0].account(), account);
+            assert_eq!(tx.orchard_outputs.len(), 1);
+
+            let output = &tx.orchard_outputs[0];
+            assert_eq!(output.index(), 0);
+            assert_eq!(output.account(), account);
+            assert_eq!(output.note().value().inner(), 5);
+            assert!(output.is_change());
+            assert_eq!(output.note_commitment_tree_position(), Position::from(2));
+            assert_eq!(output.nf(), &note.nullifier(&fvk));
+
+            // The map has an entry for each transaction in the block. The spent nullifier was
+            // linked to the wallet, so it is not added to the entry for our transaction.
+            let nullifier_map = scanned_block.orchard().nullifier_map();
+            assert_eq!(nullifier_map.len(), 2);
+            assert_eq!(nullifier_map[1].0, TxId::from_bytes([2; 32]));
+            assert_eq!(nullifier_map[1].1, 1);
+            assert!(nullifier_map[1].2.is_empty());
+            assert_eq!(
+                scanned_block
+                    .orchard()
+                    .commitments()
+                    .iter()
+                    .map(|(_, retention)| *retention)
+                    .collect::<Vec<_>>(),
+                vec![Retention::Checkpoint {
+                    id: scanned_block.height(),
+                    is_marked: true
+                }]
+            );
+
+            // When the spent nullifier is not known to the wallet, it is recorded in the
+            // map so that the spend can be linked once earlier blocks are scanned.
+            let scanned_block = scan_block(
+                &Network::TestNetwork,
+                unlinked_cb,
+                &[(&account, &fvk)],
+                &[],
+                &[],
+                None,
+            )
+            .unwrap();
+            let nullifier_map = scanned_block.orchard().nullifier_map();
+            assert!(nullifier_map[0].2.is_empty());
+            assert_eq!(nullifier_map[1].2, vec![spent_nf]);
+        }
+
+        go(false);
+        go(true);
+    }
 }
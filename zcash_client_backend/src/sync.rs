@@ -0,0 +1,803 @@
+//! A reusable driver for synchronizing a wallet with the chain.
+//!
+//! The [`data_api::chain`] module documents the sequence of operations that a light client must
+//! perform in order to bring its wallet up to date: update the wallet's view of the chain tip,
+//! download note commitment tree subtree roots, obtain the suggested scan ranges from the wallet,
+//! download and scan the blocks in those ranges, and recover from chain reorgs by rewinding the
+//! wallet when a continuity error is encountered. The [`run`] function in this module implements
+//! that state machine generically, so that it does not need to be reimplemented by each consumer
+//! of this crate.
+//!
+//! Access to chain data is abstracted by the [`BlockCache`] trait, which extends [`BlockSource`]
+//! with the ability to download blocks (typically from `lightwalletd`) into the cache that the
+//! [`BlockSource`] reads from.
+//!
+//! [`data_api::chain`]: crate::data_api::chain
+
+use std::fmt;
+use std::ops::{ControlFlow, Range};
+
+use shardtree::error::ShardTreeError;
+use zcash_primitives::consensus::{self, BlockHeight};
+
+use crate::{
+    data_api::{
+        chain::{self, scan_cached_blocks, BlockSource, CommitmentTreeRoot, ScanSummary},
+        scanning::{ScanPriority, ScanRange},
+        WalletCommitmentTrees, WalletRead, WalletWrite,
+    },
+    scanning::ScanError,
+};
+
+/// A [`BlockSource`] that is backed by a local cache which can be populated on demand with
+/// chain data downloaded from a remote source, such as a `lightwalletd` server.
+pub trait BlockCache: BlockSource {
+    /// Returns the height of the current tip of the remote chain.
+    fn chain_tip(&mut self) -> Result<BlockHeight, Self::Error>;
+
+    /// Returns the roots of the complete Sapling note commitment tree subtrees, beginning at the
+    /// subtree having the given index.
+    fn sapling_subtree_roots(
+        &mut self,
+        start_index: u64,
+    ) -> Result<Vec<CommitmentTreeRoot<sapling::Node>>, Self::Error>;
+
+    /// Returns the roots of the complete Orchard note commitment tree subtrees, beginning at the
+    /// subtree having the given index.
+    #[cfg(feature = "orchard")]
+    fn orchard_subtree_roots(
+        &mut self,
+        start_index: u64,
+    ) -> Result<Vec<CommitmentTreeRoot<orchard::tree::MerkleHashOrchard>>, Self::Error>;
+
+    /// Downloads the compact blocks in the given range into the cache, replacing any blocks
+    /// already cached at those heights.
+    fn download_blocks(&mut self, block_range: &Range<BlockHeight>) -> Result<(), Self::Error>;
+
+    /// Removes all blocks with heights greater than the given height from the cache.
+    fn truncate_to_height(&mut self, block_height: BlockHeight) -> Result<(), Self::Error>;
+}
+
+/// Parameters that control the behavior of [`run`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyncConfig {
+    batch_size: u32,
+    rewind_distance: u32,
+    max_rewinds: u32,
+}
+
+impl SyncConfig {
+    /// Constructs a new [`SyncConfig`].
+    ///
+    /// * `batch_size`: The maximum number of blocks that will be downloaded and scanned at once.
+    /// * `rewind_distance`: The number of blocks below the height at which a continuity error was
+    ///   detected to which the wallet will be rewound.
+    /// * `max_rewinds`: The maximum number of times that the wallet will be rewound in order to
+    ///   recover from continuity errors before the scanned blocks reach the height at which the
+    ///   highest of those errors was detected.
+    ///
+    /// Returns `None` if `batch_size` or `rewind_distance` is zero.
+    pub fn from_parts(batch_size: u32, rewind_distance: u32, max_rewinds: u32) -> Option<Self> {
+        if batch_size == 0 || rewind_distance == 0 {
+            None
+        } else {
+            Some(Self {
+                batch_size,
+                rewind_distance,
+                max_rewinds,
+            })
+        }
+    }
+
+    /// Returns the maximum number of blocks that will be downloaded and scanned at once.
+    pub fn batch_size(&self) -> u32 {
+        self.batch_size
+    }
+
+    /// Returns the number of blocks below the height at which a continuity error was detected to
+    /// which the wallet will be rewound.
+    pub fn rewind_distance(&self) -> u32 {
+        self.rewind_distance
+    }
+
+    /// Returns the maximum number of times that the wallet will be rewound in order to recover
+    /// from continuity errors before the scanned blocks reach the height at which the highest of
+    /// those errors was detected.
+    pub fn max_rewinds(&self) -> u32 {
+        self.max_rewinds
+    }
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 1000,
+            rewind_distance: 10,
+            max_rewinds: 10,
+        }
+    }
+}
+
+/// Information about a batch of blocks that has been scanned, passed to the progress callback
+/// provided to [`run`].
+#[derive(Clone, Debug)]
+pub struct SyncProgress {
+    chain_tip: BlockHeight,
+    priority: ScanPriority,
+    scan_summary: ScanSummary,
+}
+
+impl SyncProgress {
+    /// Returns the chain tip height that the wallet is being synchronized to.
+    pub fn chain_tip(&self) -> BlockHeight {
+        self.chain_tip
+    }
+
+    /// Returns the priority of the scan range that the scanned blocks belong to.
+    pub fn priority(&self) -> ScanPriority {
+        self.priority
+    }
+
+    /// Returns the summary of the changes made to the wallet by scanning the batch.
+    pub fn scan_summary(&self) -> &ScanSummary {
+        &self.scan_summary
+    }
+}
+
+/// The result of a successful call to [`run`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncOutcome {
+    /// The wallet has been fully scanned up to the given chain tip.
+    Complete { chain_tip: BlockHeight },
+    /// Synchronization was stopped by the progress callback before the wallet was fully scanned.
+    Cancelled,
+}
+
+/// Errors that can occur in the course of synchronizing a wallet.
+#[derive(Debug)]
+pub enum Error<WalletError, CacheError, TreeError> {
+    /// An error produced by the wallet database.
+    Wallet(WalletError),
+
+    /// An error produced by the block cache, or in the process of downloading chain data.
+    Cache(CacheError),
+
+    /// An error produced in the process of updating the wallet's note commitment trees.
+    CommitmentTree(ShardTreeError<TreeError>),
+
+    /// A block could not be scanned, either because it violated a rule other than those related
+    /// to chain continuity, or because the continuity error persisted after the wallet had been
+    /// rewound [`SyncConfig::max_rewinds`] times.
+    Scan(ScanError),
+}
+
+impl<WE: fmt::Display, CE: fmt::Display, TE: fmt::Display> fmt::Display for Error<WE, CE, TE> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            Error::Wallet(e) => write!(f, "The wallet produced the following error: {}", e),
+            Error::Cache(e) => write!(f, "The block cache produced the following error: {}", e),
+            Error::CommitmentTree(e) => {
+                write!(
+                    f,
+                    "An error occurred updating the note commitment tree: {}",
+                    e
+                )
+            }
+            Error::Scan(e) => write!(f, "Scanning produced the following error: {}", e),
+        }
+    }
+}
+
+impl<WE, CE, TE> std::error::Error for Error<WE, CE, TE>
+where
+    WE: fmt::Debug + fmt::Display + std::error::Error + 'static,
+    CE: fmt::Debug + fmt::Display + std::error::Error + 'static,
+    TE: fmt::Debug + fmt::Display + std::error::Error + 'static,
+{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self {
+            Error::Wallet(e) => Some(e),
+            Error::Cache(e) => Some(e),
+            Error::CommitmentTree(e) => Some(e),
+            Error::Scan(_) => None,
+        }
+    }
+}
+
+impl<WE, CE, TE> From<chain::error::Error<WE, CE>> for Error<WE, CE, TE> {
+    fn from(e: chain::error::Error<WE, CE>) -> Self {
+        match e {
+            chain::error::Error::Wallet(e) => Error::Wallet(e),
+            chain::error::Error::BlockSource(e) => Error::Cache(e),
+            chain::error::Error::Scan(e) => Error::Scan(e),
+        }
+    }
+}
+
+#[allow(type_alias_bounds)]
+type SyncError<CacheT: BlockSource, DbT: WalletRead + WalletCommitmentTrees> = Error<
+    <DbT as WalletRead>::Error,
+    <CacheT as BlockSource>::Error,
+    <DbT as WalletCommitmentTrees>::Error,
+>;
+
+/// Synchronizes the wallet with the chain.
+///
+/// This repeatedly updates the wallet's view of the chain tip and note commitment tree subtree
+/// roots, then downloads and scans the ranges suggested by [`WalletRead::suggest_scan_ranges`]
+/// in batches of at most [`SyncConfig::batch_size`] blocks, until no unscanned ranges remain. If
+/// a continuity error is encountered while scanning, the wallet and the block cache are rewound
+/// by [`SyncConfig::rewind_distance`] blocks below the height of the error and synchronization
+/// resumes from the updated chain tip. A reorg deeper than the rewind distance is handled by
+/// rewinding repeatedly; if the continuity errors persist for more than
+/// [`SyncConfig::max_rewinds`] consecutive rewinds, the error is returned as [`Error::Scan`].
+///
+/// `on_progress` is called after each batch of blocks has been scanned; returning
+/// [`ControlFlow::Break`] from it stops synchronization, in which case this function returns
+/// [`SyncOutcome::Cancelled`]. The wallet remains in a consistent state, and synchronization can
+/// be resumed by calling this function again.
+#[allow(clippy::type_complexity)]
+pub fn run<ParamsT, CacheT, DbT>(
+    params: &ParamsT,
+    config: &SyncConfig,
+    block_cache: &mut CacheT,
+    wallet_db: &mut DbT,
+    mut on_progress: impl FnMut(&SyncProgress) -> ControlFlow<()>,
+) -> Result<SyncOutcome, SyncError<CacheT, DbT>>
+where
+    ParamsT: consensus::Parameters + Send + 'static,
+    CacheT: BlockCache,
+    DbT: WalletWrite + WalletCommitmentTrees,
+{
+    let mut next_subtree_indices = SubtreeIndices::default();
+    // The number of rewinds performed since the wallet last scanned past the height of the
+    // highest continuity error encountered, and that height.
+    let mut rewinds = 0;
+    let mut unresolved_error_height: Option<BlockHeight> = None;
+
+    'sync: loop {
+        update_subtree_roots(block_cache, wallet_db, &mut next_subtree_indices)?;
+
+        let chain_tip = block_cache.chain_tip().map_err(Error::Cache)?;
+        wallet_db
+            .update_chain_tip(chain_tip)
+            .map_err(Error::Wallet)?;
+
+        // Scanning a range can change the priorities of the remaining ranges (for example, once
+        // the wallet's previous chain tip has been verified), so we only ever scan the range with
+        // the highest priority before returning to the wallet for updated suggestions.
+        let scan_range = match wallet_db
+            .suggest_scan_ranges()
+            .map_err(Error::Wallet)?
+            .into_iter()
+            .next()
+        {
+            Some(scan_range) => scan_range,
+            None => return Ok(SyncOutcome::Complete { chain_tip }),
+        };
+
+        for batch in batches(&scan_range, config.batch_size) {
+            block_cache
+                .download_blocks(batch.block_range())
+                .map_err(Error::Cache)?;
+
+            let scan_result = scan_cached_blocks(
+                params,
+                block_cache,
+                wallet_db,
+                batch.block_range().start,
+                batch.len(),
+            );
+
+            let scan_summary = match scan_result {
+                Ok(summary) => {
+                    if unresolved_error_height.map_or(false, |h| batch.block_range().end > h) {
+                        rewinds = 0;
+                        unresolved_error_height = None;
+                    }
+                    summary
+                }
+                Err(chain::error::Error::Scan(err)) if err.is_continuity_error() => {
+                    if rewinds >= config.max_rewinds {
+                        return Err(Error::Scan(err));
+                    }
+                    rewinds += 1;
+                    unresolved_error_height =
+                        std::cmp::max(unresolved_error_height, Some(err.at_height()));
+
+                    // Rewind the wallet and the cache to below the height at which the
+                    // discontinuity was detected, then start over from the updated chain tip, as
+                    // the blocks that we previously scanned may have been orphaned.
+                    let rewind_height = err.at_height().saturating_sub(config.rewind_distance);
+                    wallet_db
+                        .truncate_to_height(rewind_height)
+                        .map_err(Error::Wallet)?;
+                    block_cache
+                        .truncate_to_height(rewind_height)
+                        .map_err(Error::Cache)?;
+                    continue 'sync;
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            let progress = SyncProgress {
+                chain_tip,
+                priority: batch.priority(),
+                scan_summary,
+            };
+            if on_progress(&progress).is_break() {
+                return Ok(SyncOutcome::Cancelled);
+            }
+        }
+    }
+}
+
+/// The indices of the first subtree roots in each pool that have not yet been passed to the
+/// wallet database by the current call to [`run`].
+#[derive(Default)]
+struct SubtreeIndices {
+    sapling: u64,
+    #[cfg(feature = "orchard")]
+    orchard: u64,
+}
+
+/// Passes the subtree roots that have been completed since the last call to this function to
+/// the wallet database, and advances `next_indices` past them.
+fn update_subtree_roots<CacheT, DbT>(
+    block_cache: &mut CacheT,
+    wallet_db: &mut DbT,
+    next_indices: &mut SubtreeIndices,
+) -> Result<(), SyncError<CacheT, DbT>>
+where
+    CacheT: BlockCache,
+    DbT: WalletRead + WalletCommitmentTrees,
+{
+    let sapling_roots = block_cache
+        .sapling_subtree_roots(next_indices.sapling)
+        .map_err(Error::Cache)?;
+    wallet_db
+        .put_sapling_subtree_roots(next_indices.sapling, &sapling_roots)
+        .map_err(Error::CommitmentTree)?;
+    next_indices.sapling += sapling_roots.len() as u64;
+
+    #[cfg(feature = "orchard")]
+    {
+        let orchard_roots = block_cache
+            .orchard_subtree_roots(next_indices.orchard)
+            .map_err(Error::Cache)?;
+        wallet_db
+            .put_orchard_subtree_roots(next_indices.orchard, &orchard_roots)
+            .map_err(Error::CommitmentTree)?;
+        next_indices.orchard += orchard_roots.len() as u64;
+    }
+
+    Ok(())
+}
+
+/// Splits the given scan range into consecutive subranges of at most `batch_size` blocks.
+fn batches(scan_range: &ScanRange, batch_size: u32) -> impl Iterator<Item = ScanRange> {
+    let priority = scan_range.priority();
+    let end = scan_range.block_range().end;
+    std::iter::successors(
+        Some(scan_range.block_range().start).filter(|start| *start < end),
+        move |start| Some(*start + batch_size).filter(|next| *next < end),
+    )
+    .map(move |start| {
+        ScanRange::from_parts(start..std::cmp::min(start + batch_size, end), priority)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_primitives::consensus::BlockHeight;
+
+    use crate::data_api::scanning::{ScanPriority, ScanRange};
+
+    use super::{batches, SyncConfig};
+
+    #[test]
+    fn batches_cover_range() {
+        let range = ScanRange::from_parts(
+            BlockHeight::from(100)..BlockHeight::from(125),
+            ScanPriority::Historic,
+        );
+
+        assert_eq!(
+            batches(&range, 10)
+                .map(|r| r.block_range().clone())
+                .collect::<Vec<_>>(),
+            vec![
+                BlockHeight::from(100)..BlockHeight::from(110),
+                BlockHeight::from(110)..BlockHeight::from(120),
+                BlockHeight::from(120)..BlockHeight::from(125),
+            ]
+        );
+        assert!(batches(&range, 10).all(|r| r.priority() == ScanPriority::Historic));
+        assert_eq!(
+            batches(&range, 25)
+                .map(|r| r.block_range().clone())
+                .collect::<Vec<_>>(),
+            vec![BlockHeight::from(100)..BlockHeight::from(125)]
+        );
+
+        let empty = ScanRange::from_parts(
+            BlockHeight::from(100)..BlockHeight::from(100),
+            ScanPriority::Historic,
+        );
+        assert_eq!(batches(&empty, 10).count(), 0);
+    }
+
+    #[test]
+    fn config_rejects_zero_batch_size() {
+        assert_eq!(SyncConfig::from_parts(0, 10, 10), None);
+        assert_eq!(
+            SyncConfig::from_parts(1, 10, 10).map(|c| c.batch_size()),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn config_rejects_zero_rewind_distance() {
+        assert_eq!(SyncConfig::from_parts(10, 0, 10), None);
+        assert_eq!(
+            SyncConfig::from_parts(10, 1, 0).map(|c| (c.rewind_distance(), c.max_rewinds())),
+            Some((1, 0))
+        );
+    }
+
+    #[cfg(feature = "test-dependencies")]
+    mod mock {
+        use std::convert::Infallible;
+        use std::ops::{ControlFlow, Range};
+
+        use zcash_primitives::consensus::{BlockHeight, Network};
+
+        use crate::{
+            data_api::{
+                chain::{error::Error, BlockSource, CommitmentTreeRoot},
+                testing::MockWalletDb,
+            },
+            proto::compact_formats::CompactBlock,
+            sync::{run, BlockCache, SyncConfig, SyncOutcome},
+        };
+
+        #[derive(Default)]
+        struct MockBlockCache {
+            tip_requests: usize,
+            downloaded: Vec<Range<BlockHeight>>,
+        }
+
+        impl BlockSource for MockBlockCache {
+            type Error = Infallible;
+
+            fn with_blocks<F, DbErrT>(
+                &self,
+                _from_height: Option<BlockHeight>,
+                _limit: Option<usize>,
+                _with_row: F,
+            ) -> Result<(), Error<DbErrT, Infallible>>
+            where
+                F: FnMut(CompactBlock) -> Result<(), Error<DbErrT, Infallible>>,
+            {
+                Ok(())
+            }
+        }
+
+        impl BlockCache for MockBlockCache {
+            fn chain_tip(&mut self) -> Result<BlockHeight, Self::Error> {
+                self.tip_requests += 1;
+                Ok(BlockHeight::from(2_000_000))
+            }
+
+            fn sapling_subtree_roots(
+                &mut self,
+                _start_index: u64,
+            ) -> Result<Vec<CommitmentTreeRoot<sapling::Node>>, Self::Error> {
+                Ok(vec![])
+            }
+
+            #[cfg(feature = "orchard")]
+            fn orchard_subtree_roots(
+                &mut self,
+                _start_index: u64,
+            ) -> Result<Vec<CommitmentTreeRoot<orchard::tree::MerkleHashOrchard>>, Self::Error>
+            {
+                Ok(vec![])
+            }
+
+            fn download_blocks(
+                &mut self,
+                block_range: &Range<BlockHeight>,
+            ) -> Result<(), Self::Error> {
+                self.downloaded.push(block_range.clone());
+                Ok(())
+            }
+
+            fn truncate_to_height(
+                &mut self,
+                _block_height: BlockHeight,
+            ) -> Result<(), Self::Error> {
+                Ok(())
+            }
+        }
+
+        #[test]
+        fn sync_completes_when_nothing_to_scan() {
+            let mut cache = MockBlockCache::default();
+            let mut wallet_db = MockWalletDb::new(Network::TestNetwork);
+
+            let outcome = run(
+                &Network::TestNetwork,
+                &SyncConfig::default(),
+                &mut cache,
+                &mut wallet_db,
+                |_| ControlFlow::Continue(()),
+            )
+            .unwrap();
+
+            assert_eq!(
+                outcome,
+                SyncOutcome::Complete {
+                    chain_tip: BlockHeight::from(2_000_000)
+                }
+            );
+            assert_eq!(cache.tip_requests, 1);
+            assert!(cache.downloaded.is_empty());
+        }
+    }
+
+    #[cfg(all(feature = "test-dependencies", feature = "unstable-memory-wallet"))]
+    mod mem {
+        use std::collections::BTreeMap;
+        use std::convert::Infallible;
+        use std::ops::{ControlFlow, Range};
+
+        use sapling::zip32::{DiversifiableFullViewingKey, ExtendedSpendingKey};
+        use secrecy::SecretVec;
+        use zcash_primitives::{
+            block::BlockHash,
+            consensus::{BlockHeight, Network, NetworkUpgrade, Parameters},
+            transaction::components::amount::NonNegativeAmount,
+        };
+
+        use crate::{
+            data_api::{
+                chain::{error::Error, BlockSource, CommitmentTreeRoot},
+                mem::MemoryWalletDb,
+                testing::conformance::{fake_compact_block, AddressType},
+                AccountBirthday, WalletRead, WalletWrite,
+            },
+            proto::compact_formats::CompactBlock,
+            scanning::ScanError,
+            sync::{run, BlockCache, SyncConfig, SyncOutcome},
+        };
+
+        /// A block cache that downloads blocks from a fake chain, which tests may replace in
+        /// order to simulate a reorg.
+        #[derive(Default)]
+        struct MockChain {
+            chain: BTreeMap<BlockHeight, CompactBlock>,
+            cache: BTreeMap<BlockHeight, CompactBlock>,
+            truncations: Vec<BlockHeight>,
+        }
+
+        impl MockChain {
+            /// Replaces the chain above `fork_height` with `count` new blocks, each of which
+            /// contains a single Sapling output of `value` paying `dfvk`.
+            fn fork(
+                &mut self,
+                fork_height: BlockHeight,
+                count: u32,
+                dfvk: &DiversifiableFullViewingKey,
+                value: NonNegativeAmount,
+            ) {
+                self.chain.split_off(&(fork_height + 1));
+                let (mut prev_hash, mut tree_size) =
+                    self.chain
+                        .get(&fork_height)
+                        .map_or((BlockHash([0; 32]), 0), |cb| {
+                            (
+                                cb.hash(),
+                                cb.chain_metadata
+                                    .as_ref()
+                                    .unwrap()
+                                    .sapling_commitment_tree_size,
+                            )
+                        });
+
+                for height in (1..=count).map(|i| fork_height + i) {
+                    let (cb, _) = fake_compact_block(
+                        &Network::TestNetwork,
+                        height,
+                        prev_hash,
+                        dfvk,
+                        AddressType::DefaultExternal,
+                        value,
+                        tree_size,
+                        0,
+                    );
+                    prev_hash = cb.hash();
+                    tree_size += 1;
+                    self.chain.insert(height, cb);
+                }
+            }
+        }
+
+        impl BlockSource for MockChain {
+            type Error = Infallible;
+
+            fn with_blocks<F, DbErrT>(
+                &self,
+                from_height: Option<BlockHeight>,
+                limit: Option<usize>,
+                mut with_row: F,
+            ) -> Result<(), Error<DbErrT, Infallible>>
+            where
+                F: FnMut(CompactBlock) -> Result<(), Error<DbErrT, Infallible>>,
+            {
+                self.cache
+                    .range(from_height.unwrap_or_else(|| BlockHeight::from(0))..)
+                    .take(limit.unwrap_or(usize::MAX))
+                    .try_for_each(|(_, cb)| with_row(cb.clone()))
+            }
+        }
+
+        impl BlockCache for MockChain {
+            fn chain_tip(&mut self) -> Result<BlockHeight, Self::Error> {
+                Ok(*self.chain.keys().next_back().unwrap())
+            }
+
+            fn sapling_subtree_roots(
+                &mut self,
+                _start_index: u64,
+            ) -> Result<Vec<CommitmentTreeRoot<sapling::Node>>, Self::Error> {
+                Ok(vec![])
+            }
+
+            #[cfg(feature = "orchard")]
+            fn orchard_subtree_roots(
+                &mut self,
+                _start_index: u64,
+            ) -> Result<Vec<CommitmentTreeRoot<orchard::tree::MerkleHashOrchard>>, Self::Error>
+            {
+                Ok(vec![])
+            }
+
+            fn download_blocks(
+                &mut self,
+                block_range: &Range<BlockHeight>,
+            ) -> Result<(), Self::Error> {
+                for (height, cb) in self.chain.range(block_range.clone()) {
+                    self.cache.insert(*height, cb.clone());
+                }
+                Ok(())
+            }
+
+            fn truncate_to_height(&mut self, block_height: BlockHeight) -> Result<(), Self::Error> {
+                self.cache.split_off(&(block_height + 1));
+                self.truncations.push(block_height);
+                Ok(())
+            }
+        }
+
+        fn sapling_activation() -> BlockHeight {
+            Network::TestNetwork
+                .activation_height(NetworkUpgrade::Sapling)
+                .unwrap()
+        }
+
+        /// Returns a wallet containing a single account with a birthday at Sapling activation,
+        /// along with the account's Sapling full viewing key.
+        fn wallet_with_account() -> (MemoryWalletDb<Network>, DiversifiableFullViewingKey) {
+            let mut wallet_db = MemoryWalletDb::new(Network::TestNetwork, 100);
+            let (_, usk) = wallet_db
+                .create_account(
+                    &SecretVec::new(vec![0u8; 32]),
+                    AccountBirthday::from_sapling_activation(&Network::TestNetwork),
+                )
+                .unwrap();
+            let dfvk = usk.sapling().to_diversifiable_full_viewing_key();
+            (wallet_db, dfvk)
+        }
+
+        #[test]
+        fn sync_recovers_from_reorg() {
+            let (mut wallet_db, dfvk) = wallet_with_account();
+            let other_dfvk = ExtendedSpendingKey::master(&[]).to_diversifiable_full_viewing_key();
+            let value = NonNegativeAmount::const_from_u64(10000);
+            let config = SyncConfig::from_parts(5, 10, 3).unwrap();
+
+            // Sync a chain of 20 blocks, each paying the wallet.
+            let mut cache = MockChain::default();
+            cache.fork(sapling_activation() - 1, 20, &dfvk, value);
+            let tip = sapling_activation() + 19;
+            assert_eq!(
+                run(
+                    &Network::TestNetwork,
+                    &config,
+                    &mut cache,
+                    &mut wallet_db,
+                    |_| ControlFlow::Continue(())
+                )
+                .unwrap(),
+                SyncOutcome::Complete { chain_tip: tip }
+            );
+            assert_eq!(
+                wallet_db
+                    .block_max_scanned()
+                    .unwrap()
+                    .map(|m| m.block_height()),
+                Some(tip)
+            );
+            assert!(cache.truncations.is_empty());
+
+            // Reorg away the last five blocks, replacing them with a longer chain that does not
+            // pay the wallet. The reorg is detected when the wallet connects its previous tip to
+            // the new chain, and is deeper than the wallet's previous tip so that it can only
+            // be recovered from by rewinding.
+            let fork_height = sapling_activation() + 14;
+            cache.fork(fork_height, 10, &other_dfvk, value);
+            let new_tip = fork_height + 10;
+            assert_eq!(
+                run(
+                    &Network::TestNetwork,
+                    &config,
+                    &mut cache,
+                    &mut wallet_db,
+                    |_| ControlFlow::Continue(())
+                )
+                .unwrap(),
+                SyncOutcome::Complete { chain_tip: new_tip }
+            );
+
+            // The wallet was rewound below the fork point, and is now synced to the new chain.
+            assert_eq!(cache.truncations, vec![tip + 1 - 10]);
+            let max_scanned = wallet_db.block_max_scanned().unwrap().unwrap();
+            assert_eq!(max_scanned.block_height(), new_tip);
+            assert_eq!(
+                Some(max_scanned.block_hash()),
+                cache.chain.get(&new_tip).map(|cb| cb.hash())
+            );
+
+            // Only the notes received in blocks up to the fork point remain in the wallet.
+            let summary = wallet_db.get_wallet_summary(1).unwrap().unwrap();
+            let balance = summary.account_balances().values().next().unwrap();
+            assert_eq!(
+                balance.total(),
+                NonNegativeAmount::const_from_u64(15 * 10000)
+            );
+        }
+
+        #[test]
+        fn sync_fails_after_max_rewinds() {
+            let (mut wallet_db, dfvk) = wallet_with_account();
+            let value = NonNegativeAmount::const_from_u64(10000);
+            let config = SyncConfig::from_parts(5, 2, 3).unwrap();
+
+            // Break the chain at a height that no amount of rewinding can repair.
+            let mut cache = MockChain::default();
+            cache.fork(sapling_activation() - 1, 10, &dfvk, value);
+            let broken_height = sapling_activation() + 7;
+            cache
+                .chain
+                .get_mut(&broken_height)
+                .unwrap()
+                .prev_hash
+                .copy_from_slice(&[7; 32]);
+
+            let result = run(
+                &Network::TestNetwork,
+                &config,
+                &mut cache,
+                &mut wallet_db,
+                |_| ControlFlow::Continue(()),
+            );
+            assert_matches!(
+                result,
+                Err(crate::sync::Error::Scan(ScanError::PrevHashMismatch { at_height }))
+                    if at_height == broken_height
+            );
+            assert_eq!(cache.truncations, vec![broken_height - 2; 3]);
+        }
+    }
+}
@@ -77,6 +77,10 @@ pub struct WalletTx<N, S> {
     pub index: usize,
     pub sapling_spends: Vec<WalletSaplingSpend>,
     pub sapling_outputs: Vec<WalletSaplingOutput<N, S>>,
+    #[cfg(feature = "orchard")]
+    pub orchard_spends: Vec<WalletOrchardSpend>,
+    #[cfg(feature = "orchard")]
+    pub orchard_outputs: Vec<WalletOrchardOutput<S>>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -238,6 +242,115 @@ impl<N, S> WalletSaplingOutput<N, S> {
     }
 }
 
+/// A subset of an [`Action`] relevant to wallets and light clients, describing the spend of
+/// a note belonging to the wallet.
+///
+/// [`Action`]: orchard::Action
+#[cfg(feature = "orchard")]
+pub struct WalletOrchardSpend {
+    index: usize,
+    nf: orchard::note::Nullifier,
+    account: AccountId,
+}
+
+#[cfg(feature = "orchard")]
+impl WalletOrchardSpend {
+    pub fn from_parts(index: usize, nf: orchard::note::Nullifier, account: AccountId) -> Self {
+        Self { index, nf, account }
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+    pub fn nf(&self) -> &orchard::note::Nullifier {
+        &self.nf
+    }
+    pub fn account(&self) -> AccountId {
+        self.account
+    }
+}
+
+/// A subset of an [`Action`] relevant to wallets and light clients, describing an output
+/// received by the wallet.
+///
+/// Orchard outputs are always recovered using full viewing keys, and so unlike
+/// [`WalletSaplingOutput`] the nullifier for the received note is always available.
+///
+/// The type parameter `<S>` is used to specify the type of the scope of the key used to recover
+/// this output; this will usually be [`zcash_primitives::zip32::Scope`] for received notes, and
+/// `()` for sent notes.
+///
+/// [`Action`]: orchard::Action
+#[cfg(feature = "orchard")]
+pub struct WalletOrchardOutput<S> {
+    index: usize,
+    cmx: orchard::note::ExtractedNoteCommitment,
+    ephemeral_key: EphemeralKeyBytes,
+    account: AccountId,
+    note: orchard::Note,
+    is_change: bool,
+    note_commitment_tree_position: Position,
+    nf: orchard::note::Nullifier,
+    recipient_key_scope: S,
+}
+
+#[cfg(feature = "orchard")]
+impl<S> WalletOrchardOutput<S> {
+    /// Constructs a new `WalletOrchardOutput` value from its constituent parts.
+    #[allow(clippy::too_many_arguments)]
+    pub fn from_parts(
+        index: usize,
+        cmx: orchard::note::ExtractedNoteCommitment,
+        ephemeral_key: EphemeralKeyBytes,
+        account: AccountId,
+        note: orchard::Note,
+        is_change: bool,
+        note_commitment_tree_position: Position,
+        nf: orchard::note::Nullifier,
+        recipient_key_scope: S,
+    ) -> Self {
+        Self {
+            index,
+            cmx,
+            ephemeral_key,
+            account,
+            note,
+            is_change,
+            note_commitment_tree_position,
+            nf,
+            recipient_key_scope,
+        }
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+    pub fn cmx(&self) -> &orchard::note::ExtractedNoteCommitment {
+        &self.cmx
+    }
+    pub fn ephemeral_key(&self) -> &EphemeralKeyBytes {
+        &self.ephemeral_key
+    }
+    pub fn account(&self) -> AccountId {
+        self.account
+    }
+    pub fn note(&self) -> &orchard::Note {
+        &self.note
+    }
+    pub fn is_change(&self) -> bool {
+        self.is_change
+    }
+    pub fn note_commitment_tree_position(&self) -> Position {
+        self.note_commitment_tree_position
+    }
+    pub fn nf(&self) -> &orchard::note::Nullifier {
+        &self.nf
+    }
+    pub fn recipient_key_scope(&self) -> &S {
+        &self.recipient_key_scope
+    }
+}
+
 /// An enumeration of supported shielded note types for use in [`ReceivedNote`]
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Note {
@@ -375,6 +488,7 @@ impl<NoteRef> orchard_fees::InputView<NoteRef> for ReceivedNote<NoteRef, orchard
 /// viewing key, refer to [ZIP 310].
 ///
 /// [ZIP 310]: https://zips.z.cash/zip-0310
+#[derive(Clone, Debug)]
 pub enum OvkPolicy {
     /// Use the outgoing viewing key from the sender's [`ExtendedFullViewingKey`].
     ///
@@ -322,28 +322,34 @@ where
 #[cfg(test)]
 #[allow(deprecated)]
 mod tests {
-    use std::num::NonZeroU32;
+    use std::{collections::HashMap, convert::Infallible, num::NonZeroU32};
 
     use sapling::zip32::ExtendedSpendingKey;
     use zcash_primitives::{
         block::BlockHash,
+        consensus::BlockHeight,
         transaction::{components::amount::NonNegativeAmount, fees::zip317::FeeRule},
     };
 
     use zcash_client_backend::{
         address::Address,
         data_api::{
-            chain::error::Error, wallet::input_selection::GreedyInputSelector, AccountBirthday,
-            WalletRead,
+            chain::{
+                error::{Error, RewindError},
+                ChainView,
+            },
+            testing::conformance::{AddressType, TestBuilder},
+            wallet::input_selection::{GreedyInputSelector, PrivacyPolicy},
+            AccountBirthday, WalletRead,
         },
-        fees::{zip317::SingleOutputChangeStrategy, DustOutputPolicy},
+        fees::{zip317::SingleOutputChangeStrategy, ChangePoolPolicy, DustOutputPolicy},
         scanning::ScanError,
         wallet::OvkPolicy,
         zip321::{Payment, TransactionRequest},
     };
 
     use crate::{
-        testing::{AddressType, TestBuilder},
+        testing::{BlockCache, TestDbFactory},
         wallet::truncate_to_height,
         AccountId,
     };
@@ -351,7 +357,8 @@ mod tests {
     #[test]
     fn valid_chain_states() {
         let mut st = TestBuilder::new()
-            .with_block_cache()
+            .with_block_cache(BlockCache::new())
+            .with_data_store_factory(TestDbFactory)
             .with_test_account(AccountBirthday::from_sapling_activation)
             .build();
 
@@ -384,7 +391,8 @@ mod tests {
     #[test]
     fn invalid_chain_cache_disconnected() {
         let mut st = TestBuilder::new()
-            .with_block_cache()
+            .with_block_cache(BlockCache::new())
+            .with_data_store_factory(TestDbFactory)
             .with_test_account(AccountBirthday::from_sapling_activation)
             .build();
 
@@ -435,7 +443,8 @@ mod tests {
     #[test]
     fn data_db_truncation() {
         let mut st = TestBuilder::new()
-            .with_block_cache()
+            .with_block_cache(BlockCache::new())
+            .with_data_store_factory(TestDbFactory)
             .with_test_account(AccountBirthday::from_sapling_activation)
             .build();
 
@@ -488,10 +497,74 @@ mod tests {
         );
     }
 
+    struct MockChainView(HashMap<BlockHeight, BlockHash>);
+
+    impl ChainView for MockChainView {
+        type Error = Infallible;
+
+        fn block_hash(&self, height: BlockHeight) -> Result<Option<BlockHash>, Self::Error> {
+            Ok(self.0.get(&height).copied())
+        }
+    }
+
+    #[test]
+    fn plan_rewind_finds_fork_point() {
+        let mut st = TestBuilder::new()
+            .with_block_cache(BlockCache::new())
+            .with_data_store_factory(TestDbFactory)
+            .with_test_account(AccountBirthday::from_sapling_activation)
+            .build();
+
+        let dfvk = st.test_account_sapling().unwrap();
+
+        // Create fake CompactBlocks sending value to the address
+        let value = NonNegativeAmount::const_from_u64(5);
+        let (h1, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        let (h2, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        let (h3, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+
+        // Scan the cache
+        st.scan_cached_blocks(h1, 3);
+        assert_eq!(st.get_total_balance(AccountId::ZERO), (value * 3).unwrap());
+
+        // A chain that agrees with the wallet at h1, but has been reorged above it
+        let chain_view = MockChainView(
+            [
+                (h1, st.wallet().get_block_hash(h1).unwrap().unwrap()),
+                (h2, BlockHash([1; 32])),
+                (h3, BlockHash([1; 32])),
+            ]
+            .into_iter()
+            .collect(),
+        );
+
+        let plan = st.wallet().plan_rewind(h3 + 1, &chain_view).unwrap();
+        assert_eq!(plan.fork_height(), h1);
+        assert_eq!(plan.unmined_transactions().len(), 2);
+        assert_eq!(plan.unmined_notes().len(), 2);
+
+        // Planning a rewind does not modify the wallet
+        assert_eq!(st.get_total_balance(AccountId::ZERO), (value * 3).unwrap());
+
+        // A chain that shares no blocks with the wallet cannot be reconciled by a rewind
+        let disjoint_view = MockChainView(HashMap::new());
+        assert_matches!(
+            st.wallet().plan_rewind(h2, &disjoint_view),
+            Err(RewindError::RewindTooDeep { conflict_height, .. }) if conflict_height == h2
+        );
+
+        // Applying the plan leaves only the note received at the fork height
+        st.wallet_mut()
+            .transactionally(|wdb| truncate_to_height(wdb.conn.0, &wdb.params, plan.fork_height()))
+            .unwrap();
+        assert_eq!(st.get_total_balance(AccountId::ZERO), value);
+    }
+
     #[test]
     fn scan_cached_blocks_allows_blocks_out_of_order() {
         let mut st = TestBuilder::new()
-            .with_block_cache()
+            .with_block_cache(BlockCache::new())
+            .with_data_store_factory(TestDbFactory)
             .with_test_account(AccountBirthday::from_sapling_activation)
             .build();
 
@@ -529,7 +602,7 @@ mod tests {
         }])
         .unwrap();
         let input_selector = GreedyInputSelector::new(
-            SingleOutputChangeStrategy::new(FeeRule::standard(), None),
+            SingleOutputChangeStrategy::new(FeeRule::standard(), None, ChangePoolPolicy::default()),
             DustOutputPolicy::default(),
         );
         assert_matches!(
@@ -537,6 +610,7 @@ mod tests {
                 &input_selector,
                 &usk,
                 req,
+                PrivacyPolicy::FullPrivacy,
                 OvkPolicy::Sender,
                 NonZeroU32::new(1).unwrap(),
             ),
@@ -547,7 +621,8 @@ mod tests {
     #[test]
     fn scan_cached_blocks_finds_received_notes() {
         let mut st = TestBuilder::new()
-            .with_block_cache()
+            .with_block_cache(BlockCache::new())
+            .with_data_store_factory(TestDbFactory)
             .with_test_account(AccountBirthday::from_sapling_activation)
             .build();
 
@@ -589,7 +664,8 @@ mod tests {
     #[test]
     fn scan_cached_blocks_finds_change_notes() {
         let mut st = TestBuilder::new()
-            .with_block_cache()
+            .with_block_cache(BlockCache::new())
+            .with_data_store_factory(TestDbFactory)
             .with_test_account(AccountBirthday::from_sapling_activation)
             .build();
         let dfvk = st.test_account_sapling().unwrap();
@@ -627,7 +703,8 @@ mod tests {
     #[test]
     fn scan_cached_blocks_detects_spends_out_of_order() {
         let mut st = TestBuilder::new()
-            .with_block_cache()
+            .with_block_cache(BlockCache::new())
+            .with_data_store_factory(TestDbFactory)
             .with_test_account(AccountBirthday::from_sapling_activation)
             .build();
 
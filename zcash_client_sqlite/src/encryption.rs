@@ -0,0 +1,336 @@
+//! Support for encrypting the wallet database at rest.
+//!
+//! When the `sqlcipher` feature is enabled, the wallet database may be stored encrypted using
+//! [SQLCipher]. All of the data stored in an encrypted database, including memos, note values,
+//! addresses, and viewing keys, is encrypted with a caller-supplied [`DatabaseKey`].
+//!
+//! - A new encrypted database may be created, or an existing one opened, using
+//!   [`WalletDb::for_encrypted_path`].
+//! - An existing plaintext database may be encrypted in place using
+//!   [`encrypt_wallet_db_in_place`], or copied to a new encrypted database using
+//!   [`encrypt_wallet_db`].
+//! - The key for an encrypted database may be rotated using [`WalletDb::rekey`].
+//!
+//! The database file is encrypted as a whole, rather than column by column. The wallet's
+//! views and queries sum note values and join on addresses and keys within SQL, which would
+//! not be possible if those columns held ciphertext; encrypting every page of the file
+//! protects those columns, along with the indices and temporary data derived from them.
+//!
+//! This has the following consequences:
+//!
+//! - No part of the database, including data that is not sensitive such as the chain tip
+//!   height or scan progress, can be read without the key.
+//! - The database must be accessed using SQLCipher; it cannot be read by other SQLite
+//!   tooling, and every page read or written by the wallet incurs the cost of decryption or
+//!   encryption.
+//! - Encrypting an existing database, and rotating the key, rewrite the entire database
+//!   file. When an existing database is encrypted, its plaintext contents are not erased
+//!   from the underlying storage, nor from any backups of it.
+//!
+//! [SQLCipher]: https://www.zetetic.net/sqlcipher/
+//! [`WalletDb::for_encrypted_path`]: crate::WalletDb::for_encrypted_path
+//! [`WalletDb::rekey`]: crate::WalletDb::rekey
+
+use std::fmt::Write;
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+
+use rusqlite::{named_params, Connection, OpenFlags};
+use secrecy::{ExposeSecret, Secret, SecretString};
+
+/// A 256-bit key used to encrypt the wallet database.
+///
+/// The key is used directly as the SQLCipher database key, without any key derivation. Callers
+/// that wish to derive the key from a passphrase are responsible for doing so using a suitable
+/// password-based key derivation function.
+pub struct DatabaseKey(Secret<[u8; 32]>);
+
+impl DatabaseKey {
+    /// Constructs a database key from its raw bytes.
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        DatabaseKey(Secret::new(bytes))
+    }
+
+    /// Returns the SQLCipher representation of this key as a raw key literal.
+    ///
+    /// The literal is allocated at its final length up front, so that it is never reallocated
+    /// and leaves no copy of the key behind once it has been zeroized on drop.
+    fn to_sql(&self) -> SecretString {
+        let mut hex = String::with_capacity(67);
+        hex.push_str("x'");
+        for b in self.0.expose_secret() {
+            write!(hex, "{:02x}", b).expect("writing to a String cannot fail");
+        }
+        hex.push('\'');
+        SecretString::new(hex)
+    }
+}
+
+/// Sets the key that will be used to decrypt and encrypt the database to which `conn` is
+/// connected, and checks that it is able to decrypt the database.
+///
+/// This must be called before any other operation is performed using the connection.
+pub(crate) fn set_key(conn: &Connection, key: &DatabaseKey) -> Result<(), rusqlite::Error> {
+    conn.pragma_update(None, "key", key.to_sql().expose_secret())?;
+    check_key(conn)
+}
+
+/// Changes the key with which the database to which `conn` is connected is encrypted.
+pub(crate) fn rekey(conn: &Connection, new_key: &DatabaseKey) -> Result<(), rusqlite::Error> {
+    conn.pragma_update(None, "rekey", new_key.to_sql().expose_secret())?;
+    check_key(conn)
+}
+
+/// SQLCipher does not verify the key when it is set; an incorrect key is only detected when
+/// the database is first read.
+fn check_key(conn: &Connection) -> Result<(), rusqlite::Error> {
+    conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| {
+        row.get::<_, i64>(0)
+    })
+    .map(|_| ())
+}
+
+/// Creates an encrypted copy of the plaintext wallet database at `plaintext_path`, writing it
+/// to `encrypted_path` and encrypting it with the given key.
+///
+/// The plaintext database is not modified; once the encrypted copy has been opened
+/// successfully using [`WalletDb::for_encrypted_path`], the caller should securely delete the
+/// plaintext database. This function returns an error if no database exists at
+/// `plaintext_path`, or if a file already exists at `encrypted_path`.
+///
+/// [`WalletDb::for_encrypted_path`]: crate::WalletDb::for_encrypted_path
+pub fn encrypt_wallet_db<F: AsRef<Path>, G: AsRef<Path>>(
+    plaintext_path: F,
+    encrypted_path: G,
+    key: &DatabaseKey,
+) -> Result<(), rusqlite::Error> {
+    let conn = open_plaintext(plaintext_path)?;
+    export_encrypted(&conn, encrypted_path.as_ref(), key)
+}
+
+/// Encrypts the plaintext wallet database at `path` with the given key, replacing it with
+/// the encrypted database.
+///
+/// The encrypted database is first written to a temporary file alongside the plaintext
+/// database, which is then replaced with it; if an error occurs, the plaintext database is
+/// left unmodified. No other connections to the database may be open while it is being
+/// encrypted. This function returns an error if no database exists at `path`.
+pub fn encrypt_wallet_db_in_place<F: AsRef<Path>>(
+    path: F,
+    key: &DatabaseKey,
+) -> Result<(), rusqlite::Error> {
+    let path = path.as_ref();
+    let mut encrypted_path = path.as_os_str().to_owned();
+    encrypted_path.push(".encrypting");
+    let encrypted_path = PathBuf::from(encrypted_path);
+
+    let conn = open_plaintext(path)?;
+    export_encrypted(&conn, &encrypted_path, key)?;
+
+    // The connection must be closed before the plaintext database is replaced, so that its
+    // journal has been removed and cannot be applied to the encrypted database.
+    conn.close().map_err(|(_, e)| e)?;
+    std::fs::rename(&encrypted_path, path).map_err(|_| {
+        let _ = std::fs::remove_file(&encrypted_path);
+        rusqlite::Error::InvalidPath(path.to_path_buf())
+    })
+}
+
+/// Opens the plaintext database at `path`.
+///
+/// The database is opened without `SQLITE_OPEN_CREATE`, so that a mistaken path is reported
+/// as an error rather than producing an empty encrypted database.
+fn open_plaintext<F: AsRef<Path>>(path: F) -> Result<Connection, rusqlite::Error> {
+    Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_WRITE)
+}
+
+/// Exports the database to which `conn` is connected to a new database at `encrypted_path`,
+/// encrypted with the given key.
+fn export_encrypted(
+    conn: &Connection,
+    encrypted_path: &Path,
+    key: &DatabaseKey,
+) -> Result<(), rusqlite::Error> {
+    // An attached database is opened using the flags of the connection, so the encrypted
+    // database file must be created before it is attached. SQLite treats an empty file as an
+    // empty database.
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(encrypted_path)
+        .map_err(|_| rusqlite::Error::InvalidPath(encrypted_path.to_path_buf()))?;
+
+    let export = || -> Result<(), rusqlite::Error> {
+        conn.execute(
+            "ATTACH DATABASE :path AS encrypted KEY :key",
+            named_params! {
+                ":path": encrypted_path.to_string_lossy(),
+                ":key": key.to_sql().expose_secret(),
+            },
+        )?;
+        conn.query_row("SELECT sqlcipher_export('encrypted')", [], |_| Ok(()))?;
+
+        // `sqlcipher_export` does not copy the schema version of the database.
+        let user_version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        conn.pragma_update(Some("encrypted"), "user_version", user_version)?;
+
+        conn.execute("DETACH DATABASE encrypted", [])?;
+        Ok(())
+    };
+
+    // A partially-written encrypted database is removed, so that the migration may be retried.
+    export().map_err(|e| {
+        let _ = conn.execute("DETACH DATABASE encrypted", []);
+        let _ = std::fs::remove_file(encrypted_path);
+        e
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use secrecy::Secret;
+    use tempfile::{NamedTempFile, TempDir};
+    use zcash_client_backend::data_api::{AccountBirthday, WalletRead, WalletWrite};
+    use zcash_primitives::consensus::Network;
+
+    use super::{encrypt_wallet_db, encrypt_wallet_db_in_place, DatabaseKey};
+    use crate::{wallet::init::init_wallet_db, WalletDb};
+
+    const SQLITE_HEADER: &[u8] = b"SQLite format 3\0";
+
+    fn file_header(path: &std::path::Path) -> Vec<u8> {
+        let mut header = vec![0; SQLITE_HEADER.len()];
+        std::fs::File::open(path)
+            .unwrap()
+            .read_exact(&mut header)
+            .unwrap();
+        header
+    }
+
+    #[test]
+    fn encrypted_wallet_db_requires_key() {
+        let data_dir = TempDir::new().unwrap();
+        let path = data_dir.path().join("data.sqlite");
+
+        let mut db_data = WalletDb::for_encrypted_path(
+            &path,
+            Network::TestNetwork,
+            &DatabaseKey::from_bytes([1; 32]),
+        )
+        .unwrap();
+        init_wallet_db(&mut db_data, None).unwrap();
+        drop(db_data);
+
+        // The database is not readable as a plaintext SQLite database.
+        assert_ne!(file_header(&path), SQLITE_HEADER);
+        assert!(WalletDb::for_path(&path, Network::TestNetwork)
+            .unwrap()
+            .chain_height()
+            .is_err());
+
+        // The database cannot be opened with the wrong key.
+        assert!(WalletDb::for_encrypted_path(
+            &path,
+            Network::TestNetwork,
+            &DatabaseKey::from_bytes([2; 32]),
+        )
+        .is_err());
+
+        // The database can be reopened with the correct key.
+        let db_data = WalletDb::for_encrypted_path(
+            &path,
+            Network::TestNetwork,
+            &DatabaseKey::from_bytes([1; 32]),
+        )
+        .unwrap();
+        assert_eq!(db_data.chain_height().unwrap(), None);
+    }
+
+    #[test]
+    fn migrate_plaintext_wallet_db() {
+        let network = Network::TestNetwork;
+        let data_file = NamedTempFile::new().unwrap();
+        let mut db_data = WalletDb::for_path(data_file.path(), network).unwrap();
+        let seed = [0u8; 32];
+        init_wallet_db(&mut db_data, Some(Secret::new(seed.to_vec()))).unwrap();
+
+        let birthday = AccountBirthday::from_sapling_activation(&network);
+        let (account, usk) = db_data
+            .create_account(&Secret::new(seed.to_vec()), birthday)
+            .unwrap();
+        let ufvk = usk.to_unified_full_viewing_key();
+        drop(db_data);
+
+        let data_dir = TempDir::new().unwrap();
+        let encrypted_path = data_dir.path().join("encrypted.sqlite");
+        let key = DatabaseKey::from_bytes([3; 32]);
+        encrypt_wallet_db(data_file.path(), &encrypted_path, &key).unwrap();
+
+        // An existing database is never overwritten, and a missing plaintext database is
+        // not created.
+        assert!(encrypt_wallet_db(data_file.path(), &encrypted_path, &key).is_err());
+        let missing_path = data_dir.path().join("missing.sqlite");
+        assert!(encrypt_wallet_db(
+            &missing_path,
+            data_dir.path().join("encrypted2.sqlite"),
+            &key
+        )
+        .is_err());
+        assert!(!missing_path.exists());
+
+        // The encrypted database contains the wallet's accounts, and is up to date with
+        // respect to the wallet's schema migrations.
+        assert_ne!(file_header(&encrypted_path), SQLITE_HEADER);
+        let mut db_data = WalletDb::for_encrypted_path(&encrypted_path, network, &key).unwrap();
+        init_wallet_db(&mut db_data, Some(Secret::new(seed.to_vec()))).unwrap();
+        assert_eq!(db_data.get_account_for_ufvk(&ufvk).unwrap(), Some(account));
+
+        // After the key is rotated, the database can no longer be opened using the old key.
+        let new_key = DatabaseKey::from_bytes([4; 32]);
+        db_data.rekey(&new_key).unwrap();
+        drop(db_data);
+        assert!(WalletDb::for_encrypted_path(&encrypted_path, network, &key).is_err());
+        let db_data = WalletDb::for_encrypted_path(&encrypted_path, network, &new_key).unwrap();
+        assert_eq!(db_data.get_account_for_ufvk(&ufvk).unwrap(), Some(account));
+    }
+
+    #[test]
+    fn encrypt_wallet_db_in_place_replaces_plaintext() {
+        let network = Network::TestNetwork;
+        let data_dir = TempDir::new().unwrap();
+        let path = data_dir.path().join("data.sqlite");
+        let mut db_data = WalletDb::for_path(&path, network).unwrap();
+        let seed = [0u8; 32];
+        init_wallet_db(&mut db_data, Some(Secret::new(seed.to_vec()))).unwrap();
+
+        let birthday = AccountBirthday::from_sapling_activation(&network);
+        let (account, usk) = db_data
+            .create_account(&Secret::new(seed.to_vec()), birthday)
+            .unwrap();
+        let ufvk = usk.to_unified_full_viewing_key();
+        drop(db_data);
+
+        // A missing plaintext database is not created.
+        let key = DatabaseKey::from_bytes([5; 32]);
+        let missing_path = data_dir.path().join("missing.sqlite");
+        assert!(encrypt_wallet_db_in_place(&missing_path, &key).is_err());
+        assert!(!missing_path.exists());
+
+        encrypt_wallet_db_in_place(&path, &key).unwrap();
+
+        // The plaintext database has been replaced by the encrypted database, and no
+        // temporary files remain.
+        assert_ne!(file_header(&path), SQLITE_HEADER);
+        assert_eq!(std::fs::read_dir(data_dir.path()).unwrap().count(), 1);
+        assert!(WalletDb::for_path(&path, network)
+            .unwrap()
+            .chain_height()
+            .is_err());
+
+        let mut db_data = WalletDb::for_encrypted_path(&path, network, &key).unwrap();
+        init_wallet_db(&mut db_data, Some(Secret::new(seed.to_vec()))).unwrap();
+        assert_eq!(db_data.get_account_for_ufvk(&ufvk).unwrap(), Some(account));
+    }
+}
@@ -9,7 +9,9 @@ use zcash_client_backend::{
     PoolType,
 };
 use zcash_primitives::{
-    consensus::BlockHeight, transaction::components::amount::BalanceError, zip32::AccountId,
+    consensus::BlockHeight,
+    transaction::{components::amount::BalanceError, TxId},
+    zip32::AccountId,
 };
 
 use crate::wallet::commitment_tree;
@@ -85,6 +87,10 @@ pub enum SqliteClientError {
     /// A caller attempted to construct a new account with an invalid account identifier.
     AccountIdOutOfRange,
 
+    /// A caller attempted to import an account whose viewing key is already tracked by the
+    /// wallet as the given account.
+    AccountCollision(AccountId),
+
     /// The address associated with a record being inserted was not recognized as
     /// belonging to the wallet
     #[cfg(feature = "transparent-inputs")]
@@ -109,6 +115,9 @@ pub enum SqliteClientError {
 
     /// An error occurred in computing wallet balance
     BalanceError(BalanceError),
+
+    /// The wallet does not contain an unmined transaction with the given ID.
+    UnminedTransactionNotFound(TxId),
 }
 
 impl error::Error for SqliteClientError {
@@ -152,6 +161,7 @@ impl fmt::Display for SqliteClientError {
             SqliteClientError::KeyDerivationError(acct_id) => write!(f, "Key derivation failed for account {}", u32::from(*acct_id)),
             SqliteClientError::AccountIdDiscontinuity => write!(f, "Wallet account identifiers must be sequential."),
             SqliteClientError::AccountIdOutOfRange => write!(f, "Wallet account identifiers must be less than 0x7FFFFFFF."),
+            SqliteClientError::AccountCollision(account) => write!(f, "The wallet already tracks this viewing key as account {}.", u32::from(*account)),
             #[cfg(feature = "transparent-inputs")]
             SqliteClientError::AddressNotRecognized(_) => write!(f, "The address associated with a received txo is not identifiable as belonging to the wallet."),
             SqliteClientError::CommitmentTree(err) => write!(f, "An error occurred accessing or updating note commitment tree data: {}.", err),
@@ -159,6 +169,7 @@ impl fmt::Display for SqliteClientError {
             SqliteClientError::ChainHeightUnknown => write!(f, "Chain height unknown; please call `update_chain_tip`"),
             SqliteClientError::UnsupportedPoolType(t) => write!(f, "Pool type is not currently supported: {}", t),
             SqliteClientError::BalanceError(e) => write!(f, "Balance error: {}", e),
+            SqliteClientError::UnminedTransactionNotFound(txid) => write!(f, "The wallet does not contain an unmined transaction with ID {}", txid),
         }
     }
 }
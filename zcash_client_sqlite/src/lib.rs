@@ -51,7 +51,7 @@ use zcash_primitives::{
     legacy::TransparentAddress,
     memo::{Memo, MemoBytes},
     transaction::{
-        components::amount::{Amount, NonNegativeAmount},
+        components::amount::{Amount, BalanceError, NonNegativeAmount},
         Transaction, TxId,
     },
     zip32::{AccountId, DiversifierIndex, Scope},
@@ -61,13 +61,17 @@ use zcash_client_backend::{
     address::{AddressMetadata, UnifiedAddress},
     data_api::{
         self,
-        chain::{BlockSource, CommitmentTreeRoot},
+        chain::{
+            error::RewindError, find_fork_point, BlockSource, ChainView, CommitmentTreeRoot,
+            RewindPlan,
+        },
         scanning::{ScanPriority, ScanRange},
-        AccountBirthday, BlockMetadata, DecryptedTransaction, InputSource, NullifierQuery,
-        ScannedBlock, SentTransaction, WalletCommitmentTrees, WalletRead, WalletSummary,
-        WalletWrite, SAPLING_SHARD_HEIGHT,
+        AccountBirthday, AccountKind, BlockMetadata, DecryptedTransaction, InputSource,
+        NullifierQuery, ScannedBlock, SentTransaction, TransactionFilter, TransactionSummary,
+        UnminedSentTransaction, WalletCommitmentTrees, WalletRead, WalletSummary, WalletWrite,
+        SAPLING_SHARD_HEIGHT,
     },
-    keys::{UnifiedAddressRequest, UnifiedFullViewingKey, UnifiedSpendingKey},
+    keys::{SeedFingerprint, UnifiedAddressRequest, UnifiedFullViewingKey, UnifiedSpendingKey},
     proto::compact_formats::CompactBlock,
     wallet::{Note, NoteId, ReceivedNote, Recipient, WalletTransparentOutput},
     DecryptedOutput, PoolType, ShieldedProtocol, TransferType,
@@ -75,6 +79,9 @@ use zcash_client_backend::{
 
 use crate::{error::SqliteClientError, wallet::commitment_tree::SqliteShardStore};
 
+#[cfg(feature = "orchard")]
+use zcash_client_backend::data_api::ORCHARD_SHARD_HEIGHT;
+
 #[cfg(feature = "transparent-inputs")]
 use zcash_primitives::transaction::components::OutPoint;
 
@@ -86,6 +93,8 @@ use {
 };
 
 pub mod chain;
+#[cfg(feature = "sqlcipher")]
+pub mod encryption;
 pub mod error;
 
 pub mod wallet;
@@ -107,6 +116,9 @@ pub(crate) const VERIFY_LOOKAHEAD: u32 = 10;
 
 pub(crate) const SAPLING_TABLES_PREFIX: &str = "sapling";
 
+#[cfg(feature = "orchard")]
+pub(crate) const ORCHARD_TABLES_PREFIX: &str = "orchard";
+
 #[cfg(not(feature = "transparent-inputs"))]
 pub(crate) const UA_TRANSPARENT: bool = false;
 #[cfg(feature = "transparent-inputs")]
@@ -116,13 +128,16 @@ pub(crate) const DEFAULT_UA_REQUEST: UnifiedAddressRequest =
     UnifiedAddressRequest::unsafe_new(false, true, UA_TRANSPARENT);
 
 /// A newtype wrapper for received note identifiers.
+///
+/// Received notes of each shielded protocol are stored in separate tables, so the row
+/// identifier of a note is only unique in combination with the protocol of the note.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
-pub struct ReceivedNoteId(pub(crate) i64);
+pub struct ReceivedNoteId(pub(crate) ShieldedProtocol, pub(crate) i64);
 
 impl fmt::Display for ReceivedNoteId {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            ReceivedNoteId(id) => write!(f, "Received Note {}", id),
+            ReceivedNoteId(protocol, id) => write!(f, "Received {:?} Note {}", protocol, id),
         }
     }
 }
@@ -156,6 +171,33 @@ impl<P: consensus::Parameters + Clone> WalletDb<Connection, P> {
         })
     }
 
+    /// Construct a connection to the encrypted wallet database stored at the specified path,
+    /// creating a new encrypted database if none exists.
+    ///
+    /// Returns an error if the database cannot be decrypted using the given key, or if the
+    /// database at the specified path is not encrypted. See the [`encryption`] module for
+    /// details.
+    #[cfg(feature = "sqlcipher")]
+    pub fn for_encrypted_path<F: AsRef<Path>>(
+        path: F,
+        params: P,
+        key: &encryption::DatabaseKey,
+    ) -> Result<Self, rusqlite::Error> {
+        Connection::open(path).and_then(move |conn| {
+            encryption::set_key(&conn, key)?;
+            rusqlite::vtab::array::load_module(&conn)?;
+            Ok(WalletDb { conn, params })
+        })
+    }
+
+    /// Re-encrypts the wallet database using the given key.
+    ///
+    /// After this operation completes, the database may only be opened using `new_key`.
+    #[cfg(feature = "sqlcipher")]
+    pub fn rekey(&mut self, new_key: &encryption::DatabaseKey) -> Result<(), rusqlite::Error> {
+        encryption::rekey(&self.conn, new_key)
+    }
+
     pub fn transactionally<F, A, E: From<rusqlite::Error>>(&mut self, f: F) -> Result<A, E>
     where
         F: FnOnce(&mut WalletDb<SqlTransaction<'_>, P>) -> Result<A, E>,
@@ -178,28 +220,75 @@ impl<C: Borrow<rusqlite::Connection>, P: consensus::Parameters> InputSource for
     fn get_spendable_note(
         &self,
         txid: &TxId,
-        _protocol: ShieldedProtocol,
+        protocol: ShieldedProtocol,
         index: u32,
     ) -> Result<Option<ReceivedNote<Self::NoteRef, Note>>, Self::Error> {
-        wallet::sapling::get_spendable_sapling_note(self.conn.borrow(), &self.params, txid, index)
+        match protocol {
+            ShieldedProtocol::Sapling => wallet::sapling::get_spendable_sapling_note(
+                self.conn.borrow(),
+                &self.params,
+                txid,
+                index,
+            ),
+            #[cfg(all(zcash_unstable = "orchard", feature = "orchard"))]
+            ShieldedProtocol::Orchard => wallet::orchard::get_spendable_orchard_note(
+                self.conn.borrow(),
+                &self.params,
+                txid,
+                index,
+            ),
+            #[cfg(all(zcash_unstable = "orchard", not(feature = "orchard")))]
+            ShieldedProtocol::Orchard => Err(SqliteClientError::UnsupportedPoolType(
+                PoolType::Shielded(ShieldedProtocol::Orchard),
+            )),
+        }
     }
 
     fn select_spendable_notes(
         &self,
         account: AccountId,
         target_value: Amount,
-        _sources: &[ShieldedProtocol],
+        sources: &[ShieldedProtocol],
         anchor_height: BlockHeight,
         exclude: &[Self::NoteRef],
     ) -> Result<Vec<ReceivedNote<Self::NoteRef, Note>>, Self::Error> {
-        wallet::sapling::select_spendable_sapling_notes(
-            self.conn.borrow(),
-            &self.params,
-            account,
-            target_value,
-            anchor_height,
-            exclude,
-        )
+        let mut selected = vec![];
+        let mut remaining = target_value;
+        for source in sources {
+            if remaining <= Amount::zero() {
+                break;
+            }
+
+            let notes = match source {
+                ShieldedProtocol::Sapling => wallet::sapling::select_spendable_sapling_notes(
+                    self.conn.borrow(),
+                    &self.params,
+                    account,
+                    remaining,
+                    anchor_height,
+                    exclude,
+                )?,
+                #[cfg(all(zcash_unstable = "orchard", feature = "orchard"))]
+                ShieldedProtocol::Orchard => wallet::orchard::select_spendable_orchard_notes(
+                    self.conn.borrow(),
+                    &self.params,
+                    account,
+                    remaining,
+                    anchor_height,
+                    exclude,
+                )?,
+                #[cfg(all(zcash_unstable = "orchard", not(feature = "orchard")))]
+                ShieldedProtocol::Orchard => vec![],
+            };
+
+            for note in notes {
+                remaining = (remaining - Amount::from(note.note().value()))
+                    .ok_or(SqliteClientError::BalanceError(BalanceError::Underflow))?;
+                selected.push(note);
+            }
+        }
+
+        Ok(selected)
     }
 
     #[cfg(feature = "transparent-inputs")]
@@ -273,6 +362,33 @@ impl<C: Borrow<rusqlite::Connection>, P: consensus::Parameters> WalletRead for W
         wallet::get_max_height_hash(self.conn.borrow()).map_err(SqliteClientError::from)
     }
 
+    fn plan_rewind<V: ChainView>(
+        &self,
+        conflict_height: BlockHeight,
+        chain_view: &V,
+    ) -> Result<RewindPlan, RewindError<Self::Error, V::Error>> {
+        let fork_height = match wallet::get_min_rewind_height(self.conn.borrow())
+            .map_err(|e| RewindError::Wallet(e.into()))?
+        {
+            Some(min_rewind_height) => {
+                find_fork_point(self, chain_view, conflict_height, min_rewind_height)?
+            }
+            // If the note commitment trees contain no checkpoints then no blocks have been
+            // scanned, so there are no stored block hashes to compare against.
+            None => conflict_height.saturating_sub(1),
+        };
+
+        let (unmined_txids, unmined_notes) =
+            wallet::get_mined_above(self.conn.borrow(), fork_height)
+                .map_err(|e| RewindError::Wallet(e.into()))?;
+
+        Ok(RewindPlan::from_parts(
+            fork_height,
+            unmined_txids,
+            unmined_notes,
+        ))
+    }
+
     fn get_tx_height(&self, txid: TxId) -> Result<Option<BlockHeight>, Self::Error> {
         wallet::get_tx_height(self.conn.borrow(), txid).map_err(SqliteClientError::from)
     }
@@ -306,6 +422,18 @@ impl<C: Borrow<rusqlite::Connection>, P: consensus::Parameters> WalletRead for W
         wallet::get_account_for_ufvk(self.conn.borrow(), &self.params, ufvk)
     }
 
+    fn get_account_kind(&self, account: AccountId) -> Result<Option<AccountKind>, Self::Error> {
+        wallet::get_account_kind(self.conn.borrow(), account)
+    }
+
+    fn get_derived_account(
+        &self,
+        seed_fingerprint: &SeedFingerprint,
+        account_index: AccountId,
+    ) -> Result<Option<AccountId>, Self::Error> {
+        wallet::get_derived_account(self.conn.borrow(), seed_fingerprint, account_index)
+    }
+
     fn get_wallet_summary(
         &self,
         min_confirmations: u32,
@@ -333,6 +461,18 @@ impl<C: Borrow<rusqlite::Connection>, P: consensus::Parameters> WalletRead for W
         wallet::get_transaction(self.conn.borrow(), &self.params, txid).map(|(_, tx)| tx)
     }
 
+    fn get_unmined_sent_transactions(&self) -> Result<Vec<UnminedSentTransaction>, Self::Error> {
+        wallet::get_unmined_sent_transactions(self.conn.borrow())
+    }
+
+    fn get_transactions(
+        &self,
+        account: AccountId,
+        filter: &TransactionFilter,
+    ) -> Result<Vec<TransactionSummary>, Self::Error> {
+        wallet::get_transactions(self.conn.borrow(), account, filter)
+    }
+
     fn get_sapling_nullifiers(
         &self,
         query: NullifierQuery,
@@ -378,9 +518,12 @@ impl<C: Borrow<rusqlite::Connection>, P: consensus::Parameters> WalletRead for W
     #[cfg(feature = "orchard")]
     fn get_orchard_nullifiers(
         &self,
-        _query: NullifierQuery,
+        query: NullifierQuery,
     ) -> Result<Vec<(AccountId, orchard::note::Nullifier)>, Self::Error> {
-        todo!()
+        match query {
+            NullifierQuery::Unspent => wallet::orchard::get_orchard_nullifiers(self.conn.borrow()),
+            NullifierQuery::All => wallet::orchard::get_all_orchard_nullifiers(self.conn.borrow()),
+        }
     }
 
     fn get_account_ids(&self) -> Result<Vec<AccountId>, Self::Error> {
@@ -397,21 +540,71 @@ impl<P: consensus::Parameters> WalletWrite for WalletDb<rusqlite::Connection, P>
         birthday: AccountBirthday,
     ) -> Result<(AccountId, UnifiedSpendingKey), Self::Error> {
         self.transactionally(|wdb| {
+            // A seed that cannot be fingerprinted is too short or too long for key derivation
+            // to succeed at any account index.
+            let seed_fingerprint = SeedFingerprint::from_seed(seed.expose_secret())
+                .ok_or(SqliteClientError::KeyDerivationError(AccountId::ZERO))?;
+
+            // ZIP 32 account indices are allocated independently for each seed.
+            let account_index = wallet::get_max_hd_account_index(wdb.conn.0, &seed_fingerprint)?
+                .map(|a| a.next().ok_or(SqliteClientError::AccountIdOutOfRange))
+                .transpose()?
+                .unwrap_or(AccountId::ZERO);
+
             let account = wallet::get_max_account_id(wdb.conn.0)?
                 .map(|a| a.next().ok_or(SqliteClientError::AccountIdOutOfRange))
                 .transpose()?
                 .unwrap_or(AccountId::ZERO);
 
-            let usk = UnifiedSpendingKey::from_seed(&wdb.params, seed.expose_secret(), account)
-                .map_err(|_| SqliteClientError::KeyDerivationError(account))?;
+            let usk =
+                UnifiedSpendingKey::from_seed(&wdb.params, seed.expose_secret(), account_index)
+                    .map_err(|_| SqliteClientError::KeyDerivationError(account_index))?;
             let ufvk = usk.to_unified_full_viewing_key();
 
-            wallet::add_account(wdb.conn.0, &wdb.params, account, &ufvk, birthday)?;
+            wallet::add_account(
+                wdb.conn.0,
+                &wdb.params,
+                account,
+                &ufvk,
+                AccountKind::Derived {
+                    seed_fingerprint,
+                    account_index,
+                },
+                birthday,
+            )?;
 
             Ok((account, usk))
         })
     }
 
+    fn import_account_ufvk(
+        &mut self,
+        ufvk: &UnifiedFullViewingKey,
+        birthday: AccountBirthday,
+    ) -> Result<AccountId, Self::Error> {
+        self.transactionally(|wdb| {
+            if let Some(existing) = wallet::get_account_for_ufvk(wdb.conn.0, &wdb.params, ufvk)? {
+                return Err(SqliteClientError::AccountCollision(existing));
+            }
+
+            let account = wallet::get_max_account_id(wdb.conn.0)?
+                .map(|a| a.next().ok_or(SqliteClientError::AccountIdOutOfRange))
+                .transpose()?
+                .unwrap_or(AccountId::ZERO);
+
+            wallet::add_account(
+                wdb.conn.0,
+                &wdb.params,
+                account,
+                ufvk,
+                AccountKind::ViewOnly,
+                birthday,
+            )?;
+
+            Ok(account)
+        })
+    }
+
     fn get_next_available_address(
         &mut self,
         account: AccountId,
@@ -466,7 +659,16 @@ impl<P: consensus::Parameters> WalletWrite for WalletDb<rusqlite::Connection, P>
                     ),
                 )
             });
+            #[cfg(feature = "orchard")]
+            let orchard_start_position = blocks.first().map(|block| {
+                Position::from(
+                    u64::from(block.orchard().final_tree_size())
+                        - u64::try_from(block.orchard().commitments().len()).unwrap(),
+                )
+            });
             let mut sapling_commitments = vec![];
+            #[cfg(feature = "orchard")]
+            let mut orchard_commitments = vec![];
             let mut last_scanned_height = None;
             let mut note_positions = vec![];
             for block in blocks.into_iter() {
@@ -485,6 +687,10 @@ impl<P: consensus::Parameters> WalletWrite for WalletDb<rusqlite::Connection, P>
                     block.block_time(),
                     block.sapling().final_tree_size(),
                     block.sapling().commitments().len().try_into().unwrap(),
+                    #[cfg(feature = "orchard")]
+                    block.orchard().final_tree_size(),
+                    #[cfg(feature = "orchard")]
+                    block.orchard().commitments().len().try_into().unwrap(),
                 )?;
 
                 for tx in block.transactions() {
@@ -506,6 +712,24 @@ impl<P: consensus::Parameters> WalletWrite for WalletDb<rusqlite::Connection, P>
 
                         wallet::sapling::put_received_note(wdb.conn.0, output, tx_row, spent_in)?;
                     }
+
+                    #[cfg(feature = "orchard")]
+                    for spend in &tx.orchard_spends {
+                        wallet::orchard::mark_orchard_note_spent(wdb.conn.0, tx_row, spend.nf())?;
+                    }
+
+                    #[cfg(feature = "orchard")]
+                    for output in &tx.orchard_outputs {
+                        // Check whether this note was spent in a later block range that
+                        // we previously scanned.
+                        let spent_in = wallet::query_nullifier_map::<_, Scope>(
+                            wdb.conn.0,
+                            ShieldedProtocol::Orchard,
+                            &output.nf().to_bytes(),
+                        )?;
+
+                        wallet::orchard::put_received_note(wdb.conn.0, output, tx_row, spent_in)?;
+                    }
                 }
 
                 // Insert the new nullifiers from this block into the nullifier map.
@@ -516,6 +740,21 @@ impl<P: consensus::Parameters> WalletWrite for WalletDb<rusqlite::Connection, P>
                     block.sapling().nullifier_map(),
                 )?;
 
+                #[cfg(feature = "orchard")]
+                wallet::insert_nullifier_map(
+                    wdb.conn.0,
+                    block.height(),
+                    ShieldedProtocol::Orchard,
+                    &block
+                        .orchard()
+                        .nullifier_map()
+                        .iter()
+                        .map(|(txid, tx_idx, nfs)| {
+                            (*txid, *tx_idx, nfs.iter().map(|nf| nf.to_bytes()).collect())
+                        })
+                        .collect::<Vec<_>>(),
+                )?;
+
                 note_positions.extend(block.transactions().iter().flat_map(|wtx| {
                     wtx.sapling_outputs
                         .iter()
@@ -525,6 +764,8 @@ impl<P: consensus::Parameters> WalletWrite for WalletDb<rusqlite::Connection, P>
                 last_scanned_height = Some(block.height());
                 let block_commitments = block.into_commitments();
                 sapling_commitments.extend(block_commitments.sapling.into_iter().map(Some));
+                #[cfg(feature = "orchard")]
+                orchard_commitments.extend(block_commitments.orchard.into_iter().map(Some));
             }
 
             // Prune the nullifier map of entries we no longer need.
@@ -568,6 +809,37 @@ impl<P: consensus::Parameters> WalletWrite for WalletDb<rusqlite::Connection, P>
                     Ok(())
                 })?;
 
+                #[cfg(feature = "orchard")]
+                {
+                    let orchard_start_position =
+                        orchard_start_position.expect("start position is present if blocks are");
+                    let subtrees = orchard_commitments
+                        .par_chunks_mut(CHUNK_SIZE)
+                        .enumerate()
+                        .filter_map(|(i, chunk)| {
+                            let start = orchard_start_position + (i * CHUNK_SIZE) as u64;
+                            let end = start + chunk.len() as u64;
+
+                            shardtree::LocatedTree::from_iter(
+                                start..end,
+                                ORCHARD_SHARD_HEIGHT.into(),
+                                chunk.iter_mut().map(|n| n.take().expect("always Some")),
+                            )
+                        })
+                        .map(|res| (res.subtree, res.checkpoints))
+                        .collect::<Vec<_>>();
+
+                    // Update the Orchard note commitment tree with all newly read note commitments
+                    let mut subtrees = subtrees.into_iter();
+                    wdb.with_orchard_tree_mut::<_, _, Self::Error>(move |orchard_tree| {
+                        for (tree, checkpoints) in &mut subtrees {
+                            orchard_tree.insert_tree(tree, checkpoints)?;
+                        }
+
+                        Ok(())
+                    })?;
+                }
+
                 // Update now-expired transactions that didn't get mined.
                 wallet::update_expired_notes(wdb.conn.0, last_scanned_height)?;
 
@@ -646,6 +918,76 @@ impl<P: consensus::Parameters> WalletWrite for WalletDb<rusqlite::Connection, P>
                 }
             }
 
+            #[cfg(feature = "orchard")]
+            for output in d_tx.orchard_outputs {
+                match output.transfer_type {
+                    TransferType::Outgoing | TransferType::WalletInternal => {
+                        let recipient = if output.transfer_type == TransferType::Outgoing {
+                            Recipient::Unified(
+                                UnifiedAddress::from_receivers(
+                                    Some(output.note.recipient()),
+                                    None,
+                                    None,
+                                )
+                                .expect("An Orchard receiver is sufficient to construct a unified address"),
+                                PoolType::Shielded(ShieldedProtocol::Orchard),
+                            )
+                        } else {
+                            Recipient::InternalAccount(
+                                output.account,
+                                PoolType::Shielded(ShieldedProtocol::Orchard)
+                            )
+                        };
+
+                        wallet::put_sent_output(
+                            wdb.conn.0,
+                            &wdb.params,
+                            output.account,
+                            tx_ref,
+                            output.index,
+                            &recipient,
+                            NonNegativeAmount::from_u64(output.note.value().inner()).map_err(|_| {
+                                SqliteClientError::CorruptedData(
+                                    "Note value is not a valid Zcash amount.".to_string(),
+                                )
+                            })?,
+                            Some(&output.memo),
+                        )?;
+
+                        if matches!(recipient, Recipient::InternalAccount(_, _)) {
+                            wallet::orchard::put_received_note(wdb.conn.0, output, tx_ref, None)?;
+                        }
+                    }
+                    TransferType::Incoming => {
+                        wallet::orchard::put_received_note(wdb.conn.0, output, tx_ref, None)?;
+                    }
+                }
+            }
+
+            // If any of the shielded notes spent in the transaction are ours, mark them as spent.
+            // The transaction may not yet have been mined; if it expires without being mined,
+            // the notes will be released by `update_expired_notes`.
+            if let Some(bundle) = d_tx.tx.sapling_bundle() {
+                for spend in bundle.shielded_spends() {
+                    wallet::sapling::mark_sapling_note_spent(
+                        wdb.conn.0,
+                        tx_ref,
+                        spend.nullifier(),
+                    )?;
+                }
+            }
+
+            #[cfg(feature = "orchard")]
+            if let Some(bundle) = d_tx.tx.orchard_bundle() {
+                for action in bundle.actions() {
+                    wallet::orchard::mark_orchard_note_spent(
+                        wdb.conn.0,
+                        tx_ref,
+                        action.nullifier(),
+                    )?;
+                }
+            }
+
             // If any of the utxos spent in the transaction are ours, mark them as spent.
             #[cfg(feature = "transparent-inputs")]
             for txin in d_tx.tx.transparent_bundle().iter().flat_map(|b| b.vin.iter()) {
@@ -711,6 +1053,17 @@ impl<P: consensus::Parameters> WalletWrite for WalletDb<rusqlite::Connection, P>
                 }
             }
 
+            #[cfg(feature = "orchard")]
+            if let Some(bundle) = sent_tx.tx.orchard_bundle() {
+                for action in bundle.actions() {
+                    wallet::orchard::mark_orchard_note_spent(
+                        wdb.conn.0,
+                        tx_ref,
+                        action.nullifier(),
+                    )?;
+                }
+            }
+
             #[cfg(feature = "transparent-inputs")]
             for utxo_outpoint in &sent_tx.utxos_spent {
                 wallet::mark_transparent_utxo_spent(wdb.conn.0, tx_ref, utxo_outpoint)?;
@@ -741,12 +1094,34 @@ impl<P: consensus::Parameters> WalletWrite for WalletDb<rusqlite::Connection, P>
                         None,
                     )?;
                 }
+
+                #[cfg(feature = "orchard")]
+                if let Some((account, note)) = output.orchard_change_to() {
+                    wallet::orchard::put_received_note(
+                        wdb.conn.0,
+                        &DecryptedOutput {
+                            index: output.output_index(),
+                            note: *note,
+                            account: *account,
+                            memo: output
+                                .memo()
+                                .map_or_else(MemoBytes::empty, |memo| memo.clone()),
+                            transfer_type: TransferType::WalletInternal,
+                        },
+                        tx_ref,
+                        None,
+                    )?;
+                }
             }
 
             Ok(())
         })
     }
 
+    fn abandon_transaction(&mut self, txid: TxId) -> Result<(), Self::Error> {
+        self.transactionally(|wdb| wallet::abandon_transaction(wdb.conn.0, txid))
+    }
+
     fn truncate_to_height(&mut self, block_height: BlockHeight) -> Result<(), Self::Error> {
         self.transactionally(|wdb| {
             wallet::truncate_to_height(wdb.conn.0, &wdb.params, block_height)
@@ -771,6 +1146,12 @@ impl<P: consensus::Parameters> WalletCommitmentTrees for WalletDb<rusqlite::Conn
     type Error = commitment_tree::Error;
     type SaplingShardStore<'a> =
         SqliteShardStore<&'a rusqlite::Transaction<'a>, sapling::Node, SAPLING_SHARD_HEIGHT>;
+    #[cfg(feature = "orchard")]
+    type OrchardShardStore<'a> = SqliteShardStore<
+        &'a rusqlite::Transaction<'a>,
+        orchard::tree::MerkleHashOrchard,
+        ORCHARD_SHARD_HEIGHT,
+    >;
 
     fn with_sapling_tree_mut<F, A, E>(&mut self, mut callback: F) -> Result<A, E>
     where
@@ -818,12 +1199,67 @@ impl<P: consensus::Parameters> WalletCommitmentTrees for WalletDb<rusqlite::Conn
             .map_err(|e| ShardTreeError::Storage(commitment_tree::Error::Query(e)))?;
         Ok(())
     }
+
+    #[cfg(feature = "orchard")]
+    fn with_orchard_tree_mut<F, A, E>(&mut self, mut callback: F) -> Result<A, E>
+    where
+        for<'a> F: FnMut(
+            &'a mut ShardTree<
+                Self::OrchardShardStore<'a>,
+                { ORCHARD_SHARD_HEIGHT * 2 },
+                ORCHARD_SHARD_HEIGHT,
+            >,
+        ) -> Result<A, E>,
+        E: From<ShardTreeError<Self::Error>>,
+    {
+        let tx = self
+            .conn
+            .transaction()
+            .map_err(|e| ShardTreeError::Storage(commitment_tree::Error::Query(e)))?;
+        let shard_store = SqliteShardStore::from_connection(&tx, ORCHARD_TABLES_PREFIX)
+            .map_err(|e| ShardTreeError::Storage(commitment_tree::Error::Query(e)))?;
+        let result = {
+            let mut shardtree = ShardTree::new(shard_store, PRUNING_DEPTH.try_into().unwrap());
+            callback(&mut shardtree)?
+        };
+
+        tx.commit()
+            .map_err(|e| ShardTreeError::Storage(commitment_tree::Error::Query(e)))?;
+        Ok(result)
+    }
+
+    #[cfg(feature = "orchard")]
+    fn put_orchard_subtree_roots(
+        &mut self,
+        start_index: u64,
+        roots: &[CommitmentTreeRoot<orchard::tree::MerkleHashOrchard>],
+    ) -> Result<(), ShardTreeError<Self::Error>> {
+        let tx = self
+            .conn
+            .transaction()
+            .map_err(|e| ShardTreeError::Storage(commitment_tree::Error::Query(e)))?;
+        put_shard_roots::<_, { ORCHARD_SHARD_HEIGHT * 2 }, ORCHARD_SHARD_HEIGHT>(
+            &tx,
+            ORCHARD_TABLES_PREFIX,
+            start_index,
+            roots,
+        )?;
+        tx.commit()
+            .map_err(|e| ShardTreeError::Storage(commitment_tree::Error::Query(e)))?;
+        Ok(())
+    }
 }
 
 impl<'conn, P: consensus::Parameters> WalletCommitmentTrees for WalletDb<SqlTransaction<'conn>, P> {
     type Error = commitment_tree::Error;
     type SaplingShardStore<'a> =
         SqliteShardStore<&'a rusqlite::Transaction<'a>, sapling::Node, SAPLING_SHARD_HEIGHT>;
+    #[cfg(feature = "orchard")]
+    type OrchardShardStore<'a> = SqliteShardStore<
+        &'a rusqlite::Transaction<'a>,
+        orchard::tree::MerkleHashOrchard,
+        ORCHARD_SHARD_HEIGHT,
+    >;
 
     fn with_sapling_tree_mut<F, A, E>(&mut self, mut callback: F) -> Result<A, E>
     where
@@ -858,6 +1294,42 @@ impl<'conn, P: consensus::Parameters> WalletCommitmentTrees for WalletDb<SqlTran
             roots,
         )
     }
+
+    #[cfg(feature = "orchard")]
+    fn with_orchard_tree_mut<F, A, E>(&mut self, mut callback: F) -> Result<A, E>
+    where
+        for<'a> F: FnMut(
+            &'a mut ShardTree<
+                Self::OrchardShardStore<'a>,
+                { ORCHARD_SHARD_HEIGHT * 2 },
+                ORCHARD_SHARD_HEIGHT,
+            >,
+        ) -> Result<A, E>,
+        E: From<ShardTreeError<Self::Error>>,
+    {
+        let mut shardtree = ShardTree::new(
+            SqliteShardStore::from_connection(self.conn.0, ORCHARD_TABLES_PREFIX)
+                .map_err(|e| ShardTreeError::Storage(commitment_tree::Error::Query(e)))?,
+            PRUNING_DEPTH.try_into().unwrap(),
+        );
+        let result = callback(&mut shardtree)?;
+
+        Ok(result)
+    }
+
+    #[cfg(feature = "orchard")]
+    fn put_orchard_subtree_roots(
+        &mut self,
+        start_index: u64,
+        roots: &[CommitmentTreeRoot<orchard::tree::MerkleHashOrchard>],
+    ) -> Result<(), ShardTreeError<Self::Error>> {
+        put_shard_roots::<_, { ORCHARD_SHARD_HEIGHT * 2 }, ORCHARD_SHARD_HEIGHT>(
+            self.conn.0,
+            ORCHARD_TABLES_PREFIX,
+            start_index,
+            roots,
+        )
+    }
 }
 
 /// A handle for the SQLite block source.
@@ -1124,14 +1596,24 @@ extern crate assert_matches;
 
 #[cfg(test)]
 mod tests {
-    use zcash_client_backend::data_api::{AccountBirthday, WalletRead, WalletWrite};
+    use secrecy::Secret;
+    use zcash_client_backend::{
+        data_api::{
+            testing::conformance::TestBuilder, AccountBirthday, AccountKind, WalletRead,
+            WalletWrite,
+        },
+        keys::SeedFingerprint,
+    };
 
-    use crate::{testing::TestBuilder, AccountId, DEFAULT_UA_REQUEST};
+    use crate::{testing::TestDbFactory, AccountId, DEFAULT_UA_REQUEST};
+
+    #[cfg(feature = "transparent-inputs")]
+    use crate::testing::BlockCache;
 
     #[cfg(feature = "unstable")]
     use {
-        crate::testing::AddressType,
-        zcash_client_backend::keys::sapling,
+        crate::testing::FsBlockCache,
+        zcash_client_backend::{data_api::testing::conformance::AddressType, keys::sapling},
         zcash_primitives::{
             consensus::Parameters, transaction::components::amount::NonNegativeAmount,
         },
@@ -1140,6 +1622,7 @@ mod tests {
     #[test]
     pub(crate) fn get_next_available_address() {
         let mut st = TestBuilder::new()
+            .with_data_store_factory(TestDbFactory)
             .with_test_account(AccountBirthday::from_sapling_activation)
             .build();
 
@@ -1159,12 +1642,61 @@ mod tests {
         assert_eq!(addr2, addr2_cur);
     }
 
+    #[test]
+    fn accounts_from_multiple_seeds() {
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestDbFactory)
+            .build();
+        let birthday = AccountBirthday::from_sapling_activation(&st.network());
+
+        let seed_a = Secret::new(vec![0xa0; 32]);
+        let seed_b = Secret::new(vec![0xb0; 32]);
+        let fp_a = SeedFingerprint::from_seed(&[0xa0; 32]).unwrap();
+        let fp_b = SeedFingerprint::from_seed(&[0xb0; 32]).unwrap();
+
+        let (a0, _) = st
+            .wallet_mut()
+            .create_account(&seed_a, birthday.clone())
+            .unwrap();
+        let (b0, _) = st
+            .wallet_mut()
+            .create_account(&seed_b, birthday.clone())
+            .unwrap();
+        let (a1, _) = st.wallet_mut().create_account(&seed_a, birthday).unwrap();
+
+        // Account identifiers are unique across the wallet...
+        assert_eq!([a0, b0, a1].map(u32::from), [0, 1, 2],);
+
+        // ...while ZIP 32 account indices are allocated per seed.
+        let expected = [
+            (a0, fp_a, AccountId::ZERO),
+            (b0, fp_b, AccountId::ZERO),
+            (a1, fp_a, AccountId::ZERO.next().unwrap()),
+        ];
+        for (account, seed_fingerprint, account_index) in expected {
+            assert_eq!(
+                st.wallet().get_account_kind(account).unwrap(),
+                Some(AccountKind::Derived {
+                    seed_fingerprint,
+                    account_index
+                })
+            );
+            assert_eq!(
+                st.wallet()
+                    .get_derived_account(&seed_fingerprint, account_index)
+                    .unwrap(),
+                Some(account)
+            );
+        }
+    }
+
     #[cfg(feature = "transparent-inputs")]
     #[test]
     fn transparent_receivers() {
         // Add an account to the wallet.
         let st = TestBuilder::new()
-            .with_block_cache()
+            .with_block_cache(BlockCache::new())
+            .with_data_store_factory(TestDbFactory)
             .with_test_account(AccountBirthday::from_sapling_activation)
             .build();
 
@@ -1192,7 +1724,10 @@ mod tests {
     #[cfg(feature = "unstable")]
     #[test]
     pub(crate) fn fsblockdb_api() {
-        let mut st = TestBuilder::new().with_fs_block_cache().build();
+        let mut st = TestBuilder::new()
+            .with_block_cache(FsBlockCache::new())
+            .with_data_store_factory(TestDbFactory)
+            .build();
 
         // The BlockMeta DB starts off empty.
         assert_eq!(st.cache().get_max_cached_height().unwrap(), None);
@@ -90,10 +90,11 @@ use zcash_client_backend::{
     address::{Address, UnifiedAddress},
     data_api::{
         scanning::{ScanPriority, ScanRange},
-        AccountBirthday, BlockMetadata, SentTransactionOutput, SAPLING_SHARD_HEIGHT,
+        AccountBirthday, AccountKind, BlockMetadata, OutputSummary, SentTransactionOutput,
+        TransactionFilter, TransactionSummary, UnminedSentTransaction, SAPLING_SHARD_HEIGHT,
     },
     encoding::AddressCodec,
-    keys::UnifiedFullViewingKey,
+    keys::{SeedFingerprint, UnifiedFullViewingKey},
     wallet::{NoteId, Recipient, WalletTx},
     PoolType, ShieldedProtocol,
 };
@@ -105,6 +106,9 @@ use crate::{
     SAPLING_TABLES_PREFIX,
 };
 
+#[cfg(feature = "orchard")]
+use crate::ORCHARD_TABLES_PREFIX;
+
 use self::scanning::{parse_priority_code, priority_code, replace_queue_entries};
 
 #[cfg(feature = "transparent-inputs")]
@@ -120,7 +124,10 @@ use {
 };
 
 pub mod commitment_tree;
+pub mod export;
 pub mod init;
+#[cfg(feature = "orchard")]
+pub(crate) mod orchard;
 pub(crate) mod sapling;
 pub(crate) mod scanning;
 
@@ -138,6 +145,26 @@ pub(crate) fn pool_code(pool_type: PoolType) -> i64 {
     }
 }
 
+pub(crate) fn parse_pool_code(code: i64) -> Option<PoolType> {
+    match code {
+        0i64 => Some(PoolType::Transparent),
+        2i64 => Some(PoolType::Shielded(ShieldedProtocol::Sapling)),
+        #[cfg(zcash_unstable = "orchard")]
+        3i64 => Some(PoolType::Shielded(ShieldedProtocol::Orchard)),
+        _ => None,
+    }
+}
+
+pub(crate) const ACCOUNT_KIND_DERIVED: i64 = 0;
+pub(crate) const ACCOUNT_KIND_VIEW_ONLY: i64 = 1;
+
+pub(crate) fn account_kind_code(kind: &AccountKind) -> i64 {
+    match kind {
+        AccountKind::Derived { .. } => ACCOUNT_KIND_DERIVED,
+        AccountKind::ViewOnly => ACCOUNT_KIND_VIEW_ONLY,
+    }
+}
+
 pub(crate) fn scope_code(scope: Scope) -> i64 {
     match scope {
         Scope::External => 0i64,
@@ -177,18 +204,83 @@ pub(crate) fn get_max_account_id(
     })
 }
 
+/// Returns the greatest ZIP 32 account index among the accounts derived from the seed with the
+/// given fingerprint, or `None` if no such accounts are known to the wallet.
+pub(crate) fn get_max_hd_account_index(
+    conn: &rusqlite::Connection,
+    seed_fingerprint: &SeedFingerprint,
+) -> Result<Option<AccountId>, SqliteClientError> {
+    conn.query_row_and_then(
+        "SELECT MAX(hd_account_index)
+        FROM accounts
+        WHERE hd_seed_fingerprint = :hd_seed_fingerprint",
+        named_params![":hd_seed_fingerprint": seed_fingerprint.to_bytes()],
+        |row| {
+            let account_index: Option<u32> = row.get(0)?;
+            account_index
+                .map(AccountId::try_from)
+                .transpose()
+                .map_err(|_| SqliteClientError::AccountIdOutOfRange)
+        },
+    )
+}
+
+/// Returns the account derived from the seed with the given fingerprint at the given ZIP 32
+/// account index, if it is known to the wallet.
+pub(crate) fn get_derived_account(
+    conn: &rusqlite::Connection,
+    seed_fingerprint: &SeedFingerprint,
+    account_index: AccountId,
+) -> Result<Option<AccountId>, SqliteClientError> {
+    conn.query_row(
+        "SELECT account
+        FROM accounts
+        WHERE hd_seed_fingerprint = :hd_seed_fingerprint
+        AND hd_account_index = :hd_account_index",
+        named_params![
+            ":hd_seed_fingerprint": seed_fingerprint.to_bytes(),
+            ":hd_account_index": u32::from(account_index),
+        ],
+        |row| row.get::<_, u32>(0),
+    )
+    .optional()?
+    .map(|account| AccountId::try_from(account).map_err(|_| SqliteClientError::AccountIdOutOfRange))
+    .transpose()
+}
+
 pub(crate) fn add_account<P: consensus::Parameters>(
     conn: &rusqlite::Transaction,
     params: &P,
     account: AccountId,
     key: &UnifiedFullViewingKey,
+    kind: AccountKind,
     birthday: AccountBirthday,
 ) -> Result<(), SqliteClientError> {
+    let (hd_seed_fingerprint, hd_account_index) = match kind {
+        AccountKind::Derived {
+            seed_fingerprint,
+            account_index,
+        } => (
+            Some(seed_fingerprint.to_bytes()),
+            Some(u32::from(account_index)),
+        ),
+        AccountKind::ViewOnly => (None, None),
+    };
+
     conn.execute(
-        "INSERT INTO accounts (account, ufvk, birthday_height, recover_until_height)
-        VALUES (:account, :ufvk, :birthday_height, :recover_until_height)",
+        "INSERT INTO accounts (
+            account, account_kind, hd_seed_fingerprint, hd_account_index,
+            ufvk, birthday_height, recover_until_height
+        )
+        VALUES (
+            :account, :account_kind, :hd_seed_fingerprint, :hd_account_index,
+            :ufvk, :birthday_height, :recover_until_height
+        )",
         named_params![
             ":account": u32::from(account),
+            ":account_kind": account_kind_code(&kind),
+            ":hd_seed_fingerprint": hd_seed_fingerprint,
+            ":hd_account_index": hd_account_index,
             ":ufvk": &key.encode(params),
             ":birthday_height": u32::from(birthday.height()),
             ":recover_until_height": birthday.recover_until().map(u32::from)
@@ -610,21 +702,24 @@ pub(crate) fn get_wallet_summary<P: consensus::Parameters>(
     fn is_any_spendable(
         conn: &rusqlite::Connection,
         summary_height: BlockHeight,
+        table_prefix: &'static str,
     ) -> Result<bool, SqliteClientError> {
         conn.query_row(
-            "SELECT NOT EXISTS(
-                 SELECT 1 FROM v_sapling_shard_unscanned_ranges
-                 WHERE :summary_height
-                    BETWEEN subtree_start_height
-                    AND IFNULL(subtree_end_height, :summary_height)
-                 AND block_range_start <= :summary_height
-             )",
+            &format!(
+                "SELECT NOT EXISTS(
+                     SELECT 1 FROM v_{}_shard_unscanned_ranges
+                     WHERE :summary_height
+                        BETWEEN subtree_start_height
+                        AND IFNULL(subtree_end_height, :summary_height)
+                     AND block_range_start <= :summary_height
+                 )",
+                table_prefix
+            ),
             named_params![":summary_height": u32::from(summary_height)],
             |row| row.get::<_, bool>(0),
         )
         .map_err(|e| e.into())
     }
-    let any_spendable = is_any_spendable(tx, summary_height)?;
 
     let mut stmt_accounts = tx.prepare_cached("SELECT account FROM accounts")?;
     let mut account_balances = stmt_accounts
@@ -636,81 +731,178 @@ pub(crate) fn get_wallet_summary<P: consensus::Parameters>(
         })
         .collect::<Result<BTreeMap<AccountId, AccountBalance>, _>>()?;
 
-    let sapling_trace = tracing::info_span!("stmt_select_notes").entered();
-    let mut stmt_select_notes = tx.prepare_cached(
-        "SELECT n.account, n.value, n.is_change, scan_state.max_priority, t.block
-         FROM sapling_received_notes n
-         JOIN transactions t ON t.id_tx = n.tx
-         LEFT OUTER JOIN v_sapling_shards_scan_state scan_state
-            ON n.commitment_tree_position >= scan_state.start_position
-            AND n.commitment_tree_position < scan_state.end_position_exclusive
-         WHERE n.spent IS NULL
-         AND (
-             t.expiry_height IS NULL
-             OR t.block IS NOT NULL
-             OR t.expiry_height >= :summary_height
-         )",
-    )?;
-
-    let mut rows =
-        stmt_select_notes.query(named_params![":summary_height": u32::from(summary_height)])?;
-    while let Some(row) = rows.next()? {
-        let account = AccountId::try_from(row.get::<_, u32>(0)?)
-            .map_err(|_| SqliteClientError::AccountIdOutOfRange)?;
+    // Adds the values of the unspent notes in the given pool's received notes table to the
+    // account balances, using `with_pool_balance_mut` to select the pool balance to update.
+    fn count_notes<F>(
+        tx: &rusqlite::Transaction,
+        chain_tip_height: BlockHeight,
+        summary_height: BlockHeight,
+        account_balances: &mut BTreeMap<AccountId, AccountBalance>,
+        table_prefix: &'static str,
+        with_pool_balance_mut: F,
+    ) -> Result<(), SqliteClientError>
+    where
+        F: Fn(
+            &mut AccountBalance,
+            NonNegativeAmount,
+            NonNegativeAmount,
+            NonNegativeAmount,
+            NonNegativeAmount,
+        ) -> Result<(), SqliteClientError>,
+    {
+        let any_spendable = is_any_spendable(tx, summary_height, table_prefix)?;
+        let mut stmt_select_notes = tx.prepare_cached(&format!(
+            "SELECT n.account, n.value, n.is_change, scan_state.max_priority, t.block,
+                    t.expiry_height
+             FROM {}_received_notes n
+             JOIN transactions t ON t.id_tx = n.tx
+             LEFT OUTER JOIN v_{}_shards_scan_state scan_state
+                ON n.commitment_tree_position >= scan_state.start_position
+                AND n.commitment_tree_position < scan_state.end_position_exclusive
+             WHERE n.spent IS NULL
+             AND (
+                 t.expiry_height IS NULL
+                 OR t.block IS NOT NULL
+                 OR t.expiry_height >= :summary_height
+             )",
+            table_prefix, table_prefix
+        ))?;
 
-        let value_raw = row.get::<_, i64>(1)?;
-        let value = NonNegativeAmount::from_nonnegative_i64(value_raw).map_err(|_| {
-            SqliteClientError::CorruptedData(format!("Negative received note value: {}", value_raw))
-        })?;
+        let mut rows =
+            stmt_select_notes.query(named_params![":summary_height": u32::from(summary_height)])?;
+        while let Some(row) = rows.next()? {
+            let account = AccountId::try_from(row.get::<_, u32>(0)?)
+                .map_err(|_| SqliteClientError::AccountIdOutOfRange)?;
 
-        let is_change = row.get::<_, bool>(2)?;
-
-        // If `max_priority` is null, this means that the note is not positioned; the note
-        // will not be spendable, so we assign the scan priority to `ChainTip` as a priority
-        // that is greater than `Scanned`
-        let max_priority_raw = row.get::<_, Option<i64>>(3)?;
-        let max_priority = max_priority_raw.map_or_else(
-            || Ok(ScanPriority::ChainTip),
-            |raw| {
-                parse_priority_code(raw).ok_or_else(|| {
-                    SqliteClientError::CorruptedData(format!(
-                        "Priority code {} not recognized.",
-                        raw
-                    ))
-                })
-            },
-        )?;
+            let value_raw = row.get::<_, i64>(1)?;
+            let value = NonNegativeAmount::from_nonnegative_i64(value_raw).map_err(|_| {
+                SqliteClientError::CorruptedData(format!(
+                    "Negative received note value: {}",
+                    value_raw
+                ))
+            })?;
 
-        let received_height = row.get::<_, Option<u32>>(4)?.map(BlockHeight::from);
+            let is_change = row.get::<_, bool>(2)?;
+
+            // If `max_priority` is null, this means that the note is not positioned; the note
+            // will not be spendable, so we assign the scan priority to `ChainTip` as a priority
+            // that is greater than `Scanned`
+            let max_priority_raw = row.get::<_, Option<i64>>(3)?;
+            let max_priority = max_priority_raw.map_or_else(
+                || Ok(ScanPriority::ChainTip),
+                |raw| {
+                    parse_priority_code(raw).ok_or_else(|| {
+                        SqliteClientError::CorruptedData(format!(
+                            "Priority code {} not recognized.",
+                            raw
+                        ))
+                    })
+                },
+            )?;
 
-        let is_spendable = any_spendable
-            && received_height.iter().any(|h| h <= &summary_height)
-            && max_priority <= ScanPriority::Scanned;
+            let received_height = row.get::<_, Option<u32>>(4)?.map(BlockHeight::from);
+
+            // Notes received in transactions that have been observed in the mempool but not
+            // mined are reported as incoming pending value, unless the transaction has expired.
+            let is_incoming_pending = !is_change && received_height.is_none();
+            if is_incoming_pending {
+                let expiry_height = row.get::<_, Option<u32>>(5)?.map(BlockHeight::from);
+                if expiry_height
+                    .iter()
+                    .any(|h| u32::from(*h) != 0 && h <= &chain_tip_height)
+                {
+                    continue;
+                }
+            }
 
-        let is_pending_change = is_change && received_height.iter().all(|h| h > &summary_height);
+            let is_spendable = any_spendable
+                && received_height.iter().any(|h| h <= &summary_height)
+                && max_priority <= ScanPriority::Scanned;
+
+            let is_pending_change =
+                is_change && received_height.iter().all(|h| h > &summary_height);
+
+            let (
+                spendable_value,
+                change_pending_confirmation,
+                value_pending_spendability,
+                incoming_pending,
+            ) = {
+                let zero = NonNegativeAmount::ZERO;
+                if is_spendable {
+                    (value, zero, zero, zero)
+                } else if is_pending_change {
+                    (zero, value, zero, zero)
+                } else if is_incoming_pending {
+                    (zero, zero, zero, value)
+                } else {
+                    (zero, zero, value, zero)
+                }
+            };
 
-        let (spendable_value, change_pending_confirmation, value_pending_spendability) = {
-            let zero = NonNegativeAmount::ZERO;
-            if is_spendable {
-                (value, zero, zero)
-            } else if is_pending_change {
-                (zero, value, zero)
-            } else {
-                (zero, zero, value)
+            if let Some(balances) = account_balances.get_mut(&account) {
+                with_pool_balance_mut(
+                    balances,
+                    spendable_value,
+                    change_pending_confirmation,
+                    value_pending_spendability,
+                    incoming_pending,
+                )?;
             }
-        };
+        }
+
+        Ok(())
+    }
 
-        if let Some(balances) = account_balances.get_mut(&account) {
+    let sapling_trace = tracing::info_span!("stmt_select_notes").entered();
+    count_notes(
+        tx,
+        chain_tip_height,
+        summary_height,
+        &mut account_balances,
+        SAPLING_TABLES_PREFIX,
+        |balances,
+         spendable_value,
+         change_pending_confirmation,
+         value_pending_spendability,
+         incoming_pending| {
             balances.with_sapling_balance_mut::<_, SqliteClientError>(|bal| {
                 bal.add_spendable_value(spendable_value)?;
                 bal.add_pending_change_value(change_pending_confirmation)?;
                 bal.add_pending_spendable_value(value_pending_spendability)?;
+                bal.add_incoming_pending_value(incoming_pending)?;
                 Ok(())
-            })?;
-        }
-    }
+            })
+        },
+    )?;
     drop(sapling_trace);
 
+    #[cfg(feature = "orchard")]
+    {
+        let orchard_trace = tracing::info_span!("stmt_select_orchard_notes").entered();
+        count_notes(
+            tx,
+            chain_tip_height,
+            summary_height,
+            &mut account_balances,
+            ORCHARD_TABLES_PREFIX,
+            |balances,
+             spendable_value,
+             change_pending_confirmation,
+             value_pending_spendability,
+             incoming_pending| {
+                balances.with_orchard_balance_mut::<_, SqliteClientError>(|bal| {
+                    bal.add_spendable_value(spendable_value)?;
+                    bal.add_pending_change_value(change_pending_confirmation)?;
+                    bal.add_pending_spendable_value(value_pending_spendability)?;
+                    bal.add_incoming_pending_value(incoming_pending)?;
+                    Ok(())
+                })
+            },
+        )?;
+        drop(orchard_trace);
+    }
+
     #[cfg(feature = "transparent-inputs")]
     {
         let transparent_trace = tracing::info_span!("stmt_transparent_balances").entered();
@@ -797,11 +989,20 @@ pub(crate) fn get_received_memo(
             .optional()?
             .flatten(),
         #[cfg(zcash_unstable = "orchard")]
-        _ => {
-            return Err(SqliteClientError::UnsupportedPoolType(PoolType::Shielded(
-                note_id.protocol(),
-            )))
-        }
+        ShieldedProtocol::Orchard => conn
+            .query_row(
+                "SELECT memo FROM orchard_received_notes
+                JOIN transactions ON orchard_received_notes.tx = transactions.id_tx
+                WHERE transactions.txid = :txid
+                AND orchard_received_notes.action_index = :action_index",
+                named_params![
+                    ":txid": note_id.txid().as_ref(),
+                    ":action_index": note_id.output_index()
+                ],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten(),
     };
 
     memo_bytes
@@ -918,6 +1119,34 @@ pub(crate) fn get_sent_memo(
         .transpose()
 }
 
+/// Utility method for determining whether we have any spendable notes
+///
+/// If the tip shard has unscanned ranges below the anchor height and greater than or equal to
+/// the wallet birthday, none of our notes can be spent because we cannot construct witnesses at
+/// the provided anchor height.
+pub(crate) fn unscanned_tip_exists(
+    conn: &rusqlite::Connection,
+    anchor_height: BlockHeight,
+    table_prefix: &'static str,
+) -> Result<bool, rusqlite::Error> {
+    // The v_*_shard_unscanned_ranges views only return ranges ending on or after wallet
+    // birthday, so we don't need to refer to the birthday in this query.
+    conn.query_row(
+        &format!(
+            "SELECT EXISTS (
+                 SELECT 1 FROM v_{}_shard_unscanned_ranges range
+                 WHERE range.block_range_start <= :anchor_height
+                 AND :anchor_height BETWEEN
+                    range.subtree_start_height
+                    AND IFNULL(range.subtree_end_height, :anchor_height)
+             )",
+            table_prefix
+        ),
+        named_params![":anchor_height": u32::from(anchor_height),],
+        |row| row.get::<_, bool>(0),
+    )
+}
+
 /// Returns the minimum birthday height for accounts in the wallet.
 //
 // TODO ORCHARD: we should consider whether we want to permit protocol-restricted accounts; if so,
@@ -952,6 +1181,44 @@ pub(crate) fn account_birthday(
     .and_then(|opt| opt.ok_or(SqliteClientError::AccountUnknown(account)))
 }
 
+/// Returns the kind of the specified account, or `None` if the account is not known to the
+/// wallet.
+pub(crate) fn get_account_kind(
+    conn: &rusqlite::Connection,
+    account: AccountId,
+) -> Result<Option<AccountKind>, SqliteClientError> {
+    conn.query_row(
+        "SELECT account_kind, hd_seed_fingerprint, hd_account_index
+         FROM accounts
+         WHERE account = :account_id",
+        named_params![":account_id": u32::from(account)],
+        |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, Option<[u8; 32]>>(1)?,
+                row.get::<_, Option<u32>>(2)?,
+            ))
+        },
+    )
+    .optional()?
+    .map(|row| match row {
+        (ACCOUNT_KIND_DERIVED, Some(seed_fingerprint), Some(account_index)) => {
+            Ok(AccountKind::Derived {
+                seed_fingerprint: SeedFingerprint::from_bytes(seed_fingerprint),
+                account_index: AccountId::try_from(account_index)
+                    .map_err(|_| SqliteClientError::AccountIdOutOfRange)?,
+            })
+        }
+        (ACCOUNT_KIND_VIEW_ONLY, None, None) => Ok(AccountKind::ViewOnly),
+        (code, _, _) => Err(SqliteClientError::CorruptedData(format!(
+            "Invalid key derivation metadata for account {} of kind {}",
+            u32::from(account),
+            code
+        ))),
+    })
+    .transpose()
+}
+
 /// Returns the minimum and maximum heights for blocks stored in the wallet database.
 pub(crate) fn block_height_extrema(
     conn: &rusqlite::Connection,
@@ -1220,7 +1487,11 @@ pub(crate) fn get_min_unspent_height(
 ) -> Result<Option<BlockHeight>, SqliteClientError> {
     conn.query_row(
         "SELECT MIN(tx.block)
-         FROM sapling_received_notes n
+         FROM (
+             SELECT tx, spent FROM sapling_received_notes
+             UNION ALL
+             SELECT tx, spent FROM orchard_received_notes
+         ) n
          JOIN transactions tx ON tx.id_tx = n.tx
          WHERE n.spent IS NULL",
         [],
@@ -1232,6 +1503,94 @@ pub(crate) fn get_min_unspent_height(
     .map_err(SqliteClientError::from)
 }
 
+/// Returns the minimum height to which the wallet's note commitment trees can be truncated, or
+/// `Ok(None)` if the trees do not contain any checkpoints.
+///
+/// Truncation requires a checkpoint at the target height in each tree, so this is the greatest
+/// of the minimum checkpoint heights retained by the individual trees.
+pub(crate) fn get_min_rewind_height(
+    conn: &rusqlite::Connection,
+) -> Result<Option<BlockHeight>, rusqlite::Error> {
+    conn.query_row(
+        "SELECT MAX(min_checkpoint_id)
+         FROM (
+             SELECT MIN(checkpoint_id) AS min_checkpoint_id FROM sapling_tree_checkpoints
+             UNION ALL
+             SELECT MIN(checkpoint_id) AS min_checkpoint_id FROM orchard_tree_checkpoints
+         )",
+        [],
+        |row| {
+            row.get(0)
+                .map(|maybe_height: Option<u32>| maybe_height.map(BlockHeight::from))
+        },
+    )
+}
+
+/// Returns the identifiers of transactions mined in blocks above the given height, along with
+/// the identifiers of the notes received in those transactions.
+pub(crate) fn get_mined_above(
+    conn: &rusqlite::Connection,
+    block_height: BlockHeight,
+) -> Result<(Vec<TxId>, Vec<NoteId>), rusqlite::Error> {
+    let mut stmt_txids = conn.prepare_cached(
+        "SELECT txid FROM transactions
+        WHERE block > :block_height
+        ORDER BY block, tx_index",
+    )?;
+    let txids = stmt_txids
+        .query_and_then(
+            named_params![":block_height": u32::from(block_height)],
+            |row| row.get(0).map(TxId::from_bytes),
+        )?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut stmt_sapling_notes = conn.prepare_cached(
+        "SELECT t.txid, rn.output_index
+        FROM sapling_received_notes rn
+        JOIN transactions t ON t.id_tx = rn.tx
+        WHERE t.block > :block_height
+        ORDER BY t.block, t.tx_index, rn.output_index",
+    )?;
+    let mut note_ids = stmt_sapling_notes
+        .query_and_then(
+            named_params![":block_height": u32::from(block_height)],
+            |row| {
+                Ok::<_, rusqlite::Error>(NoteId::new(
+                    TxId::from_bytes(row.get(0)?),
+                    ShieldedProtocol::Sapling,
+                    row.get(1)?,
+                ))
+            },
+        )?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    #[cfg(zcash_unstable = "orchard")]
+    {
+        let mut stmt_orchard_notes = conn.prepare_cached(
+            "SELECT t.txid, rn.action_index
+            FROM orchard_received_notes rn
+            JOIN transactions t ON t.id_tx = rn.tx
+            WHERE t.block > :block_height
+            ORDER BY t.block, t.tx_index, rn.action_index",
+        )?;
+        let orchard_note_ids = stmt_orchard_notes.query_and_then(
+            named_params![":block_height": u32::from(block_height)],
+            |row| {
+                Ok::<_, rusqlite::Error>(NoteId::new(
+                    TxId::from_bytes(row.get(0)?),
+                    ShieldedProtocol::Orchard,
+                    row.get(1)?,
+                ))
+            },
+        )?;
+        for note_id in orchard_note_ids {
+            note_ids.push(note_id?);
+        }
+    }
+
+    Ok((txids, note_ids))
+}
+
 /// Truncates the database to the given height.
 ///
 /// If the requested height is greater than or equal to the height of the last scanned
@@ -1271,6 +1630,10 @@ pub(crate) fn truncate_to_height<P: consensus::Parameters>(
         wdb.with_sapling_tree_mut(|tree| {
             tree.truncate_removing_checkpoint(&block_height).map(|_| ())
         })?;
+        #[cfg(feature = "orchard")]
+        wdb.with_orchard_tree_mut(|tree| {
+            tree.truncate_removing_checkpoint(&block_height).map(|_| ())
+        })?;
 
         // Remove any legacy Sapling witnesses
         conn.execute(
@@ -1290,6 +1653,17 @@ pub(crate) fn truncate_to_height<P: consensus::Parameters>(
             );",
             [u32::from(block_height)],
         )?;
+        conn.execute(
+            "DELETE FROM orchard_received_notes
+            WHERE id_note IN (
+                SELECT rn.id_note
+                FROM orchard_received_notes rn
+                LEFT OUTER JOIN transactions tx
+                ON tx.id_tx = rn.tx
+                WHERE tx.block IS NOT NULL AND tx.block > ?
+            );",
+            [u32::from(block_height)],
+        )?;
 
         // Do not delete sent notes; this can contain data that is not recoverable
         // from the chain. Wallets must continue to operate correctly in the
@@ -1526,6 +1900,8 @@ pub(crate) fn put_block(
     block_time: u32,
     sapling_commitment_tree_size: u32,
     sapling_output_count: u32,
+    #[cfg(feature = "orchard")] orchard_commitment_tree_size: u32,
+    #[cfg(feature = "orchard")] orchard_action_count: u32,
 ) -> Result<(), SqliteClientError> {
     let block_hash_data = conn
         .query_row(
@@ -1556,7 +1932,9 @@ pub(crate) fn put_block(
             time,
             sapling_commitment_tree_size,
             sapling_output_count,
-            sapling_tree
+            sapling_tree,
+            orchard_commitment_tree_size,
+            orchard_action_count
         )
         VALUES (
             :height,
@@ -1564,21 +1942,37 @@ pub(crate) fn put_block(
             :block_time,
             :sapling_commitment_tree_size,
             :sapling_output_count,
-            x'00'
+            x'00',
+            :orchard_commitment_tree_size,
+            :orchard_action_count
         )
         ON CONFLICT (height) DO UPDATE
         SET hash = :hash,
             time = :block_time,
             sapling_commitment_tree_size = :sapling_commitment_tree_size,
-            sapling_output_count = :sapling_output_count",
+            sapling_output_count = :sapling_output_count,
+            orchard_commitment_tree_size = :orchard_commitment_tree_size,
+            orchard_action_count = :orchard_action_count",
     )?;
 
+    #[cfg(feature = "orchard")]
+    let orchard_commitment_tree_size = Some(orchard_commitment_tree_size);
+    #[cfg(not(feature = "orchard"))]
+    let orchard_commitment_tree_size: Option<u32> = None;
+
+    #[cfg(feature = "orchard")]
+    let orchard_action_count = Some(orchard_action_count);
+    #[cfg(not(feature = "orchard"))]
+    let orchard_action_count: Option<u32> = None;
+
     stmt_upsert_block.execute(named_params![
         ":height": u32::from(block_height),
         ":hash": &block_hash.0[..],
         ":block_time": block_time,
         ":sapling_commitment_tree_size": sapling_commitment_tree_size,
         ":sapling_output_count": sapling_output_count,
+        ":orchard_commitment_tree_size": orchard_commitment_tree_size,
+        ":orchard_action_count": orchard_action_count,
     ])?;
 
     Ok(())
@@ -1768,6 +2162,200 @@ pub(crate) fn update_expired_notes(
         )",
     )?;
     stmt_update_expired.execute([u32::from(expiry_height)])?;
+
+    let mut stmt_update_expired_orchard = conn.prepare_cached(
+        "UPDATE orchard_received_notes SET spent = NULL WHERE EXISTS (
+            SELECT id_tx FROM transactions
+            WHERE id_tx = orchard_received_notes.spent AND block IS NULL AND expiry_height < ?
+        )",
+    )?;
+    stmt_update_expired_orchard.execute([u32::from(expiry_height)])?;
+    Ok(())
+}
+
+/// Returns the transactions that spend the wallet's notes or UTXOs, but which have not been mined
+/// and have not expired as of the wallet's view of the chain tip.
+pub(crate) fn get_unmined_sent_transactions(
+    conn: &rusqlite::Connection,
+) -> Result<Vec<UnminedSentTransaction>, SqliteClientError> {
+    let chain_tip_height = scan_queue_extrema(conn)?.map(|range| *range.end());
+
+    let mut stmt_unmined = conn.prepare_cached(
+        "SELECT t.txid, t.raw, t.expiry_height, t.created
+        FROM transactions t
+        WHERE t.block IS NULL
+        AND t.raw IS NOT NULL
+        AND (
+            :chain_tip_height IS NULL
+            OR t.expiry_height = 0
+            OR t.expiry_height > :chain_tip_height
+        )
+        AND (
+            EXISTS (SELECT 1 FROM sapling_received_notes WHERE spent = t.id_tx)
+            OR EXISTS (SELECT 1 FROM orchard_received_notes WHERE spent = t.id_tx)
+            OR EXISTS (SELECT 1 FROM utxos WHERE spent_in_tx = t.id_tx)
+        )
+        ORDER BY t.id_tx",
+    )?;
+
+    let rows = stmt_unmined.query_and_then(
+        named_params![":chain_tip_height": chain_tip_height.map(u32::from)],
+        |row| -> Result<_, SqliteClientError> {
+            Ok(UnminedSentTransaction::from_parts(
+                TxId::from_bytes(row.get(0)?),
+                row.get(1)?,
+                BlockHeight::from(row.get::<_, u32>(2)?),
+                row.get(3)?,
+            ))
+        },
+    )?;
+
+    rows.collect()
+}
+
+/// Returns the transaction history of the given account, restricted according to the provided
+/// filter.
+pub(crate) fn get_transactions(
+    conn: &rusqlite::Connection,
+    account: AccountId,
+    filter: &TransactionFilter,
+) -> Result<Vec<TransactionSummary>, SqliteClientError> {
+    let mut stmt_txs = conn.prepare_cached(
+        "SELECT txid, mined_height, block_time, expiry_height, account_balance_delta, fee_paid,
+                expired_unmined
+        FROM v_transactions
+        WHERE account_id = :account
+        AND (
+            :range_start IS NULL
+            OR (mined_height >= :range_start AND mined_height < :range_end)
+        )
+        ORDER BY mined_height IS NOT NULL, mined_height DESC, tx_index DESC, txid
+        LIMIT :limit OFFSET :offset",
+    )?;
+
+    let mut stmt_outputs = conn.prepare_cached(
+        "SELECT output_pool, output_index, from_account, to_account, to_address, value,
+                is_change, memo
+        FROM v_tx_outputs
+        WHERE txid = :txid
+        AND (from_account = :account OR to_account = :account)
+        ORDER BY output_pool, output_index",
+    )?;
+
+    let parse_account = |raw: Option<u32>| {
+        raw.map(|a| AccountId::try_from(a).map_err(|_| SqliteClientError::AccountIdOutOfRange))
+            .transpose()
+    };
+
+    let parse_value = |raw: i64| {
+        NonNegativeAmount::from_nonnegative_i64(raw)
+            .map_err(|_| SqliteClientError::CorruptedData(format!("Invalid output value: {}", raw)))
+    };
+
+    let range = filter.mined_height_range();
+    let mut rows = stmt_txs.query(named_params![
+        ":account": u32::from(account),
+        ":range_start": range.map(|r| u32::from(r.start)),
+        ":range_end": range.map(|r| u32::from(r.end)),
+        // A negative limit indicates that there is no upper bound on the number of rows.
+        ":limit": filter.limit().map_or(-1, i64::from),
+        ":offset": filter.offset(),
+    ])?;
+
+    let mut txs = vec![];
+    while let Some(row) = rows.next()? {
+        let txid = TxId::from_bytes(row.get(0)?);
+
+        let mut output_rows = stmt_outputs.query(named_params![
+            ":txid": &txid.as_ref()[..],
+            ":account": u32::from(account),
+        ])?;
+        let mut outputs = vec![];
+        while let Some(output_row) = output_rows.next()? {
+            let pool_raw = output_row.get::<_, i64>(0)?;
+            let pool = parse_pool_code(pool_raw).ok_or_else(|| {
+                SqliteClientError::CorruptedData(format!("Invalid pool code: {}", pool_raw))
+            })?;
+
+            outputs.push(OutputSummary::from_parts(
+                pool,
+                output_row.get(1)?,
+                parse_account(output_row.get(2)?)?,
+                parse_account(output_row.get(3)?)?,
+                output_row.get(4)?,
+                parse_value(output_row.get(5)?)?,
+                output_row.get(6)?,
+                output_row
+                    .get::<_, Option<Vec<u8>>>(7)?
+                    .map(|bytes| MemoBytes::from_bytes(&bytes))
+                    .transpose()
+                    .map_err(SqliteClientError::InvalidMemo)?,
+            ));
+        }
+
+        let delta_raw = row.get::<_, i64>(4)?;
+        let account_value_delta = Amount::from_i64(delta_raw).map_err(|_| {
+            SqliteClientError::CorruptedData(format!("Invalid balance delta: {}", delta_raw))
+        })?;
+
+        txs.push(TransactionSummary::from_parts(
+            account,
+            txid,
+            row.get::<_, Option<u32>>(1)?.map(BlockHeight::from),
+            row.get(2)?,
+            row.get::<_, Option<u32>>(3)?.map(BlockHeight::from),
+            account_value_delta,
+            row.get::<_, Option<i64>>(5)?.map(parse_value).transpose()?,
+            // `expired_unmined` is null for unmined transactions with no known expiry height.
+            row.get::<_, Option<bool>>(6)?.unwrap_or(false),
+            outputs,
+        ));
+    }
+
+    Ok(txs)
+}
+
+/// Marks the unmined transaction with the given ID as abandoned, releasing the notes and UTXOs
+/// that it spends and removing the notes received in it.
+///
+/// This should only be executed inside a transactional context.
+pub(crate) fn abandon_transaction(
+    conn: &rusqlite::Transaction,
+    txid: TxId,
+) -> Result<(), SqliteClientError> {
+    let tx_ref = conn
+        .query_row(
+            "SELECT id_tx FROM transactions WHERE txid = :txid AND block IS NULL",
+            named_params![":txid": &txid.as_ref()[..]],
+            |row| row.get::<_, i64>(0),
+        )
+        .optional()?
+        .ok_or(SqliteClientError::UnminedTransactionNotFound(txid))?;
+
+    conn.execute(
+        "UPDATE sapling_received_notes SET spent = NULL WHERE spent = :tx_ref",
+        named_params![":tx_ref": tx_ref],
+    )?;
+    conn.execute(
+        "UPDATE orchard_received_notes SET spent = NULL WHERE spent = :tx_ref",
+        named_params![":tx_ref": tx_ref],
+    )?;
+    conn.execute(
+        "UPDATE utxos SET spent_in_tx = NULL WHERE spent_in_tx = :tx_ref",
+        named_params![":tx_ref": tx_ref],
+    )?;
+
+    // Notes received in the transaction will be rediscovered by scanning if the transaction is
+    // subsequently mined.
+    conn.execute(
+        "DELETE FROM sapling_received_notes WHERE tx = :tx_ref",
+        named_params![":tx_ref": tx_ref],
+    )?;
+    conn.execute(
+        "DELETE FROM orchard_received_notes WHERE tx = :tx_ref",
+        named_params![":tx_ref": tx_ref],
+    )?;
+
     Ok(())
 }
 
@@ -2015,6 +2603,10 @@ pub(crate) fn query_nullifier_map<N: AsRef<[u8]>, S>(
             index,
             sapling_spends: vec![],
             sapling_outputs: vec![],
+            #[cfg(feature = "orchard")]
+            orchard_spends: vec![],
+            #[cfg(feature = "orchard")]
+            orchard_outputs: vec![],
         },
         height,
     )
@@ -2042,21 +2634,45 @@ mod tests {
     use std::num::NonZeroU32;
 
     use sapling::zip32::ExtendedSpendingKey;
-    use zcash_client_backend::data_api::{AccountBirthday, WalletRead};
+    use zcash_client_backend::{
+        address::Address,
+        data_api::{
+            testing::conformance::{AddressType, TestBuilder, TestState},
+            AccountBirthday, TransactionFilter, WalletRead,
+        },
+        wallet::OvkPolicy,
+    };
     use zcash_primitives::{block::BlockHash, transaction::components::amount::NonNegativeAmount};
 
     use crate::{
-        testing::{AddressType, BlockCache, TestBuilder, TestState},
+        testing::{BlockCache, TestDbFactory},
         AccountId,
     };
 
+    #[cfg(feature = "orchard")]
+    use {
+        incrementalmerkletree::frontier::Frontier,
+        std::convert::Infallible,
+        zcash_client_backend::{
+            data_api::{
+                testing::conformance::input_selector, wallet::input_selection::PrivacyPolicy,
+            },
+            zip321::{Payment, TransactionRequest},
+            PoolType, ShieldedProtocol,
+        },
+        zcash_primitives::{
+            consensus::{NetworkUpgrade, Parameters},
+            transaction::fees::StandardFeeRule,
+        },
+    };
+
     #[cfg(feature = "transparent-inputs")]
     use {
         crate::PRUNING_DEPTH,
         zcash_client_backend::{
             data_api::{wallet::input_selection::GreedyInputSelector, InputSource, WalletWrite},
             encoding::AddressCodec,
-            fees::{fixed, DustOutputPolicy},
+            fees::{fixed, ChangePoolPolicy, DustOutputPolicy},
             wallet::WalletTransparentOutput,
         },
         zcash_primitives::{
@@ -2071,6 +2687,7 @@ mod tests {
     #[test]
     fn empty_database_has_no_balance() {
         let st = TestBuilder::new()
+            .with_data_store_factory(TestDbFactory)
             .with_test_account(AccountBirthday::from_sapling_activation)
             .build();
 
@@ -2102,9 +2719,8 @@ mod tests {
     #[test]
     #[cfg(feature = "transparent-inputs")]
     fn put_received_transparent_utxo() {
-        use crate::testing::TestBuilder;
-
         let mut st = TestBuilder::new()
+            .with_data_store_factory(TestDbFactory)
             .with_test_account(AccountBirthday::from_sapling_activation)
             .build();
 
@@ -2191,9 +2807,8 @@ mod tests {
 
     #[test]
     fn get_account_ids() {
-        use crate::testing::TestBuilder;
-
         let st = TestBuilder::new()
+            .with_data_store_factory(TestDbFactory)
             .with_test_account(AccountBirthday::from_sapling_activation)
             .build();
 
@@ -2203,11 +2818,203 @@ mod tests {
         );
     }
 
+    #[test]
+    fn get_transactions_returns_account_history() {
+        let mut st = TestBuilder::new()
+            .with_block_cache(BlockCache::new())
+            .with_data_store_factory(TestDbFactory)
+            .with_test_account(AccountBirthday::from_sapling_activation)
+            .build();
+
+        let (account, usk, _) = st.test_account().unwrap();
+        let dfvk = st.test_account_sapling().unwrap();
+
+        // Receive funds in a single note
+        let value = NonNegativeAmount::const_from_u64(60000);
+        let (h1, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.scan_cached_blocks(h1, 1);
+
+        // Send part of the funds to an external recipient, and mine the transaction
+        let to = Address::Sapling(ExtendedSpendingKey::master(&[]).default_address().1);
+        let amount_sent = NonNegativeAmount::const_from_u64(15000);
+        let txid = st
+            .create_spend_to_address(
+                &usk,
+                &to,
+                amount_sent,
+                None,
+                OvkPolicy::Sender,
+                NonZeroU32::new(1).unwrap(),
+                None,
+            )
+            .unwrap()[0];
+        let (h2, _) = st.generate_next_block_including(txid);
+        st.scan_cached_blocks(h2, 1);
+
+        let history = st
+            .wallet()
+            .get_transactions(account, &TransactionFilter::default())
+            .unwrap();
+        assert_eq!(history.len(), 2);
+
+        // The most recent transaction is returned first
+        let sent = &history[0];
+        assert_eq!(sent.txid(), txid);
+        assert_eq!(sent.mined_height(), Some(h2));
+        let fee = sent.fee_paid().unwrap();
+        assert_eq!(
+            i64::from(sent.account_value_delta()),
+            -i64::from((amount_sent + fee).unwrap())
+        );
+        assert_eq!(
+            sent.counterparties().collect::<Vec<_>>(),
+            vec![to.encode(&st.network())]
+        );
+        assert!(!sent.expired_unmined());
+
+        let received = &history[1];
+        assert_eq!(received.mined_height(), Some(h1));
+        assert_eq!(i64::from(received.account_value_delta()), i64::from(value));
+        assert_eq!(received.outputs().len(), 1);
+        assert_eq!(received.outputs()[0].to_account(), Some(account));
+        assert_eq!(received.outputs()[0].value(), value);
+
+        // Pagination skips the most recent transaction
+        let page = st
+            .wallet()
+            .get_transactions(account, &TransactionFilter::default().with_page(1, 10))
+            .unwrap();
+        assert_eq!(page, vec![received.clone()]);
+
+        // Restricting the mined height range excludes the sent transaction
+        let in_range = st
+            .wallet()
+            .get_transactions(
+                account,
+                &TransactionFilter::default().with_mined_height_range(h1..h2),
+            )
+            .unwrap();
+        assert_eq!(in_range, vec![received.clone()]);
+    }
+
+    #[test]
+    #[cfg(feature = "orchard")]
+    fn get_transactions_includes_orchard_history() {
+        let mut st = TestBuilder::new()
+            .with_block_cache(BlockCache::new())
+            .with_data_store_factory(TestDbFactory)
+            .with_test_account(|network| {
+                // Orchard notes may only be spent once NU5 has activated.
+                AccountBirthday::from_parts(
+                    network.activation_height(NetworkUpgrade::Nu5).unwrap(),
+                    Frontier::empty(),
+                    None,
+                )
+            })
+            .build();
+
+        let (account, usk, birthday) = st.test_account().unwrap();
+        let fvk = st.test_account_orchard().unwrap();
+
+        // Receive funds in a single Orchard note
+        let value = NonNegativeAmount::const_from_u64(60000);
+        let h1 = birthday.height();
+        st.generate_orchard_block_at(h1, BlockHash([0; 32]), &fvk, value, 0, 0);
+        st.scan_cached_blocks(h1, 1);
+
+        // Send part of the funds to an external Sapling recipient, and mine the transaction
+        let to = Address::Sapling(ExtendedSpendingKey::master(&[]).default_address().1);
+        let amount_sent = NonNegativeAmount::const_from_u64(15000);
+        let request = TransactionRequest::new(vec![Payment {
+            recipient_address: to.clone(),
+            amount: amount_sent,
+            memo: None,
+            label: None,
+            message: None,
+            other_params: vec![],
+        }])
+        .unwrap();
+        let proposal = st
+            .propose_transfer(
+                account,
+                &input_selector(StandardFeeRule::Zip317, None),
+                request,
+                PrivacyPolicy::AllowRevealedAmounts,
+                NonZeroU32::new(1).unwrap(),
+            )
+            .unwrap();
+        let txid = st
+            .create_proposed_transactions::<Infallible, _>(&usk, OvkPolicy::Sender, &proposal)
+            .unwrap()[0];
+        let (h2, _) = st.generate_next_block_including(txid);
+        st.scan_cached_blocks(h2, 1);
+
+        let history = st
+            .wallet()
+            .get_transactions(account, &TransactionFilter::default())
+            .unwrap();
+        assert_eq!(history.len(), 2);
+
+        // The sent transaction spends the Orchard note, pays the Sapling recipient, and
+        // returns change to the Orchard pool.
+        let sent = &history[0];
+        assert_eq!(sent.txid(), txid);
+        assert_eq!(sent.mined_height(), Some(h2));
+        let fee = sent.fee_paid().unwrap();
+        let change = (value - (amount_sent + fee).unwrap()).unwrap();
+        assert_eq!(
+            i64::from(sent.account_value_delta()),
+            -i64::from((amount_sent + fee).unwrap())
+        );
+        assert_eq!(
+            sent.counterparties().collect::<Vec<_>>(),
+            vec![to.encode(&st.network())]
+        );
+        assert_eq!(
+            sent.outputs()
+                .iter()
+                .map(|o| (o.pool(), o.to_account(), o.value(), o.is_change()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    PoolType::Shielded(ShieldedProtocol::Sapling),
+                    None,
+                    amount_sent,
+                    false
+                ),
+                (
+                    PoolType::Shielded(ShieldedProtocol::Orchard),
+                    Some(account),
+                    change,
+                    true
+                ),
+            ]
+        );
+
+        // The received note is reported as an Orchard output to the account.
+        let received = &history[1];
+        assert_eq!(received.mined_height(), Some(h1));
+        assert_eq!(i64::from(received.account_value_delta()), i64::from(value));
+        assert_eq!(
+            received
+                .outputs()
+                .iter()
+                .map(|o| (o.pool(), o.to_account(), o.value()))
+                .collect::<Vec<_>>(),
+            vec![(
+                PoolType::Shielded(ShieldedProtocol::Orchard),
+                Some(account),
+                value
+            )]
+        );
+    }
+
     #[test]
     #[cfg(feature = "transparent-inputs")]
     fn transparent_balance_across_shielding() {
         let mut st = TestBuilder::new()
-            .with_block_cache()
+            .with_block_cache(BlockCache::new())
+            .with_data_store_factory(TestDbFactory)
             .with_test_account(AccountBirthday::from_sapling_activation)
             .build();
 
@@ -2229,7 +3036,7 @@ mod tests {
         }
         st.scan_cached_blocks(start_height, 10);
 
-        let check_balance = |st: &TestState<_>, min_confirmations: u32, expected| {
+        let check_balance = |st: &TestState<_, TestDbFactory>, min_confirmations: u32, expected| {
             // Check the wallet summary returns the expected transparent balance.
             let summary = st
                 .wallet()
@@ -2290,12 +3097,13 @@ mod tests {
             fixed::SingleOutputChangeStrategy::new(
                 FixedFeeRule::non_standard(NonNegativeAmount::ZERO),
                 None,
+                ChangePoolPolicy::default(),
             ),
             DustOutputPolicy::default(),
         );
         let txid = st
             .shield_transparent_funds(&input_selector, value, &usk, &[*taddr], 1)
-            .unwrap();
+            .unwrap()[0];
 
         // The wallet should have zero transparent balance, because the shielding
         // transaction can be mined.
@@ -2349,11 +3157,12 @@ mod tests {
     #[test]
     fn block_fully_scanned() {
         let mut st = TestBuilder::new()
-            .with_block_cache()
+            .with_block_cache(BlockCache::new())
+            .with_data_store_factory(TestDbFactory)
             .with_test_account(AccountBirthday::from_sapling_activation)
             .build();
 
-        let block_fully_scanned = |st: &TestState<BlockCache>| {
+        let block_fully_scanned = |st: &TestState<BlockCache, TestDbFactory>| {
             st.wallet()
                 .block_fully_scanned()
                 .unwrap()
@@ -0,0 +1,864 @@
+//! Export and import of wallet data in a portable format.
+//!
+//! A [`WalletExport`] captures the information stored in the wallet database that cannot be
+//! recovered by rescanning the chain using the wallet's viewing keys: the wallet's accounts and
+//! their birthdays, the addresses that have been generated for each account, metadata about the
+//! wallet's transactions, the outputs that the wallet has sent (including the memos attached to
+//! those outputs), and the wallet's scan progress.
+//!
+//! The serialized form of a [`WalletExport`] is versioned independently of the database schema,
+//! so that an export created by one version of this crate may be imported into a wallet database
+//! created by a later version. Received notes, note commitment tree data, and nullifiers are not
+//! included in the export; after an import, the wallet's scan queue is updated so that this data
+//! is recovered by scanning the chain.
+
+use std::borrow::Borrow;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use rusqlite::{named_params, Connection};
+use zcash_client_backend::{
+    address::Address,
+    data_api::{
+        scanning::{ScanPriority, ScanRange},
+        AccountBirthday, AccountKind,
+    },
+    keys::{SeedFingerprint, UnifiedFullViewingKey},
+    PoolType,
+};
+use zcash_encoding::{Optional, Vector};
+use zcash_primitives::{
+    consensus::{self, BlockHeight},
+    memo::MemoBytes,
+    transaction::{
+        components::{amount::NonNegativeAmount, Amount},
+        TxId,
+    },
+    zip32::{AccountId, DiversifierIndex},
+};
+
+use crate::{error::SqliteClientError, WalletDb, DEFAULT_UA_REQUEST};
+
+use super::{
+    block_fully_scanned, get_account_kind, get_max_account_id, insert_address, memo_repr,
+    parse_pool_code, pool_code, scan_queue_extrema,
+    scanning::{replace_queue_entries, update_chain_tip},
+};
+
+/// The version of the serialized export format produced by [`WalletExport::write`].
+pub const FORMAT_VERSION: u32 = 1;
+
+const ACCOUNT_KIND_DERIVED_TAG: u8 = 0;
+const ACCOUNT_KIND_VIEW_ONLY_TAG: u8 = 1;
+
+fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+fn read_string<R: Read>(reader: R) -> io::Result<String> {
+    String::from_utf8(Vector::read(reader, |r| r.read_u8())?).map_err(invalid_data)
+}
+
+fn write_string<W: Write>(writer: W, s: &str) -> io::Result<()> {
+    Vector::write(writer, s.as_bytes(), |w, b| w.write_u8(*b))
+}
+
+fn read_bytes<R: Read>(reader: R) -> io::Result<Vec<u8>> {
+    Vector::read(reader, |r| r.read_u8())
+}
+
+fn write_bytes<W: Write>(writer: W, bytes: &[u8]) -> io::Result<()> {
+    Vector::write(writer, bytes, |w, b| w.write_u8(*b))
+}
+
+fn read_height<R: Read>(mut reader: R) -> io::Result<BlockHeight> {
+    reader.read_u32::<LittleEndian>().map(BlockHeight::from)
+}
+
+fn write_height<W: Write>(mut writer: W, height: BlockHeight) -> io::Result<()> {
+    writer.write_u32::<LittleEndian>(u32::from(height))
+}
+
+fn read_account_id<R: Read>(mut reader: R) -> io::Result<AccountId> {
+    AccountId::try_from(reader.read_u32::<LittleEndian>()?)
+        .map_err(|_| invalid_data("Account identifier out of range"))
+}
+
+fn write_account_id<W: Write>(mut writer: W, account: AccountId) -> io::Result<()> {
+    writer.write_u32::<LittleEndian>(u32::from(account))
+}
+
+fn read_amount<R: Read>(mut reader: R) -> io::Result<NonNegativeAmount> {
+    NonNegativeAmount::from_u64(reader.read_u64::<LittleEndian>()?)
+        .map_err(|_| invalid_data("Amount out of range"))
+}
+
+fn write_amount<W: Write>(mut writer: W, amount: NonNegativeAmount) -> io::Result<()> {
+    writer.write_u64::<LittleEndian>(u64::from(amount))
+}
+
+/// An account exported from the wallet database.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExportedAccount {
+    account: AccountId,
+    kind: AccountKind,
+    ufvk: String,
+    birthday_height: BlockHeight,
+    recover_until_height: Option<BlockHeight>,
+    addresses: Vec<(DiversifierIndex, String)>,
+}
+
+impl ExportedAccount {
+    /// Returns the identifier of the account within the wallet.
+    pub fn account(&self) -> AccountId {
+        self.account
+    }
+
+    /// Returns the kind of the account.
+    pub fn kind(&self) -> AccountKind {
+        self.kind
+    }
+
+    /// Returns the encoded unified full viewing key for the account.
+    pub fn ufvk(&self) -> &str {
+        &self.ufvk
+    }
+
+    /// Returns the birthday height of the account.
+    pub fn birthday_height(&self) -> BlockHeight {
+        self.birthday_height
+    }
+
+    /// Returns the height up to which the account was being recovered at the time that it was
+    /// added to the wallet, if any.
+    pub fn recover_until_height(&self) -> Option<BlockHeight> {
+        self.recover_until_height
+    }
+
+    /// Returns the encoded unified addresses that have been generated for the account, along
+    /// with the diversifier index at which each was generated.
+    pub fn addresses(&self) -> &[(DiversifierIndex, String)] {
+        &self.addresses
+    }
+
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let account = read_account_id(&mut reader)?;
+        let kind = match reader.read_u8()? {
+            ACCOUNT_KIND_DERIVED_TAG => {
+                let mut seed_fingerprint = [0; 32];
+                reader.read_exact(&mut seed_fingerprint)?;
+                let account_index = read_account_id(&mut reader)?;
+                AccountKind::Derived {
+                    seed_fingerprint: SeedFingerprint::from_bytes(seed_fingerprint),
+                    account_index,
+                }
+            }
+            ACCOUNT_KIND_VIEW_ONLY_TAG => AccountKind::ViewOnly,
+            tag => {
+                return Err(invalid_data(format!("Unknown account kind {}", tag)));
+            }
+        };
+        let ufvk = read_string(&mut reader)?;
+        let birthday_height = read_height(&mut reader)?;
+        let recover_until_height = Optional::read(&mut reader, read_height)?;
+        let addresses = Vector::read(&mut reader, |r| {
+            let mut diversifier_index = [0; 11];
+            r.read_exact(&mut diversifier_index)?;
+            let address = read_string(r)?;
+            Ok((DiversifierIndex::from(diversifier_index), address))
+        })?;
+
+        Ok(ExportedAccount {
+            account,
+            kind,
+            ufvk,
+            birthday_height,
+            recover_until_height,
+            addresses,
+        })
+    }
+
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write_account_id(&mut writer, self.account)?;
+        match &self.kind {
+            AccountKind::Derived {
+                seed_fingerprint,
+                account_index,
+            } => {
+                writer.write_u8(ACCOUNT_KIND_DERIVED_TAG)?;
+                writer.write_all(&seed_fingerprint.to_bytes())?;
+                write_account_id(&mut writer, *account_index)?;
+            }
+            AccountKind::ViewOnly => writer.write_u8(ACCOUNT_KIND_VIEW_ONLY_TAG)?,
+        }
+        write_string(&mut writer, &self.ufvk)?;
+        write_height(&mut writer, self.birthday_height)?;
+        Optional::write(&mut writer, self.recover_until_height, write_height)?;
+        Vector::write(
+            &mut writer,
+            &self.addresses,
+            |w, (diversifier_index, address)| {
+                w.write_all(diversifier_index.as_bytes())?;
+                write_string(w, address)
+            },
+        )
+    }
+}
+
+/// An output sent by the wallet, as recorded in the wallet database.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExportedSentOutput {
+    output_pool: PoolType,
+    output_index: u32,
+    from_account: AccountId,
+    to_address: Option<String>,
+    to_account: Option<AccountId>,
+    value: NonNegativeAmount,
+    memo: Option<MemoBytes>,
+}
+
+impl ExportedSentOutput {
+    /// Returns the pool to which the output was sent.
+    pub fn output_pool(&self) -> PoolType {
+        self.output_pool
+    }
+
+    /// Returns the index of the output within the bundle for [`Self::output_pool`].
+    pub fn output_index(&self) -> u32 {
+        self.output_index
+    }
+
+    /// Returns the account from which the output was sent.
+    pub fn from_account(&self) -> AccountId {
+        self.from_account
+    }
+
+    /// Returns the encoded address to which the output was sent, if the output was sent to an
+    /// address external to the wallet.
+    pub fn to_address(&self) -> Option<&str> {
+        self.to_address.as_deref()
+    }
+
+    /// Returns the account to which the output was sent, if the output was sent to one of the
+    /// wallet's own accounts.
+    pub fn to_account(&self) -> Option<AccountId> {
+        self.to_account
+    }
+
+    /// Returns the value of the output.
+    pub fn value(&self) -> NonNegativeAmount {
+        self.value
+    }
+
+    /// Returns the memo attached to the output, if any.
+    pub fn memo(&self) -> Option<&MemoBytes> {
+        self.memo.as_ref()
+    }
+
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let output_pool = parse_pool_code(i64::from(reader.read_u8()?))
+            .ok_or_else(|| invalid_data("Unknown output pool"))?;
+        let output_index = reader.read_u32::<LittleEndian>()?;
+        let from_account = read_account_id(&mut reader)?;
+        let to_address = Optional::read(&mut reader, read_string)?;
+        let to_account = Optional::read(&mut reader, read_account_id)?;
+        let value = read_amount(&mut reader)?;
+        let memo = Optional::read(&mut reader, |r| {
+            MemoBytes::from_bytes(&read_bytes(r)?).map_err(|_| invalid_data("Memo too long"))
+        })?;
+
+        Ok(ExportedSentOutput {
+            output_pool,
+            output_index,
+            from_account,
+            to_address,
+            to_account,
+            value,
+            memo,
+        })
+    }
+
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_u8(
+            u8::try_from(pool_code(self.output_pool)).expect("pool codes are single bytes"),
+        )?;
+        writer.write_u32::<LittleEndian>(self.output_index)?;
+        write_account_id(&mut writer, self.from_account)?;
+        Optional::write(&mut writer, self.to_address.as_deref(), write_string)?;
+        Optional::write(&mut writer, self.to_account, write_account_id)?;
+        write_amount(&mut writer, self.value)?;
+        Optional::write(&mut writer, self.memo.as_ref(), |w, m| {
+            write_bytes(w, m.as_slice())
+        })
+    }
+}
+
+/// A transaction known to the wallet, along with the outputs that the wallet sent in it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExportedTransaction {
+    txid: TxId,
+    created: Option<String>,
+    mined_height: Option<BlockHeight>,
+    expiry_height: Option<BlockHeight>,
+    fee: Option<NonNegativeAmount>,
+    raw: Option<Vec<u8>>,
+    sent_outputs: Vec<ExportedSentOutput>,
+}
+
+impl ExportedTransaction {
+    /// Returns the transaction's identifier.
+    pub fn txid(&self) -> TxId {
+        self.txid
+    }
+
+    /// Returns the time at which the transaction was created by the wallet, if it was created
+    /// by the wallet.
+    pub fn created(&self) -> Option<&str> {
+        self.created.as_deref()
+    }
+
+    /// Returns the height at which the transaction was mined, if known.
+    pub fn mined_height(&self) -> Option<BlockHeight> {
+        self.mined_height
+    }
+
+    /// Returns the expiry height of the transaction, if known.
+    pub fn expiry_height(&self) -> Option<BlockHeight> {
+        self.expiry_height
+    }
+
+    /// Returns the fee paid by the transaction, if known.
+    pub fn fee(&self) -> Option<NonNegativeAmount> {
+        self.fee
+    }
+
+    /// Returns the serialized transaction data, if known.
+    pub fn raw(&self) -> Option<&[u8]> {
+        self.raw.as_deref()
+    }
+
+    /// Returns the outputs of the transaction that were sent by the wallet.
+    pub fn sent_outputs(&self) -> &[ExportedSentOutput] {
+        &self.sent_outputs
+    }
+
+    fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut txid = [0; 32];
+        reader.read_exact(&mut txid)?;
+        let created = Optional::read(&mut reader, read_string)?;
+        let mined_height = Optional::read(&mut reader, read_height)?;
+        let expiry_height = Optional::read(&mut reader, read_height)?;
+        let fee = Optional::read(&mut reader, read_amount)?;
+        let raw = Optional::read(&mut reader, read_bytes)?;
+        let sent_outputs = Vector::read(&mut reader, ExportedSentOutput::read)?;
+
+        Ok(ExportedTransaction {
+            txid: TxId::from_bytes(txid),
+            created,
+            mined_height,
+            expiry_height,
+            fee,
+            raw,
+            sent_outputs,
+        })
+    }
+
+    fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(self.txid.as_ref())?;
+        Optional::write(&mut writer, self.created.as_deref(), write_string)?;
+        Optional::write(&mut writer, self.mined_height, write_height)?;
+        Optional::write(&mut writer, self.expiry_height, write_height)?;
+        Optional::write(&mut writer, self.fee, write_amount)?;
+        Optional::write(&mut writer, self.raw.as_deref(), write_bytes)?;
+        Vector::write(&mut writer, &self.sent_outputs, |w, output| output.write(w))
+    }
+}
+
+/// The contents of a wallet database, in a form that is independent of the database schema.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WalletExport {
+    accounts: Vec<ExportedAccount>,
+    transactions: Vec<ExportedTransaction>,
+    fully_scanned_height: Option<BlockHeight>,
+    chain_tip_height: Option<BlockHeight>,
+}
+
+impl WalletExport {
+    /// Returns the accounts of the exported wallet.
+    pub fn accounts(&self) -> &[ExportedAccount] {
+        &self.accounts
+    }
+
+    /// Returns the transactions of the exported wallet.
+    pub fn transactions(&self) -> &[ExportedTransaction] {
+        &self.transactions
+    }
+
+    /// Returns the height below which the exported wallet had scanned all blocks, if any.
+    pub fn fully_scanned_height(&self) -> Option<BlockHeight> {
+        self.fully_scanned_height
+    }
+
+    /// Returns the exported wallet's view of the chain tip height, if known.
+    pub fn chain_tip_height(&self) -> Option<BlockHeight> {
+        self.chain_tip_height
+    }
+
+    /// Reads a wallet export in the format written by [`WalletExport::write`].
+    ///
+    /// Returns an error of kind [`io::ErrorKind::InvalidData`] if the export was written using
+    /// a format version that is not supported by this version of the crate.
+    pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let version = reader.read_u32::<LittleEndian>()?;
+        if version != FORMAT_VERSION {
+            return Err(invalid_data(format!(
+                "Unsupported wallet export format version {}",
+                version
+            )));
+        }
+
+        let accounts = Vector::read(&mut reader, ExportedAccount::read)?;
+        let transactions = Vector::read(&mut reader, ExportedTransaction::read)?;
+        let fully_scanned_height = Optional::read(&mut reader, read_height)?;
+        let chain_tip_height = Optional::read(&mut reader, read_height)?;
+
+        Ok(WalletExport {
+            accounts,
+            transactions,
+            fully_scanned_height,
+            chain_tip_height,
+        })
+    }
+
+    /// Writes the wallet export using format version [`FORMAT_VERSION`].
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_u32::<LittleEndian>(FORMAT_VERSION)?;
+        Vector::write(&mut writer, &self.accounts, |w, account| account.write(w))?;
+        Vector::write(&mut writer, &self.transactions, |w, tx| tx.write(w))?;
+        Optional::write(&mut writer, self.fully_scanned_height, write_height)?;
+        Optional::write(&mut writer, self.chain_tip_height, write_height)
+    }
+}
+
+fn export_accounts(conn: &Connection) -> Result<Vec<ExportedAccount>, SqliteClientError> {
+    let mut stmt_accounts = conn.prepare(
+        "SELECT account, ufvk, birthday_height, recover_until_height
+        FROM accounts
+        ORDER BY account",
+    )?;
+    let mut stmt_addresses = conn.prepare(
+        "SELECT diversifier_index_be, address
+        FROM addresses
+        WHERE account = :account
+        ORDER BY diversifier_index_be",
+    )?;
+
+    let mut rows = stmt_accounts.query([])?;
+    let mut accounts = vec![];
+    while let Some(row) = rows.next()? {
+        let account = AccountId::try_from(row.get::<_, u32>(0)?)
+            .map_err(|_| SqliteClientError::AccountIdOutOfRange)?;
+        let kind =
+            get_account_kind(conn, account)?.ok_or(SqliteClientError::AccountUnknown(account))?;
+
+        let addresses = stmt_addresses
+            .query_and_then(
+                named_params![":account": u32::from(account)],
+                |row| -> Result<_, SqliteClientError> {
+                    let mut di_be: [u8; 11] =
+                        row.get::<_, Vec<u8>>(0)?.try_into().map_err(|_| {
+                            SqliteClientError::CorruptedData(
+                                "Diversifier index is not an 11-byte value".to_owned(),
+                            )
+                        })?;
+                    di_be.reverse();
+                    Ok((DiversifierIndex::from(di_be), row.get(1)?))
+                },
+            )?
+            .collect::<Result<_, _>>()?;
+
+        accounts.push(ExportedAccount {
+            account,
+            kind,
+            ufvk: row.get(1)?,
+            birthday_height: BlockHeight::from(row.get::<_, u32>(2)?),
+            recover_until_height: row.get::<_, Option<u32>>(3)?.map(BlockHeight::from),
+            addresses,
+        });
+    }
+
+    Ok(accounts)
+}
+
+fn export_transactions(conn: &Connection) -> Result<Vec<ExportedTransaction>, SqliteClientError> {
+    let mut stmt_txs = conn.prepare(
+        "SELECT id_tx, txid, created, block, expiry_height, fee, raw
+        FROM transactions
+        ORDER BY id_tx",
+    )?;
+    let mut stmt_sent_outputs = conn.prepare(
+        "SELECT output_pool, output_index, from_account, to_address, to_account, value, memo
+        FROM sent_notes
+        WHERE tx = :tx
+        ORDER BY output_pool, output_index",
+    )?;
+
+    let mut rows = stmt_txs.query([])?;
+    let mut transactions = vec![];
+    while let Some(row) = rows.next()? {
+        let id_tx: i64 = row.get(0)?;
+        let fee = row
+            .get::<_, Option<i64>>(5)?
+            .map(|fee| {
+                u64::try_from(fee)
+                    .ok()
+                    .and_then(|fee| NonNegativeAmount::from_u64(fee).ok())
+                    .ok_or_else(|| {
+                        SqliteClientError::CorruptedData(format!("Invalid fee value {}", fee))
+                    })
+            })
+            .transpose()?;
+
+        let sent_outputs = stmt_sent_outputs
+            .query_and_then(
+                named_params![":tx": id_tx],
+                |row| -> Result<_, SqliteClientError> {
+                    let output_pool = row.get::<_, i64>(0)?;
+                    let value = row.get::<_, i64>(5)?;
+                    Ok(ExportedSentOutput {
+                        output_pool: parse_pool_code(output_pool).ok_or_else(|| {
+                            SqliteClientError::CorruptedData(format!(
+                                "Invalid output pool code {}",
+                                output_pool
+                            ))
+                        })?,
+                        output_index: row.get(1)?,
+                        from_account: AccountId::try_from(row.get::<_, u32>(2)?)
+                            .map_err(|_| SqliteClientError::AccountIdOutOfRange)?,
+                        to_address: row.get(3)?,
+                        to_account: row
+                            .get::<_, Option<u32>>(4)?
+                            .map(AccountId::try_from)
+                            .transpose()
+                            .map_err(|_| SqliteClientError::AccountIdOutOfRange)?,
+                        value: u64::try_from(value)
+                            .ok()
+                            .and_then(|v| NonNegativeAmount::from_u64(v).ok())
+                            .ok_or_else(|| {
+                                SqliteClientError::CorruptedData(format!(
+                                    "Invalid sent output value {}",
+                                    value
+                                ))
+                            })?,
+                        memo: row
+                            .get::<_, Option<Vec<u8>>>(6)?
+                            .map(|m| MemoBytes::from_bytes(&m))
+                            .transpose()?,
+                    })
+                },
+            )?
+            .collect::<Result<_, _>>()?;
+
+        transactions.push(ExportedTransaction {
+            txid: TxId::from_bytes(row.get(1)?),
+            created: row.get(2)?,
+            mined_height: row.get::<_, Option<u32>>(3)?.map(BlockHeight::from),
+            expiry_height: row.get::<_, Option<u32>>(4)?.map(BlockHeight::from),
+            fee,
+            raw: row.get(6)?,
+            sent_outputs,
+        });
+    }
+
+    Ok(transactions)
+}
+
+/// Exports the contents of the given wallet database.
+///
+/// See the [module documentation](self) for a description of the data that is exported.
+pub fn export_wallet<C: Borrow<Connection>, P: consensus::Parameters>(
+    wdb: &WalletDb<C, P>,
+) -> Result<WalletExport, SqliteClientError> {
+    let conn = wdb.conn.borrow();
+
+    Ok(WalletExport {
+        accounts: export_accounts(conn)?,
+        transactions: export_transactions(conn)?,
+        fully_scanned_height: block_fully_scanned(conn, &wdb.params)?.map(|m| m.block_height()),
+        chain_tip_height: scan_queue_extrema(conn)?.map(|range| *range.end()),
+    })
+}
+
+/// Imports the given wallet export into an empty wallet database.
+///
+/// The wallet database must have been initialized with [`init_wallet_db`], and must not contain
+/// any accounts; [`SqliteClientError::TableNotEmpty`] is returned otherwise.
+///
+/// The export does not contain the note commitment tree state at each account's birthday, so
+/// the caller must provide a `birthday` function that constructs the [`AccountBirthday`] for
+/// each exported account (typically by obtaining the tree state at
+/// [`ExportedAccount::birthday_height`] from a light wallet server).
+///
+/// Transactions are imported without their mined heights; these, along with the wallet's
+/// received notes, are restored by scanning the chain. If the export includes a chain tip
+/// height, the blocks between each account's birthday and that height are queued for scanning,
+/// and the blocks containing the exported transactions are given [`ScanPriority::FoundNote`]
+/// priority so that they are scanned first.
+///
+/// [`init_wallet_db`]: crate::wallet::init::init_wallet_db
+pub fn import_wallet<P, F>(
+    wdb: &mut WalletDb<Connection, P>,
+    export: &WalletExport,
+    mut birthday: F,
+) -> Result<(), SqliteClientError>
+where
+    P: consensus::Parameters,
+    F: FnMut(&ExportedAccount) -> AccountBirthday,
+{
+    wdb.transactionally(|wdb| {
+        if get_max_account_id(wdb.conn.0)?.is_some() {
+            return Err(SqliteClientError::TableNotEmpty);
+        }
+
+        for account in &export.accounts {
+            let ufvk = UnifiedFullViewingKey::decode(&wdb.params, &account.ufvk)
+                .map_err(SqliteClientError::CorruptedData)?;
+            super::add_account(
+                wdb.conn.0,
+                &wdb.params,
+                account.account,
+                &ufvk,
+                account.kind,
+                birthday(account),
+            )?;
+
+            // `add_account` always inserts the account's default address.
+            let (_, default_index) = ufvk.default_address(DEFAULT_UA_REQUEST);
+            for (diversifier_index, address) in &account.addresses {
+                if *diversifier_index == default_index {
+                    continue;
+                }
+                match Address::decode(&wdb.params, address) {
+                    Some(Address::Unified(ua)) => insert_address(
+                        wdb.conn.0,
+                        &wdb.params,
+                        account.account,
+                        *diversifier_index,
+                        &ua,
+                    )?,
+                    _ => {
+                        return Err(SqliteClientError::CorruptedData(format!(
+                            "{} is not a valid unified address",
+                            address
+                        )));
+                    }
+                }
+            }
+        }
+
+        let mut stmt_insert_tx = wdb.conn.0.prepare(
+            "INSERT INTO transactions (txid, created, expiry_height, raw, fee)
+            VALUES (:txid, :created, :expiry_height, :raw, :fee)
+            RETURNING id_tx",
+        )?;
+        let mut stmt_insert_sent_output = wdb.conn.0.prepare(
+            "INSERT INTO sent_notes (
+                tx, output_pool, output_index, from_account,
+                to_address, to_account, value, memo)
+            VALUES (
+                :tx, :output_pool, :output_index, :from_account,
+                :to_address, :to_account, :value, :memo)",
+        )?;
+
+        for tx in &export.transactions {
+            // The mined height of the transaction cannot be restored until the block that
+            // contains it has been scanned, because of the foreign key constraint on the
+            // `blocks` table.
+            let id_tx = stmt_insert_tx.query_row(
+                named_params![
+                    ":txid": tx.txid.as_ref(),
+                    ":created": tx.created,
+                    ":expiry_height": tx.expiry_height.map(u32::from),
+                    ":raw": tx.raw,
+                    ":fee": tx.fee.map(|fee| i64::from(Amount::from(fee))),
+                ],
+                |row| row.get::<_, i64>(0),
+            )?;
+
+            for output in &tx.sent_outputs {
+                stmt_insert_sent_output.execute(named_params![
+                    ":tx": id_tx,
+                    ":output_pool": pool_code(output.output_pool),
+                    ":output_index": output.output_index,
+                    ":from_account": u32::from(output.from_account),
+                    ":to_address": output.to_address,
+                    ":to_account": output.to_account.map(u32::from),
+                    ":value": i64::from(Amount::from(output.value)),
+                    ":memo": memo_repr(output.memo.as_ref()),
+                ])?;
+            }
+        }
+
+        if let Some(chain_tip_height) = export.chain_tip_height {
+            update_chain_tip(wdb.conn.0, &wdb.params, chain_tip_height)?;
+
+            for mined_height in export
+                .transactions
+                .iter()
+                .filter_map(|tx| tx.mined_height)
+                .filter(|h| *h <= chain_tip_height)
+            {
+                let range = mined_height..(mined_height + 1);
+                replace_queue_entries::<SqliteClientError>(
+                    wdb.conn.0,
+                    &range,
+                    Some(ScanRange::from_parts(
+                        range.clone(),
+                        ScanPriority::FoundNote,
+                    ))
+                    .into_iter(),
+                    false,
+                )?;
+            }
+        }
+
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use sapling::zip32::ExtendedSpendingKey;
+    use tempfile::NamedTempFile;
+    use zcash_client_backend::{
+        address::Address,
+        data_api::{
+            scanning::ScanPriority,
+            testing::conformance::{AddressType, TestBuilder},
+            AccountBirthday, WalletRead,
+        },
+        wallet::{NoteId, OvkPolicy},
+        ShieldedProtocol,
+    };
+    use zcash_primitives::{
+        memo::{Memo, MemoBytes},
+        transaction::components::amount::NonNegativeAmount,
+    };
+
+    use super::{export_wallet, import_wallet, WalletExport, FORMAT_VERSION};
+    use crate::{
+        error::SqliteClientError,
+        testing::{BlockCache, TestDbFactory},
+        wallet::init::init_wallet_db,
+        WalletDb,
+    };
+
+    #[test]
+    fn export_import_roundtrip() {
+        let mut st = TestBuilder::new()
+            .with_block_cache(BlockCache::new())
+            .with_data_store_factory(TestDbFactory)
+            .with_test_account(AccountBirthday::from_sapling_activation)
+            .build();
+
+        let (account, usk, _) = st.test_account().unwrap();
+        let dfvk = st.test_account_sapling().unwrap();
+
+        // Add funds to the wallet in a single note
+        let value = NonNegativeAmount::const_from_u64(60000);
+        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.scan_cached_blocks(h, 1);
+
+        // Send some of the funds to an external address with a memo
+        let to: Address = ExtendedSpendingKey::master(&[]).default_address().1.into();
+        let memo = "Outgoing memo".parse::<Memo>().unwrap();
+        let txid = st
+            .create_spend_to_address(
+                &usk,
+                &to,
+                NonNegativeAmount::const_from_u64(10000),
+                Some(MemoBytes::from(&memo)),
+                OvkPolicy::Sender,
+                NonZeroU32::new(1).unwrap(),
+                None,
+            )
+            .unwrap()[0];
+        let (h, _) = st.generate_next_block_including(txid);
+        st.scan_cached_blocks(h, 1);
+
+        // The export survives serialization.
+        let export = export_wallet(st.wallet()).unwrap();
+        assert_eq!(export.accounts().len(), 1);
+        assert_eq!(export.chain_tip_height(), Some(h));
+        let network = st.network();
+        let sent_output = export
+            .transactions()
+            .iter()
+            .filter(|tx| tx.txid() == txid)
+            .flat_map(|tx| tx.sent_outputs())
+            .find(|output| output.to_address() == Some(to.encode(&network).as_str()))
+            .unwrap();
+        assert_eq!(sent_output.memo(), Some(&MemoBytes::from(&memo)));
+        let sent_note_id = NoteId::new(
+            txid,
+            ShieldedProtocol::Sapling,
+            sent_output.output_index().try_into().unwrap(),
+        );
+        let mut buf = vec![];
+        export.write(&mut buf).unwrap();
+        assert_eq!(WalletExport::read(&buf[..]).unwrap(), export);
+
+        // Import the export into a fresh wallet database.
+        let data_file = NamedTempFile::new().unwrap();
+        let mut db_data = WalletDb::for_path(data_file.path(), network).unwrap();
+        init_wallet_db(&mut db_data, None).unwrap();
+        import_wallet(&mut db_data, &export, |_| {
+            AccountBirthday::from_sapling_activation(&network)
+        })
+        .unwrap();
+
+        // The account and the memo of the sent output have been restored.
+        let ufvk = usk.to_unified_full_viewing_key();
+        assert_eq!(db_data.get_account_for_ufvk(&ufvk).unwrap(), Some(account));
+        assert_eq!(db_data.get_memo(sent_note_id).unwrap(), Some(memo));
+
+        // The block containing the sent transaction is queued for scanning ahead of the rest of
+        // the wallet's history.
+        let scan_ranges = db_data.suggest_scan_ranges().unwrap();
+        assert_eq!(scan_ranges[0].priority(), ScanPriority::FoundNote);
+        assert!(scan_ranges[0].block_range().contains(&h));
+        assert!(scan_ranges
+            .iter()
+            .any(|range| range.priority() == ScanPriority::Historic));
+
+        // Importing into a non-empty wallet fails.
+        assert_matches!(
+            import_wallet(&mut db_data, &export, |_| {
+                AccountBirthday::from_sapling_activation(&network)
+            }),
+            Err(SqliteClientError::TableNotEmpty)
+        );
+    }
+
+    #[test]
+    fn unsupported_format_version() {
+        let export = WalletExport {
+            accounts: vec![],
+            transactions: vec![],
+            fully_scanned_height: None,
+            chain_tip_height: None,
+        };
+        let mut buf = vec![];
+        export.write(&mut buf).unwrap();
+        assert_eq!(WalletExport::read(&buf[..]).unwrap(), export);
+
+        buf[..4].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            WalletExport::read(&buf[..]).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+    }
+}
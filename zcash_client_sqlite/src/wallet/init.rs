@@ -1,5 +1,6 @@
 //! Functions for initializing the various databases.
 
+use std::collections::HashSet;
 use std::fmt;
 
 use rusqlite::{self};
@@ -20,6 +21,8 @@ use super::commitment_tree::{self};
 
 mod migrations;
 
+const MIGRATIONS_TABLE: &str = "schemer_migrations";
+
 #[derive(Debug)]
 pub enum WalletMigrationError {
     /// The seed is required for the migration.
@@ -36,6 +39,11 @@ pub enum WalletMigrationError {
 
     /// Wrapper for commitment tree invariant violations
     CommitmentTree(ShardTreeError<commitment_tree::Error>),
+
+    /// Reverting the specified migration is not supported, either because the migration has no
+    /// down-migration, or because the wallet contains data that cannot be represented without
+    /// the changes made by the migration.
+    CannotRevert(Uuid),
 }
 
 impl From<rusqlite::Error> for WalletMigrationError {
@@ -71,6 +79,9 @@ impl fmt::Display for WalletMigrationError {
             WalletMigrationError::DbError(e) => write!(f, "{}", e),
             WalletMigrationError::BalanceError(e) => write!(f, "Balance error: {:?}", e),
             WalletMigrationError::CommitmentTree(e) => write!(f, "Commitment tree error: {:?}", e),
+            WalletMigrationError::CannotRevert(uuid) => {
+                write!(f, "Reverting migration {} is not supported", uuid)
+            }
         }
     }
 }
@@ -90,7 +101,9 @@ impl std::error::Error for WalletMigrationError {
 /// the database structure required by the current version of this library, and should be invoked
 /// at least once any time a client program upgrades to a new version of this library.  The
 /// operation of this procedure is idempotent, so it is safe (though not required) to invoke this
-/// operation every time the wallet is opened.
+/// operation every time the wallet is opened. [`plan_wallet_db_migrations`] may be used to
+/// determine which migrations will be applied, and whether each of them can be reverted, before
+/// invoking this procedure.
 ///
 /// It is safe to use a wallet database previously created without the ability to create
 /// transparent spends with a build that enables transparent spends (via use of the
@@ -139,13 +152,15 @@ fn init_wallet_db_internal<P: consensus::Parameters + 'static>(
              PRAGMA legacy_alter_table = TRUE;",
         )
         .map_err(|e| MigratorError::Adapter(WalletMigrationError::from(e)))?;
-    let adapter = RusqliteAdapter::new(&mut wdb.conn, Some("schemer_migrations".to_string()));
-    adapter.init().expect("Migrations table setup succeeds.");
+    let adapter = RusqliteAdapter::new(&mut wdb.conn, Some(MIGRATIONS_TABLE.to_string()));
+    adapter
+        .init()
+        .map_err(|e| MigratorError::Adapter(WalletMigrationError::from(e)))?;
 
     let mut migrator = Migrator::new(adapter);
     migrator
         .register_multiple(migrations::all_migrations(&wdb.params, seed))
-        .expect("Wallet migration registration should have been successful.");
+        .map_err(MigratorError::Dependency)?;
     if target_migrations.is_empty() {
         migrator.up(None)?;
     } else {
@@ -159,6 +174,81 @@ fn init_wallet_db_internal<P: consensus::Parameters + 'static>(
     Ok(())
 }
 
+/// A migration that would be applied by [`init_wallet_db`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlannedMigration {
+    id: Uuid,
+    description: &'static str,
+    reversible: bool,
+}
+
+impl PlannedMigration {
+    /// Returns the unique identifier of the migration.
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// Returns a human-readable description of the migration.
+    pub fn description(&self) -> &'static str {
+        self.description
+    }
+
+    /// Returns whether the migration provides a down-migration.
+    ///
+    /// A migration that is not reversible can only be undone by restoring a backup of the wallet
+    /// database taken before the migration was applied. Reverting a reversible migration may
+    /// still fail with [`WalletMigrationError::CannotRevert`] if the wallet contains data that
+    /// cannot be represented without the changes made by the migration.
+    pub fn is_reversible(&self) -> bool {
+        self.reversible
+    }
+}
+
+/// Performs a dry run of [`init_wallet_db`], returning the migrations that it would apply to the
+/// wallet database, in the order in which they would be applied.
+///
+/// The wallet database is not modified. Callers may use this to warn users before upgrading a
+/// wallet database to a version of the schema that cannot be downgraded.
+pub fn plan_wallet_db_migrations<P: consensus::Parameters + 'static>(
+    wdb: &WalletDb<rusqlite::Connection, P>,
+) -> Result<Vec<PlannedMigration>, WalletMigrationError> {
+    let migrations_table_exists = wdb.conn.query_row(
+        "SELECT EXISTS (
+            SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = :table_name
+        )",
+        rusqlite::named_params![":table_name": MIGRATIONS_TABLE],
+        |row| row.get::<_, bool>(0),
+    )?;
+
+    let applied = if migrations_table_exists {
+        let mut stmt = wdb
+            .conn
+            .prepare(&format!("SELECT id FROM {}", MIGRATIONS_TABLE))?;
+        let applied = stmt
+            .query_map([], |row| row.get::<_, Vec<u8>>(0))?
+            .map(|id| {
+                Uuid::from_slice(&id?).map_err(|_| {
+                    WalletMigrationError::CorruptedData("Invalid migration identifier".to_owned())
+                })
+            })
+            .collect::<Result<HashSet<_>, _>>()?;
+        applied
+    } else {
+        HashSet::new()
+    };
+
+    // `all_migrations` lists each migration after all of its dependencies.
+    Ok(migrations::all_migrations(&wdb.params, None)
+        .into_iter()
+        .filter(|m| !applied.contains(&m.id()))
+        .map(|m| PlannedMigration {
+            id: m.id(),
+            description: m.description(),
+            reversible: migrations::is_reversible(m.id()),
+        })
+        .collect())
+}
+
 #[cfg(test)]
 #[allow(deprecated)]
 mod tests {
@@ -169,7 +259,7 @@ mod tests {
 
     use zcash_client_backend::{
         address::Address,
-        data_api::scanning::ScanPriority,
+        data_api::{scanning::ScanPriority, testing::conformance::TestBuilder},
         encoding::{encode_extended_full_viewing_key, encode_payment_address},
         keys::{sapling, UnifiedFullViewingKey, UnifiedSpendingKey},
     };
@@ -182,10 +272,13 @@ mod tests {
     };
 
     use crate::{
-        testing::TestBuilder, wallet::scanning::priority_code, WalletDb, DEFAULT_UA_REQUEST,
+        testing::TestDbFactory, wallet::scanning::priority_code, WalletDb, DEFAULT_UA_REQUEST,
     };
 
-    use super::init_wallet_db;
+    use super::{
+        init_wallet_db, init_wallet_db_internal, migrations, plan_wallet_db_migrations,
+        WalletMigrationError,
+    };
 
     #[cfg(feature = "transparent-inputs")]
     use {
@@ -197,7 +290,9 @@ mod tests {
 
     #[test]
     fn verify_schema() {
-        let st = TestBuilder::new().build();
+        let st = TestBuilder::new()
+            .with_data_store_factory(TestDbFactory)
+            .build();
 
         use regex::Regex;
         let re = Regex::new(r"\s+").unwrap();
@@ -205,9 +300,19 @@ mod tests {
         let expected_tables = vec![
             "CREATE TABLE \"accounts\" (
                 account INTEGER PRIMARY KEY,
+                account_kind INTEGER NOT NULL DEFAULT 0,
+                hd_seed_fingerprint BLOB,
+                hd_account_index INTEGER,
                 ufvk TEXT NOT NULL,
                 birthday_height INTEGER NOT NULL,
-                recover_until_height INTEGER )",
+                recover_until_height INTEGER,
+                CHECK (
+                    (account_kind = 0 AND hd_seed_fingerprint IS NOT NULL AND hd_account_index IS NOT NULL)
+                    OR
+                    (account_kind = 1 AND hd_seed_fingerprint IS NULL AND hd_account_index IS NULL)
+                ),
+                CONSTRAINT hd_account UNIQUE (hd_seed_fingerprint, hd_account_index)
+            )",
             "CREATE TABLE addresses (
                 account INTEGER NOT NULL,
                 diversifier_index_be BLOB NOT NULL,
@@ -237,6 +342,51 @@ mod tests {
                     ON UPDATE RESTRICT,
                 CONSTRAINT nf_uniq UNIQUE (spend_pool, nf)
             )",
+            "CREATE TABLE orchard_received_notes (
+                id_note INTEGER PRIMARY KEY,
+                tx INTEGER NOT NULL,
+                action_index INTEGER NOT NULL,
+                account INTEGER NOT NULL,
+                diversifier BLOB NOT NULL,
+                value INTEGER NOT NULL,
+                rho BLOB NOT NULL,
+                rseed BLOB NOT NULL,
+                nf BLOB UNIQUE,
+                is_change INTEGER NOT NULL,
+                memo BLOB,
+                spent INTEGER,
+                commitment_tree_position INTEGER,
+                recipient_key_scope INTEGER NOT NULL,
+                FOREIGN KEY (tx) REFERENCES transactions(id_tx),
+                FOREIGN KEY (account) REFERENCES accounts(account),
+                FOREIGN KEY (spent) REFERENCES transactions(id_tx),
+                CONSTRAINT tx_output UNIQUE (tx, action_index)
+            )",
+            "CREATE TABLE orchard_tree_cap (
+                -- cap_id exists only to be able to take advantage of `ON CONFLICT`
+                -- upsert functionality; the table will only ever contain one row
+                cap_id INTEGER PRIMARY KEY,
+                cap_data BLOB NOT NULL
+            )",
+            "CREATE TABLE orchard_tree_checkpoint_marks_removed (
+                checkpoint_id INTEGER NOT NULL,
+                mark_removed_position INTEGER NOT NULL,
+                FOREIGN KEY (checkpoint_id) REFERENCES orchard_tree_checkpoints(checkpoint_id)
+                ON DELETE CASCADE,
+                CONSTRAINT spend_position_unique UNIQUE (checkpoint_id, mark_removed_position)
+            )",
+            "CREATE TABLE orchard_tree_checkpoints (
+                checkpoint_id INTEGER PRIMARY KEY,
+                position INTEGER
+            )",
+            "CREATE TABLE orchard_tree_shards (
+                shard_index INTEGER PRIMARY KEY,
+                subtree_end_height INTEGER,
+                root_hash BLOB,
+                shard_data BLOB,
+                contains_marked INTEGER,
+                CONSTRAINT root_unique UNIQUE (root_hash)
+            )",
             "CREATE TABLE sapling_received_notes (
                 id_note INTEGER PRIMARY KEY,
                 tx INTEGER NOT NULL,
@@ -371,6 +521,70 @@ mod tests {
         }
 
         let expected_views = vec![
+            // v_orchard_shard_scan_ranges
+            format!(
+                "CREATE VIEW v_orchard_shard_scan_ranges AS
+                SELECT
+                    shard.shard_index,
+                    shard.shard_index << 16 AS start_position,
+                    (shard.shard_index + 1) << 16 AS end_position_exclusive,
+                    IFNULL(prev_shard.subtree_end_height, {}) AS subtree_start_height,
+                    shard.subtree_end_height,
+                    shard.contains_marked,
+                    scan_queue.block_range_start,
+                    scan_queue.block_range_end,
+                    scan_queue.priority
+                FROM orchard_tree_shards shard
+                LEFT OUTER JOIN orchard_tree_shards prev_shard
+                    ON shard.shard_index = prev_shard.shard_index + 1
+                -- Join with scan ranges that overlap with the subtree's involved blocks.
+                INNER JOIN scan_queue ON (
+                    subtree_start_height < scan_queue.block_range_end AND
+                    (
+                        scan_queue.block_range_start <= shard.subtree_end_height OR
+                        shard.subtree_end_height IS NULL
+                    )
+                )",
+                u32::from(st.network().activation_height(NetworkUpgrade::Nu5).unwrap()),
+            ),
+            // v_orchard_shard_unscanned_ranges
+            format!(
+                "CREATE VIEW v_orchard_shard_unscanned_ranges AS
+                WITH wallet_birthday AS (SELECT MIN(birthday_height) AS height FROM accounts)
+                SELECT
+                    shard_index,
+                    start_position,
+                    end_position_exclusive,
+                    subtree_start_height,
+                    subtree_end_height,
+                    contains_marked,
+                    block_range_start,
+                    block_range_end,
+                    priority
+                FROM v_orchard_shard_scan_ranges
+                INNER JOIN wallet_birthday
+                WHERE priority > {}
+                AND block_range_end > wallet_birthday.height",
+                priority_code(&ScanPriority::Scanned)
+            ),
+            // v_orchard_shards_scan_state
+            "CREATE VIEW v_orchard_shards_scan_state AS
+            SELECT
+                shard_index,
+                start_position,
+                end_position_exclusive,
+                subtree_start_height,
+                subtree_end_height,
+                contains_marked,
+                MAX(priority) AS max_priority
+            FROM v_orchard_shard_scan_ranges
+            GROUP BY
+                shard_index,
+                start_position,
+                end_position_exclusive,
+                subtree_start_height,
+                subtree_end_height,
+                contains_marked".to_owned(),
             // v_sapling_shard_scan_ranges
             format!(
                 "CREATE VIEW v_sapling_shard_scan_ranges AS
@@ -462,6 +676,29 @@ mod tests {
                 JOIN transactions
                      ON transactions.id_tx = sapling_received_notes.tx
                 UNION
+                SELECT orchard_received_notes.id_note        AS id,
+                       orchard_received_notes.account        AS account_id,
+                       transactions.block                    AS block,
+                       transactions.txid                     AS txid,
+                       3                                     AS pool,
+                       orchard_received_notes.value          AS value,
+                       CASE
+                            WHEN orchard_received_notes.is_change THEN 1
+                            ELSE 0
+                       END AS is_change,
+                       CASE
+                            WHEN orchard_received_notes.is_change THEN 0
+                            ELSE 1
+                       END AS received_count,
+                       CASE
+                         WHEN (orchard_received_notes.memo IS NULL OR orchard_received_notes.memo = X'F6')
+                           THEN 0
+                         ELSE 1
+                       END AS memo_present
+                FROM orchard_received_notes
+                JOIN transactions
+                     ON transactions.id_tx = orchard_received_notes.tx
+                UNION
                 SELECT utxos.id_utxo                 AS id,
                        utxos.received_by_account     AS account_id,
                        utxos.height                  AS block,
@@ -486,6 +723,19 @@ mod tests {
                 JOIN transactions
                      ON transactions.id_tx = sapling_received_notes.spent
                 UNION
+                SELECT orchard_received_notes.id_note        AS id,
+                       orchard_received_notes.account        AS account_id,
+                       transactions.block                    AS block,
+                       transactions.txid                     AS txid,
+                       3                                     AS pool,
+                       -orchard_received_notes.value         AS value,
+                       0                             AS is_change,
+                       0                             AS received_count,
+                       0                             AS memo_present
+                FROM orchard_received_notes
+                JOIN transactions
+                     ON transactions.id_tx = orchard_received_notes.spent
+                UNION
                 SELECT utxos.id_utxo                 AS id,
                        utxos.received_by_account     AS account_id,
                        transactions.block            AS block,
@@ -505,7 +755,12 @@ mod tests {
                        COUNT(DISTINCT sent_notes.id_note) as sent_notes,
                        SUM(
                          CASE
-                           WHEN (sent_notes.memo IS NULL OR sent_notes.memo = X'F6' OR sapling_received_notes.tx IS NOT NULL)
+                           WHEN (
+                             sent_notes.memo IS NULL
+                             OR sent_notes.memo = X'F6'
+                             OR sapling_received_notes.tx IS NOT NULL
+                             OR orchard_received_notes.tx IS NOT NULL
+                           )
                              THEN 0
                            ELSE 1
                          END
@@ -516,7 +771,10 @@ mod tests {
                 LEFT JOIN sapling_received_notes
                           ON (sent_notes.tx, sent_notes.output_pool, sent_notes.output_index) =
                              (sapling_received_notes.tx, 2, sapling_received_notes.output_index)
-                WHERE COALESCE(sapling_received_notes.is_change, 0) = 0
+                LEFT JOIN orchard_received_notes
+                          ON (sent_notes.tx, sent_notes.output_pool, sent_notes.output_index) =
+                             (orchard_received_notes.tx, 3, orchard_received_notes.action_index)
+                WHERE COALESCE(sapling_received_notes.is_change, orchard_received_notes.is_change, 0) = 0
                 GROUP BY account_id, txid
             ),
             blocks_max_height AS (
@@ -564,7 +822,23 @@ mod tests {
                  ON transactions.id_tx = sapling_received_notes.tx
             LEFT JOIN sent_notes
                       ON (sent_notes.tx, sent_notes.output_pool, sent_notes.output_index) =
-                         (sapling_received_notes.tx, 2, sent_notes.output_index)
+                         (sapling_received_notes.tx, 2, sapling_received_notes.output_index)
+            UNION
+            SELECT transactions.txid                   AS txid,
+                   3                                   AS output_pool,
+                   orchard_received_notes.action_index AS output_index,
+                   sent_notes.from_account             AS from_account,
+                   orchard_received_notes.account      AS to_account,
+                   NULL                                AS to_address,
+                   orchard_received_notes.value        AS value,
+                   orchard_received_notes.is_change    AS is_change,
+                   orchard_received_notes.memo         AS memo
+            FROM orchard_received_notes
+            JOIN transactions
+                 ON transactions.id_tx = orchard_received_notes.tx
+            LEFT JOIN sent_notes
+                      ON (sent_notes.tx, sent_notes.output_pool, sent_notes.output_index) =
+                         (orchard_received_notes.tx, 3, orchard_received_notes.action_index)
             UNION
             SELECT utxos.prevout_txid          AS txid,
                    0                           AS output_pool,
@@ -581,7 +855,10 @@ mod tests {
                    sent_notes.output_pool         AS output_pool,
                    sent_notes.output_index        AS output_index,
                    sent_notes.from_account        AS from_account,
-                   sapling_received_notes.account AS to_account,
+                   COALESCE(
+                       sapling_received_notes.account,
+                       orchard_received_notes.account
+                   )                              AS to_account,
                    sent_notes.to_address          AS to_address,
                    sent_notes.value               AS value,
                    0                              AS is_change,
@@ -592,7 +869,10 @@ mod tests {
             LEFT JOIN sapling_received_notes
                       ON (sent_notes.tx, sent_notes.output_pool, sent_notes.output_index) =
                          (sapling_received_notes.tx, 2, sapling_received_notes.output_index)
-            WHERE COALESCE(sapling_received_notes.is_change, 0) = 0".to_owned(),
+            LEFT JOIN orchard_received_notes
+                      ON (sent_notes.tx, sent_notes.output_pool, sent_notes.output_index) =
+                         (orchard_received_notes.tx, 3, orchard_received_notes.action_index)
+            WHERE COALESCE(sapling_received_notes.is_change, orchard_received_notes.is_change, 0) = 0".to_owned(),
         ];
 
         let mut views_query = st
@@ -1066,6 +1346,54 @@ mod tests {
         );
     }
 
+    #[test]
+    fn plan_migrations() {
+        let data_file = NamedTempFile::new().unwrap();
+        let mut db_data = WalletDb::for_path(data_file.path(), Network::TestNetwork).unwrap();
+
+        // All migrations are pending for a new database, and planning them does not modify it.
+        let plan = plan_wallet_db_migrations(&db_data).unwrap();
+        assert_eq!(
+            plan.len(),
+            migrations::all_migrations(&db_data.params, None).len()
+        );
+        assert!(!plan[0].is_reversible());
+        assert_eq!(plan_wallet_db_migrations(&db_data).unwrap(), plan);
+
+        // Only the migrations that have not yet been applied are planned. No migration depends
+        // on the last migration, so all of the others may be applied without it.
+        let targets = plan[..plan.len() - 1]
+            .iter()
+            .map(|m| m.id())
+            .collect::<Vec<_>>();
+        init_wallet_db_internal(&mut db_data, None, &targets).unwrap();
+        assert_eq!(
+            plan_wallet_db_migrations(&db_data).unwrap(),
+            vec![plan[plan.len() - 1].clone()]
+        );
+
+        init_wallet_db(&mut db_data, None).unwrap();
+        assert_eq!(plan_wallet_db_migrations(&db_data).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn irreversible_migrations_cannot_be_reverted() {
+        let data_file = NamedTempFile::new().unwrap();
+        let mut db_data = WalletDb::for_path(data_file.path(), Network::TestNetwork).unwrap();
+        init_wallet_db(&mut db_data, None).unwrap();
+
+        for migration in migrations::all_migrations(&db_data.params, None) {
+            if migrations::is_reversible(migration.id()) {
+                continue;
+            }
+            let transaction = db_data.conn.transaction().unwrap();
+            assert_matches!(
+                migration.down(&transaction),
+                Err(WalletMigrationError::CannotRevert(id)) if id == migration.id()
+            );
+        }
+    }
+
     #[test]
     #[cfg(feature = "transparent-inputs")]
     fn account_produces_expected_ua_sequence() {
@@ -3,7 +3,10 @@ mod add_transaction_views;
 mod add_utxo_account;
 mod addresses_table;
 mod initial_setup;
+mod multi_seed_accounts;
 mod nullifier_map;
+mod orchard_received_notes;
+mod orchard_shardtree;
 mod received_notes_nullable_nf;
 mod receiving_key_scopes;
 mod sapling_memo_consistency;
@@ -14,13 +17,18 @@ mod utxos_table;
 mod v_sapling_shard_unscanned_ranges;
 mod v_transactions_net;
 mod v_transactions_note_uniqueness;
+mod v_transactions_orchard;
 mod v_transactions_shielding_balance;
 mod v_transactions_transparent_history;
 mod v_tx_outputs_use_legacy_false;
+mod view_only_accounts;
 mod wallet_summaries;
 
+use std::rc::Rc;
+
 use schemer_rusqlite::RusqliteMigration;
 use secrecy::SecretVec;
+use uuid::Uuid;
 use zcash_primitives::consensus;
 
 use super::WalletMigrationError;
@@ -29,6 +37,8 @@ pub(super) fn all_migrations<P: consensus::Parameters + 'static>(
     params: &P,
     seed: Option<SecretVec<u8>>,
 ) -> Vec<Box<dyn RusqliteMigration<Error = WalletMigrationError>>> {
+    let seed = seed.map(Rc::new);
+
     //                         initial_setup
     //                         /           \
     //                utxos_table         ufvk_support
@@ -46,18 +56,26 @@ pub(super) fn all_migrations<P: consensus::Parameters + 'static>(
     //                 shardtree_support      nullifier_map       sapling_memo_consistency
     //                  /              \                                      |
     //      add_account_birthdays   receiving_key_scopes      v_transactions_transparent_history
-    //                  |                                                     |
-    // v_sapling_shard_unscanned_ranges                         v_tx_outputs_use_legacy_false
-    //                  |                                                     |
-    //        wallet_summaries                                 v_transactions_shielding_balance
-    //                                                                        |
-    //                                                          v_transactions_note_uniqueness
+    //                  |                   |                                 |
+    // v_sapling_shard_unscanned_ranges     |                   v_tx_outputs_use_legacy_false
+    //                  |                   |                                 |
+    //        wallet_summaries              |              v_transactions_shielding_balance
+    //                  |                   |                                 |
+    //         orchard_shardtree            |               v_transactions_note_uniqueness
+    //                          \           /                                 |
+    //                      orchard_received_notes                            |
+    //                                  |                                     |
+    //                          view_only_accounts                            |
+    //                                  |                                     |
+    //                          multi_seed_accounts                           |
+    //                                           \                           /
+    //                                             v_transactions_orchard
     vec![
         Box::new(initial_setup::Migration {}),
         Box::new(utxos_table::Migration {}),
         Box::new(ufvk_support::Migration {
             params: params.clone(),
-            seed,
+            seed: seed.clone(),
         }),
         Box::new(addresses_table::Migration {
             params: params.clone(),
@@ -90,5 +108,109 @@ pub(super) fn all_migrations<P: consensus::Parameters + 'static>(
         Box::new(receiving_key_scopes::Migration {
             params: params.clone(),
         }),
+        Box::new(orchard_shardtree::Migration {
+            params: params.clone(),
+        }),
+        Box::new(orchard_received_notes::Migration),
+        Box::new(view_only_accounts::Migration),
+        Box::new(multi_seed_accounts::Migration {
+            params: params.clone(),
+            seed,
+        }),
+        Box::new(v_transactions_orchard::Migration),
     ]
 }
+
+/// The migrations that provide a down-migration. The down-migration of every other migration
+/// returns [`WalletMigrationError::CannotRevert`].
+///
+/// The down-migrations of [`view_only_accounts`] and [`multi_seed_accounts`] also return
+/// [`WalletMigrationError::CannotRevert`] if the wallet contains accounts that could not be
+/// represented once they had been reverted.
+const REVERSIBLE_MIGRATIONS: &[Uuid] = &[
+    utxos_table::MIGRATION_ID,
+    add_utxo_account::MIGRATION_ID,
+    nullifier_map::MIGRATION_ID,
+    v_sapling_shard_unscanned_ranges::MIGRATION_ID,
+    wallet_summaries::MIGRATION_ID,
+    orchard_shardtree::MIGRATION_ID,
+    orchard_received_notes::MIGRATION_ID,
+    view_only_accounts::MIGRATION_ID,
+    multi_seed_accounts::MIGRATION_ID,
+    v_transactions_orchard::MIGRATION_ID,
+];
+
+/// Returns whether the migration with the given identifier provides a down-migration.
+pub(super) fn is_reversible(id: Uuid) -> bool {
+    REVERSIBLE_MIGRATIONS.contains(&id)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::NamedTempFile;
+    use zcash_primitives::consensus::Network;
+
+    use crate::{wallet::init::init_wallet_db_internal, WalletDb};
+
+    use super::{all_migrations, is_reversible, REVERSIBLE_MIGRATIONS};
+
+    #[test]
+    fn reversible_migrations_can_be_reverted() {
+        // Returns the columns of each table and view in the database.
+        fn schema(db_data: &WalletDb<rusqlite::Connection, Network>) -> Vec<(String, String)> {
+            let mut stmt = db_data
+                .conn
+                .prepare(
+                    "SELECT m.name, p.name
+                    FROM sqlite_master m
+                    JOIN pragma_table_info(m.name) p
+                    WHERE m.type IN ('table', 'view')
+                    ORDER BY m.name, p.name",
+                )
+                .unwrap();
+            let columns = stmt
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            columns
+        }
+
+        let network = Network::TestNetwork;
+        let mut reverted = vec![];
+        for migration in all_migrations(&network, None) {
+            if !is_reversible(migration.id()) {
+                continue;
+            }
+
+            // Migrate a new database to the migration's dependencies.
+            let data_file = NamedTempFile::new().unwrap();
+            let mut db_data = WalletDb::for_path(data_file.path(), network).unwrap();
+            let dependencies = migration.dependencies().into_iter().collect::<Vec<_>>();
+            assert!(!dependencies.is_empty());
+            init_wallet_db_internal(&mut db_data, None, &dependencies).unwrap();
+            let expected = schema(&db_data);
+
+            // Applying and then reverting the migration restores the prior schema.
+            db_data
+                .conn
+                .execute_batch("PRAGMA foreign_keys = OFF")
+                .unwrap();
+            let transaction = db_data.conn.transaction().unwrap();
+            migration.up(&transaction).unwrap();
+            assert_matches!(migration.down(&transaction), Ok(()));
+            transaction.commit().unwrap();
+            assert_eq!(schema(&db_data), expected, "{}", migration.description());
+
+            // The migration can be applied again once it has been reverted.
+            let transaction = db_data.conn.transaction().unwrap();
+            migration.up(&transaction).unwrap();
+            transaction.commit().unwrap();
+
+            reverted.push(migration.id());
+        }
+
+        // Every migration listed as reversible exists.
+        assert_eq!(reverted.len(), REVERSIBLE_MIGRATIONS.len());
+    }
+}
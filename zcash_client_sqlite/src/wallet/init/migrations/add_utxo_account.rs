@@ -118,8 +118,36 @@ impl<P: consensus::Parameters> RusqliteMigration for Migration<P> {
         Ok(())
     }
 
-    fn down(&self, _transaction: &rusqlite::Transaction) -> Result<(), WalletMigrationError> {
-        // TODO: something better than just panic?
-        panic!("Cannot revert this migration.");
+    fn down(&self, transaction: &rusqlite::Transaction) -> Result<(), WalletMigrationError> {
+        transaction.execute_batch(
+            "CREATE TABLE utxos_new (
+                id_utxo INTEGER PRIMARY KEY,
+                address TEXT NOT NULL,
+                prevout_txid BLOB NOT NULL,
+                prevout_idx INTEGER NOT NULL,
+                script BLOB NOT NULL,
+                value_zat INTEGER NOT NULL,
+                height INTEGER NOT NULL,
+                spent_in_tx INTEGER,
+                FOREIGN KEY (spent_in_tx) REFERENCES transactions(id_tx),
+                CONSTRAINT tx_outpoint UNIQUE (prevout_txid, prevout_idx)
+            );
+            INSERT INTO utxos_new (
+                id_utxo, address,
+                prevout_txid, prevout_idx, script, value_zat,
+                height, spent_in_tx)
+            SELECT
+                id_utxo, address,
+                prevout_txid, prevout_idx, script, value_zat,
+                height, spent_in_tx
+            FROM utxos;",
+        )?;
+
+        transaction.execute_batch(
+            "DROP TABLE utxos;
+            ALTER TABLE utxos_new RENAME TO utxos;",
+        )?;
+
+        Ok(())
     }
 }
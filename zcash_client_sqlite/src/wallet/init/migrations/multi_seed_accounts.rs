@@ -0,0 +1,347 @@
+//! This migration records the seed fingerprint and ZIP 32 account index from which each derived
+//! account was generated, so that the wallet may hold accounts derived from more than one seed.
+
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use rusqlite::named_params;
+use schemer_rusqlite::RusqliteMigration;
+use secrecy::{ExposeSecret, SecretVec};
+use uuid::Uuid;
+use zcash_client_backend::keys::{SeedFingerprint, UnifiedFullViewingKey, UnifiedSpendingKey};
+use zcash_primitives::{consensus, zip32::AccountId};
+
+use crate::wallet::{init::WalletMigrationError, ACCOUNT_KIND_DERIVED};
+
+use super::view_only_accounts;
+
+pub(super) const MIGRATION_ID: Uuid = Uuid::from_u128(0x6d02ec76_8720_4cc6_b646_c4e2ce69221c);
+
+pub(super) struct Migration<P> {
+    pub(super) params: P,
+    pub(super) seed: Option<Rc<SecretVec<u8>>>,
+}
+
+impl<P> schemer::Migration for Migration<P> {
+    fn id(&self) -> Uuid {
+        MIGRATION_ID
+    }
+
+    fn dependencies(&self) -> HashSet<Uuid> {
+        [view_only_accounts::MIGRATION_ID].into_iter().collect()
+    }
+
+    fn description(&self) -> &'static str {
+        "Records the seed fingerprint and ZIP 32 account index for each derived account."
+    }
+}
+
+impl<P: consensus::Parameters> RusqliteMigration for Migration<P> {
+    type Error = WalletMigrationError;
+
+    fn up(&self, transaction: &rusqlite::Transaction) -> Result<(), Self::Error> {
+        // Prior to this migration, every derived account was derived from the wallet's single
+        // seed, at the ZIP 32 account index equal to its account identifier. We only need the
+        // seed if there are derived accounts to migrate.
+        let mut seed_fingerprint = None;
+        {
+            let mut stmt_derived_accounts = transaction.prepare(
+                "SELECT account, ufvk FROM accounts WHERE account_kind = :account_kind_derived",
+            )?;
+            let mut rows = stmt_derived_accounts
+                .query(named_params![":account_kind_derived": ACCOUNT_KIND_DERIVED])?;
+            while let Some(row) = rows.next()? {
+                let seed = self
+                    .seed
+                    .as_ref()
+                    .ok_or(WalletMigrationError::SeedRequired)?;
+
+                let account: u32 = row.get(0)?;
+                let account = AccountId::try_from(account).map_err(|_| {
+                    WalletMigrationError::CorruptedData("Account ID is invalid".to_owned())
+                })?;
+                let ufvk_str: String = row.get(1)?;
+                let ufvk = UnifiedFullViewingKey::decode(&self.params, &ufvk_str)
+                    .map_err(WalletMigrationError::CorruptedData)?;
+
+                // Verify that the provided seed is the one from which the account was derived.
+                let usk =
+                    UnifiedSpendingKey::from_seed(&self.params, seed.expose_secret(), account)
+                        .map_err(|_| {
+                            WalletMigrationError::CorruptedData(format!(
+                                "Unable to derive spending key for account {}",
+                                u32::from(account)
+                            ))
+                        })?;
+                let expected_dfvk = usk.sapling().to_diversifiable_full_viewing_key();
+                if ufvk.sapling().map(|dfvk| dfvk.to_bytes()) != Some(expected_dfvk.to_bytes()) {
+                    return Err(WalletMigrationError::CorruptedData(format!(
+                        "The provided seed does not correspond to the viewing key for account {}",
+                        u32::from(account)
+                    )));
+                }
+
+                seed_fingerprint = Some(
+                    SeedFingerprint::from_seed(seed.expose_secret()).ok_or_else(|| {
+                        WalletMigrationError::CorruptedData(
+                            "The provided seed is invalid".to_owned(),
+                        )
+                    })?,
+                );
+            }
+        }
+
+        transaction.execute_batch(
+            "CREATE TABLE accounts_new (
+                account INTEGER PRIMARY KEY,
+                account_kind INTEGER NOT NULL DEFAULT 0,
+                hd_seed_fingerprint BLOB,
+                hd_account_index INTEGER,
+                ufvk TEXT NOT NULL,
+                birthday_height INTEGER NOT NULL,
+                recover_until_height INTEGER,
+                CHECK (
+                    (account_kind = 0 AND hd_seed_fingerprint IS NOT NULL AND hd_account_index IS NOT NULL)
+                    OR
+                    (account_kind = 1 AND hd_seed_fingerprint IS NULL AND hd_account_index IS NULL)
+                ),
+                CONSTRAINT hd_account UNIQUE (hd_seed_fingerprint, hd_account_index)
+            );",
+        )?;
+
+        transaction.execute(
+            "INSERT INTO accounts_new (
+                account, account_kind, hd_seed_fingerprint, hd_account_index,
+                ufvk, birthday_height, recover_until_height
+            )
+            SELECT
+                account, account_kind,
+                CASE WHEN account_kind = :account_kind_derived THEN :seed_fingerprint END,
+                CASE WHEN account_kind = :account_kind_derived THEN account END,
+                ufvk, birthday_height, recover_until_height
+            FROM accounts",
+            named_params![
+                ":account_kind_derived": ACCOUNT_KIND_DERIVED,
+                ":seed_fingerprint": seed_fingerprint.map(|fp| fp.to_bytes()),
+            ],
+        )?;
+
+        transaction.execute_batch(
+            "PRAGMA foreign_keys=OFF;
+            PRAGMA legacy_alter_table = ON;
+            DROP TABLE accounts;
+            ALTER TABLE accounts_new RENAME TO accounts;
+            PRAGMA legacy_alter_table = OFF;
+            PRAGMA foreign_keys=ON;",
+        )?;
+
+        Ok(())
+    }
+
+    fn down(&self, transaction: &rusqlite::Transaction) -> Result<(), Self::Error> {
+        // The prior schema can only represent derived accounts that were all derived from a
+        // single seed, at the ZIP 32 account index equal to their account identifier.
+        let representable: bool = transaction.query_row(
+            "SELECT
+                (SELECT COUNT(DISTINCT hd_seed_fingerprint) FROM accounts) <= 1
+                AND NOT EXISTS (
+                    SELECT 1 FROM accounts
+                    WHERE account_kind = :account_kind_derived
+                    AND hd_account_index != account
+                )",
+            named_params![":account_kind_derived": ACCOUNT_KIND_DERIVED],
+            |row| row.get(0),
+        )?;
+        if !representable {
+            return Err(WalletMigrationError::CannotRevert(MIGRATION_ID));
+        }
+
+        transaction.execute_batch(
+            "CREATE TABLE accounts_new (
+                account INTEGER PRIMARY KEY,
+                ufvk TEXT NOT NULL,
+                birthday_height INTEGER NOT NULL,
+                recover_until_height INTEGER,
+                account_kind INTEGER NOT NULL DEFAULT 0
+            );
+
+            INSERT INTO accounts_new (
+                account, ufvk, birthday_height, recover_until_height, account_kind
+            )
+            SELECT account, ufvk, birthday_height, recover_until_height, account_kind
+            FROM accounts;
+
+            PRAGMA foreign_keys=OFF;
+            PRAGMA legacy_alter_table = ON;
+            DROP TABLE accounts;
+            ALTER TABLE accounts_new RENAME TO accounts;
+            PRAGMA legacy_alter_table = OFF;
+            PRAGMA foreign_keys=ON;",
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::named_params;
+    use schemer_rusqlite::RusqliteMigration;
+    use secrecy::Secret;
+    use tempfile::NamedTempFile;
+    use zcash_client_backend::keys::{SeedFingerprint, UnifiedSpendingKey};
+    use zcash_primitives::{consensus::Network, zip32::AccountId};
+
+    use crate::{
+        wallet::init::{init_wallet_db, init_wallet_db_internal, WalletMigrationError},
+        WalletDb,
+    };
+
+    use super::view_only_accounts;
+
+    fn insert_derived_account(db_data: &WalletDb<rusqlite::Connection, Network>, seed: &[u8]) {
+        let usk = UnifiedSpendingKey::from_seed(&db_data.params, seed, AccountId::ZERO).unwrap();
+        db_data
+            .conn
+            .execute(
+                "INSERT INTO accounts (account, ufvk, birthday_height) VALUES (0, :ufvk, 1)",
+                named_params![
+                    ":ufvk": usk.to_unified_full_viewing_key().encode(&db_data.params)
+                ],
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn migrate_derived_account() {
+        let data_file = NamedTempFile::new().unwrap();
+        let mut db_data = WalletDb::for_path(data_file.path(), Network::TestNetwork).unwrap();
+        init_wallet_db_internal(&mut db_data, None, &[view_only_accounts::MIGRATION_ID]).unwrap();
+
+        let seed = [0xab; 32];
+        insert_derived_account(&db_data, &seed);
+
+        // The seed is required to migrate derived accounts.
+        assert_matches!(
+            init_wallet_db(&mut db_data, None),
+            Err(schemer::MigratorError::Migration {
+                error: WalletMigrationError::SeedRequired,
+                ..
+            })
+        );
+
+        // A seed that does not correspond to the account is rejected.
+        assert_matches!(
+            init_wallet_db(&mut db_data, Some(Secret::new(vec![0xcd; 32]))),
+            Err(schemer::MigratorError::Migration {
+                error: WalletMigrationError::CorruptedData(_),
+                ..
+            })
+        );
+
+        init_wallet_db(&mut db_data, Some(Secret::new(seed.to_vec()))).unwrap();
+
+        let (fingerprint, account_index): (Vec<u8>, u32) = db_data
+            .conn
+            .query_row(
+                "SELECT hd_seed_fingerprint, hd_account_index FROM accounts WHERE account = 0",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(
+            fingerprint,
+            SeedFingerprint::from_seed(&seed).unwrap().to_bytes()
+        );
+        assert_eq!(account_index, 0);
+    }
+
+    #[test]
+    fn revert_migration() {
+        let data_file = NamedTempFile::new().unwrap();
+        let mut db_data = WalletDb::for_path(data_file.path(), Network::TestNetwork).unwrap();
+        init_wallet_db_internal(&mut db_data, None, &[view_only_accounts::MIGRATION_ID]).unwrap();
+
+        let seed = [0xab; 32];
+        insert_derived_account(&db_data, &seed);
+        init_wallet_db(&mut db_data, Some(Secret::new(seed.to_vec()))).unwrap();
+
+        let migration = super::Migration {
+            params: Network::TestNetwork,
+            seed: None,
+        };
+
+        // An account derived from a second seed cannot be represented by the prior schema.
+        db_data
+            .conn
+            .execute(
+                "INSERT INTO accounts (
+                    account, account_kind, hd_seed_fingerprint, hd_account_index,
+                    ufvk, birthday_height
+                )
+                VALUES (1, 0, :hd_seed_fingerprint, 0, '', 1)",
+                named_params![":hd_seed_fingerprint": [0xcd; 32]],
+            )
+            .unwrap();
+        {
+            let transaction = db_data.conn.transaction().unwrap();
+            assert_matches!(
+                migration.down(&transaction),
+                Err(WalletMigrationError::CannotRevert(id)) if id == super::MIGRATION_ID
+            );
+        }
+
+        db_data
+            .conn
+            .execute("DELETE FROM accounts WHERE account = 1", [])
+            .unwrap();
+
+        // Nor can an account derived at a ZIP 32 account index other than its identifier.
+        db_data
+            .conn
+            .execute(
+                "INSERT INTO accounts (
+                    account, account_kind, hd_seed_fingerprint, hd_account_index,
+                    ufvk, birthday_height
+                )
+                VALUES (2, 0, :hd_seed_fingerprint, 1, '', 1)",
+                named_params![
+                    ":hd_seed_fingerprint": SeedFingerprint::from_seed(&seed).unwrap().to_bytes()
+                ],
+            )
+            .unwrap();
+        {
+            let transaction = db_data.conn.transaction().unwrap();
+            assert_matches!(
+                migration.down(&transaction),
+                Err(WalletMigrationError::CannotRevert(id)) if id == super::MIGRATION_ID
+            );
+        }
+
+        db_data
+            .conn
+            .execute("DELETE FROM accounts WHERE account = 2", [])
+            .unwrap();
+        let transaction = db_data.conn.transaction().unwrap();
+        migration.down(&transaction).unwrap();
+        transaction.commit().unwrap();
+
+        let has_fingerprint_column: bool = db_data
+            .conn
+            .query_row(
+                "SELECT EXISTS (
+                    SELECT 1 FROM pragma_table_info('accounts')
+                    WHERE name = 'hd_seed_fingerprint'
+                )",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(!has_fingerprint_column);
+        let account_count: u32 = db_data
+            .conn
+            .query_row("SELECT COUNT(*) FROM accounts", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(account_count, 1);
+    }
+}
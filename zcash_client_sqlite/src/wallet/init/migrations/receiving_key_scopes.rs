@@ -260,8 +260,7 @@ impl<P: consensus::Parameters> RusqliteMigration for Migration<P> {
     }
 
     fn down(&self, _transaction: &rusqlite::Transaction) -> Result<(), WalletMigrationError> {
-        // TODO: something better than just panic?
-        panic!("Cannot revert this migration.");
+        Err(WalletMigrationError::CannotRevert(MIGRATION_ID))
     }
 }
 
@@ -488,6 +487,8 @@ mod tests {
                 tx,
                 &[(AccountId::ZERO, ufvk0)].into_iter().collect(),
             ),
+            #[cfg(feature = "orchard")]
+            orchard_outputs: &vec![],
         };
         db_data
             .transactionally::<_, _, rusqlite::Error>(|wdb| {
@@ -602,6 +603,8 @@ mod tests {
             block,
             &[(&AccountId::ZERO, ufvk0.sapling().unwrap())],
             &[],
+            #[cfg(feature = "orchard")]
+            &[],
             Some(&BlockMetadata::from_parts(
                 height - 1,
                 prev_hash,
@@ -645,6 +648,10 @@ mod tests {
                         block.block_time(),
                         block.sapling().final_tree_size(),
                         block.sapling().commitments().len().try_into().unwrap(),
+                        #[cfg(feature = "orchard")]
+                        block.orchard().final_tree_size(),
+                        #[cfg(feature = "orchard")]
+                        block.orchard().commitments().len().try_into().unwrap(),
                     )?;
 
                     for tx in block.transactions() {
@@ -0,0 +1,134 @@
+//! This migration adds an account kind to each account record, so that accounts imported from a
+//! unified full viewing key can be distinguished from accounts derived from the wallet's seed.
+
+use std::collections::HashSet;
+
+use rusqlite::named_params;
+use schemer_rusqlite::RusqliteMigration;
+use uuid::Uuid;
+
+use crate::wallet::{init::WalletMigrationError, ACCOUNT_KIND_VIEW_ONLY};
+
+use super::orchard_received_notes;
+
+pub(super) const MIGRATION_ID: Uuid = Uuid::from_u128(0x1b104345_f27e_42da_a9e3_1de22694da43);
+
+pub(super) struct Migration;
+
+impl schemer::Migration for Migration {
+    fn id(&self) -> Uuid {
+        MIGRATION_ID
+    }
+
+    fn dependencies(&self) -> HashSet<Uuid> {
+        [orchard_received_notes::MIGRATION_ID].into_iter().collect()
+    }
+
+    fn description(&self) -> &'static str {
+        "Adds an account kind to each account, to support view-only accounts."
+    }
+}
+
+impl RusqliteMigration for Migration {
+    type Error = WalletMigrationError;
+
+    fn up(&self, transaction: &rusqlite::Transaction) -> Result<(), Self::Error> {
+        // All accounts that exist prior to this migration were derived from the wallet's seed.
+        transaction.execute_batch(
+            "ALTER TABLE accounts ADD COLUMN account_kind INTEGER NOT NULL DEFAULT 0;",
+        )?;
+
+        Ok(())
+    }
+
+    fn down(&self, transaction: &rusqlite::Transaction) -> Result<(), Self::Error> {
+        // The prior schema treats every account as having been derived from the wallet's seed,
+        // and so cannot represent view-only accounts.
+        let has_view_only_accounts: bool = transaction.query_row(
+            "SELECT EXISTS (SELECT 1 FROM accounts WHERE account_kind = :account_kind_view_only)",
+            named_params![":account_kind_view_only": ACCOUNT_KIND_VIEW_ONLY],
+            |row| row.get(0),
+        )?;
+        if has_view_only_accounts {
+            return Err(WalletMigrationError::CannotRevert(MIGRATION_ID));
+        }
+
+        transaction.execute_batch("ALTER TABLE accounts DROP COLUMN account_kind;")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::named_params;
+    use schemer_rusqlite::RusqliteMigration;
+    use tempfile::NamedTempFile;
+    use zcash_primitives::consensus::Network;
+
+    use crate::{
+        wallet::{
+            init::{init_wallet_db_internal, WalletMigrationError},
+            ACCOUNT_KIND_DERIVED, ACCOUNT_KIND_VIEW_ONLY,
+        },
+        WalletDb,
+    };
+
+    fn insert_account(
+        db_data: &WalletDb<rusqlite::Connection, Network>,
+        account: u32,
+        account_kind: i64,
+    ) {
+        db_data
+            .conn
+            .execute(
+                "INSERT INTO accounts (account, ufvk, birthday_height, account_kind)
+                VALUES (:account, '', 1, :account_kind)",
+                named_params![":account": account, ":account_kind": account_kind],
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn revert_migration() {
+        let data_file = NamedTempFile::new().unwrap();
+        let mut db_data = WalletDb::for_path(data_file.path(), Network::TestNetwork).unwrap();
+        init_wallet_db_internal(&mut db_data, None, &[super::MIGRATION_ID]).unwrap();
+
+        insert_account(&db_data, 0, ACCOUNT_KIND_DERIVED);
+        insert_account(&db_data, 1, ACCOUNT_KIND_VIEW_ONLY);
+
+        // A view-only account cannot be represented by the prior schema.
+        {
+            let transaction = db_data.conn.transaction().unwrap();
+            assert_matches!(
+                super::Migration.down(&transaction),
+                Err(WalletMigrationError::CannotRevert(id)) if id == super::MIGRATION_ID
+            );
+        }
+
+        db_data
+            .conn
+            .execute("DELETE FROM accounts WHERE account = 1", [])
+            .unwrap();
+        let transaction = db_data.conn.transaction().unwrap();
+        super::Migration.down(&transaction).unwrap();
+        transaction.commit().unwrap();
+
+        let has_account_kind_column: bool = db_data
+            .conn
+            .query_row(
+                "SELECT EXISTS (
+                    SELECT 1 FROM pragma_table_info('accounts') WHERE name = 'account_kind'
+                )",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(!has_account_kind_column);
+        let account_count: u32 = db_data
+            .conn
+            .query_row("SELECT COUNT(*) FROM accounts", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(account_count, 1);
+    }
+}
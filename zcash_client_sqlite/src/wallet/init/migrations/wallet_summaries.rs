@@ -83,7 +83,13 @@ impl RusqliteMigration for Migration {
         Ok(())
     }
 
-    fn down(&self, _transaction: &rusqlite::Transaction) -> Result<(), Self::Error> {
-        panic!("This migration cannot be reverted.");
+    fn down(&self, transaction: &rusqlite::Transaction) -> Result<(), Self::Error> {
+        transaction.execute_batch(
+            "DROP VIEW v_sapling_shards_scan_state;
+            ALTER TABLE blocks DROP COLUMN orchard_action_count;
+            ALTER TABLE blocks DROP COLUMN sapling_output_count;",
+        )?;
+
+        Ok(())
     }
 }
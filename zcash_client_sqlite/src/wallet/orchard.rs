@@ -0,0 +1,464 @@
+//! Functions for Orchard support in the wallet.
+
+use incrementalmerkletree::Position;
+use orchard::{
+    keys::Diversifier,
+    note::{Note, Nullifier, RandomSeed},
+    value::NoteValue,
+};
+use rusqlite::{named_params, params, types::Value, Connection, Row};
+use std::rc::Rc;
+
+use zcash_primitives::{
+    consensus::{self, BlockHeight},
+    memo::MemoBytes,
+    transaction::{components::Amount, TxId},
+    zip32::{AccountId, Scope},
+};
+
+use zcash_client_backend::{
+    keys::UnifiedFullViewingKey,
+    wallet::{Note as WalletNote, ReceivedNote, WalletOrchardOutput},
+    DecryptedOutput, ShieldedProtocol, TransferType,
+};
+
+use crate::{error::SqliteClientError, ReceivedNoteId, ORCHARD_TABLES_PREFIX};
+
+use super::{memo_repr, parse_scope, scope_code, unscanned_tip_exists, wallet_birthday};
+
+/// This trait provides a generalization over Orchard output representations.
+pub(crate) trait ReceivedOrchardOutput {
+    fn index(&self) -> usize;
+    fn account(&self) -> AccountId;
+    fn note(&self) -> &Note;
+    fn memo(&self) -> Option<&MemoBytes>;
+    fn is_change(&self) -> bool;
+    fn nullifier(&self) -> Option<&Nullifier>;
+    fn note_commitment_tree_position(&self) -> Option<Position>;
+    fn recipient_key_scope(&self) -> Scope;
+}
+
+impl ReceivedOrchardOutput for WalletOrchardOutput<Scope> {
+    fn index(&self) -> usize {
+        self.index()
+    }
+    fn account(&self) -> AccountId {
+        WalletOrchardOutput::account(self)
+    }
+    fn note(&self) -> &Note {
+        WalletOrchardOutput::note(self)
+    }
+    fn memo(&self) -> Option<&MemoBytes> {
+        None
+    }
+    fn is_change(&self) -> bool {
+        WalletOrchardOutput::is_change(self)
+    }
+    fn nullifier(&self) -> Option<&Nullifier> {
+        Some(self.nf())
+    }
+    fn note_commitment_tree_position(&self) -> Option<Position> {
+        Some(WalletOrchardOutput::note_commitment_tree_position(self))
+    }
+    fn recipient_key_scope(&self) -> Scope {
+        *self.recipient_key_scope()
+    }
+}
+
+impl ReceivedOrchardOutput for DecryptedOutput<Note> {
+    fn index(&self) -> usize {
+        self.index
+    }
+    fn account(&self) -> AccountId {
+        self.account
+    }
+    fn note(&self) -> &Note {
+        &self.note
+    }
+    fn memo(&self) -> Option<&MemoBytes> {
+        Some(&self.memo)
+    }
+    fn is_change(&self) -> bool {
+        self.transfer_type == TransferType::WalletInternal
+    }
+    fn nullifier(&self) -> Option<&Nullifier> {
+        None
+    }
+    fn note_commitment_tree_position(&self) -> Option<Position> {
+        None
+    }
+    fn recipient_key_scope(&self) -> Scope {
+        if self.transfer_type == TransferType::WalletInternal {
+            Scope::Internal
+        } else {
+            Scope::External
+        }
+    }
+}
+
+fn to_spendable_note<P: consensus::Parameters>(
+    params: &P,
+    row: &Row,
+) -> Result<ReceivedNote<ReceivedNoteId, WalletNote>, SqliteClientError> {
+    let note_id = ReceivedNoteId(ShieldedProtocol::Orchard, row.get(0)?);
+    let txid = row.get::<_, [u8; 32]>(1).map(TxId::from_bytes)?;
+    let action_index = row.get(2)?;
+    let diversifier = {
+        let d: Vec<_> = row.get(3)?;
+        if d.len() != 11 {
+            return Err(SqliteClientError::CorruptedData(
+                "Invalid diversifier length".to_string(),
+            ));
+        }
+        let mut tmp = [0; 11];
+        tmp.copy_from_slice(&d);
+        Diversifier::from_bytes(tmp)
+    };
+
+    let note_value: u64 = row.get::<_, i64>(4)?.try_into().map_err(|_e| {
+        SqliteClientError::CorruptedData("Note values must be nonnegative".to_string())
+    })?;
+
+    let rho = {
+        let rho_bytes: [u8; 32] = row.get(5)?;
+        Option::from(Nullifier::from_bytes(&rho_bytes))
+            .ok_or_else(|| SqliteClientError::CorruptedData("Invalid rho.".to_string()))
+    }?;
+
+    let rseed = {
+        let rseed_bytes: [u8; 32] = row.get(6)?;
+        Option::from(RandomSeed::from_bytes(rseed_bytes, &rho))
+            .ok_or_else(|| SqliteClientError::CorruptedData("Invalid rseed.".to_string()))
+    }?;
+
+    let note_commitment_tree_position =
+        Position::from(u64::try_from(row.get::<_, i64>(7)?).map_err(|_| {
+            SqliteClientError::CorruptedData("Note commitment tree position invalid.".to_string())
+        })?);
+
+    let ufvk_str: String = row.get(8)?;
+    let ufvk = UnifiedFullViewingKey::decode(params, &ufvk_str)
+        .map_err(SqliteClientError::CorruptedData)?;
+
+    let scope_code: i64 = row.get(9)?;
+    let spending_key_scope = parse_scope(scope_code).ok_or_else(|| {
+        SqliteClientError::CorruptedData(format!("Invalid key scope code {}", scope_code))
+    })?;
+
+    let recipient = ufvk
+        .orchard()
+        .map(|fvk| fvk.address(diversifier, spending_key_scope))
+        .ok_or_else(|| {
+            SqliteClientError::CorruptedData(
+                "Unified full viewing key does not contain an Orchard key.".to_owned(),
+            )
+        })?;
+
+    let note = Option::from(Note::from_parts(
+        recipient,
+        NoteValue::from_raw(note_value),
+        rho,
+        rseed,
+    ))
+    .ok_or_else(|| SqliteClientError::CorruptedData("Invalid Orchard note.".to_string()))?;
+
+    Ok(ReceivedNote::from_parts(
+        note_id,
+        txid,
+        action_index,
+        WalletNote::Orchard(note),
+        spending_key_scope,
+        note_commitment_tree_position,
+    ))
+}
+
+// The `clippy::let_and_return` lint is explicitly allowed here because a bug in Clippy
+// (https://github.com/rust-lang/rust-clippy/issues/11308) means it fails to identify that the `result` temporary
+// is required in order to resolve the borrows involved in the `query_and_then` call.
+#[allow(clippy::let_and_return)]
+pub(crate) fn get_spendable_orchard_note<P: consensus::Parameters>(
+    conn: &Connection,
+    params: &P,
+    txid: &TxId,
+    index: u32,
+) -> Result<Option<ReceivedNote<ReceivedNoteId, WalletNote>>, SqliteClientError> {
+    let mut stmt_select_note = conn.prepare_cached(
+        "SELECT id_note, txid, action_index, diversifier, value, rho, rseed,
+                commitment_tree_position, accounts.ufvk, recipient_key_scope
+         FROM orchard_received_notes
+         INNER JOIN accounts on accounts.account = orchard_received_notes.account
+         INNER JOIN transactions ON transactions.id_tx = orchard_received_notes.tx
+         WHERE txid = :txid
+         AND action_index = :action_index
+         AND spent IS NULL",
+    )?;
+
+    let result = stmt_select_note
+        .query_and_then(
+            named_params![
+               ":txid": txid.as_ref(),
+               ":action_index": index,
+            ],
+            |r| to_spendable_note(params, r),
+        )?
+        .next()
+        .transpose();
+
+    result
+}
+
+pub(crate) fn select_spendable_orchard_notes<P: consensus::Parameters>(
+    conn: &Connection,
+    params: &P,
+    account: AccountId,
+    target_value: Amount,
+    anchor_height: BlockHeight,
+    exclude: &[ReceivedNoteId],
+) -> Result<Vec<ReceivedNote<ReceivedNoteId, WalletNote>>, SqliteClientError> {
+    let birthday_height = match wallet_birthday(conn)? {
+        Some(birthday) => birthday,
+        None => {
+            // the wallet birthday can only be unknown if there are no accounts in the wallet; in
+            // such a case, the wallet has no notes to spend.
+            return Ok(vec![]);
+        }
+    };
+
+    if unscanned_tip_exists(conn, anchor_height, ORCHARD_TABLES_PREFIX)? {
+        return Ok(vec![]);
+    }
+
+    // This query follows the same structure as that used for Sapling note selection; see
+    // `select_spendable_sapling_notes` for a description of the approach.
+    let mut stmt_select_notes = conn.prepare_cached(
+        "WITH eligible AS (
+             SELECT
+                 id_note, txid, action_index, diversifier, value, rho, rseed,
+                 commitment_tree_position,
+                 SUM(value)
+                    OVER (PARTITION BY orchard_received_notes.account, spent ORDER BY id_note) AS so_far,
+                 accounts.ufvk as ufvk, recipient_key_scope
+             FROM orchard_received_notes
+             INNER JOIN accounts on accounts.account = orchard_received_notes.account
+             INNER JOIN transactions
+                ON transactions.id_tx = orchard_received_notes.tx
+             WHERE orchard_received_notes.account = :account
+             AND commitment_tree_position IS NOT NULL
+             AND spent IS NULL
+             AND transactions.block <= :anchor_height
+             AND id_note NOT IN rarray(:exclude)
+             AND NOT EXISTS (
+                SELECT 1 FROM v_orchard_shard_unscanned_ranges unscanned
+                -- select all the unscanned ranges involving the shard containing this note
+                WHERE orchard_received_notes.commitment_tree_position >= unscanned.start_position
+                AND orchard_received_notes.commitment_tree_position < unscanned.end_position_exclusive
+                -- exclude unscanned ranges that start above the anchor height (they don't affect spendability)
+                AND unscanned.block_range_start <= :anchor_height
+                -- exclude unscanned ranges that end below the wallet birthday
+                AND unscanned.block_range_end > :wallet_birthday
+             )
+         )
+         SELECT id_note, txid, action_index, diversifier, value, rho, rseed,
+                commitment_tree_position, ufvk, recipient_key_scope
+         FROM eligible WHERE so_far < :target_value
+         UNION
+         SELECT id_note, txid, action_index, diversifier, value, rho, rseed,
+                commitment_tree_position, ufvk, recipient_key_scope
+         FROM (SELECT * from eligible WHERE so_far >= :target_value LIMIT 1)",
+    )?;
+
+    let excluded: Vec<Value> = exclude
+        .iter()
+        .filter_map(|ReceivedNoteId(protocol, id)| {
+            (*protocol == ShieldedProtocol::Orchard).then(|| Value::from(*id))
+        })
+        .collect();
+    let excluded_ptr = Rc::new(excluded);
+
+    let notes = stmt_select_notes.query_and_then(
+        named_params![
+            ":account": &u32::from(account),
+            ":anchor_height": &u32::from(anchor_height),
+            ":target_value": &i64::from(target_value),
+            ":exclude": &excluded_ptr,
+            ":wallet_birthday": u32::from(birthday_height)
+        ],
+        |r| to_spendable_note(params, r),
+    )?;
+
+    notes.collect::<Result<_, _>>()
+}
+
+fn to_nullifier_entry(row: &Row) -> Result<(AccountId, Nullifier), SqliteClientError> {
+    let account: u32 = row.get(1)?;
+    let nf_bytes: [u8; 32] = row.get(2)?;
+    let account =
+        AccountId::try_from(account).map_err(|_| SqliteClientError::AccountIdOutOfRange)?;
+    let nf = Option::from(Nullifier::from_bytes(&nf_bytes)).ok_or_else(|| {
+        SqliteClientError::CorruptedData("Invalid Orchard nullifier.".to_string())
+    })?;
+    Ok((account, nf))
+}
+
+/// Retrieves the set of nullifiers for "potentially spendable" Orchard notes that the
+/// wallet is tracking.
+///
+/// "Potentially spendable" means:
+/// - The transaction in which the note was created has been observed as mined.
+/// - No transaction in which the note's nullifier appears has been observed as mined.
+pub(crate) fn get_orchard_nullifiers(
+    conn: &Connection,
+) -> Result<Vec<(AccountId, Nullifier)>, SqliteClientError> {
+    // Get the nullifiers for the notes we are tracking
+    let mut stmt_fetch_nullifiers = conn.prepare(
+        "SELECT rn.id_note, rn.account, rn.nf, tx.block as block
+         FROM orchard_received_notes rn
+         LEFT OUTER JOIN transactions tx
+         ON tx.id_tx = rn.spent
+         WHERE block IS NULL
+         AND nf IS NOT NULL",
+    )?;
+    let nullifiers = stmt_fetch_nullifiers.query_and_then([], to_nullifier_entry)?;
+
+    let res: Vec<_> = nullifiers.collect::<Result<_, _>>()?;
+    Ok(res)
+}
+
+/// Returns the nullifiers for the Orchard notes that this wallet is tracking.
+pub(crate) fn get_all_orchard_nullifiers(
+    conn: &Connection,
+) -> Result<Vec<(AccountId, Nullifier)>, SqliteClientError> {
+    // Get the nullifiers for the notes we are tracking
+    let mut stmt_fetch_nullifiers = conn.prepare(
+        "SELECT rn.id_note, rn.account, rn.nf
+         FROM orchard_received_notes rn
+         WHERE nf IS NOT NULL",
+    )?;
+    let nullifiers = stmt_fetch_nullifiers.query_and_then([], to_nullifier_entry)?;
+
+    let res: Vec<_> = nullifiers.collect::<Result<_, _>>()?;
+    Ok(res)
+}
+
+/// Marks a given nullifier as having been revealed in the construction
+/// of the specified transaction.
+///
+/// Marking a note spent in this fashion does NOT imply that the
+/// spending transaction has been mined.
+pub(crate) fn mark_orchard_note_spent(
+    conn: &Connection,
+    tx_ref: i64,
+    nf: &Nullifier,
+) -> Result<bool, SqliteClientError> {
+    let mut stmt_mark_orchard_note_spent =
+        conn.prepare_cached("UPDATE orchard_received_notes SET spent = ? WHERE nf = ?")?;
+
+    match stmt_mark_orchard_note_spent.execute(params![tx_ref, &nf.to_bytes()[..]])? {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => unreachable!("nf column is marked as UNIQUE"),
+    }
+}
+
+/// Records the specified Orchard output as having been received.
+///
+/// This implementation relies on the facts that:
+/// - A transaction will not contain more than 2^63 Orchard actions.
+/// - A note value will never exceed 2^63 zatoshis.
+pub(crate) fn put_received_note<T: ReceivedOrchardOutput>(
+    conn: &Connection,
+    output: &T,
+    tx_ref: i64,
+    spent_in: Option<i64>,
+) -> Result<(), SqliteClientError> {
+    let mut stmt_upsert_received_note = conn.prepare_cached(
+        "INSERT INTO orchard_received_notes
+        (tx, action_index, account, diversifier, value, rho, rseed, memo, nf,
+         is_change, spent, commitment_tree_position,
+         recipient_key_scope)
+        VALUES (
+            :tx,
+            :action_index,
+            :account,
+            :diversifier,
+            :value,
+            :rho,
+            :rseed,
+            :memo,
+            :nf,
+            :is_change,
+            :spent,
+            :commitment_tree_position,
+            :recipient_key_scope
+        )
+        ON CONFLICT (tx, action_index) DO UPDATE
+        SET account = :account,
+            diversifier = :diversifier,
+            value = :value,
+            rho = :rho,
+            rseed = :rseed,
+            nf = IFNULL(:nf, nf),
+            memo = IFNULL(:memo, memo),
+            is_change = IFNULL(:is_change, is_change),
+            spent = IFNULL(:spent, spent),
+            commitment_tree_position = IFNULL(:commitment_tree_position, commitment_tree_position),
+            recipient_key_scope = :recipient_key_scope",
+    )?;
+
+    let note = output.note();
+    let diversifier = note.recipient().diversifier();
+
+    let sql_args = named_params![
+        ":tx": &tx_ref,
+        ":action_index": i64::try_from(output.index()).expect("action indices are representable as i64"),
+        ":account": u32::from(output.account()),
+        ":diversifier": diversifier.as_array(),
+        ":value": i64::try_from(note.value().inner()).expect("note values are representable as i64"),
+        ":rho": note.rho().to_bytes(),
+        ":rseed": note.rseed().as_bytes(),
+        ":nf": output.nullifier().map(|nf| nf.to_bytes()),
+        ":memo": memo_repr(output.memo()),
+        ":is_change": output.is_change(),
+        ":spent": spent_in,
+        ":commitment_tree_position": output.note_commitment_tree_position().map(u64::from),
+        ":recipient_key_scope": scope_code(output.recipient_key_scope()),
+    ];
+
+    stmt_upsert_received_note
+        .execute(sql_args)
+        .map_err(SqliteClientError::from)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use zcash_client_backend::data_api::testing::conformance::orchard as scenarios;
+
+    use crate::testing::TestDbFactory;
+
+    #[test]
+    fn receive_then_spend() {
+        scenarios::receive_then_spend(TestDbFactory)
+    }
+
+    #[test]
+    fn send_with_orchard_change() {
+        scenarios::send_with_orchard_change(TestDbFactory)
+    }
+
+    #[test]
+    fn cross_pool_payment_requires_revealed_amounts() {
+        scenarios::cross_pool_payment_requires_revealed_amounts(TestDbFactory)
+    }
+
+    #[test]
+    fn send_max_respects_privacy_policy() {
+        scenarios::send_max_respects_privacy_policy(TestDbFactory)
+    }
+
+    #[test]
+    fn consolidation_skips_dust() {
+        scenarios::consolidation_skips_dust(TestDbFactory)
+    }
+}
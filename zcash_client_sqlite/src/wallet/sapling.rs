@@ -19,12 +19,12 @@ use zcash_primitives::{
 use zcash_client_backend::{
     keys::UnifiedFullViewingKey,
     wallet::{Note, ReceivedNote, WalletSaplingOutput},
-    DecryptedOutput, TransferType,
+    DecryptedOutput, ShieldedProtocol, TransferType,
 };
 
-use crate::{error::SqliteClientError, ReceivedNoteId};
+use crate::{error::SqliteClientError, ReceivedNoteId, SAPLING_TABLES_PREFIX};
 
-use super::{memo_repr, parse_scope, scope_code, wallet_birthday};
+use super::{memo_repr, parse_scope, scope_code, unscanned_tip_exists, wallet_birthday};
 
 /// This trait provides a generalization over shielded output representations.
 pub(crate) trait ReceivedSaplingOutput {
@@ -101,7 +101,7 @@ fn to_spendable_note<P: consensus::Parameters>(
     params: &P,
     row: &Row,
 ) -> Result<ReceivedNote<ReceivedNoteId, Note>, SqliteClientError> {
-    let note_id = ReceivedNoteId(row.get(0)?);
+    let note_id = ReceivedNoteId(ShieldedProtocol::Sapling, row.get(0)?);
     let txid = row.get::<_, [u8; 32]>(1).map(TxId::from_bytes)?;
     let output_index = row.get(2)?;
     let diversifier = {
@@ -208,30 +208,6 @@ pub(crate) fn get_spendable_sapling_note<P: consensus::Parameters>(
     result
 }
 
-/// Utility method for determining whether we have any spendable notes
-///
-/// If the tip shard has unscanned ranges below the anchor height and greater than or equal to
-/// the wallet birthday, none of our notes can be spent because we cannot construct witnesses at
-/// the provided anchor height.
-fn unscanned_tip_exists(
-    conn: &Connection,
-    anchor_height: BlockHeight,
-) -> Result<bool, rusqlite::Error> {
-    // v_sapling_shard_unscanned_ranges only returns ranges ending on or after wallet birthday, so
-    // we don't need to refer to the birthday in this query.
-    conn.query_row(
-        "SELECT EXISTS (
-             SELECT 1 FROM v_sapling_shard_unscanned_ranges range
-             WHERE range.block_range_start <= :anchor_height
-             AND :anchor_height BETWEEN
-                range.subtree_start_height
-                AND IFNULL(range.subtree_end_height, :anchor_height)
-         )",
-        named_params![":anchor_height": u32::from(anchor_height),],
-        |row| row.get::<_, bool>(0),
-    )
-}
-
 pub(crate) fn select_spendable_sapling_notes<P: consensus::Parameters>(
     conn: &Connection,
     params: &P,
@@ -249,7 +225,7 @@ pub(crate) fn select_spendable_sapling_notes<P: consensus::Parameters>(
         }
     };
 
-    if unscanned_tip_exists(conn, anchor_height)? {
+    if unscanned_tip_exists(conn, anchor_height, SAPLING_TABLES_PREFIX)? {
         return Ok(vec![]);
     }
 
@@ -303,7 +279,12 @@ pub(crate) fn select_spendable_sapling_notes<P: consensus::Parameters>(
          FROM (SELECT * from eligible WHERE so_far >= :target_value LIMIT 1)",
     )?;
 
-    let excluded: Vec<Value> = exclude.iter().map(|n| Value::from(n.0)).collect();
+    let excluded: Vec<Value> = exclude
+        .iter()
+        .filter_map(|ReceivedNoteId(protocol, id)| {
+            (*protocol == ShieldedProtocol::Sapling).then(|| Value::from(*id))
+        })
+        .collect();
     let excluded_ptr = Rc::new(excluded);
 
     let notes = stmt_select_notes.query_and_then(
@@ -465,1151 +446,592 @@ pub(crate) fn put_received_note<T: ReceivedSaplingOutput>(
 pub(crate) mod tests {
     use std::{convert::Infallible, num::NonZeroU32};
 
-    use incrementalmerkletree::Hashable;
-    use rusqlite::params;
-    use secrecy::Secret;
-    use zcash_proofs::prover::LocalTxProver;
-
-    use sapling::{
-        self,
-        note_encryption::try_sapling_output_recovery,
-        prover::{OutputProver, SpendProver},
-        zip32::ExtendedSpendingKey,
-        Node, PaymentAddress,
-    };
+    use sapling::zip32::ExtendedSpendingKey;
     use zcash_primitives::{
         block::BlockHash,
-        consensus::{sapling_zip212_enforcement, BranchId},
         legacy::TransparentAddress,
-        memo::{Memo, MemoBytes},
+        memo::MemoBytes,
         transaction::{
             components::{amount::NonNegativeAmount, Amount},
-            fees::{
-                fixed::FeeRule as FixedFeeRule, zip317::FeeError as Zip317FeeError, StandardFeeRule,
-            },
-            Transaction,
+            fees::StandardFeeRule,
         },
-        zip32::Scope,
     };
 
     use zcash_client_backend::{
         address::Address,
         data_api::{
-            self,
-            chain::CommitmentTreeRoot,
             error::Error,
-            wallet::input_selection::{GreedyInputSelector, GreedyInputSelectorError},
-            AccountBirthday, Ratio, WalletCommitmentTrees, WalletRead, WalletWrite,
+            testing::conformance::{
+                check_proposal_serialization_roundtrip, input_selector, test_prover, AddressType,
+                TestBuilder,
+            },
+            wallet::{
+                input_selection::{GreedyInputSelectorError, PrivacyPolicy, PrivacyViolation},
+                pczt::{create_pczt_from_proposal, extract_and_store_pczt, sign_pczt, Pczt},
+            },
+            AccountBirthday, WalletWrite,
         },
-        decrypt_transaction,
-        fees::{fixed, standard, DustOutputPolicy},
+        fees::{self, TransactionShape},
         keys::UnifiedSpendingKey,
         wallet::OvkPolicy,
-        zip321::{self, Payment, TransactionRequest},
+        zip321::{Payment, TransactionRequest},
         ShieldedProtocol,
     };
 
     use crate::{
-        error::SqliteClientError,
-        testing::{input_selector, AddressType, BlockCache, TestBuilder, TestState},
-        wallet::{
-            block_max_scanned, commitment_tree, parse_scope,
-            sapling::select_spendable_sapling_notes, scanning::tests::test_with_canopy_birthday,
-        },
-        AccountId, NoteId, ReceivedNoteId,
+        testing::{BlockCache, TestDbFactory},
+        wallet::sapling::select_spendable_sapling_notes,
+        AccountId,
     };
 
     #[cfg(feature = "transparent-inputs")]
     use {
-        zcash_client_backend::wallet::WalletTransparentOutput,
-        zcash_primitives::transaction::components::{OutPoint, TxOut},
+        nonempty::NonEmpty,
+        zcash_client_backend::{
+            data_api::{
+                wallet::input_selection::{
+                    Proposal, ProposalError, Step, StepOutput, StepOutputIndex,
+                },
+                WalletRead,
+            },
+            fees::TransactionBalance,
+        },
     };
 
-    pub(crate) fn test_prover() -> impl SpendProver + OutputProver {
-        LocalTxProver::bundled()
-    }
-
     #[test]
-    fn send_proposed_transfer() {
+    fn send_proposed_transfer_via_pczt() {
         let mut st = TestBuilder::new()
-            .with_block_cache()
-            .with_test_account(AccountBirthday::from_sapling_activation)
+            .with_block_cache(BlockCache::new())
+            .with_data_store_factory(TestDbFactory)
             .build();
 
-        let (account, usk, _) = st.test_account().unwrap();
-        let dfvk = st.test_account_sapling().unwrap();
+        // The online wallet holds only the viewing key for the account; the spending key
+        // is held by a separate signing device.
+        let usk =
+            UnifiedSpendingKey::from_seed(&st.network(), &[0u8; 32], AccountId::ZERO).unwrap();
+        let account = st
+            .wallet_mut()
+            .import_account_ufvk(
+                &usk.to_unified_full_viewing_key(),
+                AccountBirthday::from_sapling_activation(&st.network()),
+            )
+            .unwrap();
 
-        // Add funds to the wallet in a single note
+        let dfvk = usk.sapling().to_diversifiable_full_viewing_key();
         let value = NonNegativeAmount::const_from_u64(60000);
         let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
         st.scan_cached_blocks(h, 1);
-
-        // Spendable balance matches total balance
         assert_eq!(st.get_total_balance(account), value);
-        assert_eq!(st.get_spendable_balance(account, 1), value);
-
-        assert_eq!(
-            block_max_scanned(&st.wallet().conn, &st.wallet().params)
-                .unwrap()
-                .unwrap()
-                .block_height(),
-            h
-        );
-
-        let to_extsk = ExtendedSpendingKey::master(&[]);
-        let to: Address = to_extsk.default_address().1.into();
-        let request = zip321::TransactionRequest::new(vec![Payment {
-            recipient_address: to,
-            amount: NonNegativeAmount::const_from_u64(10000),
-            memo: None, // this should result in the creation of an empty memo
-            label: None,
-            message: None,
-            other_params: vec![],
-        }])
-        .unwrap();
-
-        // TODO: This test was originally written to use the pre-zip-313 fee rule
-        // and has not yet been updated.
-        #[allow(deprecated)]
-        let fee_rule = StandardFeeRule::PreZip313;
-
-        let change_memo = "Test change memo".parse::<Memo>().unwrap();
-        let change_strategy =
-            standard::SingleOutputChangeStrategy::new(fee_rule, Some(change_memo.clone().into()));
-        let input_selector =
-            &GreedyInputSelector::new(change_strategy, DustOutputPolicy::default());
 
+        let to = ExtendedSpendingKey::master(&[]).default_address().1.into();
+        let amount_sent = NonNegativeAmount::const_from_u64(10000);
         let proposal = st
-            .propose_transfer(
+            .propose_standard_transfer::<Infallible>(
                 account,
-                input_selector,
-                request,
+                StandardFeeRule::Zip317,
+                PrivacyPolicy::FullPrivacy,
                 NonZeroU32::new(1).unwrap(),
+                &to,
+                amount_sent,
+                None,
+                None,
             )
             .unwrap();
 
-        let create_proposed_result =
-            st.create_proposed_transaction::<Infallible, _>(&usk, OvkPolicy::Sender, &proposal);
-        assert_matches!(create_proposed_result, Ok(_));
-
-        let sent_tx_id = create_proposed_result.unwrap();
-
-        // Verify that the sent transaction was stored and that we can decrypt the memos
-        let tx = st
-            .wallet()
-            .get_transaction(sent_tx_id)
-            .expect("Created transaction was stored.");
-        let ufvks = [(account, usk.to_unified_full_viewing_key())]
-            .into_iter()
-            .collect();
-        let decrypted_outputs = decrypt_transaction(&st.network(), h + 1, &tx, &ufvks);
-        assert_eq!(decrypted_outputs.len(), 2);
-
-        let mut found_tx_change_memo = false;
-        let mut found_tx_empty_memo = false;
-        for output in decrypted_outputs {
-            if output.memo == change_memo.clone().into() {
-                found_tx_change_memo = true
-            }
-            if output.memo == Memo::Empty.into() {
-                found_tx_empty_memo = true
-            }
-        }
-        assert!(found_tx_change_memo);
-        assert!(found_tx_empty_memo);
+        // Create the PCZT on the online device.
+        let params = st.network();
+        let pczt = create_pczt_from_proposal::<_, _, Infallible, _, _>(
+            st.wallet_mut(),
+            &params,
+            account,
+            OvkPolicy::Sender,
+            &proposal,
+        )
+        .unwrap();
+        assert_eq!(
+            pczt.fee(),
+            proposal.steps().first().balance().fee_required()
+        );
 
-        // Verify that the stored sent notes match what we're expecting
-        let mut stmt_sent_notes = st
-            .wallet()
-            .conn
-            .prepare(
-                "SELECT output_index
-                FROM sent_notes
-                JOIN transactions ON transactions.id_tx = sent_notes.tx
-                WHERE transactions.txid = ?",
-            )
-            .unwrap();
+        // An unsigned PCZT cannot be extracted.
+        assert_matches!(
+            extract_and_store_pczt(st.wallet_mut(), &params, &pczt),
+            Err(Error::PcztNotSigned)
+        );
 
-        let sent_note_ids = stmt_sent_notes
-            .query(rusqlite::params![sent_tx_id.as_ref()])
-            .unwrap()
-            .mapped(|row| {
-                Ok(NoteId::new(
-                    sent_tx_id,
-                    ShieldedProtocol::Sapling,
-                    row.get(0)?,
-                ))
-            })
-            .collect::<Result<Vec<_>, _>>()
-            .unwrap();
+        // Sign the PCZT on the offline device.
+        let mut pczt_bytes = vec![];
+        pczt.write(&mut pczt_bytes).unwrap();
+        let prover = test_prover();
+        let signed = sign_pczt(
+            &params,
+            &prover,
+            &prover,
+            &usk,
+            Pczt::read(&pczt_bytes[..]).unwrap(),
+        )
+        .unwrap();
 
-        assert_eq!(sent_note_ids.len(), 2);
-
-        // The sent memo should be the empty memo for the sent output, and the
-        // change output's memo should be as specified.
-        let mut found_sent_change_memo = false;
-        let mut found_sent_empty_memo = false;
-        for sent_note_id in sent_note_ids {
-            match st
-                .wallet()
-                .get_memo(sent_note_id)
-                .expect("Note id is valid")
-                .as_ref()
-            {
-                Some(m) if m == &change_memo => {
-                    found_sent_change_memo = true;
-                }
-                Some(m) if m == &Memo::Empty => {
-                    found_sent_empty_memo = true;
-                }
-                Some(other) => panic!("Unexpected memo value: {:?}", other),
-                None => panic!("Memo should not be stored as NULL"),
-            }
-        }
-        assert!(found_sent_change_memo);
-        assert!(found_sent_empty_memo);
+        // Extract the transaction on the online device, and store it in the wallet.
+        let mut signed_bytes = vec![];
+        signed.write(&mut signed_bytes).unwrap();
+        let signed = Pczt::read(&signed_bytes[..]).unwrap();
+        let txid = extract_and_store_pczt(st.wallet_mut(), &params, &signed).unwrap();
+        assert_eq!(signed.transaction().map(|tx| tx.txid()), Some(txid));
 
-        // Check that querying for a nonexistent sent note returns None
-        assert_matches!(
-            st.wallet()
-                .get_memo(NoteId::new(sent_tx_id, ShieldedProtocol::Sapling, 12345)),
-            Ok(None)
+        // The change output of the transaction is visible to the wallet once mined.
+        let (h, _) = st.generate_next_block_including(txid);
+        st.scan_cached_blocks(h, 1);
+        assert_eq!(
+            st.get_total_balance(account),
+            (value - (amount_sent + pczt.fee()).unwrap()).unwrap()
         );
     }
 
     #[test]
-    #[allow(deprecated)]
-    fn create_to_address_fails_on_incorrect_usk() {
+    fn privacy_policy_prevents_revealed_recipients() {
         let mut st = TestBuilder::new()
+            .with_block_cache(BlockCache::new())
+            .with_data_store_factory(TestDbFactory)
             .with_test_account(AccountBirthday::from_sapling_activation)
             .build();
+
+        let (account, _, _) = st.test_account().unwrap();
         let dfvk = st.test_account_sapling().unwrap();
-        let to = dfvk.default_address().1.into();
 
-        // Create a USK that doesn't exist in the wallet
-        let acct1 = AccountId::try_from(1).unwrap();
-        let usk1 = UnifiedSpendingKey::from_seed(&st.network(), &[1u8; 32], acct1).unwrap();
+        // Add funds to the wallet in a single note
+        let value = NonNegativeAmount::const_from_u64(60000);
+        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.scan_cached_blocks(h, 1);
+
+        let request = TransactionRequest::new(vec![Payment {
+            recipient_address: TransparentAddress::PublicKeyHash([7; 20]).into(),
+            amount: NonNegativeAmount::const_from_u64(10000),
+            memo: None,
+            label: None,
+            message: None,
+            other_params: vec![],
+        }])
+        .unwrap();
+
+        let input_selector = input_selector(StandardFeeRule::Zip317, None);
+        let min_confirmations = NonZeroU32::new(1).unwrap();
+
+        // Paying a transparent recipient is forbidden by policies that do not allow revealed
+        // recipients.
+        for policy in [
+            PrivacyPolicy::FullPrivacy,
+            PrivacyPolicy::AllowRevealedAmounts,
+        ] {
+            assert_matches!(
+                st.propose_transfer(
+                    account,
+                    &input_selector,
+                    request.clone(),
+                    policy,
+                    min_confirmations,
+                ),
+                Err(Error::PrivacyPolicyViolation {
+                    policy: p,
+                    violation: PrivacyViolation::RevealedRecipients,
+                }) if p == policy
+            );
+        }
 
-        // Attempting to spend with a USK that is not in the wallet results in an error
+        // The same request may be proposed under a policy that allows revealed recipients.
         assert_matches!(
-            st.create_spend_to_address(
-                &usk1,
-                &to,
-                NonNegativeAmount::const_from_u64(1),
-                None,
-                OvkPolicy::Sender,
-                NonZeroU32::new(1).unwrap(),
-                None
+            st.propose_transfer(
+                account,
+                &input_selector,
+                request,
+                PrivacyPolicy::AllowRevealedRecipients,
+                min_confirmations,
             ),
-            Err(data_api::error::Error::KeyNotRecognized)
+            Ok(_)
         );
     }
 
     #[test]
-    #[allow(deprecated)]
-    fn proposal_fails_with_no_blocks() {
+    fn estimate_fee_matches_proposal() {
         let mut st = TestBuilder::new()
+            .with_block_cache(BlockCache::new())
+            .with_data_store_factory(TestDbFactory)
             .with_test_account(AccountBirthday::from_sapling_activation)
             .build();
 
         let (account, _, _) = st.test_account().unwrap();
         let dfvk = st.test_account_sapling().unwrap();
-        let to = dfvk.default_address().1.into();
 
-        // Wallet summary is not yet available
-        assert_eq!(st.get_wallet_summary(0), None);
+        // Add funds to the wallet in two notes
+        let value = NonNegativeAmount::const_from_u64(30000);
+        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        st.scan_cached_blocks(h, 2);
 
-        // We cannot do anything if we aren't synchronised
-        assert_matches!(
-            st.propose_standard_transfer::<Infallible>(
+        let to: Address = ExtendedSpendingKey::master(&[]).default_address().1.into();
+        let request = TransactionRequest::new(vec![Payment {
+            recipient_address: to,
+            amount: NonNegativeAmount::const_from_u64(40000),
+            memo: None,
+            label: None,
+            message: None,
+            other_params: vec![],
+        }])
+        .unwrap();
+
+        let input_selector = input_selector(StandardFeeRule::Zip317, None);
+        let min_confirmations = NonZeroU32::new(1).unwrap();
+
+        // Spending both notes to a Sapling recipient, with change, requires two logical actions.
+        let estimate = st
+            .estimate_fee(
                 account,
-                StandardFeeRule::PreZip313,
-                NonZeroU32::new(1).unwrap(),
-                &to,
-                NonNegativeAmount::const_from_u64(1),
-                None,
-                None
-            ),
-            Err(data_api::error::Error::ScanRequired)
+                &input_selector,
+                request.clone(),
+                PrivacyPolicy::FullPrivacy,
+                min_confirmations,
+            )
+            .unwrap();
+        assert_eq!(estimate, NonNegativeAmount::const_from_u64(10000));
+
+        // The estimate agrees with the fee computed from the shape of the transaction alone.
+        assert_eq!(
+            fees::estimate_fee(
+                &st.network(),
+                h + 2,
+                &StandardFeeRule::Zip317,
+                &TransactionShape::new(0, 0, 2, 2, 0),
+            )
+            .unwrap(),
+            estimate
+        );
+
+        // Estimating the fee does not prevent the same notes from being proposed.
+        let proposal = st
+            .propose_transfer(
+                account,
+                &input_selector,
+                request,
+                PrivacyPolicy::FullPrivacy,
+                min_confirmations,
+            )
+            .unwrap();
+        let step = proposal.steps().first();
+        assert_eq!(step.balance().fee_required(), estimate);
+        assert_eq!(
+            step.shielded_inputs().map(|inputs| inputs.notes().len()),
+            Some(2)
         );
     }
 
     #[test]
-    fn spend_fails_on_unverified_notes() {
+    fn send_max_transfer_spends_entire_balance() {
         let mut st = TestBuilder::new()
-            .with_block_cache()
+            .with_block_cache(BlockCache::new())
+            .with_data_store_factory(TestDbFactory)
             .with_test_account(AccountBirthday::from_sapling_activation)
             .build();
 
         let (account, usk, _) = st.test_account().unwrap();
         let dfvk = st.test_account_sapling().unwrap();
 
-        // Add funds to the wallet in a single note
-        let value = NonNegativeAmount::const_from_u64(50000);
-        let (h1, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
-        st.scan_cached_blocks(h1, 1);
-
-        // Spendable balance matches total balance at 1 confirmation.
-        assert_eq!(st.get_total_balance(account), value);
-        assert_eq!(st.get_spendable_balance(account, 1), value);
-
-        // Value is considered pending at 10 confirmations.
-        assert_eq!(st.get_pending_shielded_balance(account, 10), value);
-        assert_eq!(
-            st.get_spendable_balance(account, 10),
-            NonNegativeAmount::ZERO
-        );
-
-        // Wallet is fully scanned
-        let summary = st.get_wallet_summary(1);
-        assert_eq!(
-            summary.and_then(|s| s.scan_progress()),
-            Some(Ratio::new(1, 1))
+        // Add funds to the wallet in three notes
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(50000),
         );
+        for value in [30000, 20000] {
+            st.generate_next_block(
+                &dfvk,
+                AddressType::DefaultExternal,
+                NonNegativeAmount::const_from_u64(value),
+            );
+        }
+        st.scan_cached_blocks(h, 3);
 
-        // Add more funds to the wallet in a second note
-        let (h2, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
-        st.scan_cached_blocks(h2, 1);
-
-        // Verified balance does not include the second note
-        let total = (value + value).unwrap();
-        assert_eq!(st.get_spendable_balance(account, 2), value);
-        assert_eq!(st.get_pending_shielded_balance(account, 2), value);
-        assert_eq!(st.get_total_balance(account), total);
+        let total = NonNegativeAmount::const_from_u64(100000);
+        assert_eq!(st.get_spendable_balance(account, 1), total);
 
-        // Wallet is still fully scanned
-        let summary = st.get_wallet_summary(1);
-        assert_eq!(
-            summary.and_then(|s| s.scan_progress()),
-            Some(Ratio::new(2, 2))
-        );
+        let input_selector = input_selector(StandardFeeRule::Zip317, None);
+        let min_confirmations = NonZeroU32::new(1).unwrap();
 
-        // Spend fails because there are insufficient verified notes
-        let extsk2 = ExtendedSpendingKey::master(&[]);
-        let to = extsk2.default_address().1.into();
+        // A memo may not be sent to a transparent recipient, and a transparent recipient may
+        // only be paid under a policy that allows revealed recipients.
+        let taddr = TransparentAddress::PublicKeyHash([7; 20]).into();
         assert_matches!(
-            st.propose_standard_transfer::<Infallible>(
+            st.propose_send_max_transfer(
                 account,
-                StandardFeeRule::Zip317,
-                NonZeroU32::new(2).unwrap(),
-                &to,
-                NonNegativeAmount::const_from_u64(70000),
-                None,
-                None
+                &input_selector,
+                &taddr,
+                Some(MemoBytes::empty()),
+                PrivacyPolicy::AllowRevealedRecipients,
+                min_confirmations,
             ),
-            Err(data_api::error::Error::InsufficientFunds {
-                available,
-                required
-            })
-            if available == NonNegativeAmount::const_from_u64(50000)
-                && required == NonNegativeAmount::const_from_u64(80000)
+            Err(Error::MemoForbidden)
         );
-
-        // Mine blocks SAPLING_ACTIVATION_HEIGHT + 2 to 9 until just before the second
-        // note is verified
-        for _ in 2..10 {
-            st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
-        }
-        st.scan_cached_blocks(h2 + 1, 8);
-
-        // Total balance is value * number of blocks scanned (10).
-        assert_eq!(st.get_total_balance(account), (value * 10).unwrap());
-
-        // Spend still fails
         assert_matches!(
-            st.propose_standard_transfer::<Infallible>(
+            st.propose_send_max_transfer(
                 account,
-                StandardFeeRule::Zip317,
-                NonZeroU32::new(10).unwrap(),
-                &to,
-                NonNegativeAmount::const_from_u64(70000),
+                &input_selector,
+                &taddr,
                 None,
-                None
+                PrivacyPolicy::AllowRevealedAmounts,
+                min_confirmations,
             ),
-            Err(data_api::error::Error::InsufficientFunds {
-                available,
-                required
+            Err(Error::PrivacyPolicyViolation {
+                policy: PrivacyPolicy::AllowRevealedAmounts,
+                violation: PrivacyViolation::RevealedRecipients,
             })
-            if available == NonNegativeAmount::const_from_u64(50000)
-                && required == NonNegativeAmount::const_from_u64(80000)
-        );
-
-        // Mine block 11 so that the second note becomes verified
-        let (h11, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
-        st.scan_cached_blocks(h11, 1);
-
-        // Total balance is value * number of blocks scanned (11).
-        assert_eq!(st.get_total_balance(account), (value * 11).unwrap());
-        // Spendable balance at 10 confirmations is value * 2.
-        assert_eq!(st.get_spendable_balance(account, 10), (value * 2).unwrap());
-        assert_eq!(
-            st.get_pending_shielded_balance(account, 10),
-            (value * 9).unwrap()
         );
 
-        // Should now be able to generate a proposal
-        let amount_sent = NonNegativeAmount::from_u64(70000).unwrap();
-        let min_confirmations = NonZeroU32::new(10).unwrap();
+        let to = ExtendedSpendingKey::master(&[]).default_address().1.into();
         let proposal = st
-            .propose_standard_transfer::<Infallible>(
+            .propose_send_max_transfer(
                 account,
-                StandardFeeRule::Zip317,
-                min_confirmations,
+                &input_selector,
                 &to,
-                amount_sent,
-                None,
                 None,
+                PrivacyPolicy::FullPrivacy,
+                min_confirmations,
             )
             .unwrap();
 
-        // Executing the proposal should succeed
+        // All three notes are spent, paying a fee for three logical actions and
+        // producing no change.
+        let fee = NonNegativeAmount::const_from_u64(15000);
+        let step = proposal.steps().first();
+        assert_eq!(step.shielded_inputs().map(|i| i.notes().len()), Some(3));
+        assert_eq!(step.balance().fee_required(), fee);
+        assert!(step.balance().proposed_change().is_empty());
+        assert_eq!(
+            step.transaction_request().total(),
+            Ok((total - fee).unwrap())
+        );
+
         let txid = st
-            .create_proposed_transaction::<Infallible, _>(&usk, OvkPolicy::Sender, &proposal)
-            .unwrap();
+            .create_proposed_transactions::<Infallible, _>(&usk, OvkPolicy::Sender, &proposal)
+            .unwrap()[0];
 
         let (h, _) = st.generate_next_block_including(txid);
         st.scan_cached_blocks(h, 1);
 
-        // TODO: send to an account so that we can check its balance.
-        assert_eq!(
-            st.get_total_balance(account),
-            ((value * 11).unwrap()
-                - (amount_sent + NonNegativeAmount::from_u64(10000).unwrap()).unwrap())
-            .unwrap()
-        );
+        assert_eq!(st.get_total_balance(account), NonNegativeAmount::ZERO);
     }
 
     #[test]
-    fn spend_fails_on_locked_notes() {
+    fn consolidation_merges_smallest_notes() {
         let mut st = TestBuilder::new()
-            .with_block_cache()
+            .with_block_cache(BlockCache::new())
+            .with_data_store_factory(TestDbFactory)
             .with_test_account(AccountBirthday::from_sapling_activation)
             .build();
 
         let (account, usk, _) = st.test_account().unwrap();
         let dfvk = st.test_account_sapling().unwrap();
 
-        // TODO: This test was originally written to use the pre-zip-313 fee rule
-        // and has not yet been updated.
-        #[allow(deprecated)]
-        let fee_rule = StandardFeeRule::PreZip313;
-
-        // Add funds to the wallet in a single note
-        let value = NonNegativeAmount::const_from_u64(50000);
-        let (h1, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
-        st.scan_cached_blocks(h1, 1);
-
-        // Spendable balance matches total balance at 1 confirmation.
-        assert_eq!(st.get_total_balance(account), value);
-        assert_eq!(st.get_spendable_balance(account, 1), value);
-
-        // Send some of the funds to another address, but don't mine the tx.
-        let extsk2 = ExtendedSpendingKey::master(&[]);
-        let to = extsk2.default_address().1.into();
-        let min_confirmations = NonZeroU32::new(1).unwrap();
-        let proposal = st
-            .propose_standard_transfer::<Infallible>(
-                account,
-                fee_rule,
-                min_confirmations,
-                &to,
-                NonNegativeAmount::const_from_u64(15000),
-                None,
-                None,
-            )
-            .unwrap();
-
-        // Executing the proposal should succeed
-        assert_matches!(
-            st.create_proposed_transaction::<Infallible, _>(&usk, OvkPolicy::Sender, &proposal,),
-            Ok(_)
-        );
-
-        // A second proposal fails because there are no usable notes
-        assert_matches!(
-            st.propose_standard_transfer::<Infallible>(
-                account,
-                fee_rule,
-                NonZeroU32::new(1).unwrap(),
-                &to,
-                NonNegativeAmount::const_from_u64(2000),
-                None,
-                None
-            ),
-            Err(data_api::error::Error::InsufficientFunds {
-                available,
-                required
-            })
-            if available == NonNegativeAmount::ZERO && required == NonNegativeAmount::const_from_u64(12000)
+        // Add two dust notes and four notes that are worth spending to the wallet
+        let (h, _, _) = st.generate_next_block(
+            &dfvk,
+            AddressType::DefaultExternal,
+            NonNegativeAmount::const_from_u64(1000),
         );
-
-        // Mine blocks SAPLING_ACTIVATION_HEIGHT + 1 to 41 (that don't send us funds)
-        // until just before the first transaction expires
-        for i in 1..42 {
+        for value in [1000, 10000, 15000, 20000, 30000] {
             st.generate_next_block(
-                &ExtendedSpendingKey::master(&[i as u8]).to_diversifiable_full_viewing_key(),
+                &dfvk,
                 AddressType::DefaultExternal,
-                value,
+                NonNegativeAmount::const_from_u64(value),
             );
         }
-        st.scan_cached_blocks(h1 + 1, 41);
+        st.scan_cached_blocks(h, 6);
+
+        let total = NonNegativeAmount::const_from_u64(77000);
+        assert_eq!(st.get_spendable_balance(account, 1), total);
+
+        let input_selector = input_selector(StandardFeeRule::Zip317, None);
+        let min_confirmations = NonZeroU32::new(1).unwrap();
 
-        // Second proposal still fails
+        // A single note cannot be consolidated.
         assert_matches!(
-            st.propose_standard_transfer::<Infallible>(
+            st.propose_consolidation(
                 account,
-                fee_rule,
-                NonZeroU32::new(1).unwrap(),
-                &to,
-                NonNegativeAmount::const_from_u64(2000),
-                None,
-                None
+                &input_selector,
+                1,
+                ShieldedProtocol::Sapling,
+                PrivacyPolicy::FullPrivacy,
+                min_confirmations,
             ),
-            Err(data_api::error::Error::InsufficientFunds {
-                available,
-                required
-            })
-            if available == NonNegativeAmount::ZERO && required == NonNegativeAmount::const_from_u64(12000)
-        );
-
-        // Mine block SAPLING_ACTIVATION_HEIGHT + 42 so that the first transaction expires
-        let (h43, _, _) = st.generate_next_block(
-            &ExtendedSpendingKey::master(&[42]).to_diversifiable_full_viewing_key(),
-            AddressType::DefaultExternal,
-            value,
+            Err(Error::NoteSelection(
+                GreedyInputSelectorError::NothingToConsolidate
+            ))
         );
-        st.scan_cached_blocks(h43, 1);
 
-        // Spendable balance matches total balance at 1 confirmation.
-        assert_eq!(st.get_total_balance(account), value);
-        assert_eq!(st.get_spendable_balance(account, 1), value);
-
-        // Second spend should now succeed
-        let amount_sent2 = NonNegativeAmount::const_from_u64(2000);
-        let min_confirmations = NonZeroU32::new(1).unwrap();
         let proposal = st
-            .propose_standard_transfer::<Infallible>(
+            .propose_consolidation(
                 account,
-                fee_rule,
+                &input_selector,
+                3,
+                ShieldedProtocol::Sapling,
+                PrivacyPolicy::FullPrivacy,
                 min_confirmations,
-                &to,
-                amount_sent2,
-                None,
-                None,
             )
             .unwrap();
 
-        let txid2 = st
-            .create_proposed_transaction::<Infallible, _>(&usk, OvkPolicy::Sender, &proposal)
-            .unwrap();
-
-        let (h, _) = st.generate_next_block_including(txid2);
-        st.scan_cached_blocks(h, 1);
-
-        // TODO: send to an account so that we can check its balance.
+        // The dust notes are skipped, and the three smallest remaining notes are merged
+        // into a single change note, paying a fee for three logical actions.
+        let fee = NonNegativeAmount::const_from_u64(15000);
+        let step = proposal.steps().first();
+        assert_eq!(step.transaction_request().payments().len(), 0);
         assert_eq!(
-            st.get_total_balance(account),
-            (value - (amount_sent2 + NonNegativeAmount::from_u64(10000).unwrap()).unwrap())
-                .unwrap()
-        );
-    }
-
-    #[test]
-    fn ovk_policy_prevents_recovery_from_chain() {
-        let mut st = TestBuilder::new()
-            .with_block_cache()
-            .with_test_account(AccountBirthday::from_sapling_activation)
-            .build();
-
-        let (account, usk, _) = st.test_account().unwrap();
-        let dfvk = st.test_account_sapling().unwrap();
-
-        // Add funds to the wallet in a single note
-        let value = NonNegativeAmount::const_from_u64(50000);
-        let (h1, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
-        st.scan_cached_blocks(h1, 1);
-
-        // Spendable balance matches total balance at 1 confirmation.
-        assert_eq!(st.get_total_balance(account), value);
-        assert_eq!(st.get_spendable_balance(account, 1), value);
-
-        let extsk2 = ExtendedSpendingKey::master(&[]);
-        let addr2 = extsk2.default_address().1;
-        let to = addr2.into();
-
-        // TODO: This test was originally written to use the pre-zip-313 fee rule
-        // and has not yet been updated.
-        #[allow(deprecated)]
-        let fee_rule = StandardFeeRule::PreZip313;
-
-        #[allow(clippy::type_complexity)]
-        let send_and_recover_with_policy = |st: &mut TestState<BlockCache>,
-                                            ovk_policy|
-         -> Result<
-            Option<(sapling::Note, PaymentAddress, MemoBytes)>,
-            Error<
-                SqliteClientError,
-                commitment_tree::Error,
-                GreedyInputSelectorError<Zip317FeeError, ReceivedNoteId>,
-                Zip317FeeError,
-            >,
-        > {
-            let min_confirmations = NonZeroU32::new(1).unwrap();
-            let proposal = st.propose_standard_transfer(
-                account,
-                fee_rule,
-                min_confirmations,
-                &to,
-                NonNegativeAmount::const_from_u64(15000),
-                None,
-                None,
-            )?;
-
-            // Executing the proposal should succeed
-            let txid = st.create_proposed_transaction(&usk, ovk_policy, &proposal)?;
-
-            // Fetch the transaction from the database
-            let raw_tx: Vec<_> = st
-                .wallet()
-                .conn
-                .query_row(
-                    "SELECT raw FROM transactions
-                    WHERE txid = ?",
-                    [txid.as_ref()],
-                    |row| row.get(0),
-                )
-                .unwrap();
-            let tx = Transaction::read(&raw_tx[..], BranchId::Canopy).unwrap();
-
-            for output in tx.sapling_bundle().unwrap().shielded_outputs() {
-                // Find the output that decrypts with the external OVK
-                let result = try_sapling_output_recovery(
-                    &dfvk.to_ovk(Scope::External),
-                    output,
-                    sapling_zip212_enforcement(&st.network(), h1),
-                );
-
-                if result.is_some() {
-                    return Ok(result.map(|(note, addr, memo)| {
-                        (
-                            note,
-                            addr,
-                            MemoBytes::from_bytes(&memo).expect("correct length"),
-                        )
-                    }));
-                }
-            }
-
-            Ok(None)
-        };
-
-        // Send some of the funds to another address, keeping history.
-        // The recipient output is decryptable by the sender.
-        assert_matches!(
-            send_and_recover_with_policy(&mut st, OvkPolicy::Sender),
-            Ok(Some((_, recovered_to, _))) if recovered_to == addr2
+            step.shielded_inputs().map(|i| i
+                .notes()
+                .iter()
+                .map(|n| n.note().value())
+                .collect::<Vec<_>>()),
+            Some(
+                [10000, 15000, 20000]
+                    .into_iter()
+                    .map(NonNegativeAmount::const_from_u64)
+                    .collect()
+            )
         );
-
-        // Mine blocks SAPLING_ACTIVATION_HEIGHT + 1 to 42 (that don't send us funds)
-        // so that the first transaction expires
-        for i in 1..=42 {
-            st.generate_next_block(
-                &ExtendedSpendingKey::master(&[i as u8]).to_diversifiable_full_viewing_key(),
-                AddressType::DefaultExternal,
-                value,
-            );
-        }
-        st.scan_cached_blocks(h1 + 1, 42);
-
-        // Send the funds again, discarding history.
-        // Neither transaction output is decryptable by the sender.
+        assert_eq!(step.balance().fee_required(), fee);
         assert_matches!(
-            send_and_recover_with_policy(&mut st, OvkPolicy::Discard),
-            Ok(None)
+            &step.balance().proposed_change()[..],
+            [change] if change.value() == NonNegativeAmount::const_from_u64(30000)
+                && change.output_pool() == ShieldedProtocol::Sapling
         );
-    }
 
-    #[test]
-    fn spend_succeeds_to_t_addr_zero_change() {
-        let mut st = TestBuilder::new()
-            .with_block_cache()
-            .with_test_account(AccountBirthday::from_sapling_activation)
-            .build();
+        check_proposal_serialization_roundtrip(&st.network(), st.wallet(), &proposal);
 
-        let (account, usk, _) = st.test_account().unwrap();
-        let dfvk = st.test_account_sapling().unwrap();
+        let txid = st
+            .create_proposed_transactions::<Infallible, _>(&usk, OvkPolicy::Sender, &proposal)
+            .unwrap()[0];
 
-        // Add funds to the wallet in a single note
-        let value = NonNegativeAmount::const_from_u64(60000);
-        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
+        let (h, _) = st.generate_next_block_including(txid);
         st.scan_cached_blocks(h, 1);
 
-        // Spendable balance matches total balance at 1 confirmation.
-        assert_eq!(st.get_total_balance(account), value);
-        assert_eq!(st.get_spendable_balance(account, 1), value);
-
-        // TODO: This test was originally written to use the pre-zip-313 fee rule
-        // and has not yet been updated.
-        #[allow(deprecated)]
-        let fee_rule = StandardFeeRule::PreZip313;
-
-        // TODO: generate_next_block_from_tx does not currently support transparent outputs.
-        let to = TransparentAddress::PublicKeyHash([7; 20]).into();
-        let min_confirmations = NonZeroU32::new(1).unwrap();
-        let proposal = st
-            .propose_standard_transfer::<Infallible>(
-                account,
-                fee_rule,
-                min_confirmations,
-                &to,
-                NonNegativeAmount::const_from_u64(50000),
-                None,
-                None,
-            )
-            .unwrap();
-
-        // Executing the proposal should succeed
-        assert_matches!(
-            st.create_proposed_transaction::<Infallible, _>(&usk, OvkPolicy::Sender, &proposal),
-            Ok(_)
-        );
+        assert_eq!(st.get_total_balance(account), (total - fee).unwrap());
     }
 
     #[test]
-    fn change_note_spends_succeed() {
+    #[cfg(feature = "transparent-inputs")]
+    fn send_multi_step_proposed_transfer() {
         let mut st = TestBuilder::new()
-            .with_block_cache()
+            .with_block_cache(BlockCache::new())
+            .with_data_store_factory(TestDbFactory)
             .with_test_account(AccountBirthday::from_sapling_activation)
             .build();
 
         let (account, usk, _) = st.test_account().unwrap();
         let dfvk = st.test_account_sapling().unwrap();
 
-        // Add funds to the wallet in a single note owned by the internal spending key
         let value = NonNegativeAmount::const_from_u64(60000);
-        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::Internal, value);
+        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
         st.scan_cached_blocks(h, 1);
 
-        // Spendable balance matches total balance at 1 confirmation.
-        assert_eq!(st.get_total_balance(account), value);
-        assert_eq!(st.get_spendable_balance(account, 1), value);
-
-        // Value is considered pending at 10 confirmations.
-        assert_eq!(st.get_pending_shielded_balance(account, 10), value);
-        assert_eq!(
-            st.get_spendable_balance(account, 10),
-            NonNegativeAmount::ZERO
-        );
-
-        let change_note_scope = st.wallet().conn.query_row(
-            "SELECT recipient_key_scope
-             FROM sapling_received_notes
-             WHERE value = ?",
-            params![u64::from(value)],
-            |row| Ok(parse_scope(row.get(0)?)),
-        );
-        assert_matches!(change_note_scope, Ok(Some(Scope::Internal)));
+        // The first step pays from the shielded balance to the account's own transparent
+        // address.
+        let uaddr = st.wallet().get_current_address(account).unwrap().unwrap();
+        let own_taddr = *uaddr.transparent().unwrap();
 
-        // TODO: This test was originally written to use the pre-zip-313 fee rule
-        // and has not yet been updated.
         #[allow(deprecated)]
         let fee_rule = StandardFeeRule::PreZip313;
-
-        // TODO: generate_next_block_from_tx does not currently support transparent outputs.
-        let to = TransparentAddress::PublicKeyHash([7; 20]).into();
-        let min_confirmations = NonZeroU32::new(1).unwrap();
-        let proposal = st
+        let fee = NonNegativeAmount::const_from_u64(10000);
+        let intermediate = NonNegativeAmount::const_from_u64(20000);
+        let proposal0 = st
             .propose_standard_transfer::<Infallible>(
                 account,
                 fee_rule,
-                min_confirmations,
-                &to,
-                NonNegativeAmount::const_from_u64(50000),
+                PrivacyPolicy::AllowRevealedRecipients,
+                NonZeroU32::new(1).unwrap(),
+                &Address::Transparent(own_taddr),
+                intermediate,
                 None,
                 None,
             )
             .unwrap();
-
-        // Executing the proposal should succeed
-        assert_matches!(
-            st.create_proposed_transaction::<Infallible, _>(&usk, OvkPolicy::Sender, &proposal),
-            Ok(_)
-        );
-    }
-
-    #[test]
-    fn external_address_change_spends_detected_in_restore_from_seed() {
-        let mut st = TestBuilder::new().with_block_cache().build();
-
-        // Add two accounts to the wallet.
-        let seed = Secret::new([0u8; 32].to_vec());
-        let birthday = AccountBirthday::from_sapling_activation(&st.network());
-        let (_, usk) = st
-            .wallet_mut()
-            .create_account(&seed, birthday.clone())
-            .unwrap();
-        let dfvk = usk.sapling().to_diversifiable_full_viewing_key();
-
-        let (_, usk2) = st
-            .wallet_mut()
-            .create_account(&seed, birthday.clone())
-            .unwrap();
-        let dfvk2 = usk2.sapling().to_diversifiable_full_viewing_key();
-
-        // Add funds to the wallet in a single note
-        let value = NonNegativeAmount::from_u64(100000).unwrap();
-        let (h, _, _) = st.generate_next_block(&dfvk, AddressType::DefaultExternal, value);
-        st.scan_cached_blocks(h, 1);
-
-        // Spendable balance matches total balance
-        assert_eq!(st.get_total_balance(AccountId::ZERO), value);
-        assert_eq!(st.get_spendable_balance(AccountId::ZERO, 1), value);
-        assert_eq!(
-            st.get_total_balance(AccountId::try_from(1).unwrap()),
-            NonNegativeAmount::ZERO,
-        );
-
-        let amount_sent = NonNegativeAmount::from_u64(20000).unwrap();
-        let amount_legacy_change = NonNegativeAmount::from_u64(30000).unwrap();
-        let addr = dfvk.default_address().1;
-        let addr2 = dfvk2.default_address().1;
-        let req = TransactionRequest::new(vec![
-            // payment to an external recipient
-            Payment {
-                recipient_address: Address::Sapling(addr2),
-                amount: amount_sent,
-                memo: None,
-                label: None,
-                message: None,
-                other_params: vec![],
-            },
-            // payment back to the originating wallet, simulating legacy change
-            Payment {
-                recipient_address: Address::Sapling(addr),
-                amount: amount_legacy_change,
-                memo: None,
-                label: None,
-                message: None,
-                other_params: vec![],
-            },
-        ])
-        .unwrap();
-
-        #[allow(deprecated)]
-        let fee_rule = FixedFeeRule::standard();
-        let input_selector = GreedyInputSelector::new(
-            fixed::SingleOutputChangeStrategy::new(fee_rule, None),
-            DustOutputPolicy::default(),
-        );
-
-        let txid = st
-            .spend(
-                &input_selector,
-                &usk,
-                req,
-                OvkPolicy::Sender,
-                NonZeroU32::new(1).unwrap(),
-            )
-            .unwrap();
-
-        let amount_left = (value - (amount_sent + fee_rule.fixed_fee()).unwrap()).unwrap();
-        let pending_change = (amount_left - amount_legacy_change).unwrap();
-
-        // The "legacy change" is not counted by get_pending_change().
-        assert_eq!(st.get_pending_change(AccountId::ZERO, 1), pending_change);
-        // We spent the only note so we only have pending change.
-        assert_eq!(st.get_total_balance(AccountId::ZERO), pending_change);
-
-        let (h, _) = st.generate_next_block_including(txid);
-        st.scan_cached_blocks(h, 1);
-
-        assert_eq!(
-            st.get_total_balance(AccountId::try_from(1).unwrap()),
-            amount_sent,
-        );
-        assert_eq!(st.get_total_balance(AccountId::ZERO), amount_left);
-
-        st.reset();
-
-        // Account creation and DFVK derivation should be deterministic.
-        let (_, restored_usk) = st
-            .wallet_mut()
-            .create_account(&seed, birthday.clone())
-            .unwrap();
-        assert_eq!(
-            restored_usk
-                .sapling()
-                .to_diversifiable_full_viewing_key()
-                .to_bytes(),
-            dfvk.to_bytes()
-        );
-
-        let (_, restored_usk2) = st.wallet_mut().create_account(&seed, birthday).unwrap();
-        assert_eq!(
-            restored_usk2
-                .sapling()
-                .to_diversifiable_full_viewing_key()
-                .to_bytes(),
-            dfvk2.to_bytes()
-        );
-
-        st.scan_cached_blocks(st.sapling_activation_height(), 2);
-
-        assert_eq!(
-            st.get_total_balance(AccountId::try_from(1).unwrap()),
-            amount_sent,
-        );
-        assert_eq!(st.get_total_balance(AccountId::ZERO), amount_left);
-    }
-
-    #[test]
-    fn zip317_spend() {
-        let mut st = TestBuilder::new()
-            .with_block_cache()
-            .with_test_account(AccountBirthday::from_sapling_activation)
-            .build();
-
-        let (account, usk, _) = st.test_account().unwrap();
-        let dfvk = st.test_account_sapling().unwrap();
-
-        // Add funds to the wallet
-        let (h1, _, _) = st.generate_next_block(
-            &dfvk,
-            AddressType::Internal,
-            NonNegativeAmount::const_from_u64(50000),
-        );
-
-        // Add 10 dust notes to the wallet
-        for _ in 1..=10 {
-            st.generate_next_block(
-                &dfvk,
-                AddressType::DefaultExternal,
-                NonNegativeAmount::const_from_u64(1000),
-            );
-        }
-
-        st.scan_cached_blocks(h1, 11);
-
-        // Spendable balance matches total balance
-        let total = NonNegativeAmount::const_from_u64(60000);
-        assert_eq!(st.get_total_balance(account), total);
-        assert_eq!(st.get_spendable_balance(account, 1), total);
-
-        let input_selector = input_selector(StandardFeeRule::Zip317, None);
-
-        // This first request will fail due to insufficient non-dust funds
-        let req = TransactionRequest::new(vec![Payment {
-            recipient_address: Address::Sapling(dfvk.default_address().1),
-            amount: NonNegativeAmount::const_from_u64(50000),
+        let step0 = proposal0.steps().first().clone();
+
+        // The second step spends that transparent output to pay an external recipient.
+        let to = TransparentAddress::PublicKeyHash([7; 20]);
+        let step0_payment = StepOutput::new(0, StepOutputIndex::Payment(0));
+        let step1_request = TransactionRequest::new(vec![Payment {
+            recipient_address: Address::Transparent(to),
+            amount: (intermediate - fee).unwrap(),
             memo: None,
             label: None,
             message: None,
             other_params: vec![],
         }])
         .unwrap();
+        let step1 = Step::from_parts(
+            &[step0.clone()],
+            step1_request.clone(),
+            vec![],
+            None,
+            vec![step0_payment],
+            TransactionBalance::new(vec![], fee).unwrap(),
+            false,
+        )
+        .unwrap();
 
+        // A step may not spend the output of a prior step more than once.
         assert_matches!(
-            st.spend(
-                &input_selector,
-                &usk,
-                req,
-                OvkPolicy::Sender,
-                NonZeroU32::new(1).unwrap(),
+            Proposal::multi_step(
+                fee_rule,
+                proposal0.min_target_height(),
+                NonEmpty::from_vec(vec![step0.clone(), step1.clone(), step1.clone()]).unwrap(),
             ),
-            Err(Error::InsufficientFunds { available, required })
-                if available == NonNegativeAmount::const_from_u64(51000)
-                && required == NonNegativeAmount::const_from_u64(60000)
-        );
-
-        // This request will succeed, spending a single dust input to pay the 10000
-        // ZAT fee in addition to the 41000 ZAT output to the recipient
-        let req = TransactionRequest::new(vec![Payment {
-            recipient_address: Address::Sapling(dfvk.default_address().1),
-            amount: NonNegativeAmount::const_from_u64(41000),
-            memo: None,
-            label: None,
-            message: None,
-            other_params: vec![],
-        }])
-        .unwrap();
-
-        let txid = st
-            .spend(
-                &input_selector,
-                &usk,
-                req,
-                OvkPolicy::Sender,
-                NonZeroU32::new(1).unwrap(),
-            )
-            .unwrap();
-
-        let (h, _) = st.generate_next_block_including(txid);
-        st.scan_cached_blocks(h, 1);
-
-        // TODO: send to an account so that we can check its balance.
-        // We sent back to the same account so the amount_sent should be included
-        // in the total balance.
-        assert_eq!(
-            st.get_total_balance(account),
-            (total - NonNegativeAmount::const_from_u64(10000)).unwrap()
+            Err(ProposalError::StepDoubleSpend(r)) if r == step0_payment
         );
-    }
 
-    #[test]
-    #[cfg(feature = "transparent-inputs")]
-    fn shield_transparent() {
-        let mut st = TestBuilder::new()
-            .with_block_cache()
-            .with_test_account(AccountBirthday::from_sapling_activation)
-            .build();
-
-        let (account_id, usk, _) = st.test_account().unwrap();
-        let dfvk = st.test_account_sapling().unwrap();
-
-        let uaddr = st
-            .wallet()
-            .get_current_address(account_id)
-            .unwrap()
-            .unwrap();
-        let taddr = uaddr.transparent().unwrap();
-
-        // Ensure that the wallet has at least one block
-        let (h, _, _) = st.generate_next_block(
-            &dfvk,
-            AddressType::Internal,
-            NonNegativeAmount::const_from_u64(50000),
+        // A step may not refer to the outputs of a step that follows it.
+        assert_matches!(
+            Proposal::multi_step(
+                fee_rule,
+                proposal0.min_target_height(),
+                NonEmpty::from_vec(vec![step1.clone(), step0.clone()]).unwrap(),
+            ),
+            Err(ProposalError::ReferenceError(r)) if r == step0_payment
         );
-        st.scan_cached_blocks(h, 1);
 
-        let utxo = WalletTransparentOutput::from_parts(
-            OutPoint::new([1u8; 32], 1),
-            TxOut {
-                value: NonNegativeAmount::const_from_u64(10000),
-                script_pubkey: taddr.script(),
-            },
-            h,
+        let proposal = Proposal::multi_step(
+            fee_rule,
+            proposal0.min_target_height(),
+            NonEmpty::from_vec(vec![step0, step1]).unwrap(),
         )
         .unwrap();
+        check_proposal_serialization_roundtrip(&st.network(), st.wallet(), &proposal);
 
-        let res0 = st.wallet_mut().put_received_transparent_utxo(&utxo);
-        assert!(matches!(res0, Ok(_)));
-
-        // TODO: This test was originally written to use the pre-zip-313 fee rule
-        // and has not yet been updated.
-        #[allow(deprecated)]
-        let fee_rule = StandardFeeRule::PreZip313;
-
-        let input_selector = GreedyInputSelector::new(
-            standard::SingleOutputChangeStrategy::new(fee_rule, None),
-            DustOutputPolicy::default(),
-        );
-
-        assert_matches!(
-            st.shield_transparent_funds(
-                &input_selector,
-                NonNegativeAmount::from_u64(10000).unwrap(),
-                &usk,
-                &[*taddr],
-                1
-            ),
-            Ok(_)
-        );
-    }
-
-    #[test]
-    fn birthday_in_anchor_shard() {
-        let (mut st, dfvk, birthday, _) = test_with_canopy_birthday();
-
-        // Set up the following situation:
-        //
-        //        |<------ 500 ------->|<--- 10 --->|<--- 10 --->|
-        // last_shard_start   wallet_birthday  received_tx  anchor_height
-        //
-        // Set up some shard root history before the wallet birthday.
-        let prev_shard_start = birthday.height() - 500;
-        st.wallet_mut()
-            .put_sapling_subtree_roots(
-                0,
-                &[CommitmentTreeRoot::from_parts(
-                    prev_shard_start,
-                    // fake a hash, the value doesn't matter
-                    Node::empty_leaf(),
-                )],
-            )
+        let txids = st
+            .create_proposed_transactions::<Infallible, _>(&usk, OvkPolicy::Sender, &proposal)
             .unwrap();
+        assert_eq!(txids.len(), 2);
 
-        let received_tx_height = birthday.height() + 10;
-
-        let initial_sapling_tree_size =
-            u64::from(birthday.sapling_frontier().value().unwrap().position() + 1)
-                .try_into()
-                .unwrap();
+        // The second transaction spends the transparent output of the first.
+        let tx1 = st.wallet().get_transaction(txids[1]).unwrap();
+        let vin = &tx1.transparent_bundle().unwrap().vin;
+        assert_eq!(vin.len(), 1);
+        assert_eq!(vin[0].prevout.hash(), txids[0].as_ref());
 
-        // Generate 9 blocks that have no value for us, starting at the birthday height.
-        let not_our_key = ExtendedSpendingKey::master(&[]).to_diversifiable_full_viewing_key();
-        let not_our_value = NonNegativeAmount::const_from_u64(10000);
-        st.generate_block_at(
-            birthday.height(),
-            BlockHash([0; 32]),
-            &not_our_key,
-            AddressType::DefaultExternal,
-            not_our_value,
-            initial_sapling_tree_size,
-        );
-        for _ in 1..9 {
-            st.generate_next_block(&not_our_key, AddressType::DefaultExternal, not_our_value);
+        for txid in txids {
+            let (h, _) = st.generate_next_block_including(txid);
+            st.scan_cached_blocks(h, 1);
         }
 
-        // Now, generate a block that belongs to our wallet
-        st.generate_next_block(
-            &dfvk,
-            AddressType::DefaultExternal,
-            NonNegativeAmount::const_from_u64(500000),
+        // Only the shielded change of the first step remains in the wallet.
+        assert_eq!(
+            st.get_total_balance(account),
+            (value - (intermediate + fee).unwrap()).unwrap()
         );
-
-        // Generate some more blocks to get above our anchor height
-        for _ in 0..15 {
-            st.generate_next_block(&not_our_key, AddressType::DefaultExternal, not_our_value);
-        }
-
-        // Scan a block range that includes our received note, but skips some blocks we need to
-        // make it spendable.
-        st.scan_cached_blocks(birthday.height() + 5, 20);
-
-        // Verify that the received note is not considered spendable
-        let spendable = select_spendable_sapling_notes(
-            &st.wallet().conn,
-            &st.wallet().params,
-            AccountId::ZERO,
-            Amount::const_from_i64(300000),
-            received_tx_height + 10,
-            &[],
-        )
-        .unwrap();
-
-        assert_eq!(spendable.len(), 0);
-
-        // Scan the blocks we skipped
-        st.scan_cached_blocks(birthday.height(), 5);
-
-        // Verify that the received note is now considered spendable
-        let spendable = select_spendable_sapling_notes(
-            &st.wallet().conn,
-            &st.wallet().params,
-            AccountId::ZERO,
-            Amount::const_from_i64(300000),
-            received_tx_height + 10,
-            &[],
-        )
-        .unwrap();
-
-        assert_eq!(spendable.len(), 1);
     }
 
     #[test]
     fn checkpoint_gaps() {
         let mut st = TestBuilder::new()
-            .with_block_cache()
+            .with_block_cache(BlockCache::new())
+            .with_data_store_factory(TestDbFactory)
             .with_test_account(AccountBirthday::from_sapling_activation)
             .build();
 
@@ -1673,4 +1095,92 @@ pub(crate) mod tests {
             Ok(_)
         );
     }
+
+    /// Runs the backend-agnostic Sapling conformance scenarios against the SQLite wallet.
+    mod conformance {
+        use zcash_client_backend::data_api::testing::conformance::sapling as scenarios;
+
+        use crate::testing::TestDbFactory;
+
+        #[test]
+        fn send_proposed_transfer() {
+            scenarios::send_proposed_transfer(TestDbFactory)
+        }
+
+        #[test]
+        fn create_to_address_fails_on_incorrect_usk() {
+            scenarios::create_to_address_fails_on_incorrect_usk(TestDbFactory)
+        }
+
+        #[test]
+        fn create_to_address_fails_for_view_only_account() {
+            scenarios::create_to_address_fails_for_view_only_account(TestDbFactory)
+        }
+
+        #[test]
+        fn proposal_fails_with_no_blocks() {
+            scenarios::proposal_fails_with_no_blocks(TestDbFactory)
+        }
+
+        #[test]
+        fn spend_fails_on_unverified_notes() {
+            scenarios::spend_fails_on_unverified_notes(TestDbFactory)
+        }
+
+        #[test]
+        fn spend_fails_on_locked_notes() {
+            scenarios::spend_fails_on_locked_notes(TestDbFactory)
+        }
+
+        #[test]
+        fn ovk_policy_prevents_recovery_from_chain() {
+            scenarios::ovk_policy_prevents_recovery_from_chain(TestDbFactory)
+        }
+
+        #[test]
+        fn spend_succeeds_to_t_addr_zero_change() {
+            scenarios::spend_succeeds_to_t_addr_zero_change(TestDbFactory)
+        }
+
+        #[test]
+        fn change_note_spends_succeed() {
+            scenarios::change_note_spends_succeed(TestDbFactory)
+        }
+
+        #[test]
+        fn external_address_change_spends_detected_in_restore_from_seed() {
+            scenarios::external_address_change_spends_detected_in_restore_from_seed(TestDbFactory)
+        }
+
+        #[test]
+        fn mempool_transaction_is_pending_until_mined() {
+            scenarios::mempool_transaction_is_pending_until_mined(TestDbFactory)
+        }
+
+        #[test]
+        fn mempool_transaction_expires_unmined() {
+            scenarios::mempool_transaction_expires_unmined(TestDbFactory)
+        }
+
+        #[test]
+        fn abandoned_transaction_releases_spent_notes() {
+            scenarios::abandoned_transaction_releases_spent_notes(TestDbFactory)
+        }
+
+        #[test]
+        fn zip317_spend() {
+            scenarios::zip317_spend(TestDbFactory)
+        }
+
+        #[test]
+        #[cfg(feature = "transparent-inputs")]
+        fn shield_transparent() {
+            scenarios::shield_transparent(TestDbFactory)
+        }
+
+        #[test]
+        fn birthday_in_anchor_shard() {
+            scenarios::birthday_in_anchor_shard(TestDbFactory)
+        }
+    }
 }
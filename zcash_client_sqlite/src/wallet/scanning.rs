@@ -506,6 +506,7 @@ pub(crate) mod tests {
     use zcash_client_backend::data_api::{
         chain::CommitmentTreeRoot,
         scanning::{spanning_tree::testing::scan_range, ScanPriority},
+        testing::conformance::{AddressType, TestBuilder, TestState},
         AccountBirthday, Ratio, WalletCommitmentTrees, WalletRead, WalletWrite,
         SAPLING_SHARD_HEIGHT,
     };
@@ -517,7 +518,7 @@ pub(crate) mod tests {
 
     use crate::{
         error::SqliteClientError,
-        testing::{AddressType, BlockCache, TestBuilder, TestState},
+        testing::{BlockCache, TestDbFactory},
         wallet::scanning::{insert_queue_entries, replace_queue_entries, suggest_scan_ranges},
         VERIFY_LOOKAHEAD,
     };
@@ -527,7 +528,8 @@ pub(crate) mod tests {
         use ScanPriority::*;
 
         let mut st = TestBuilder::new()
-            .with_block_cache()
+            .with_block_cache(BlockCache::new())
+            .with_data_store_factory(TestDbFactory)
             .with_test_account(AccountBirthday::from_sapling_activation)
             .build();
 
@@ -649,13 +651,14 @@ pub(crate) mod tests {
     }
 
     pub(crate) fn test_with_canopy_birthday() -> (
-        TestState<BlockCache>,
+        TestState<BlockCache, TestDbFactory>,
         DiversifiableFullViewingKey,
         AccountBirthday,
         u32,
     ) {
         let st = TestBuilder::new()
-            .with_block_cache()
+            .with_block_cache(BlockCache::new())
+            .with_data_store_factory(TestDbFactory)
             .with_test_account(|network| {
                 // We use Canopy activation as an arbitrary birthday height that's greater than Sapling
                 // activation. We set the Canopy frontier to be 1234 notes into the second shard.
@@ -697,7 +700,10 @@ pub(crate) mod tests {
     fn update_chain_tip_before_create_account() {
         use ScanPriority::*;
 
-        let mut st = TestBuilder::new().with_block_cache().build();
+        let mut st = TestBuilder::new()
+            .with_block_cache(BlockCache::new())
+            .with_data_store_factory(TestDbFactory)
+            .build();
         let sap_active = st.sapling_activation_height();
 
         // Update the chain tip.
@@ -1035,7 +1041,9 @@ pub(crate) mod tests {
     fn replace_queue_entries_merges_previous_range() {
         use ScanPriority::*;
 
-        let mut st = TestBuilder::new().build();
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestDbFactory)
+            .build();
 
         let ranges = vec![
             scan_range(150..200, ChainTip),
@@ -1078,7 +1086,9 @@ pub(crate) mod tests {
     fn replace_queue_entries_merges_subsequent_range() {
         use ScanPriority::*;
 
-        let mut st = TestBuilder::new().build();
+        let mut st = TestBuilder::new()
+            .with_data_store_factory(TestDbFactory)
+            .build();
 
         let ranges = vec![
             scan_range(150..200, ChainTip),
@@ -67,6 +67,55 @@ pub mod sapling {
     }
 }
 
+/// The personalization for ZIP 32 seed fingerprints.
+const ZIP32_SEED_FP_PERSONALIZATION: &[u8; 16] = b"Zcash_HD_Seed_FP";
+
+/// The fingerprint of a ZIP 32 seed, as defined in [ZIP 32].
+///
+/// A seed fingerprint allows a wallet to recognize which of several seeds an account was
+/// derived from, without retaining the seed itself.
+///
+/// [ZIP 32]: https://zips.z.cash/zip-0032#seed-fingerprints
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SeedFingerprint([u8; 32]);
+
+impl SeedFingerprint {
+    /// Computes the fingerprint of the given seed.
+    ///
+    /// Returns `None` if the length of `seed` is less than 32 or greater than 252 bytes, as
+    /// required by ZIP 32.
+    pub fn from_seed(seed: &[u8]) -> Option<SeedFingerprint> {
+        let seed_len = seed.len();
+        if (32..=252).contains(&seed_len) {
+            let hash = blake2b_simd::Params::new()
+                .hash_length(32)
+                .personal(ZIP32_SEED_FP_PERSONALIZATION)
+                .to_state()
+                .update(&[seed_len as u8])
+                .update(seed)
+                .finalize();
+
+            Some(SeedFingerprint(
+                hash.as_bytes()
+                    .try_into()
+                    .expect("BLAKE2b-256 output is 32 bytes"),
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Reconstructs a seed fingerprint from its byte representation.
+    pub fn from_bytes(bytes: [u8; 32]) -> SeedFingerprint {
+        SeedFingerprint(bytes)
+    }
+
+    /// Returns the byte representation of the fingerprint.
+    pub fn to_bytes(&self) -> [u8; 32] {
+        self.0
+    }
+}
+
 #[cfg(feature = "transparent-inputs")]
 fn to_transparent_child_index(j: DiversifierIndex) -> Option<u32> {
     let (low_4_bytes, rest) = j.as_bytes().split_at(4);
@@ -773,7 +822,7 @@ pub mod testing {
 mod tests {
     use proptest::prelude::proptest;
 
-    use super::{sapling, UnifiedFullViewingKey};
+    use super::{sapling, SeedFingerprint, UnifiedFullViewingKey};
     use zcash_primitives::consensus::MAIN_NETWORK;
     use zip32::AccountId;
 
@@ -809,6 +858,18 @@ mod tests {
         let _ = sapling::spending_key(&[0; 31][..], 0, AccountId::ZERO);
     }
 
+    #[test]
+    fn seed_fingerprint() {
+        let seed: Vec<u8> = (0..32).collect();
+        assert_eq!(
+            hex::encode(SeedFingerprint::from_seed(&seed).unwrap().to_bytes()),
+            "deff604c246710f7176dead02aa746f2fd8d5389f7072556dcb555fdbe5e3ae3"
+        );
+
+        assert_eq!(SeedFingerprint::from_seed(&[0; 31]), None);
+        assert_eq!(SeedFingerprint::from_seed(&[0; 253]), None);
+    }
+
     #[cfg(feature = "transparent-inputs")]
     #[test]
     fn pk_to_taddr() {
@@ -49,6 +49,10 @@ use crate::{
 
 use super::components::amount::NonNegativeAmount;
 
+use self::signer::{Signer, SoftwareSigner};
+
+pub mod signer;
+
 /// Since Blossom activation, the default transaction expiry delta should be 40 blocks.
 /// <https://zips.z.cash/zip-0203#changes-for-blossom>
 const DEFAULT_TX_EXPIRY_DELTA: u32 = 40;
@@ -98,6 +102,8 @@ pub enum Error<FE> {
     /// The builder was constructed with a target height before NU5 activation, but an Orchard
     /// spend or output was added.
     OrchardBuilderNotAvailable,
+    /// An error occurred in obtaining the authorizing signatures for a transaction.
+    Signer(Box<dyn error::Error + Send + Sync>),
     /// An error occurred in constructing the TZE parts of a transaction.
     #[cfg(feature = "zfuture")]
     TzeBuild(tze::builder::Error),
@@ -131,6 +137,7 @@ impl<FE: fmt::Display> fmt::Display for Error<FE> {
                 f,
                 "Cannot create Orchard transactions without an Orchard anchor, or before NU5 activation"
             ),
+            Error::Signer(err) => write!(f, "An error occurred in signing the transaction: {}", err),
             #[cfg(feature = "zfuture")]
             Error::TzeBuild(err) => err.fmt(f),
         }
@@ -266,6 +273,11 @@ impl BuildResult {
     pub fn orchard_meta(&self) -> &orchard::builder::BundleMetadata {
         &self.orchard_meta
     }
+
+    /// Consumes this result, returning the transaction that was constructed by the builder.
+    pub fn into_transaction(self) -> Transaction {
+        self.transaction
+    }
 }
 
 /// Generates a [`Transaction`] from its inputs and outputs.
@@ -277,12 +289,10 @@ pub struct Builder<'a, P, U: sapling::builder::ProverProgress> {
     transparent_builder: TransparentBuilder,
     sapling_builder: Option<sapling::builder::Builder>,
     orchard_builder: Option<orchard::builder::Builder>,
-    // TODO: In the future, instead of taking the spending keys as arguments when calling
-    // `add_sapling_spend` or `add_orchard_spend`, we will build an unauthorized, unproven
-    // transaction, and then the caller will be responsible for using the spending keys or their
-    // derivatives for proving and signing to complete transaction creation.
-    sapling_asks: Vec<sapling::keys::SpendAuthorizingKey>,
-    orchard_saks: Vec<orchard::keys::SpendAuthorizingKey>,
+    // The spend authorizing keys for the shielded spends that were added along with their
+    // spending keys. These are used by `build`, but not by `build_with_signer`, which obtains
+    // every spend authorization signature from the provided signer.
+    signer: SoftwareSigner,
     #[cfg(feature = "zfuture")]
     tze_builder: TzeBuilder<'a, TransactionData<Unauthorized>>,
     #[cfg(not(feature = "zfuture"))]
@@ -366,8 +376,7 @@ impl<'a, P: consensus::Parameters> Builder<'a, P, ()> {
             transparent_builder: TransparentBuilder::empty(),
             sapling_builder,
             orchard_builder,
-            sapling_asks: vec![],
-            orchard_saks: Vec::new(),
+            signer: SoftwareSigner::new(),
             #[cfg(feature = "zfuture")]
             tze_builder: TzeBuilder::empty(),
             #[cfg(not(feature = "zfuture"))]
@@ -394,8 +403,7 @@ impl<'a, P: consensus::Parameters> Builder<'a, P, ()> {
             transparent_builder: self.transparent_builder,
             sapling_builder: self.sapling_builder,
             orchard_builder: self.orchard_builder,
-            sapling_asks: self.sapling_asks,
-            orchard_saks: self.orchard_saks,
+            signer: self.signer,
             tze_builder: self.tze_builder,
             progress_notifier,
         }
@@ -405,6 +413,10 @@ impl<'a, P: consensus::Parameters> Builder<'a, P, ()> {
 impl<'a, P: consensus::Parameters, U: sapling::builder::ProverProgress> Builder<'a, P, U> {
     /// Adds an Orchard note to be spent in this bundle.
     ///
+    /// The spend authorizing key derived from `sk` is used to sign the spend when the
+    /// transaction is built with [`Builder::build`]; [`Builder::build_with_signer`] instead
+    /// requires the [`Signer`] to authorize it.
+    ///
     /// Returns an error if the given Merkle path does not have the required anchor for
     /// the given note.
     pub fn add_orchard_spend<FE>(
@@ -416,8 +428,8 @@ impl<'a, P: consensus::Parameters, U: sapling::builder::ProverProgress> Builder<
         if let Some(builder) = self.orchard_builder.as_mut() {
             builder.add_spend(orchard::keys::FullViewingKey::from(sk), note, merkle_path)?;
 
-            self.orchard_saks
-                .push(orchard::keys::SpendAuthorizingKey::from(sk));
+            self.signer
+                .add_orchard_key(orchard::keys::SpendAuthorizingKey::from(sk));
 
             Ok(())
         } else {
@@ -425,6 +437,27 @@ impl<'a, P: consensus::Parameters, U: sapling::builder::ProverProgress> Builder<
         }
     }
 
+    /// Adds an Orchard note to be spent in this bundle, without its spending key.
+    ///
+    /// The spend must be authorized by the [`Signer`] passed to
+    /// [`Builder::build_with_signer`].
+    ///
+    /// Returns an error if the given Merkle path does not have the required anchor for
+    /// the given note.
+    pub fn add_orchard_spend_with_fvk<FE>(
+        &mut self,
+        fvk: &orchard::keys::FullViewingKey,
+        note: orchard::Note,
+        merkle_path: orchard::tree::MerklePath,
+    ) -> Result<(), Error<FE>> {
+        self.orchard_builder
+            .as_mut()
+            .ok_or(Error::OrchardBuilderNotAvailable)?
+            .add_spend(fvk.clone(), note, merkle_path)?;
+
+        Ok(())
+    }
+
     /// Adds an Orchard recipient to the transaction.
     pub fn add_orchard_output<FE>(
         &mut self,
@@ -447,6 +480,10 @@ impl<'a, P: consensus::Parameters, U: sapling::builder::ProverProgress> Builder<
 
     /// Adds a Sapling note to be spent in this transaction.
     ///
+    /// The proof generation key for the spend is derived from `extsk`. Its spend authorizing
+    /// key is used to sign the spend when the transaction is built with [`Builder::build`];
+    /// [`Builder::build_with_signer`] instead requires the [`Signer`] to authorize it.
+    ///
     /// Returns an error if the given Merkle path does not have the same anchor as the
     /// paths for previous Sapling notes.
     pub fn add_sapling_spend<FE>(
@@ -458,7 +495,7 @@ impl<'a, P: consensus::Parameters, U: sapling::builder::ProverProgress> Builder<
         if let Some(builder) = self.sapling_builder.as_mut() {
             builder.add_spend(extsk, note, merkle_path)?;
 
-            self.sapling_asks.push(extsk.expsk.ask.clone());
+            self.signer.add_sapling_key(extsk.expsk.ask.clone());
             Ok(())
         } else {
             Err(Error::SaplingBuilderNotAvailable)
@@ -496,6 +533,20 @@ impl<'a, P: consensus::Parameters, U: sapling::builder::ProverProgress> Builder<
         self.transparent_builder.add_input(sk, utxo, coin)
     }
 
+    /// Adds a transparent coin to be spent in this transaction, without its secret key.
+    ///
+    /// The input must be signed by the [`Signer`] passed to [`Builder::build_with_signer`].
+    #[cfg(feature = "transparent-inputs")]
+    pub fn add_transparent_input_with_pubkey(
+        &mut self,
+        pubkey: secp256k1::PublicKey,
+        utxo: transparent::OutPoint,
+        coin: TxOut,
+    ) -> Result<(), transparent::builder::Error> {
+        self.transparent_builder
+            .add_input_with_pubkey(pubkey, utxo, coin)
+    }
+
     /// Adds a transparent address to send funds to.
     pub fn add_transparent_output(
         &mut self,
@@ -626,14 +677,46 @@ impl<'a, P: consensus::Parameters, U: sapling::builder::ProverProgress> Builder<
     /// Upon success, returns a tuple containing the final transaction, and the
     /// [`SaplingMetadata`] generated during the build process.
     pub fn build<R: RngCore + CryptoRng, SP: SpendProver, OP: OutputProver, FR: FeeRule>(
-        self,
+        mut self,
         rng: R,
         spend_prover: &SP,
         output_prover: &OP,
         fee_rule: &FR,
     ) -> Result<BuildResult, Error<FR::Error>> {
         let fee = self.get_fee(fee_rule).map_err(Error::Fee)?;
-        self.build_internal(rng, spend_prover, output_prover, fee)
+        let signer = std::mem::take(&mut self.signer);
+        self.build_internal(rng, spend_prover, output_prover, fee, &signer)
+    }
+
+    /// Builds a transaction from the configured spends and outputs, obtaining the spend
+    /// authorization signatures for its Sapling and Orchard spends, and the signatures for
+    /// any transparent inputs that were added without their secret keys, from the given
+    /// [`Signer`].
+    ///
+    /// The builder does not sign any shielded spend itself, even if it was added along with
+    /// its spending key. Sapling spends must currently always be added with their
+    /// [`ExtendedSpendingKey`], because the proof generation key is derived from it.
+    ///
+    /// [`ExtendedSpendingKey`]: sapling::zip32::ExtendedSpendingKey
+    pub fn build_with_signer<
+        R: RngCore + CryptoRng,
+        SP: SpendProver,
+        OP: OutputProver,
+        FR: FeeRule,
+        S: Signer,
+    >(
+        self,
+        rng: R,
+        spend_prover: &SP,
+        output_prover: &OP,
+        fee_rule: &FR,
+        signer: &S,
+    ) -> Result<BuildResult, Error<FR::Error>>
+    where
+        S::Error: error::Error + Send + Sync + 'static,
+    {
+        let fee = self.get_fee(fee_rule).map_err(Error::Fee)?;
+        self.build_internal(rng, spend_prover, output_prover, fee, signer)
     }
 
     /// Builds a transaction from the configured spends and outputs.
@@ -647,23 +730,28 @@ impl<'a, P: consensus::Parameters, U: sapling::builder::ProverProgress> Builder<
         OP: OutputProver,
         FR: FutureFeeRule,
     >(
-        self,
+        mut self,
         rng: R,
         spend_prover: &SP,
         output_prover: &OP,
         fee_rule: &FR,
     ) -> Result<BuildResult, Error<FR::Error>> {
         let fee = self.get_fee_zfuture(fee_rule).map_err(Error::Fee)?;
-        self.build_internal(rng, spend_prover, output_prover, fee)
+        let signer = std::mem::take(&mut self.signer);
+        self.build_internal(rng, spend_prover, output_prover, fee, &signer)
     }
 
-    fn build_internal<R: RngCore + CryptoRng, SP: SpendProver, OP: OutputProver, FE>(
+    fn build_internal<R: RngCore + CryptoRng, SP: SpendProver, OP: OutputProver, S: Signer, FE>(
         self,
         mut rng: R,
         spend_prover: &SP,
         output_prover: &OP,
         fee: NonNegativeAmount,
-    ) -> Result<BuildResult, Error<FE>> {
+        signer: &S,
+    ) -> Result<BuildResult, Error<FE>>
+    where
+        S::Error: error::Error + Send + Sync + 'static,
+    {
         let consensus_branch_id = BranchId::for_height(&self.params, self.target_height);
 
         // determine transaction version
@@ -754,14 +842,25 @@ impl<'a, P: consensus::Parameters, U: sapling::builder::ProverProgress> Builder<
         //
         let txid_parts = unauthed_tx.digest(TxIdDigester);
 
-        let transparent_bundle = unauthed_tx.transparent_bundle.clone().map(|b| {
-            b.apply_signatures(
-                #[cfg(feature = "transparent-inputs")]
-                &unauthed_tx,
-                #[cfg(feature = "transparent-inputs")]
-                &txid_parts,
-            )
-        });
+        #[cfg(feature = "transparent-inputs")]
+        let transparent_bundle = unauthed_tx
+            .transparent_bundle
+            .clone()
+            .map(|b| {
+                b.apply_external_signatures(&unauthed_tx, &txid_parts, |pubkey, sighash| {
+                    signer.sign_transparent(pubkey, sighash)
+                })
+            })
+            .transpose()
+            .map_err(|e| Error::Signer(Box::new(e)))?;
+
+        #[cfg(not(feature = "transparent-inputs"))]
+        let transparent_bundle = unauthed_tx
+            .transparent_bundle
+            .clone()
+            .map(|b| b.apply_signatures())
+            .transpose()
+            .map_err(Error::TransparentBuild)?;
 
         #[cfg(feature = "zfuture")]
         let tze_bundle = unauthed_tx
@@ -779,30 +878,30 @@ impl<'a, P: consensus::Parameters, U: sapling::builder::ProverProgress> Builder<
 
         let sapling_bundle = unauthed_tx
             .sapling_bundle
-            .map(|b| {
-                b.apply_signatures(
-                    &mut rng,
-                    *shielded_sig_commitment.as_ref(),
-                    &self.sapling_asks,
-                )
+            .map(|b| -> Result<_, Error<FE>> {
+                let b = b.prepare(&mut rng, *shielded_sig_commitment.as_ref());
+                signer
+                    .sign_sapling(&mut rng, b)
+                    .map_err(|e| Error::Signer(Box::new(e)))?
+                    .finalize()
+                    .map_err(Error::SaplingBuild)
             })
-            .transpose()
-            .map_err(Error::SaplingBuild)?;
+            .transpose()?;
 
         let orchard_bundle = unauthed_tx
             .orchard_bundle
-            .map(|b| {
-                b.create_proof(&orchard::circuit::ProvingKey::build(), &mut rng)
-                    .and_then(|b| {
-                        b.apply_signatures(
-                            &mut rng,
-                            *shielded_sig_commitment.as_ref(),
-                            &self.orchard_saks,
-                        )
-                    })
+            .map(|b| -> Result<_, Error<FE>> {
+                let b = b
+                    .create_proof(&orchard::circuit::ProvingKey::build(), &mut rng)
+                    .map_err(Error::OrchardBuild)?;
+                let b = b.prepare(&mut rng, *shielded_sig_commitment.as_ref());
+                signer
+                    .sign_orchard(&mut rng, b)
+                    .map_err(|e| Error::Signer(Box::new(e)))?
+                    .finalize()
+                    .map_err(Error::OrchardBuild)
             })
-            .transpose()
-            .map_err(Error::OrchardBuild)?;
+            .transpose()?;
 
         let authorized_tx = TransactionData {
             version: unauthed_tx.version,
@@ -950,7 +1049,7 @@ mod tests {
     #[cfg(feature = "transparent-inputs")]
     fn binding_sig_absent_if_no_shielded_spend_or_output() {
         use crate::consensus::NetworkUpgrade;
-        use crate::transaction::builder::{self, TransparentBuilder};
+        use crate::transaction::builder::{self, signer::SoftwareSigner, TransparentBuilder};
 
         let sapling_activation_height = TEST_NETWORK
             .activation_height(NetworkUpgrade::Sapling)
@@ -973,8 +1072,7 @@ mod tests {
             tze_builder: std::marker::PhantomData,
             progress_notifier: (),
             orchard_builder: None,
-            sapling_asks: vec![],
-            orchard_saks: Vec::new(),
+            signer: SoftwareSigner::new(),
         };
 
         let tsk = AccountPrivKey::from_seed(&TEST_NETWORK, &[0u8; 32], AccountId::ZERO).unwrap();
@@ -1009,6 +1107,286 @@ mod tests {
         assert!(res.transaction().sapling_bundle.is_none());
     }
 
+    #[test]
+    #[cfg(feature = "transparent-inputs")]
+    fn transparent_input_signed_by_external_signer() {
+        use crate::{
+            sapling::prover::mock::{MockOutputProver, MockSpendProver},
+            transaction::{
+                builder::signer::{testing::MockRemoteSigner, SoftwareSigner},
+                fees::fixed,
+            },
+        };
+
+        let tx_height = TEST_NETWORK.activation_height(NetworkUpgrade::Nu5).unwrap();
+
+        let tsk = AccountPrivKey::from_seed(&TEST_NETWORK, &[0u8; 32], AccountId::ZERO).unwrap();
+        let sk = tsk.derive_external_secret_key(0).unwrap();
+        let pubkey = secp256k1::PublicKey::from_secret_key(&secp256k1::Secp256k1::new(), &sk);
+        let prev_coin = TxOut {
+            value: NonNegativeAmount::const_from_u64(50000),
+            script_pubkey: tsk
+                .to_account_pubkey()
+                .derive_external_ivk()
+                .unwrap()
+                .derive_address(0)
+                .unwrap()
+                .script(),
+        };
+        let fee_rule = fixed::FeeRule::non_standard(NonNegativeAmount::const_from_u64(10000));
+
+        let new_builder = || {
+            let mut builder = Builder::new(
+                TEST_NETWORK,
+                tx_height,
+                BuildConfig::Standard {
+                    sapling_anchor: None,
+                    orchard_anchor: None,
+                },
+            );
+            builder
+                .add_transparent_input_with_pubkey(
+                    pubkey,
+                    OutPoint::new([0u8; 32], 1),
+                    prev_coin.clone(),
+                )
+                .unwrap();
+            builder
+                .add_transparent_output(
+                    &TransparentAddress::PublicKeyHash([0; 20]),
+                    NonNegativeAmount::const_from_u64(40000),
+                )
+                .unwrap();
+            builder
+        };
+
+        // The builder does not hold the key for the input, so it cannot sign it alone.
+        assert_matches!(
+            new_builder().build(OsRng, &MockSpendProver, &MockOutputProver, &fee_rule),
+            Err(Error::Signer(_))
+        );
+
+        // A signer that does not hold the key is also unable to sign it.
+        let signer = MockRemoteSigner::new(SoftwareSigner::new());
+        assert_matches!(
+            new_builder().build_with_signer(
+                OsRng,
+                &MockSpendProver,
+                &MockOutputProver,
+                &fee_rule,
+                &signer
+            ),
+            Err(Error::Signer(_))
+        );
+
+        let mut device = SoftwareSigner::new();
+        device.add_transparent_key(sk);
+        let signer = MockRemoteSigner::new(device);
+        let res = new_builder()
+            .build_with_signer(
+                OsRng,
+                &MockSpendProver,
+                &MockOutputProver,
+                &fee_rule,
+                &signer,
+            )
+            .unwrap();
+        assert_eq!(signer.request_count(), 1);
+
+        // ECDSA signing is deterministic, so the result must be identical to that of
+        // signing with the key held by the builder.
+        let mut builder = Builder::new(
+            TEST_NETWORK,
+            tx_height,
+            BuildConfig::Standard {
+                sapling_anchor: None,
+                orchard_anchor: None,
+            },
+        );
+        builder
+            .add_transparent_input(sk, OutPoint::new([0u8; 32], 1), prev_coin.clone())
+            .unwrap();
+        builder
+            .add_transparent_output(
+                &TransparentAddress::PublicKeyHash([0; 20]),
+                NonNegativeAmount::const_from_u64(40000),
+            )
+            .unwrap();
+        let expected = builder
+            .build(OsRng, &MockSpendProver, &MockOutputProver, &fee_rule)
+            .unwrap();
+        assert_eq!(res.transaction().txid(), expected.transaction().txid());
+    }
+
+    #[test]
+    fn sapling_spend_signed_by_external_signer() {
+        use crate::{
+            sapling::prover::mock::{MockOutputProver, MockSpendProver},
+            transaction::{
+                builder::signer::{testing::MockRemoteSigner, SoftwareSigner},
+                fees::fixed,
+            },
+        };
+
+        let extsk = ExtendedSpendingKey::master(&[]);
+        let to = extsk
+            .to_diversifiable_full_viewing_key()
+            .default_address()
+            .1;
+        let note = to.create_note(
+            sapling::value::NoteValue::from_raw(50000),
+            Rseed::BeforeZip212(jubjub::Fr::random(OsRng)),
+        );
+        let mut tree = CommitmentTree::<Node, 32>::empty();
+        tree.append(Node::from_cmu(&note.cmu())).unwrap();
+        let witness = IncrementalWitness::from_tree(tree);
+
+        let tx_height = TEST_NETWORK
+            .activation_height(NetworkUpgrade::Sapling)
+            .unwrap();
+        let fee_rule = fixed::FeeRule::non_standard(NonNegativeAmount::const_from_u64(10000));
+
+        let new_builder = || {
+            let mut builder = Builder::new(
+                TEST_NETWORK,
+                tx_height,
+                BuildConfig::Standard {
+                    sapling_anchor: Some(witness.root().into()),
+                    orchard_anchor: None,
+                },
+            );
+            builder
+                .add_sapling_spend::<Infallible>(&extsk, note.clone(), witness.path().unwrap())
+                .unwrap();
+            builder
+                .add_transparent_output(
+                    &TransparentAddress::PublicKeyHash([0; 20]),
+                    NonNegativeAmount::const_from_u64(40000),
+                )
+                .unwrap();
+            builder
+        };
+
+        // When a signer is provided, the builder does not sign the spend itself, so a signer
+        // that does not hold the spend authorizing key cannot authorize the transaction.
+        let signer = MockRemoteSigner::new(SoftwareSigner::new());
+        assert_matches!(
+            new_builder().build_with_signer(
+                OsRng,
+                &MockSpendProver,
+                &MockOutputProver,
+                &fee_rule,
+                &signer
+            ),
+            Err(Error::SaplingBuild(_))
+        );
+        assert_eq!(signer.request_count(), 1);
+
+        let mut device = SoftwareSigner::new();
+        device.add_sapling_key(extsk.expsk.ask.clone());
+        let signer = MockRemoteSigner::new(device);
+        let res = new_builder()
+            .build_with_signer(
+                OsRng,
+                &MockSpendProver,
+                &MockOutputProver,
+                &fee_rule,
+                &signer,
+            )
+            .unwrap();
+        assert_eq!(signer.request_count(), 1);
+        assert_eq!(
+            res.transaction()
+                .sapling_bundle()
+                .map(|b| b.shielded_spends().len()),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn orchard_spend_signed_by_external_signer() {
+        use orchard::{
+            keys::{FullViewingKey, Scope, SpendAuthorizingKey, SpendingKey},
+            note::{ExtractedNoteCommitment, Nullifier, RandomSeed},
+            tree::{MerkleHashOrchard, MerklePath},
+            value::NoteValue,
+        };
+
+        use crate::{
+            sapling::prover::mock::{MockOutputProver, MockSpendProver},
+            transaction::{
+                builder::signer::{testing::MockRemoteSigner, SoftwareSigner},
+                fees::fixed,
+            },
+        };
+
+        let sk = SpendingKey::from_bytes([7; 32]).unwrap();
+        let fvk = FullViewingKey::from(&sk);
+        let rho = Nullifier::from_bytes(&[0; 32]).unwrap();
+        let note = orchard::Note::from_parts(
+            fvk.address_at(0u32, Scope::External),
+            NoteValue::from_raw(50000),
+            rho,
+            RandomSeed::from_bytes([7; 32], &rho).unwrap(),
+        )
+        .unwrap();
+        let mut tree = CommitmentTree::<MerkleHashOrchard, 32>::empty();
+        tree.append(MerkleHashOrchard::from_cmx(&ExtractedNoteCommitment::from(
+            note.commitment(),
+        )))
+        .unwrap();
+        let witness = IncrementalWitness::from_tree(tree);
+
+        let tx_height = TEST_NETWORK.activation_height(NetworkUpgrade::Nu5).unwrap();
+        let fee_rule = fixed::FeeRule::non_standard(NonNegativeAmount::const_from_u64(10000));
+
+        let new_builder = || {
+            let mut builder = Builder::new(
+                TEST_NETWORK,
+                tx_height,
+                BuildConfig::Standard {
+                    sapling_anchor: None,
+                    orchard_anchor: Some(witness.root().into()),
+                },
+            );
+            builder
+                .add_orchard_spend_with_fvk::<Infallible>(
+                    &fvk,
+                    note,
+                    MerklePath::from(witness.path().unwrap()),
+                )
+                .unwrap();
+            builder
+                .add_transparent_output(
+                    &TransparentAddress::PublicKeyHash([0; 20]),
+                    NonNegativeAmount::const_from_u64(40000),
+                )
+                .unwrap();
+            builder
+        };
+
+        // The builder holds no key for the spend, so it cannot authorize it alone.
+        assert_matches!(
+            new_builder().build(OsRng, &MockSpendProver, &MockOutputProver, &fee_rule),
+            Err(Error::OrchardBuild(_))
+        );
+
+        let mut device = SoftwareSigner::new();
+        device.add_orchard_key(SpendAuthorizingKey::from(&sk));
+        let signer = MockRemoteSigner::new(device);
+        let res = new_builder()
+            .build_with_signer(
+                OsRng,
+                &MockSpendProver,
+                &MockOutputProver,
+                &fee_rule,
+                &signer,
+            )
+            .unwrap();
+        assert_eq!(signer.request_count(), 1);
+        assert!(res.transaction().orchard_bundle().is_some());
+    }
+
     #[test]
     fn binding_sig_present_if_shielded_spend() {
         let extsk = ExtendedSpendingKey::master(&[]);
@@ -0,0 +1,269 @@
+//! Abstractions for delegating the authorization of transaction spends.
+//!
+//! [`Builder::build_with_signer`] constructs and proves every bundle of a transaction,
+//! and then hands the resulting sighashes to a [`Signer`], which may hold the spending
+//! keys locally (as [`SoftwareSigner`] does) or forward each request to an external
+//! device.
+//!
+//! For the shielded protocols, the signer is given the partially-authorized bundle. Each
+//! spend in such a bundle carries the randomized verification key `rk` that its
+//! signature must verify under, along with the sighash that all spend authorization
+//! signatures commit to. The signer may either sign the bundle directly with a spend
+//! authorizing key, or append signatures that were produced elsewhere via the bundle's
+//! `append_signatures` method.
+//!
+//! The spend authorization signature for a shielded spend is made with the spend
+//! authorizing key randomized by `alpha`, which the builder samples when it constructs the
+//! bundle. The versions of the `sapling-crypto` and `orchard` crates used by this crate do
+//! not expose `alpha` outside of the bundle, so a device that signs on behalf of a
+//! [`Signer`] must be given the bundle itself (or hold the key in this process); a device
+//! that accepts only `(alpha, sighash)` requests cannot yet be supported. Likewise, Sapling
+//! spends must be added to the builder with their extended spending key, because
+//! `sapling-crypto` derives the proof generation key for each spend from it.
+//!
+//! [`Builder::build_with_signer`]: super::Builder::build_with_signer
+
+use std::fmt;
+
+use rand::{CryptoRng, RngCore};
+
+use crate::{
+    sapling::{self, builder as sapling_builder},
+    transaction::components::amount::Amount,
+};
+
+/// A Sapling bundle that has been proven, and is awaiting spend authorization signatures.
+pub type PartiallyAuthorizedSaplingBundle = sapling::Bundle<
+    sapling_builder::InProgress<sapling_builder::Proven, sapling_builder::PartiallyAuthorized>,
+    Amount,
+>;
+
+/// An Orchard bundle that has been proven, and is awaiting spend authorization
+/// signatures.
+pub type PartiallyAuthorizedOrchardBundle = orchard::Bundle<
+    orchard::builder::InProgress<orchard::circuit::Proof, orchard::builder::PartiallyAuthorized>,
+    Amount,
+>;
+
+/// A source of the authorizing signatures for the spends of a transaction.
+pub trait Signer {
+    /// The type of errors produced by this signer.
+    type Error;
+
+    /// Returns an ECDSA signature over `sighash`, made with the secret key corresponding
+    /// to `pubkey`.
+    ///
+    /// This is called once for each transparent input that was added to the builder
+    /// without its secret key.
+    #[cfg(feature = "transparent-inputs")]
+    fn sign_transparent(
+        &self,
+        pubkey: &secp256k1::PublicKey,
+        sighash: &[u8; 32],
+    ) -> Result<secp256k1::ecdsa::Signature, Self::Error>;
+
+    /// Adds spend authorization signatures to the given Sapling bundle for each spend
+    /// that this signer is able to authorize.
+    fn sign_sapling<R: RngCore + CryptoRng>(
+        &self,
+        rng: R,
+        bundle: PartiallyAuthorizedSaplingBundle,
+    ) -> Result<PartiallyAuthorizedSaplingBundle, Self::Error>;
+
+    /// Adds spend authorization signatures to the given Orchard bundle for each spend
+    /// that this signer is able to authorize.
+    fn sign_orchard<R: RngCore + CryptoRng>(
+        &self,
+        rng: R,
+        bundle: PartiallyAuthorizedOrchardBundle,
+    ) -> Result<PartiallyAuthorizedOrchardBundle, Self::Error>;
+}
+
+/// Errors that can be produced by a [`SoftwareSigner`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SoftwareSignerError {
+    /// The signer does not hold the secret key for a transparent input.
+    TransparentKeyNotFound,
+}
+
+impl fmt::Display for SoftwareSignerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SoftwareSignerError::TransparentKeyNotFound => write!(
+                f,
+                "The signer does not hold the secret key for a transparent input"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SoftwareSignerError {}
+
+/// A [`Signer`] that holds spending keys in memory.
+///
+/// Shielded spends for which the signer holds no key are left unsigned, so that the
+/// bundle may be passed on to another signer.
+#[derive(Default)]
+pub struct SoftwareSigner {
+    #[cfg(feature = "transparent-inputs")]
+    secp: secp256k1::Secp256k1<secp256k1::All>,
+    #[cfg(feature = "transparent-inputs")]
+    transparent_keys: Vec<secp256k1::SecretKey>,
+    sapling_keys: Vec<sapling::keys::SpendAuthorizingKey>,
+    orchard_keys: Vec<orchard::keys::SpendAuthorizingKey>,
+}
+
+impl SoftwareSigner {
+    /// Constructs a new signer that holds no keys.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a key that will be used to sign transparent inputs spending from the
+    /// corresponding P2PKH address.
+    #[cfg(feature = "transparent-inputs")]
+    pub fn add_transparent_key(&mut self, sk: secp256k1::SecretKey) {
+        self.transparent_keys.push(sk);
+    }
+
+    /// Adds a key that will be used to sign Sapling spends.
+    pub fn add_sapling_key(&mut self, ask: sapling::keys::SpendAuthorizingKey) {
+        self.sapling_keys.push(ask);
+    }
+
+    /// Adds a key that will be used to sign Orchard spends.
+    pub fn add_orchard_key(&mut self, ask: orchard::keys::SpendAuthorizingKey) {
+        self.orchard_keys.push(ask);
+    }
+}
+
+impl Signer for SoftwareSigner {
+    type Error = SoftwareSignerError;
+
+    #[cfg(feature = "transparent-inputs")]
+    fn sign_transparent(
+        &self,
+        pubkey: &secp256k1::PublicKey,
+        sighash: &[u8; 32],
+    ) -> Result<secp256k1::ecdsa::Signature, Self::Error> {
+        let sk = self
+            .transparent_keys
+            .iter()
+            .find(|sk| &secp256k1::PublicKey::from_secret_key(&self.secp, sk) == pubkey)
+            .ok_or(SoftwareSignerError::TransparentKeyNotFound)?;
+
+        let msg = secp256k1::Message::from_slice(sighash).expect("32 bytes");
+        Ok(self.secp.sign_ecdsa(&msg, sk))
+    }
+
+    fn sign_sapling<R: RngCore + CryptoRng>(
+        &self,
+        mut rng: R,
+        bundle: PartiallyAuthorizedSaplingBundle,
+    ) -> Result<PartiallyAuthorizedSaplingBundle, Self::Error> {
+        Ok(self
+            .sapling_keys
+            .iter()
+            .fold(bundle, |bundle, ask| bundle.sign(&mut rng, ask)))
+    }
+
+    fn sign_orchard<R: RngCore + CryptoRng>(
+        &self,
+        mut rng: R,
+        bundle: PartiallyAuthorizedOrchardBundle,
+    ) -> Result<PartiallyAuthorizedOrchardBundle, Self::Error> {
+        Ok(self
+            .orchard_keys
+            .iter()
+            .fold(bundle, |bundle, ask| bundle.sign(&mut rng, ask)))
+    }
+}
+
+#[cfg(any(test, feature = "test-dependencies"))]
+pub mod testing {
+    use std::cell::Cell;
+
+    use rand::{CryptoRng, RngCore};
+
+    use super::{
+        PartiallyAuthorizedOrchardBundle, PartiallyAuthorizedSaplingBundle, Signer, SoftwareSigner,
+        SoftwareSignerError,
+    };
+
+    /// A [`Signer`] that simulates a remote signing device.
+    ///
+    /// Transparent signing requests and responses are passed through their serialized
+    /// encodings, as they would be when sent to an external device. Every request made of
+    /// the signer is counted.
+    pub struct MockRemoteSigner {
+        device: SoftwareSigner,
+        requests: Cell<usize>,
+    }
+
+    impl MockRemoteSigner {
+        /// Constructs a remote signer that delegates to the given device.
+        pub fn new(device: SoftwareSigner) -> Self {
+            MockRemoteSigner {
+                device,
+                requests: Cell::new(0),
+            }
+        }
+
+        /// Returns the number of signing requests that have been made of this signer.
+        pub fn request_count(&self) -> usize {
+            self.requests.get()
+        }
+
+        fn record_request(&self) {
+            self.requests.set(self.requests.get() + 1);
+        }
+    }
+
+    impl Signer for MockRemoteSigner {
+        type Error = SoftwareSignerError;
+
+        #[cfg(feature = "transparent-inputs")]
+        fn sign_transparent(
+            &self,
+            pubkey: &secp256k1::PublicKey,
+            sighash: &[u8; 32],
+        ) -> Result<secp256k1::ecdsa::Signature, Self::Error> {
+            self.record_request();
+
+            // Encode the request as the device would receive it.
+            let mut request = pubkey.serialize().to_vec();
+            request.extend_from_slice(sighash);
+
+            let (pubkey, sighash) = request.split_at(secp256k1::constants::PUBLIC_KEY_SIZE);
+            let pubkey = secp256k1::PublicKey::from_slice(pubkey).expect("valid pubkey");
+            let sighash = sighash.try_into().expect("32 bytes");
+
+            // The device responds with the DER encoding of its signature.
+            let response = self
+                .device
+                .sign_transparent(&pubkey, sighash)?
+                .serialize_der()
+                .to_vec();
+
+            Ok(secp256k1::ecdsa::Signature::from_der(&response).expect("valid DER signature"))
+        }
+
+        fn sign_sapling<R: RngCore + CryptoRng>(
+            &self,
+            rng: R,
+            bundle: PartiallyAuthorizedSaplingBundle,
+        ) -> Result<PartiallyAuthorizedSaplingBundle, Self::Error> {
+            self.record_request();
+            self.device.sign_sapling(rng, bundle)
+        }
+
+        fn sign_orchard<R: RngCore + CryptoRng>(
+            &self,
+            rng: R,
+            bundle: PartiallyAuthorizedOrchardBundle,
+        ) -> Result<PartiallyAuthorizedOrchardBundle, Self::Error> {
+            self.record_request();
+            self.device.sign_orchard(rng, bundle)
+        }
+    }
+}
@@ -29,6 +29,9 @@ use {
 pub enum Error {
     InvalidAddress,
     InvalidAmount,
+    /// An input was added without its secret key, and so cannot be signed by
+    /// [`Bundle::apply_signatures`].
+    MissingSigningKey,
 }
 
 impl fmt::Display for Error {
@@ -36,6 +39,7 @@ impl fmt::Display for Error {
         match self {
             Error::InvalidAddress => write!(f, "Invalid address"),
             Error::InvalidAmount => write!(f, "Invalid amount"),
+            Error::MissingSigningKey => write!(f, "Missing signing key for a transparent input"),
         }
     }
 }
@@ -43,8 +47,8 @@ impl fmt::Display for Error {
 #[cfg(feature = "transparent-inputs")]
 #[derive(Debug, Clone)]
 pub struct TransparentInputInfo {
-    sk: secp256k1::SecretKey,
-    pubkey: [u8; secp256k1::constants::PUBLIC_KEY_SIZE],
+    sk: Option<secp256k1::SecretKey>,
+    pubkey: secp256k1::PublicKey,
     utxo: OutPoint,
     coin: TxOut,
 }
@@ -58,6 +62,11 @@ impl TransparentInputInfo {
     pub fn coin(&self) -> &TxOut {
         &self.coin
     }
+
+    /// Returns the public key whose signature is required to spend this input.
+    pub fn pubkey(&self) -> &secp256k1::PublicKey {
+        &self.pubkey
+    }
 }
 
 pub struct TransparentBuilder {
@@ -112,16 +121,41 @@ impl TransparentBuilder {
         utxo: OutPoint,
         coin: TxOut,
     ) -> Result<(), Error> {
-        // Ensure that the RIPEMD-160 digest of the public key associated with the
-        // provided secret key matches that of the address to which the provided
-        // output may be spent.
-        let pubkey = secp256k1::PublicKey::from_secret_key(&self.secp, &sk).serialize();
+        let pubkey = secp256k1::PublicKey::from_secret_key(&self.secp, &sk);
+        self.add_input_internal(Some(sk), pubkey, utxo, coin)
+    }
+
+    /// Adds a coin (the output of a previous transaction) to be spent to the transaction,
+    /// without providing the secret key that controls it.
+    ///
+    /// The signature for this input must be provided at the time the bundle is authorized,
+    /// via [`Bundle::apply_external_signatures`].
+    #[cfg(feature = "transparent-inputs")]
+    pub fn add_input_with_pubkey(
+        &mut self,
+        pubkey: secp256k1::PublicKey,
+        utxo: OutPoint,
+        coin: TxOut,
+    ) -> Result<(), Error> {
+        self.add_input_internal(None, pubkey, utxo, coin)
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn add_input_internal(
+        &mut self,
+        sk: Option<secp256k1::SecretKey>,
+        pubkey: secp256k1::PublicKey,
+        utxo: OutPoint,
+        coin: TxOut,
+    ) -> Result<(), Error> {
+        // Ensure that the RIPEMD-160 digest of the public key matches that of the
+        // address to which the provided output may be spent.
         match coin.script_pubkey.address() {
             Some(TransparentAddress::PublicKeyHash(hash)) => {
                 use ripemd::Ripemd160;
                 use sha2::Sha256;
 
-                if hash[..] != Ripemd160::digest(Sha256::digest(pubkey))[..] {
+                if hash[..] != Ripemd160::digest(Sha256::digest(pubkey.serialize()))[..] {
                     return Err(Error::InvalidAddress);
                 }
             }
@@ -239,14 +273,58 @@ impl TransparentAuthorizingContext for Unauthorized {
 }
 
 impl Bundle<Unauthorized> {
+    /// Signs each input of this bundle with the secret key that was provided when the
+    /// input was added.
+    ///
+    /// Returns [`Error::MissingSigningKey`] if any input was added via
+    /// [`TransparentBuilder::add_input_with_pubkey`]; use
+    /// [`Bundle::apply_external_signatures`] to authorize such bundles.
     pub fn apply_signatures(
         self,
         #[cfg(feature = "transparent-inputs")] mtx: &TransactionData<tx::Unauthorized>,
         #[cfg(feature = "transparent-inputs")] txid_parts_cache: &TxDigests<Blake2bHash>,
-    ) -> Bundle<Authorized> {
+    ) -> Result<Bundle<Authorized>, Error> {
         #[cfg(feature = "transparent-inputs")]
-        let script_sigs = self
-            .authorization
+        let script_sigs =
+            self.script_sigs(mtx, txid_parts_cache, |_, _| Err(Error::MissingSigningKey))?;
+
+        #[cfg(not(feature = "transparent-inputs"))]
+        let script_sigs = vec![];
+
+        Ok(self.into_authorized(script_sigs))
+    }
+
+    /// Signs each input of this bundle.
+    ///
+    /// Inputs that were added with their secret key are signed directly; for inputs that
+    /// were added via [`TransparentBuilder::add_input_with_pubkey`], the `sign` callback is
+    /// invoked with the input's public key and sighash, and must return an ECDSA signature
+    /// over that sighash. The first error returned by `sign` aborts signing.
+    #[cfg(feature = "transparent-inputs")]
+    pub fn apply_external_signatures<E, F>(
+        self,
+        mtx: &TransactionData<tx::Unauthorized>,
+        txid_parts_cache: &TxDigests<Blake2bHash>,
+        sign: F,
+    ) -> Result<Bundle<Authorized>, E>
+    where
+        F: Fn(&secp256k1::PublicKey, &[u8; 32]) -> Result<secp256k1::ecdsa::Signature, E>,
+    {
+        let script_sigs = self.script_sigs(mtx, txid_parts_cache, sign)?;
+        Ok(self.into_authorized(script_sigs))
+    }
+
+    #[cfg(feature = "transparent-inputs")]
+    fn script_sigs<E, F>(
+        &self,
+        mtx: &TransactionData<tx::Unauthorized>,
+        txid_parts_cache: &TxDigests<Blake2bHash>,
+        sign: F,
+    ) -> Result<Vec<Script>, E>
+    where
+        F: Fn(&secp256k1::PublicKey, &[u8; 32]) -> Result<secp256k1::ecdsa::Signature, E>,
+    {
+        self.authorization
             .inputs
             .iter()
             .enumerate()
@@ -263,20 +341,31 @@ impl Bundle<Unauthorized> {
                     txid_parts_cache,
                 );
 
-                let msg = secp256k1::Message::from_slice(sighash.as_ref()).expect("32 bytes");
-                let sig = self.authorization.secp.sign_ecdsa(&msg, &info.sk);
+                let sig = match &info.sk {
+                    Some(sk) => {
+                        let msg =
+                            secp256k1::Message::from_slice(sighash.as_ref()).expect("32 bytes");
+                        self.authorization.secp.sign_ecdsa(&msg, sk)
+                    }
+                    None => {
+                        let mut sig = sign(&info.pubkey, sighash.as_ref())?;
+                        // Consensus rules require signatures to be in lower-S form.
+                        sig.normalize_s();
+                        sig
+                    }
+                };
 
                 // Signature has to have "SIGHASH_ALL" appended to it
                 let mut sig_bytes: Vec<u8> = sig.serialize_der()[..].to_vec();
                 sig_bytes.extend([SIGHASH_ALL]);
 
                 // P2PKH scriptSig
-                Script::default() << &sig_bytes[..] << &info.pubkey[..]
-            });
-
-        #[cfg(not(feature = "transparent-inputs"))]
-        let script_sigs = std::iter::empty::<Script>();
+                Ok(Script::default() << &sig_bytes[..] << &info.pubkey.serialize()[..])
+            })
+            .collect()
+    }
 
+    fn into_authorized(self, script_sigs: Vec<Script>) -> Bundle<Authorized> {
         transparent::Bundle {
             vin: self
                 .vin